@@ -2,18 +2,227 @@ CREATE TABLE IF NOT EXISTS [Clients] (
     id          INTEGER PRIMARY KEY,
     available   BIGINT NOT NULL,
     held        BIGINT NOT NULL,
-    locked      BOOLEAN NOT NULL
+    locked      BOOLEAN NOT NULL,
+    -- When the client last made a transaction, in milliseconds since the unix epoch
+    last_activity_at BIGINT,
+    -- The counts and sums of the client's applied transactions
+    deposits    INTEGER NOT NULL DEFAULT 0,
+    deposited   BIGINT NOT NULL DEFAULT 0,
+    withdrawals INTEGER NOT NULL DEFAULT 0,
+    withdrawn   BIGINT NOT NULL DEFAULT 0,
+    disputes    INTEGER NOT NULL DEFAULT 0,
+    chargebacks INTEGER NOT NULL DEFAULT 0,
+    -- Raised and lowered by the `score` actions of validation rules
+    risk_score  BIGINT NOT NULL DEFAULT 0
 );
 
+-- Balances are integers of minor units, and a client's total is always derived as
+-- available + held rather than stored, so it cannot drift from them. Held funds can never be
+-- negative, which the database checks itself to catch arithmetic bugs. These are triggers, as
+-- SQLite cannot add a CHECK constraint to the tables of existing databases.
+CREATE TRIGGER IF NOT EXISTS [ClientsHeldInsert] BEFORE INSERT ON [Clients]
+WHEN NEW.held < 0
+BEGIN
+    SELECT RAISE(ABORT, 'CHECK constraint failed: held >= 0');
+END;
+CREATE TRIGGER IF NOT EXISTS [ClientsHeldUpdate] BEFORE UPDATE OF held ON [Clients]
+WHEN NEW.held < 0
+BEGIN
+    SELECT RAISE(ABORT, 'CHECK constraint failed: held >= 0');
+END;
+
+CREATE TABLE IF NOT EXISTS [Batches] (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    source      TEXT NOT NULL,
+    sha256      TEXT,
+    created_at  BIGINT NOT NULL
+);
+
+-- The clients whose balances the transactions of each batch changed
+CREATE TABLE IF NOT EXISTS [BatchClients] (
+    batch_id    INTEGER NOT NULL,
+    client_id   INTEGER NOT NULL,
+    PRIMARY KEY (batch_id, client_id)
+) WITHOUT ROWID;
+
 CREATE TABLE IF NOT EXISTS [Transactions] (
     id                      INTEGER PRIMARY KEY,
     [type]                  TEXT NOT NULL,
     client_id              INTEGER NOT NULL,
     amount                  BIGINT,
-	FOREIGN KEY(client_id) REFERENCES Clients(id)
+    batch_id                INTEGER,
+    line                    INTEGER,
+    reference               TEXT,
+    -- When the transaction was applied, in milliseconds since the unix epoch. Empty for
+    -- transactions applied before it was recorded and those imported with import-state
+    created_at              BIGINT,
+	FOREIGN KEY(client_id) REFERENCES Clients(id),
+	FOREIGN KEY(batch_id) REFERENCES Batches(id)
 );
 
 CREATE TABLE IF NOT EXISTS [Disputes] (
     transaction_id INTEGER PRIMARY KEY,
+    opened_at      BIGINT NOT NULL,
+    escalated_at   BIGINT,
+    reason_code    TEXT,
+    notes          TEXT,
+    -- The DisputeStatus of the risk team's workflow, and who is working on it
+    status         TEXT NOT NULL DEFAULT 'open',
+    assignee       TEXT,
+	FOREIGN KEY(transaction_id) REFERENCES Transactions(id)
+);
+
+-- References to the evidence of open disputes, see TransactionService::add_dispute_evidence
+CREATE TABLE IF NOT EXISTS [DisputeEvidence] (
+    id             INTEGER PRIMARY KEY AUTOINCREMENT,
+    transaction_id INTEGER NOT NULL,
+    reference      TEXT NOT NULL,
+    added_at       BIGINT NOT NULL,
+    UNIQUE (transaction_id, reference)
+);
+
+-- Deposits held until they clear, see TransactionPolicy::settlement_delay
+CREATE TABLE IF NOT EXISTS [Settlements] (
+    transaction_id INTEGER PRIMARY KEY,
+    release_at     BIGINT NOT NULL,
 	FOREIGN KEY(transaction_id) REFERENCES Transactions(id)
-);
\ No newline at end of file
+);
+CREATE INDEX IF NOT EXISTS [SettlementsByRelease] ON [Settlements] (release_at);
+
+-- Clearing deposits voided by an operator, see TransactionService::void_deposit
+CREATE TABLE IF NOT EXISTS [Voids] (
+    transaction_id INTEGER PRIMARY KEY,
+    client_id      INTEGER NOT NULL,
+    amount         BIGINT NOT NULL,
+    reason         TEXT NOT NULL,
+    operator       TEXT NOT NULL,
+    voided_at      BIGINT NOT NULL,
+	FOREIGN KEY(client_id) REFERENCES Clients(id)
+);
+
+-- The counterparties of changes to client funds, see TransactionService::get_cash_position
+CREATE TABLE IF NOT EXISTS [HouseAccounts] (
+    name        TEXT PRIMARY KEY,
+    balance     BIGINT NOT NULL
+);
+INSERT OR IGNORE INTO [HouseAccounts] (name, balance)
+    VALUES ('cash', 0), ('chargeback_expense', 0), ('fees', 0);
+
+CREATE TABLE IF NOT EXISTS [Adjustments] (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    client_id   INTEGER NOT NULL,
+    amount      BIGINT NOT NULL,
+    reason      TEXT NOT NULL,
+    operator    TEXT NOT NULL,
+	FOREIGN KEY(client_id) REFERENCES Clients(id)
+);
+
+-- Unlocks and adjustments held for a second operator, see ApprovalRules
+CREATE TABLE IF NOT EXISTS [Approvals] (
+    id           INTEGER PRIMARY KEY AUTOINCREMENT,
+    client_id    INTEGER NOT NULL,
+    action       TEXT NOT NULL,
+    -- The amount and reason of adjustments
+    amount       BIGINT,
+    reason       TEXT,
+    status       TEXT NOT NULL,
+    requested_by TEXT NOT NULL,
+    requested_at BIGINT NOT NULL,
+    decided_by   TEXT,
+    decided_at   BIGINT
+);
+CREATE INDEX IF NOT EXISTS [ApprovalsByStatus] ON [Approvals] (status, id);
+
+-- The last offset applied from each partition of a log, see process_with_offsets
+CREATE TABLE IF NOT EXISTS [SourceOffsets] (
+    consumer    TEXT NOT NULL,
+    [partition] INTEGER NOT NULL,
+    [offset]    BIGINT NOT NULL,
+    PRIMARY KEY (consumer, [partition])
+);
+
+CREATE TABLE IF NOT EXISTS [IdempotencyKeys] (
+    [key]       TEXT PRIMARY KEY,
+    request     TEXT NOT NULL,
+    outcomes    TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS [Outbox] (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    client_id   INTEGER NOT NULL,
+    payload     TEXT NOT NULL,
+    created_at  BIGINT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS [AuditLog] (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    event       TEXT NOT NULL,
+    created_at  BIGINT NOT NULL,
+    hash        TEXT NOT NULL,
+    -- The content hash of the event before it was redacted by an erasure
+    redacted_hash TEXT
+);
+
+-- For the balances of a client as of a past moment
+CREATE INDEX IF NOT EXISTS [TransactionReferences] ON [Transactions] (reference);
+CREATE INDEX IF NOT EXISTS [AuditLogClients] ON [AuditLog] (json_extract(event, '$.client.client'), created_at);
+
+CREATE TABLE IF NOT EXISTS [DayCloses] (
+    business_date   TEXT PRIMARY KEY,
+    closed_at       BIGINT NOT NULL,
+    -- The id of the last AuditLog record the date covers
+    checkpoint      INTEGER NOT NULL,
+    clients         TEXT NOT NULL,
+    totals          TEXT NOT NULL
+);
+
+-- Every client's balances at a moment, so past balances are read from the last snapshot
+-- before them and the audit log after it, see TransactionService::snapshot_balances
+CREATE TABLE IF NOT EXISTS [BalanceSnapshots] (
+    taken_at    BIGINT NOT NULL,
+    -- The id of the last AuditLog record the snapshot covers
+    checkpoint  INTEGER NOT NULL,
+    client_id   INTEGER NOT NULL,
+    available   BIGINT NOT NULL,
+    held        BIGINT NOT NULL,
+    locked      BOOLEAN NOT NULL,
+    PRIMARY KEY (checkpoint, client_id)
+);
+CREATE INDEX IF NOT EXISTS [BalanceSnapshotsByTime] ON [BalanceSnapshots] (taken_at);
+
+CREATE TABLE IF NOT EXISTS [Erasures] (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    client_id   INTEGER NOT NULL,
+    policy      TEXT NOT NULL,
+    operator    TEXT NOT NULL,
+    erased_at   BIGINT NOT NULL
+);
+
+-- Segments of clients, such as vip or retail, see TransactionService::tag_clients
+CREATE TABLE IF NOT EXISTS [ClientTags] (
+    client_id   INTEGER NOT NULL,
+    tag         TEXT NOT NULL,
+    PRIMARY KEY (client_id, tag)
+);
+CREATE INDEX IF NOT EXISTS [ClientTagsByTag] ON [ClientTags] (tag, client_id);
+
+-- The chargeback escalations of clients, see ChargebackEscalation
+CREATE TABLE IF NOT EXISTS [ClientRisks] (
+    client_id    INTEGER PRIMARY KEY,
+    level        TEXT NOT NULL,
+    chargebacks  BIGINT NOT NULL,
+    escalated_at BIGINT NOT NULL
+);
+
+-- Partner-specific string ids of clients, resolved by readers configured with ExternalIds
+CREATE TABLE IF NOT EXISTS [ExternalIds] (
+    external_id TEXT PRIMARY KEY NOT NULL,
+    client_id   INTEGER NOT NULL UNIQUE
+);
+
+CREATE TABLE IF NOT EXISTS [Annotations] (
+    id              INTEGER PRIMARY KEY AUTOINCREMENT,
+    transaction_id  INTEGER NOT NULL,
+    [type]          TEXT NOT NULL,
+    annotation      TEXT NOT NULL
+);
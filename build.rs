@@ -0,0 +1,13 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=proto/ledger.proto");
+
+    #[cfg(feature = "grpc")]
+    {
+        std::env::set_var(
+            "PROTOC",
+            protoc_bin_vendored::protoc_bin_path().expect("No vendored protoc for this platform"),
+        );
+        tonic_prost_build::compile_protos("proto/ledger.proto").expect("Failed to compile protos");
+    }
+}
@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use transaction_app::TransactionReader;
+
+// Any input must either parse or return an error, never panic.
+fuzz_target!(|data: &[u8]| {
+    let mut reader = TransactionReader::new(data);
+    for transaction in reader.transactions() {
+        let _ = transaction;
+    }
+});
@@ -0,0 +1,72 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use rust_decimal::Decimal;
+use transaction_app::{Transaction, TransactionService, TransactionType};
+
+#[derive(Debug, Arbitrary)]
+enum Kind {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+}
+
+#[derive(Debug, Arbitrary)]
+struct Op {
+    kind: Kind,
+    /// Ids and client ids are kept small so operations refer to each other.
+    id: u8,
+    client: u8,
+    /// Positive amounts only: negative deposits are accepted by the engine and disputing
+    /// one moves a negative amount into held funds.
+    amount: u32,
+    scale: u8,
+    with_amount: bool,
+}
+
+impl Op {
+    fn into_transaction(self) -> Transaction {
+        let transaction_type = match self.kind {
+            Kind::Deposit => TransactionType::Deposit,
+            Kind::Withdrawal => TransactionType::Withdrawal,
+            Kind::Dispute => TransactionType::Dispute,
+            Kind::Resolve => TransactionType::Resolve,
+            Kind::Chargeback => TransactionType::Chargeback,
+        };
+        Transaction {
+            id: (self.id % 16).into(),
+            transaction_type,
+            client_id: (self.client % 4).into(),
+            amount: self
+                .with_amount
+                .then(|| Decimal::new(self.amount.into(), (self.scale % 9).into())),
+            reference: None,
+            reason_code: None,
+            notes: None,
+            sequence: None,
+        }
+    }
+}
+
+// Processing any sequence may fail individual transactions but must not panic, and must
+// leave every client with total = available + held and no negative held funds.
+fuzz_target!(|ops: Vec<Op>| {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async {
+            let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+            let svc = TransactionService::new(pool).await.unwrap();
+            for op in ops {
+                let _ = svc.process_transaction(&op.into_transaction()).await;
+            }
+            for client in svc.get_clients_vec().await.unwrap() {
+                assert_eq!(client.total, client.available + client.held, "{client:?}");
+                assert!(client.held >= Decimal::ZERO, "{client:?}");
+            }
+        });
+});
@@ -0,0 +1,29 @@
+#![forbid(unsafe_code)]
+//! Processes client transactions (deposits, withdrawals, disputes, resolves and chargebacks)
+//! and keeps track of the resulting client balances.
+//!
+//! The engine can be embedded directly:
+//!
+//! ```no_run
+//! # async fn run() -> anyhow::Result<()> {
+//! use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
+//! use std::str::FromStr;
+//! use transaction_app::{TransactionReader, TransactionService};
+//!
+//! let options = SqliteConnectOptions::from_str("sqlite://:memory:")?;
+//! let svc = TransactionService::new(SqlitePool::connect_with(options).await?).await?;
+//!
+//! let csv = "type, client, tx, amount\ndeposit, 1, 1, 1.0";
+//! let mut reader = TransactionReader::new(csv.as_bytes());
+//! for transaction in reader.transactions() {
+//!     svc.process_transaction(&transaction?).await?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+#[cfg(feature = "server")]
+pub mod server;
+pub mod transactions;
+
+pub use transactions::*;
@@ -1,62 +1,3295 @@
 #![forbid(unsafe_code)]
-mod transactions;
-
 use anyhow::Context;
-use futures::TryStreamExt;
-use sqlx::sqlite::SqliteConnectOptions;
+use clap::{Parser, Subcommand, ValueEnum};
+use futures::{StreamExt, TryStreamExt};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
 use std::io;
-use std::{fs::File, str::FromStr};
+#[cfg(feature = "server")]
+use std::num::NonZeroU32;
+use std::path::Path;
+
+use transaction_app::{
+    content_sha256, AlertThresholds, Approval, ApprovalAction, ApprovalRules, Client, ClientFilter,
+    ClientId, ClientWithStats, Currency, Dispute, DisputeStatus, ErasurePolicy, EventObserver,
+    ExternalIds, GroupBy, InputVerifier, JsonLinesReader, LedgerState, MonotonicIds,
+    NonMonotonicIdAction, NumberLocale, Pagination, ProcessingOutcome, Pseudonymizer,
+    ReloadableRules, ReorderBuffer, ReportLocale, RetryPolicy, ReturnFile, ReturnFormat, RuleSet,
+    Simulation, ThroughputRecorder, Transaction, TransactionError, TransactionFilter,
+    TransactionId, TransactionOutcome, TransactionPolicy, TransactionReader, TransactionService,
+    TransactionServiceBuilder, TransactionSource, TransactionType, UnknownTargetAction,
+    UnsignedInputPolicy,
+};
+
+/// Processes a file of transactions and prints the resulting client balances as csv.
+#[derive(Parser)]
+#[command(
+    version,
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// The sqlite database to store clients and transactions in.
+    #[arg(long, global = true, default_value = "sqlite://:memory:")]
+    database: String,
+    /// Export tracing spans to this OTLP gRPC collector, e.g. `http://localhost:4317`.
+    #[cfg(feature = "otel")]
+    #[arg(long, global = true)]
+    otlp_endpoint: Option<String>,
+    /// POST a json event to this url whenever a chargeback is applied, a client is locked, a
+    /// dispute is escalated or an alert is raised.
+    #[cfg(feature = "webhook")]
+    #[arg(long, global = true)]
+    webhook_url: Option<String>,
+    /// A file containing the secret used to sign webhook events.
+    #[cfg(feature = "webhook")]
+    #[arg(long, global = true, requires = "webhook_url")]
+    webhook_secret_file: Option<String>,
+    /// Email the chargebacks, client locks and large withdrawals configured in this toml file,
+    /// see `EmailConfig`.
+    #[cfg(feature = "email")]
+    #[arg(long, global = true)]
+    email_config: Option<String>,
+    /// Record every change in a hash chained audit log, checked with `verify-chain`.
+    #[arg(long, global = true)]
+    audit_log: bool,
+    /// A file containing a key to replace client ids with in the output and logs, see
+    /// `Pseudonymizer`.
+    #[arg(long, global = true)]
+    pseudonymize_key_file: Option<String>,
+    /// Alert when a transaction drops a client's available funds below this, e.g. `0`.
+    #[arg(long, global = true)]
+    alert_min_available: Option<rust_decimal::Decimal>,
+    /// Alert when a transaction raises a client's held funds above this.
+    #[arg(long, global = true)]
+    alert_max_held: Option<rust_decimal::Decimal>,
+    /// Hold unlocks for a second operator's approval, see the `approve` command.
+    #[arg(long, global = true)]
+    approve_unlocks: bool,
+    /// Hold adjustments larger than this, in either direction, for a second operator's
+    /// approval.
+    #[arg(long, global = true)]
+    approve_adjustments_over: Option<rust_decimal::Decimal>,
+    /// Log a warning with the time spent in each step when processing a single transaction
+    /// takes longer than this many milliseconds.
+    #[arg(long, global = true)]
+    slow_transaction_ms: Option<u64>,
+    /// What to do with disputes of unknown transactions, and resolves and chargebacks of
+    /// transactions that are not under dispute.
+    #[arg(long, global = true, value_enum, default_value_t = UnknownTargets::Ignore)]
+    unknown_targets: UnknownTargets,
+    /// How many times a write is attempted when the database is busy or locked by another
+    /// process, backing off between attempts.
+    #[arg(long, global = true, default_value_t = RetryPolicy::default().max_attempts,
+        value_parser = clap::value_parser!(u32).range(1..))]
+    db_attempts: u32,
+    /// Roll back only the transaction that failed with an error, rejecting it as `failed`,
+    /// instead of the whole batch it is in.
+    #[arg(long, global = true)]
+    isolate_failures: bool,
+    /// A toml file with a `rules` array of validation rules, e.g.
+    /// `reject when type == "withdrawal" && amount > 10000`.
+    #[arg(long, global = true)]
+    rules: Option<String>,
+    /// A toml file with settings that can change without restarting: `rules`, `webhook_url`,
+    /// and the `rate_limit`, `caller_rate_limit` and `client_rate_limit` of `serve`. Each
+    /// replaces its flag, and the file is reloaded on SIGHUP and whenever it is modified.
+    #[arg(long, global = true)]
+    config: Option<String>,
+    /// A toml file with the deposit and withdrawal limits and dispute window, and `[[segments]]`
+    /// replacing them for the clients with a tag.
+    #[arg(long, global = true)]
+    policy_file: Option<String>,
+    /// A rhai script run before every transaction, which can reject or annotate it.
+    #[cfg(feature = "scripting")]
+    #[arg(long, global = true)]
+    validation_script: Option<String>,
+    /// Write the amounts of the client reports with the minor units of this ISO 4217
+    /// currency, e.g. `EUR`, for reports sent to people.
+    #[arg(long, global = true, value_parser = parse_currency)]
+    currency: Option<Currency>,
+    /// Add a `currency` column with the code or symbol of `--currency` to the client reports.
+    #[arg(long, global = true, value_enum, requires = "currency")]
+    currency_column: Option<CurrencyColumn>,
+    /// Write the client and aggregate reports for people in this language, e.g. `de`: with
+    /// translated column titles, grouped amounts in the local format and, where `,` is the
+    /// decimal separator, `;` delimited.
+    #[arg(long, global = true, value_parser = parse_report_locale)]
+    report_locale: Option<ReportLocale>,
+    /// Add the report format version, when the report was generated, the version of this
+    /// program and the id of the input's batch to the client reports, so parsers can detect
+    /// changes to the columns.
+    #[arg(long, global = true, value_enum)]
+    report_metadata: Option<ReportMetadata>,
+    #[command(flatten)]
+    args: Args,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Serve the json HTTP api
+    ///
+    /// SIGUSR1 pauses writes, e.g. to copy the database file, and the next SIGUSR1 resumes them.
+    #[cfg(feature = "server")]
+    Serve(Box<ServeArgs>),
+    /// Process a generated workload and report on the resulting ledger and throughput.
+    /// Fails if the ledger is inconsistent.
+    Simulate(SimulateArgs),
+    /// Check the ledger in `--database` against its audit log. Fails if it was modified.
+    VerifyChain,
+    /// Erase the personal data recorded about a client in `--database`, keeping its balances.
+    ForgetClient(ForgetClientArgs),
+    /// Unlock a client in `--database` that was locked by a chargeback.
+    Unlock {
+        /// The client to unlock.
+        client: ClientId,
+        /// Who is unlocking the client, recorded in the audit log.
+        #[arg(long)]
+        operator: String,
+    },
+    /// Correct a client's available funds in `--database`, e.g.
+    /// `adjust 42 -1.50 --reason "Fee" --operator alice`.
+    Adjust(AdjustArgs),
+    /// Print the unlocks and adjustments in `--database` waiting for approval, as csv.
+    Approvals,
+    /// Approve and apply an unlock or adjustment requested by another operator.
+    Approve {
+        /// The approval id.
+        id: i64,
+        /// Who is approving, which must not be the operator who requested it.
+        #[arg(long)]
+        operator: String,
+    },
+    /// Reject a requested unlock or adjustment.
+    Reject {
+        /// The approval id.
+        id: i64,
+        /// Who is rejecting it.
+        #[arg(long)]
+        operator: String,
+    },
+    /// Print the input file and line a stored deposit or withdrawal in `--database` came from.
+    Provenance {
+        /// The transaction id.
+        tx: TransactionId,
+    },
+    /// Close a business date in `--database`, writing its client balances and the totals of
+    /// the day's changes to an output directory. Needs a database written with `--audit-log`.
+    EodClose(EodCloseArgs),
+    /// Print the clients in `--database` that have not made a transaction for a number of days,
+    /// with their balances, as csv.
+    Dormant {
+        /// How many days a client must have been inactive for.
+        #[arg(long)]
+        days: u64,
+    },
+    /// Print the balances of the house accounts in `--database`, and the funds owed to
+    /// clients, as csv.
+    CashPosition,
+    /// Print the partner-specific client ids in `--database` and the client ids they map to,
+    /// as csv.
+    ExternalIds,
+    /// Print the open disputes in `--database`, with the disputed amount, when they were
+    /// opened, their reason code, notes and workflow status, and their evidence, as csv.
+    Disputes {
+        /// Only print the disputes of this client's transactions.
+        #[arg(long)]
+        client: Option<ClientId>,
+    },
+    /// Assign an open dispute in `--database` or move it through the risk team's workflow,
+    /// printing it as csv, e.g. `update-dispute 42 --status under-review --assignee alice`.
+    UpdateDispute(UpdateDisputeArgs),
+    /// Print the stored deposits and withdrawals in `--database` matching every filter given,
+    /// as csv, e.g. `query --client 42 --type withdrawal --min-amount 1000 --from 2024-01-22`.
+    Query(QueryArgs),
+    /// Print the number and sum of the stored deposits and withdrawals in `--database` by
+    /// type, client or day, as csv, e.g. `aggregate --group-by day --type withdrawal`. Takes
+    /// the filters of `query`.
+    Aggregate(AggregateArgs),
+    /// Print the clients, stored transactions, open disputes and end-of-day closes in
+    /// `--database` as a versioned json document, to load into another database with
+    /// `import-state`.
+    ExportState {
+        /// Write the document to this file instead.
+        #[arg(long)]
+        output: Option<std::path::PathBuf>,
+    },
+    /// Load a document written by `export-state` into `--database`, which must be empty.
+    ImportState {
+        /// The json document.
+        file: std::path::PathBuf,
+    },
+    /// Check the invariants of a ledger loaded into `--database` with `import-state` before it
+    /// goes live, e.g. that held funds match the open disputes, printing every violation and
+    /// failing if there are any.
+    AuditImport {
+        /// Also write the violations to this csv file, with `invariant,client,tx,detail`
+        /// columns.
+        #[arg(long)]
+        report: Option<std::path::PathBuf>,
+    },
+    /// Write a consistent copy of `--database` to a new file, while it is in use, e.g. by
+    /// `serve`.
+    Backup {
+        /// The file to write the copy to, which must not exist.
+        #[arg(long)]
+        to: std::path::PathBuf,
+    },
+    /// Load a copy written by `backup` into `--database`, which must be empty.
+    Restore {
+        /// The copy to load.
+        #[arg(long)]
+        from: std::path::PathBuf,
+    },
+    /// Check the integrity of `--database`, then reclaim free space and update the query
+    /// planner statistics. Fails if it has integrity problems, or if another process is
+    /// writing to it; pause or stop `serve` first.
+    Maintain,
+    /// Print the client balances in `--database` as csv.
+    Report {
+        /// Print the balances as they were at this moment instead, an RFC 3339 timestamp such
+        /// as `2024-01-31T23:59:59Z` or milliseconds since the unix epoch. Needs a database
+        /// written with `--audit-log`.
+        #[arg(long, value_parser = parse_timestamp)]
+        as_of: Option<i64>,
+        /// Also print each client's deposit, withdrawal, dispute and chargeback counts and sums.
+        #[arg(long, conflicts_with = "as_of")]
+        stats: bool,
+        /// Only print the clients with this tag, e.g. `vip`.
+        #[arg(long)]
+        tag: Option<String>,
+    },
+    /// Tag clients in `--database` from a csv file with a `client,tag` header, one tag per
+    /// row, e.g. to report on `vip` and `retail` clients separately with `--tag`.
+    TagClients {
+        /// The csv file.
+        file: std::path::PathBuf,
+    },
+    /// Move the deposits in `--database` held by the settlement delay of `--policy-file` whose
+    /// clearing period has passed to their client's available funds, e.g. from cron when not
+    /// running `serve`, which releases them every minute.
+    ReleaseSettlements,
+    /// Void a deposit in `--database` held by the settlement delay before it clears, e.g.
+    /// `void 42 --reason "Returned by the bank" --operator alice`.
+    Void {
+        /// The deposit's transaction id.
+        tx: TransactionId,
+        /// Why the deposit is voided.
+        #[arg(long)]
+        reason: String,
+        /// Who is voiding it, recorded with the void.
+        #[arg(long)]
+        operator: String,
+    },
+    /// Record every client's balances in `--database`, so `report --as-of` only reads the audit
+    /// log after the last snapshot, e.g. from cron when not running `serve --schedule`.
+    SnapshotBalances,
+}
+
+fn parse_currency(code: &str) -> Result<Currency, String> {
+    Currency::from_code(code).ok_or_else(|| {
+        format!(
+            "unknown currency, expected one of {}",
+            Currency::codes().collect::<Vec<_>>().join(", ")
+        )
+    })
+}
+
+fn parse_report_locale(tag: &str) -> Result<ReportLocale, String> {
+    ReportLocale::from_tag(tag).ok_or_else(|| {
+        format!(
+            "unknown locale, expected one of {}",
+            ReportLocale::tags().collect::<Vec<_>>().join(", ")
+        )
+    })
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum CurrencyColumn {
+    /// The ISO 4217 code, e.g. `EUR`.
+    Code,
+    /// The symbol, e.g. `€`.
+    Symbol,
+}
+
+/// Where the client reports carry their metadata, see `--report-metadata`.
+#[derive(Clone, Copy, ValueEnum)]
+enum ReportMetadata {
+    /// A first line such as `# report_version=1 generated_at=1700000000000 engine_version=0.1.0
+    /// batch=3`, before the header.
+    Comment,
+    /// `report_version,generated_at,engine_version,batch` columns after the others.
+    Columns,
+}
+
+/// The version of the client report columns, increased when they change.
+const REPORT_VERSION: u32 = 1;
+
+/// The `--report-metadata` of a client report.
+#[derive(serde::Serialize)]
+struct MetadataColumns {
+    report_version: u32,
+    /// In milliseconds since the unix epoch.
+    generated_at: u128,
+    engine_version: &'static str,
+    /// Empty for reports not written after processing an input, and for sharded databases.
+    batch: Option<i64>,
+}
+
+/// Writes the rows of a client report with its `--report-metadata`.
+struct ReportWriter<W: io::Write> {
+    w: csv::Writer<W>,
+    columns: Option<MetadataColumns>,
+    /// The locale of the column titles, until they are written with the first row.
+    header: Option<ReportLocale>,
+}
+
+impl<W: io::Write> ReportWriter<W> {
+    fn new(
+        mut out: W,
+        metadata: Option<ReportMetadata>,
+        batch: Option<i64>,
+        locale: Option<ReportLocale>,
+    ) -> io::Result<Self> {
+        let columns = MetadataColumns {
+            report_version: REPORT_VERSION,
+            generated_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis(),
+            engine_version: env!("CARGO_PKG_VERSION"),
+            batch,
+        };
+        let columns = match metadata {
+            Some(ReportMetadata::Columns) => Some(columns),
+            Some(ReportMetadata::Comment) => {
+                writeln!(
+                    out,
+                    "# report_version={} generated_at={} engine_version={} batch={}",
+                    columns.report_version,
+                    columns.generated_at,
+                    columns.engine_version,
+                    batch.map(|b| b.to_string()).unwrap_or_default()
+                )?;
+                None
+            }
+            None => None,
+        };
+        let w = csv::WriterBuilder::new()
+            .has_headers(locale.is_none())
+            .delimiter(locale.map_or(b',', ReportLocale::delimiter))
+            .from_writer(out);
+        Ok(Self {
+            w,
+            columns,
+            header: locale,
+        })
+    }
+
+    fn serialize(&mut self, row: impl serde::Serialize) -> csv::Result<()> {
+        match self.columns.take() {
+            Some(columns) => {
+                let result = self.write((row, &columns));
+                self.columns = Some(columns);
+                result
+            }
+            None => self.write(row),
+        }
+    }
+
+    fn write(&mut self, row: impl serde::Serialize) -> csv::Result<()> {
+        if let Some(locale) = self.header.take() {
+            // The column names are those csv would write as the header
+            let mut names = csv::Writer::from_writer(Vec::new());
+            names.serialize(&row)?;
+            let names = names
+                .into_inner()
+                .map_err(|e| io::Error::from(e.error().kind()))?;
+            let mut names = csv::ReaderBuilder::new()
+                .has_headers(false)
+                .from_reader(names.as_slice());
+            if let Some(names) = names.records().next().transpose()? {
+                self.w
+                    .write_record(names.iter().map(|name| locale.column(name)))?;
+            }
+        }
+        self.w.serialize(row)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+/// How the amounts of the client reports are written.
+#[derive(Clone, Copy, Default)]
+struct AmountFormat {
+    currency: Option<Currency>,
+    column: Option<CurrencyColumn>,
+    locale: Option<ReportLocale>,
+}
+
+impl AmountFormat {
+    fn amount(&self, amount: rust_decimal::Decimal) -> String {
+        let amount = self.currency.map_or(amount, |c| c.round(amount));
+        match self.locale {
+            Some(locale) => locale.format(amount),
+            None => amount.to_string(),
+        }
+    }
+
+    /// The value of the `currency` column, if there is one.
+    fn currency(&self) -> Option<&'static str> {
+        let currency = self.currency?;
+        Some(match self.column? {
+            CurrencyColumn::Code => currency.code(),
+            CurrencyColumn::Symbol => currency.symbol(),
+        })
+    }
+}
+
+/// Parses an RFC 3339 timestamp, or milliseconds since the unix epoch, into the latter.
+fn parse_timestamp(timestamp: &str) -> Result<i64, String> {
+    if let Ok(millis) = timestamp.parse() {
+        return Ok(millis);
+    }
+    let timestamp =
+        time::OffsetDateTime::parse(timestamp, &time::format_description::well_known::Rfc3339)
+            .map_err(|e| e.to_string())?;
+    i64::try_from(timestamp.unix_timestamp_nanos() / 1_000_000).map_err(|e| e.to_string())
+}
+
+/// The filters of the `query` and `aggregate` commands.
+#[derive(clap::Args)]
+struct FilterArgs {
+    /// Only print this client's transactions.
+    #[arg(long)]
+    client: Option<ClientId>,
+    /// Only print transactions of this type, e.g. `withdrawal`.
+    #[arg(long = "type", value_parser = parse_transaction_type)]
+    transaction_type: Option<TransactionType>,
+    /// Only print transactions of at least this amount.
+    #[arg(long)]
+    min_amount: Option<rust_decimal::Decimal>,
+    /// Only print transactions of at most this amount.
+    #[arg(long)]
+    max_amount: Option<rust_decimal::Decimal>,
+    /// Only print transactions applied at or after this moment, an RFC 3339 timestamp, a date
+    /// such as `2024-01-22` (UTC midnight) or milliseconds since the unix epoch.
+    #[arg(long, value_parser = parse_date_or_timestamp)]
+    from: Option<i64>,
+    /// Only print transactions applied before this moment, in the same formats as `--from`.
+    #[arg(long, value_parser = parse_date_or_timestamp)]
+    until: Option<i64>,
+    /// Only print transactions under an open dispute.
+    #[arg(long, conflicts_with = "undisputed")]
+    disputed: bool,
+    /// Only print transactions that are not under an open dispute.
+    #[arg(long)]
+    undisputed: bool,
+    /// Only print transactions of clients with this tag, e.g. `vip`.
+    #[arg(long)]
+    tag: Option<String>,
+}
+
+impl FilterArgs {
+    fn filter(self) -> TransactionFilter {
+        TransactionFilter {
+            client_id: self.client,
+            transaction_type: self.transaction_type,
+            disputed: (self.disputed || self.undisputed).then_some(self.disputed),
+            min_amount: self.min_amount,
+            max_amount: self.max_amount,
+            from: self.from,
+            until: self.until,
+            tag: self.tag,
+        }
+    }
+}
+
+#[derive(clap::Args)]
+struct QueryArgs {
+    #[command(flatten)]
+    filter: FilterArgs,
+    /// Only print transactions with an id greater than this, the last id of the previous page.
+    #[arg(long)]
+    after: Option<TransactionId>,
+    /// Print at most this many transactions.
+    #[arg(long)]
+    limit: Option<u32>,
+}
+
+#[derive(clap::Args)]
+struct AggregateArgs {
+    /// What to group the transactions by.
+    #[arg(long, value_enum)]
+    group_by: Group,
+    #[command(flatten)]
+    filter: FilterArgs,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Group {
+    /// The transaction type.
+    Type,
+    /// The client.
+    Client,
+    /// The UTC date the transaction was applied.
+    Day,
+}
+
+fn parse_transaction_type(name: &str) -> Result<TransactionType, String> {
+    TransactionType::from_str(name).ok_or_else(|| "empty transaction type".to_string())
+}
+
+/// Parses a date as its UTC midnight, or a timestamp like [`parse_timestamp`].
+fn parse_date_or_timestamp(value: &str) -> Result<i64, String> {
+    parse_timestamp(&format!("{}T00:00:00Z", value)).or_else(|_| parse_timestamp(value))
+}
+
+#[derive(clap::Args)]
+struct ForgetClientArgs {
+    /// The client to erase.
+    client: ClientId,
+    /// Who requested the erasure, recorded with it.
+    #[arg(long)]
+    operator: String,
+    /// Whether to keep the client's transactions, or remove them too.
+    #[arg(long, value_enum, default_value_t = Erasure::Anonymize)]
+    policy: Erasure,
+}
+
+#[derive(clap::Args)]
+struct AdjustArgs {
+    /// The client to adjust.
+    client: ClientId,
+    /// Added to the client's available funds, negative to deduct.
+    #[arg(allow_negative_numbers = true)]
+    amount: rust_decimal::Decimal,
+    /// Why the adjustment was made.
+    #[arg(long)]
+    reason: String,
+    /// Who is making the adjustment, recorded with it.
+    #[arg(long)]
+    operator: String,
+}
+
+#[derive(clap::Args)]
+struct EodCloseArgs {
+    /// The business date to close, e.g. `2024-01-31`. Defaults to the current UTC date.
+    #[arg(long, value_parser = parse_business_date)]
+    date: Option<time::Date>,
+    /// The directory to write `<date>-clients.csv` and `<date>-totals.csv` to.
+    #[arg(long)]
+    output_dir: std::path::PathBuf,
+    /// Write the files of a date that is already closed again, without closing anything.
+    #[arg(long)]
+    reemit: bool,
+}
+
+fn parse_business_date(date: &str) -> Result<time::Date, String> {
+    time::Date::parse(date, &time::format_description::well_known::Iso8601::DATE)
+        .map_err(|e| e.to_string())
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Erasure {
+    Anonymize,
+    Tombstone,
+}
+
+#[derive(clap::Args)]
+struct UpdateDisputeArgs {
+    /// The id of the disputed transaction.
+    tx: TransactionId,
+    /// Move the dispute to this status. `resolved` and `charged-back` resolve or charge back
+    /// the disputed transaction.
+    #[arg(long, value_enum)]
+    status: Option<DisputeState>,
+    /// Assign the dispute to this member of the risk team.
+    #[arg(long)]
+    assignee: Option<String>,
+    /// Remove the dispute's assignee.
+    #[arg(long, conflicts_with = "assignee")]
+    unassign: bool,
+    /// Attach a URL or blob hash of evidence for the dispute. Can be given more than once.
+    #[arg(long)]
+    evidence: Vec<String>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DisputeState {
+    Open,
+    UnderReview,
+    AwaitingEvidence,
+    Resolved,
+    ChargedBack,
+}
+
+#[derive(clap::Args)]
+struct SimulateArgs {
+    /// The seed the workload is generated from.
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// The number of transactions to generate.
+    #[arg(long, default_value_t = 10_000)]
+    transactions: u32,
+    /// The number of clients the transactions are spread over.
+    #[arg(long, default_value_t = 100)]
+    clients: u16,
+}
+
+#[cfg(feature = "server")]
+#[derive(clap::Args)]
+struct ServeArgs {
+    /// The address to listen on.
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    listen: String,
+    /// Also serve the gRPC api on this address.
+    #[cfg(feature = "grpc")]
+    #[arg(long)]
+    grpc_listen: Option<std::net::SocketAddr>,
+    /// A csv of static bearer tokens with `name`, `role` and `token` columns.
+    #[arg(long)]
+    tokens: Option<String>,
+    /// A file containing the secret used to verify HS256 JWT bearer tokens.
+    #[arg(long)]
+    jwt_secret_file: Option<String>,
+    /// A PEM certificate chain to serve both apis over TLS with. Requires `--tls-key`.
+    #[cfg(feature = "tls")]
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<String>,
+    /// The PEM private key of `--tls-cert`.
+    #[cfg(feature = "tls")]
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<String>,
+    /// The transactions per second that can be submitted by all callers together.
+    #[arg(long)]
+    rate_limit: Option<NonZeroU32>,
+    /// The transactions per second that can be submitted by each authenticated caller.
+    #[arg(long)]
+    caller_rate_limit: Option<NonZeroU32>,
+    /// The transactions per second that can be submitted for each client.
+    #[arg(long)]
+    client_rate_limit: Option<NonZeroU32>,
+    /// Reject submissions once this many in a row failed because the database was
+    /// unavailable, until it recovers. 0 keeps accepting them.
+    #[arg(long, default_value_t = 5)]
+    circuit_failures: u32,
+    /// How many seconds submissions are rejected for before one is let through to probe
+    /// whether the database recovered.
+    #[arg(long, default_value_t = 30)]
+    circuit_open_secs: u64,
+    /// Ship every change to the ledger to `kafka://<brokers>/<topic>`, through an outbox table.
+    #[cfg(feature = "kafka")]
+    #[arg(long)]
+    outbox: Option<String>,
+    /// Serve without authentication.
+    #[arg(long, conflicts_with_all = ["tokens", "jwt_secret_file"])]
+    no_auth: bool,
+    /// Expire disputes that have held a client's funds for longer than this many seconds.
+    #[arg(long)]
+    max_hold_age: Option<u64>,
+    /// What to do with disputes older than `--max-hold-age`.
+    #[arg(long, value_enum, default_value_t = ExpiredHolds::Resolve, requires = "max_hold_age")]
+    expired_holds: ExpiredHolds,
+    /// A toml file of cron schedules for the periodic jobs: hold expiry, settlement release,
+    /// dormancy checks and closing the business date. `--max-hold-age` replaces its
+    /// `expire_holds` job, and settlements are released every minute unless it schedules them.
+    #[arg(long)]
+    schedule: Option<String>,
+    /// Serve queries from a read-only copy of `--database`, kept up to date by e.g. LiteFS or
+    /// Litestream. Can be given multiple times to take turns between copies.
+    #[arg(long)]
+    read_replica: Vec<String>,
+    /// Read the data of this many of the most active clients before serving, so the first
+    /// requests for them do not wait on the disk.
+    #[arg(long, default_value_t = 0)]
+    warm_up_clients: u32,
+}
+
+#[cfg(feature = "server")]
+#[derive(Clone, Copy, ValueEnum)]
+enum ExpiredHolds {
+    /// Release the held funds back to the client's available funds.
+    Resolve,
+    /// Keep the funds held and notify the webhook once.
+    Escalate,
+}
+
+/// How many live updates a slow `/events` subscriber can fall behind by.
+#[cfg(feature = "server")]
+const LIVE_UPDATE_CAPACITY: usize = 1024;
+
+/// The longest the scheduler sleeps between checking for due jobs.
+#[cfg(feature = "server")]
+const SCHEDULER_MAX_WAIT: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// The interval `--throughput-stats` counts the committed transactions of.
+const THROUGHPUT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// How often `--config` is checked for modifications.
+const CONFIG_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// How often the outbox is checked for new events once it is empty.
+#[cfg(feature = "kafka")]
+const OUTBOX_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+#[derive(clap::Args)]
+struct Args {
+    /// The transactions to process: a file, `-` for stdin, or `kafka://<brokers>/<topic>[?group=<id>]`
+    #[arg(required = true)]
+    input: Option<String>,
+    /// The input format. Detected from the file extension (`.jsonl`, `.ndjson`) when not set.
+    #[arg(long, value_enum)]
+    format: Option<InputFormat>,
+    /// A minisign public key file. The input must have a valid detached signature from one of
+    /// the given keys in `<input>.minisig`, or be listed in `--checksums`.
+    #[arg(long)]
+    public_key: Vec<String>,
+    /// A `sha256sum` manifest the input's checksum must match, if it is listed in it.
+    #[arg(long)]
+    checksums: Option<String>,
+    /// What to do with inputs that are neither signed nor in `--checksums`, when either is
+    /// given. Inputs with an invalid signature or checksum are always rejected.
+    #[arg(long, value_enum, default_value_t = UnsignedInput::Reject)]
+    unsigned_input: UnsignedInput,
+    /// Write every transaction that was not applied to this csv file, with its input line and
+    /// the reason, e.g. `insufficient_funds` or `duplicate`.
+    #[arg(long)]
+    rejected: Option<std::path::PathBuf>,
+    /// Acknowledge the input with a return file listing every transaction as accepted or
+    /// rejected, with a reason code, for the partner who sent it.
+    #[arg(long)]
+    return_file: Option<std::path::PathBuf>,
+    /// The format of `--return-file`.
+    #[arg(long, value_enum, default_value_t = ReturnFileFormat::Csv, requires = "return_file")]
+    return_format: ReturnFileFormat,
+    /// Also print each client's deposit, withdrawal, dispute and chargeback counts and sums
+    /// with its balances.
+    #[arg(long)]
+    stats: bool,
+    /// Only print the clients whose balances the input changed, rather than every client in
+    /// the database.
+    #[arg(long)]
+    changed_only: bool,
+    /// Write a client's balances to stdout as a json line whenever the input changes them,
+    /// instead of printing the clients once the input is processed.
+    #[arg(long, conflicts_with_all = ["shards", "stats", "changed_only"])]
+    stream_updates: bool,
+    /// Apply the input without storing it, and print how it would change the balances of the
+    /// clients it touches instead of the clients.
+    #[arg(long, conflicts_with_all = ["shards", "stream_updates", "stats", "changed_only", "return_file"])]
+    dry_run: bool,
+    /// Write the number of transactions committed in each minute of the run, their input lines
+    /// and the percentiles of their latency from being read to being committed, to this json
+    /// file.
+    #[arg(long, conflicts_with_all = ["shards", "dry_run"])]
+    throughput_stats: Option<std::path::PathBuf>,
+    /// Hold back up to this many transactions and apply them in the order of their `sequence`
+    /// column or field, for inputs that are delivered slightly out of order.
+    #[arg(long)]
+    reorder_buffer: Option<usize>,
+    /// Require the deposit and withdrawal ids of the input to be strictly increasing, rejecting
+    /// the transactions that are not, or applying them with a warning.
+    #[arg(long, value_enum)]
+    strict_ids: Option<StrictIds>,
+    /// Replay historical transactions without side effects, to rebuild a ledger: no webhooks
+    /// or alerts are sent, and `--rules` and `--validation-script` are not applied.
+    #[arg(long)]
+    backfill: bool,
+    /// The `client` column or field holds partner-specific string ids, which are mapped to
+    /// client ids stored in `--database`, creating new clients on first sight.
+    #[arg(long)]
+    external_ids: bool,
+    /// How the amounts of the input are written. Csv amounts with a `,` must be quoted, e.g.
+    /// `deposit,1,1,"1.234,56"`.
+    #[arg(long, value_enum, default_value_t = AmountLocale::Plain)]
+    number_locale: AmountLocale,
+    /// Spread clients over this many databases, written at the same time: `--database` with
+    /// `.0`, `.1` and so on appended. Always use the same number of shards for a database.
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..), conflicts_with = "external_ids")]
+    shards: Option<u16>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum AmountLocale {
+    /// `1234.56`
+    Plain,
+    /// `1,234.56` or `1 234.56`
+    Point,
+    /// `1.234,56` or `1 234,56`
+    Comma,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum UnknownTargets {
+    /// Ignore them with the reason `unknown_target` or `not_disputed`.
+    Ignore,
+    /// Ignore them, logging a warning and counting them in the server's `/metrics`.
+    Warn,
+    /// Stop processing, rolling back the transactions not yet committed.
+    Error,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ReturnFileFormat {
+    /// `line,tx,type,status,reason_code,reason`
+    Csv,
+    /// An ISO 20022 pain.002 payment status report.
+    Pain002,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum StrictIds {
+    /// Reject them with the reason `non_monotonic_id`.
+    Reject,
+    /// Apply them, logging a warning and annotating them with `non_monotonic_id`.
+    Warn,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum InputFormat {
+    Csv,
+    Jsonl,
+}
 
-use transactions::{TransactionReader, TransactionService};
+#[derive(Clone, Copy, ValueEnum)]
+enum UnsignedInput {
+    Reject,
+    Warn,
+}
+
+/// A row of the output with the client id replaced by its pseudonym or the amounts formatted
+/// for `--currency`.
+#[derive(serde::Serialize)]
+struct ClientRow {
+    client: String,
+    available: String,
+    held: String,
+    total: String,
+    locked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    currency: Option<&'static str>,
+}
+
+/// A row of the `--stats` output, with the client id pseudonymized if enabled.
+#[derive(serde::Serialize)]
+struct ClientStatsRow {
+    client: String,
+    available: String,
+    held: String,
+    total: String,
+    locked: bool,
+    deposits: u64,
+    deposited: String,
+    withdrawals: u64,
+    withdrawn: String,
+    disputes: u64,
+    chargebacks: u64,
+    risk_score: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    currency: Option<&'static str>,
+}
+
+/// The clients with `tag`, or `None` to include every client.
+async fn get_tagged_clients(
+    transaction_svc: &TransactionService,
+    tag: Option<&str>,
+) -> anyhow::Result<Option<HashSet<ClientId>>> {
+    Ok(match tag {
+        Some(tag) => Some(
+            (transaction_svc.get_tagged_clients(tag).await?)
+                .into_iter()
+                .collect(),
+        ),
+        None => None,
+    })
+}
+
+/// The clients whose balances the transactions of `batch` changed, or `None` to include every
+/// client.
+async fn get_changed_clients(
+    transaction_svc: &TransactionService,
+    batch: Option<i64>,
+) -> anyhow::Result<Option<HashSet<ClientId>>> {
+    let Some(batch) = batch else {
+        return Ok(None);
+    };
+    let filter = ClientFilter {
+        changed_in_batch: Some(batch),
+        ..Default::default()
+    };
+    let clients = transaction_svc
+        .get_clients(&filter, Pagination::default())
+        .await;
+    Ok(Some(clients.map_ok(|c| c.id).try_collect().await?))
+}
 
-async fn print_client_csv(transaction_svc: &mut TransactionService) -> anyhow::Result<()> {
-    let stdout = io::stdout().lock();
+fn is_included(included: &Option<HashSet<ClientId>>, client_id: ClientId) -> bool {
+    included
+        .as_ref()
+        .is_none_or(|included| included.contains(&client_id))
+}
 
-    let mut w = csv::Writer::from_writer(stdout);
-    let mut client_stream = transaction_svc.get_clients().await;
+/// Prints the clients with `tag`, or changed by the transactions of `changed_in_batch`, or
+/// every client.
+async fn print_client_csv(
+    transaction_svc: &mut TransactionService,
+    stats: bool,
+    tag: Option<&str>,
+    changed_in_batch: Option<i64>,
+    amounts: &AmountFormat,
+    metadata: Option<ReportMetadata>,
+    batch: Option<i64>,
+) -> anyhow::Result<()> {
+    let mut w = ReportWriter::new(io::stdout().lock(), metadata, batch, amounts.locale)?;
+    if stats {
+        let tagged = get_tagged_clients(transaction_svc, tag).await?;
+        let changed = get_changed_clients(transaction_svc, changed_in_batch).await?;
+        for client in transaction_svc.get_client_stats().await? {
+            let id = client.client.id;
+            if is_included(&tagged, id) && is_included(&changed, id) {
+                write_client_stats(&mut w, transaction_svc.pseudonymizer(), client, amounts)?;
+            }
+        }
+        return Ok(());
+    }
+    let filter = ClientFilter {
+        tag: tag.map(str::to_string),
+        changed_in_batch,
+        ..Default::default()
+    };
+    let mut client_stream = transaction_svc
+        .get_clients(&filter, Pagination::default())
+        .await;
     while let Some(c) = client_stream.try_next().await? {
-        w.serialize(c)?;
+        write_client(&mut w, transaction_svc.pseudonymizer(), c, amounts)?;
     }
 
     Ok(())
 }
 
-fn get_transaction_reader() -> anyhow::Result<TransactionReader<std::io::BufReader<std::fs::File>>>
-{
-    let transaction_file = match std::env::args().skip(1).next() {
-        Some(f) => f,
-        None => {
-            anyhow::bail!("Usage: {}.exe <transaction-file>", env!("CARGO_PKG_NAME"));
+fn write_client_stats(
+    w: &mut ReportWriter<impl io::Write>,
+    pseudonymizer: Option<&Pseudonymizer>,
+    ClientWithStats { client: c, stats }: ClientWithStats,
+    amounts: &AmountFormat,
+) -> csv::Result<()> {
+    w.serialize(ClientStatsRow {
+        client: client_label(pseudonymizer, c.id),
+        available: amounts.amount(c.available),
+        held: amounts.amount(c.held),
+        total: amounts.amount(c.total),
+        locked: c.locked,
+        deposits: stats.deposits,
+        deposited: amounts.amount(stats.deposited),
+        withdrawals: stats.withdrawals,
+        withdrawn: amounts.amount(stats.withdrawn),
+        disputes: stats.disputes,
+        chargebacks: stats.chargebacks,
+        risk_score: stats.risk_score,
+        currency: amounts.currency(),
+    })
+}
+
+fn write_client(
+    w: &mut ReportWriter<impl io::Write>,
+    pseudonymizer: Option<&Pseudonymizer>,
+    c: Client,
+    amounts: &AmountFormat,
+) -> csv::Result<()> {
+    if pseudonymizer.is_none() && amounts.currency.is_none() && amounts.locale.is_none() {
+        return w.serialize(c);
+    }
+    w.serialize(client_row(pseudonymizer, &c, amounts))
+}
+
+/// How client `client_id` is written in reports: its pseudonym when the service pseudonymizes
+/// client ids, or the id itself.
+fn client_label(pseudonymizer: Option<&Pseudonymizer>, client_id: ClientId) -> String {
+    match pseudonymizer {
+        Some(pseudonymizer) => pseudonymizer.client(client_id),
+        None => client_id.to_string(),
+    }
+}
+
+fn client_row(
+    pseudonymizer: Option<&Pseudonymizer>,
+    c: &Client,
+    amounts: &AmountFormat,
+) -> ClientRow {
+    ClientRow {
+        client: client_label(pseudonymizer, c.id),
+        available: amounts.amount(c.available),
+        held: amounts.amount(c.held),
+        total: amounts.amount(c.total),
+        locked: c.locked,
+        currency: amounts.currency(),
+    }
+}
+
+/// Sends the ids of the clients whose balances changed to [`stream_updates`].
+struct ClientUpdates(tokio::sync::mpsc::UnboundedSender<ClientId>);
+
+impl ClientUpdates {
+    fn send(&self, client_id: ClientId) {
+        // The receiver is only dropped once processing is done
+        let _ = self.0.send(client_id);
+    }
+}
+
+impl EventObserver for ClientUpdates {
+    fn on_deposit(&self, deposit: &Transaction) {
+        self.send(deposit.client_id);
+    }
+
+    fn on_withdrawal(&self, withdrawal: &Transaction) {
+        self.send(withdrawal.client_id);
+    }
+
+    fn on_dispute_opened(&self, disputed: &Transaction) {
+        self.send(disputed.client_id);
+    }
+
+    fn on_dispute_resolved(&self, disputed: &Transaction) {
+        self.send(disputed.client_id);
+    }
+
+    fn on_chargeback(&self, disputed: &Transaction) {
+        self.send(disputed.client_id);
+    }
+
+    fn on_custom(&self, transaction: &Transaction) {
+        self.send(transaction.client_id);
+    }
+}
+
+/// Runs `processing`, writing the balances of each client it changes to `out` as a json line
+/// once the change is committed. Clients changed again before their line is written show the
+/// later balances.
+async fn stream_updates<T>(
+    transaction_svc: &TransactionService,
+    mut updates: tokio::sync::mpsc::UnboundedReceiver<ClientId>,
+    amounts: &AmountFormat,
+    mut out: impl io::Write,
+    processing: impl std::future::Future<Output = T>,
+) -> anyhow::Result<T> {
+    let (done_tx, mut done) = tokio::sync::oneshot::channel();
+    let processing = async {
+        let result = processing.await;
+        let _ = done_tx.send(());
+        result
+    };
+    // Json amounts are not localized
+    let amounts = AmountFormat {
+        locale: None,
+        ..*amounts
+    };
+    let mut write = |client: Client| -> anyhow::Result<()> {
+        let row = client_row(transaction_svc.pseudonymizer(), &client, &amounts);
+        serde_json::to_writer(&mut out, &row)?;
+        out.write_all(b"\n")?;
+        out.flush()?;
+        Ok(())
+    };
+    let writer = async {
+        loop {
+            let client_id = tokio::select! {
+                biased;
+                Some(client_id) = updates.recv() => client_id,
+                _ = &mut done => break,
+            };
+            if let Some(client) = transaction_svc.get_client(client_id).await? {
+                write(client)?;
+            }
         }
+        // The changes committed last
+        while let Ok(client_id) = updates.try_recv() {
+            if let Some(client) = transaction_svc.get_client(client_id).await? {
+                write(client)?;
+            }
+        }
+        anyhow::Ok(())
+    };
+    let (result, written) = tokio::join!(processing, writer);
+    written?;
+    Ok(result)
+}
+
+async fn eod_close(args: EodCloseArgs, builder: TransactionServiceBuilder) -> anyhow::Result<()> {
+    let transaction_svc = builder
+        .audit_log(true)
+        .build()
+        .await
+        .context("Failed to get transaction service")?;
+    let date = args
+        .date
+        .unwrap_or_else(|| time::OffsetDateTime::now_utc().date());
+    let close = if args.reemit {
+        transaction_svc
+            .get_day_close(date)
+            .await?
+            .with_context(|| format!("Business date {} is not closed", date))?
+    } else {
+        transaction_svc.close_day(date).await?
     };
 
-    let f = File::open(&transaction_file).map_err(|_| {
-        anyhow::format_err!(
-            "Could not locate the transaction file \"{}\"",
-            &transaction_file
+    let path = |name: &str| args.output_dir.join(format!("{}-{}.csv", date, name));
+    let write = || -> anyhow::Result<()> {
+        std::fs::create_dir_all(&args.output_dir)?;
+        let mut w = ReportWriter::new(File::create(path("clients"))?, None, None, None)?;
+        // Kept in the machine format
+        for c in close.clients {
+            write_client(
+                &mut w,
+                transaction_svc.pseudonymizer(),
+                c,
+                &AmountFormat::default(),
+            )?;
+        }
+        w.flush()?;
+        let mut w = csv::Writer::from_path(path("totals"))?;
+        for total in close.totals {
+            w.serialize(total)?;
+        }
+        w.flush()?;
+        Ok(())
+    };
+    write().with_context(|| {
+        format!(
+            "Business date {} is closed but its files could not be written, write them with --reemit",
+            date
         )
     })?;
-    Ok(TransactionReader::new(io::BufReader::new(f)))
+    eprintln!(
+        "{} closed at checkpoint {}, wrote {} and {}",
+        date,
+        close.checkpoint,
+        path("clients").display(),
+        path("totals").display()
+    );
+    Ok(())
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let mut transaction_reader = get_transaction_reader()?;
+/// A row of the dormant client report.
+#[derive(serde::Serialize)]
+struct DormantRow {
+    client: String,
+    available: rust_decimal::Decimal,
+    held: rust_decimal::Decimal,
+    total: rust_decimal::Decimal,
+    locked: bool,
+    /// In milliseconds since the unix epoch, empty if unknown.
+    last_activity_at: Option<i64>,
+}
 
-    let mut transaction_svc = {
-        let options = SqliteConnectOptions::from_str("sqlite://:memory:")?.create_if_missing(true);
-        let db_pool = sqlx::sqlite::SqlitePool::connect_with(options).await?;
-        TransactionService::new(db_pool)
-            .await
-            .context("Failed to get transaction service")?
+async fn dormant(days: u64, builder: TransactionServiceBuilder) -> anyhow::Result<()> {
+    let transaction_svc = builder
+        .build()
+        .await
+        .context("Failed to get transaction service")?;
+    let inactive_for = std::time::Duration::from_secs(days.saturating_mul(24 * 60 * 60));
+    let mut w = csv::Writer::from_writer(io::stdout().lock());
+    for dormant in transaction_svc.get_dormant_clients(inactive_for).await? {
+        let c = dormant.client;
+        w.serialize(DormantRow {
+            client: client_label(transaction_svc.pseudonymizer(), c.id),
+            available: c.available,
+            held: c.held,
+            total: c.total,
+            locked: c.locked,
+            last_activity_at: dormant.last_activity_at,
+        })?;
+    }
+    Ok(())
+}
+
+/// A row of the cash position report.
+#[derive(serde::Serialize)]
+struct CashPositionRow<'a> {
+    account: &'a str,
+    balance: rust_decimal::Decimal,
+}
+
+async fn cash_position(builder: TransactionServiceBuilder) -> anyhow::Result<()> {
+    let transaction_svc = builder
+        .build()
+        .await
+        .context("Failed to get transaction service")?;
+    let position = transaction_svc.get_cash_position().await?;
+    let mut w = csv::Writer::from_writer(io::stdout().lock());
+    for account in &position.accounts {
+        w.serialize(CashPositionRow {
+            account: &account.name,
+            balance: account.balance,
+        })?;
+    }
+    w.serialize(CashPositionRow {
+        account: "client_funds",
+        balance: position.client_funds,
+    })?;
+    Ok(())
+}
+
+/// A row of the external client ids report.
+#[derive(serde::Serialize)]
+struct ExternalIdRow<'a> {
+    external_id: &'a str,
+    client: String,
+}
+
+async fn external_ids(builder: TransactionServiceBuilder) -> anyhow::Result<()> {
+    let transaction_svc = builder
+        .build()
+        .await
+        .context("Failed to get transaction service")?;
+    let mut w = csv::Writer::from_writer(io::stdout().lock());
+    for mapping in transaction_svc.get_external_ids().await?.mappings() {
+        w.serialize(ExternalIdRow {
+            external_id: &mapping.external_id,
+            client: client_label(transaction_svc.pseudonymizer(), mapping.client),
+        })?;
+    }
+    Ok(())
+}
+
+/// A row of the open disputes report.
+#[derive(serde::Serialize)]
+struct DisputeRow {
+    tx: TransactionId,
+    client: String,
+    amount: Option<rust_decimal::Decimal>,
+    /// In milliseconds since the unix epoch.
+    opened_at: i64,
+    escalated_at: Option<i64>,
+    reason_code: Option<String>,
+    notes: Option<String>,
+    status: &'static str,
+    assignee: Option<String>,
+    /// The references of the dispute's evidence, separated by spaces.
+    evidence: String,
+}
+
+impl DisputeRow {
+    fn new(dispute: Dispute, transaction_svc: &TransactionService) -> Self {
+        let t = dispute.transaction;
+        Self {
+            tx: t.id,
+            client: client_label(transaction_svc.pseudonymizer(), t.client_id),
+            amount: t.amount,
+            opened_at: dispute.opened_at,
+            escalated_at: dispute.escalated_at,
+            reason_code: dispute.reason_code,
+            notes: dispute.notes,
+            status: dispute.status.to_str(),
+            assignee: dispute.assignee,
+            evidence: (dispute.evidence.iter())
+                .map(|e| e.reference.as_str())
+                .collect::<Vec<_>>()
+                .join(" "),
+        }
+    }
+}
+
+async fn disputes(
+    client: Option<ClientId>,
+    builder: TransactionServiceBuilder,
+) -> anyhow::Result<()> {
+    let transaction_svc = builder
+        .build()
+        .await
+        .context("Failed to get transaction service")?;
+    let mut w = csv::Writer::from_writer(io::stdout().lock());
+    let mut disputes = transaction_svc.get_open_disputes(client);
+    while let Some(dispute) = disputes.try_next().await? {
+        w.serialize(DisputeRow::new(dispute, &transaction_svc))?;
+    }
+    Ok(())
+}
+
+async fn update_dispute(
+    args: UpdateDisputeArgs,
+    builder: TransactionServiceBuilder,
+) -> anyhow::Result<()> {
+    let transaction_svc = builder
+        .build()
+        .await
+        .context("Failed to get transaction service")?;
+    let mut dispute = None;
+    for reference in &args.evidence {
+        dispute = Some(
+            (transaction_svc.add_dispute_evidence(args.tx, reference))
+                .await
+                .with_context(|| {
+                    format!("Failed to attach evidence to the dispute of {}", args.tx)
+                })?,
+        );
+    }
+    if args.assignee.is_some() || args.unassign {
+        dispute = Some(
+            (transaction_svc.assign_dispute(args.tx, args.assignee.as_deref()))
+                .await
+                .with_context(|| format!("Failed to assign the dispute of {}", args.tx))?,
+        );
+    }
+    if let Some(status) = args.status {
+        let status = match status {
+            DisputeState::Open => DisputeStatus::Open,
+            DisputeState::UnderReview => DisputeStatus::UnderReview,
+            DisputeState::AwaitingEvidence => DisputeStatus::AwaitingEvidence,
+            DisputeState::Resolved => DisputeStatus::Resolved,
+            DisputeState::ChargedBack => DisputeStatus::ChargedBack,
+        };
+        dispute = Some(
+            (transaction_svc.update_dispute_status(args.tx, status))
+                .await
+                .with_context(|| format!("Failed to update the dispute of {}", args.tx))?,
+        );
+    }
+    let dispute = dispute
+        .context("Nothing to update, give --status, --assignee, --unassign or --evidence")?;
+    let mut w = csv::Writer::from_writer(io::stdout().lock());
+    w.serialize(DisputeRow::new(dispute, &transaction_svc))?;
+    Ok(())
+}
+
+/// A row of the transaction search results.
+#[derive(serde::Serialize)]
+struct TransactionRow {
+    #[serde(rename = "type")]
+    transaction_type: TransactionType,
+    client: String,
+    tx: TransactionId,
+    amount: Option<rust_decimal::Decimal>,
+    reference: Option<String>,
+}
+
+async fn query(args: QueryArgs, builder: TransactionServiceBuilder) -> anyhow::Result<()> {
+    let transaction_svc = builder
+        .build()
+        .await
+        .context("Failed to get transaction service")?;
+    let filter = args.filter.filter();
+    let pagination = Pagination {
+        after: args.after,
+        limit: args.limit,
+    };
+    let mut w = csv::Writer::from_writer(io::stdout().lock());
+    let mut transactions = transaction_svc.search_transactions(&filter, pagination);
+    while let Some(t) = transactions.try_next().await? {
+        w.serialize(TransactionRow {
+            transaction_type: t.transaction_type,
+            client: client_label(transaction_svc.pseudonymizer(), t.client_id),
+            tx: t.id,
+            amount: t.amount,
+            reference: t.reference,
+        })?;
+    }
+    Ok(())
+}
+
+async fn tag_clients(
+    file: &std::path::Path,
+    builder: TransactionServiceBuilder,
+) -> anyhow::Result<()> {
+    let transaction_svc = builder
+        .build()
+        .await
+        .context("Failed to get transaction service")?;
+    let input =
+        File::open(file).with_context(|| format!("Failed to open \"{}\"", file.display()))?;
+    let tags = transaction_app::read_client_tags(input)
+        .with_context(|| format!("Failed to read \"{}\"", file.display()))?;
+    let added = transaction_svc.tag_clients(&tags).await?;
+    eprintln!("Added {} of {} tags", added, tags.len());
+    Ok(())
+}
+
+async fn release_settlements(builder: TransactionServiceBuilder) -> anyhow::Result<()> {
+    let released = builder
+        .build()
+        .await
+        .context("Failed to get transaction service")?
+        .release_settlements()
+        .await
+        .context("Failed to release the cleared deposits")?;
+    eprintln!("Released {} cleared deposits", released.len());
+    Ok(())
+}
+
+async fn void_deposit(
+    transaction_id: TransactionId,
+    reason: &str,
+    operator: &str,
+    builder: TransactionServiceBuilder,
+) -> anyhow::Result<()> {
+    let transaction_svc = builder
+        .build()
+        .await
+        .context("Failed to get transaction service")?;
+    let void = transaction_svc
+        .void_deposit(transaction_id, reason, operator)
+        .await
+        .with_context(|| format!("Failed to void deposit {}", transaction_id))?;
+    println!(
+        "Voided deposit {} of {} for client {} ({})",
+        void.transaction_id,
+        void.amount,
+        client_label(transaction_svc.pseudonymizer(), void.client_id),
+        void.operator
+    );
+    Ok(())
+}
+
+async fn snapshot_balances(builder: TransactionServiceBuilder) -> anyhow::Result<()> {
+    let snapshot = builder
+        .audit_log(true)
+        .build()
+        .await
+        .context("Failed to get transaction service")?
+        .snapshot_balances()
+        .await
+        .context("Failed to snapshot the balances")?;
+    eprintln!(
+        "Snapshotted the balances of {} clients at audit log record {}",
+        snapshot.clients, snapshot.checkpoint
+    );
+    Ok(())
+}
+
+/// A row of the aggregate report, with client groups pseudonymized if enabled.
+#[derive(serde::Serialize)]
+struct AggregateRow {
+    group: Option<String>,
+    count: u64,
+    amount: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    currency: Option<&'static str>,
+}
+
+async fn aggregate(
+    args: AggregateArgs,
+    amounts: &AmountFormat,
+    builder: TransactionServiceBuilder,
+) -> anyhow::Result<()> {
+    let transaction_svc = builder
+        .build()
+        .await
+        .context("Failed to get transaction service")?;
+    let group_by = match args.group_by {
+        Group::Type => GroupBy::TransactionType,
+        Group::Client => GroupBy::Client,
+        Group::Day => GroupBy::Day,
     };
+    let aggregates = transaction_svc
+        .get_aggregates(group_by, &args.filter.filter())
+        .await?;
+    let mut w = ReportWriter::new(io::stdout().lock(), None, None, amounts.locale)?;
+    for aggregate in aggregates {
+        let group = match (group_by, transaction_svc.pseudonymizer(), aggregate.group) {
+            (GroupBy::Client, Some(pseudonymizer), Some(client)) => {
+                Some(pseudonymizer.client(client.parse()?))
+            }
+            (_, _, group) => group,
+        };
+        w.serialize(AggregateRow {
+            group,
+            count: aggregate.count,
+            amount: amounts.amount(aggregate.amount),
+            currency: amounts.currency(),
+        })?;
+    }
+    Ok(())
+}
+
+async fn export_state(
+    output: Option<std::path::PathBuf>,
+    builder: TransactionServiceBuilder,
+) -> anyhow::Result<()> {
+    let transaction_svc = builder
+        .build()
+        .await
+        .context("Failed to get transaction service")?;
+    let state = transaction_svc.export_state().await?;
+    match output {
+        Some(path) => {
+            let file = File::create(&path)
+                .with_context(|| format!("Failed to create \"{}\"", path.display()))?;
+            serde_json::to_writer_pretty(io::BufWriter::new(file), &state)?;
+        }
+        None => serde_json::to_writer_pretty(io::stdout().lock(), &state)?,
+    }
+    Ok(())
+}
 
-    for transaction in transaction_reader.transactions() {
-        let transaction = transaction?;
-        transaction_svc.process_transaction(&transaction).await?;
+async fn import_state(file: &Path, builder: TransactionServiceBuilder) -> anyhow::Result<()> {
+    let state: LedgerState = serde_json::from_reader(io::BufReader::new(
+        File::open(file).with_context(|| format!("Failed to open \"{}\"", file.display()))?,
+    ))
+    .with_context(|| format!("Invalid ledger state in \"{}\"", file.display()))?;
+    let transaction_svc = builder
+        .build()
+        .await
+        .context("Failed to get transaction service")?;
+    transaction_svc.import_state(&state).await?;
+    eprintln!(
+        "Imported {} clients, {} transactions, {} disputes and {} closed business dates",
+        state.clients.len(),
+        state.transactions.len(),
+        state.disputes.len(),
+        state.day_closes.len()
+    );
+    Ok(())
+}
+
+async fn audit_import(
+    report: Option<std::path::PathBuf>,
+    builder: TransactionServiceBuilder,
+) -> anyhow::Result<()> {
+    let check = builder
+        .build()
+        .await
+        .context("Failed to get transaction service")?
+        .check_ledger()
+        .await
+        .context("Failed to check the ledger")?;
+    if let Some(path) = report {
+        let mut w = csv::Writer::from_path(&path)
+            .with_context(|| format!("Failed to create \"{}\"", path.display()))?;
+        for violation in &check.violations {
+            w.serialize(violation)?;
+        }
+        w.flush()?;
     }
+    println!("{}", check);
+    if !check.is_consistent() {
+        anyhow::bail!("The imported ledger is inconsistent, do not let it go live");
+    }
+    Ok(())
+}
+
+async fn backup(to: &Path, builder: TransactionServiceBuilder) -> anyhow::Result<()> {
+    let transaction_svc = builder
+        .build()
+        .await
+        .context("Failed to get transaction service")?;
+    transaction_svc
+        .backup(to)
+        .await
+        .with_context(|| format!("Failed to back up to \"{}\"", to.display()))?;
+    Ok(())
+}
+
+async fn restore(from: &Path, builder: TransactionServiceBuilder) -> anyhow::Result<()> {
+    let transaction_svc = builder
+        .build()
+        .await
+        .context("Failed to get transaction service")?;
+    transaction_svc
+        .restore(from)
+        .await
+        .with_context(|| format!("Failed to restore \"{}\"", from.display()))?;
+    Ok(())
+}
 
-    print_client_csv(&mut transaction_svc).await?;
+async fn maintain(builder: TransactionServiceBuilder) -> anyhow::Result<()> {
+    let report = builder
+        .build()
+        .await
+        .context("Failed to get transaction service")?
+        .maintain()
+        .await
+        .context("Failed to maintain the database")?;
+    println!("{}", report);
+    if !report.is_healthy() {
+        anyhow::bail!("The database has integrity problems");
+    }
+    Ok(())
+}
 
+async fn report(
+    as_of: Option<i64>,
+    stats: bool,
+    tag: Option<&str>,
+    amounts: &AmountFormat,
+    metadata: Option<ReportMetadata>,
+    builder: TransactionServiceBuilder,
+) -> anyhow::Result<()> {
+    // Past balances are read from the audit log, which reports do not write to
+    let builder = match as_of {
+        Some(_) => builder.audit_log(true),
+        None => builder,
+    };
+    let mut transaction_svc = builder
+        .build()
+        .await
+        .context("Failed to get transaction service")?;
+    let Some(as_of) = as_of else {
+        return print_client_csv(
+            &mut transaction_svc,
+            stats,
+            tag,
+            None,
+            amounts,
+            metadata,
+            None,
+        )
+        .await;
+    };
+    let tagged = get_tagged_clients(&transaction_svc, tag).await?;
+    let clients = transaction_svc.get_clients_as_of(as_of).await?;
+    let mut w = ReportWriter::new(io::stdout().lock(), metadata, None, amounts.locale)?;
+    for c in clients.into_iter().filter(|c| is_included(&tagged, c.id)) {
+        write_client(&mut w, transaction_svc.pseudonymizer(), c, amounts)?;
+    }
     Ok(())
 }
+
+/// The source of the input and, for files, the hex sha256 of their contents.
+fn get_transaction_source(
+    args: &Args,
+    external_ids: Option<ExternalIds>,
+) -> anyhow::Result<(Box<dyn TransactionSource>, Option<String>)> {
+    let input = args
+        .input
+        .as_deref()
+        .context("No transaction input given")?;
+    if let Some(kafka_uri) = kafka_input(args)? {
+        return Ok((get_kafka_source(kafka_uri)?, None));
+    }
+    let verifier = get_input_verifier(args)?;
+    if verifier.is_some() && input == "-" {
+        check_unsigned_stream(args, input)?;
+    }
+
+    let format = args.format.unwrap_or_else(|| {
+        match Path::new(input).extension().and_then(|e| e.to_str()) {
+            Some("jsonl" | "ndjson") => InputFormat::Jsonl,
+            _ => InputFormat::Csv,
+        }
+    });
+
+    let (reader, sha256): (Box<dyn io::BufRead + Send>, _) = if input == "-" {
+        (Box::new(io::BufReader::new(io::stdin())), None)
+    } else if let Some(verifier) = verifier {
+        if !Path::new(input).exists() {
+            anyhow::bail!("Could not locate the transaction file \"{}\"", input);
+        }
+        let contents = verifier.read(input)?;
+        let sha256 = content_sha256(&contents[..])?;
+        (Box::new(io::Cursor::new(contents)), Some(sha256))
+    } else {
+        let open = || {
+            File::open(input).map_err(|_| {
+                anyhow::format_err!("Could not locate the transaction file \"{}\"", input)
+            })
+        };
+        let sha256 = content_sha256(open()?)?;
+        (Box::new(io::BufReader::new(open()?)), Some(sha256))
+    };
+
+    let number_locale = match args.number_locale {
+        AmountLocale::Plain => NumberLocale::Plain,
+        AmountLocale::Point => NumberLocale::Point,
+        AmountLocale::Comma => NumberLocale::Comma,
+    };
+    let source: Box<dyn TransactionSource> = match format {
+        InputFormat::Csv => {
+            let reader = TransactionReader::new(reader).number_locale(number_locale);
+            match external_ids {
+                Some(ids) => Box::new(reader.external_ids(ids)),
+                None => Box::new(reader),
+            }
+        }
+        InputFormat::Jsonl => {
+            let reader = JsonLinesReader::new(reader).number_locale(number_locale);
+            match external_ids {
+                Some(ids) => Box::new(reader.external_ids(ids)),
+                None => Box::new(reader),
+            }
+        }
+    };
+    Ok((source, sha256))
+}
+
+/// The location of a `kafka://` input, checking the options that only apply to files.
+fn kafka_input(args: &Args) -> anyhow::Result<Option<&str>> {
+    let Some(input) = args.input.as_deref().filter(|i| i.starts_with("kafka://")) else {
+        return Ok(None);
+    };
+    if get_input_verifier(args)?.is_some() {
+        check_unsigned_stream(args, input)?;
+    }
+    if args.external_ids {
+        anyhow::bail!("External client ids are only supported for csv and jsonl input");
+    }
+    if !matches!(args.number_locale, AmountLocale::Plain) {
+        anyhow::bail!("Number locales are only supported for csv and jsonl input");
+    }
+    if args.return_file.is_some() {
+        anyhow::bail!("Return files are only written for csv and jsonl input");
+    }
+    if args.stream_updates {
+        anyhow::bail!("Updates are only streamed for csv and jsonl input");
+    }
+    if args.throughput_stats.is_some() {
+        anyhow::bail!("Throughput stats are only recorded for csv and jsonl input");
+    }
+    Ok(input.strip_prefix("kafka://"))
+}
+
+/// Streams have no signature to check, so are only processed with `--unsigned-input warn`.
+fn check_unsigned_stream(args: &Args, input: &str) -> anyhow::Result<()> {
+    match args.unsigned_input {
+        UnsignedInput::Reject => anyhow::bail!("Only input files can be verified"),
+        UnsignedInput::Warn => tracing::warn!(input, "Processing an input that is not signed"),
+    }
+    Ok(())
+}
+
+/// The verifier for the input file, if `--public-key` or `--checksums` are given.
+fn get_input_verifier(args: &Args) -> anyhow::Result<Option<InputVerifier>> {
+    if args.public_key.is_empty() && args.checksums.is_none() {
+        return Ok(None);
+    }
+    let mut verifier = InputVerifier::new().unsigned(match args.unsigned_input {
+        UnsignedInput::Reject => UnsignedInputPolicy::Reject,
+        UnsignedInput::Warn => UnsignedInputPolicy::Warn,
+    });
+    for path in &args.public_key {
+        verifier = verifier
+            .public_key_file(path)
+            .with_context(|| format!("Invalid public key \"{}\"", path))?;
+    }
+    if let Some(path) = &args.checksums {
+        verifier = verifier
+            .checksums_file(path)
+            .with_context(|| format!("Invalid checksum manifest \"{}\"", path))?;
+    }
+    Ok(Some(verifier))
+}
+
+/// The brokers, topic and consumer group of a `kafka://` input.
+#[cfg(feature = "kafka")]
+fn parse_kafka_uri(uri: &str) -> anyhow::Result<(&str, &str, &str)> {
+    let (location, query) = uri.split_once('?').unwrap_or((uri, ""));
+    let (brokers, topic) = location
+        .split_once('/')
+        .filter(|(_, topic)| !topic.is_empty())
+        .context("Kafka input must be kafka://<brokers>/<topic>")?;
+    let group_id = query
+        .split('&')
+        .find_map(|param| param.strip_prefix("group="))
+        .unwrap_or(env!("CARGO_PKG_NAME"));
+    Ok((brokers, topic, group_id))
+}
+
+#[cfg(feature = "kafka")]
+fn get_kafka_source(uri: &str) -> anyhow::Result<Box<dyn TransactionSource>> {
+    let (brokers, topic, group_id) = parse_kafka_uri(uri)?;
+    Ok(Box::new(transaction_app::KafkaSource::new(
+        brokers, group_id, topic,
+    )?))
+}
+
+/// Processes a kafka topic exactly once, resuming from the offsets stored in the ledger.
+#[cfg(feature = "kafka")]
+async fn process_kafka(
+    uri: &str,
+    args: &Args,
+    transaction_svc: TransactionService,
+) -> anyhow::Result<()> {
+    let (brokers, topic, group_id) = parse_kafka_uri(uri)?;
+    let consumer = format!("kafka:{}:{}", group_id, topic);
+    let offsets = transaction_svc.get_source_offsets(&consumer).await?;
+    let mut source = transaction_app::KafkaSource::from_offsets(brokers, topic, &offsets)
+        .with_context(|| format!("Failed to consume kafka topic {}", topic))?;
+    tracing::info!(
+        consumer,
+        partitions = offsets.len(),
+        "Resuming from stored offsets"
+    );
+
+    let mut rejected = create_rejected(args)?;
+    let summary = transaction_svc
+        .process_with_offsets(
+            &consumer,
+            source.stream_with_offsets(),
+            |offset, transaction, outcome| {
+                write_rejected(
+                    &mut rejected,
+                    transaction_svc.pseudonymizer(),
+                    offset.offset as u64,
+                    transaction,
+                    outcome,
+                )
+            },
+        )
+        .await?;
+    if let Some(mut w) = rejected {
+        w.flush()?;
+    }
+    print_summary(&summary, transaction_svc.transaction_counts());
+    Ok(())
+}
+
+#[cfg(not(feature = "kafka"))]
+fn get_kafka_source(_uri: &str) -> anyhow::Result<Box<dyn TransactionSource>> {
+    anyhow::bail!("Kafka input requires building with the \"kafka\" feature")
+}
+
+/// Flushes exported spans when dropped.
+struct TracingGuard {
+    #[cfg(feature = "otel")]
+    provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+impl Drop for TracingGuard {
+    fn drop(&mut self) {
+        #[cfg(feature = "otel")]
+        if let Some(provider) = self.provider.take() {
+            let _ = provider.shutdown();
+        }
+    }
+}
+
+/// Logs to stderr filtered by `RUST_LOG`, and exports spans when `--otlp-endpoint` is set.
+#[cfg_attr(not(feature = "otel"), allow(unused_variables))]
+fn init_tracing(cli: &Cli) -> anyhow::Result<TracingGuard> {
+    use tracing_subscriber::prelude::*;
+    use tracing_subscriber::EnvFilter;
+
+    let registry = tracing_subscriber::registry().with(
+        tracing_subscriber::fmt::layer()
+            .with_writer(io::stderr)
+            .with_filter(EnvFilter::from_default_env()),
+    );
+
+    #[cfg(feature = "otel")]
+    if let Some(endpoint) = &cli.otlp_endpoint {
+        use opentelemetry::trace::TracerProvider;
+        use opentelemetry_otlp::WithExportConfig;
+        use tracing::Level;
+        use tracing_subscriber::filter::Targets;
+
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .build()
+            .context("Failed to create the OTLP exporter")?;
+        let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(
+                opentelemetry_sdk::Resource::builder()
+                    .with_service_name(env!("CARGO_PKG_NAME"))
+                    .build(),
+            )
+            .build();
+        let spans = Targets::new()
+            .with_target("transaction_app", Level::DEBUG)
+            .with_target("tower_http", Level::INFO);
+        registry
+            .with(
+                tracing_opentelemetry::layer()
+                    .with_tracer(provider.tracer(env!("CARGO_PKG_NAME")))
+                    .with_filter(spans),
+            )
+            .init();
+        return Ok(TracingGuard {
+            provider: Some(provider),
+        });
+    }
+
+    registry.init();
+    Ok(TracingGuard {
+        #[cfg(feature = "otel")]
+        provider: None,
+    })
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let _tracing = init_tracing(&cli)?;
+
+    let builder = TransactionService::builder()
+        .database_url(&cli.database)
+        .audit_log(cli.audit_log)
+        .backfill(cli.args.backfill)
+        .alerts(AlertThresholds {
+            min_available: cli.alert_min_available,
+            max_held: cli.alert_max_held,
+        })
+        .approvals(ApprovalRules {
+            unlocks: cli.approve_unlocks,
+            adjustments_over: cli.approve_adjustments_over,
+        })
+        .unknown_targets(match cli.unknown_targets {
+            UnknownTargets::Ignore => UnknownTargetAction::Ignore,
+            UnknownTargets::Warn => UnknownTargetAction::Warn,
+            UnknownTargets::Error => UnknownTargetAction::Error,
+        })
+        .retry_policy(RetryPolicy {
+            max_attempts: cli.db_attempts,
+            ..RetryPolicy::default()
+        })
+        .isolate_failures(cli.isolate_failures);
+    let builder = match cli.slow_transaction_ms {
+        Some(ms) => builder.slow_transaction_threshold(std::time::Duration::from_millis(ms)),
+        None => builder,
+    };
+    #[allow(unused_mut)]
+    let mut reloadable = Reloadable::new(&cli);
+    let config = reloadable.read()?;
+    #[cfg(feature = "webhook")]
+    let (builder, webhook) = match add_webhook(&cli, reloadable.webhook_url(&config))? {
+        Some((sink, handle)) => {
+            reloadable.webhook = Some(sink.clone());
+            (builder.observer(sink), Some(handle))
+        }
+        None => (builder, None),
+    };
+    #[cfg(feature = "email")]
+    let (builder, email) = match &cli.email_config {
+        Some(path) => {
+            let config = transaction_app::EmailConfig::from_file(path)
+                .with_context(|| format!("Could not read the email config \"{}\"", path))?;
+            let (sink, handle) = config.spawn();
+            (builder.observer(sink), Some(handle))
+        }
+        None => (builder, None),
+    };
+    let builder = match &cli.pseudonymize_key_file {
+        Some(path) => builder
+            .pseudonymizer(Pseudonymizer::from_file(path).with_context(|| {
+                format!("Could not read the pseudonymization key \"{}\"", path)
+            })?),
+        None => builder,
+    };
+    let builder = match &cli.policy_file {
+        Some(path) => builder.policy(
+            TransactionPolicy::from_file(path)
+                .with_context(|| format!("Invalid policy file \"{}\"", path))?,
+        ),
+        None => builder,
+    };
+    // Fraud rules are not applied again to historical transactions
+    let builder = if (cli.rules.is_some() || cli.config.is_some()) && !cli.args.backfill {
+        builder.validator(reloadable.rules.clone())
+    } else {
+        builder
+    };
+    reloadable.apply(config)?;
+    let builder = match cli.args.strict_ids {
+        Some(strict_ids) => builder.validator(MonotonicIds::new().action(match strict_ids {
+            StrictIds::Reject => NonMonotonicIdAction::Reject,
+            StrictIds::Warn => NonMonotonicIdAction::Warn,
+        })),
+        None => builder,
+    };
+    #[cfg(feature = "scripting")]
+    let builder = match &cli.validation_script {
+        Some(path) if !cli.args.backfill => builder.validator(
+            transaction_app::ScriptValidator::from_file(path)
+                .with_context(|| format!("Invalid validation script \"{}\"", path))?,
+        ),
+        _ => builder,
+    };
+
+    let amounts = AmountFormat {
+        currency: cli.currency,
+        column: cli.currency_column,
+        locale: cli.report_locale,
+    };
+    let command = async {
+        match cli.command {
+            #[cfg(feature = "server")]
+            Some(Command::Serve(args)) => serve(*args, builder, reloadable.rate_limits()).await?,
+            Some(Command::Simulate(args)) => simulate(args, builder).await?,
+            Some(Command::VerifyChain) => verify_chain(builder).await?,
+            Some(Command::ForgetClient(args)) => forget_client(args, builder).await?,
+            Some(Command::Unlock { client, operator }) => {
+                unlock(client, &operator, builder).await?
+            }
+            Some(Command::Adjust(args)) => adjust(args, builder).await?,
+            Some(Command::Approvals) => approvals(builder).await?,
+            Some(Command::Approve { id, operator }) => {
+                decide_approval(id, &operator, true, builder).await?
+            }
+            Some(Command::Reject { id, operator }) => {
+                decide_approval(id, &operator, false, builder).await?
+            }
+            Some(Command::Provenance { tx }) => provenance(tx, builder).await?,
+            Some(Command::EodClose(args)) => eod_close(args, builder).await?,
+            Some(Command::Report { as_of, stats, tag }) => {
+                let tag = tag.as_deref();
+                report(as_of, stats, tag, &amounts, cli.report_metadata, builder).await?
+            }
+            Some(Command::Dormant { days }) => dormant(days, builder).await?,
+            Some(Command::CashPosition) => cash_position(builder).await?,
+            Some(Command::ExternalIds) => external_ids(builder).await?,
+            Some(Command::Disputes { client }) => disputes(client, builder).await?,
+            Some(Command::UpdateDispute(args)) => update_dispute(args, builder).await?,
+            Some(Command::Query(args)) => query(args, builder).await?,
+            Some(Command::TagClients { file }) => tag_clients(&file, builder).await?,
+            Some(Command::ReleaseSettlements) => release_settlements(builder).await?,
+            Some(Command::Void {
+                tx,
+                reason,
+                operator,
+            }) => void_deposit(tx, &reason, &operator, builder).await?,
+            Some(Command::SnapshotBalances) => snapshot_balances(builder).await?,
+            Some(Command::Aggregate(args)) => aggregate(args, &amounts, builder).await?,
+            Some(Command::ExportState { output }) => export_state(output, builder).await?,
+            Some(Command::ImportState { file }) => import_state(&file, builder).await?,
+            Some(Command::AuditImport { report }) => audit_import(report, builder).await?,
+            Some(Command::Backup { to }) => backup(&to, builder).await?,
+            Some(Command::Restore { from }) => restore(&from, builder).await?,
+            Some(Command::Maintain) => maintain(builder).await?,
+            None if cli.args.dry_run => {
+                let out = io::stdout().lock();
+                dry_run(&cli.args, &amounts, cli.report_metadata, builder, out).await?
+            }
+            None => match cli.args.shards {
+                Some(shards) => {
+                    process_sharded(&cli.args, shards, &amounts, cli.report_metadata, builder)
+                        .await?
+                }
+                None => process_input(&cli.args, &amounts, cli.report_metadata, builder).await?,
+            },
+        }
+        anyhow::Ok(())
+    };
+    tokio::select! {
+        result = command => result?,
+        () = reloadable.watch() => {}
+    }
+    // Its webhook sink would keep the delivery task waiting for events
+    drop(reloadable);
+
+    // Wait for the queued events now the service, and with it the sinks, has been dropped
+    #[cfg(feature = "webhook")]
+    if let Some(webhook) = webhook {
+        webhook.await?;
+    }
+    #[cfg(feature = "email")]
+    if let Some(email) = email {
+        email.await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "webhook")]
+fn add_webhook(
+    cli: &Cli,
+    url: Option<&str>,
+) -> anyhow::Result<Option<(transaction_app::WebhookSink, tokio::task::JoinHandle<()>)>> {
+    let Some(url) = url else {
+        return Ok(None);
+    };
+    let mut config = transaction_app::WebhookConfig::new(url);
+    if let Some(secret_file) = &cli.webhook_secret_file {
+        let secret = std::fs::read(secret_file)
+            .with_context(|| format!("Could not read the webhook secret \"{}\"", secret_file))?;
+        config = config.secret(secret.trim_ascii());
+    }
+    Ok(Some(config.spawn()))
+}
+
+/// The settings of `--config`. Each one that is set replaces its flag.
+#[derive(Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    rules: Option<Vec<String>>,
+    webhook_url: Option<String>,
+    #[cfg(feature = "server")]
+    rate_limit: Option<NonZeroU32>,
+    #[cfg(feature = "server")]
+    caller_rate_limit: Option<NonZeroU32>,
+    #[cfg(feature = "server")]
+    client_rate_limit: Option<NonZeroU32>,
+}
+
+/// The settings that can change without restarting, applied from `--config` on top of the
+/// flags at startup and again whenever it is reloaded.
+struct Reloadable {
+    config: Option<String>,
+    rules_file: Option<String>,
+    rules: ReloadableRules,
+    #[cfg(feature = "webhook")]
+    webhook_url: Option<String>,
+    #[cfg(feature = "webhook")]
+    webhook: Option<transaction_app::WebhookSink>,
+    /// `--rate-limit`, `--caller-rate-limit` and `--client-rate-limit`.
+    #[cfg(feature = "server")]
+    rate_limit_flags: [Option<NonZeroU32>; 3],
+    #[cfg(feature = "server")]
+    rate_limits: std::sync::Arc<transaction_app::server::rate_limit::RateLimits>,
+}
+
+impl Reloadable {
+    fn new(cli: &Cli) -> Self {
+        Self {
+            config: cli.config.clone(),
+            rules_file: cli.rules.clone(),
+            rules: ReloadableRules::default(),
+            #[cfg(feature = "webhook")]
+            webhook_url: cli.webhook_url.clone(),
+            #[cfg(feature = "webhook")]
+            webhook: None,
+            #[cfg(feature = "server")]
+            rate_limit_flags: match &cli.command {
+                Some(Command::Serve(args)) => [
+                    args.rate_limit,
+                    args.caller_rate_limit,
+                    args.client_rate_limit,
+                ],
+                _ => [None; 3],
+            },
+            #[cfg(feature = "server")]
+            rate_limits: Default::default(),
+        }
+    }
+
+    fn read(&self) -> anyhow::Result<ConfigFile> {
+        let Some(path) = &self.config else {
+            return Ok(ConfigFile::default());
+        };
+        let config = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read the config \"{}\"", path))?;
+        toml::from_str(&config).with_context(|| format!("Invalid config \"{}\"", path))
+    }
+
+    #[cfg(feature = "webhook")]
+    fn webhook_url<'a>(&'a self, config: &'a ConfigFile) -> Option<&'a str> {
+        config
+            .webhook_url
+            .as_deref()
+            .or(self.webhook_url.as_deref())
+    }
+
+    /// The rate limits of `serve`, which follow the config, or `None` to use the flags.
+    #[cfg(feature = "server")]
+    fn rate_limits(
+        &self,
+    ) -> Option<std::sync::Arc<transaction_app::server::rate_limit::RateLimits>> {
+        self.config.as_ref().map(|_| self.rate_limits.clone())
+    }
+
+    /// Applies `config`, or nothing if any of its settings is invalid.
+    fn apply(&self, config: ConfigFile) -> anyhow::Result<()> {
+        let rules = match (&config.rules, &self.rules_file) {
+            (Some(rules), _) => RuleSet::new(rules).context("Invalid rules in the config")?,
+            (None, Some(path)) => RuleSet::from_file(path)
+                .with_context(|| format!("Invalid rules file \"{}\"", path))?,
+            (None, None) => RuleSet::default(),
+        };
+        #[cfg(not(feature = "webhook"))]
+        if config.webhook_url.is_some() {
+            anyhow::bail!("The webhook_url of the config requires the webhook feature");
+        }
+
+        self.rules.replace(rules);
+        #[cfg(feature = "webhook")]
+        if let (Some(webhook), Some(url)) = (&self.webhook, self.webhook_url(&config)) {
+            webhook.retarget(url);
+        }
+        #[cfg(feature = "server")]
+        {
+            let [global, per_caller, per_client] = self.rate_limit_flags;
+            self.rate_limits.replace(rate_limits(
+                config.rate_limit.or(global),
+                config.caller_rate_limit.or(per_caller),
+                config.client_rate_limit.or(per_client),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Reads and applies `--config`, keeping the previous settings if it is invalid.
+    fn reload(&self) -> anyhow::Result<()> {
+        self.read().and_then(|config| self.apply(config))
+    }
+
+    /// Reloads `--config` on SIGHUP and whenever it is modified, until the command is done.
+    /// An invalid config is logged, keeping the previous settings.
+    async fn watch(&self) {
+        let Some(path) = &self.config else {
+            return std::future::pending().await;
+        };
+        #[cfg(unix)]
+        let mut hangups = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+            .inspect_err(|e| tracing::error!(error = %e, "Failed to listen for SIGHUP"))
+            .ok();
+        let modified = || std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let mut last_modified = modified();
+        let mut poll = tokio::time::interval(CONFIG_POLL_INTERVAL);
+        poll.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            let hangup = async {
+                #[cfg(unix)]
+                if let Some(hangups) = &mut hangups {
+                    hangups.recv().await;
+                    return;
+                }
+                std::future::pending::<()>().await
+            };
+            tokio::select! {
+                () = hangup => {}
+                _ = poll.tick() => {
+                    let modified = modified();
+                    if modified == last_modified {
+                        continue;
+                    }
+                    last_modified = modified;
+                }
+            }
+            match self.reload() {
+                Ok(()) => tracing::info!(config = %path, "Reloaded the config"),
+                Err(e) => tracing::error!(
+                    config = %path,
+                    error = format!("{:#}", e),
+                    "Failed to reload the config, keeping the previous settings"
+                ),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+async fn serve(
+    args: ServeArgs,
+    builder: TransactionServiceBuilder,
+    rate_limits: Option<std::sync::Arc<transaction_app::server::rate_limit::RateLimits>>,
+) -> anyhow::Result<()> {
+    use transaction_app::server::circuit_breaker::CircuitBreaker;
+    use transaction_app::server::{AppState, LiveUpdates};
+    use transaction_app::{ExpiredHoldAction, HoldExpiry, Job, Schedule, Scheduler};
+
+    let auth = get_authenticator(&args)?;
+    let mut scheduler = match &args.schedule {
+        Some(path) => Scheduler::from_file(path)
+            .with_context(|| format!("Invalid schedule file \"{}\"", path))?,
+        None => Scheduler::new(),
+    };
+    if let Some(max_hold_age) = args.max_hold_age {
+        let expiry = HoldExpiry::new(std::time::Duration::from_secs(max_hold_age)).action(
+            match args.expired_holds {
+                ExpiredHolds::Resolve => ExpiredHoldAction::Resolve,
+                ExpiredHolds::Escalate => ExpiredHoldAction::Escalate,
+            },
+        );
+        scheduler = scheduler.job(Job::ExpireHolds(expiry), Schedule::every_minute());
+    }
+    if !scheduler.contains("release_settlements") {
+        scheduler = scheduler.job(Job::ReleaseSettlements, Schedule::every_minute());
+    }
+    let scheduler = std::sync::Arc::new(scheduler);
+    #[cfg(feature = "tls")]
+    let tls = get_tls_config(&args)?;
+    let rate_limits = rate_limits.or_else(|| get_rate_limits(&args));
+    let circuit_breaker = (args.circuit_failures > 0).then(|| {
+        std::sync::Arc::new(CircuitBreaker::new(
+            args.circuit_failures,
+            std::time::Duration::from_secs(args.circuit_open_secs),
+        ))
+    });
+    let live_updates = LiveUpdates::new(LIVE_UPDATE_CAPACITY);
+    #[cfg(feature = "kafka")]
+    let (builder, outbox_relay) = match &args.outbox {
+        Some(uri) => (builder.outbox(true), Some(get_outbox_relay(uri)?)),
+        None => (builder, None),
+    };
+    let builder =
+        (args.read_replica.iter()).fold(builder, |builder, url| builder.read_replica_url(url));
+    let transaction_svc = std::sync::Arc::new(
+        builder
+            .observer(live_updates.clone())
+            .build()
+            .await
+            .context("Failed to get transaction service")?,
+    );
+    if args.warm_up_clients > 0 {
+        let warmed = (transaction_svc.warm_up(args.warm_up_clients).await)
+            .context("Failed to warm up the most active clients")?;
+        tracing::info!(clients = warmed, "Warmed up the most active clients");
+    }
+
+    let http = async {
+        let mut state = AppState::new(transaction_svc.clone())
+            .with_live_updates(live_updates)
+            .with_scheduler(scheduler.clone());
+        if let Some(auth) = &auth {
+            state = state.with_auth(auth.clone());
+        }
+        if let Some(rate_limits) = &rate_limits {
+            state = state.with_rate_limits(rate_limits.clone());
+        }
+        if let Some(circuit_breaker) = &circuit_breaker {
+            state = state.with_circuit_breaker(circuit_breaker.clone());
+        }
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &tls {
+            return transaction_app::server::serve_tls(state, &args.listen, tls)
+                .await
+                .context("Failed to run the HTTPS server");
+        }
+        transaction_app::server::serve(state, &args.listen)
+            .await
+            .context("Failed to run the HTTP server")
+    };
+
+    #[cfg(not(feature = "grpc"))]
+    let servers = http;
+    #[cfg(feature = "grpc")]
+    let servers = async {
+        if let Some(grpc_listen) = args.grpc_listen {
+            use transaction_app::server::grpc::LedgerService;
+
+            let grpc = async {
+                let mut service = LedgerService::new(transaction_svc.clone());
+                if let Some(auth) = &auth {
+                    service = service.with_auth(auth.clone());
+                }
+                if let Some(rate_limits) = &rate_limits {
+                    service = service.with_rate_limits(rate_limits.clone());
+                }
+                if let Some(circuit_breaker) = &circuit_breaker {
+                    service = service.with_circuit_breaker(circuit_breaker.clone());
+                }
+                #[cfg(feature = "tls")]
+                if let Some(tls) = &tls {
+                    return transaction_app::server::grpc::serve_tls(service, grpc_listen, tls)
+                        .await
+                        .context("Failed to run the gRPC server");
+                }
+                transaction_app::server::grpc::serve(service, grpc_listen)
+                    .await
+                    .context("Failed to run the gRPC server")
+            };
+            tokio::try_join!(http, grpc)?;
+            return Ok(());
+        }
+
+        http.await
+    };
+
+    let servers = async {
+        tokio::select! {
+            result = servers => result,
+            () = scheduler.run(&transaction_svc, SCHEDULER_MAX_WAIT) => Ok(()),
+        }
+    };
+
+    #[cfg(unix)]
+    let servers = async {
+        tokio::select! {
+            result = servers => result,
+            result = toggle_pause_on_signal(&transaction_svc) => result,
+        }
+    };
+
+    #[cfg(feature = "kafka")]
+    if let Some(relay) = &outbox_relay {
+        return tokio::select! {
+            result = servers => result,
+            result = relay.run(&transaction_svc, OUTBOX_POLL_INTERVAL) => {
+                result.context("Failed to ship the outbox")
+            }
+        };
+    }
+
+    servers.await
+}
+
+/// Pauses writes on SIGUSR1 and resumes them on the next one, so the database file can be
+/// copied without stopping the server.
+#[cfg(all(feature = "server", unix))]
+async fn toggle_pause_on_signal(svc: &TransactionService) -> anyhow::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut signals =
+        signal(SignalKind::user_defined1()).context("Failed to listen for SIGUSR1")?;
+    loop {
+        signals.recv().await;
+        if svc.is_paused() {
+            svc.resume().await;
+        } else {
+            svc.pause().await;
+        }
+    }
+}
+
+#[cfg(all(feature = "server", feature = "kafka"))]
+fn get_outbox_relay(uri: &str) -> anyhow::Result<transaction_app::KafkaOutboxRelay> {
+    let (brokers, topic) = uri
+        .strip_prefix("kafka://")
+        .and_then(|location| location.split_once('/'))
+        .filter(|(_, topic)| !topic.is_empty())
+        .context("The outbox must be kafka://<brokers>/<topic>")?;
+    Ok(transaction_app::KafkaOutboxRelay::new(brokers, topic)?)
+}
+
+#[cfg(feature = "server")]
+fn get_authenticator(
+    args: &ServeArgs,
+) -> anyhow::Result<Option<std::sync::Arc<transaction_app::server::auth::Authenticator>>> {
+    if args.no_auth {
+        return Ok(None);
+    }
+    if args.tokens.is_none() && args.jwt_secret_file.is_none() {
+        anyhow::bail!("Serving requires --tokens or --jwt-secret-file, or --no-auth to serve without authentication");
+    }
+
+    let mut auth = transaction_app::server::auth::Authenticator::new();
+    if let Some(tokens) = &args.tokens {
+        let f = File::open(tokens)
+            .map_err(|_| anyhow::format_err!("Could not locate the tokens file \"{}\"", tokens))?;
+        auth = auth
+            .read_tokens(f)
+            .with_context(|| format!("Invalid tokens file \"{}\"", tokens))?;
+    }
+    if let Some(secret_file) = &args.jwt_secret_file {
+        let secret = std::fs::read(secret_file)
+            .with_context(|| format!("Could not read the JWT secret \"{}\"", secret_file))?;
+        auth = auth.with_jwt_secret(secret.trim_ascii());
+    }
+    Ok(Some(std::sync::Arc::new(auth)))
+}
+
+#[cfg(feature = "tls")]
+fn get_tls_config(args: &ServeArgs) -> anyhow::Result<Option<transaction_app::server::TlsConfig>> {
+    let (Some(cert), Some(key)) = (&args.tls_cert, &args.tls_key) else {
+        return Ok(None);
+    };
+    let tls = transaction_app::server::TlsConfig::from_pem_files(cert, key).with_context(|| {
+        format!(
+            "Could not read the TLS certificate \"{}\" or key \"{}\"",
+            cert, key
+        )
+    })?;
+    Ok(Some(tls))
+}
+
+#[cfg(feature = "server")]
+fn get_rate_limits(
+    args: &ServeArgs,
+) -> Option<std::sync::Arc<transaction_app::server::rate_limit::RateLimits>> {
+    if args.rate_limit.is_none()
+        && args.caller_rate_limit.is_none()
+        && args.client_rate_limit.is_none()
+    {
+        return None;
+    }
+    Some(std::sync::Arc::new(rate_limits(
+        args.rate_limit,
+        args.caller_rate_limit,
+        args.client_rate_limit,
+    )))
+}
+
+/// Limits the transactions per second submitted overall, by each caller and for each client.
+#[cfg(feature = "server")]
+fn rate_limits(
+    global: Option<NonZeroU32>,
+    per_caller: Option<NonZeroU32>,
+    per_client: Option<NonZeroU32>,
+) -> transaction_app::server::rate_limit::RateLimits {
+    use transaction_app::server::rate_limit::Quota;
+
+    let mut rate_limits = transaction_app::server::rate_limit::RateLimits::new();
+    if let Some(per_second) = global {
+        rate_limits = rate_limits.global(Quota::per_second(per_second));
+    }
+    if let Some(per_second) = per_caller {
+        rate_limits = rate_limits.per_caller(Quota::per_second(per_second));
+    }
+    if let Some(per_second) = per_client {
+        rate_limits = rate_limits.per_client(Quota::per_second(per_second));
+    }
+    rate_limits
+}
+
+async fn simulate(args: SimulateArgs, builder: TransactionServiceBuilder) -> anyhow::Result<()> {
+    let report = Simulation::new(args.seed)
+        .transactions(args.transactions)
+        .clients(args.clients)
+        .run(builder)
+        .await
+        .context("Failed to run the simulation")?;
+    println!("{}", report);
+    if !report.violations.is_empty() {
+        anyhow::bail!("The simulated ledger is inconsistent");
+    }
+    Ok(())
+}
+
+async fn verify_chain(builder: TransactionServiceBuilder) -> anyhow::Result<()> {
+    let verification = builder
+        .build()
+        .await
+        .context("Failed to get transaction service")?
+        .verify_audit_log()
+        .await
+        .context("Failed to read the audit log")?;
+    println!("{}", verification);
+    if !verification.is_valid() {
+        anyhow::bail!("The ledger does not match its audit log");
+    }
+    Ok(())
+}
+
+async fn forget_client(
+    args: ForgetClientArgs,
+    builder: TransactionServiceBuilder,
+) -> anyhow::Result<()> {
+    let policy = match args.policy {
+        Erasure::Anonymize => ErasurePolicy::Anonymize,
+        Erasure::Tombstone => ErasurePolicy::Tombstone,
+    };
+    let transaction_svc = builder
+        .build()
+        .await
+        .context("Failed to get transaction service")?;
+    let client = client_label(transaction_svc.pseudonymizer(), args.client);
+    let erasure = transaction_svc
+        .forget_client(args.client, policy, &args.operator)
+        .await
+        .with_context(|| format!("Failed to erase client {}", client))?;
+    println!(
+        "Erased client {} ({}): {} texts redacted, {} transactions removed",
+        client_label(transaction_svc.pseudonymizer(), erasure.client_id),
+        policy.to_str(),
+        erasure.redacted,
+        erasure.removed
+    );
+    Ok(())
+}
+
+async fn unlock(
+    client_id: ClientId,
+    operator: &str,
+    builder: TransactionServiceBuilder,
+) -> anyhow::Result<()> {
+    let transaction_svc = builder
+        .build()
+        .await
+        .context("Failed to get transaction service")?;
+    let pseudonymizer = transaction_svc.pseudonymizer();
+    let client = client_label(pseudonymizer, client_id);
+    match transaction_svc.unlock_client(client_id, operator).await {
+        Err(TransactionError::ApprovalRequired(approval, _)) => {
+            print_requested(pseudonymizer, &approval)
+        }
+        result => {
+            result.with_context(|| format!("Failed to unlock client {}", client))?;
+            println!("Unlocked client {} ({})", client, operator);
+        }
+    }
+    Ok(())
+}
+
+async fn adjust(args: AdjustArgs, builder: TransactionServiceBuilder) -> anyhow::Result<()> {
+    let transaction_svc = builder
+        .build()
+        .await
+        .context("Failed to get transaction service")?;
+    let pseudonymizer = transaction_svc.pseudonymizer();
+    let result = transaction_svc
+        .adjust_balance(args.client, args.amount, &args.reason, &args.operator)
+        .await;
+    match result {
+        Err(TransactionError::ApprovalRequired(approval, _)) => {
+            print_requested(pseudonymizer, &approval)
+        }
+        result => {
+            let adjustment = result.with_context(|| {
+                format!(
+                    "Failed to adjust client {}",
+                    client_label(pseudonymizer, args.client)
+                )
+            })?;
+            println!(
+                "Adjusted client {} by {} ({}): adjustment {}",
+                client_label(pseudonymizer, adjustment.client_id),
+                adjustment.amount,
+                adjustment.operator,
+                adjustment.id
+            );
+        }
+    }
+    Ok(())
+}
+
+fn print_requested(pseudonymizer: Option<&Pseudonymizer>, approval: &Approval) {
+    println!(
+        "The {} of client {} needs a second operator's approval: requested as approval {}",
+        approval.action.to_str(),
+        client_label(pseudonymizer, approval.client_id),
+        approval.id
+    );
+}
+
+/// A row of the pending approvals list, with the client pseudonymized if enabled.
+#[derive(serde::Serialize)]
+struct ApprovalRow<'a> {
+    id: i64,
+    client: String,
+    action: &'a str,
+    /// Empty for unlocks.
+    amount: Option<rust_decimal::Decimal>,
+    reason: Option<&'a str>,
+    requested_by: &'a str,
+    /// In milliseconds since the unix epoch.
+    requested_at: i64,
+}
+
+async fn approvals(builder: TransactionServiceBuilder) -> anyhow::Result<()> {
+    let transaction_svc = builder
+        .build()
+        .await
+        .context("Failed to get transaction service")?;
+    let approvals = transaction_svc.get_pending_approvals().await?;
+    let mut w = csv::Writer::from_writer(io::stdout().lock());
+    for approval in &approvals {
+        let (amount, reason) = match &approval.action {
+            ApprovalAction::Unlock => (None, None),
+            ApprovalAction::Adjustment { amount, reason } => (Some(*amount), Some(reason.as_str())),
+        };
+        w.serialize(ApprovalRow {
+            id: approval.id,
+            client: client_label(transaction_svc.pseudonymizer(), approval.client_id),
+            action: approval.action.to_str(),
+            amount,
+            reason,
+            requested_by: &approval.requested_by,
+            requested_at: approval.requested_at,
+        })?;
+    }
+    Ok(())
+}
+
+async fn decide_approval(
+    id: i64,
+    operator: &str,
+    approve: bool,
+    builder: TransactionServiceBuilder,
+) -> anyhow::Result<()> {
+    let transaction_svc = builder
+        .build()
+        .await
+        .context("Failed to get transaction service")?;
+    let approval = if approve {
+        transaction_svc.approve_request(id, operator).await
+    } else {
+        transaction_svc.reject_request(id, operator).await
+    }
+    .with_context(|| format!("Failed to decide approval {}", id))?;
+    println!(
+        "Approval {} ({} of client {}) {} by {}",
+        approval.id,
+        approval.action.to_str(),
+        client_label(transaction_svc.pseudonymizer(), approval.client_id),
+        approval.status.to_str(),
+        operator
+    );
+    Ok(())
+}
+
+async fn provenance(
+    transaction_id: TransactionId,
+    builder: TransactionServiceBuilder,
+) -> anyhow::Result<()> {
+    let provenance = builder
+        .build()
+        .await
+        .context("Failed to get transaction service")?
+        .get_provenance(transaction_id)
+        .await?
+        .with_context(|| format!("No provenance recorded for transaction {}", transaction_id))?;
+    println!("source: {}", provenance.batch.source);
+    println!(
+        "sha256: {}",
+        provenance.batch.sha256.as_deref().unwrap_or("-")
+    );
+    println!("line:   {}", provenance.line);
+    println!("batch:  {}", provenance.batch.id);
+    Ok(())
+}
+
+/// A row of the `--rejected` file.
+#[derive(serde::Serialize)]
+struct RejectedRow<'a> {
+    line: u64,
+    #[serde(rename = "type")]
+    transaction_type: &'a str,
+    client: String,
+    tx: TransactionId,
+    amount: Option<rust_decimal::Decimal>,
+    reason: &'a str,
+}
+
+async fn process_input(
+    args: &Args,
+    amounts: &AmountFormat,
+    metadata: Option<ReportMetadata>,
+    builder: TransactionServiceBuilder,
+) -> anyhow::Result<()> {
+    let (builder, updates) = match args.stream_updates {
+        true => {
+            let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+            (builder.observer(ClientUpdates(sender)), Some(receiver))
+        }
+        false => (builder, None),
+    };
+    let mut transaction_svc = builder
+        .build()
+        .await
+        .context("Failed to get transaction service")?;
+    // The reorder buffer holds back messages, so only their group's offsets are committed
+    #[cfg(feature = "kafka")]
+    if let Some(uri) = kafka_input(args)?.filter(|_| args.reorder_buffer.is_none()) {
+        return process_kafka(uri, args, transaction_svc).await;
+    }
+    let external_ids = match args.external_ids {
+        true => Some(transaction_svc.get_external_ids().await?),
+        false => None,
+    };
+    let (mut transaction_source, sha256) = get_transaction_source(args, external_ids.clone())?;
+    if let Some(capacity) = args.reorder_buffer {
+        transaction_source = Box::new(ReorderBuffer::new(transaction_source, capacity));
+    }
+
+    let batch = transaction_svc
+        .begin_batch(args.input.as_deref().unwrap_or_default(), sha256.as_deref())
+        .await?;
+    let mut rejected = create_rejected(args)?;
+    let mut return_file = args
+        .return_file
+        .as_ref()
+        .map(|_| ReturnFile::new(&batch.source));
+    let mut throughput = args
+        .throughput_stats
+        .as_ref()
+        .map(|_| ThroughputRecorder::new(THROUGHPUT_INTERVAL));
+    let recording = throughput.is_some();
+    let read_at = std::sync::Mutex::new(HashMap::new());
+    let transactions = transaction_source
+        .stream_with_lines()
+        .inspect(|transaction| {
+            if let (true, Ok((line, _))) = (recording, transaction) {
+                read_at
+                    .lock()
+                    .unwrap()
+                    .insert(*line, std::time::Instant::now());
+            }
+        });
+    let processing =
+        transaction_svc.process_batch_with(&batch, transactions, |line, transaction, outcome| {
+            if let Some(return_file) = &mut return_file {
+                return_file.record(line, transaction, outcome);
+            }
+            if let Some(throughput) = &mut throughput {
+                if let Some(read_at) = read_at.lock().unwrap().remove(&line) {
+                    throughput.record(line, read_at);
+                }
+            }
+            write_rejected(
+                &mut rejected,
+                transaction_svc.pseudonymizer(),
+                line,
+                transaction,
+                outcome,
+            )
+        });
+    let summary = match updates {
+        Some(updates) => {
+            let out = io::stdout();
+            stream_updates(&transaction_svc, updates, amounts, out, processing).await?
+        }
+        None => processing.await,
+    };
+    // Also when processing failed, as transactions committed before the failure may use them
+    if let Some(external_ids) = &external_ids {
+        transaction_svc.save_external_ids(external_ids).await?;
+    }
+    let summary = summary?;
+    if let Some(mut w) = rejected {
+        w.flush()?;
+    }
+    write_return_file(args, return_file)?;
+    write_throughput_stats(args, throughput)?;
+    print_summary(&summary, transaction_svc.transaction_counts());
+    if args.stream_updates {
+        return Ok(());
+    }
+
+    print_client_csv(
+        &mut transaction_svc,
+        args.stats,
+        None,
+        args.changed_only.then_some(batch.id),
+        amounts,
+        metadata,
+        Some(batch.id),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// A row of the `--dry-run` report.
+#[derive(serde::Serialize)]
+struct ClientDiffRow {
+    client: String,
+    available_before: String,
+    available_after: String,
+    held_before: String,
+    held_after: String,
+    total_before: String,
+    total_after: String,
+    total_change: String,
+    locked_before: bool,
+    locked_after: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    currency: Option<&'static str>,
+}
+
+/// Like [`process_input`], rolling the input back and writing the balances of the clients it
+/// would change before and after it to `out`.
+async fn dry_run(
+    args: &Args,
+    amounts: &AmountFormat,
+    metadata: Option<ReportMetadata>,
+    builder: TransactionServiceBuilder,
+    out: impl io::Write,
+) -> anyhow::Result<()> {
+    if args
+        .input
+        .as_deref()
+        .is_some_and(|i| i.starts_with("kafka://"))
+    {
+        anyhow::bail!("Dry runs are only supported for csv and jsonl input");
+    }
+    let transaction_svc = builder
+        .build()
+        .await
+        .context("Failed to get transaction service")?;
+    let external_ids = match args.external_ids {
+        true => Some(transaction_svc.get_external_ids().await?),
+        false => None,
+    };
+    let (mut transaction_source, _) = get_transaction_source(args, external_ids)?;
+    if let Some(capacity) = args.reorder_buffer {
+        transaction_source = Box::new(ReorderBuffer::new(transaction_source, capacity));
+    }
+    let (lines, transactions): (Vec<_>, Vec<_>) = transaction_source
+        .stream_with_lines()
+        .try_collect::<Vec<_>>()
+        .await?
+        .into_iter()
+        .unzip();
+    let projection = transaction_svc.simulate(&transactions).await?;
+
+    let pseudonymizer = transaction_svc.pseudonymizer();
+    let mut rejected = create_rejected(args)?;
+    let mut counts = BTreeMap::<_, Vec<_>>::new();
+    for ((line, transaction), outcome) in lines.iter().zip(&transactions).zip(&projection.outcomes)
+    {
+        write_rejected(&mut rejected, pseudonymizer, *line, transaction, outcome)?;
+        counts
+            .entry(transaction.transaction_type.to_str().to_string())
+            .or_default()
+            .push(outcome);
+    }
+    if let Some(mut w) = rejected {
+        w.flush()?;
+    }
+    print_summary(
+        &projection.outcomes.iter().collect(),
+        counts
+            .into_iter()
+            .map(|(transaction_type, outcomes)| (transaction_type, outcomes.into_iter().collect()))
+            .collect(),
+    );
+
+    let mut w = ReportWriter::new(out, metadata, None, amounts.locale)?;
+    for (before, after) in projection.changes() {
+        // New clients start without funds, at the ledger's precision
+        let zero = rust_decimal::Decimal::new(0, after.total.scale());
+        let (available, held, locked) =
+            before.map_or((zero, zero, false), |c| (c.available, c.held, c.locked));
+        w.serialize(ClientDiffRow {
+            client: client_label(pseudonymizer, after.id),
+            available_before: amounts.amount(available),
+            available_after: amounts.amount(after.available),
+            held_before: amounts.amount(held),
+            held_after: amounts.amount(after.held),
+            total_before: amounts.amount(available + held),
+            total_after: amounts.amount(after.total),
+            total_change: amounts.amount(after.total - (available + held)),
+            locked_before: locked,
+            locked_after: after.locked,
+            currency: amounts.currency(),
+        })?;
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// Like [`process_input`], spreading clients over `shards` databases.
+async fn process_sharded(
+    args: &Args,
+    shards: u16,
+    amounts: &AmountFormat,
+    metadata: Option<ReportMetadata>,
+    builder: TransactionServiceBuilder,
+) -> anyhow::Result<()> {
+    let sharded = builder
+        .build_sharded(shards.into())
+        .await
+        .context("Failed to get transaction service")?;
+    // The shards share their configuration
+    let pseudonymizer = sharded.shards()[0].pseudonymizer();
+    let (mut transaction_source, sha256) = get_transaction_source(args, None)?;
+    if let Some(capacity) = args.reorder_buffer {
+        transaction_source = Box::new(ReorderBuffer::new(transaction_source, capacity));
+    }
+
+    let source = args.input.as_deref().unwrap_or_default();
+    let mut rejected = create_rejected(args)?;
+    let mut return_file = args.return_file.as_ref().map(|_| ReturnFile::new(source));
+    // Each shard has a batch of its own, so the changed clients are collected here
+    let mut changed = args.changed_only.then(HashSet::new);
+    let summary = sharded
+        .process_batch_with(
+            source,
+            sha256.as_deref(),
+            transaction_source.stream_with_lines(),
+            |line, transaction, outcome| {
+                if let Some(return_file) = &mut return_file {
+                    return_file.record(line, transaction, outcome);
+                }
+                if let Some(changed) = changed.as_mut().filter(|_| outcome.is_applied()) {
+                    changed.insert(outcome.changed_client(transaction));
+                }
+                write_rejected(&mut rejected, pseudonymizer, line, transaction, outcome)
+            },
+        )
+        .await?;
+    if let Some(mut w) = rejected {
+        w.flush()?;
+    }
+    write_return_file(args, return_file)?;
+    print_summary(&summary, sharded.transaction_counts());
+
+    // Each shard has a batch of its own
+    let mut w = ReportWriter::new(io::stdout().lock(), metadata, None, amounts.locale)?;
+    if args.stats {
+        for client in sharded.get_client_stats().await? {
+            if is_included(&changed, client.client.id) {
+                write_client_stats(&mut w, pseudonymizer, client, amounts)?;
+            }
+        }
+    } else {
+        for c in sharded.get_clients_vec().await? {
+            if is_included(&changed, c.id) {
+                write_client(&mut w, pseudonymizer, c, amounts)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn create_rejected(args: &Args) -> anyhow::Result<Option<csv::Writer<File>>> {
+    let Some(path) = &args.rejected else {
+        return Ok(None);
+    };
+    let w = csv::Writer::from_path(path)
+        .with_context(|| format!("Failed to create \"{}\"", path.display()))?;
+    Ok(Some(w))
+}
+
+/// Writes the `--return-file` once the input was processed.
+fn write_return_file(args: &Args, return_file: Option<ReturnFile>) -> anyhow::Result<()> {
+    let (Some(path), Some(return_file)) = (&args.return_file, return_file) else {
+        return Ok(());
+    };
+    let format = match args.return_format {
+        ReturnFileFormat::Csv => ReturnFormat::Csv,
+        ReturnFileFormat::Pain002 => ReturnFormat::Pain002,
+    };
+    let created_at = time::OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000;
+    let mut w = io::BufWriter::new(
+        File::create(path).with_context(|| format!("Failed to create \"{}\"", path.display()))?,
+    );
+    return_file.write(format, &mut w, created_at as i64)?;
+    io::Write::flush(&mut w)?;
+    Ok(())
+}
+
+fn write_throughput_stats(
+    args: &Args,
+    throughput: Option<ThroughputRecorder>,
+) -> anyhow::Result<()> {
+    let (Some(path), Some(throughput)) = (&args.throughput_stats, throughput) else {
+        return Ok(());
+    };
+    let w = io::BufWriter::new(
+        File::create(path).with_context(|| format!("Failed to create \"{}\"", path.display()))?,
+    );
+    serde_json::to_writer_pretty(w, &throughput.finish())?;
+    Ok(())
+}
+
+/// Writes a rejected transaction to the `--rejected` file, if there is one.
+fn write_rejected(
+    rejected: &mut Option<csv::Writer<File>>,
+    pseudonymizer: Option<&Pseudonymizer>,
+    line: u64,
+    transaction: &Transaction,
+    outcome: &TransactionOutcome,
+) -> transaction_app::Result<()> {
+    if let (Some(w), Some(reason)) = (rejected, outcome.reason()) {
+        w.serialize(RejectedRow {
+            line,
+            transaction_type: transaction.transaction_type.to_str(),
+            client: client_label(pseudonymizer, transaction.client_id),
+            tx: transaction.id,
+            amount: transaction.amount,
+            reason,
+        })?;
+    }
+    Ok(())
+}
+
+fn print_summary(
+    summary: &ProcessingOutcome,
+    transaction_counts: BTreeMap<String, ProcessingOutcome>,
+) {
+    eprintln!(
+        "Processed {} transactions: {} applied, {} rejected ({} for insufficient funds), {} ignored",
+        summary.processed,
+        summary.applied,
+        summary.rejected,
+        summary.insufficient_funds,
+        summary.ignored
+    );
+    for (transaction_type, counts) in transaction_counts {
+        eprintln!(
+            "  {}: {} applied, {} rejected, {} ignored",
+            transaction_type, counts.applied, counts.rejected, counts.ignored
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dry_run, stream_updates, AmountFormat, Cli, ClientUpdates};
+    use clap::Parser;
+    use futures::TryStreamExt;
+    use transaction_app::{
+        Client, ClientFilter, Pagination, TransactionReader, TransactionService,
+    };
+
+    async fn clients(svc: &TransactionService) -> Vec<Client> {
+        svc.get_clients(&ClientFilter::default(), Pagination::default())
+            .await
+            .try_collect()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_dry_run() {
+        let dir = std::env::temp_dir().join(format!("dry-run-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let database = format!("sqlite://{}", dir.join("ledger.db").display());
+        let input = dir.join("input.csv");
+        let svc = TransactionService::builder()
+            .database_url(&database)
+            .build()
+            .await
+            .unwrap();
+        let csv = "type, client, tx, amount
+            deposit, 1, 1, 10
+            deposit, 2, 2, 5";
+        let mut reader = TransactionReader::new(csv.as_bytes());
+        svc.process_stream(futures::stream::iter(reader.transactions()))
+            .await
+            .unwrap();
+        let before = clients(&svc).await;
+
+        // Client 1 changes, client 2 is untouched and client 3 is new
+        std::fs::write(
+            &input,
+            "type,client,tx,amount\nwithdrawal,1,3,4\ndeposit,3,4,2.5\n",
+        )
+        .unwrap();
+        let cli = Cli::try_parse_from([
+            "transaction-app",
+            input.to_str().unwrap(),
+            "--dry-run",
+            "--database",
+            &database,
+        ])
+        .unwrap();
+        let mut out = Vec::new();
+        let builder = TransactionService::builder().database_url(&database);
+        dry_run(&cli.args, &AmountFormat::default(), None, builder, &mut out)
+            .await
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,available_before,available_after,held_before,held_after,total_before,total_after,total_change,locked_before,locked_after
+1,10.0000,6.0000,0.0000,0.0000,10.0000,6.0000,-4.0000,false,false
+3,0.0000,2.5000,0.0000,0.0000,0.0000,2.5000,2.5000,false,false
+"
+        );
+
+        assert_eq!(clients(&svc).await, before);
+        assert!(svc.get_transaction(3).await.unwrap().is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stream_updates() {
+        let (sender, updates) = tokio::sync::mpsc::unbounded_channel();
+        let svc = TransactionService::builder()
+            .observer(ClientUpdates(sender))
+            .build()
+            .await
+            .unwrap();
+        // The dispute is submitted under another client than the deposit's owner
+        let csv = "type, client, tx, amount
+            deposit, 1, 1, 10
+            deposit, 2, 2, 5
+            withdrawal, 2, 3, 50
+            dispute, 2, 1,";
+        let mut reader = TransactionReader::new(csv.as_bytes());
+        let processing = svc.process_stream(futures::stream::iter(reader.transactions()));
+        let mut out = Vec::new();
+        let outcome = stream_updates(
+            &svc,
+            updates,
+            &AmountFormat::default(),
+            &mut out,
+            processing,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(outcome.applied, 3);
+
+        // A line per applied transaction, the last ones after processing finished
+        let lines: Vec<serde_json::Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let clients: Vec<_> = lines.iter().map(|line| line["client"].clone()).collect();
+        assert_eq!(clients, ["1", "2", "1"]);
+        assert_eq!(
+            lines[1],
+            serde_json::json!({
+                "client": "2", "available": "5.0000", "held": "0.0000", "total": "5.0000",
+                "locked": false,
+            })
+        );
+        assert_eq!(
+            lines[2],
+            serde_json::json!({
+                "client": "1", "available": "0.0000", "held": "10.0000", "total": "10.0000",
+                "locked": false,
+            })
+        );
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn test_reload_config() {
+        use super::Reloadable;
+        use rust_decimal::Decimal;
+        use rust_decimal_macros::dec;
+        use transaction_app::{Transaction, TransactionType, TransactionValidator, Verdict};
+
+        // Whether the rules of `reloadable` reject a deposit of `amount`
+        let rejects = |reloadable: &Reloadable, amount: Decimal| {
+            let deposit = Transaction {
+                id: 1,
+                transaction_type: TransactionType::Deposit,
+                client_id: 1,
+                amount: Some(amount),
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            };
+            matches!(
+                reloadable.rules.validate(&deposit, None).unwrap(),
+                Verdict::Reject { .. }
+            )
+        };
+        let path = std::env::temp_dir().join(format!("reload-config-{}.toml", std::process::id()));
+        let write = |config: &str| std::fs::write(&path, config).unwrap();
+        let cli = Cli::try_parse_from([
+            "transaction-app",
+            "serve",
+            "--config",
+            path.to_str().unwrap(),
+            "--rate-limit",
+            "1",
+        ])
+        .unwrap();
+        let reloadable = Reloadable::new(&cli);
+        let rate_limits = reloadable.rate_limits().unwrap();
+
+        write(
+            r#"rules = ['reject "too_large" when amount > 100']
+            rate_limit = 1000
+            caller_rate_limit = 1"#,
+        );
+        reloadable.reload().unwrap();
+        assert!(rejects(&reloadable, dec!(150)) && !rejects(&reloadable, dec!(50)));
+        assert!(rate_limits.check(Some("alice"), &[1]).is_ok());
+        assert!(rate_limits.check(Some("alice"), &[1]).is_err());
+        assert!(rate_limits.check(Some("bob"), &[1]).is_ok());
+
+        // New limits and thresholds apply to later transactions and requests
+        write(
+            r#"rules = ['reject "too_large" when amount > 10']
+            rate_limit = 1000
+            caller_rate_limit = 1000"#,
+        );
+        reloadable.reload().unwrap();
+        assert!(rejects(&reloadable, dec!(50)));
+        assert!(rate_limits.check(Some("alice"), &[1]).is_ok());
+        assert!(rate_limits.check(Some("alice"), &[1]).is_ok());
+
+        // An invalid config keeps the previous settings
+        for invalid in [
+            r#"rules = ['reject when amount >']"#,
+            "rate_limit = 0",
+            // Settings that need a restart are not part of the config
+            r#"database = "sqlite://other.db""#,
+        ] {
+            write(invalid);
+            assert!(reloadable.reload().is_err(), "{}", invalid);
+            assert!(rejects(&reloadable, dec!(50)) && !rejects(&reloadable, dec!(5)));
+            assert!(rate_limits.check(Some("alice"), &[1]).is_ok());
+        }
+
+        // Settings left out of the config fall back to their flags given at startup
+        write("rules = []");
+        reloadable.reload().unwrap();
+        assert!(!rejects(&reloadable, dec!(50)));
+        assert!(rate_limits.check(Some("alice"), &[1]).is_ok());
+        assert!(rate_limits.check(Some("bob"), &[1]).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,233 @@
+//! Bearer token authentication and role based authorization.
+//!
+//! Callers send `Authorization: Bearer <token>`, where the token is either one of the static
+//! tokens given to [`Authenticator::with_token`] or a HS256 JWT signed with the secret given to
+//! [`Authenticator::with_jwt_secret`] and carrying `sub` and `role` claims.
+
+use super::{ApiError, AppState};
+use axum::extract::{Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+
+/// What an authenticated caller is allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Can read clients, transactions and disputes.
+    Viewer,
+    /// Can submit transactions.
+    Submitter,
+    /// Can do everything, including unlocking clients and adjusting balances.
+    Admin,
+}
+
+impl Role {
+    /// Whether this role may perform actions that require `required`.
+    pub fn allows(self, required: Role) -> bool {
+        self == required || self == Role::Admin
+    }
+}
+
+/// An authenticated caller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    /// The token's name, or the JWT `sub` claim.
+    pub name: String,
+    pub role: Role,
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    sub: String,
+    role: Role,
+}
+
+#[derive(Deserialize)]
+struct TokenRecord {
+    name: String,
+    role: Role,
+    token: String,
+}
+
+/// Resolves bearer tokens to an [`Identity`].
+#[derive(Default)]
+pub struct Authenticator {
+    tokens: HashMap<String, Identity>,
+    jwt: Option<(DecodingKey, Validation)>,
+}
+
+impl Authenticator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads static tokens from a csv with `name`, `role` and `token` columns.
+    pub fn read_tokens(mut self, reader: impl io::Read) -> Result<Self, csv::Error> {
+        for record in csv::Reader::from_reader(reader).deserialize() {
+            let TokenRecord { name, role, token } = record?;
+            self = self.with_token(token, Identity { name, role });
+        }
+        Ok(self)
+    }
+
+    /// Accepts `token` as `identity`.
+    pub fn with_token(mut self, token: impl Into<String>, identity: Identity) -> Self {
+        self.tokens.insert(token.into(), identity);
+        self
+    }
+
+    /// Accepts HS256 JWTs signed with `secret`. Tokens must have an `exp` claim.
+    pub fn with_jwt_secret(mut self, secret: &[u8]) -> Self {
+        self.jwt = Some((
+            DecodingKey::from_secret(secret),
+            Validation::new(Algorithm::HS256),
+        ));
+        self
+    }
+
+    /// Resolves a bearer token, returning `None` if it is not valid.
+    pub fn authenticate(&self, token: &str) -> Option<Identity> {
+        if let Some(identity) = self.tokens.get(token) {
+            return Some(identity.clone());
+        }
+        let (key, validation) = self.jwt.as_ref()?;
+        let claims = jsonwebtoken::decode::<Claims>(token, key, validation)
+            .ok()?
+            .claims;
+        Some(Identity {
+            name: claims.sub,
+            role: claims.role,
+        })
+    }
+
+    /// Resolves an `Authorization` header value and checks the caller has the `required` role.
+    pub fn authorize(
+        &self,
+        authorization: Option<&str>,
+        required: Role,
+    ) -> Result<Identity, AuthError> {
+        let token = authorization
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or(AuthError::Unauthenticated)?;
+        let identity = self
+            .authenticate(token.trim())
+            .ok_or(AuthError::Unauthenticated)?;
+        if !identity.role.allows(required) {
+            return Err(AuthError::Forbidden(required));
+        }
+        Ok(identity)
+    }
+}
+
+/// Why a request was not authorized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthError {
+    /// The bearer token is missing or invalid.
+    Unauthenticated,
+    /// The caller does not have the contained role.
+    Forbidden(Role),
+}
+
+impl From<AuthError> for ApiError {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::Unauthenticated => {
+                ApiError::new(StatusCode::UNAUTHORIZED, "A valid bearer token is required")
+            }
+            AuthError::Forbidden(role) => ApiError::new(
+                StatusCode::FORBIDDEN,
+                format!("The {:?} role is required", role),
+            ),
+        }
+    }
+}
+
+/// The state of the [`authorize`] middleware.
+#[derive(Clone)]
+pub struct RequireRole {
+    pub auth: Option<Arc<Authenticator>>,
+    pub role: Role,
+}
+
+impl RequireRole {
+    pub fn new(state: &AppState, role: Role) -> Self {
+        Self {
+            auth: state.auth.clone(),
+            role,
+        }
+    }
+}
+
+/// Middleware rejecting requests without the required role. Authorized requests get the
+/// caller's [`Identity`] as a request extension. Every request is allowed when there is no
+/// [`Authenticator`].
+pub async fn authorize(
+    State(require): State<RequireRole>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    if let Some(auth) = require.auth {
+        let authorization = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok());
+        let identity = auth.authorize(authorization, require.role)?;
+        request.extensions_mut().insert(identity);
+    }
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AuthError, Authenticator, Identity, Role};
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::json;
+
+    #[test]
+    fn test_jwt() {
+        let auth = Authenticator::new().with_jwt_secret(b"secret");
+        let token = |secret: &[u8], role: &str| {
+            jsonwebtoken::encode(
+                &Header::default(),
+                &json!({"sub": "partner-a", "role": role, "exp": 4102444800u64}),
+                &EncodingKey::from_secret(secret),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(
+            auth.authorize(
+                Some(&format!("Bearer {}", token(b"secret", "submitter"))),
+                Role::Submitter
+            ),
+            Ok(Identity {
+                name: "partner-a".into(),
+                role: Role::Submitter,
+            })
+        );
+        assert_eq!(
+            auth.authorize(
+                Some(&format!("Bearer {}", token(b"secret", "viewer"))),
+                Role::Submitter
+            ),
+            Err(AuthError::Forbidden(Role::Submitter))
+        );
+        assert_eq!(
+            auth.authorize(
+                Some(&format!("Bearer {}", token(b"other", "admin"))),
+                Role::Viewer
+            ),
+            Err(AuthError::Unauthenticated)
+        );
+        assert_eq!(
+            auth.authorize(None, Role::Viewer),
+            Err(AuthError::Unauthenticated)
+        );
+    }
+}
@@ -0,0 +1,229 @@
+//! Fails submissions fast while the database is unavailable.
+
+use super::ApiError;
+use axum::http::StatusCode;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long callers are asked to wait while another submission probes the database.
+const PROBE_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Whether a [`CircuitBreaker`] lets submissions through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Submissions are processed.
+    Closed,
+    /// The database failed too many submissions in a row, and submissions are rejected.
+    Open,
+    /// A single submission is probing whether the database recovered.
+    HalfOpen,
+}
+
+impl CircuitState {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Closed => "closed",
+            Self::Open => "open",
+            Self::HalfOpen => "half_open",
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Circuit {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen,
+}
+
+#[derive(Debug)]
+struct Inner {
+    circuit: Circuit,
+    trips: u64,
+}
+
+/// Trips after `failure_threshold` submissions in a row failed because the database was
+/// unavailable, and then rejects submissions without waiting on the database. After
+/// `open_for`, the next submission is let through as a probe: the breaker closes if it
+/// succeeds, and opens again if it does not.
+///
+/// Submissions rejected by a client error tell nothing about the database, and are not counted
+/// either way.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    open_for: Duration,
+    inner: Mutex<Inner>,
+}
+
+/// A submission was rejected by an open [`CircuitBreaker`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitOpen {
+    /// How long until the database is probed again.
+    pub retry_after: Duration,
+}
+
+impl From<CircuitOpen> for ApiError {
+    fn from(e: CircuitOpen) -> Self {
+        ApiError {
+            // Round up so a retry after `Retry-After` seconds is let through.
+            retry_after: Some(
+                e.retry_after.as_secs() + u64::from(e.retry_after.subsec_nanos() > 0),
+            ),
+            ..ApiError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "The database is unavailable, submissions are rejected until it recovers",
+            )
+        }
+    }
+}
+
+/// A submission let through by a [`CircuitBreaker`], reporting whether the database handled it.
+#[derive(Debug)]
+pub struct Permit<'a> {
+    breaker: &'a CircuitBreaker,
+    probe: bool,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, open_for: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            open_for,
+            inner: Mutex::new(Inner {
+                circuit: Circuit::Closed { failures: 0 },
+                trips: 0,
+            }),
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        match self.inner.lock().unwrap().circuit {
+            Circuit::Closed { .. } => CircuitState::Closed,
+            Circuit::Open { .. } => CircuitState::Open,
+            Circuit::HalfOpen => CircuitState::HalfOpen,
+        }
+    }
+
+    /// How many times the breaker opened.
+    pub fn trips(&self) -> u64 {
+        self.inner.lock().unwrap().trips
+    }
+
+    /// Lets a submission through, unless the breaker is open or another submission is probing.
+    pub fn acquire(&self) -> Result<Permit<'_>, CircuitOpen> {
+        let mut inner = self.inner.lock().unwrap();
+        let probe = match inner.circuit {
+            Circuit::Closed { .. } => false,
+            Circuit::Open { until } => {
+                let now = Instant::now();
+                if now < until {
+                    return Err(CircuitOpen {
+                        retry_after: until - now,
+                    });
+                }
+                inner.circuit = Circuit::HalfOpen;
+                true
+            }
+            Circuit::HalfOpen => {
+                return Err(CircuitOpen {
+                    retry_after: PROBE_RETRY_AFTER,
+                })
+            }
+        };
+        Ok(Permit {
+            breaker: self,
+            probe,
+        })
+    }
+}
+
+impl Permit<'_> {
+    /// The database processed the submission.
+    pub fn succeeded(mut self) {
+        let mut inner = self.breaker.inner.lock().unwrap();
+        if self.probe {
+            tracing::info!("The database recovered, accepting submissions again");
+            self.probe = false;
+            inner.circuit = Circuit::Closed { failures: 0 };
+        } else if let Circuit::Closed { failures } = &mut inner.circuit {
+            *failures = 0;
+        }
+    }
+
+    /// The submission failed because the database was unavailable.
+    pub fn failed(mut self) {
+        let breaker = self.breaker;
+        let mut inner = breaker.inner.lock().unwrap();
+        let open = match &mut inner.circuit {
+            _ if self.probe => true,
+            Circuit::Closed { failures } => {
+                *failures += 1;
+                *failures >= breaker.failure_threshold
+            }
+            // Submissions let through before the breaker opened
+            Circuit::Open { .. } | Circuit::HalfOpen => false,
+        };
+        if open {
+            if !self.probe {
+                inner.trips += 1;
+            }
+            tracing::warn!(
+                open_for = ?breaker.open_for,
+                "The database is unavailable, rejecting submissions"
+            );
+            self.probe = false;
+            inner.circuit = Circuit::Open {
+                until: Instant::now() + breaker.open_for,
+            };
+        }
+    }
+}
+
+impl Drop for Permit<'_> {
+    /// A probe that was dropped, or rejected for a client error, lets the next submission
+    /// probe instead.
+    fn drop(&mut self) {
+        if self.probe {
+            let mut inner = self.breaker.inner.lock().unwrap();
+            inner.circuit = Circuit::Open {
+                until: Instant::now(),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CircuitBreaker, CircuitState};
+    use std::time::Duration;
+
+    #[test]
+    fn test_circuit_breaker() {
+        let breaker = CircuitBreaker::new(2, Duration::from_secs(60));
+        breaker.acquire().unwrap().failed();
+        breaker.acquire().unwrap().succeeded();
+        breaker.acquire().unwrap().failed();
+        // Client errors are not counted
+        drop(breaker.acquire().unwrap());
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        breaker.acquire().unwrap().failed();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert_eq!(breaker.trips(), 1);
+        let open = breaker.acquire().unwrap_err();
+        assert!(open.retry_after > Duration::from_secs(59));
+
+        let breaker = CircuitBreaker::new(1, Duration::ZERO);
+        breaker.acquire().unwrap().failed();
+        // One probe at a time
+        let probe = breaker.acquire().unwrap();
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(breaker.acquire().is_err());
+        probe.failed();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        drop(breaker.acquire().unwrap());
+        breaker.acquire().unwrap().succeeded();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert_eq!(breaker.trips(), 1);
+    }
+}
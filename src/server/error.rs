@@ -0,0 +1,59 @@
+use crate::TransactionError;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde_json::json;
+
+/// An error response, rendered as `{"error": "<message>"}`.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub message: String,
+    /// Sent as the `Retry-After` header, in seconds.
+    pub retry_after: Option<u64>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+            retry_after: None,
+        }
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, message)
+    }
+}
+
+impl From<TransactionError> for ApiError {
+    fn from(e: TransactionError) -> Self {
+        let status = match &e {
+            TransactionError::Csv(_)
+            | TransactionError::Json(_)
+            | TransactionError::InvalidTransaction { .. }
+            | TransactionError::InvalidArgument(_) => StatusCode::BAD_REQUEST,
+            TransactionError::ClientNotFound { .. } => StatusCode::NOT_FOUND,
+            TransactionError::ClientLocked { .. } => StatusCode::CONFLICT,
+            TransactionError::InsufficientFunds { .. }
+            | TransactionError::BalanceOverflow { .. }
+            | TransactionError::IdempotencyKeyReused { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            e if e.is_unavailable() => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Self::new(status, e.to_string())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut response = (self.status, Json(json!({ "error": self.message }))).into_response();
+        if let Some(retry_after) = self.retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, retry_after.into());
+        }
+        response
+    }
+}
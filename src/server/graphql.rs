@@ -0,0 +1,335 @@
+//! A read-only GraphQL schema over clients, transactions and disputes.
+//!
+//! Served on `POST /graphql`, with GraphiQL on `GET /graphql`.
+
+use super::handlers::page_size;
+use crate::{
+    Client, ClientFilter, ClientId, Dispute, Pagination, Transaction, TransactionFilter,
+    TransactionId, TransactionService,
+};
+use async_graphql::http::GraphiQLSource;
+use async_graphql::{Context, EmptyMutation, EmptySubscription, Enum, Object};
+use axum::extract::State;
+use axum::response::Html;
+use axum::Json;
+use futures::TryStreamExt;
+use rust_decimal::Decimal;
+use std::sync::Arc;
+
+pub type LedgerSchema = async_graphql::Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+/// Builds the schema, resolving queries against `svc`.
+pub fn schema(svc: Arc<TransactionService>) -> LedgerSchema {
+    async_graphql::Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(svc)
+        .finish()
+}
+
+/// `POST /graphql`
+pub async fn post_graphql(
+    State(schema): State<LedgerSchema>,
+    Json(request): Json<async_graphql::Request>,
+) -> Json<async_graphql::Response> {
+    Json(schema.execute(request).await)
+}
+
+/// `GET /graphql`
+pub async fn get_graphiql() -> Html<String> {
+    Html(GraphiQLSource::build().endpoint("/graphql").finish())
+}
+
+fn service<'a>(ctx: &Context<'a>) -> &'a TransactionService {
+    ctx.data_unchecked::<Arc<TransactionService>>()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum)]
+#[graphql(name = "TransactionType")]
+pub enum GraphQLTransactionType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+}
+
+impl From<&crate::TransactionType> for GraphQLTransactionType {
+    fn from(transaction_type: &crate::TransactionType) -> Self {
+        use crate::TransactionType::*;
+        match transaction_type {
+            Deposit => Self::Deposit,
+            Withdrawal => Self::Withdrawal,
+            Dispute => Self::Dispute,
+            Resolve => Self::Resolve,
+            Chargeback => Self::Chargeback,
+            Custom(_) => unreachable!("Custom transactions are not stored"),
+        }
+    }
+}
+
+impl From<GraphQLTransactionType> for crate::TransactionType {
+    fn from(transaction_type: GraphQLTransactionType) -> Self {
+        use GraphQLTransactionType::*;
+        match transaction_type {
+            Deposit => Self::Deposit,
+            Withdrawal => Self::Withdrawal,
+            Dispute => Self::Dispute,
+            Resolve => Self::Resolve,
+            Chargeback => Self::Chargeback,
+        }
+    }
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// A single client.
+    async fn client(
+        &self,
+        ctx: &Context<'_>,
+        id: ClientId,
+    ) -> async_graphql::Result<Option<Client>> {
+        Ok(service(ctx).get_client(id).await?)
+    }
+
+    /// Clients ordered by id. `limit` defaults to 100 and is capped at 1000.
+    #[allow(clippy::too_many_arguments)]
+    async fn clients(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] locked_only: bool,
+        min_total: Option<Decimal>,
+        max_total: Option<Decimal>,
+        tag: Option<String>,
+        min_risk_score: Option<i64>,
+        after: Option<ClientId>,
+        limit: Option<u32>,
+    ) -> async_graphql::Result<Vec<Client>> {
+        let filter = ClientFilter {
+            locked_only,
+            min_total,
+            max_total,
+            tag,
+            min_risk_score,
+            changed_in_batch: None,
+        };
+        let pagination = Pagination {
+            after,
+            limit: Some(page_size(limit)),
+        };
+        Ok(service(ctx)
+            .get_clients(&filter, pagination)
+            .await
+            .try_collect()
+            .await?)
+    }
+
+    /// A single stored deposit or withdrawal.
+    async fn transaction(
+        &self,
+        ctx: &Context<'_>,
+        id: TransactionId,
+    ) -> async_graphql::Result<Option<Transaction>> {
+        Ok(service(ctx).get_transaction(id).await?)
+    }
+
+    /// The stored deposits and withdrawals with an external reference, ordered by id.
+    async fn transactions_by_reference(
+        &self,
+        ctx: &Context<'_>,
+        reference: String,
+    ) -> async_graphql::Result<Vec<Transaction>> {
+        Ok(service(ctx)
+            .get_transactions_by_reference(&reference)
+            .await?)
+    }
+
+    /// Open disputes, optionally only those of `client`.
+    async fn disputes(
+        &self,
+        ctx: &Context<'_>,
+        client: Option<ClientId>,
+    ) -> async_graphql::Result<Vec<Dispute>> {
+        Ok(service(ctx).get_open_disputes(client).try_collect().await?)
+    }
+}
+
+#[Object]
+impl Client {
+    async fn id(&self) -> ClientId {
+        self.id
+    }
+
+    async fn available(&self) -> Decimal {
+        self.available
+    }
+
+    async fn held(&self) -> Decimal {
+        self.held
+    }
+
+    async fn total(&self) -> Decimal {
+        self.total
+    }
+
+    async fn locked(&self) -> bool {
+        self.locked
+    }
+
+    /// The client's transactions ordered by id. `limit` defaults to 100 and is capped at 1000.
+    async fn transactions(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(name = "type")] transaction_type: Option<GraphQLTransactionType>,
+        disputed: Option<bool>,
+        after: Option<TransactionId>,
+        limit: Option<u32>,
+    ) -> async_graphql::Result<Vec<Transaction>> {
+        let filter = TransactionFilter {
+            transaction_type: transaction_type.map(Into::into),
+            disputed,
+            ..Default::default()
+        };
+        let pagination = Pagination {
+            after,
+            limit: Some(page_size(limit)),
+        };
+        Ok(service(ctx)
+            .get_client_transactions(self.id, &filter, pagination)
+            .try_collect()
+            .await?)
+    }
+}
+
+#[Object]
+impl Transaction {
+    async fn id(&self) -> TransactionId {
+        self.id
+    }
+
+    #[graphql(name = "type")]
+    async fn transaction_type(&self) -> GraphQLTransactionType {
+        (&self.transaction_type).into()
+    }
+
+    async fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    async fn amount(&self) -> Option<Decimal> {
+        self.amount
+    }
+
+    /// The external reference of a deposit or withdrawal, such as the bank's.
+    async fn reference(&self) -> Option<&str> {
+        self.reference.as_deref()
+    }
+
+    async fn client(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<Client>> {
+        Ok(service(ctx).get_client(self.client_id).await?)
+    }
+}
+
+#[Object]
+impl Dispute {
+    /// The disputed transaction.
+    async fn transaction(&self) -> &Transaction {
+        &self.transaction
+    }
+
+    /// When the dispute was opened, in milliseconds since the unix epoch.
+    async fn opened_at(&self) -> i64 {
+        self.opened_at
+    }
+
+    /// When the dispute was escalated for holding its funds for too long, if it was.
+    async fn escalated_at(&self) -> Option<i64> {
+        self.escalated_at
+    }
+
+    /// Why the dispute was opened, as given when opening it.
+    async fn reason_code(&self) -> Option<&str> {
+        self.reason_code.as_deref()
+    }
+
+    async fn notes(&self) -> Option<&str> {
+        self.notes.as_deref()
+    }
+
+    /// Where the dispute is in the risk team's workflow, e.g. `under_review`.
+    async fn status(&self) -> &str {
+        self.status.to_str()
+    }
+
+    /// Who is working on the dispute, if anyone.
+    async fn assignee(&self) -> Option<&str> {
+        self.assignee.as_deref()
+    }
+
+    /// The URLs or blob hashes of the evidence attached to the dispute, in the order they
+    /// were attached.
+    async fn evidence(&self) -> Vec<&str> {
+        self.evidence.iter().map(|e| e.reference.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::schema;
+    use crate::{Transaction, TransactionService, TransactionType};
+    use rust_decimal_macros::dec;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_query() {
+        let svc = Arc::new(TransactionService::builder().build().await.unwrap());
+        for (id, transaction_type) in [(1, TransactionType::Deposit), (2, TransactionType::Deposit)]
+        {
+            svc.process_transaction(&Transaction {
+                id,
+                transaction_type,
+                client_id: 1,
+                amount: Some(dec!(1.5)),
+                reference: Some(format!("BANK-{}", id)),
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+            .await
+            .unwrap();
+        }
+        svc.process_transaction(&Transaction {
+            id: 2,
+            transaction_type: TransactionType::Dispute,
+            client_id: 1,
+            amount: None,
+            reference: None,
+            reason_code: None,
+            notes: None,
+            sequence: None,
+        })
+        .await
+        .unwrap();
+
+        let response = schema(svc)
+            .execute(
+                "{ client(id: 1) { available held transactions(disputed: false) { id type } } \
+                   disputes { transaction { id client { total } } } \
+                   transactionsByReference(reference: \"BANK-2\") { id reference } }",
+            )
+            .await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        assert_eq!(
+            response.data.into_json().unwrap(),
+            serde_json::json!({
+                "client": {
+                    "available": "1.5000",
+                    "held": "1.5000",
+                    "transactions": [{ "id": 1, "type": "DEPOSIT" }],
+                },
+                "disputes": [{ "transaction": { "id": 2, "client": { "total": "3.0000" } } }],
+                "transactionsByReference": [{ "id": 2, "reference": "BANK-2" }],
+            })
+        );
+    }
+}
@@ -0,0 +1,412 @@
+//! A gRPC api over a [`TransactionService`], see `proto/ledger.proto`.
+
+use super::auth::{AuthError, Authenticator, Identity, Role};
+use super::circuit_breaker::{CircuitBreaker, Permit};
+use super::rate_limit::RateLimits;
+use crate::{
+    Client, ClientId, ProcessingOutcome, TransactionError, TransactionId, TransactionService,
+    TransactionType, WritePriority,
+};
+use futures::{StreamExt, TryStreamExt};
+use rust_decimal::Decimal;
+use std::str::FromStr;
+use std::sync::Arc;
+use tonic::{Request, Response, Status, Streaming};
+
+#[allow(clippy::all)]
+pub mod proto {
+    tonic::include_proto!("ledger");
+}
+
+use proto::ledger_server::{Ledger, LedgerServer};
+
+/// Implements the `Ledger` gRPC service.
+pub struct LedgerService {
+    svc: Arc<TransactionService>,
+    auth: Option<Arc<Authenticator>>,
+    rate_limits: Option<Arc<RateLimits>>,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+}
+
+impl LedgerService {
+    pub fn new(svc: Arc<TransactionService>) -> Self {
+        Self {
+            svc,
+            auth: None,
+            rate_limits: None,
+            circuit_breaker: None,
+        }
+    }
+
+    /// Requires every call to carry `authorization: Bearer <token>` metadata accepted by `auth`,
+    /// with the submitter role to submit transactions and the viewer role to read clients.
+    pub fn with_auth(mut self, auth: Arc<Authenticator>) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// Rejects submissions exceeding `rate_limits` with `RESOURCE_EXHAUSTED`.
+    pub fn with_rate_limits(mut self, rate_limits: Arc<RateLimits>) -> Self {
+        self.rate_limits = Some(rate_limits);
+        self
+    }
+
+    /// Rejects submissions with `UNAVAILABLE` while `circuit_breaker` is open.
+    pub fn with_circuit_breaker(mut self, circuit_breaker: Arc<CircuitBreaker>) -> Self {
+        self.circuit_breaker = Some(circuit_breaker);
+        self
+    }
+
+    fn acquire(&self) -> Result<Option<Permit<'_>>, Status> {
+        let Some(circuit_breaker) = &self.circuit_breaker else {
+            return Ok(None);
+        };
+        circuit_breaker.acquire().map(Some).map_err(|_| {
+            Status::unavailable(
+                "The database is unavailable, submissions are rejected until it recovers",
+            )
+        })
+    }
+
+    fn authorize<T>(
+        &self,
+        request: &Request<T>,
+        required: Role,
+    ) -> Result<Option<Identity>, Status> {
+        let Some(auth) = &self.auth else {
+            return Ok(None);
+        };
+        let authorization = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok());
+        match auth.authorize(authorization, required) {
+            Ok(identity) => Ok(Some(identity)),
+            Err(AuthError::Unauthenticated) => {
+                Err(Status::unauthenticated("A valid bearer token is required"))
+            }
+            Err(AuthError::Forbidden(role)) => Err(Status::permission_denied(format!(
+                "The {:?} role is required",
+                role
+            ))),
+        }
+    }
+
+    /// Wraps the service for use with a [`tonic::transport::Server`].
+    pub fn into_server(self) -> LedgerServer<Self> {
+        LedgerServer::new(self)
+    }
+}
+
+/// Serves the gRPC api on `addr` until ctrl-c is pressed.
+pub async fn serve(
+    service: LedgerService,
+    addr: std::net::SocketAddr,
+) -> Result<(), tonic::transport::Error> {
+    tonic::transport::Server::builder()
+        .trace_fn(|request| tracing::info_span!("grpc_request", path = %request.uri().path()))
+        .add_service(service.into_server())
+        .serve_with_shutdown(addr, async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await
+}
+
+/// Serves the gRPC api over TLS on `addr` until ctrl-c is pressed.
+#[cfg(feature = "tls")]
+pub async fn serve_tls(
+    service: LedgerService,
+    addr: std::net::SocketAddr,
+    tls: &super::TlsConfig,
+) -> Result<(), tonic::transport::Error> {
+    use tonic::transport::{Identity, ServerTlsConfig};
+
+    super::tls::install_crypto_provider();
+    tonic::transport::Server::builder()
+        .trace_fn(|request| tracing::info_span!("grpc_request", path = %request.uri().path()))
+        .tls_config(ServerTlsConfig::new().identity(Identity::from_pem(&tls.cert, &tls.key)))?
+        .add_service(service.into_server())
+        .serve_with_shutdown(addr, async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await
+}
+
+fn check_rate_limits(
+    rate_limits: Option<&RateLimits>,
+    identity: Option<&Identity>,
+    client_id: ClientId,
+) -> Result<(), Status> {
+    match rate_limits.map(|r| r.check(identity.map(|i| i.name.as_str()), &[client_id])) {
+        Some(Err(_)) => Err(Status::resource_exhausted("Rate limit exceeded")),
+        _ => Ok(()),
+    }
+}
+
+/// Reports whether the database handled a submission let through by a circuit breaker.
+fn record<T>(permit: Option<Permit<'_>>, result: &Result<T, Status>) {
+    match (permit, result) {
+        (Some(permit), Ok(_)) => permit.succeeded(),
+        (Some(permit), Err(status)) if status.code() == tonic::Code::Unavailable => permit.failed(),
+        _ => {}
+    }
+}
+
+/// Rejects submissions while writes are paused, rather than holding them until they resume.
+fn check_paused(svc: &TransactionService) -> Result<(), Status> {
+    if svc.is_paused() {
+        return Err(Status::unavailable("Writes are paused"));
+    }
+    Ok(())
+}
+
+fn status_from_error(e: TransactionError) -> Status {
+    // Errors from the request stream, or rate limits on it, are passed through as is
+    if let TransactionError::Source(source) = &e {
+        if let Some(status) = source.downcast_ref::<Status>() {
+            return status.clone();
+        }
+    }
+    match &e {
+        TransactionError::Csv(_)
+        | TransactionError::Json(_)
+        | TransactionError::InvalidTransaction { .. }
+        | TransactionError::InvalidArgument(_) => Status::invalid_argument(e.to_string()),
+        TransactionError::ClientNotFound { .. } => Status::not_found(e.to_string()),
+        TransactionError::ClientLocked { .. }
+        | TransactionError::InsufficientFunds { .. }
+        | TransactionError::BalanceOverflow { .. }
+        | TransactionError::IdempotencyKeyReused { .. } => {
+            Status::failed_precondition(e.to_string())
+        }
+        e if e.is_unavailable() => Status::unavailable(e.to_string()),
+        _ => Status::internal(e.to_string()),
+    }
+}
+
+/// The request metadata identifying a submission, so a retried call is applied once.
+pub const IDEMPOTENCY_KEY_METADATA: &str = "idempotency-key";
+
+fn idempotency_key(metadata: &tonic::metadata::MetadataMap) -> Result<Option<String>, Status> {
+    metadata
+        .get(IDEMPOTENCY_KEY_METADATA)
+        .map(|key| {
+            key.to_str()
+                .map(str::to_string)
+                .map_err(|_| Status::invalid_argument("The idempotency-key must be visible ascii"))
+        })
+        .transpose()
+}
+
+impl TryFrom<proto::Transaction> for crate::Transaction {
+    type Error = TransactionError;
+
+    fn try_from(t: proto::Transaction) -> Result<Self, Self::Error> {
+        let id = TransactionId::try_from(t.tx).map_err(|_| {
+            TransactionError::InvalidArgument(format!("transaction id {} out of range", t.tx))
+        })?;
+        let transaction_type = match t.r#type() {
+            proto::TransactionType::Deposit => TransactionType::Deposit,
+            proto::TransactionType::Withdrawal => TransactionType::Withdrawal,
+            proto::TransactionType::Dispute => TransactionType::Dispute,
+            proto::TransactionType::Resolve => TransactionType::Resolve,
+            proto::TransactionType::Chargeback => TransactionType::Chargeback,
+            proto::TransactionType::Unspecified => {
+                return Err(TransactionError::invalid(id, "Missing transaction type"))
+            }
+        };
+        let client_id = ClientId::try_from(t.client)
+            .map_err(|_| TransactionError::invalid(id, "Client id out of range"))?;
+        let amount = t
+            .amount
+            .as_deref()
+            .map(Decimal::from_str)
+            .transpose()
+            .map_err(|_| TransactionError::invalid(id, "Invalid amount"))?;
+
+        Ok(crate::Transaction {
+            id,
+            transaction_type,
+            client_id,
+            amount,
+            reference: t.reference,
+            reason_code: t.reason_code,
+            notes: t.notes,
+            sequence: None,
+        })
+    }
+}
+
+impl From<Client> for proto::Client {
+    fn from(c: Client) -> Self {
+        Self {
+            client: c.id.into(),
+            available: c.available.to_string(),
+            held: c.held.to_string(),
+            total: c.total.to_string(),
+            locked: c.locked,
+        }
+    }
+}
+
+impl From<ProcessingOutcome> for proto::ProcessingOutcome {
+    fn from(o: ProcessingOutcome) -> Self {
+        Self {
+            processed: o.processed as u64,
+            applied: o.applied as u64,
+            rejected: o.rejected as u64,
+            ignored: o.ignored as u64,
+            insufficient_funds: o.insufficient_funds as u64,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Ledger for LedgerService {
+    async fn submit_transaction(
+        &self,
+        request: Request<proto::Transaction>,
+    ) -> Result<Response<proto::SubmitTransactionReply>, Status> {
+        let identity = self.authorize(&request, Role::Submitter)?;
+        check_paused(&self.svc)?;
+        let idempotency_key = idempotency_key(request.metadata())?;
+        let transaction =
+            crate::Transaction::try_from(request.into_inner()).map_err(status_from_error)?;
+        check_rate_limits(
+            self.rate_limits.as_deref(),
+            identity.as_ref(),
+            transaction.client_id,
+        )?;
+        let permit = self.acquire()?;
+        let outcome = match idempotency_key {
+            Some(key) => self.svc.process_transaction_once(&key, &transaction).await,
+            None => self.svc.process_transaction(&transaction).await,
+        }
+        .map_err(status_from_error);
+        record(permit, &outcome);
+        let outcome = outcome?;
+
+        // Already a u64 with the wide-ids feature
+        #[allow(clippy::unnecessary_cast)]
+        let tx = transaction.id as u64;
+        Ok(Response::new(proto::SubmitTransactionReply {
+            tx,
+            outcome: outcome.to_str().to_string(),
+        }))
+    }
+
+    async fn submit_transaction_stream(
+        &self,
+        request: Request<Streaming<proto::Transaction>>,
+    ) -> Result<Response<proto::ProcessingOutcome>, Status> {
+        let identity = self.authorize(&request, Role::Submitter)?;
+        check_paused(&self.svc)?;
+        let idempotency_key = idempotency_key(request.metadata())?;
+        let rate_limits = self.rate_limits.as_deref();
+        let permit = self.acquire()?;
+        let transactions = request.into_inner().map(|t| {
+            let transaction = match t {
+                Ok(t) => crate::Transaction::try_from(t)?,
+                Err(status) => return Err(TransactionError::Source(Box::new(status))),
+            };
+            check_rate_limits(rate_limits, identity.as_ref(), transaction.client_id)
+                .map_err(|status| TransactionError::Source(Box::new(status)))?;
+            Ok(transaction)
+        });
+        // Streams wait behind single submissions
+        let outcome = WritePriority::Bulk
+            .scope(async {
+                match idempotency_key {
+                    // The whole stream is needed to tell a replay from a different submission.
+                    Some(key) => {
+                        let transactions: Vec<_> = transactions.try_collect().await?;
+                        self.svc
+                            .process_transactions_once(&key, &transactions)
+                            .await
+                            .map(|outcomes| outcomes.iter().collect())
+                    }
+                    None => self.svc.process_stream(transactions).await,
+                }
+            })
+            .await
+            .map_err(status_from_error);
+        record(permit, &outcome);
+
+        Ok(Response::new(outcome?.into()))
+    }
+
+    async fn get_client(
+        &self,
+        request: Request<proto::GetClientRequest>,
+    ) -> Result<Response<proto::Client>, Status> {
+        self.authorize(&request, Role::Viewer)?;
+        let client_id = request.into_inner().client;
+        let client = ClientId::try_from(client_id)
+            .ok()
+            .map(|id| self.svc.get_client(id));
+        let client = match client {
+            Some(client) => client.await.map_err(status_from_error)?,
+            None => None,
+        };
+
+        client
+            .map(|c| Response::new(c.into()))
+            .ok_or_else(|| Status::not_found(format!("client {} does not exist", client_id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{proto, Ledger, LedgerService};
+    use crate::TransactionService;
+    use std::sync::Arc;
+    use tonic::{Code, Request};
+
+    #[tokio::test]
+    async fn test_submit_and_get_client() {
+        let svc = TransactionService::builder().build().await.unwrap();
+        let ledger = LedgerService::new(Arc::new(svc));
+
+        let reply = ledger
+            .submit_transaction(Request::new(proto::Transaction {
+                tx: 1,
+                r#type: proto::TransactionType::Deposit.into(),
+                client: 1,
+                amount: Some("1.5".into()),
+                reference: None,
+                reason_code: None,
+                notes: None,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(reply.outcome, "deposit");
+
+        let client = ledger
+            .get_client(Request::new(proto::GetClientRequest { client: 1 }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(client.available, "1.5000");
+
+        let status = ledger
+            .get_client(Request::new(proto::GetClientRequest { client: 70000 }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        let status = ledger
+            .submit_transaction(Request::new(proto::Transaction {
+                tx: 2,
+                r#type: proto::TransactionType::Unspecified.into(),
+                client: 1,
+                amount: None,
+                reference: None,
+                reason_code: None,
+                notes: None,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+}
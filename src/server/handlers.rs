@@ -0,0 +1,770 @@
+//! Request handlers and their query parameters.
+
+use super::auth::Identity;
+use super::circuit_breaker::CircuitState;
+use super::{ApiError, AppState};
+use crate::transactions::reader::JsonTransaction;
+use crate::{
+    AlertKind, Annotation, Approval, AuditRecord, Checkpoints, Client, ClientDump, ClientFilter,
+    ClientId, ClientRisk, ClientTag, Dispute, DisputeStatus, GroupBy, Pagination,
+    ProcessingOutcome, Transaction, TransactionAggregate, TransactionError, TransactionFilter,
+    TransactionId, TransactionOutcome, TransactionType, Void, WritePriority,
+};
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use axum::Json;
+use futures::TryStreamExt;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt::Write;
+
+const DEFAULT_PAGE_SIZE: u32 = 100;
+const MAX_PAGE_SIZE: u32 = 1000;
+
+pub(crate) fn page_size(limit: Option<u32>) -> u32 {
+    limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE)
+}
+
+fn parse_transaction(value: Value) -> Result<Transaction, ApiError> {
+    let transaction: JsonTransaction =
+        serde_json::from_value(value).map_err(crate::TransactionError::from)?;
+    Ok(transaction.into_transaction()?)
+}
+
+/// The response to `POST /transactions`.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum SubmissionResponse {
+    Single {
+        tx: TransactionId,
+        outcome: &'static str,
+        /// Why a validator rejected the transaction.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    Batch(ProcessingOutcome),
+}
+
+/// The header identifying a submission, so a retried `POST /transactions` is applied once.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// `POST /transactions` with a single transaction object, or an array of transactions which
+/// are processed in order as a batch.
+///
+/// With an `Idempotency-Key` header the submission is applied at most once, and retrying it
+/// with the same key returns the original outcome.
+///
+/// While the state's [`CircuitBreaker`](super::circuit_breaker::CircuitBreaker) is open,
+/// submissions are rejected with `503 Service Unavailable` and a `Retry-After` header.
+pub async fn post_transactions(
+    State(state): State<AppState>,
+    identity: Option<Extension<Identity>>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> Result<Json<SubmissionResponse>, ApiError> {
+    let Some(circuit_breaker) = &state.circuit_breaker else {
+        return submit_transactions(&state, identity, &headers, body).await;
+    };
+    let permit = circuit_breaker.acquire()?;
+    let response = submit_transactions(&state, identity, &headers, body).await;
+    match &response {
+        Ok(_) => permit.succeeded(),
+        Err(e) if e.status == StatusCode::SERVICE_UNAVAILABLE => permit.failed(),
+        Err(_) => {}
+    }
+    response
+}
+
+async fn submit_transactions(
+    state: &AppState,
+    identity: Option<Extension<Identity>>,
+    headers: &HeaderMap,
+    body: Value,
+) -> Result<Json<SubmissionResponse>, ApiError> {
+    let check_rate_limits = |transactions: &[Transaction]| match &state.rate_limits {
+        Some(rate_limits) => {
+            let client_ids: Vec<_> = transactions.iter().map(|t| t.client_id).collect();
+            rate_limits.check(identity.as_ref().map(|i| i.name.as_str()), &client_ids)
+        }
+        None => Ok(()),
+    };
+
+    let idempotency_key = headers
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .map(|key| key.to_str())
+        .transpose()
+        .map_err(|_| {
+            ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "The Idempotency-Key header must be visible ascii",
+            )
+        })?;
+
+    match body {
+        Value::Array(values) => {
+            let transactions = values
+                .into_iter()
+                .map(parse_transaction)
+                .collect::<Result<Vec<_>, _>>()?;
+            check_rate_limits(&transactions)?;
+            // Batches wait behind single submissions
+            let outcome = WritePriority::Bulk
+                .scope(async {
+                    match idempotency_key {
+                        Some(key) => state
+                            .svc
+                            .process_transactions_once(key, &transactions)
+                            .await
+                            .map(|outcomes| outcomes.iter().collect()),
+                        None => {
+                            state
+                                .svc
+                                .process_stream(futures::stream::iter(
+                                    transactions.into_iter().map(Ok),
+                                ))
+                                .await
+                        }
+                    }
+                })
+                .await?;
+            Ok(Json(SubmissionResponse::Batch(outcome)))
+        }
+        value => {
+            let transaction = parse_transaction(value)?;
+            check_rate_limits(std::slice::from_ref(&transaction))?;
+            let outcome = match idempotency_key {
+                Some(key) => {
+                    state
+                        .svc
+                        .process_transaction_once(key, &transaction)
+                        .await?
+                }
+                None => state.svc.process_transaction(&transaction).await?,
+            };
+            Ok(Json(SubmissionResponse::Single {
+                tx: transaction.id,
+                outcome: outcome.to_str(),
+                reason: match outcome {
+                    TransactionOutcome::Rejected { reason } => Some(reason),
+                    _ => None,
+                },
+            }))
+        }
+    }
+}
+
+/// Query parameters for `GET /clients`.
+#[derive(Debug, Default, Deserialize)]
+pub struct ClientQuery {
+    #[serde(default)]
+    pub locked_only: bool,
+    pub min_total: Option<Decimal>,
+    pub max_total: Option<Decimal>,
+    pub tag: Option<String>,
+    pub min_risk_score: Option<i64>,
+    /// Only return clients whose balances the transactions of this batch changed.
+    pub changed_in_batch: Option<i64>,
+    /// Only return clients with an id greater than this.
+    pub after: Option<ClientId>,
+    /// Page size, defaults to 100 and is capped at 1000.
+    pub limit: Option<u32>,
+}
+
+/// `GET /clients`
+pub async fn get_clients(
+    State(state): State<AppState>,
+    Query(query): Query<ClientQuery>,
+) -> Result<Json<Vec<Client>>, ApiError> {
+    let filter = ClientFilter {
+        locked_only: query.locked_only,
+        min_total: query.min_total,
+        max_total: query.max_total,
+        tag: query.tag,
+        min_risk_score: query.min_risk_score,
+        changed_in_batch: query.changed_in_batch,
+    };
+    let pagination = Pagination {
+        after: query.after,
+        limit: Some(page_size(query.limit)),
+    };
+    let clients = state
+        .svc
+        .get_clients(&filter, pagination)
+        .await
+        .try_collect()
+        .await?;
+    Ok(Json(clients))
+}
+
+/// `GET /clients/{id}`
+pub async fn get_client(
+    State(state): State<AppState>,
+    Path(client_id): Path<ClientId>,
+) -> Result<Json<Client>, ApiError> {
+    state
+        .svc
+        .get_client(client_id)
+        .await?
+        .map(Json)
+        .ok_or_else(|| ApiError::not_found(format!("client {} does not exist", client_id)))
+}
+
+/// Query parameters for `GET /clients/{id}/transactions`.
+#[derive(Debug, Default, Deserialize)]
+pub struct TransactionQuery {
+    #[serde(rename = "type")]
+    pub transaction_type: Option<TransactionType>,
+    pub disputed: Option<bool>,
+    /// Only return transactions with an id greater than this.
+    pub after: Option<TransactionId>,
+    /// Page size, defaults to 100 and is capped at 1000.
+    pub limit: Option<u32>,
+}
+
+/// `GET /clients/{id}/transactions`
+pub async fn get_client_transactions(
+    State(state): State<AppState>,
+    Path(client_id): Path<ClientId>,
+    Query(query): Query<TransactionQuery>,
+) -> Result<Json<Vec<Transaction>>, ApiError> {
+    let filter = TransactionFilter {
+        transaction_type: query.transaction_type,
+        disputed: query.disputed,
+        ..Default::default()
+    };
+    let pagination = Pagination {
+        after: query.after,
+        limit: Some(page_size(query.limit)),
+    };
+    let transactions = state
+        .svc
+        .get_client_transactions(client_id, &filter, pagination)
+        .try_collect()
+        .await?;
+    Ok(Json(transactions))
+}
+
+/// Query parameters for `GET /transactions`, e.g.
+/// `?client=42&type=withdrawal&min_amount=1000&from=1700000000000`. With `reference`, every
+/// transaction with that external reference is returned and the other parameters are ignored.
+#[derive(Debug, Default, Deserialize)]
+pub struct TransactionSearchQuery {
+    pub reference: Option<String>,
+    pub client: Option<ClientId>,
+    #[serde(rename = "type")]
+    pub transaction_type: Option<TransactionType>,
+    pub disputed: Option<bool>,
+    pub min_amount: Option<Decimal>,
+    pub max_amount: Option<Decimal>,
+    /// Only return transactions applied at or after this moment, in milliseconds since the
+    /// unix epoch.
+    pub from: Option<i64>,
+    /// Only return transactions applied before this moment, in milliseconds since the unix
+    /// epoch.
+    pub until: Option<i64>,
+    /// Only return transactions of clients with this tag.
+    pub tag: Option<String>,
+    /// Only return transactions with an id greater than this.
+    pub after: Option<TransactionId>,
+    /// Page size, defaults to 100 and is capped at 1000.
+    pub limit: Option<u32>,
+}
+
+/// `GET /transactions`
+pub async fn get_transactions(
+    State(state): State<AppState>,
+    Query(query): Query<TransactionSearchQuery>,
+) -> Result<Json<Vec<Transaction>>, ApiError> {
+    if let Some(reference) = &query.reference {
+        return Ok(Json(
+            state.svc.get_transactions_by_reference(reference).await?,
+        ));
+    }
+    let filter = TransactionFilter {
+        client_id: query.client,
+        transaction_type: query.transaction_type,
+        disputed: query.disputed,
+        min_amount: query.min_amount,
+        max_amount: query.max_amount,
+        from: query.from,
+        until: query.until,
+        tag: query.tag,
+    };
+    let pagination = Pagination {
+        after: query.after,
+        limit: Some(page_size(query.limit)),
+    };
+    let transactions = state
+        .svc
+        .search_transactions(&filter, pagination)
+        .try_collect()
+        .await?;
+    Ok(Json(transactions))
+}
+
+/// Query parameters for `GET /aggregates`, e.g. `?group_by=day&type=withdrawal`: `group_by`
+/// is `type`, `client` or `day`, and the filters are those of [`TransactionSearchQuery`].
+#[derive(Debug, Deserialize)]
+pub struct AggregateQuery {
+    pub group_by: GroupBy,
+    pub client: Option<ClientId>,
+    #[serde(rename = "type")]
+    pub transaction_type: Option<TransactionType>,
+    pub disputed: Option<bool>,
+    pub min_amount: Option<Decimal>,
+    pub max_amount: Option<Decimal>,
+    pub from: Option<i64>,
+    pub until: Option<i64>,
+    pub tag: Option<String>,
+}
+
+/// `GET /aggregates`
+pub async fn get_aggregates(
+    State(state): State<AppState>,
+    Query(query): Query<AggregateQuery>,
+) -> Result<Json<Vec<TransactionAggregate>>, ApiError> {
+    let filter = TransactionFilter {
+        client_id: query.client,
+        transaction_type: query.transaction_type,
+        disputed: query.disputed,
+        min_amount: query.min_amount,
+        max_amount: query.max_amount,
+        from: query.from,
+        until: query.until,
+        tag: query.tag,
+    };
+    Ok(Json(
+        state.svc.get_aggregates(query.group_by, &filter).await?,
+    ))
+}
+
+/// Query parameters for `GET /disputes`.
+#[derive(Debug, Default, Deserialize)]
+pub struct DisputeQuery {
+    pub client: Option<ClientId>,
+    pub status: Option<DisputeStatus>,
+    pub assignee: Option<String>,
+}
+
+/// `GET /disputes`
+pub async fn get_disputes(
+    State(state): State<AppState>,
+    Query(query): Query<DisputeQuery>,
+) -> Result<Json<Vec<Dispute>>, ApiError> {
+    let disputes = state
+        .svc
+        .get_open_disputes(query.client)
+        .try_filter(|dispute| {
+            let matches = query.status.is_none_or(|status| dispute.status == status)
+                && (query.assignee.is_none() || dispute.assignee == query.assignee);
+            std::future::ready(matches)
+        })
+        .try_collect()
+        .await?;
+    Ok(Json(disputes))
+}
+
+/// The body of `PATCH /disputes/{tx}`. An empty `assignee` unassigns the dispute.
+#[derive(Debug, Deserialize)]
+pub struct DisputeUpdate {
+    pub status: Option<DisputeStatus>,
+    pub assignee: Option<String>,
+}
+
+/// `PATCH /disputes/{tx}`, assigning the dispute before moving it to `status`, see
+/// [`TransactionService::update_dispute_status`](crate::TransactionService::update_dispute_status).
+pub async fn patch_dispute(
+    State(state): State<AppState>,
+    Path(transaction_id): Path<TransactionId>,
+    Json(update): Json<DisputeUpdate>,
+) -> Result<Json<Dispute>, ApiError> {
+    let mut dispute = None;
+    if let Some(assignee) = &update.assignee {
+        let assignee = Some(assignee.as_str()).filter(|a| !a.is_empty());
+        dispute = Some(state.svc.assign_dispute(transaction_id, assignee).await?);
+    }
+    if let Some(status) = update.status {
+        dispute = Some(
+            (state.svc)
+                .update_dispute_status(transaction_id, status)
+                .await?,
+        );
+    }
+    dispute
+        .map(Json)
+        .ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST, "Nothing to update"))
+}
+
+/// The body of `POST /disputes/{tx}/evidence`.
+#[derive(Debug, Deserialize)]
+pub struct EvidenceRequest {
+    /// A URL or blob hash, see [`DisputeEvidence::is_valid_reference`](crate::DisputeEvidence::is_valid_reference).
+    pub reference: String,
+}
+
+/// `POST /disputes/{tx}/evidence`, see
+/// [`TransactionService::add_dispute_evidence`](crate::TransactionService::add_dispute_evidence).
+pub async fn post_dispute_evidence(
+    State(state): State<AppState>,
+    Path(transaction_id): Path<TransactionId>,
+    Json(request): Json<EvidenceRequest>,
+) -> Result<Json<Dispute>, ApiError> {
+    Ok(Json(
+        (state.svc)
+            .add_dispute_evidence(transaction_id, &request.reference)
+            .await?,
+    ))
+}
+
+/// `GET /metrics`, the service's counters in the Prometheus text format.
+pub async fn get_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let alerts = state.svc.alert_counts();
+    let mut body = String::from(
+        "# HELP transaction_app_alerts_total Alerts raised by clients crossing a threshold.\n\
+        # TYPE transaction_app_alerts_total counter\n",
+    );
+    for (kind, count) in [
+        (AlertKind::LowAvailable, alerts.low_available),
+        (AlertKind::HighHeld, alerts.high_held),
+        (AlertKind::BalanceOverflow, alerts.balance_overflow),
+    ] {
+        let _ = writeln!(
+            body,
+            "transaction_app_alerts_total{{kind=\"{}\"}} {}",
+            kind.to_str(),
+            count
+        );
+    }
+    body.push_str(
+        "# HELP transaction_app_transactions_total Committed transactions by type and outcome.\n\
+        # TYPE transaction_app_transactions_total counter\n",
+    );
+    for (transaction_type, counts) in state.svc.transaction_counts() {
+        for (outcome, count) in [
+            ("applied", counts.applied),
+            ("rejected", counts.rejected),
+            ("ignored", counts.ignored),
+        ] {
+            let _ = writeln!(
+                body,
+                "transaction_app_transactions_total{{type=\"{}\",outcome=\"{}\"}} {}",
+                transaction_type, outcome, count
+            );
+        }
+    }
+    let insufficient_funds: usize = state
+        .svc
+        .transaction_counts()
+        .values()
+        .map(|counts| counts.insufficient_funds)
+        .sum();
+    let _ = write!(
+        body,
+        "# HELP transaction_app_insufficient_funds_total Withdrawals rejected for insufficient funds.\n\
+        # TYPE transaction_app_insufficient_funds_total counter\n\
+        transaction_app_insufficient_funds_total {}\n",
+        insufficient_funds
+    );
+    if let Some(circuit_breaker) = &state.circuit_breaker {
+        let _ = write!(
+            body,
+            "# HELP transaction_app_circuit_open Whether submissions are rejected because the database is unavailable.\n\
+            # TYPE transaction_app_circuit_open gauge\n\
+            transaction_app_circuit_open {}\n\
+            # HELP transaction_app_circuit_trips_total Times submissions started being rejected because the database was unavailable.\n\
+            # TYPE transaction_app_circuit_trips_total counter\n\
+            transaction_app_circuit_trips_total {}\n",
+            u8::from(circuit_breaker.state() != CircuitState::Closed),
+            circuit_breaker.trips()
+        );
+    }
+    let _ = write!(
+        body,
+        "# HELP transaction_app_unknown_targets_total Disputes, resolves and chargebacks warned about for an unknown target.\n\
+        # TYPE transaction_app_unknown_targets_total counter\n\
+        transaction_app_unknown_targets_total {}\n",
+        state.svc.unknown_target_count()
+    );
+    if let Some(scheduler) = &state.scheduler {
+        let jobs = scheduler.metrics();
+        body.push_str(
+            "# HELP transaction_app_job_runs_total Runs of the scheduled jobs.\n\
+            # TYPE transaction_app_job_runs_total counter\n",
+        );
+        for job in &jobs {
+            let _ = writeln!(
+                body,
+                "transaction_app_job_runs_total{{job=\"{}\"}} {}",
+                job.job, job.runs
+            );
+        }
+        body.push_str(
+            "# HELP transaction_app_job_failures_total Failed runs of the scheduled jobs.\n\
+            # TYPE transaction_app_job_failures_total counter\n",
+        );
+        for job in &jobs {
+            let _ = writeln!(
+                body,
+                "transaction_app_job_failures_total{{job=\"{}\"}} {}",
+                job.job, job.failures
+            );
+        }
+        body.push_str(
+            "# HELP transaction_app_job_last_duration_seconds How long the last run of each scheduled job took.\n\
+            # TYPE transaction_app_job_last_duration_seconds gauge\n",
+        );
+        for job in &jobs {
+            let _ = writeln!(
+                body,
+                "transaction_app_job_last_duration_seconds{{job=\"{}\"}} {}",
+                job.job,
+                job.last_duration.as_secs_f64()
+            );
+        }
+    }
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
+
+/// `GET /transactions/{id}/annotations`, the annotations validators attached to the
+/// transaction and any disputes, resolves and chargebacks of it.
+pub async fn get_annotations(
+    State(state): State<AppState>,
+    Path(transaction_id): Path<TransactionId>,
+) -> Result<Json<Vec<Annotation>>, ApiError> {
+    Ok(Json(state.svc.get_annotations(transaction_id).await?))
+}
+
+/// The body of `POST /clients/{id}/adjustments`.
+#[derive(Debug, Deserialize)]
+pub struct AdjustmentRequest {
+    /// Added to the client's available funds, negative to deduct.
+    pub amount: Decimal,
+    pub reason: String,
+}
+
+/// The name of the caller, recorded as the operator of administrative changes.
+fn operator(identity: Option<Extension<Identity>>) -> String {
+    identity.map_or_else(|| "anonymous".to_string(), |Extension(i)| i.name)
+}
+
+/// Responds with the result of a change, or with `202 Accepted` and the pending [`Approval`]
+/// when the change needs a second operator's approval.
+fn held_for_approval<T: Serialize>(
+    result: Result<T, TransactionError>,
+) -> Result<Response, ApiError> {
+    match result {
+        Ok(value) => Ok(Json(value).into_response()),
+        Err(TransactionError::ApprovalRequired(approval, _)) => {
+            Ok((StatusCode::ACCEPTED, Json(approval)).into_response())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// `POST /clients/{id}/adjustments`, recording the caller as the operator.
+pub async fn post_adjustment(
+    State(state): State<AppState>,
+    Path(client_id): Path<ClientId>,
+    identity: Option<Extension<Identity>>,
+    Json(request): Json<AdjustmentRequest>,
+) -> Result<Response, ApiError> {
+    held_for_approval(
+        (state.svc)
+            .adjust_balance(
+                client_id,
+                request.amount,
+                &request.reason,
+                &operator(identity),
+            )
+            .await,
+    )
+}
+
+/// `POST /clients/{id}/unlock`, recording the caller as the operator.
+pub async fn post_unlock(
+    State(state): State<AppState>,
+    Path(client_id): Path<ClientId>,
+    identity: Option<Extension<Identity>>,
+) -> Result<Response, ApiError> {
+    held_for_approval(
+        state
+            .svc
+            .unlock_client(client_id, &operator(identity))
+            .await,
+    )
+}
+
+/// The body of `POST /transactions/{tx}/void`.
+#[derive(Debug, Deserialize)]
+pub struct VoidRequest {
+    pub reason: String,
+}
+
+/// `POST /transactions/{tx}/void`, voiding a clearing deposit with the caller as the operator,
+/// see [`TransactionService::void_deposit`](crate::TransactionService::void_deposit).
+pub async fn post_void(
+    State(state): State<AppState>,
+    Path(transaction_id): Path<TransactionId>,
+    identity: Option<Extension<Identity>>,
+    Json(request): Json<VoidRequest>,
+) -> Result<Json<Void>, ApiError> {
+    let void = (state.svc)
+        .void_deposit(transaction_id, &request.reason, &operator(identity))
+        .await?;
+    Ok(Json(void))
+}
+
+/// `GET /approvals`, the changes waiting for a second operator's approval.
+pub async fn get_approvals(State(state): State<AppState>) -> Result<Json<Vec<Approval>>, ApiError> {
+    Ok(Json(state.svc.get_pending_approvals().await?))
+}
+
+/// `POST /approvals/{id}/approve`, approving as the caller, see
+/// [`TransactionService::approve_request`](crate::TransactionService::approve_request).
+pub async fn post_approve(
+    State(state): State<AppState>,
+    Path(approval_id): Path<i64>,
+    identity: Option<Extension<Identity>>,
+) -> Result<Json<Approval>, ApiError> {
+    let approval = (state.svc)
+        .approve_request(approval_id, &operator(identity))
+        .await?;
+    Ok(Json(approval))
+}
+
+/// `POST /approvals/{id}/reject`, rejecting as the caller.
+pub async fn post_reject(
+    State(state): State<AppState>,
+    Path(approval_id): Path<i64>,
+    identity: Option<Extension<Identity>>,
+) -> Result<Json<Approval>, ApiError> {
+    let approval = (state.svc)
+        .reject_request(approval_id, &operator(identity))
+        .await?;
+    Ok(Json(approval))
+}
+
+/// `GET /clients/{id}/tags`
+pub async fn get_client_tags(
+    State(state): State<AppState>,
+    Path(client_id): Path<ClientId>,
+) -> Result<Json<Vec<String>>, ApiError> {
+    Ok(Json(state.svc.get_client_tags(client_id).await?))
+}
+
+/// `GET /clients/{id}/risk`, `null` unless the client's chargebacks escalated.
+pub async fn get_client_risk(
+    State(state): State<AppState>,
+    Path(client_id): Path<ClientId>,
+) -> Result<Json<Option<ClientRisk>>, ApiError> {
+    Ok(Json(state.svc.get_client_risk(client_id).await?))
+}
+
+/// `PUT /clients/{id}/tags/{tag}`, responding with the client's tags.
+pub async fn put_client_tag(
+    State(state): State<AppState>,
+    Path((client_id, tag)): Path<(ClientId, String)>,
+) -> Result<Json<Vec<String>>, ApiError> {
+    state
+        .svc
+        .tag_clients(&[ClientTag {
+            client: client_id,
+            tag,
+        }])
+        .await?;
+    Ok(Json(state.svc.get_client_tags(client_id).await?))
+}
+
+/// `DELETE /clients/{id}/tags/{tag}`, responding with the client's remaining tags.
+pub async fn delete_client_tag(
+    State(state): State<AppState>,
+    Path((client_id, tag)): Path<(ClientId, String)>,
+) -> Result<Json<Vec<String>>, ApiError> {
+    state.svc.untag_client(client_id, &tag).await?;
+    Ok(Json(state.svc.get_client_tags(client_id).await?))
+}
+
+/// Query parameters for `GET /admin/events`.
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditRecordQuery {
+    /// Only return records with an id less than this.
+    pub before: Option<i64>,
+    /// Page size, defaults to 100 and is capped at 1000.
+    pub limit: Option<u32>,
+}
+
+/// `GET /admin/events`, the latest records of the audit log, newest first.
+pub async fn get_audit_records(
+    State(state): State<AppState>,
+    Query(query): Query<AuditRecordQuery>,
+) -> Result<Json<Vec<AuditRecord>>, ApiError> {
+    let records = (state.svc)
+        .audit_records(query.before, page_size(query.limit))
+        .await?;
+    Ok(Json(records))
+}
+
+/// Query parameters for `GET /admin/clients/{id}/rows`.
+#[derive(Debug, Default, Deserialize)]
+pub struct ClientDumpQuery {
+    /// The number of rows of each table, defaults to 100 and is capped at 1000.
+    pub limit: Option<u32>,
+}
+
+/// `GET /admin/clients/{id}/rows`, the latest rows of every table about a client, as they are
+/// stored.
+pub async fn get_client_rows(
+    State(state): State<AppState>,
+    Path(client_id): Path<ClientId>,
+    Query(query): Query<ClientDumpQuery>,
+) -> Result<Json<ClientDump>, ApiError> {
+    let dump = (state.svc)
+        .dump_client(client_id, page_size(query.limit))
+        .await?;
+    Ok(Json(dump))
+}
+
+/// `GET /admin/checkpoints`, the end of the audit log, the closed business dates, the balance
+/// snapshots and the committed source offsets.
+pub async fn get_checkpoints(State(state): State<AppState>) -> Result<Json<Checkpoints>, ApiError> {
+    Ok(Json(state.svc.checkpoints().await?))
+}
+
+/// The response to `POST /admin/pause` and `POST /admin/resume`.
+#[derive(Debug, Serialize)]
+pub struct PauseResponse {
+    pub paused: bool,
+}
+
+/// `POST /admin/pause`, pausing writes once those in progress commit. Responds once they
+/// have, so the database file can be copied.
+pub async fn post_pause(State(state): State<AppState>) -> Json<PauseResponse> {
+    state.svc.pause().await;
+    Json(PauseResponse { paused: true })
+}
+
+/// `POST /admin/resume`, resuming the writes paused by `POST /admin/pause`.
+pub async fn post_resume(State(state): State<AppState>) -> Json<PauseResponse> {
+    state.svc.resume().await;
+    Json(PauseResponse { paused: false })
+}
+
+/// Middleware rejecting requests that write with `503 Service Unavailable` while writes are
+/// paused, rather than holding them until they resume.
+pub async fn reject_while_paused(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    if state.svc.is_paused() {
+        return Err(ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "writes are paused",
+        ));
+    }
+    Ok(next.run(request).await)
+}
@@ -0,0 +1,146 @@
+use super::AppState;
+use crate::{Adjustment, ClientId, EventObserver, Transaction};
+use axum::extract::{Query, State};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use futures::{Stream, StreamExt};
+use serde::Deserialize;
+use serde_json::json;
+use std::convert::Infallible;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// A change to a client that is pushed to `GET /events` subscribers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LiveEvent {
+    /// The client's balances changed.
+    ClientUpdated(ClientId),
+    /// The client was locked.
+    ClientLocked(ClientId),
+}
+
+impl LiveEvent {
+    fn client_id(&self) -> ClientId {
+        match self {
+            Self::ClientUpdated(id) | Self::ClientLocked(id) => *id,
+        }
+    }
+}
+
+/// Broadcasts client changes to `GET /events` subscribers. Register it as an observer with
+/// [`TransactionServiceBuilder::observer`](crate::TransactionServiceBuilder::observer) and
+/// pass it to [`AppState::with_live_updates`].
+#[derive(Clone)]
+pub struct LiveUpdates {
+    sender: broadcast::Sender<LiveEvent>,
+}
+
+impl LiveUpdates {
+    /// `capacity` is how many events a slow subscriber can fall behind by before it misses
+    /// events and receives a `lagged` event instead.
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self { sender }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<LiveEvent> {
+        self.sender.subscribe()
+    }
+
+    fn send(&self, event: LiveEvent) {
+        // Sending only fails when there are no subscribers
+        let _ = self.sender.send(event);
+    }
+}
+
+impl EventObserver for LiveUpdates {
+    fn on_deposit(&self, deposit: &Transaction) {
+        self.send(LiveEvent::ClientUpdated(deposit.client_id));
+    }
+
+    fn on_withdrawal(&self, withdrawal: &Transaction) {
+        self.send(LiveEvent::ClientUpdated(withdrawal.client_id));
+    }
+
+    fn on_dispute_opened(&self, disputed: &Transaction) {
+        self.send(LiveEvent::ClientUpdated(disputed.client_id));
+    }
+
+    fn on_dispute_resolved(&self, disputed: &Transaction) {
+        self.send(LiveEvent::ClientUpdated(disputed.client_id));
+    }
+
+    fn on_chargeback(&self, disputed: &Transaction) {
+        self.send(LiveEvent::ClientUpdated(disputed.client_id));
+    }
+
+    fn on_client_locked(&self, client_id: ClientId) {
+        self.send(LiveEvent::ClientLocked(client_id));
+    }
+
+    fn on_custom(&self, transaction: &Transaction) {
+        self.send(LiveEvent::ClientUpdated(transaction.client_id));
+    }
+
+    fn on_client_unlocked(&self, client_id: ClientId) {
+        self.send(LiveEvent::ClientUpdated(client_id));
+    }
+
+    fn on_adjustment(&self, adjustment: &Adjustment) {
+        self.send(LiveEvent::ClientUpdated(adjustment.client_id));
+    }
+}
+
+/// Query parameters for `GET /events`.
+#[derive(Debug, Default, Deserialize)]
+pub struct EventQuery {
+    /// Only send events for this client.
+    pub client: Option<ClientId>,
+}
+
+/// `GET /events`, a server sent event stream of `client_updated` events containing the
+/// client's new state, `client_locked` events, and `lagged` events when the subscriber fell
+/// too far behind and should refetch the clients it is tracking.
+pub async fn get_events(
+    State(state): State<AppState>,
+    Query(query): Query<EventQuery>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let receiver = state.live_updates.as_ref().map(LiveUpdates::subscribe);
+
+    let events = futures::stream::unfold(receiver, |receiver| async move {
+        let mut receiver = receiver?;
+        let event = match receiver.recv().await {
+            Ok(event) => Ok(event),
+            Err(RecvError::Lagged(missed)) => Err(missed),
+            Err(RecvError::Closed) => return None,
+        };
+        Some((event, Some(receiver)))
+    })
+    .filter(move |event| {
+        let matches = match (event, query.client) {
+            (Ok(event), Some(client_id)) => event.client_id() == client_id,
+            _ => true,
+        };
+        async move { matches }
+    })
+    .then(move |event| {
+        let svc = state.svc.clone();
+        async move {
+            let event = match event {
+                Ok(LiveEvent::ClientUpdated(client_id)) => match svc.get_client(client_id).await {
+                    Ok(Some(client)) => Event::default().event("client_updated").json_data(client),
+                    _ => Event::default()
+                        .event("client_updated")
+                        .json_data(json!({ "client": client_id })),
+                },
+                Ok(LiveEvent::ClientLocked(client_id)) => Event::default()
+                    .event("client_locked")
+                    .json_data(json!({ "client": client_id })),
+                Err(missed) => Event::default()
+                    .event("lagged")
+                    .json_data(json!({ "missed": missed })),
+            };
+            Ok(event.unwrap_or_default())
+        }
+    });
+
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
@@ -0,0 +1,1000 @@
+//! Exposes a [`TransactionService`] over a json HTTP api.
+//!
+//! | Route | Role | |
+//! |---|---|---|
+//! | `POST /transactions` | submitter | Process a single transaction object, or an array of them as a batch |
+//! | `GET /clients` | viewer | List clients, see [`handlers::ClientQuery`] |
+//! | `GET /clients/{id}` | viewer | Get a single client |
+//! | `GET /clients/{id}/tags` | viewer | List a client's tags |
+//! | `GET /clients/{id}/risk` | viewer | Get the risk level a client's chargebacks escalated to |
+//! | `GET /clients/{id}/transactions` | viewer | List a client's transactions, see [`handlers::TransactionQuery`] |
+//! | `GET /transactions` | viewer | Search the deposits and withdrawals, or list those with an external reference, see [`handlers::TransactionSearchQuery`] |
+//! | `GET /transactions/{id}/annotations` | viewer | List the annotations validators attached to a transaction |
+//! | `GET /aggregates` | viewer | Count and sum the deposits and withdrawals by type, client or day, see [`handlers::AggregateQuery`] |
+//! | `GET /disputes` | viewer | List open disputes, optionally `?client=<id>` |
+//! | `GET /metrics` | viewer | The service's counters in the Prometheus text format, see [`handlers::get_metrics`] |
+//! | `GET /events` | viewer | Server sent events of client updates, see [`live::get_events`] |
+//! | `POST /graphql` | viewer | GraphQL queries when built with the `graphql` feature, see [`graphql`] |
+//! | `POST /clients/{id}/adjustments` | admin | Adjust a client's available funds, see [`handlers::AdjustmentRequest`] |
+//! | `POST /clients/{id}/unlock` | admin | Unlock a locked client |
+//! | `POST /transactions/{tx}/void` | admin | Void a deposit before it clears, see [`handlers::VoidRequest`] |
+//! | `GET /approvals` | admin | List the unlocks and adjustments waiting for a second operator's approval |
+//! | `POST /approvals/{id}/approve` | admin | Approve and apply a change requested by another operator |
+//! | `POST /approvals/{id}/reject` | admin | Reject a requested change |
+//! | `PATCH /disputes/{tx}` | admin | Assign a dispute or move it through the workflow, see [`handlers::DisputeUpdate`] |
+//! | `POST /disputes/{tx}/evidence` | admin | Attach a reference to evidence, see [`handlers::EvidenceRequest`] |
+//! | `PUT /clients/{id}/tags/{tag}` | admin | Tag a client, e.g. as `vip` |
+//! | `DELETE /clients/{id}/tags/{tag}` | admin | Remove a tag from a client |
+//! | `GET /admin/events` | admin | List the latest audit log records, see [`handlers::AuditRecordQuery`] |
+//! | `GET /admin/clients/{id}/rows` | admin | Dump the rows stored about a client, see [`handlers::ClientDumpQuery`] |
+//! | `GET /admin/checkpoints` | admin | Get the audit log head, closed dates, balance snapshots and source offsets |
+//! | `POST /admin/pause` | admin | Pause writes, see [`handlers::post_pause`] |
+//! | `POST /admin/resume` | admin | Resume paused writes |
+//!
+//! Roles are only checked when the state has an [`auth::Authenticator`]. While writes are
+//! paused, submissions and the other admin routes are rejected with `503 Service Unavailable`,
+//! as are submissions while the database is unavailable, see
+//! [`circuit_breaker::CircuitBreaker`].
+//! Adjustments and unlocks that need approval are answered with `202 Accepted` and the pending
+//! [`Approval`](crate::Approval).
+
+pub mod auth;
+pub mod circuit_breaker;
+mod error;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod handlers;
+pub mod live;
+pub mod rate_limit;
+#[cfg(feature = "tls")]
+pub mod tls;
+
+use crate::{Scheduler, TransactionService};
+use auth::{Authenticator, RequireRole, Role};
+use axum::extract::{MatchedPath, Request};
+use axum::middleware;
+use axum::routing::{get, patch, post, put};
+use axum::Router;
+use circuit_breaker::CircuitBreaker;
+use rate_limit::RateLimits;
+use std::sync::Arc;
+use tokio::net::{TcpListener, ToSocketAddrs};
+use tower_http::trace::{DefaultMakeSpan, MakeSpan, TraceLayer};
+use tracing::Level;
+
+pub use error::ApiError;
+pub use live::LiveUpdates;
+#[cfg(feature = "tls")]
+pub use tls::TlsConfig;
+
+/// State shared by every request handler.
+#[derive(Clone)]
+pub struct AppState {
+    pub svc: Arc<TransactionService>,
+    pub live_updates: Option<LiveUpdates>,
+    pub auth: Option<Arc<Authenticator>>,
+    pub rate_limits: Option<Arc<RateLimits>>,
+    pub scheduler: Option<Arc<Scheduler>>,
+    pub circuit_breaker: Option<Arc<CircuitBreaker>>,
+}
+
+impl AppState {
+    pub fn new(svc: Arc<TransactionService>) -> Self {
+        Self {
+            svc,
+            live_updates: None,
+            auth: None,
+            rate_limits: None,
+            scheduler: None,
+            circuit_breaker: None,
+        }
+    }
+
+    /// Publishes the changes observed by `live_updates` on `GET /events`. Without this the
+    /// event stream stays empty.
+    pub fn with_live_updates(mut self, live_updates: LiveUpdates) -> Self {
+        self.live_updates = Some(live_updates);
+        self
+    }
+
+    /// Requires every request to carry a bearer token accepted by `auth` with the route's role.
+    pub fn with_auth(mut self, auth: Arc<Authenticator>) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// Rejects submissions exceeding `rate_limits` with `429 Too Many Requests`.
+    pub fn with_rate_limits(mut self, rate_limits: Arc<RateLimits>) -> Self {
+        self.rate_limits = Some(rate_limits);
+        self
+    }
+
+    /// Rejects submissions with `503 Service Unavailable` while `circuit_breaker` is open.
+    pub fn with_circuit_breaker(mut self, circuit_breaker: Arc<CircuitBreaker>) -> Self {
+        self.circuit_breaker = Some(circuit_breaker);
+        self
+    }
+
+    /// Reports the metrics of the `scheduler`'s jobs on `GET /metrics`.
+    pub fn with_scheduler(mut self, scheduler: Arc<Scheduler>) -> Self {
+        self.scheduler = Some(scheduler);
+        self
+    }
+}
+
+/// Builds the api routes.
+pub fn router(state: AppState) -> Router {
+    let require =
+        |role| middleware::from_fn_with_state(RequireRole::new(&state, role), auth::authorize);
+
+    let read = Router::new()
+        .route("/clients", get(handlers::get_clients))
+        .route("/clients/{id}", get(handlers::get_client))
+        .route("/clients/{id}/tags", get(handlers::get_client_tags))
+        .route("/clients/{id}/risk", get(handlers::get_client_risk))
+        .route(
+            "/clients/{id}/transactions",
+            get(handlers::get_client_transactions),
+        )
+        .route("/transactions", get(handlers::get_transactions))
+        .route(
+            "/transactions/{id}/annotations",
+            get(handlers::get_annotations),
+        )
+        .route("/aggregates", get(handlers::get_aggregates))
+        .route("/disputes", get(handlers::get_disputes))
+        .route("/metrics", get(handlers::get_metrics))
+        .route("/events", get(live::get_events));
+
+    #[cfg(feature = "graphql")]
+    let read = read.route(
+        "/graphql",
+        get(graphql::get_graphiql)
+            .post(graphql::post_graphql)
+            .with_state(graphql::schema(state.svc.clone())),
+    );
+
+    let reject_while_paused =
+        || middleware::from_fn_with_state(state.clone(), handlers::reject_while_paused);
+
+    let submit = Router::new()
+        .route("/transactions", post(handlers::post_transactions))
+        .route_layer(reject_while_paused());
+
+    let admin = Router::new()
+        .route("/clients/{id}/adjustments", post(handlers::post_adjustment))
+        .route("/clients/{id}/unlock", post(handlers::post_unlock))
+        .route("/transactions/{tx}/void", post(handlers::post_void))
+        .route("/approvals", get(handlers::get_approvals))
+        .route("/approvals/{id}/approve", post(handlers::post_approve))
+        .route("/approvals/{id}/reject", post(handlers::post_reject))
+        .route("/disputes/{tx}", patch(handlers::patch_dispute))
+        .route(
+            "/disputes/{tx}/evidence",
+            post(handlers::post_dispute_evidence),
+        )
+        .route(
+            "/clients/{id}/tags/{tag}",
+            put(handlers::put_client_tag).delete(handlers::delete_client_tag),
+        )
+        .route_layer(reject_while_paused())
+        .route("/admin/events", get(handlers::get_audit_records))
+        .route("/admin/clients/{id}/rows", get(handlers::get_client_rows))
+        .route("/admin/checkpoints", get(handlers::get_checkpoints))
+        .route("/admin/pause", post(handlers::post_pause))
+        .route("/admin/resume", post(handlers::post_resume));
+
+    Router::new()
+        .merge(read.route_layer(require(Role::Viewer)))
+        .merge(submit.route_layer(require(Role::Submitter)))
+        .merge(admin.route_layer(require(Role::Admin)))
+        .layer(TraceLayer::new_for_http().make_span_with(make_span(&state)))
+        .with_state(state)
+}
+
+/// Creates the span of each request. When the service pseudonymizes client ids the span has the
+/// matched route, e.g. `/clients/{id}`, instead of the uri.
+fn make_span<B>(state: &AppState) -> impl MakeSpan<B> + Clone {
+    let pseudonymize = state.svc.pseudonymizer().is_some();
+    move |request: &Request<B>| {
+        if !pseudonymize {
+            return DefaultMakeSpan::new().level(Level::INFO).make_span(request);
+        }
+        let route = request
+            .extensions()
+            .get::<MatchedPath>()
+            .map(MatchedPath::as_str);
+        tracing::info_span!("request", method = %request.method(), route)
+    }
+}
+
+/// Serves the api on `addr` until ctrl-c is pressed.
+pub async fn serve(state: AppState, addr: impl ToSocketAddrs) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    axum::serve(listener, router(state))
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await
+}
+
+/// Serves the api over TLS on `addr` until ctrl-c is pressed.
+#[cfg(feature = "tls")]
+pub async fn serve_tls(
+    state: AppState,
+    addr: impl ToSocketAddrs,
+    tls: &TlsConfig,
+) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?.into_std()?;
+    let handle = axum_server::Handle::new();
+    let shutdown = handle.clone();
+    tokio::spawn(async move {
+        let _ = tokio::signal::ctrl_c().await;
+        shutdown.graceful_shutdown(None);
+    });
+
+    axum_server::from_tcp_rustls(listener, tls.rustls_config().await?)
+        .handle(handle)
+        .serve(router(state).into_make_service())
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::auth::Authenticator;
+    use super::{router, AppState, LiveUpdates};
+    use crate::{TransactionId, TransactionService};
+    use axum::body::Body;
+    use axum::http::{Method, Request, StatusCode};
+    use axum::Router;
+    use futures::StreamExt;
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    async fn create_router() -> Router {
+        let svc = TransactionService::builder().build().await.unwrap();
+        router(AppState::new(Arc::new(svc)))
+    }
+
+    async fn request(
+        router: &Router,
+        method: Method,
+        uri: &str,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(body.map_or_else(Body::empty, |b| Body::from(b.to_string())))
+            .unwrap();
+        send(router, request).await
+    }
+
+    async fn send(router: &Router, request: Request<Body>) -> (StatusCode, Value) {
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (
+            status,
+            serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_submit_and_query() {
+        let router = create_router().await;
+
+        let (status, body) = request(
+            &router,
+            Method::POST,
+            "/transactions",
+            Some(json!({
+                "type": "deposit",
+                "client": 1,
+                "tx": 1,
+                "amount": "10.5",
+                "reference": "BANK-1"
+            })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({"tx": 1, "outcome": "deposit"}));
+
+        let (status, body) = request(
+            &router,
+            Method::POST,
+            "/transactions",
+            Some(json!([
+                {"type": "deposit", "client": 2, "tx": 2, "amount": 3},
+                {"type": "withdrawal", "client": 1, "tx": 3, "amount": "20"},
+                {"type": "dispute", "client": 2, "tx": 2},
+            ])),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            json!({"processed": 3, "applied": 2, "rejected": 1, "ignored": 0, "insufficient_funds": 1})
+        );
+
+        let (status, body) = request(&router, Method::GET, "/clients/1", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["available"], "10.5000");
+
+        let (status, _) = request(&router, Method::GET, "/clients/9", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (_, body) = request(&router, Method::GET, "/clients?limit=1&after=1", None).await;
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(body[0]["client"], 2);
+
+        let (_, body) = request(
+            &router,
+            Method::GET,
+            "/clients/1/transactions?type=withdrawal",
+            None,
+        )
+        .await;
+        assert_eq!(
+            body,
+            json!([{"tx": 3, "type": "withdrawal", "client": 1, "amount": "20.0000"}])
+        );
+
+        let (_, body) = request(&router, Method::GET, "/disputes?client=2", None).await;
+        assert_eq!(body[0]["transaction"]["tx"], 2);
+        assert_eq!(body[0]["status"], "open");
+        let update = json!({"status": "under_review", "assignee": "alice"});
+        let (status, body) = request(&router, Method::PATCH, "/disputes/2", Some(update)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            (&body["status"], &body["assignee"]),
+            (&json!("under_review"), &json!("alice"))
+        );
+        let (_, body) = request(&router, Method::GET, "/disputes?assignee=bob", None).await;
+        assert_eq!(body, json!([]));
+        let (_, body) = request(&router, Method::GET, "/disputes?status=under_review", None).await;
+        assert_eq!(body[0]["transaction"]["tx"], 2);
+        let (status, _) = request(&router, Method::PATCH, "/disputes/1", Some(json!({}))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let evidence = json!({"reference": "https://docs.example.com/receipt.pdf"});
+        let (status, body) = request(
+            &router,
+            Method::POST,
+            "/disputes/2/evidence",
+            Some(evidence),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body["evidence"][0]["reference"],
+            "https://docs.example.com/receipt.pdf"
+        );
+        let evidence = json!({"reference": "a receipt"});
+        let (status, _) = request(
+            &router,
+            Method::POST,
+            "/disputes/2/evidence",
+            Some(evidence),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, body) =
+            request(&router, Method::GET, "/transactions?reference=BANK-1", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            json!([{
+                "tx": 1,
+                "type": "deposit",
+                "client": 1,
+                "amount": "10.5000",
+                "reference": "BANK-1"
+            }])
+        );
+
+        let (_, body) = request(
+            &router,
+            Method::GET,
+            "/transactions?min_amount=5&from=0&limit=1",
+            None,
+        )
+        .await;
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(body[0]["tx"], 1);
+        let (_, body) = request(
+            &router,
+            Method::GET,
+            "/transactions?client=2&disputed=true&after=1",
+            None,
+        )
+        .await;
+        assert_eq!(body[0]["tx"], 2);
+        let (_, body) = request(&router, Method::GET, "/transactions?max_amount=1", None).await;
+        assert_eq!(body, json!([]));
+
+        let (status, body) = request(&router, Method::GET, "/aggregates?group_by=type", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            json!([
+                {"group": "deposit", "count": 2, "amount": "13.5000"},
+                {"group": "withdrawal", "count": 1, "amount": "20.0000"}
+            ])
+        );
+        let (_, body) = request(
+            &router,
+            Method::GET,
+            "/aggregates?group_by=client&type=deposit",
+            None,
+        )
+        .await;
+        assert_eq!(
+            body[1],
+            json!({"group": "2", "count": 1, "amount": "3.0000"})
+        );
+        let (status, _) =
+            request(&router, Method::GET, "/aggregates?group_by=currency", None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, body) = request(&router, Method::PUT, "/clients/2/tags/vip", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!(["vip"]));
+        let (_, body) = request(&router, Method::GET, "/clients?tag=vip", None).await;
+        assert_eq!(body[0]["client"], 2);
+        let (_, body) = request(&router, Method::GET, "/transactions?tag=vip", None).await;
+        assert_eq!(body[0]["tx"], 2);
+        let (_, body) = request(&router, Method::DELETE, "/clients/2/tags/vip", None).await;
+        assert_eq!(body, json!([]));
+        let (_, body) = request(&router, Method::GET, "/clients/2/tags", None).await;
+        assert_eq!(body, json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_pause() {
+        let router = create_router().await;
+        let deposit = |tx| json!({"type": "deposit", "client": 1, "tx": tx, "amount": 1});
+
+        let (status, body) = request(&router, Method::POST, "/admin/pause", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({"paused": true}));
+
+        let (status, _) = request(&router, Method::POST, "/transactions", Some(deposit(1))).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let (status, _) = request(&router, Method::POST, "/clients/1/unlock", None).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let (status, _) = request(&router, Method::GET, "/clients", None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = request(&router, Method::POST, "/admin/resume", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({"paused": false}));
+        let (status, _) = request(&router, Method::POST, "/transactions", Some(deposit(1))).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_inspection() {
+        let svc = TransactionService::builder()
+            .audit_log(true)
+            .build()
+            .await
+            .unwrap();
+        let router = router(AppState::new(Arc::new(svc)));
+        request(
+            &router,
+            Method::POST,
+            "/transactions",
+            Some(json!([
+                {"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"},
+                {"type": "dispute", "client": 1, "tx": 1},
+            ])),
+        )
+        .await;
+
+        let (status, events) = request(&router, Method::GET, "/admin/events", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let events = events.as_array().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["event"]["transaction"]["type"], "dispute");
+        let (_, older) = request(
+            &router,
+            Method::GET,
+            &format!("/admin/events?before={}", events[0]["id"]),
+            None,
+        )
+        .await;
+        assert_eq!(older, json!([events[1]]));
+
+        let (status, dump) = request(&router, Method::GET, "/admin/clients/1/rows", None).await;
+        assert_eq!(status, StatusCode::OK);
+        // As stored, in fixed point units
+        assert_eq!(dump["tables"]["Clients"][0]["held"], 15000);
+        assert_eq!(dump["tables"]["Transactions"][0]["type"], "deposit");
+        assert_eq!(
+            dump["tables"]["Disputes"][0]["transaction_id"],
+            dump["tables"]["Transactions"][0]["id"]
+        );
+        assert_eq!(dump["tables"]["Adjustments"], json!([]));
+
+        let (status, checkpoints) = request(&router, Method::GET, "/admin/checkpoints", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            checkpoints,
+            json!({
+                "audit_head": events[0]["id"],
+                "day_closes": [],
+                "balance_snapshots": [],
+                "source_offsets": [],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let svc = TransactionService::builder()
+            .alerts(crate::AlertThresholds {
+                max_held: Some(rust_decimal_macros::dec!(1)),
+                ..Default::default()
+            })
+            .build()
+            .await
+            .unwrap();
+        let scheduler = crate::Scheduler::new().job(
+            crate::Job::ReleaseSettlements,
+            crate::Schedule::every_minute(),
+        );
+        let router = router(AppState::new(Arc::new(svc)).with_scheduler(Arc::new(scheduler)));
+        request(
+            &router,
+            Method::POST,
+            "/transactions",
+            Some(json!([
+                {"type": "deposit", "client": 1, "tx": 1, "amount": 5},
+                {"type": "dispute", "client": 1, "tx": 1},
+                {"type": "withdrawal", "client": 1, "tx": 2, "amount": 10},
+            ])),
+        )
+        .await;
+
+        let request = Request::builder()
+            .uri("/metrics")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("transaction_app_alerts_total{kind=\"high_held\"} 1\n"));
+        assert!(body.contains("transaction_app_alerts_total{kind=\"low_available\"} 0\n"));
+        assert!(body.contains("transaction_app_unknown_targets_total 0\n"));
+        assert!(body.contains("transaction_app_insufficient_funds_total 1\n"));
+        assert!(body.contains(
+            "transaction_app_transactions_total{type=\"deposit\",outcome=\"applied\"} 1\n"
+        ));
+        assert!(body.contains(
+            "transaction_app_transactions_total{type=\"dispute\",outcome=\"ignored\"} 0\n"
+        ));
+        assert!(body.contains("transaction_app_job_runs_total{job=\"release_settlements\"} 0\n"));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_transaction() {
+        let router = create_router().await;
+
+        let (status, body) = request(
+            &router,
+            Method::POST,
+            "/transactions",
+            Some(json!({"type": "deposit", "client": 1, "tx": 1})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"].is_string());
+
+        let (status, _) = request(
+            &router,
+            Method::POST,
+            "/transactions",
+            Some(json!({"type": "unknown", "client": 1, "tx": 1})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_pseudonymized_errors() {
+        let pseudonymizer = crate::Pseudonymizer::new("secret");
+        let pseudonym = pseudonymizer.client(7101);
+        let svc = TransactionService::builder()
+            .pseudonymizer(pseudonymizer)
+            .build()
+            .await
+            .unwrap();
+        let router = router(AppState::new(Arc::new(svc)));
+        for transaction in [
+            json!({"type": "deposit", "client": 7101, "tx": 1, "amount": "2"}),
+            json!({"type": "dispute", "client": 7101, "tx": 1}),
+            json!({"type": "chargeback", "client": 7101, "tx": 1}),
+        ] {
+            let (status, _) =
+                request(&router, Method::POST, "/transactions", Some(transaction)).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+
+        let (status, body) = request(
+            &router,
+            Method::POST,
+            "/clients/7101/adjustments",
+            Some(json!({"amount": "1", "reason": "Goodwill"})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let error = body["error"].as_str().unwrap();
+        assert!(error.contains(&pseudonym), "{}", error);
+        assert!(!error.contains("7101"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_key() {
+        let router = create_router().await;
+        let post = |body: Value| {
+            Request::builder()
+                .method(Method::POST)
+                .uri("/transactions")
+                .header("content-type", "application/json")
+                .header("idempotency-key", "deposit-1")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        let deposit = json!({"type": "deposit", "client": 1, "tx": 1, "amount": "2.5"});
+
+        for _ in 0..2 {
+            let (status, body) = send(&router, post(deposit.clone())).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body, json!({"tx": 1, "outcome": "deposit"}));
+        }
+        let (_, client) = request(&router, Method::GET, "/clients/1", None).await;
+        assert_eq!(client["available"], "2.5000");
+
+        let withdrawal = json!({"type": "withdrawal", "client": 1, "tx": 2, "amount": "1"});
+        let (status, _) = send(&router, post(withdrawal)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_authorization() {
+        let auth = Authenticator::new()
+            .read_tokens(
+                "name,role,token\nui,viewer,view-token\nops,admin,admin-token\nlead,admin,lead-token\n"
+                    .as_bytes(),
+            )
+            .unwrap();
+        let svc = TransactionService::builder()
+            .approvals(crate::ApprovalRules {
+                unlocks: false,
+                adjustments_over: Some(rust_decimal_macros::dec!(1)),
+            })
+            .build()
+            .await
+            .unwrap();
+        let router = router(AppState::new(Arc::new(svc)).with_auth(Arc::new(auth)));
+        let call = |method: Method, uri: &str, token: Option<&str>, body: Value| {
+            let mut request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("content-type", "application/json");
+            if let Some(token) = token {
+                request = request.header("authorization", format!("Bearer {}", token));
+            }
+            request.body(Body::from(body.to_string())).unwrap()
+        };
+        let deposit = json!({"type": "deposit", "client": 1, "tx": 1, "amount": "2"});
+
+        let (status, _) = send(&router, call(Method::GET, "/clients", None, Value::Null)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = send(
+            &router,
+            call(Method::GET, "/clients", Some("wrong"), Value::Null),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = send(
+            &router,
+            call(Method::GET, "/clients", Some("view-token"), Value::Null),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = send(
+            &router,
+            call(
+                Method::POST,
+                "/transactions",
+                Some("view-token"),
+                deposit.clone(),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = send(
+            &router,
+            call(Method::POST, "/transactions", Some("admin-token"), deposit),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let adjustment = json!({"amount": "-0.5", "reason": "Fee refund reversal"});
+        let (status, _) = send(
+            &router,
+            call(
+                Method::POST,
+                "/clients/1/adjustments",
+                Some("view-token"),
+                adjustment.clone(),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, body) = send(
+            &router,
+            call(
+                Method::POST,
+                "/clients/1/adjustments",
+                Some("admin-token"),
+                adjustment,
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["operator"], "ops");
+
+        let (status, body) = send(
+            &router,
+            call(
+                Method::POST,
+                "/clients/1/unlock",
+                Some("admin-token"),
+                Value::Null,
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["locked"], false);
+
+        let large = json!({"amount": "-1.5", "reason": "Duplicate deposit"});
+        let (status, body) = send(
+            &router,
+            call(
+                Method::POST,
+                "/clients/1/adjustments",
+                Some("admin-token"),
+                large,
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(body["status"], "pending");
+        assert_eq!(body["action"], "adjustment");
+        let approve = format!("/approvals/{}/approve", body["id"]);
+        let (_, pending) = send(
+            &router,
+            call(Method::GET, "/approvals", Some("admin-token"), Value::Null),
+        )
+        .await;
+        assert_eq!(pending, json!([body]));
+        let (status, _) = send(
+            &router,
+            call(Method::POST, &approve, Some("admin-token"), Value::Null),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, body) = send(
+            &router,
+            call(Method::POST, &approve, Some("lead-token"), Value::Null),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["decided_by"], "lead");
+        let (_, client) = send(
+            &router,
+            call(Method::GET, "/clients/1", Some("view-token"), Value::Null),
+        )
+        .await;
+        assert_eq!(client["available"], "0.0000");
+    }
+
+    #[cfg(feature = "tls")]
+    #[tokio::test]
+    async fn test_serve_tls() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio_rustls::rustls::{pki_types::ServerName, ClientConfig, RootCertStore};
+
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let tls =
+            super::TlsConfig::from_pem(certified.cert.pem(), certified.signing_key.serialize_pem());
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let svc = TransactionService::builder().build().await.unwrap();
+        tokio::spawn(
+            async move { super::serve_tls(AppState::new(Arc::new(svc)), addr, &tls).await },
+        );
+
+        super::tls::install_crypto_provider();
+        let mut roots = RootCertStore::empty();
+        roots.add(certified.cert.der().clone()).unwrap();
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(
+            ClientConfig::builder()
+                .with_root_certificates(roots)
+                .with_no_client_auth(),
+        ));
+        let mut tcp = None;
+        for _ in 0..50 {
+            match tokio::net::TcpStream::connect(addr).await {
+                Ok(stream) => {
+                    tcp = Some(stream);
+                    break;
+                }
+                Err(_) => tokio::time::sleep(std::time::Duration::from_millis(20)).await,
+            }
+        }
+        let mut stream = connector
+            .connect(ServerName::try_from("localhost").unwrap(), tcp.unwrap())
+            .await
+            .unwrap();
+
+        stream
+            .write_all(b"GET /clients HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("[]"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker() {
+        use super::circuit_breaker::CircuitBreaker;
+        use std::time::Duration;
+
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let svc = TransactionService::builder()
+            .pool(pool.clone())
+            .build()
+            .await
+            .unwrap();
+        let circuit_breaker = Arc::new(CircuitBreaker::new(2, Duration::from_secs(60)));
+        let router = router(AppState::new(Arc::new(svc)).with_circuit_breaker(circuit_breaker));
+        let deposit =
+            |tx: TransactionId| json!({"type": "deposit", "client": 1, "tx": tx, "amount": "1"});
+
+        let (status, _) = request(&router, Method::POST, "/transactions", Some(deposit(1))).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // The database goes away
+        pool.close().await;
+        for tx in 2..=3 {
+            let (status, body) =
+                request(&router, Method::POST, "/transactions", Some(deposit(tx))).await;
+            assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+            assert!(!body["error"].as_str().unwrap().contains("submissions"));
+        }
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri("/transactions")
+                    .header("content-type", "application/json")
+                    .body(Body::from(deposit(4).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()["retry-after"], "60");
+
+        let request = Request::builder()
+            .uri("/metrics")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let metrics = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let metrics = String::from_utf8(metrics.to_vec()).unwrap();
+        assert!(metrics.contains("transaction_app_circuit_open 1\n"));
+        assert!(metrics.contains("transaction_app_circuit_trips_total 1\n"));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limits() {
+        use super::rate_limit::{Quota, RateLimits};
+        use std::num::NonZeroU32;
+
+        let svc = TransactionService::builder().build().await.unwrap();
+        let rate_limits = RateLimits::new().global(Quota::per_hour(NonZeroU32::new(2).unwrap()));
+        let router = router(AppState::new(Arc::new(svc)).with_rate_limits(Arc::new(rate_limits)));
+        let deposit =
+            |tx: TransactionId| json!({"type": "deposit", "client": 1, "tx": tx, "amount": "1"});
+
+        for tx in 1..=2 {
+            let (status, _) =
+                request(&router, Method::POST, "/transactions", Some(deposit(tx))).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri("/transactions")
+                    .header("content-type", "application/json")
+                    .body(Body::from(deposit(3).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key("retry-after"));
+
+        // Reads are not limited
+        let (status, _) = request(&router, Method::GET, "/clients/1", None).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_live_updates() {
+        let live_updates = LiveUpdates::new(16);
+        let svc = TransactionService::builder()
+            .observer(live_updates.clone())
+            .build()
+            .await
+            .unwrap();
+        let router = router(AppState::new(Arc::new(svc)).with_live_updates(live_updates));
+
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/events?client=2")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut events = response.into_body().into_data_stream();
+
+        for (client, tx) in [(1, 1), (2, 2)] {
+            request(
+                &router,
+                Method::POST,
+                "/transactions",
+                Some(json!({"type": "deposit", "client": client, "tx": tx, "amount": "1"})),
+            )
+            .await;
+        }
+
+        let event = events.next().await.unwrap().unwrap();
+        let event = String::from_utf8(event.to_vec()).unwrap();
+        assert!(event.starts_with("event: client_updated\n"), "{}", event);
+        assert!(event.contains(r#""client":2"#), "{}", event);
+    }
+}
@@ -0,0 +1,164 @@
+//! Rate limits on transaction submissions.
+
+use super::ApiError;
+use crate::ClientId;
+use axum::http::StatusCode;
+use governor::clock::{Clock, DefaultClock};
+use governor::{DefaultDirectRateLimiter, DefaultKeyedRateLimiter, NotUntil, RateLimiter};
+use std::collections::BTreeMap;
+use std::num::NonZeroU32;
+use std::sync::RwLock;
+use std::time::Duration;
+
+pub use governor::Quota;
+
+/// Limits how many transactions can be submitted, overall, by each caller and for each client.
+/// Every transaction in a batch counts against the limits.
+///
+/// The limits can be changed while the server runs with [`RateLimits::replace`].
+#[derive(Default)]
+pub struct RateLimits {
+    limiters: RwLock<Limiters>,
+}
+
+#[derive(Default)]
+struct Limiters {
+    global: Option<DefaultDirectRateLimiter>,
+    per_caller: Option<DefaultKeyedRateLimiter<String>>,
+    per_client: Option<DefaultKeyedRateLimiter<ClientId>>,
+}
+
+/// A submission was rejected by a [`RateLimits`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimited {
+    /// How long until the submission can be retried, `None` if it is larger than the limit
+    /// allows at once.
+    pub retry_after: Option<Duration>,
+}
+
+impl From<RateLimited> for ApiError {
+    fn from(e: RateLimited) -> Self {
+        match e.retry_after {
+            Some(retry_after) => ApiError {
+                // Round up so a retry after `Retry-After` seconds is allowed.
+                retry_after: Some(
+                    retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0),
+                ),
+                ..ApiError::new(StatusCode::TOO_MANY_REQUESTS, "Rate limit exceeded")
+            },
+            None => ApiError::new(
+                StatusCode::TOO_MANY_REQUESTS,
+                "The submission is larger than the rate limit allows",
+            ),
+        }
+    }
+}
+
+type Checked =
+    Result<Result<(), NotUntil<<DefaultClock as Clock>::Instant>>, governor::InsufficientCapacity>;
+
+impl RateLimits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits the transactions submitted by all callers together.
+    pub fn global(mut self, quota: Quota) -> Self {
+        self.limiters_mut().global = Some(RateLimiter::direct(quota));
+        self
+    }
+
+    /// Limits the transactions submitted by each authenticated caller, keyed by the name of
+    /// their [`Identity`](super::auth::Identity).
+    pub fn per_caller(mut self, quota: Quota) -> Self {
+        self.limiters_mut().per_caller = Some(RateLimiter::keyed(quota));
+        self
+    }
+
+    /// Limits the transactions submitted for each client id.
+    pub fn per_client(mut self, quota: Quota) -> Self {
+        self.limiters_mut().per_client = Some(RateLimiter::keyed(quota));
+        self
+    }
+
+    /// Applies the limits of `limits` from now on, e.g. when the configuration is reloaded.
+    /// The submissions counted so far are forgotten.
+    pub fn replace(&self, limits: RateLimits) {
+        let limiters = limits.limiters.into_inner().unwrap();
+        *self.limiters.write().unwrap() = limiters;
+    }
+
+    fn limiters_mut(&mut self) -> &mut Limiters {
+        self.limiters.get_mut().unwrap()
+    }
+
+    /// Counts a submission of transactions for `client_ids` by `caller` against the limits.
+    pub fn check(&self, caller: Option<&str>, client_ids: &[ClientId]) -> Result<(), RateLimited> {
+        let Some(total) = NonZeroU32::new(client_ids.len().try_into().unwrap_or(u32::MAX)) else {
+            return Ok(());
+        };
+
+        let limiters = self.limiters.read().unwrap();
+        if let Some(limiter) = &limiters.per_client {
+            let mut counts = BTreeMap::<ClientId, u32>::new();
+            for client_id in client_ids {
+                *counts.entry(*client_id).or_default() += 1;
+            }
+            for (client_id, count) in counts {
+                let count = NonZeroU32::new(count).unwrap_or(NonZeroU32::MIN);
+                Self::limited(limiter.check_key_n(&client_id, count))?;
+            }
+        }
+        if let (Some(limiter), Some(caller)) = (&limiters.per_caller, caller) {
+            Self::limited(limiter.check_key_n(&caller.to_string(), total))?;
+        }
+        if let Some(limiter) = &limiters.global {
+            Self::limited(limiter.check_n(total))?;
+        }
+        Ok(())
+    }
+
+    fn limited(checked: Checked) -> Result<(), RateLimited> {
+        match checked {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(not_until)) => Err(RateLimited {
+                retry_after: Some(not_until.wait_time_from(DefaultClock::default().now())),
+            }),
+            Err(_) => Err(RateLimited { retry_after: None }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Quota, RateLimits};
+    use std::num::NonZeroU32;
+
+    #[test]
+    fn test_limits() {
+        let limits = RateLimits::new()
+            .per_client(Quota::per_minute(NonZeroU32::new(2).unwrap()))
+            .per_caller(Quota::per_minute(NonZeroU32::new(3).unwrap()));
+
+        assert!(limits.check(Some("a"), &[1, 1]).is_ok());
+        assert!(limits
+            .check(Some("a"), &[1])
+            .unwrap_err()
+            .retry_after
+            .is_some());
+        assert!(limits.check(Some("a"), &[2]).is_ok());
+        // The caller used their 3 transactions, other callers are unaffected
+        assert!(limits.check(Some("a"), &[3]).is_err());
+        assert!(limits.check(Some("b"), &[3]).is_ok());
+        assert!(limits.check(None, &[4, 4]).is_ok());
+
+        assert_eq!(
+            limits.check(None, &[5, 5, 5]).unwrap_err().retry_after,
+            None
+        );
+
+        limits
+            .replace(RateLimits::new().per_client(Quota::per_minute(NonZeroU32::new(3).unwrap())));
+        assert!(limits.check(Some("a"), &[5, 5, 5]).is_ok());
+    }
+}
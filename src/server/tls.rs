@@ -0,0 +1,36 @@
+//! TLS termination for the HTTP and gRPC listeners.
+
+use std::io;
+use std::path::Path;
+
+/// A PEM encoded certificate chain and private key to terminate TLS with.
+#[derive(Clone)]
+pub struct TlsConfig {
+    pub(super) cert: Vec<u8>,
+    pub(super) key: Vec<u8>,
+}
+
+impl TlsConfig {
+    pub fn from_pem(cert: impl Into<Vec<u8>>, key: impl Into<Vec<u8>>) -> Self {
+        Self {
+            cert: cert.into(),
+            key: key.into(),
+        }
+    }
+
+    /// Reads the certificate chain and private key from PEM files.
+    pub fn from_pem_files(cert: impl AsRef<Path>, key: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::from_pem(std::fs::read(cert)?, std::fs::read(key)?))
+    }
+
+    pub(super) async fn rustls_config(&self) -> io::Result<axum_server::tls_rustls::RustlsConfig> {
+        install_crypto_provider();
+        axum_server::tls_rustls::RustlsConfig::from_pem(self.cert.clone(), self.key.clone()).await
+    }
+}
+
+/// Selects the rustls crypto provider, which must happen before building a rustls config.
+pub(super) fn install_crypto_provider() {
+    // Fails when a provider is already installed, which is fine.
+    let _ = rustls::crypto::ring::default_provider().install_default();
+}
@@ -0,0 +1,137 @@
+use super::{Client, ClientId};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+/// Balances that raise an [`Alert`] when a client's balances cross them, configured with
+/// [`TransactionServiceBuilder::alerts`](super::TransactionServiceBuilder::alerts).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AlertThresholds {
+    /// Alert when a client's available funds drop below this, e.g. `0` for negative balances.
+    pub min_available: Option<Decimal>,
+    /// Alert when a client's held funds rise above this.
+    pub max_held: Option<Decimal>,
+}
+
+impl AlertThresholds {
+    pub(super) fn is_empty(&self) -> bool {
+        self.min_available.is_none() && self.max_held.is_none()
+    }
+
+    /// The thresholds `after` has crossed since `before`, a client that did not exist yet
+    /// being within every threshold.
+    pub(super) fn crossed(&self, before: Option<&Client>, after: &Client) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        if let Some(min) = self.min_available {
+            if after.available < min && before.is_none_or(|b| b.available >= min) {
+                alerts.push((AlertKind::LowAvailable, min));
+            }
+        }
+        if let Some(max) = self.max_held {
+            if after.held > max && before.is_none_or(|b| b.held <= max) {
+                alerts.push((AlertKind::HighHeld, max));
+            }
+        }
+        alerts
+            .into_iter()
+            .map(|(kind, threshold)| Alert {
+                kind,
+                threshold,
+                client_id: after.id,
+                available: after.available,
+                held: after.held,
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    /// Available funds dropped below [`AlertThresholds::min_available`].
+    LowAvailable,
+    /// Held funds rose above [`AlertThresholds::max_held`].
+    HighHeld,
+    /// A transaction was rejected because it would have taken the balances above the largest
+    /// amount that can be stored, the threshold. Always raised, whatever the thresholds.
+    BalanceOverflow,
+}
+
+impl AlertKind {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::LowAvailable => "low_available",
+            Self::HighHeld => "high_held",
+            Self::BalanceOverflow => "balance_overflow",
+        }
+    }
+}
+
+/// A client's balances crossed one of the [`AlertThresholds`]. Raised once when crossing, not
+/// again until the balances are back within the threshold and cross it again.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Alert {
+    pub kind: AlertKind,
+    pub threshold: Decimal,
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    /// The client's balances after crossing the threshold.
+    pub available: Decimal,
+    pub held: Decimal,
+}
+
+/// The number of alerts raised since the service was built, see
+/// [`TransactionService::alert_counts`](super::TransactionService::alert_counts).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AlertCounts {
+    pub low_available: u64,
+    pub high_held: u64,
+    pub balance_overflow: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AlertKind, AlertThresholds};
+    use crate::Client;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    fn client(available: Decimal, held: Decimal) -> Client {
+        Client {
+            id: 1,
+            available,
+            held,
+            total: available + held,
+            locked: false,
+        }
+    }
+
+    #[test]
+    fn test_crossed() {
+        let thresholds = AlertThresholds {
+            min_available: Some(dec!(0)),
+            max_held: Some(dec!(100)),
+        };
+        let kinds = |before: Option<&Client>, after: &Client| {
+            thresholds
+                .crossed(before, after)
+                .iter()
+                .map(|a| a.kind)
+                .collect::<Vec<_>>()
+        };
+        let ok = client(dec!(10), dec!(0));
+        let negative = client(dec!(-5), dec!(0));
+        let held = client(dec!(-5), dec!(150));
+
+        assert_eq!(kinds(Some(&ok), &negative), [AlertKind::LowAvailable]);
+        assert_eq!(kinds(None, &negative), [AlertKind::LowAvailable]);
+        // Only when crossing
+        assert_eq!(kinds(Some(&negative), &negative), []);
+        assert_eq!(kinds(Some(&negative), &held), [AlertKind::HighHeld]);
+        assert_eq!(
+            kinds(Some(&ok), &held),
+            [AlertKind::LowAvailable, AlertKind::HighHeld]
+        );
+        assert_eq!(kinds(Some(&negative), &ok), []);
+        assert!(AlertThresholds::default().is_empty());
+    }
+}
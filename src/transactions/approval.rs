@@ -0,0 +1,119 @@
+use super::{ClientId, Result, TransactionError};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+/// The administrative changes that need a second operator's approval, see
+/// [`TransactionServiceBuilder::approvals`](super::TransactionServiceBuilder::approvals).
+/// Nothing needs approval by default.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ApprovalRules {
+    /// Unlocking a client.
+    pub unlocks: bool,
+    /// Adjusting a client's available funds by more than this amount, in either direction.
+    pub adjustments_over: Option<Decimal>,
+}
+
+impl ApprovalRules {
+    /// Whether `action` needs approval.
+    pub fn requires(&self, action: &ApprovalAction) -> bool {
+        match action {
+            ApprovalAction::Unlock => self.unlocks,
+            ApprovalAction::Adjustment { amount, .. } => self
+                .adjustments_over
+                .is_some_and(|threshold| amount.abs() > threshold),
+        }
+    }
+}
+
+/// The change an [`Approval`] applies once approved.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ApprovalAction {
+    /// [`TransactionService::unlock_client`](super::TransactionService::unlock_client).
+    Unlock,
+    /// [`TransactionService::adjust_balance`](super::TransactionService::adjust_balance).
+    Adjustment { amount: Decimal, reason: String },
+}
+
+impl ApprovalAction {
+    pub fn to_str(&self) -> &'static str {
+        match self {
+            Self::Unlock => "unlock",
+            Self::Adjustment { .. } => "adjustment",
+        }
+    }
+}
+
+/// Whether an [`Approval`] was decided.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApprovalStatus {
+    Pending,
+    /// Approved by a second operator, and its change applied.
+    Approved,
+    Rejected,
+}
+
+impl ApprovalStatus {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Approved => "approved",
+            Self::Rejected => "rejected",
+        }
+    }
+
+    pub(super) fn parse(status: &str) -> Result<Self> {
+        match status {
+            "pending" => Ok(Self::Pending),
+            "approved" => Ok(Self::Approved),
+            "rejected" => Ok(Self::Rejected),
+            _ => Err(TransactionError::InvalidArgument(format!(
+                "Unknown approval status \"{}\"",
+                status
+            ))),
+        }
+    }
+}
+
+/// A change held for a second operator's approval by the [`ApprovalRules`], returned in
+/// [`TransactionError::ApprovalRequired`] when it is requested.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Approval {
+    pub id: i64,
+    pub client_id: ClientId,
+    #[serde(flatten)]
+    pub action: ApprovalAction,
+    pub status: ApprovalStatus,
+    /// The operator who requested the change.
+    pub requested_by: String,
+    /// In milliseconds since the unix epoch, from the service's [`Clock`](super::Clock).
+    pub requested_at: i64,
+    /// The operator who approved or rejected it.
+    pub decided_by: Option<String>,
+    pub decided_at: Option<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_approval_rules() {
+        let adjustment = |amount| ApprovalAction::Adjustment {
+            amount,
+            reason: "Refund".into(),
+        };
+        let rules = ApprovalRules {
+            unlocks: true,
+            adjustments_over: Some(dec!(100)),
+        };
+        assert!(rules.requires(&ApprovalAction::Unlock));
+        assert!(!rules.requires(&adjustment(dec!(100))));
+        assert!(rules.requires(&adjustment(dec!(100.01))));
+        assert!(rules.requires(&adjustment(dec!(-500))));
+        assert!(!ApprovalRules::default().requires(&ApprovalAction::Unlock));
+        assert!(!ApprovalRules::default().requires(&adjustment(dec!(-500))));
+    }
+}
@@ -0,0 +1,96 @@
+use super::{Client, Transaction};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::fmt::{self, Write};
+
+/// The hash the first record of the audit log is chained to.
+pub(super) const GENESIS_HASH: &str =
+    "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// The SHA-256 of a record's event, as hex.
+pub(super) fn content_hash(event: &str) -> String {
+    hex(&Sha256::digest(event.as_bytes()))
+}
+
+/// The hash of an audit log record: the SHA-256 of the previous record's hash, when the record
+/// was made and the [`content_hash`] of its event, as hex. Changing, inserting or removing a
+/// record changes the hash of every record after it.
+///
+/// Chaining the content hash instead of the event lets
+/// [`TransactionService::forget_client`](super::TransactionService::forget_client) redact an
+/// event while keeping the hash of the original, without breaking the chain.
+pub(super) fn chain_hash(prev_hash: &str, created_at: i64, content_hash: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(prev_hash.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(created_at.to_string().as_bytes());
+    hasher.update(b"\n");
+    hasher.update(content_hash.as_bytes());
+    hex(&hasher.finalize())
+}
+
+pub(super) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+    })
+}
+
+/// The parts of a recorded [`LedgerEvent`](super::LedgerEvent) checked against the ledger.
+#[derive(Deserialize)]
+pub(super) struct AuditedEvent {
+    pub transaction: Option<Transaction>,
+    pub client: Option<Client>,
+}
+
+/// The result of [`TransactionService::verify_audit_log`](super::TransactionService::verify_audit_log).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditVerification {
+    /// The number of records in the audit log.
+    pub records: u64,
+    /// The hash of the last record. Keep a copy of it elsewhere to also detect records removed
+    /// from the end of the log.
+    pub head: String,
+    /// A description of every modification found, empty if there were none.
+    pub problems: Vec<String>,
+}
+
+impl AuditVerification {
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl fmt::Display for AuditVerification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "records: {}", self.records)?;
+        writeln!(f, "head:    {}", self.head)?;
+        if self.problems.is_empty() {
+            write!(f, "chain:   ok")
+        } else {
+            write!(f, "chain:   {} problems", self.problems.len())?;
+            for problem in &self.problems {
+                write!(f, "\n  {}", problem)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{chain_hash, content_hash, GENESIS_HASH};
+
+    #[test]
+    fn test_chain_hash() {
+        let content = content_hash("{}");
+        assert_eq!(content.len(), 64);
+        assert_ne!(content_hash("{ }"), content);
+        let hash = chain_hash(GENESIS_HASH, 1, &content);
+        assert_eq!(hash.len(), 64);
+        assert_eq!(chain_hash(GENESIS_HASH, 1, &content), hash);
+        assert_ne!(chain_hash(&hash, 1, &content), hash);
+        assert_ne!(chain_hash(GENESIS_HASH, 2, &content), hash);
+        assert_ne!(chain_hash(GENESIS_HASH, 1, &content_hash("{ }")), hash);
+    }
+}
@@ -0,0 +1,210 @@
+use super::{
+    Adjustment, CashPosition, Client, ClientId, ClientTag, ClientWithStats, DayClose, ExternalIds,
+    GroupBy, LedgerState, MaintenanceReport, ProcessingOutcome, Projection, Result, Transaction,
+    TransactionAggregate, TransactionFilter, TransactionId, TransactionOutcome, TransactionService,
+};
+use rust_decimal::Decimal;
+use std::future::Future;
+use std::path::Path;
+use tokio::runtime::Runtime;
+
+/// A [`TransactionService`] for callers without an async runtime, built with
+/// [`TransactionServiceBuilder::build_blocking`](super::TransactionServiceBuilder::build_blocking).
+///
+/// Every call blocks the current thread until it completes, running the service on a
+/// single-threaded runtime owned by this type. Background work, such as delivering webhooks,
+/// only makes progress during those calls. Calling it from inside an async runtime panics, use
+/// [`TransactionService`] there instead.
+///
+/// ```
+/// # fn main() -> transaction_app::Result<()> {
+/// use transaction_app::{TransactionReader, TransactionService};
+///
+/// let svc = TransactionService::builder().build_blocking()?;
+///
+/// let csv = "type, client, tx, amount\ndeposit, 1, 1, 1.0";
+/// let mut reader = TransactionReader::new(csv.as_bytes());
+/// svc.process_transactions(reader.transactions())?;
+/// assert_eq!(svc.get_clients()?.len(), 1);
+/// # Ok(())
+/// # }
+/// ```
+pub struct BlockingTransactionService {
+    // Dropped before the runtime it was created on
+    svc: TransactionService,
+    runtime: Runtime,
+}
+
+impl BlockingTransactionService {
+    pub(super) fn new(svc: TransactionService, runtime: Runtime) -> Self {
+        Self { svc, runtime }
+    }
+
+    /// The wrapped service, for the methods not offered here, which can be run with
+    /// [`Self::block_on`].
+    pub fn service(&self) -> &TransactionService {
+        &self.svc
+    }
+
+    /// Runs `future` to completion on the service's runtime.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+
+    /// See [`TransactionService::process_transaction`].
+    pub fn process_transaction(&self, transaction: &Transaction) -> Result<TransactionOutcome> {
+        self.block_on(self.svc.process_transaction(transaction))
+    }
+
+    /// Applies every transaction from `transactions` in batches, see
+    /// [`TransactionService::process_stream`].
+    pub fn process_transactions<I>(&self, transactions: I) -> Result<ProcessingOutcome>
+    where
+        I: IntoIterator<Item = Result<Transaction>>,
+    {
+        self.block_on(self.svc.process_stream(futures::stream::iter(transactions)))
+    }
+
+    pub fn get_client(&self, client_id: ClientId) -> Result<Option<Client>> {
+        self.block_on(self.svc.get_client(client_id))
+    }
+
+    /// Collects every client into a [`Vec`].
+    pub fn get_clients(&self) -> Result<Vec<Client>> {
+        self.block_on(self.svc.get_clients_vec())
+    }
+
+    /// See [`TransactionService::get_client_stats`].
+    pub fn get_client_stats(&self) -> Result<Vec<ClientWithStats>> {
+        self.block_on(self.svc.get_client_stats())
+    }
+
+    pub fn get_transaction(&self, transaction_id: TransactionId) -> Result<Option<Transaction>> {
+        self.block_on(self.svc.get_transaction(transaction_id))
+    }
+
+    /// See [`TransactionService::get_aggregates`].
+    pub fn get_aggregates(
+        &self,
+        group_by: GroupBy,
+        filter: &TransactionFilter,
+    ) -> Result<Vec<TransactionAggregate>> {
+        self.block_on(self.svc.get_aggregates(group_by, filter))
+    }
+
+    /// See [`TransactionService::get_cash_position`].
+    pub fn get_cash_position(&self) -> Result<CashPosition> {
+        self.block_on(self.svc.get_cash_position())
+    }
+
+    /// See [`TransactionService::get_external_ids`].
+    pub fn get_external_ids(&self) -> Result<ExternalIds> {
+        self.block_on(self.svc.get_external_ids())
+    }
+
+    /// See [`TransactionService::save_external_ids`].
+    pub fn save_external_ids(&self, external_ids: &ExternalIds) -> Result<()> {
+        self.block_on(self.svc.save_external_ids(external_ids))
+    }
+
+    /// See [`TransactionService::simulate`].
+    pub fn simulate(&self, transactions: &[Transaction]) -> Result<Projection> {
+        self.block_on(self.svc.simulate(transactions))
+    }
+
+    /// See [`TransactionService::adjust_balance`].
+    pub fn adjust_balance(
+        &self,
+        client_id: ClientId,
+        delta: Decimal,
+        reason: &str,
+        operator: &str,
+    ) -> Result<Adjustment> {
+        self.block_on(self.svc.adjust_balance(client_id, delta, reason, operator))
+    }
+
+    /// See [`TransactionService::unlock_client`].
+    pub fn unlock_client(&self, client_id: ClientId, operator: &str) -> Result<Client> {
+        self.block_on(self.svc.unlock_client(client_id, operator))
+    }
+
+    /// See [`TransactionService::tag_clients`].
+    pub fn tag_clients(&self, tags: &[ClientTag]) -> Result<u64> {
+        self.block_on(self.svc.tag_clients(tags))
+    }
+
+    /// See [`TransactionService::close_day`].
+    pub fn close_day(&self, business_date: time::Date) -> Result<DayClose> {
+        self.block_on(self.svc.close_day(business_date))
+    }
+
+    /// See [`TransactionService::export_state`].
+    pub fn export_state(&self) -> Result<LedgerState> {
+        self.block_on(self.svc.export_state())
+    }
+
+    /// See [`TransactionService::import_state`].
+    pub fn import_state(&self, state: &LedgerState) -> Result<()> {
+        self.block_on(self.svc.import_state(state))
+    }
+
+    /// See [`TransactionService::backup`].
+    pub fn backup(&self, path: &Path) -> Result<()> {
+        self.block_on(self.svc.backup(path))
+    }
+
+    /// See [`TransactionService::restore`].
+    pub fn restore(&self, path: &Path) -> Result<()> {
+        self.block_on(self.svc.restore(path))
+    }
+
+    /// See [`TransactionService::maintain`].
+    pub fn maintain(&self) -> Result<MaintenanceReport> {
+        self.block_on(self.svc.maintain())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Transaction, TransactionOutcome, TransactionService, TransactionType};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_blocking() {
+        let svc = TransactionService::builder().build_blocking().unwrap();
+        let deposit = |id, amount| {
+            Ok(Transaction {
+                id,
+                transaction_type: TransactionType::Deposit,
+                client_id: 1,
+                amount: Some(amount),
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+        };
+        let outcome = svc
+            .process_transactions([deposit(1, dec!(2)), deposit(2, dec!(3))])
+            .unwrap();
+        assert_eq!(outcome.applied, 2);
+        assert_eq!(
+            svc.process_transaction(&Transaction {
+                id: 3,
+                transaction_type: TransactionType::Withdrawal,
+                client_id: 1,
+                amount: Some(dec!(10)),
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+            .unwrap(),
+            TransactionOutcome::WithdrawalRejected
+        );
+        assert_eq!(svc.get_client(1).unwrap().unwrap().available, dec!(5));
+        assert_eq!(svc.get_clients().unwrap().len(), 1);
+        assert!(svc.get_transaction(2).unwrap().is_some());
+        assert_eq!(svc.block_on(svc.service().get_dispute(1)).unwrap(), None);
+    }
+}
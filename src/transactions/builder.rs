@@ -0,0 +1,367 @@
+use super::{
+    AlertThresholds, ApprovalRules, BlockingTransactionService, Clock, EventObserver,
+    Pseudonymizer, Result, RetryPolicy, ShardedTransactionService, SystemClock, TransactionError,
+    TransactionHandler, TransactionPolicy, TransactionService, TransactionType,
+    TransactionValidator, UnknownTargetAction,
+};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::{Pool, Sqlite};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+const DEFAULT_DATABASE_URL: &str = "sqlite://:memory:";
+const DEFAULT_PRECISION: u32 = 4;
+/// The most decimal places amounts can be stored with, as an `i64`.
+const MAX_PRECISION: u32 = 18;
+const DEFAULT_BATCH_SIZE: usize = 1000;
+
+/// The database url of shard `index`. In-memory databases are left as they are, as every pool
+/// opens a database of its own.
+fn shard_url(url: &str, index: usize) -> String {
+    if url.contains(":memory:") {
+        return url.to_string();
+    }
+    match url.split_once('?') {
+        Some((path, options)) => format!("{}.{}?{}", path, index, options),
+        None => format!("{}.{}", url, index),
+    }
+}
+
+#[derive(Clone)]
+enum Storage {
+    Pool(Pool<Sqlite>),
+    Url(String),
+}
+
+/// Configures and creates a [`TransactionService`].
+///
+/// ```no_run
+/// # async fn run() -> transaction_app::Result<()> {
+/// use transaction_app::TransactionService;
+///
+/// let svc = TransactionService::builder()
+///     .database_url("sqlite://ledger.db")
+///     .max_connections(4)
+///     .precision(4)
+///     .build()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct TransactionServiceBuilder {
+    storage: Storage,
+    read_replicas: Vec<String>,
+    max_connections: Option<u32>,
+    pub(super) precision: u32,
+    pub(super) policy: TransactionPolicy,
+    pub(super) observers: Vec<Arc<dyn EventObserver>>,
+    pub(super) batch_size: usize,
+    pub(super) outbox: bool,
+    pub(super) audit_log: bool,
+    pub(super) handlers: HashMap<String, Arc<dyn TransactionHandler>>,
+    pub(super) validators: Vec<Arc<dyn TransactionValidator>>,
+    pub(super) clock: Arc<dyn Clock>,
+    pub(super) pseudonymizer: Option<Pseudonymizer>,
+    pub(super) alerts: AlertThresholds,
+    pub(super) approvals: ApprovalRules,
+    pub(super) slow_transaction: Option<Duration>,
+    pub(super) backfill: bool,
+    pub(super) unknown_targets: UnknownTargetAction,
+    pub(super) retry: RetryPolicy,
+    pub(super) isolate_failures: bool,
+    #[cfg(feature = "chaos")]
+    pub(super) chaos: Option<super::ChaosConfig>,
+}
+
+impl Default for TransactionServiceBuilder {
+    fn default() -> Self {
+        Self {
+            storage: Storage::Url(DEFAULT_DATABASE_URL.to_string()),
+            read_replicas: Vec::new(),
+            max_connections: None,
+            precision: DEFAULT_PRECISION,
+            policy: TransactionPolicy::default(),
+            observers: Vec::new(),
+            batch_size: DEFAULT_BATCH_SIZE,
+            outbox: false,
+            audit_log: false,
+            handlers: HashMap::new(),
+            validators: Vec::new(),
+            clock: Arc::new(SystemClock),
+            pseudonymizer: None,
+            alerts: AlertThresholds::default(),
+            approvals: ApprovalRules::default(),
+            slow_transaction: None,
+            backfill: false,
+            unknown_targets: UnknownTargetAction::Ignore,
+            retry: RetryPolicy::default(),
+            isolate_failures: false,
+            #[cfg(feature = "chaos")]
+            chaos: None,
+        }
+    }
+}
+
+impl TransactionServiceBuilder {
+    /// Uses an existing connection pool. Overrides [`Self::database_url`].
+    pub fn pool(mut self, pool: Pool<Sqlite>) -> Self {
+        self.storage = Storage::Pool(pool);
+        self
+    }
+
+    /// Connects to the sqlite database at `url`, creating it if it is missing.
+    /// Defaults to an in-memory database.
+    pub fn database_url(mut self, url: impl Into<String>) -> Self {
+        self.storage = Storage::Url(url.into());
+        self
+    }
+
+    /// Reads clients, transactions, disputes and reports from a read-only copy of the database
+    /// at `url` instead, e.g. one kept up to date by LiteFS or Litestream, so that queries do
+    /// not compete with writes. Reads from a copy can lag behind the writes. Reads made
+    /// together with a write, such as [`TransactionService::get_cash_position`], still use
+    /// the database. Can be called multiple times to spread reads over several copies.
+    pub fn read_replica_url(mut self, url: impl Into<String>) -> Self {
+        self.read_replicas.push(url.into());
+        self
+    }
+
+    /// The maximum number of database connections to open. Only used with
+    /// [`Self::database_url`].
+    pub fn max_connections(mut self, max_connections: u32) -> Self {
+        self.max_connections = Some(max_connections);
+        self
+    }
+
+    /// The number of decimal places amounts are stored with. Defaults to 4.
+    ///
+    /// Amounts with more decimal places are truncated. The precision must stay the same for
+    /// the lifetime of a database, and be at most 18, or building the service fails.
+    pub fn precision(mut self, decimal_places: u32) -> Self {
+        self.precision = decimal_places;
+        self
+    }
+
+    /// Limits applied to incoming transactions.
+    pub fn policy(mut self, policy: TransactionPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Adds an observer that is notified of every change made by the service.
+    /// Can be called multiple times to add several observers.
+    pub fn observer(mut self, observer: impl EventObserver + 'static) -> Self {
+        self.observers.push(Arc::new(observer));
+        self
+    }
+
+    /// The maximum number of transactions
+    /// [`TransactionService::process_stream`] commits in a single database transaction.
+    /// Defaults to 1000.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Writes a [`LedgerEvent`](super::LedgerEvent) to the outbox for every change, in the
+    /// same database transaction as the change. The events are read with
+    /// [`TransactionService::outbox_events`] and removed once shipped. Defaults to off.
+    pub fn outbox(mut self, enabled: bool) -> Self {
+        self.outbox = enabled;
+        self
+    }
+
+    /// Records every change as a [`LedgerEvent`](super::LedgerEvent) in a hash chained audit
+    /// log, in the same database transaction as the change, so later modifications of the
+    /// ledger are found by [`TransactionService::verify_audit_log`]. Enable it for the whole
+    /// life of a database, changes made without it are reported as modifications. Defaults
+    /// to off.
+    pub fn audit_log(mut self, enabled: bool) -> Self {
+        self.audit_log = enabled;
+        self
+    }
+
+    /// Adds a validator that checks every transaction before it is applied.
+    /// Can be called multiple times, the validators run in the order they were added.
+    pub fn validator(mut self, validator: impl TransactionValidator + 'static) -> Self {
+        self.validators.push(Arc::new(validator));
+        self
+    }
+
+    /// Applies transactions of the custom type `name` with `handler`. Transactions of a custom
+    /// type without a handler fail with [`TransactionError::InvalidTransaction`].
+    ///
+    /// Building fails if `name` is one of the built in types.
+    pub fn handler(
+        mut self,
+        name: impl Into<String>,
+        handler: impl TransactionHandler + 'static,
+    ) -> Self {
+        self.handlers.insert(name.into(), Arc::new(handler));
+        self
+    }
+
+    /// The source of the current time. Defaults to the [`SystemClock`].
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Logs client ids as tokens from `pseudonymizer`, see
+    /// [`TransactionService::pseudonymizer`].
+    pub fn pseudonymizer(mut self, pseudonymizer: Pseudonymizer) -> Self {
+        self.pseudonymizer = Some(pseudonymizer);
+        self
+    }
+
+    /// Raises an [`Alert`](super::Alert) when a transaction moves a client's balances across
+    /// one of `thresholds`. Alerts are logged, counted in
+    /// [`TransactionService::alert_counts`] and sent to the observers once the change commits.
+    pub fn alerts(mut self, thresholds: AlertThresholds) -> Self {
+        self.alerts = thresholds;
+        self
+    }
+
+    /// Holds the unlocks and adjustments matching `rules` until a second operator approves
+    /// them with [`TransactionService::approve_request`]. Requesting one fails with
+    /// [`TransactionError::ApprovalRequired`].
+    pub fn approvals(mut self, rules: ApprovalRules) -> Self {
+        self.approvals = rules;
+        self
+    }
+
+    /// Logs a warning with the time spent in each step, from waiting for the write lock to
+    /// the commit, whenever [`TransactionService::process_transaction`] takes longer than
+    /// `threshold`.
+    pub fn slow_transaction_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_transaction = Some(threshold);
+        self
+    }
+
+    /// Replays historical transactions without their side effects, to rebuild a ledger: the
+    /// observers are not notified, no alerts are raised, nothing is written to the outbox,
+    /// chargebacks do not escalate clients' risk levels and the risk score changes of the
+    /// validators are dropped. Validators still accept or reject transactions, and the audit
+    /// log is still written. Defaults to off.
+    pub fn backfill(mut self, enabled: bool) -> Self {
+        self.backfill = enabled;
+        self
+    }
+
+    /// What to do with disputes, resolves and chargebacks that refer to a transaction that is
+    /// not stored or not under dispute. Defaults to ignoring them.
+    pub fn unknown_targets(mut self, action: UnknownTargetAction) -> Self {
+        self.unknown_targets = action;
+        self
+    }
+
+    /// Retries processing transactions after transient database errors, such as another
+    /// process holding the database, according to `policy`. Defaults to
+    /// [`RetryPolicy::default`].
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Applies each transaction of a batch in a savepoint, so a transaction failing with an
+    /// error, such as a failed insert, only rolls back its own changes and is
+    /// [rejected](super::TransactionOutcome::Rejected) with the reason
+    /// [`TransactionOutcome::FAILED`](super::TransactionOutcome::FAILED), while the rest of the
+    /// batch is committed. Errors of the database being unavailable, which are retried with
+    /// the whole batch, and invalid transactions, such as those stopped by
+    /// [`UnknownTargetAction::Error`], still fail the batch. Defaults to off.
+    pub fn isolate_failures(mut self, enabled: bool) -> Self {
+        self.isolate_failures = enabled;
+        self
+    }
+
+    /// Injects faults while processing, see [`ChaosConfig`](super::ChaosConfig).
+    #[cfg(feature = "chaos")]
+    pub fn chaos(mut self, config: super::ChaosConfig) -> Self {
+        self.chaos = Some(config);
+        self
+    }
+
+    /// Builds a [`BlockingTransactionService`] on a new single-threaded runtime, for callers
+    /// without an async runtime. Panics when called from inside one.
+    pub fn build_blocking(self) -> Result<BlockingTransactionService> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let svc = runtime.block_on(self.build())?;
+        Ok(BlockingTransactionService::new(svc, runtime))
+    }
+
+    /// Builds a service for each of `shards` databases, spreading clients over them, see
+    /// [`ShardedTransactionService`]. Shard `i` of `sqlite://ledger.db` is stored in
+    /// `sqlite://ledger.db.<i>`. Needs [`Self::database_url`].
+    pub async fn build_sharded(self, shards: usize) -> Result<ShardedTransactionService> {
+        let Storage::Url(url) = &self.storage else {
+            return Err(TransactionError::InvalidArgument(
+                "Sharding needs a database url".into(),
+            ));
+        };
+        let mut services = Vec::with_capacity(shards);
+        for index in 0..shards {
+            let mut builder = self.clone();
+            builder.storage = Storage::Url(shard_url(url, index));
+            builder.read_replicas = (self.read_replicas.iter())
+                .map(|url| shard_url(url, index))
+                .collect();
+            services.push(builder.build().await?);
+        }
+        ShardedTransactionService::new(services)
+    }
+
+    pub async fn build(mut self) -> Result<TransactionService> {
+        if self.precision > MAX_PRECISION {
+            return Err(TransactionError::InvalidArgument(format!(
+                "Can not store amounts with {} decimal places, at most {}",
+                self.precision, MAX_PRECISION
+            )));
+        }
+        for name in self.handlers.keys() {
+            if !matches!(
+                TransactionType::from_str(name),
+                Some(TransactionType::Custom(_))
+            ) {
+                return Err(TransactionError::InvalidArgument(format!(
+                    "Can not register a handler for the \"{}\" transaction type",
+                    name
+                )));
+            }
+        }
+
+        let pool = match &self.storage {
+            Storage::Pool(pool) => pool.clone(),
+            Storage::Url(url) => {
+                let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+                let mut pool_options = SqlitePoolOptions::new();
+                if let Some(max_connections) = self.max_connections {
+                    pool_options = pool_options.max_connections(max_connections);
+                }
+                pool_options.connect_with(options).await?
+            }
+        };
+
+        let mut read_pools = Vec::with_capacity(self.read_replicas.len());
+        for url in &self.read_replicas {
+            let options = SqliteConnectOptions::from_str(url)?.read_only(true);
+            let mut pool_options = SqlitePoolOptions::new();
+            if let Some(max_connections) = self.max_connections {
+                pool_options = pool_options.max_connections(max_connections);
+            }
+            read_pools.push(pool_options.connect_with(options).await?);
+        }
+
+        if self.backfill {
+            self.observers = Vec::new();
+            self.outbox = false;
+            self.alerts = AlertThresholds::default();
+        }
+        TransactionService::from_builder(self, pool, read_pools).await
+    }
+}
@@ -0,0 +1,276 @@
+use super::{Result, TransactionError};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Faults a [`TransactionService`](super::TransactionService) injects while processing, set
+/// with [`TransactionServiceBuilder::chaos`](super::TransactionServiceBuilder::chaos). For
+/// testing that callers recover from failures, never for production use.
+///
+/// Every fault fails the call with [`TransactionError::Injected`]. Faults are drawn from a
+/// generator seeded with `seed`, so a run can be repeated as long as calls are made in the
+/// same order.
+#[derive(Debug, Clone)]
+pub struct ChaosConfig {
+    seed: u64,
+    error_rate: f64,
+    crash_rate: f64,
+    lost_ack_rate: f64,
+    max_commit_delay: Duration,
+}
+
+impl ChaosConfig {
+    /// A config that injects nothing until rates are set.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            error_rate: 0.0,
+            crash_rate: 0.0,
+            lost_ack_rate: 0.0,
+            max_commit_delay: Duration::ZERO,
+        }
+    }
+
+    /// The chance that applying a transaction fails like a database error. The database
+    /// transaction it was part of, and with it the rest of its batch, is rolled back.
+    pub fn error_rate(mut self, rate: f64) -> Self {
+        self.error_rate = rate;
+        self
+    }
+
+    /// The chance of crashing just before a commit, after every transaction in it was
+    /// applied. Nothing in the database transaction is kept.
+    pub fn crash_rate(mut self, rate: f64) -> Self {
+        self.crash_rate = rate;
+        self
+    }
+
+    /// The chance of failing just after a commit, as if the process died before the caller
+    /// heard back. The changes are kept.
+    pub fn lost_ack_rate(mut self, rate: f64) -> Self {
+        self.lost_ack_rate = rate;
+        self
+    }
+
+    /// Delays every commit by a random duration of up to `delay`.
+    pub fn max_commit_delay(mut self, delay: Duration) -> Self {
+        self.max_commit_delay = delay;
+        self
+    }
+}
+
+pub(super) struct Chaos {
+    config: ChaosConfig,
+    state: Mutex<u64>,
+}
+
+impl Chaos {
+    pub(super) fn new(config: ChaosConfig) -> Self {
+        // xorshift gets stuck on zero
+        let state = Mutex::new(config.seed | 1);
+        Self { config, state }
+    }
+
+    /// A uniformly distributed number in `[0, 1)`.
+    fn next(&self) -> f64 {
+        let mut state = self.state.lock().unwrap();
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        (*state >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn fault(&self, rate: f64, fault: &'static str) -> Result<()> {
+        if self.next() < rate {
+            tracing::debug!(fault, "Injecting fault");
+            return Err(TransactionError::Injected(fault));
+        }
+        Ok(())
+    }
+
+    pub(super) fn before_apply(&self) -> Result<()> {
+        self.fault(self.config.error_rate, "database error")
+    }
+
+    pub(super) async fn before_commit(&self) -> Result<()> {
+        if !self.config.max_commit_delay.is_zero() {
+            tokio::time::sleep(self.config.max_commit_delay.mul_f64(self.next())).await;
+        }
+        self.fault(self.config.crash_rate, "crash before commit")
+    }
+
+    pub(super) fn after_commit(&self) -> Result<()> {
+        self.fault(self.config.lost_ack_rate, "crash after commit")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChaosConfig;
+    use crate::{
+        Client, ClientId, Transaction, TransactionError, TransactionId, TransactionService,
+        TransactionType,
+    };
+    use rust_decimal::Decimal;
+    use std::time::Duration;
+
+    const BATCH_SIZE: usize = 10;
+
+    /// Rounds of a deposit, a second deposit, a withdrawal, a dispute of the first deposit and
+    /// a resolve of it, or a chargeback every seventh round when `chargebacks` is set.
+    fn transactions(count: TransactionId, chargebacks: bool) -> Vec<Transaction> {
+        (1..=count)
+            .map(|id| {
+                let round = id / 5;
+                let (transaction_type, tx, amount) = match id % 5 {
+                    0 | 1 => (
+                        TransactionType::Deposit,
+                        id,
+                        Some(Decimal::new(id as i64, 1)),
+                    ),
+                    2 => (TransactionType::Withdrawal, id, Some(Decimal::new(15, 0))),
+                    3 => (TransactionType::Dispute, id - 3, None),
+                    _ if chargebacks && round % 7 == 6 => {
+                        (TransactionType::Chargeback, id - 4, None)
+                    }
+                    _ => (TransactionType::Resolve, id - 4, None),
+                };
+                Transaction {
+                    id: tx,
+                    transaction_type,
+                    client_id: (round % 4) as ClientId,
+                    amount,
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                }
+            })
+            .collect()
+    }
+
+    async fn service(chaos: Option<ChaosConfig>) -> TransactionService {
+        let mut builder = TransactionService::builder().batch_size(BATCH_SIZE);
+        if let Some(chaos) = chaos {
+            builder = builder.chaos(chaos);
+        }
+        builder.build().await.unwrap()
+    }
+
+    async fn clients(svc: &TransactionService) -> Vec<Client> {
+        let mut clients = svc.get_clients_vec().await.unwrap();
+        clients.sort_by_key(|c| c.id);
+        clients
+    }
+
+    #[tokio::test]
+    async fn test_idempotent_replay() {
+        let transactions = transactions(400, true);
+        let batches: Vec<_> = transactions.chunks(BATCH_SIZE).collect();
+
+        let reference = service(None).await;
+        let mut expected = Vec::new();
+        for (i, batch) in batches.iter().enumerate() {
+            let key = format!("batch-{i}");
+            expected.push(
+                reference
+                    .process_transactions_once(&key, batch)
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        let svc = service(Some(
+            ChaosConfig::new(7)
+                .error_rate(0.02)
+                .crash_rate(0.2)
+                .lost_ack_rate(0.2)
+                .max_commit_delay(Duration::from_millis(1)),
+        ))
+        .await;
+        // Resume from the first batch that was not acknowledged, replaying batches that were
+        // committed without the caller hearing back
+        let mut checkpoint = 0;
+        let mut faults = 0;
+        while checkpoint < batches.len() {
+            let key = format!("batch-{checkpoint}");
+            match svc
+                .process_transactions_once(&key, batches[checkpoint])
+                .await
+            {
+                Ok(outcomes) => {
+                    assert_eq!(outcomes, expected[checkpoint]);
+                    checkpoint += 1;
+                }
+                Err(TransactionError::Injected(_)) => faults += 1,
+                Err(e) => panic!("{e}"),
+            }
+        }
+
+        assert!(faults > 10, "only {faults} faults were injected");
+        assert_eq!(clients(&svc).await, clients(&reference).await);
+    }
+
+    #[tokio::test]
+    async fn test_stream_crash_resume() {
+        let transactions = transactions(200, false);
+
+        // The ledger after each batch
+        let reference = service(None).await;
+        let mut snapshots = vec![Vec::new()];
+        for batch in transactions.chunks(BATCH_SIZE) {
+            for transaction in batch {
+                reference.process_transaction(transaction).await.unwrap();
+            }
+            snapshots.push(clients(&reference).await);
+        }
+
+        for seed in 0..8 {
+            let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+            let svc = TransactionService::builder()
+                .pool(pool.clone())
+                .batch_size(BATCH_SIZE)
+                .chaos(ChaosConfig::new(seed).crash_rate(0.1).lost_ack_rate(0.1))
+                .build()
+                .await
+                .unwrap();
+            let stream = futures::stream::iter(transactions.iter().cloned().map(Ok));
+            match svc.process_stream(stream).await {
+                Ok(_) => {}
+                Err(TransactionError::Injected(_)) => {}
+                Err(e) => panic!("{e}"),
+            }
+
+            // Every deposit is stored, so a batch was committed if its first deposit is
+            let mut checkpoint = 0;
+            while checkpoint < snapshots.len() - 1
+                && svc
+                    .get_transaction(transactions[checkpoint * BATCH_SIZE].id)
+                    .await
+                    .unwrap()
+                    .is_some()
+            {
+                checkpoint += 1;
+            }
+            assert_eq!(clients(&svc).await, snapshots[checkpoint], "seed {seed}");
+
+            let resumed = TransactionService::builder()
+                .pool(pool)
+                .batch_size(BATCH_SIZE)
+                .build()
+                .await
+                .unwrap();
+            let rest = futures::stream::iter(
+                transactions[checkpoint * BATCH_SIZE..]
+                    .iter()
+                    .cloned()
+                    .map(Ok),
+            );
+            resumed.process_stream(rest).await.unwrap();
+            assert_eq!(
+                clients(&resumed).await,
+                clients(&reference).await,
+                "seed {seed}"
+            );
+        }
+    }
+}
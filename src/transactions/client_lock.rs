@@ -0,0 +1,119 @@
+use super::ClientId;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex as StdMutex};
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+/// An async lock per client, held by a submission from before it is written until its
+/// observers are notified.
+///
+/// The database write itself still waits for the service's write lock, as sqlite has one
+/// writer, but the rest of a submission does not: a submission retrying after a transient
+/// error, or notifying its observers, only holds up later submissions for the same client.
+/// Those wait, so a client's submissions are applied and notified in the order they were made.
+#[derive(Debug, Default)]
+pub(super) struct ClientLocks {
+    /// The locks of the clients with a submission in progress, removed once none is.
+    locks: StdMutex<HashMap<ClientId, Arc<Mutex<()>>>>,
+}
+
+/// Held by a submission for a client until it is done, see [`ClientLocks`].
+pub(super) struct ClientGuard<'a> {
+    locks: &'a ClientLocks,
+    client_id: ClientId,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl ClientLocks {
+    /// Waits for the lock of `client_id`.
+    pub async fn lock(&self, client_id: ClientId) -> ClientGuard<'_> {
+        let lock = self
+            .locks
+            .lock()
+            .unwrap()
+            .entry(client_id)
+            .or_default()
+            .clone();
+        ClientGuard {
+            locks: self,
+            client_id,
+            guard: Some(lock.lock_owned().await),
+        }
+    }
+
+    /// Waits for the locks of every client in `client_ids`, taken in client id order so that
+    /// submissions for several clients can not wait for each other.
+    pub async fn lock_all(
+        &self,
+        client_ids: impl IntoIterator<Item = ClientId>,
+    ) -> Vec<ClientGuard<'_>> {
+        let mut client_ids: Vec<_> = client_ids.into_iter().collect();
+        client_ids.sort_unstable();
+        client_ids.dedup();
+        let mut guards = Vec::with_capacity(client_ids.len());
+        for client_id in client_ids {
+            guards.push(self.lock(client_id).await);
+        }
+        guards
+    }
+
+    /// The number of clients with a submission in progress or waiting.
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.locks.lock().unwrap().len()
+    }
+}
+
+impl Drop for ClientGuard<'_> {
+    fn drop(&mut self) {
+        let mut locks = self.locks.locks.lock().unwrap();
+        self.guard.take();
+        // Only the map's reference is left when no one holds or waits for the lock
+        if locks
+            .get(&self.client_id)
+            .is_some_and(|lock| Arc::strong_count(lock) == 1)
+        {
+            locks.remove(&self.client_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ClientLocks;
+    use std::sync::{Arc, Mutex as StdMutex};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_client_locks() {
+        let locks = Arc::new(ClientLocks::default());
+        let order = Arc::new(StdMutex::new(Vec::new()));
+
+        let held = locks.lock(1).await;
+        // Another client is not held up
+        tokio::time::timeout(Duration::from_secs(1), locks.lock(2))
+            .await
+            .expect("A different client waited");
+
+        // The same client waits until the first submission is done
+        let waiting = {
+            let (locks, order) = (locks.clone(), order.clone());
+            tokio::spawn(async move {
+                let _guard = locks.lock(1).await;
+                order.lock().unwrap().push("second");
+            })
+        };
+        tokio::task::yield_now().await;
+        order.lock().unwrap().push("first");
+        drop(held);
+        waiting.await.unwrap();
+        assert_eq!(*order.lock().unwrap(), ["first", "second"]);
+
+        // Several clients at once, in either order
+        let guards = locks.lock_all([3, 1, 3]).await;
+        assert_eq!(guards.len(), 2);
+        drop(guards);
+
+        // The locks of clients without a submission are dropped
+        assert_eq!(locks.len(), 0);
+    }
+}
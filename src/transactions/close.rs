@@ -0,0 +1,68 @@
+use super::{Client, ExpiredHoldAction, Result, Transaction, TransactionError};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use time::format_description::well_known::Iso8601;
+
+/// A business date closed with
+/// [`TransactionService::close_day`](super::TransactionService::close_day).
+#[derive(Debug, PartialEq)]
+pub struct DayClose {
+    pub business_date: time::Date,
+    /// When the date was closed, in milliseconds since the unix epoch, from the service's
+    /// [`Clock`](super::Clock).
+    pub closed_at: i64,
+    /// The checkpoint the date was closed at, the id of the last audit log record it covers.
+    /// The date covers the records after the checkpoint of the previous close.
+    pub checkpoint: i64,
+    /// Every client at the close, ordered by client id.
+    pub clients: Vec<Client>,
+    /// The changes made during the date, by type, ordered by type.
+    pub totals: Vec<TypeTotal>,
+}
+
+/// Every client's balances recorded by
+/// [`TransactionService::snapshot_balances`](super::TransactionService::snapshot_balances).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BalanceSnapshot {
+    /// In milliseconds since the unix epoch, from the service's [`Clock`](super::Clock).
+    pub taken_at: i64,
+    /// The id of the last audit log record the snapshot covers.
+    pub checkpoint: i64,
+    /// The number of clients recorded.
+    pub clients: u64,
+}
+
+/// The changes of one type made during a closed business date.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TypeTotal {
+    /// The [`TransactionType`](super::TransactionType) name, `adjustment`, or `expired_resolve`
+    /// and `expired_escalate` for the disputes expired by
+    /// [`TransactionService::expire_holds`](super::TransactionService::expire_holds).
+    #[serde(rename = "type")]
+    pub name: String,
+    /// The number of applied transactions or adjustments.
+    pub count: u64,
+    /// The sum of their amounts. Disputes, resolves and chargebacks count the amount of the
+    /// transaction they refer to.
+    pub amount: Decimal,
+}
+
+/// The parts of a recorded [`LedgerEvent`](super::LedgerEvent) counted in the totals.
+#[derive(Deserialize)]
+pub(super) struct ClosedEvent {
+    pub transaction: Option<Transaction>,
+    pub adjustment: Option<ClosedAdjustment>,
+    pub disputed: Option<Transaction>,
+    pub action: Option<ExpiredHoldAction>,
+}
+
+#[derive(Deserialize)]
+pub(super) struct ClosedAdjustment {
+    pub amount: Decimal,
+}
+
+/// Parses a business date as stored in the `DayCloses` table.
+pub(super) fn parse_business_date(date: &str) -> Result<time::Date> {
+    time::Date::parse(date, &Iso8601::DATE)
+        .map_err(|e| TransactionError::InvalidArgument(format!("Invalid business date: {}", e)))
+}
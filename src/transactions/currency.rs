@@ -0,0 +1,111 @@
+use rust_decimal::{Decimal, RoundingStrategy};
+
+/// An ISO 4217 currency, to write amounts for people with [`Currency::format`]. The ledger
+/// itself does not record a currency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Currency {
+    code: &'static str,
+    symbol: &'static str,
+    minor_units: u32,
+}
+
+const fn currency(code: &'static str, symbol: &'static str, minor_units: u32) -> Currency {
+    Currency {
+        code,
+        symbol,
+        minor_units,
+    }
+}
+
+const CURRENCIES: &[Currency] = &[
+    currency("AUD", "A$", 2),
+    currency("BHD", "BD", 3),
+    currency("CAD", "CA$", 2),
+    currency("CHF", "CHF", 2),
+    currency("CNY", "CN¥", 2),
+    currency("CZK", "Kč", 2),
+    currency("DKK", "kr.", 2),
+    currency("EUR", "€", 2),
+    currency("GBP", "£", 2),
+    currency("HUF", "Ft", 2),
+    currency("INR", "₹", 2),
+    currency("ISK", "kr", 0),
+    currency("JPY", "¥", 0),
+    currency("KRW", "₩", 0),
+    currency("KWD", "KD", 3),
+    currency("NOK", "kr", 2),
+    currency("NZD", "NZ$", 2),
+    currency("PLN", "zł", 2),
+    currency("SEK", "kr", 2),
+    currency("USD", "$", 2),
+];
+
+impl Currency {
+    /// The currency with an ISO 4217 code such as `EUR`, ignoring case.
+    pub fn from_code(code: &str) -> Option<Self> {
+        CURRENCIES
+            .iter()
+            .find(|c| c.code.eq_ignore_ascii_case(code))
+            .copied()
+    }
+
+    /// The ISO 4217 codes of the known currencies.
+    pub fn codes() -> impl Iterator<Item = &'static str> {
+        CURRENCIES.iter().map(|c| c.code)
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        self.symbol
+    }
+
+    /// The number of decimal places of the currency's minor unit, e.g. 2 for cents.
+    pub fn minor_units(&self) -> u32 {
+        self.minor_units
+    }
+
+    /// Writes an amount with exactly the currency's minor units, rounding half away from zero,
+    /// e.g. `1234.5` as `1234.50` for EUR and `1234.5` as `1235` for JPY.
+    pub fn format(&self, amount: Decimal) -> String {
+        self.round(amount).to_string()
+    }
+
+    /// Rounds an amount to exactly the currency's minor units, as [`Currency::format`] writes
+    /// it.
+    pub fn round(&self, amount: Decimal) -> Decimal {
+        let mut rounded =
+            amount.round_dp_with_strategy(self.minor_units, RoundingStrategy::MidpointAwayFromZero);
+        rounded.rescale(self.minor_units);
+        rounded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Currency;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_currency_format() {
+        let eur = Currency::from_code("eur").unwrap();
+        assert_eq!(
+            (eur.code(), eur.symbol(), eur.minor_units()),
+            ("EUR", "€", 2)
+        );
+        assert_eq!(eur.format(dec!(1234.5)), "1234.50");
+        assert_eq!(eur.format(dec!(1.0050)), "1.01");
+        assert_eq!(eur.format(dec!(-1.0050)), "-1.01");
+        assert_eq!(eur.format(dec!(0)), "0.00");
+
+        let jpy = Currency::from_code("JPY").unwrap();
+        assert_eq!(jpy.format(dec!(1234.5)), "1235");
+        let bhd = Currency::from_code("BHD").unwrap();
+        assert_eq!(bhd.format(dec!(1.23456)), "1.235");
+
+        assert_eq!(Currency::from_code("XYZ"), None);
+        assert!(Currency::codes().all(|code| Currency::from_code(code).is_some()));
+    }
+}
@@ -0,0 +1,73 @@
+use super::{Approval, ClientId, TransactionId};
+use std::io;
+use thiserror::Error;
+
+/// Errors returned by the transaction engine.
+///
+/// Errors about a client name it by `client`, its pseudonym when the service pseudonymizes
+/// client ids, so that their messages can be logged and returned to callers.
+#[derive(Debug, Error)]
+pub enum TransactionError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("failed to read transaction csv: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("failed to read transaction json: {0}")]
+    Json(#[from] serde_json::Error),
+    #[cfg(feature = "kafka")]
+    #[error("kafka error: {0}")]
+    Kafka(#[from] rdkafka::error::KafkaError),
+    #[cfg(feature = "scripting")]
+    #[error("script error: {0}")]
+    Script(String),
+    #[cfg(feature = "chaos")]
+    #[error("injected fault: {0}")]
+    Injected(&'static str),
+    #[error("transaction source error: {0}")]
+    Source(Box<dyn std::error::Error + Send + Sync>),
+    #[error("database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("invalid transaction {transaction_id}: {reason}")]
+    InvalidTransaction {
+        transaction_id: TransactionId,
+        reason: String,
+    },
+    #[error("client {client} is locked")]
+    ClientLocked { client_id: ClientId, client: String },
+    #[error("client {client} does not exist")]
+    ClientNotFound { client_id: ClientId, client: String },
+    #[error("client {client} has insufficient available funds")]
+    InsufficientFunds { client_id: ClientId, client: String },
+    #[error("the change would overflow the balances of client {client}")]
+    BalanceOverflow { client_id: ClientId, client: String },
+    #[error("idempotency key \"{key}\" was already used for a different request")]
+    IdempotencyKeyReused { key: String },
+    #[error("input \"{name}\" could not be verified: {reason}")]
+    UnverifiedInput { name: String, reason: String },
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+    #[error("incompatible database: {0}")]
+    IncompatibleDatabase(String),
+    /// The change was held as a pending [`Approval`] by the
+    /// [`ApprovalRules`](super::ApprovalRules), and is applied once a second operator approves
+    /// it. Names the client as the other client errors do.
+    #[error(
+        "the {} of client {} needs a second operator's approval, requested as approval {}",
+        .0.action.to_str(),
+        .1,
+        .0.id
+    )]
+    ApprovalRequired(Box<Approval>, String),
+}
+
+impl TransactionError {
+    pub(crate) fn invalid(transaction_id: TransactionId, reason: impl Into<String>) -> Self {
+        Self::InvalidTransaction {
+            transaction_id,
+            reason: reason.into(),
+        }
+    }
+}
+
+/// Result type used throughout the library.
+pub type Result<T, E = TransactionError> = std::result::Result<T, E>;
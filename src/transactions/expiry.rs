@@ -0,0 +1,70 @@
+use super::{Result, TransactionService};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// What happens to a dispute that has held its funds for longer than
+/// [`HoldExpiry::max_age`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExpiredHoldAction {
+    /// Resolve the dispute, releasing its funds back to the client's available funds.
+    #[default]
+    Resolve,
+    /// Keep the funds held and mark the dispute as escalated, notifying
+    /// [`EventObserver::on_hold_escalated`](super::EventObserver::on_hold_escalated) once.
+    Escalate,
+}
+
+impl ExpiredHoldAction {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Resolve => "resolve",
+            Self::Escalate => "escalate",
+        }
+    }
+}
+
+/// Limits how long disputes can hold a client's funds, applied by
+/// [`TransactionService::expire_holds`].
+///
+/// ```no_run
+/// # async fn run(svc: transaction_app::TransactionService) -> transaction_app::Result<()> {
+/// use std::time::Duration;
+/// use transaction_app::{ExpiredHoldAction, HoldExpiry};
+///
+/// let expiry = HoldExpiry::new(Duration::from_secs(90 * 24 * 60 * 60))
+///     .action(ExpiredHoldAction::Escalate);
+/// expiry.run(&svc, Duration::from_secs(60)).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HoldExpiry {
+    /// How long a dispute can be open before it expires.
+    pub max_age: Duration,
+    pub action: ExpiredHoldAction,
+}
+
+impl HoldExpiry {
+    /// Resolves disputes open for longer than `max_age`.
+    pub fn new(max_age: Duration) -> Self {
+        Self {
+            max_age,
+            action: ExpiredHoldAction::default(),
+        }
+    }
+
+    /// What to do with expired disputes. Defaults to [`ExpiredHoldAction::Resolve`].
+    pub fn action(mut self, action: ExpiredHoldAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    /// Expires disputes every `interval` until an error occurs.
+    pub async fn run(&self, svc: &TransactionService, interval: Duration) -> Result<()> {
+        loop {
+            svc.expire_holds(self).await?;
+            tokio::time::sleep(interval).await;
+        }
+    }
+}
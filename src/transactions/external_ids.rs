@@ -0,0 +1,150 @@
+use super::{ClientId, Result, TransactionError};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// A partner-specific string id and the client id it maps to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExternalId {
+    pub external_id: String,
+    pub client: ClientId,
+}
+
+/// Maps the string client ids of partner input files to client ids, for readers configured
+/// with [`TransactionReader::external_ids`](super::TransactionReader::external_ids) or
+/// [`JsonLinesReader::external_ids`](super::JsonLinesReader::external_ids).
+///
+/// An external id seen for the first time is given the client id after the highest one in
+/// use. Clones share their mappings, so a reader can resolve ids while the caller keeps a
+/// handle to store the new mappings with
+/// [`TransactionService::save_external_ids`](super::TransactionService::save_external_ids)
+/// once the input is processed. Load the stored mappings with
+/// [`TransactionService::get_external_ids`](super::TransactionService::get_external_ids).
+#[derive(Debug, Clone, Default)]
+pub struct ExternalIds(Arc<Mutex<Mappings>>);
+
+#[derive(Debug, Default)]
+struct Mappings {
+    ids: HashMap<String, ClientId>,
+    /// The highest client id in use.
+    last: ClientId,
+    /// The mappings created since the last call to [`ExternalIds::take_created`].
+    created: Vec<ExternalId>,
+}
+
+impl ExternalIds {
+    /// Mappings starting from client 1.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Existing mappings, with new external ids given client ids after `last_client`.
+    pub fn with_mappings(
+        mappings: impl IntoIterator<Item = ExternalId>,
+        last_client: ClientId,
+    ) -> Self {
+        let ids: HashMap<_, _> = mappings
+            .into_iter()
+            .map(|m| (m.external_id, m.client))
+            .collect();
+        let last = ids.values().copied().fold(last_client, ClientId::max);
+        Self(Arc::new(Mutex::new(Mappings {
+            ids,
+            last,
+            created: Vec::new(),
+        })))
+    }
+
+    /// The client id of an external id, if it is mapped.
+    pub fn get(&self, external_id: &str) -> Option<ClientId> {
+        self.0.lock().unwrap().ids.get(external_id).copied()
+    }
+
+    /// Every mapping, ordered by client id.
+    pub fn mappings(&self) -> Vec<ExternalId> {
+        let mut mappings: Vec<_> = self
+            .0
+            .lock()
+            .unwrap()
+            .ids
+            .iter()
+            .map(|(external_id, &client)| ExternalId {
+                external_id: external_id.clone(),
+                client,
+            })
+            .collect();
+        mappings.sort_by_key(|m| m.client);
+        mappings
+    }
+
+    /// The client id of an external id, mapping it to a new client id on first sight.
+    pub fn resolve(&self, external_id: &str) -> Result<ClientId> {
+        if external_id.is_empty() {
+            return Err(TransactionError::InvalidArgument(
+                "missing external client id".to_string(),
+            ));
+        }
+        let mut mappings = self.0.lock().unwrap();
+        if let Some(&client) = mappings.ids.get(external_id) {
+            return Ok(client);
+        }
+        let client = mappings.last.checked_add(1).ok_or_else(|| {
+            TransactionError::InvalidArgument(format!(
+                "no client id left for external id \"{}\"",
+                external_id
+            ))
+        })?;
+        tracing::debug!(external_id, client, "Mapping a new external client id");
+        mappings.last = client;
+        mappings.ids.insert(external_id.to_string(), client);
+        mappings.created.push(ExternalId {
+            external_id: external_id.to_string(),
+            client,
+        });
+        Ok(client)
+    }
+
+    /// Removes and returns the mappings created since the last call, in the order they were
+    /// created.
+    pub fn take_created(&self) -> Vec<ExternalId> {
+        std::mem::take(&mut self.0.lock().unwrap().created)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ExternalId, ExternalIds};
+
+    #[test]
+    fn test_external_ids() {
+        let mapping = |external_id: &str, client| ExternalId {
+            external_id: external_id.to_string(),
+            client,
+        };
+        let ids = ExternalIds::with_mappings([mapping("ACME-1", 3)], 2);
+        let reader = ids.clone();
+        assert_eq!(reader.resolve("ACME-1").unwrap(), 3);
+        assert_eq!(reader.resolve("ACME-9").unwrap(), 4);
+        assert_eq!(reader.resolve("ACME-2").unwrap(), 5);
+        assert_eq!(reader.resolve("ACME-9").unwrap(), 4);
+        assert!(reader.resolve("").is_err());
+
+        assert_eq!(ids.get("ACME-2"), Some(5));
+        assert_eq!(ids.get("ACME-3"), None);
+        assert_eq!(
+            ids.take_created(),
+            [mapping("ACME-9", 4), mapping("ACME-2", 5)]
+        );
+        assert_eq!(ids.take_created(), []);
+        assert_eq!(
+            ids.mappings(),
+            [
+                mapping("ACME-1", 3),
+                mapping("ACME-9", 4),
+                mapping("ACME-2", 5)
+            ]
+        );
+
+        assert_eq!(ExternalIds::new().resolve("ACME-1").unwrap(), 1);
+    }
+}
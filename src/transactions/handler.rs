@@ -0,0 +1,81 @@
+use super::{processor::Precision, Client, ClientId, Result, Transaction, TransactionService};
+use futures::future::BoxFuture;
+use sqlx::SqliteConnection;
+
+/// Applies transactions of a [`TransactionType::Custom`](super::TransactionType::Custom) type,
+/// registered with
+/// [`TransactionServiceBuilder::handler`](super::TransactionServiceBuilder::handler).
+///
+/// ```
+/// use futures::future::BoxFuture;
+/// use transaction_app::{Result, StorageHandle, Transaction, TransactionHandler};
+///
+/// /// Awards loyalty points, e.g. `loyalty, 1, 7, 25` in csv input.
+/// struct LoyaltyPoints;
+///
+/// impl TransactionHandler for LoyaltyPoints {
+///     fn schema(&self) -> &str {
+///         "CREATE TABLE IF NOT EXISTS LoyaltyPoints (client_id INTEGER PRIMARY KEY, points TEXT)"
+///     }
+///
+///     fn apply<'a>(
+///         &'a self,
+///         storage: &'a mut StorageHandle<'_>,
+///         transaction: &'a Transaction,
+///     ) -> BoxFuture<'a, Result<bool>> {
+///         Box::pin(async move {
+///             sqlx::query("INSERT OR REPLACE INTO LoyaltyPoints VALUES (?, ?)")
+///                 .bind(transaction.client_id)
+///                 .bind(transaction.amount.unwrap_or_default().to_string())
+///                 .execute(storage.connection())
+///                 .await?;
+///             Ok(true)
+///         })
+///     }
+/// }
+/// ```
+pub trait TransactionHandler: Send + Sync {
+    /// Statements run when the service is built, such as `CREATE TABLE IF NOT EXISTS` for the
+    /// tables the handler uses.
+    fn schema(&self) -> &str {
+        ""
+    }
+
+    /// Applies `transaction` within the database transaction of `storage`, returning whether
+    /// it changed anything.
+    ///
+    /// Returning an error fails the transaction like any other processing error, and rolls
+    /// back everything written in the database transaction. Transactions for locked clients
+    /// are ignored without calling the handler.
+    fn apply<'a>(
+        &'a self,
+        storage: &'a mut StorageHandle<'_>,
+        transaction: &'a Transaction,
+    ) -> BoxFuture<'a, Result<bool>>;
+}
+
+/// Access to the database transaction a [`TransactionHandler`] is applied in.
+pub struct StorageHandle<'c> {
+    connection: &'c mut SqliteConnection,
+    precision: Precision,
+}
+
+impl<'c> StorageHandle<'c> {
+    pub(super) fn new(connection: &'c mut SqliteConnection, precision: Precision) -> Self {
+        Self {
+            connection,
+            precision,
+        }
+    }
+
+    /// The connection, for running queries in the database transaction.
+    pub fn connection(&mut self) -> &mut SqliteConnection {
+        self.connection
+    }
+
+    /// Gets a client by id, as seen by the database transaction.
+    pub async fn client(&mut self, client_id: ClientId) -> Result<Option<Client>> {
+        let client = TransactionService::fetch_client(&mut *self.connection, client_id).await?;
+        Ok(client.map(|c| c.into_client(self.precision)))
+    }
+}
@@ -0,0 +1,150 @@
+use super::{audit, BalanceSnapshot, ClientId, Result};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use sqlx::sqlite::SqliteRow;
+use sqlx::{Column, Row, TypeInfo, ValueRef};
+
+/// The tables with rows about a client, and the query selecting the latest of them, see
+/// [`TransactionService::dump_client`](super::TransactionService::dump_client). Each query
+/// binds the client id, then the number of rows.
+pub(super) const CLIENT_TABLES: &[(&str, &str)] = &[
+    ("Clients", "SELECT * FROM Clients WHERE id = ? LIMIT ?"),
+    (
+        "Transactions",
+        "SELECT * FROM Transactions WHERE client_id = ? ORDER BY id DESC LIMIT ?",
+    ),
+    (
+        "Disputes",
+        "SELECT d.* FROM Disputes d JOIN Transactions t ON t.id = d.transaction_id
+        WHERE t.client_id = ? ORDER BY d.opened_at DESC LIMIT ?",
+    ),
+    (
+        "DisputeEvidence",
+        "SELECT e.* FROM DisputeEvidence e JOIN Transactions t ON t.id = e.transaction_id
+        WHERE t.client_id = ? ORDER BY e.id DESC LIMIT ?",
+    ),
+    (
+        "Settlements",
+        "SELECT s.* FROM Settlements s JOIN Transactions t ON t.id = s.transaction_id
+        WHERE t.client_id = ? ORDER BY s.release_at DESC LIMIT ?",
+    ),
+    (
+        "Voids",
+        "SELECT * FROM Voids WHERE client_id = ? ORDER BY voided_at DESC LIMIT ?",
+    ),
+    (
+        "Annotations",
+        "SELECT a.* FROM Annotations a JOIN Transactions t ON t.id = a.transaction_id
+        WHERE t.client_id = ? ORDER BY a.id DESC LIMIT ?",
+    ),
+    (
+        "Adjustments",
+        "SELECT * FROM Adjustments WHERE client_id = ? ORDER BY id DESC LIMIT ?",
+    ),
+    (
+        "Approvals",
+        "SELECT * FROM Approvals WHERE client_id = ? ORDER BY id DESC LIMIT ?",
+    ),
+    (
+        "ClientTags",
+        "SELECT * FROM ClientTags WHERE client_id = ? ORDER BY tag LIMIT ?",
+    ),
+    (
+        "ClientRisks",
+        "SELECT * FROM ClientRisks WHERE client_id = ? LIMIT ?",
+    ),
+    (
+        "ExternalIds",
+        "SELECT * FROM ExternalIds WHERE client_id = ? LIMIT ?",
+    ),
+    (
+        "BalanceSnapshots",
+        "SELECT * FROM BalanceSnapshots WHERE client_id = ? ORDER BY checkpoint DESC LIMIT ?",
+    ),
+    (
+        "Erasures",
+        "SELECT * FROM Erasures WHERE client_id = ? ORDER BY id DESC LIMIT ?",
+    ),
+    (
+        "Outbox",
+        "SELECT * FROM Outbox WHERE client_id = ? ORDER BY id DESC LIMIT ?",
+    ),
+];
+
+/// A record of the audit log, see
+/// [`TransactionService::audit_records`](super::TransactionService::audit_records).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuditRecord {
+    pub id: i64,
+    /// In milliseconds since the unix epoch, from the service's [`Clock`](super::Clock).
+    pub created_at: i64,
+    /// The recorded [`LedgerEvent`](super::LedgerEvent).
+    pub event: Value,
+    pub hash: String,
+    /// Whether the event was redacted by
+    /// [`TransactionService::forget_client`](super::TransactionService::forget_client).
+    pub redacted: bool,
+}
+
+/// The rows stored about a client, as they are in the database, see
+/// [`TransactionService::dump_client`](super::TransactionService::dump_client).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ClientDump {
+    pub client_id: ClientId,
+    /// The latest rows of each table, by table name, newest first. Amounts are in the
+    /// database's fixed point units, and timestamps in milliseconds since the unix epoch.
+    pub tables: Map<String, Value>,
+}
+
+/// Where the ledger's checkpoints are, see
+/// [`TransactionService::checkpoints`](super::TransactionService::checkpoints).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Checkpoints {
+    /// The id of the last audit log record, 0 if there is none.
+    pub audit_head: i64,
+    /// The closed business dates, the latest first.
+    pub day_closes: Vec<DayCheckpoint>,
+    /// The balance snapshots, the latest first.
+    pub balance_snapshots: Vec<BalanceSnapshot>,
+    /// The offsets committed by each consumer of a partitioned source.
+    pub source_offsets: Vec<ConsumerOffset>,
+}
+
+/// A closed business date, without its balances.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, sqlx::FromRow)]
+pub struct DayCheckpoint {
+    /// As `YYYY-MM-DD`.
+    pub business_date: String,
+    pub closed_at: i64,
+    pub checkpoint: i64,
+}
+
+/// The next offset of a partition a consumer will process, see
+/// [`TransactionService::process_with_offsets`](super::TransactionService::process_with_offsets).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, sqlx::FromRow)]
+pub struct ConsumerOffset {
+    pub consumer: String,
+    pub partition: i32,
+    pub offset: i64,
+}
+
+/// A row as a json object of its columns. Blobs are hex encoded.
+pub(super) fn row_to_json(row: &SqliteRow) -> Result<Value> {
+    let mut object = Map::new();
+    for column in row.columns() {
+        let i = column.ordinal();
+        let raw = row.try_get_raw(i)?;
+        let value = if raw.is_null() {
+            Value::Null
+        } else {
+            match raw.type_info().name() {
+                "INTEGER" | "BIGINT" | "BOOLEAN" => row.try_get::<i64, _>(i)?.into(),
+                "REAL" => row.try_get::<f64, _>(i)?.into(),
+                "BLOB" => audit::hex(&row.try_get::<Vec<u8>, _>(i)?).into(),
+                _ => row.try_get::<String, _>(i)?.into(),
+            }
+        };
+        object.insert(column.name().to_string(), value);
+    }
+    Ok(Value::Object(object))
+}
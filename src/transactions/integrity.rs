@@ -0,0 +1,285 @@
+use super::{Result, TransactionError};
+use minisign_verify::{PublicKey, Signature};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+
+/// The extension of the detached signature of an input file, e.g. `input.csv.minisig`.
+pub const SIGNATURE_EXTENSION: &str = "minisig";
+
+/// What [`InputVerifier`] does with files that are neither signed nor listed in the checksum
+/// manifest.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnsignedInputPolicy {
+    /// Fail with [`TransactionError::UnverifiedInput`].
+    #[default]
+    Reject,
+    /// Log a warning and process the file anyway.
+    Warn,
+}
+
+/// Checks input files before they are processed, against detached minisign (ed25519)
+/// signatures made by one of the trusted public keys, and a sha256 checksum manifest.
+///
+/// A file is verified if its signature, `<file>.minisig`, is valid or its checksum matches the
+/// manifest. A file with an invalid signature or a checksum that does not match is always
+/// rejected, a file with neither is handled according to the [`UnsignedInputPolicy`].
+///
+/// ```no_run
+/// # fn run() -> transaction_app::Result<()> {
+/// use transaction_app::InputVerifier;
+///
+/// let verifier = InputVerifier::new()
+///     .public_key_file("partner.pub")?
+///     .checksums_file("SHA256SUMS")?;
+/// let contents = verifier.read("partner.csv")?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct InputVerifier {
+    public_keys: Vec<PublicKey>,
+    /// File name to lowercase hex sha256.
+    checksums: HashMap<String, String>,
+    unsigned: UnsignedInputPolicy,
+}
+
+impl InputVerifier {
+    /// A verifier that trusts nothing, rejecting every file until keys or checksums are added.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trusts signatures made with a minisign public key, either the base64 key or the
+    /// contents of a `.pub` file. Can be called multiple times to trust several keys.
+    pub fn public_key(mut self, key: &str) -> Result<Self> {
+        let key = key.trim();
+        let key = if key.starts_with("untrusted comment:") {
+            PublicKey::decode(key)
+        } else {
+            PublicKey::from_base64(key)
+        }
+        .map_err(|e| TransactionError::InvalidArgument(format!("invalid public key: {}", e)))?;
+        self.public_keys.push(key);
+        Ok(self)
+    }
+
+    /// Trusts signatures made with the minisign public key in `path`.
+    pub fn public_key_file(self, path: impl AsRef<Path>) -> Result<Self> {
+        self.public_key(&std::fs::read_to_string(path)?)
+    }
+
+    /// Adds the checksums of a manifest in the `sha256sum` format: a hex sha256 and a file
+    /// name on each line. Files are looked up by their file name, without the directory.
+    pub fn checksums(mut self, manifest: &str) -> Result<Self> {
+        for (i, line) in manifest.lines().enumerate() {
+            let line = line.trim_end();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || {
+                TransactionError::InvalidArgument(format!(
+                    "invalid checksum manifest line {}",
+                    i + 1
+                ))
+            };
+            let (checksum, name) = line.split_once(char::is_whitespace).ok_or_else(invalid)?;
+            // `sha256sum --binary` marks names with a `*`
+            let name = name.trim_start().trim_start_matches('*');
+            if checksum.len() != 64 || !checksum.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(invalid());
+            }
+            let name = Path::new(name)
+                .file_name()
+                .ok_or_else(invalid)?
+                .to_string_lossy()
+                .into_owned();
+            self.checksums.insert(name, checksum.to_ascii_lowercase());
+        }
+        Ok(self)
+    }
+
+    /// Adds the checksums of the manifest in `path`, see [`InputVerifier::checksums`].
+    pub fn checksums_file(self, path: impl AsRef<Path>) -> Result<Self> {
+        self.checksums(&std::fs::read_to_string(path)?)
+    }
+
+    /// What to do with files that are neither signed nor in the manifest. Defaults to
+    /// [`UnsignedInputPolicy::Reject`].
+    pub fn unsigned(mut self, policy: UnsignedInputPolicy) -> Self {
+        self.unsigned = policy;
+        self
+    }
+
+    /// Reads and verifies the file at `path`, returning its contents. The contents are
+    /// verified after they are read, so the file can not be swapped in between.
+    pub fn read(&self, path: impl AsRef<Path>) -> Result<Vec<u8>> {
+        let path = path.as_ref();
+        let contents = std::fs::read(path)?;
+        let mut signature_path = path.as_os_str().to_owned();
+        signature_path.push(".");
+        signature_path.push(SIGNATURE_EXTENSION);
+        let signature = match std::fs::read_to_string(signature_path) {
+            Ok(signature) => Some(signature),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        self.verify(&name, &contents, signature.as_deref())?;
+        Ok(contents)
+    }
+
+    /// Verifies the contents of the file `name` against its detached `signature`, if it has
+    /// one, and the manifest.
+    pub fn verify(&self, name: &str, contents: &[u8], signature: Option<&str>) -> Result<()> {
+        let unverified = |reason: &str| TransactionError::UnverifiedInput {
+            name: name.to_string(),
+            reason: reason.to_string(),
+        };
+
+        let mut verified = false;
+        if let Some(signature) = signature {
+            let signature = Signature::decode(signature)
+                .map_err(|_| unverified("the signature is malformed"))?;
+            if self.public_keys.is_empty() {
+                return Err(unverified(
+                    "no public key is trusted to check the signature",
+                ));
+            }
+            if !self
+                .public_keys
+                .iter()
+                .any(|key| key.verify(contents, &signature, true).is_ok())
+            {
+                return Err(unverified(
+                    "the signature does not match the contents or a trusted key",
+                ));
+            }
+            verified = true;
+        }
+        if let Some(expected) = self.checksums.get(name) {
+            let checksum =
+                Sha256::digest(contents)
+                    .iter()
+                    .fold(String::new(), |mut checksum, byte| {
+                        let _ = write!(checksum, "{:02x}", byte);
+                        checksum
+                    });
+            if checksum != *expected {
+                return Err(unverified("the checksum does not match the manifest"));
+            }
+            verified = true;
+        }
+
+        if !verified {
+            match self.unsigned {
+                UnsignedInputPolicy::Reject => {
+                    return Err(unverified("the file is not signed or in the manifest"))
+                }
+                UnsignedInputPolicy::Warn => {
+                    tracing::warn!(name, "Processing an input file that is not signed")
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InputVerifier, UnsignedInputPolicy};
+    use crate::TransactionError;
+
+    /// From the minisign-verify test vectors, a signature of `test`.
+    const PUBLIC_KEY: &str = "untrusted comment: minisign public key E7620F1842B4E81F
+RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3";
+    const SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RWQf6LRCGA9i59SLOFxz6NxvASXDJeRtuZykwQepbDEGt87ig1BNpWaVWuNrm73YiIiJbq71Wi+dP9eKL8OC351vwIasSSbXxwA=
+trusted comment: timestamp:1555779966\tfile:test
+QtKMXWyYcwdpZAlPF7tE2ENJkRd1ujvKjlj1m9RtHTBnZPa5WKU5uWRs5GoP5M/VqE81QFuMKI5k/SfNQUaOAA==";
+    const OTHER_KEY: &str = "RWTgzhJKFYq3tnEiGDbT8/KBVJeS+AFpDt4Po0avrQCaF4yHUEZhEt5u";
+    /// The sha256 of `test`.
+    const CHECKSUM: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+
+    fn reason(result: crate::Result<()>) -> String {
+        match result {
+            Err(TransactionError::UnverifiedInput { reason, .. }) => reason,
+            other => panic!("{other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_signature() {
+        let verifier = InputVerifier::new().public_key(PUBLIC_KEY).unwrap();
+        verifier.verify("test", b"test", Some(SIGNATURE)).unwrap();
+        assert_eq!(
+            reason(verifier.verify("test", b"Test", Some(SIGNATURE))),
+            "the signature does not match the contents or a trusted key"
+        );
+        assert_eq!(
+            reason(verifier.verify("test", b"test", None)),
+            "the file is not signed or in the manifest"
+        );
+        assert_eq!(
+            reason(verifier.verify("test", b"test", Some("garbage"))),
+            "the signature is malformed"
+        );
+
+        let other = InputVerifier::new().public_key(OTHER_KEY).unwrap();
+        assert!(other.verify("test", b"test", Some(SIGNATURE)).is_err());
+        other
+            .public_key(PUBLIC_KEY)
+            .unwrap()
+            .verify("test", b"test", Some(SIGNATURE))
+            .unwrap();
+        assert!(InputVerifier::new().public_key("not a key").is_err());
+    }
+
+    #[test]
+    fn test_checksums() {
+        let verifier = InputVerifier::new()
+            .checksums(&format!(
+                "{CHECKSUM}  partner/test.csv\n\n{CHECKSUM} *other.csv\n"
+            ))
+            .unwrap();
+        verifier.verify("test.csv", b"test", None).unwrap();
+        verifier.verify("other.csv", b"test", None).unwrap();
+        assert_eq!(
+            reason(verifier.verify("test.csv", b"test\n", None)),
+            "the checksum does not match the manifest"
+        );
+        assert!(verifier.verify("unknown.csv", b"test", None).is_err());
+        assert!(InputVerifier::new().checksums("abc test.csv").is_err());
+
+        // A valid signature does not excuse a mismatched checksum
+        let signed = verifier.public_key(PUBLIC_KEY).unwrap();
+        assert!(signed.verify("test.csv", b"Test", Some(SIGNATURE)).is_err());
+    }
+
+    #[test]
+    fn test_unsigned_policy() {
+        let warn = InputVerifier::new().unsigned(UnsignedInputPolicy::Warn);
+        warn.verify("test.csv", b"test", None).unwrap();
+        // Tampered files are rejected regardless
+        let warn = warn.public_key(PUBLIC_KEY).unwrap();
+        assert!(warn.verify("test", b"Test", Some(SIGNATURE)).is_err());
+    }
+
+    #[test]
+    fn test_read() {
+        let dir = std::env::temp_dir().join(format!("input-verifier-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("test");
+        std::fs::write(&input, "test").unwrap();
+        let verifier = InputVerifier::new().public_key(PUBLIC_KEY).unwrap();
+        assert!(verifier.read(&input).is_err());
+
+        std::fs::write(dir.join("test.minisig"), SIGNATURE).unwrap();
+        assert_eq!(verifier.read(&input).unwrap(), b"test");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,65 @@
+use super::{ClientId, TransactionId};
+use serde::Serialize;
+use std::fmt;
+
+/// A broken invariant of a stored ledger, see
+/// [`TransactionService::check_ledger`](super::TransactionService::check_ledger).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Violation {
+    /// The name of the invariant, e.g. `held_funds`.
+    pub invariant: &'static str,
+    /// The client the violation is about, if any.
+    #[serde(rename = "client")]
+    pub client_id: Option<ClientId>,
+    /// The transaction the violation is about, if any.
+    #[serde(rename = "tx")]
+    pub transaction_id: Option<TransactionId>,
+    /// What is wrong, e.g. `locked without a chargeback`.
+    pub detail: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.invariant)?;
+        if let Some(client_id) = self.client_id {
+            write!(f, " client {}", client_id)?;
+        }
+        if let Some(transaction_id) = self.transaction_id {
+            write!(f, " tx {}", transaction_id)?;
+        }
+        write!(f, ": {}", self.detail)
+    }
+}
+
+/// The result of [`TransactionService::check_ledger`](super::TransactionService::check_ledger).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LedgerCheck {
+    /// The number of clients checked.
+    pub clients: u64,
+    /// The number of stored transactions checked.
+    pub transactions: u64,
+    /// Every broken invariant, empty if the ledger is consistent.
+    pub violations: Vec<Violation>,
+}
+
+impl LedgerCheck {
+    pub fn is_consistent(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+impl fmt::Display for LedgerCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "clients:      {}", self.clients)?;
+        writeln!(f, "transactions: {}", self.transactions)?;
+        if self.violations.is_empty() {
+            write!(f, "invariants:   ok")
+        } else {
+            write!(f, "invariants:   {} violated", self.violations.len())?;
+            for violation in &self.violations {
+                write!(f, "\n  {}", violation)?;
+            }
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use super::{
+    reader::parse_json_transaction, Result, SourceOffset, Transaction, TransactionError,
+    TransactionService, TransactionSource,
+};
+use futures::stream::{BoxStream, StreamExt};
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::message::{Header, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::util::Timeout;
+use rdkafka::{Message, Offset, TopicPartitionList};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Consumes json encoded transactions (see [`JsonLinesReader`](super::JsonLinesReader)) from a
+/// kafka topic. The stream does not end on its own.
+pub struct KafkaSource {
+    consumer: StreamConsumer,
+}
+
+/// How long to wait for the partitions of a topic.
+const METADATA_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl KafkaSource {
+    /// Subscribes to `topic` on the comma separated `brokers` as part of `group_id`.
+    pub fn new(brokers: &str, group_id: &str, topic: &str) -> Result<Self> {
+        let consumer: StreamConsumer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("group.id", group_id)
+            .set("auto.offset.reset", "earliest")
+            .create()?;
+        consumer.subscribe(&[topic])?;
+        Ok(Self { consumer })
+    }
+
+    /// Consumes every partition of `topic` on the comma separated `brokers` from after the
+    /// `offsets` stored by
+    /// [`TransactionService::process_with_offsets`](super::TransactionService::process_with_offsets),
+    /// and from the beginning of partitions without one. Nothing is committed to kafka, so the
+    /// ledger is the only record of what was consumed. Partitions added to the topic later are
+    /// not consumed until the source is created again.
+    pub fn from_offsets(brokers: &str, topic: &str, offsets: &BTreeMap<i32, i64>) -> Result<Self> {
+        let consumer: StreamConsumer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("group.id", env!("CARGO_PKG_NAME"))
+            .set("enable.auto.commit", "false")
+            .create()?;
+        let metadata = consumer.fetch_metadata(Some(topic), Timeout::After(METADATA_TIMEOUT))?;
+        let partitions = (metadata.topics().iter())
+            .find(|t| t.name() == topic)
+            .map(|t| t.partitions())
+            .filter(|partitions| !partitions.is_empty())
+            .ok_or_else(|| {
+                TransactionError::InvalidArgument(format!(
+                    "Kafka topic {} has no partitions",
+                    topic
+                ))
+            })?;
+        let mut assignment = TopicPartitionList::new();
+        for partition in partitions {
+            let offset = match offsets.get(&partition.id()) {
+                Some(offset) => Offset::Offset(offset + 1),
+                None => Offset::Beginning,
+            };
+            assignment.add_partition_offset(topic, partition.id(), offset)?;
+        }
+        consumer.assign(&assignment)?;
+        Ok(Self { consumer })
+    }
+
+    /// Streams the transactions with the partition and offset of their message, for
+    /// [`TransactionService::process_with_offsets`](super::TransactionService::process_with_offsets).
+    pub fn stream_with_offsets(&mut self) -> BoxStream<'_, Result<(SourceOffset, Transaction)>> {
+        self.consumer
+            .stream()
+            .map(|message| {
+                let message = message?;
+                let offset = SourceOffset {
+                    partition: message.partition(),
+                    offset: message.offset(),
+                };
+                let transaction = parse_json_transaction(message.payload().unwrap_or_default())?;
+                Ok((offset, transaction))
+            })
+            .boxed()
+    }
+}
+
+impl TransactionSource for KafkaSource {
+    fn stream(&mut self) -> BoxStream<'_, Result<Transaction>> {
+        self.consumer
+            .stream()
+            .map(|message| parse_json_transaction(message?.payload().unwrap_or_default()))
+            .boxed()
+    }
+}
+
+/// Ships the [`TransactionService`] outbox (see
+/// [`TransactionServiceBuilder::outbox`](super::TransactionServiceBuilder::outbox)) to a kafka
+/// topic.
+///
+/// Events are keyed by client id, so the events of a client stay in order on one partition,
+/// and carry their outbox id in the `outbox-id` header. Events are removed from the outbox
+/// once kafka acknowledged them. If the relay stops between the two, the events are shipped
+/// again on restart, so consumers should skip outbox ids they have already seen.
+pub struct KafkaOutboxRelay {
+    producer: FutureProducer,
+    topic: String,
+}
+
+impl KafkaOutboxRelay {
+    /// The number of events shipped at once.
+    const BATCH_SIZE: u32 = 500;
+
+    /// Produces to `topic` on the comma separated `brokers`.
+    pub fn new(brokers: &str, topic: &str) -> Result<Self> {
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("enable.idempotence", "true")
+            .create()?;
+        Ok(Self {
+            producer,
+            topic: topic.to_string(),
+        })
+    }
+
+    /// Ships a batch of events, returning how many were shipped.
+    pub async fn ship(&self, svc: &TransactionService) -> Result<usize> {
+        let events = svc.outbox_events(Self::BATCH_SIZE).await?;
+        let Some(last) = events.last() else {
+            return Ok(0);
+        };
+
+        // The producer is idempotent, so the events are written in the order they are sent
+        let deliveries = events.iter().map(|event| {
+            let key = event.client_id.to_string();
+            let id = event.id.to_string();
+            async move {
+                let record = FutureRecord::to(&self.topic)
+                    .key(&key)
+                    .payload(&event.payload)
+                    .timestamp(event.created_at)
+                    .headers(OwnedHeaders::new().insert(Header {
+                        key: "outbox-id",
+                        value: Some(&id),
+                    }));
+                self.producer
+                    .send(record, Timeout::Never)
+                    .await
+                    .map_err(|(e, _)| e)
+            }
+        });
+        futures::future::try_join_all(deliveries).await?;
+
+        svc.remove_outbox_events(last.id).await?;
+        Ok(events.len())
+    }
+
+    /// Ships events until an error occurs, checking for new events every `poll_interval`
+    /// once the outbox is empty.
+    pub async fn run(&self, svc: &TransactionService, poll_interval: Duration) -> Result<()> {
+        loop {
+            if self.ship(svc).await? == 0 {
+                tokio::time::sleep(poll_interval).await;
+            }
+        }
+    }
+}
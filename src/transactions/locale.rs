@@ -0,0 +1,262 @@
+use rust_decimal::Decimal;
+
+/// A language and region to write reports for people in: the column titles, the number
+/// format and the csv delimiter. Reports read by programs should not be localized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportLocale {
+    /// English, `1,234.56`.
+    En,
+    /// German, `1.234,56`.
+    De,
+    /// French, `1 234,56`.
+    Fr,
+    /// Spanish, `1.234,56`.
+    Es,
+    /// Italian, `1.234,56`.
+    It,
+    /// Dutch, `1.234,56`.
+    Nl,
+}
+
+/// The titles of the report columns, in the order of the locales.
+const COLUMNS: &[(&str, [&str; 6])] = &[
+    (
+        "client",
+        ["Client", "Kunde", "Client", "Cliente", "Cliente", "Klant"],
+    ),
+    (
+        "available",
+        [
+            "Available",
+            "Verfügbar",
+            "Disponible",
+            "Disponible",
+            "Disponibile",
+            "Beschikbaar",
+        ],
+    ),
+    (
+        "held",
+        [
+            "Held",
+            "Einbehalten",
+            "Bloqué",
+            "Retenido",
+            "Trattenuto",
+            "Vastgehouden",
+        ],
+    ),
+    (
+        "total",
+        ["Total", "Gesamt", "Total", "Total", "Totale", "Totaal"],
+    ),
+    (
+        "locked",
+        [
+            "Locked",
+            "Gesperrt",
+            "Verrouillé",
+            "Bloqueado",
+            "Bloccato",
+            "Geblokkeerd",
+        ],
+    ),
+    (
+        "deposits",
+        [
+            "Deposits",
+            "Einzahlungen",
+            "Dépôts",
+            "Depósitos",
+            "Depositi",
+            "Stortingen",
+        ],
+    ),
+    (
+        "deposited",
+        [
+            "Deposited",
+            "Eingezahlt",
+            "Déposé",
+            "Depositado",
+            "Depositato",
+            "Gestort",
+        ],
+    ),
+    (
+        "withdrawals",
+        [
+            "Withdrawals",
+            "Auszahlungen",
+            "Retraits",
+            "Retiros",
+            "Prelievi",
+            "Opnames",
+        ],
+    ),
+    (
+        "withdrawn",
+        [
+            "Withdrawn",
+            "Ausgezahlt",
+            "Retiré",
+            "Retirado",
+            "Prelevato",
+            "Opgenomen",
+        ],
+    ),
+    (
+        "disputes",
+        [
+            "Disputes",
+            "Reklamationen",
+            "Contestations",
+            "Disputas",
+            "Contestazioni",
+            "Geschillen",
+        ],
+    ),
+    (
+        "chargebacks",
+        [
+            "Chargebacks",
+            "Rückbuchungen",
+            "Rétrofacturations",
+            "Contracargos",
+            "Storni",
+            "Terugboekingen",
+        ],
+    ),
+    (
+        "risk_score",
+        [
+            "Risk score",
+            "Risikowert",
+            "Score de risque",
+            "Puntuación de riesgo",
+            "Punteggio di rischio",
+            "Risicoscore",
+        ],
+    ),
+    (
+        "currency",
+        [
+            "Currency", "Währung", "Devise", "Moneda", "Valuta", "Valuta",
+        ],
+    ),
+    (
+        "group",
+        ["Group", "Gruppe", "Groupe", "Grupo", "Gruppo", "Groep"],
+    ),
+    (
+        "count",
+        ["Count", "Anzahl", "Nombre", "Cantidad", "Numero", "Aantal"],
+    ),
+    (
+        "amount",
+        [
+            "Amount", "Betrag", "Montant", "Importe", "Importo", "Bedrag",
+        ],
+    ),
+];
+
+const LOCALES: &[(&str, ReportLocale)] = &[
+    ("en", ReportLocale::En),
+    ("de", ReportLocale::De),
+    ("fr", ReportLocale::Fr),
+    ("es", ReportLocale::Es),
+    ("it", ReportLocale::It),
+    ("nl", ReportLocale::Nl),
+];
+
+impl ReportLocale {
+    /// The locale with a language tag such as `de`, ignoring case and any region, e.g. `de-AT`.
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        let language = tag.split(['-', '_']).next()?;
+        LOCALES
+            .iter()
+            .find(|(t, _)| t.eq_ignore_ascii_case(language))
+            .map(|(_, locale)| *locale)
+    }
+
+    /// The language tags of the known locales.
+    pub fn tags() -> impl Iterator<Item = &'static str> {
+        LOCALES.iter().map(|(tag, _)| *tag)
+    }
+
+    /// The title of the report column `name`, e.g. `Verfügbar` for `available` in German.
+    /// Columns without a translation keep their name.
+    pub fn column(self, name: &str) -> &str {
+        COLUMNS
+            .iter()
+            .find(|(column, _)| *column == name)
+            .map_or(name, |(_, titles)| titles[self as usize])
+    }
+
+    /// The decimal separator and the separator of the groups of thousands.
+    pub fn separators(self) -> (char, char) {
+        match self {
+            Self::En => ('.', ','),
+            Self::Fr => (',', ' '),
+            Self::De | Self::Es | Self::It | Self::Nl => (',', '.'),
+        }
+    }
+
+    /// The csv delimiter spreadsheets expect in the locale: `;` where `,` is the decimal
+    /// separator.
+    pub fn delimiter(self) -> u8 {
+        match self.separators() {
+            (',', _) => b';',
+            _ => b',',
+        }
+    }
+
+    /// Writes an amount with the locale's separators, keeping its decimal places, e.g.
+    /// `-1234.50` as `-1.234,50` in German.
+    pub fn format(self, amount: Decimal) -> String {
+        let (decimal, grouping) = self.separators();
+        let plain = amount.abs().to_string();
+        let (integer, fraction) = plain.split_once('.').unwrap_or((&plain, ""));
+        let mut formatted = String::with_capacity(plain.len() * 4 / 3 + 2);
+        if amount.is_sign_negative() && !amount.is_zero() {
+            formatted.push('-');
+        }
+        for (i, digit) in integer.chars().enumerate() {
+            if i > 0 && (integer.len() - i) % 3 == 0 {
+                formatted.push(grouping);
+            }
+            formatted.push(digit);
+        }
+        if !fraction.is_empty() {
+            formatted.push(decimal);
+            formatted.push_str(fraction);
+        }
+        formatted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReportLocale;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_report_locale() {
+        let de = ReportLocale::from_tag("de-AT").unwrap();
+        assert_eq!(de, ReportLocale::De);
+        assert_eq!(de.format(dec!(-1234567.50)), "-1.234.567,50");
+        assert_eq!(de.format(dec!(123)), "123");
+        assert_eq!(de.format(dec!(0.5)), "0,5");
+        assert_eq!(de.column("available"), "Verfügbar");
+        assert_eq!(de.column("report_version"), "report_version");
+        assert_eq!(de.delimiter(), b';');
+
+        let en = ReportLocale::from_tag("EN").unwrap();
+        assert_eq!(en.format(dec!(1234.5)), "1,234.5");
+        assert_eq!(en.delimiter(), b',');
+        assert_eq!(ReportLocale::Fr.format(dec!(-1000)), "-1 000");
+
+        assert_eq!(ReportLocale::from_tag("pt"), None);
+        assert!(ReportLocale::tags().all(|tag| ReportLocale::from_tag(tag).is_some()));
+    }
+}
@@ -0,0 +1,39 @@
+use std::fmt;
+
+/// The result of [`TransactionService::maintain`](super::TransactionService::maintain).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaintenanceReport {
+    /// The problems `PRAGMA integrity_check` found, empty if there were none.
+    pub problems: Vec<String>,
+    /// The size of the database before maintenance, in bytes.
+    pub size_before: u64,
+    /// The size of the database after maintenance, in bytes. `None` when it was not vacuumed
+    /// because of integrity problems.
+    pub size_after: Option<u64>,
+}
+
+impl MaintenanceReport {
+    pub fn is_healthy(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl fmt::Display for MaintenanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.size_after {
+            Some(size_after) => {
+                writeln!(f, "size:      {} -> {} bytes", self.size_before, size_after)?
+            }
+            None => writeln!(f, "size:      {} bytes, not vacuumed", self.size_before)?,
+        }
+        if self.problems.is_empty() {
+            write!(f, "integrity: ok")
+        } else {
+            write!(f, "integrity: {} problems", self.problems.len())?;
+            for problem in &self.problems {
+                write!(f, "\n  {}", problem)?;
+            }
+            Ok(())
+        }
+    }
+}
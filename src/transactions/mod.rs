@@ -1,65 +1,475 @@
+mod alert;
+mod approval;
+mod audit;
+mod blocking;
+mod builder;
+#[cfg(feature = "chaos")]
+mod chaos;
+mod client_lock;
+mod clock;
+mod close;
+mod currency;
+#[cfg(feature = "email")]
+pub mod email;
+mod error;
+mod expiry;
+mod external_ids;
+mod handler;
+mod inspect;
+mod integrity;
+mod invariants;
+#[cfg(feature = "kafka")]
+mod kafka;
+mod locale;
+mod maintenance;
+mod monotonic;
+mod observer;
+mod outbox;
+mod outcome;
+mod policy;
+mod priority;
 mod processor;
-mod reader;
+mod provenance;
+mod pseudonym;
+mod query;
+pub(crate) mod reader;
+mod reorder;
+mod retry;
+mod return_file;
+mod risk;
+mod rules;
+mod scheduler;
+mod schema;
+#[cfg(feature = "scripting")]
+mod script;
+mod shard;
+mod simulation;
+mod slow;
+mod source;
+mod state;
+mod tags;
+mod throughput;
+mod validator;
+#[cfg(feature = "webhook")]
+pub mod webhook;
 
 use rust_decimal::Decimal;
 
-pub use processor::TransactionService;
-pub use reader::*;
+pub use alert::{Alert, AlertCounts, AlertKind, AlertThresholds};
+pub use approval::{Approval, ApprovalAction, ApprovalRules, ApprovalStatus};
+pub use audit::AuditVerification;
+pub use blocking::BlockingTransactionService;
+pub use builder::TransactionServiceBuilder;
+#[cfg(feature = "chaos")]
+pub use chaos::ChaosConfig;
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use close::{BalanceSnapshot, DayClose, TypeTotal};
+pub use currency::Currency;
+#[cfg(feature = "email")]
+pub use email::{EmailConfig, EmailEvent, EmailSink, EmailTemplate};
+pub use error::{Result, TransactionError};
+pub use expiry::{ExpiredHoldAction, HoldExpiry};
+pub use external_ids::{ExternalId, ExternalIds};
+pub use handler::{StorageHandle, TransactionHandler};
+pub use inspect::{AuditRecord, Checkpoints, ClientDump, ConsumerOffset, DayCheckpoint};
+pub use integrity::{InputVerifier, UnsignedInputPolicy, SIGNATURE_EXTENSION};
+pub use invariants::{LedgerCheck, Violation};
+#[cfg(feature = "kafka")]
+pub use kafka::{KafkaOutboxRelay, KafkaSource};
+pub use locale::ReportLocale;
+pub use maintenance::MaintenanceReport;
+pub use monotonic::{MonotonicIds, NonMonotonicIdAction};
+pub use observer::EventObserver;
+pub use outbox::{LedgerEvent, OutboxEvent};
+pub use outcome::{IgnoreReason, ProcessingOutcome, TransactionOutcome, UnknownTargetAction};
+pub use policy::{RiskLimit, SegmentPolicy, SettlementDelay, TransactionPolicy};
+pub use priority::WritePriority;
+pub use processor::{TransactionService, MAX_IDEMPOTENCY_KEY_LEN};
+pub use provenance::{content_sha256, Batch, Provenance};
+pub use pseudonym::Pseudonymizer;
+pub use query::{ClientFilter, GroupBy, Pagination, TransactionAggregate, TransactionFilter};
+pub use reader::{JsonLinesReader, NumberLocale, TransactionReader};
+pub use reorder::ReorderBuffer;
+pub use retry::RetryPolicy;
+pub use return_file::{ReturnFile, ReturnFormat};
+pub use risk::{ChargebackEscalation, ClientRisk, RiskLevel};
+pub use rules::{ReloadableRules, RuleSet};
+pub use scheduler::{Job, JobMetrics, Schedule, Scheduler};
+pub use schema::SCHEMA_VERSION;
+#[cfg(feature = "scripting")]
+pub use script::ScriptValidator;
+pub use shard::ShardedTransactionService;
+pub use simulation::{Simulation, SimulationReport};
+pub use source::{SourceOffset, TransactionSource};
+pub use state::{
+    LedgerState, StateClient, StateDayClose, StateDispute, StateSettlement, STATE_VERSION,
+};
+pub use tags::{read_client_tags, ClientTag};
+pub use throughput::{LatencyPercentiles, ThroughputBucket, ThroughputRecorder, ThroughputStats};
+pub use validator::{Annotation, TransactionValidator, Verdict};
+#[cfg(feature = "webhook")]
+pub use webhook::{WebhookConfig, WebhookSink};
 
-use serde::{Serialize, Deserialize};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize, PartialEq)]
+/// The kind of operation a [`Transaction`] performs.
+#[derive(Debug, Clone, PartialEq)]
 pub enum TransactionType {
-    #[serde(rename = "deposit")]
     Deposit,
-    #[serde(rename = "withdrawal")]
     Withdrawal,
-    #[serde(rename = "dispute")]
     Dispute,
-    #[serde(rename = "resolve")]
     Resolve,
-    #[serde(rename = "chargeback")]
     Chargeback,
+    /// Any other type, applied by the [`TransactionHandler`] registered for it with
+    /// [`TransactionServiceBuilder::handler`].
+    Custom(String),
 }
 impl TransactionType {
-    pub fn to_str(&self) -> &'static str {
+    /// The name used for this type in csv files and in the database.
+    pub fn to_str(&self) -> &str {
         match self {
             Self::Deposit => "deposit",
             Self::Withdrawal => "withdrawal",
             Self::Dispute => "dispute",
             Self::Resolve => "resolve",
             Self::Chargeback => "chargeback",
+            Self::Custom(name) => name,
         }
     }
+    /// Parses a transaction type from its [`TransactionType::to_str`] name. Names other than
+    /// the built in types are [`TransactionType::Custom`], only an empty name is invalid.
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(t: &str) -> Option<Self> {
         match t {
+            "" => None,
             "deposit" => Some(Self::Deposit),
             "withdrawal" => Some(Self::Withdrawal),
             "dispute" => Some(Self::Dispute),
             "resolve" => Some(Self::Resolve),
             "chargeback" => Some(Self::Chargeback),
-            _ => None,
+            custom => Some(Self::Custom(custom.to_string())),
         }
     }
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+impl Serialize for TransactionType {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.to_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for TransactionType {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Self::from_str(&name).ok_or_else(|| serde::de::Error::custom("empty transaction type"))
+    }
+}
+
+/// The id of a client, `u16` unless built with the `wide-ids` feature, which makes it `u32`.
+#[cfg(not(feature = "wide-ids"))]
+pub type ClientId = u16;
+/// The id of a client, `u16` unless built with the `wide-ids` feature, which makes it `u32`.
+#[cfg(feature = "wide-ids")]
+pub type ClientId = u32;
+
+/// The id of a transaction, `u32` unless built with the `wide-ids` feature, which makes it
+/// `u64`.
+#[cfg(not(feature = "wide-ids"))]
+pub type TransactionId = u32;
+/// The id of a transaction, `u32` unless built with the `wide-ids` feature, which makes it
+/// `u64`.
+#[cfg(feature = "wide-ids")]
+pub type TransactionId = u64;
+
+/// A single row of transaction input.
+///
+/// `amount` is only present for deposits and withdrawals. For disputes, resolves and
+/// chargebacks `id` refers to the transaction being disputed.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Transaction {
     #[serde(rename = "tx")]
-    pub id: u32,
+    pub id: TransactionId,
     #[serde(rename = "type")]
     pub transaction_type: TransactionType,
     #[serde(rename = "client")]
-    pub client_id: u16,
+    pub client_id: ClientId,
     pub amount: Option<Decimal>,
+    /// An external reference for a deposit or withdrawal, such as the one on the bank
+    /// statement, see [`TransactionService::get_transactions_by_reference`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    /// Why a dispute was opened, such as a card scheme reason code. Kept on the [`Dispute`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason_code: Option<String>,
+    /// Free-text notes on a dispute. Kept on the [`Dispute`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    /// The position of the transaction in its upstream system, such as a sequence number or a
+    /// timestamp, used by [`ReorderBuffer`] to apply slightly out of order input in order. Not
+    /// stored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
 }
 
-#[derive(Debug, PartialEq, Serialize)]
+/// The current state of a client account.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Client {
     #[serde(rename = "client")]
-    pub id: u16,
+    pub id: ClientId,
     pub available: Decimal,
     pub held: Decimal,
     pub total: Decimal,
     pub locked: bool,
-}
\ No newline at end of file
+}
+
+/// A client without recent activity, see [`TransactionService::get_dormant_clients`].
+#[derive(Debug, PartialEq, Serialize)]
+pub struct DormantClient {
+    pub client: Client,
+    /// When the client last made a transaction, in milliseconds since the unix epoch, from the
+    /// service's [`Clock`]. Missing for clients that have not transacted since activity started
+    /// being recorded.
+    pub last_activity_at: Option<i64>,
+}
+
+/// Counts and sums of a client's applied transactions, see
+/// [`TransactionService::get_client_stats`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ClientStats {
+    pub deposits: u64,
+    /// The sum of the client's deposits.
+    pub deposited: Decimal,
+    pub withdrawals: u64,
+    /// The sum of the client's withdrawals, not counting those rejected for insufficient funds.
+    pub withdrawn: Decimal,
+    /// Disputes opened on the client's transactions, including those since resolved.
+    pub disputes: u64,
+    pub chargebacks: u64,
+    /// The sum of the risk score changes of the client's transactions, see
+    /// [`Verdict::Accept`].
+    #[serde(default)]
+    pub risk_score: i64,
+}
+
+/// A client's balances with its [`ClientStats`].
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ClientWithStats {
+    pub client: Client,
+    pub stats: ClientStats,
+}
+
+/// An internal account of the ledger, the counterparty of changes to client funds.
+///
+/// - `cash`: the funds held for clients, moved by deposits, withdrawals and chargebacks
+/// - `fees`: pays for positive adjustments and receives deductions
+/// - `chargeback_expense`: the part of chargebacks that took a client's total below zero,
+///   which the house carries until the client pays it back
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HouseAccount {
+    pub name: String,
+    pub balance: Decimal,
+}
+
+/// The house accounts with the funds owed to clients, see
+/// [`TransactionService::get_cash_position`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CashPosition {
+    pub accounts: Vec<HouseAccount>,
+    /// The sum of the clients' totals.
+    pub client_funds: Decimal,
+}
+
+/// What a sequence of transactions would do to the ledger, see
+/// [`TransactionService::simulate`].
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Projection {
+    /// The outcome of each transaction, in order.
+    pub outcomes: Vec<TransactionOutcome>,
+    /// The balances the clients changed by the transactions would have, ordered by client id.
+    pub clients: Vec<Client>,
+    /// The balances the same clients had before the transactions, in the same order, `None`
+    /// for the clients the transactions would create.
+    pub before: Vec<Option<Client>>,
+}
+
+impl Projection {
+    /// The clients whose balances or lock the transactions would change, before and after.
+    /// Clients created without funds, such as by a rejected withdrawal, are left out.
+    pub fn changes(&self) -> impl Iterator<Item = (Option<&Client>, &Client)> {
+        self.before
+            .iter()
+            .zip(&self.clients)
+            .map(|(before, after)| (before.as_ref(), after))
+            .filter(|(before, after)| match before {
+                Some(before) => before != after,
+                None => !after.available.is_zero() || !after.held.is_zero() || after.locked,
+            })
+    }
+}
+
+/// An open dispute on a deposit or withdrawal.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Dispute {
+    /// The disputed transaction.
+    pub transaction: Transaction,
+    /// When the dispute was opened, in milliseconds since the unix epoch, from the service's
+    /// [`Clock`].
+    pub opened_at: i64,
+    /// When the dispute was escalated by [`TransactionService::expire_holds`], if it was.
+    pub escalated_at: Option<i64>,
+    /// The `reason_code` of the dispute transaction that opened it.
+    pub reason_code: Option<String>,
+    /// The `notes` of the dispute transaction that opened it.
+    pub notes: Option<String>,
+    /// Where the dispute is in the risk team's workflow.
+    pub status: DisputeStatus,
+    /// Who is working on the dispute, if anyone, see [`TransactionService::assign_dispute`].
+    pub assignee: Option<String>,
+    /// The evidence attached with [`TransactionService::add_dispute_evidence`], in the order
+    /// it was attached.
+    pub evidence: Vec<DisputeEvidence>,
+}
+
+/// A reference to evidence for a dispute, such as a document's URL or the hash of a stored
+/// blob, kept for representment.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DisputeEvidence {
+    pub reference: String,
+    /// When it was attached, in milliseconds since the unix epoch.
+    pub added_at: i64,
+}
+
+impl DisputeEvidence {
+    /// Whether `reference` looks like a URL, e.g. `https://docs.example.com/receipt.pdf`, or a
+    /// hex blob hash with an optional algorithm, e.g. `sha256:9f86d0...`.
+    pub fn is_valid_reference(reference: &str) -> bool {
+        if reference.is_empty() || reference.len() > 2048 {
+            return false;
+        }
+        if let Some((scheme, rest)) = reference.split_once("://") {
+            return !scheme.is_empty()
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+                && !rest.is_empty()
+                && !rest.chars().any(char::is_whitespace);
+        }
+        let hash = reference
+            .split_once(':')
+            .map_or(reference, |(_, hash)| hash);
+        hash.len() >= 32 && hash.chars().all(|c| c.is_ascii_hexdigit())
+    }
+}
+
+/// Where a dispute is in the risk team's workflow, set with
+/// [`TransactionService::update_dispute_status`]. Disputes are open until they are resolved
+/// or charged back, and only open disputes are stored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisputeStatus {
+    #[default]
+    Open,
+    UnderReview,
+    AwaitingEvidence,
+    /// The dispute was resolved, releasing its held funds.
+    Resolved,
+    /// The disputed transaction was charged back.
+    ChargedBack,
+}
+
+impl DisputeStatus {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Open => "open",
+            Self::UnderReview => "under_review",
+            Self::AwaitingEvidence => "awaiting_evidence",
+            Self::Resolved => "resolved",
+            Self::ChargedBack => "charged_back",
+        }
+    }
+
+    pub fn parse(status: &str) -> Result<Self> {
+        match status {
+            "open" => Ok(Self::Open),
+            "under_review" => Ok(Self::UnderReview),
+            "awaiting_evidence" => Ok(Self::AwaitingEvidence),
+            "resolved" => Ok(Self::Resolved),
+            "charged_back" => Ok(Self::ChargedBack),
+            _ => Err(TransactionError::InvalidArgument(format!(
+                "Unknown dispute status \"{}\"",
+                status
+            ))),
+        }
+    }
+
+    /// Whether the dispute still holds its funds.
+    pub fn is_open(self) -> bool {
+        !matches!(self, Self::Resolved | Self::ChargedBack)
+    }
+}
+
+/// A manual correction to a client's available funds made with
+/// [`TransactionService::adjust_balance`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Adjustment {
+    pub id: i64,
+    pub client_id: ClientId,
+    /// The amount added to the client's available funds. Negative for deductions.
+    pub amount: Decimal,
+    pub reason: String,
+    /// Who made the adjustment.
+    pub operator: String,
+}
+
+/// A deposit voided with [`TransactionService::void_deposit`] while it was still clearing,
+/// so its amount never became available.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Void {
+    pub transaction_id: TransactionId,
+    pub client_id: ClientId,
+    /// The amount of the deposit, removed from the client's held funds.
+    pub amount: Decimal,
+    pub reason: String,
+    /// Who voided the deposit.
+    pub operator: String,
+    /// When the deposit was voided, in milliseconds since the unix epoch.
+    pub voided_at: i64,
+}
+
+/// How [`TransactionService::forget_client`] erases a client.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErasurePolicy {
+    /// Replaces the free text recorded about the client, the reasons of its adjustments and
+    /// the validator annotations of its transactions, keeping every record.
+    #[default]
+    Anonymize,
+    /// Also removes the client's transactions, keeping only its balances. Disputes of the
+    /// removed transactions are ignored like disputes of unknown transactions.
+    Tombstone,
+}
+
+impl ErasurePolicy {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Anonymize => "anonymize",
+            Self::Tombstone => "tombstone",
+        }
+    }
+}
+
+/// The record of a client erased with [`TransactionService::forget_client`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Erasure {
+    pub id: i64,
+    pub client_id: ClientId,
+    pub policy: ErasurePolicy,
+    /// Who requested the erasure.
+    pub operator: String,
+    /// In milliseconds since the unix epoch, from the service's [`Clock`].
+    pub erased_at: i64,
+    /// The number of adjustment reasons and annotations replaced.
+    pub redacted: u64,
+    /// The number of transactions removed by [`ErasurePolicy::Tombstone`].
+    pub removed: u64,
+}
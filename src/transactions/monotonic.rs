@@ -0,0 +1,147 @@
+use super::{
+    Client, Result, Transaction, TransactionId, TransactionType, TransactionValidator, Verdict,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+/// What [`MonotonicIds`] does with a deposit or withdrawal whose id is not above every id it
+/// has seen before.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NonMonotonicIdAction {
+    /// Reject the transaction with the reason `non_monotonic_id`.
+    #[default]
+    Reject,
+    /// Apply the transaction, annotating it with `non_monotonic_id` and logging a warning.
+    Warn,
+}
+
+impl NonMonotonicIdAction {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Reject => "reject",
+            Self::Warn => "warn",
+        }
+    }
+}
+
+/// A [`TransactionValidator`] for inputs whose deposit and withdrawal ids are strictly
+/// increasing, where a lower or repeated id means the input is corrupted.
+///
+/// Ids are compared with those of every deposit and withdrawal the validator accepted before,
+/// so a validator covers a single input: register a new one with
+/// [`TransactionServiceBuilder::validator`](super::TransactionServiceBuilder::validator) for
+/// each. Disputes, resolves and chargebacks refer to earlier ids and are not checked.
+#[derive(Debug, Default)]
+pub struct MonotonicIds {
+    action: NonMonotonicIdAction,
+    last: Mutex<Option<TransactionId>>,
+}
+
+impl MonotonicIds {
+    /// The reason and annotation given to transactions with a non-monotonic id.
+    pub const REASON: &'static str = "non_monotonic_id";
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets what happens to transactions with a non-monotonic id, rejecting them by default.
+    pub fn action(mut self, action: NonMonotonicIdAction) -> Self {
+        self.action = action;
+        self
+    }
+}
+
+impl TransactionValidator for MonotonicIds {
+    fn validate(&self, transaction: &Transaction, _client: Option<&Client>) -> Result<Verdict> {
+        if !matches!(
+            transaction.transaction_type,
+            TransactionType::Deposit | TransactionType::Withdrawal
+        ) {
+            return Ok(Verdict::accept());
+        }
+        let mut last = self.last.lock().unwrap();
+        let previous = match *last {
+            Some(previous) if transaction.id <= previous => previous,
+            _ => {
+                *last = Some(transaction.id);
+                return Ok(Verdict::accept());
+            }
+        };
+        match self.action {
+            NonMonotonicIdAction::Reject => Ok(Verdict::Reject {
+                reason: Self::REASON.to_string(),
+            }),
+            NonMonotonicIdAction::Warn => {
+                tracing::warn!(
+                    tx = transaction.id,
+                    previous,
+                    "Applying a transaction whose id is not above the previous one"
+                );
+                Ok(Verdict::Accept {
+                    annotations: vec![Self::REASON.to_string()],
+                    risk_score_change: 0,
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        MonotonicIds, NonMonotonicIdAction, Transaction, TransactionId, TransactionType,
+        TransactionValidator, Verdict,
+    };
+    use rust_decimal_macros::dec;
+
+    fn transaction(id: TransactionId, transaction_type: TransactionType) -> Transaction {
+        Transaction {
+            id,
+            transaction_type,
+            client_id: 1,
+            amount: Some(dec!(1)),
+            reference: None,
+            reason_code: None,
+            notes: None,
+            sequence: None,
+        }
+    }
+
+    #[test]
+    fn test_monotonic_ids() {
+        let validator = MonotonicIds::new();
+        let verdict = |id, transaction_type| {
+            validator
+                .validate(&transaction(id, transaction_type), None)
+                .unwrap()
+        };
+        let rejected = Verdict::Reject {
+            reason: "non_monotonic_id".into(),
+        };
+        assert_eq!(verdict(2, TransactionType::Deposit), Verdict::accept());
+        assert_eq!(verdict(5, TransactionType::Withdrawal), Verdict::accept());
+        assert_eq!(verdict(5, TransactionType::Deposit), rejected);
+        assert_eq!(verdict(3, TransactionType::Withdrawal), rejected);
+        // Refers to an earlier transaction
+        assert_eq!(verdict(2, TransactionType::Dispute), Verdict::accept());
+        // Rejected ids do not move the last id
+        assert_eq!(verdict(4, TransactionType::Deposit), rejected);
+        assert_eq!(verdict(6, TransactionType::Deposit), Verdict::accept());
+
+        let validator = MonotonicIds::new().action(NonMonotonicIdAction::Warn);
+        validator
+            .validate(&transaction(2, TransactionType::Deposit), None)
+            .unwrap();
+        assert_eq!(
+            validator
+                .validate(&transaction(1, TransactionType::Deposit), None)
+                .unwrap(),
+            Verdict::Accept {
+                annotations: vec!["non_monotonic_id".into()],
+                risk_score_change: 0,
+            }
+        );
+    }
+}
@@ -0,0 +1,53 @@
+use super::{Adjustment, Alert, ClientId, Transaction};
+
+/// Receives notifications about changes made by the [`TransactionService`](super::TransactionService).
+///
+/// Every method has an empty default implementation so observers only need to implement the
+/// events they care about. Notifications are sent after the change has been committed, from the
+/// task processing the transaction, so long running work should be moved off to another task.
+pub trait EventObserver: Send + Sync {
+    /// A deposit was added to the client's available funds.
+    fn on_deposit(&self, _deposit: &Transaction) {}
+
+    /// A withdrawal was taken from the client's available funds.
+    fn on_withdrawal(&self, _withdrawal: &Transaction) {}
+
+    /// A withdrawal was not applied because the client did not have enough available funds.
+    fn on_withdrawal_rejected(&self, _withdrawal: &Transaction) {}
+
+    /// A [`TransactionValidator`](super::TransactionValidator) rejected the transaction.
+    fn on_rejected(&self, _transaction: &Transaction, _reason: &str) {}
+
+    /// A dispute was opened on `disputed` and its amount moved to held funds.
+    fn on_dispute_opened(&self, _disputed: &Transaction) {}
+
+    /// The dispute on `disputed` was resolved and its amount released back to available funds.
+    fn on_dispute_resolved(&self, _disputed: &Transaction) {}
+
+    /// The dispute on `disputed` held its funds for longer than the [`HoldExpiry`] allows and
+    /// was escalated, see [`ExpiredHoldAction::Escalate`].
+    ///
+    /// [`HoldExpiry`]: super::HoldExpiry
+    /// [`ExpiredHoldAction::Escalate`]: super::ExpiredHoldAction::Escalate
+    fn on_hold_escalated(&self, _disputed: &Transaction) {}
+
+    /// The disputed transaction was charged back and its held amount removed.
+    fn on_chargeback(&self, _disputed: &Transaction) {}
+
+    /// The client was locked, after a chargeback.
+    fn on_client_locked(&self, _client_id: ClientId) {}
+
+    /// An operator unlocked a previously locked client.
+    fn on_client_unlocked(&self, _client_id: ClientId) {}
+
+    /// A transaction of a custom type was applied by its
+    /// [`TransactionHandler`](super::TransactionHandler).
+    fn on_custom(&self, _transaction: &Transaction) {}
+
+    /// An operator manually adjusted a client's available funds.
+    fn on_adjustment(&self, _adjustment: &Adjustment) {}
+
+    /// A client's balances crossed one of the
+    /// [`AlertThresholds`](super::AlertThresholds).
+    fn on_alert(&self, _alert: &Alert) {}
+}
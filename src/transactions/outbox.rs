@@ -0,0 +1,71 @@
+use super::{
+    Adjustment, Approval, Client, ClientId, ClientRisk, Dispute, Erasure, ExpiredHoldAction,
+    Transaction, TransactionOutcome, Void,
+};
+use serde::Serialize;
+use sqlx::FromRow;
+
+/// A change to the ledger, written to the outbox in the same database transaction as the
+/// change itself when the outbox is enabled with
+/// [`TransactionServiceBuilder::outbox`](super::TransactionServiceBuilder::outbox).
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum LedgerEvent {
+    /// A transaction was applied, see [`TransactionOutcome::is_applied`].
+    Transaction {
+        transaction: Transaction,
+        outcome: TransactionOutcome,
+        /// The affected client after the transaction. Only missing for custom transactions of
+        /// clients without any deposits or withdrawals.
+        client: Option<Client>,
+    },
+    /// An operator adjusted a client's available funds.
+    Adjustment {
+        adjustment: Adjustment,
+        client: Client,
+    },
+    /// An operator unlocked a client.
+    ClientUnlocked { client: Client, operator: String },
+    /// An operator requested a change that needs a second operator's approval, see
+    /// [`ApprovalRules`](super::ApprovalRules).
+    ApprovalRequested { approval: Approval },
+    /// A second operator approved or rejected a requested change. Approved changes are
+    /// recorded with their own event before this one.
+    ApprovalDecided { approval: Approval },
+    /// A dispute held its funds for longer than the [`HoldExpiry`](super::HoldExpiry) allows.
+    HoldExpired {
+        disputed: Transaction,
+        action: ExpiredHoldAction,
+        client: Client,
+    },
+    /// A deposit held by the [`SettlementDelay`](super::SettlementDelay) cleared and its
+    /// amount moved to the client's available funds.
+    SettlementReleased {
+        deposit: Transaction,
+        client: Client,
+    },
+    /// An operator voided a deposit held by the [`SettlementDelay`](super::SettlementDelay)
+    /// before it cleared.
+    DepositVoided { void: Void, client: Client },
+    /// The risk team moved an open dispute to another status, or assigned it.
+    DisputeUpdated { dispute: Dispute },
+    /// A client's chargebacks reached a threshold of the
+    /// [`ChargebackEscalation`](super::ChargebackEscalation), raising its risk level.
+    RiskEscalated { risk: ClientRisk },
+    /// A client's personal data was erased.
+    ClientForgotten { erasure: Erasure },
+}
+
+/// A [`LedgerEvent`] waiting in the outbox to be shipped.
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+pub struct OutboxEvent {
+    /// Increases with every event. Consumers can use it to discard events that were shipped
+    /// more than once.
+    pub id: i64,
+    pub client_id: ClientId,
+    /// When the change was made, in milliseconds since the unix epoch, from the service's
+    /// [`Clock`](super::Clock).
+    pub created_at: i64,
+    /// The json encoded [`LedgerEvent`].
+    pub payload: String,
+}
@@ -0,0 +1,196 @@
+use super::{ClientId, Transaction};
+use serde::{Deserialize, Serialize};
+
+/// What applying a single transaction did.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionOutcome {
+    /// The deposit was added to the client's available funds.
+    Deposit,
+    /// The withdrawal was taken from the client's available funds.
+    Withdrawal,
+    /// The withdrawal was recorded but not applied because of insufficient available funds.
+    WithdrawalRejected,
+    /// A dispute was opened on the contained transaction.
+    DisputeOpened(Transaction),
+    /// The dispute on the contained transaction was resolved.
+    DisputeResolved(Transaction),
+    /// The disputed transaction was charged back. `locked` is set if this locked the client.
+    Chargeback { disputed: Transaction, locked: bool },
+    /// The [`TransactionHandler`](super::TransactionHandler) of a custom transaction type
+    /// applied the transaction.
+    Custom,
+    /// A [`TransactionValidator`](super::TransactionValidator) rejected the transaction,
+    /// applying it would have overflowed the stored balances, see
+    /// [`TransactionOutcome::OVERFLOW`], its amount was not positive, see
+    /// [`TransactionOutcome::INVALID_AMOUNT`], or it failed in a batch isolating failures, see
+    /// [`TransactionOutcome::FAILED`].
+    Rejected { reason: String },
+    /// Nothing was changed, for the given reason.
+    Ignored { reason: IgnoreReason },
+}
+
+impl TransactionOutcome {
+    /// The reason of a transaction [rejected](Self::Rejected) because it would have taken a
+    /// client's balances, or the house's cash, beyond the largest amount that can be stored.
+    pub const OVERFLOW: &'static str = "amount_overflow";
+
+    /// The reason of a deposit or withdrawal [rejected](Self::Rejected) because its amount is
+    /// zero or negative.
+    pub const INVALID_AMOUNT: &'static str = "invalid_amount";
+
+    /// The reason of a transaction [rejected](Self::Rejected) because applying it failed with
+    /// an error and was rolled back on its own, see
+    /// [`TransactionServiceBuilder::isolate_failures`](super::TransactionServiceBuilder::isolate_failures).
+    pub const FAILED: &'static str = "failed";
+
+    /// A short name for the outcome, e.g. `withdrawal_rejected`.
+    pub fn to_str(&self) -> &'static str {
+        match self {
+            Self::Deposit => "deposit",
+            Self::Withdrawal => "withdrawal",
+            Self::WithdrawalRejected => "withdrawal_rejected",
+            Self::DisputeOpened(_) => "dispute_opened",
+            Self::DisputeResolved(_) => "dispute_resolved",
+            Self::Chargeback { .. } => "chargeback",
+            Self::Custom => "custom",
+            Self::Rejected { .. } => "rejected",
+            Self::Ignored { .. } => "ignored",
+        }
+    }
+
+    /// Whether the transaction changed a client's balance.
+    pub fn is_applied(&self) -> bool {
+        !matches!(
+            self,
+            Self::WithdrawalRejected | Self::Rejected { .. } | Self::Ignored { .. }
+        )
+    }
+
+    /// The client whose balances `transaction` changed with this outcome, the owner of the
+    /// disputed transaction for disputes, resolves and chargebacks.
+    pub fn changed_client(&self, transaction: &Transaction) -> ClientId {
+        match self {
+            Self::DisputeOpened(disputed)
+            | Self::DisputeResolved(disputed)
+            | Self::Chargeback { disputed, .. } => disputed.client_id,
+            _ => transaction.client_id,
+        }
+    }
+
+    /// Why the transaction was not applied, `None` if it was.
+    pub fn reason(&self) -> Option<&str> {
+        match self {
+            Self::WithdrawalRejected => Some("insufficient_funds"),
+            Self::Rejected { reason } => Some(reason),
+            Self::Ignored { reason } => Some(reason.to_str()),
+            _ => None,
+        }
+    }
+}
+
+/// Why a transaction was [`TransactionOutcome::Ignored`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IgnoreReason {
+    /// The client is locked, after a chargeback.
+    ClientLocked,
+    /// The amount, or the age of a disputed transaction, is outside what the
+    /// [`TransactionPolicy`](super::TransactionPolicy) of the client allows.
+    OutsidePolicy,
+    /// A dispute of a transaction that is not a stored deposit or withdrawal.
+    UnknownTarget,
+    /// A resolve or chargeback of a transaction that is not under dispute.
+    NotDisputed,
+    /// A withdrawal of a client whose chargebacks escalated to
+    /// [`RiskLevel::WithdrawalsBlocked`](super::RiskLevel::WithdrawalsBlocked) or higher.
+    WithdrawalsBlocked,
+    /// A deposit or withdrawal reusing the id of a stored one, or a dispute of a transaction
+    /// that is already under dispute.
+    Duplicate,
+    /// The [`TransactionHandler`](super::TransactionHandler) of a custom transaction type did
+    /// not apply it.
+    Declined,
+}
+
+impl IgnoreReason {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::ClientLocked => "client_locked",
+            Self::OutsidePolicy => "outside_policy",
+            Self::WithdrawalsBlocked => "withdrawals_blocked",
+            Self::UnknownTarget => "unknown_target",
+            Self::NotDisputed => "not_disputed",
+            Self::Duplicate => "duplicate",
+            Self::Declined => "declined",
+        }
+    }
+}
+
+/// What to do with a dispute of a transaction that is not stored, or a resolve or chargeback
+/// of one that is not under dispute, see
+/// [`TransactionServiceBuilder::unknown_targets`](super::TransactionServiceBuilder::unknown_targets).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnknownTargetAction {
+    /// Ignore it with [`IgnoreReason::UnknownTarget`] or [`IgnoreReason::NotDisputed`].
+    #[default]
+    Ignore,
+    /// Ignore it, logging a warning and counting it in
+    /// [`TransactionService::unknown_target_count`](super::TransactionService::unknown_target_count).
+    Warn,
+    /// Fail with [`TransactionError::InvalidTransaction`](super::TransactionError::InvalidTransaction),
+    /// which stops processing a stream.
+    Error,
+}
+
+/// A summary of the transactions processed by
+/// [`TransactionService::process_stream`](super::TransactionService::process_stream).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ProcessingOutcome {
+    /// The number of transactions read from the stream.
+    pub processed: usize,
+    /// Transactions that changed a client's balance.
+    pub applied: usize,
+    /// Withdrawals rejected for insufficient funds, transactions rejected by a
+    /// [`TransactionValidator`](super::TransactionValidator), and transactions that would have
+    /// overflowed the balances.
+    pub rejected: usize,
+    /// Transactions that had no effect.
+    pub ignored: usize,
+    /// The rejected withdrawals that were rejected for insufficient funds.
+    pub insufficient_funds: usize,
+}
+
+impl ProcessingOutcome {
+    pub(crate) fn record(&mut self, outcome: &TransactionOutcome) {
+        self.processed += 1;
+        match outcome {
+            TransactionOutcome::WithdrawalRejected => {
+                self.rejected += 1;
+                self.insufficient_funds += 1;
+            }
+            TransactionOutcome::Rejected { .. } => self.rejected += 1,
+            TransactionOutcome::Ignored { .. } => self.ignored += 1,
+            _ => self.applied += 1,
+        }
+    }
+
+    /// Adds the counts of `other`.
+    pub(crate) fn merge(&mut self, other: &ProcessingOutcome) {
+        self.processed += other.processed;
+        self.applied += other.applied;
+        self.rejected += other.rejected;
+        self.ignored += other.ignored;
+        self.insufficient_funds += other.insufficient_funds;
+    }
+}
+
+impl<'a> FromIterator<&'a TransactionOutcome> for ProcessingOutcome {
+    fn from_iter<I: IntoIterator<Item = &'a TransactionOutcome>>(outcomes: I) -> Self {
+        let mut summary = Self::default();
+        for outcome in outcomes {
+            summary.record(outcome);
+        }
+        summary
+    }
+}
@@ -0,0 +1,307 @@
+use super::{ChargebackEscalation, Result, TransactionError, TransactionType};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::path::Path;
+use std::time::Duration;
+
+/// Limits applied to transactions before they are processed.
+///
+/// Transactions that break a policy are ignored in the same way as a withdrawal with
+/// insufficient funds.
+///
+/// Policies are usually loaded from a toml file, where `[[segments]]` tables replace the
+/// policy for clients with a [tag](super::ClientTag):
+///
+/// ```toml
+/// max_withdrawal = "1000"
+/// dispute_window_days = 90
+///
+/// [settlement_delay]
+/// threshold = "5000"
+/// clearing_hours = 48
+///
+/// [chargeback_escalation]
+/// flag = 1
+/// block_withdrawals = 2
+/// lock_permanently = 3
+///
+/// [[risk_limits]]
+/// min_score = 50
+/// max_withdrawal = "200"
+///
+/// [[segments]]
+/// tag = "vip"
+/// max_withdrawal = "50000"
+/// dispute_window_days = 180
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TransactionPolicy {
+    /// The largest amount accepted for a single deposit.
+    pub max_deposit: Option<Decimal>,
+    /// The largest amount accepted for a single withdrawal.
+    pub max_withdrawal: Option<Decimal>,
+    /// How long after a deposit or withdrawal was applied it can still be disputed.
+    /// Transactions applied before the schema recorded when they were applied can always be
+    /// disputed.
+    pub dispute_window: Option<Duration>,
+    /// Holds large deposits until they clear.
+    pub settlement_delay: Option<SettlementDelay>,
+    /// Raises the risk level of clients that keep being charged back.
+    pub chargeback_escalation: ChargebackEscalation,
+    /// Lower limits for clients with a high
+    /// [`ClientStats::risk_score`](super::ClientStats::risk_score).
+    pub risk_limits: Vec<RiskLimit>,
+    /// Policies replacing this one for the clients with a tag. The first segment with a tag
+    /// of the client applies, and the segments of a segment's policy are not used.
+    pub segments: Vec<SegmentPolicy>,
+}
+
+/// Deposits over `threshold` are added to the client's held funds, and only become available
+/// `clearing_period` after they were applied, when released by
+/// [`TransactionService::release_settlements`](super::TransactionService::release_settlements).
+#[derive(Debug, Clone, PartialEq)]
+pub struct SettlementDelay {
+    pub threshold: Decimal,
+    pub clearing_period: Duration,
+}
+
+/// Limits replacing [`TransactionPolicy::max_deposit`] and
+/// [`TransactionPolicy::max_withdrawal`] for the clients with a risk score of at least
+/// `min_score`, where they are lower. Of several matching limits the lowest applies.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RiskLimit {
+    pub min_score: i64,
+    pub max_deposit: Option<Decimal>,
+    pub max_withdrawal: Option<Decimal>,
+}
+
+/// The policy of the clients with a tag, see [`TransactionPolicy::segments`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SegmentPolicy {
+    pub tag: String,
+    pub policy: TransactionPolicy,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyFile {
+    max_deposit: Option<Decimal>,
+    max_withdrawal: Option<Decimal>,
+    dispute_window_days: Option<u64>,
+    settlement_delay: Option<SettlementDelayFile>,
+    chargeback_escalation: Option<ChargebackEscalationFile>,
+    #[serde(default)]
+    risk_limits: Vec<RiskLimitFile>,
+    #[serde(default)]
+    segments: Vec<SegmentFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SegmentFile {
+    tag: String,
+    max_deposit: Option<Decimal>,
+    max_withdrawal: Option<Decimal>,
+    dispute_window_days: Option<u64>,
+    settlement_delay: Option<SettlementDelayFile>,
+    chargeback_escalation: Option<ChargebackEscalationFile>,
+    #[serde(default)]
+    risk_limits: Vec<RiskLimitFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RiskLimitFile {
+    min_score: i64,
+    max_deposit: Option<Decimal>,
+    max_withdrawal: Option<Decimal>,
+}
+
+impl From<RiskLimitFile> for RiskLimit {
+    fn from(file: RiskLimitFile) -> Self {
+        Self {
+            min_score: file.min_score,
+            max_deposit: file.max_deposit,
+            max_withdrawal: file.max_withdrawal,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ChargebackEscalationFile {
+    flag: Option<u64>,
+    block_withdrawals: Option<u64>,
+    lock_permanently: Option<u64>,
+}
+
+impl From<ChargebackEscalationFile> for ChargebackEscalation {
+    fn from(file: ChargebackEscalationFile) -> Self {
+        Self {
+            flag: file.flag,
+            block_withdrawals: file.block_withdrawals,
+            lock_permanently: file.lock_permanently,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SettlementDelayFile {
+    threshold: Decimal,
+    clearing_hours: u64,
+}
+
+impl From<SettlementDelayFile> for SettlementDelay {
+    fn from(file: SettlementDelayFile) -> Self {
+        Self {
+            threshold: file.threshold,
+            clearing_period: Duration::from_secs(file.clearing_hours.saturating_mul(60 * 60)),
+        }
+    }
+}
+
+fn days(days: Option<u64>) -> Option<Duration> {
+    days.map(|days| Duration::from_secs(days.saturating_mul(24 * 60 * 60)))
+}
+
+impl TransactionPolicy {
+    /// Checks `amount` against the limit for `transaction_type` of a client with `risk_score`.
+    pub fn allows(
+        &self,
+        transaction_type: &TransactionType,
+        amount: Decimal,
+        risk_score: i64,
+    ) -> bool {
+        let (limit, risk_limit): (_, fn(&RiskLimit) -> Option<Decimal>) = match transaction_type {
+            TransactionType::Deposit => (self.max_deposit, |l| l.max_deposit),
+            TransactionType::Withdrawal => (self.max_withdrawal, |l| l.max_withdrawal),
+            _ => return true,
+        };
+        self.risk_limits
+            .iter()
+            .filter(|l| risk_score >= l.min_score)
+            .filter_map(risk_limit)
+            .chain(limit)
+            .min()
+            .is_none_or(|limit| amount <= limit)
+    }
+
+    /// How long a deposit of `amount` is held before it becomes available, if it is.
+    pub fn clearing_period(&self, amount: Decimal) -> Option<Duration> {
+        self.settlement_delay
+            .as_ref()
+            .filter(|delay| amount > delay.threshold)
+            .map(|delay| delay.clearing_period)
+    }
+
+    /// The policy of a client with `tags`.
+    pub fn for_tags<S: AsRef<str>>(&self, tags: &[S]) -> &TransactionPolicy {
+        self.segments
+            .iter()
+            .find(|segment| tags.iter().any(|tag| tag.as_ref() == segment.tag))
+            .map_or(self, |segment| &segment.policy)
+    }
+
+    /// Parses a toml policy document.
+    pub fn from_toml(toml: &str) -> Result<Self> {
+        let file: PolicyFile = toml::from_str(toml).map_err(|e| {
+            TransactionError::InvalidArgument(format!("Invalid policy file: {}", e))
+        })?;
+        let segments = file
+            .segments
+            .into_iter()
+            .map(|segment| SegmentPolicy {
+                tag: segment.tag,
+                policy: TransactionPolicy {
+                    max_deposit: segment.max_deposit,
+                    max_withdrawal: segment.max_withdrawal,
+                    dispute_window: days(segment.dispute_window_days),
+                    settlement_delay: segment.settlement_delay.map(Into::into),
+                    chargeback_escalation: segment
+                        .chargeback_escalation
+                        .map(Into::into)
+                        .unwrap_or_default(),
+                    risk_limits: segment.risk_limits.into_iter().map(Into::into).collect(),
+                    segments: Vec::new(),
+                },
+            })
+            .collect();
+        Ok(TransactionPolicy {
+            max_deposit: file.max_deposit,
+            max_withdrawal: file.max_withdrawal,
+            dispute_window: days(file.dispute_window_days),
+            settlement_delay: file.settlement_delay.map(Into::into),
+            chargeback_escalation: file
+                .chargeback_escalation
+                .map(Into::into)
+                .unwrap_or_default(),
+            risk_limits: file.risk_limits.into_iter().map(Into::into).collect(),
+            segments,
+        })
+    }
+
+    /// Reads a toml policy file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_policy_from_toml() {
+        let policy = TransactionPolicy::from_toml(
+            r#"
+            max_withdrawal = "1000"
+            dispute_window_days = 90
+
+            [settlement_delay]
+            threshold = 5000
+            clearing_hours = 48
+
+            [[risk_limits]]
+            min_score = 50
+            max_withdrawal = "200"
+
+            [[risk_limits]]
+            min_score = 100
+            max_deposit = "10"
+            max_withdrawal = "2000"
+
+            [[segments]]
+            tag = "vip"
+            max_withdrawal = 50000
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(policy.max_withdrawal, Some(dec!(1000)));
+        assert_eq!(
+            policy.dispute_window,
+            Some(Duration::from_secs(90 * 24 * 60 * 60))
+        );
+        assert_eq!(policy.clearing_period(dec!(5000)), None);
+        assert_eq!(
+            policy.clearing_period(dec!(5000.01)),
+            Some(Duration::from_secs(48 * 60 * 60))
+        );
+        let withdrawal = TransactionType::Withdrawal;
+        assert!(policy.allows(&withdrawal, dec!(1000), 49));
+        assert!(!policy.allows(&withdrawal, dec!(1000), 50));
+        // The lowest of the matching limits applies
+        assert!(!policy.allows(&withdrawal, dec!(1000), 100));
+        assert!(policy.allows(&TransactionType::Deposit, dec!(100), 50));
+        assert!(!policy.allows(&TransactionType::Deposit, dec!(100), 100));
+        assert_eq!(policy.for_tags(&["retail"]), &policy);
+        let vip = policy.for_tags(&["retail", "vip"]);
+        assert_eq!(vip.max_withdrawal, Some(dec!(50000)));
+        // Segments replace the whole policy
+        assert_eq!(vip.dispute_window, None);
+
+        assert!(TransactionPolicy::from_toml("max_overdraft = 10").is_err());
+    }
+}
@@ -0,0 +1,134 @@
+use std::future::Future;
+use tokio::sync::{Mutex, MutexGuard};
+
+tokio::task_local! {
+    static PRIORITY: WritePriority;
+}
+
+/// Which lane a write waits in for the database, so that a large backfill does not delay the
+/// writes someone is waiting for, see [`WritePriority::scope`].
+///
+/// Writes are applied one at a time. Interactive writes queue for the database directly, while
+/// bulk writes first queue behind each other, so at most one of them is waiting for the
+/// database at a time: an interactive write waits for at most the write in progress and one
+/// bulk write, rather than for every queued batch. Batches are committed every
+/// [`TransactionServiceBuilder::batch_size`](super::TransactionServiceBuilder::batch_size)
+/// transactions, letting interactive writes in between.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WritePriority {
+    /// Writes someone is waiting for, such as single submissions. The default.
+    #[default]
+    Interactive,
+    /// Batches, backfills and scheduled jobs.
+    Bulk,
+}
+
+impl WritePriority {
+    /// Runs `f` with the writes it makes on the current task in this lane.
+    pub async fn scope<F: Future>(self, f: F) -> F::Output {
+        PRIORITY.scope(self, f).await
+    }
+
+    /// The lane of the current task's writes.
+    pub fn current() -> Self {
+        PRIORITY.try_with(|priority| *priority).unwrap_or_default()
+    }
+}
+
+/// The lock serializing writes, and the lane bulk writes queue in before it.
+#[derive(Debug, Default)]
+pub(super) struct WriteLanes {
+    bulk: Mutex<()>,
+}
+
+/// Held by a write until it is committed or rolled back.
+pub(super) struct WriteGuard<'a> {
+    _write: MutexGuard<'a, ()>,
+    _lane: Option<MutexGuard<'a, ()>>,
+}
+
+impl WriteLanes {
+    /// Waits for `write_lock` in the current task's lane.
+    pub(super) async fn lock<'a>(&'a self, write_lock: &'a Mutex<()>) -> WriteGuard<'a> {
+        let lane = match WritePriority::current() {
+            WritePriority::Interactive => None,
+            WritePriority::Bulk => Some(self.bulk.lock().await),
+        };
+        WriteGuard {
+            _write: write_lock.lock().await,
+            _lane: lane,
+        }
+    }
+
+    /// Takes `write_lock` in the current task's lane if neither is held, without waiting.
+    pub(super) fn try_lock<'a>(&'a self, write_lock: &'a Mutex<()>) -> Option<WriteGuard<'a>> {
+        let lane = match WritePriority::current() {
+            WritePriority::Interactive => None,
+            WritePriority::Bulk => Some(self.bulk.try_lock().ok()?),
+        };
+        Some(WriteGuard {
+            _write: write_lock.try_lock().ok()?,
+            _lane: lane,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WriteLanes, WritePriority};
+    use std::sync::{Arc, Mutex as StdMutex};
+    use tokio::sync::Mutex;
+
+    #[tokio::test]
+    async fn test_write_lanes() {
+        assert_eq!(WritePriority::current(), WritePriority::Interactive);
+        assert_eq!(
+            WritePriority::Bulk
+                .scope(async { WritePriority::current() })
+                .await,
+            WritePriority::Bulk
+        );
+
+        let lanes = Arc::new(WriteLanes::default());
+        let write_lock = Arc::new(Mutex::new(()));
+        let order = Arc::new(StdMutex::new(Vec::new()));
+        let held = write_lock.lock().await;
+        let mut tasks = Vec::new();
+        // Two bulk writes queue first, then an interactive one
+        for (name, priority) in [
+            ("bulk 1", WritePriority::Bulk),
+            ("bulk 2", WritePriority::Bulk),
+            ("interactive", WritePriority::Interactive),
+        ] {
+            let (lanes, write_lock, order) = (lanes.clone(), write_lock.clone(), order.clone());
+            tasks.push(tokio::spawn(priority.scope(async move {
+                let _guard = lanes.lock(&write_lock).await;
+                order.lock().unwrap().push(name);
+            })));
+            tokio::task::yield_now().await;
+        }
+        drop(held);
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), ["bulk 1", "interactive", "bulk 2"]);
+    }
+
+    #[tokio::test]
+    async fn test_write_lanes_try_lock() {
+        let lanes = WriteLanes::default();
+        let write_lock = Mutex::new(());
+        let held = write_lock.lock().await;
+        assert!(lanes.try_lock(&write_lock).is_none());
+        drop(held);
+
+        // A bulk write waiting for the database holds up other bulk writes only
+        let _bulk = lanes.bulk.lock().await;
+        assert!(
+            WritePriority::Bulk
+                .scope(async { lanes.try_lock(&write_lock).is_none() })
+                .await
+        );
+        assert!(lanes.try_lock(&write_lock).is_some());
+    }
+}
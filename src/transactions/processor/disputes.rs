@@ -0,0 +1,341 @@
+use super::super::{
+    ClientId, Dispute, DisputeEvidence, DisputeStatus, ExpiredHoldAction, HoldExpiry, IgnoreReason,
+    LedgerEvent, Result, Transaction, TransactionError, TransactionId, TransactionOutcome,
+    TransactionType,
+};
+use super::{db_id, DBDispute, DBTransaction, Precision, TransactionService};
+use futures::stream::{Stream, StreamExt};
+use sqlx::{sqlite::Sqlite, Executor};
+
+impl TransactionService {
+    /// Gets the disputed transaction with `transaction_id`, if a dispute is currently open on it.
+    pub async fn get_dispute(&self, transaction_id: TransactionId) -> Result<Option<Transaction>> {
+        let transaction = Self::fetch_dispute(self.read_pool(), transaction_id).await?;
+        transaction
+            .map(|t| t.into_transaction(self.precision))
+            .transpose()
+    }
+
+    /// Streams the currently open disputes, optionally only for transactions made by
+    /// `client_id`, ordered by the disputed transaction id.
+    pub fn get_open_disputes(
+        &self,
+        client_id: Option<ClientId>,
+    ) -> impl Stream<Item = Result<Dispute>> + '_ {
+        let precision = self.precision;
+        sqlx::query_as::<_, DBDispute>(concat!(
+            "SELECT ",
+            dispute_columns!(),
+            "
+            FROM [Disputes] d
+            INNER JOIN [Transactions] t ON t.id = d.transaction_id
+            WHERE ?1 IS NULL OR t.client_id = ?1
+            ORDER BY d.transaction_id"
+        ))
+        .bind(client_id)
+        .fetch(self.read_pool())
+        .map(move |d| d?.into_dispute(precision))
+    }
+
+    /// Moves an open dispute to another status of the risk team's workflow, returning the
+    /// updated dispute. Moving it to [`DisputeStatus::Resolved`] or
+    /// [`DisputeStatus::ChargedBack`] processes a resolve or chargeback of the disputed
+    /// transaction, in the same way as submitting one.
+    ///
+    /// Other changes are recorded as a [`LedgerEvent::DisputeUpdated`]. Fails if the
+    /// transaction is not under dispute, or if the resolve or chargeback is ignored or
+    /// rejected, e.g. because the client is locked, leaving the dispute as it was.
+    #[tracing::instrument(skip(self), fields(status = status.to_str()))]
+    pub async fn update_dispute_status(
+        &self,
+        transaction_id: TransactionId,
+        status: DisputeStatus,
+    ) -> Result<Dispute> {
+        if status.is_open() {
+            return self
+                .update_dispute(transaction_id, |dispute| dispute.status = status)
+                .await;
+        }
+
+        let _client = self.lock_owner(transaction_id).await?;
+        // Checked under the write lock, so the dispute can not be closed in between
+        let (dispute, transaction, outcome) = self
+            .with_retries(|| async {
+                let (_write, mut tx) = self.begin_write().await?;
+                let dispute =
+                    Self::fetch_open_dispute(&mut *tx, transaction_id, self.precision).await?;
+                let transaction = Transaction {
+                    id: transaction_id,
+                    transaction_type: match status {
+                        DisputeStatus::Resolved => TransactionType::Resolve,
+                        _ => TransactionType::Chargeback,
+                    },
+                    client_id: dispute.transaction.client_id,
+                    amount: None,
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                };
+                let outcome = self.apply(&mut tx, &transaction).await?;
+                self.commit(tx).await?;
+                Ok((dispute, transaction, outcome))
+            })
+            .await?;
+        self.notify(&transaction, &outcome);
+
+        match (status, &outcome) {
+            (DisputeStatus::Resolved, TransactionOutcome::DisputeResolved(_))
+            | (DisputeStatus::ChargedBack, TransactionOutcome::Chargeback { .. }) => {
+                Ok(Dispute { status, ..dispute })
+            }
+            _ => Err(TransactionError::invalid(
+                transaction_id,
+                format!(
+                    "The dispute could not be {}: {}",
+                    status.to_str().replace('_', " "),
+                    outcome.reason().unwrap_or(outcome.to_str())
+                ),
+            )),
+        }
+    }
+
+    /// Assigns an open dispute to someone on the risk team, or unassigns it when `assignee`
+    /// is `None`, returning the updated dispute. The change is recorded as a
+    /// [`LedgerEvent::DisputeUpdated`].
+    ///
+    /// Fails if the transaction is not under dispute or `assignee` is empty.
+    #[tracing::instrument(skip(self))]
+    pub async fn assign_dispute(
+        &self,
+        transaction_id: TransactionId,
+        assignee: Option<&str>,
+    ) -> Result<Dispute> {
+        if assignee.is_some_and(|assignee| assignee.trim().is_empty()) {
+            return Err(TransactionError::InvalidArgument(
+                "A dispute can not be assigned to an empty name".into(),
+            ));
+        }
+        self.update_dispute(transaction_id, |dispute| {
+            dispute.assignee = assignee.map(str::to_string)
+        })
+        .await
+    }
+
+    /// Attaches a reference to evidence for an open dispute, such as a document's URL or a
+    /// blob hash, returning the updated dispute. The evidence is recorded as a
+    /// [`LedgerEvent::DisputeUpdated`], and is removed with the dispute when it is resolved or
+    /// charged back. Attaching a reference the dispute already has changes nothing.
+    ///
+    /// Fails if the transaction is not under dispute or `reference` is not a URL or hex hash,
+    /// see [`DisputeEvidence::is_valid_reference`].
+    #[tracing::instrument(skip(self))]
+    pub async fn add_dispute_evidence(
+        &self,
+        transaction_id: TransactionId,
+        reference: &str,
+    ) -> Result<Dispute> {
+        if !DisputeEvidence::is_valid_reference(reference) {
+            return Err(TransactionError::InvalidArgument(format!(
+                "\"{}\" is not a URL or blob hash",
+                reference
+            )));
+        }
+        let (_write, mut tx) = self.begin_write().await?;
+        let mut dispute =
+            Self::fetch_open_dispute(&mut *tx, transaction_id, self.precision).await?;
+        if dispute.evidence.iter().any(|e| e.reference == reference) {
+            return Ok(dispute);
+        }
+
+        let evidence = DisputeEvidence {
+            reference: reference.to_string(),
+            added_at: self.clock.unix_millis(),
+        };
+        sqlx::query(
+            "INSERT INTO DisputeEvidence (transaction_id, reference, added_at) VALUES (?, ?, ?)",
+        )
+        .bind(db_id(transaction_id))
+        .bind(&evidence.reference)
+        .bind(evidence.added_at)
+        .execute(&mut *tx)
+        .await?;
+        dispute.evidence.push(evidence);
+        if self.records_events() {
+            let client_id = dispute.transaction.client_id;
+            let event = LedgerEvent::DisputeUpdated {
+                dispute: dispute.clone(),
+            };
+            self.record_event(&mut tx, client_id, &event).await?;
+        }
+        self.commit(tx).await?;
+        tracing::info!(
+            evidence = dispute.evidence.len(),
+            "Attached dispute evidence"
+        );
+        Ok(dispute)
+    }
+
+    async fn fetch_open_dispute<'e>(
+        executor: impl Executor<'e, Database = Sqlite>,
+        transaction_id: TransactionId,
+        precision: Precision,
+    ) -> Result<Dispute> {
+        sqlx::query_as::<_, DBDispute>(concat!(
+            "SELECT ",
+            dispute_columns!(),
+            "
+            FROM [Disputes] d
+            INNER JOIN [Transactions] t ON t.id = d.transaction_id
+            WHERE d.transaction_id = ?"
+        ))
+        .bind(db_id(transaction_id))
+        .fetch_optional(executor)
+        .await?
+        .ok_or_else(|| TransactionError::invalid(transaction_id, "Not under dispute"))?
+        .into_dispute(precision)
+    }
+
+    /// Applies `update` to the status or assignee of an open dispute.
+    async fn update_dispute(
+        &self,
+        transaction_id: TransactionId,
+        update: impl FnOnce(&mut Dispute),
+    ) -> Result<Dispute> {
+        let (_write, mut tx) = self.begin_write().await?;
+        let mut dispute =
+            Self::fetch_open_dispute(&mut *tx, transaction_id, self.precision).await?;
+        let before = (dispute.status, dispute.assignee.clone());
+        update(&mut dispute);
+        if (dispute.status, &dispute.assignee) == (before.0, &before.1) {
+            return Ok(dispute);
+        }
+
+        sqlx::query("UPDATE Disputes SET status = ?, assignee = ? WHERE transaction_id = ?")
+            .bind(dispute.status.to_str())
+            .bind(&dispute.assignee)
+            .bind(db_id(transaction_id))
+            .execute(&mut *tx)
+            .await?;
+        if self.records_events() {
+            let client_id = dispute.transaction.client_id;
+            let event = LedgerEvent::DisputeUpdated {
+                dispute: dispute.clone(),
+            };
+            self.record_event(&mut tx, client_id, &event).await?;
+        }
+        self.commit(tx).await?;
+        tracing::info!(
+            status = dispute.status.to_str(),
+            assignee = dispute.assignee.as_deref(),
+            "Updated the dispute"
+        );
+        Ok(dispute)
+    }
+
+    #[tracing::instrument(level = "debug", skip(executor))]
+    pub(super) async fn fetch_dispute<'e>(
+        executor: impl Executor<'e, Database = Sqlite>,
+        transaction_id: TransactionId,
+    ) -> Result<Option<DBTransaction>> {
+        sqlx::query_as("SELECT t.* FROM [Disputes] d LEFT JOIN [Transactions] t on t.id = d.transaction_id WHERE d.transaction_id=? LIMIT 1")
+            .bind(db_id(transaction_id))
+            .fetch_optional(executor)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Resolves or escalates, according to `expiry`, the disputes that have been open for
+    /// longer than its max age, returning the expired disputes. Escalated disputes are only
+    /// expired again when resolving. Disputes that can not be resolved, e.g. because the client
+    /// is locked, are left open and not returned.
+    ///
+    /// Each expiry is recorded as a [`LedgerEvent::HoldExpired`], and observers are notified
+    /// of resolved disputes as if they were resolved by a transaction.
+    #[tracing::instrument(skip_all, fields(action = expiry.action.to_str()))]
+    pub async fn expire_holds(&self, expiry: &HoldExpiry) -> Result<Vec<Dispute>> {
+        let now = self.clock.unix_millis();
+        let cutoff =
+            now.saturating_sub(i64::try_from(expiry.max_age.as_millis()).unwrap_or(i64::MAX));
+        let (_write, mut tx) = self.begin_write().await?;
+
+        let disputes: Vec<DBDispute> = sqlx::query_as(concat!(
+            "SELECT ",
+            dispute_columns!(),
+            "
+            FROM [Disputes] d
+            INNER JOIN [Transactions] t ON t.id = d.transaction_id
+            WHERE d.opened_at <= ? AND (? OR d.escalated_at IS NULL)
+            ORDER BY d.opened_at, d.transaction_id"
+        ))
+        .bind(cutoff)
+        .bind(expiry.action == ExpiredHoldAction::Resolve)
+        .fetch_all(&mut *tx)
+        .await?;
+        let mut expired = Vec::with_capacity(disputes.len());
+        for dispute in disputes {
+            let mut dispute = dispute.into_dispute(self.precision)?;
+            let disputed = &dispute.transaction;
+            match expiry.action {
+                ExpiredHoldAction::Resolve => {
+                    let locked = Self::fetch_client(&mut *tx, disputed.client_id)
+                        .await?
+                        .is_some_and(|c| c.locked);
+                    let outcome = match locked {
+                        true => Self::ignored(IgnoreReason::ClientLocked),
+                        false => self.process_resolve(&mut tx, disputed.id).await?,
+                    };
+                    // The dispute stays open, and is expired again by a later sweep
+                    if !outcome.is_applied() {
+                        tracing::warn!(
+                            tx = disputed.id,
+                            client = %self.client_label(disputed.client_id),
+                            reason = outcome.reason(),
+                            "Could not resolve a dispute that held its funds for too long"
+                        );
+                        continue;
+                    }
+                }
+                ExpiredHoldAction::Escalate => {
+                    sqlx::query("UPDATE Disputes SET escalated_at = ? WHERE transaction_id = ?")
+                        .bind(now)
+                        .bind(db_id(disputed.id))
+                        .execute(&mut *tx)
+                        .await?;
+                    dispute.escalated_at = Some(now);
+                }
+            }
+            if self.records_events() {
+                let client = self
+                    .fetch_updated_client(&mut *tx, disputed.client_id)
+                    .await?;
+                let event = LedgerEvent::HoldExpired {
+                    disputed: disputed.clone(),
+                    action: expiry.action,
+                    client,
+                };
+                self.record_event(&mut tx, disputed.client_id, &event)
+                    .await?;
+            }
+            tracing::info!(
+                tx = disputed.id,
+                client = %self.client_label(disputed.client_id),
+                "Dispute held its funds for too long"
+            );
+            expired.push(dispute);
+        }
+        self.commit(tx).await?;
+
+        for dispute in &expired {
+            for observer in &self.observers {
+                match expiry.action {
+                    ExpiredHoldAction::Resolve => {
+                        observer.on_dispute_resolved(&dispute.transaction)
+                    }
+                    ExpiredHoldAction::Escalate => observer.on_hold_escalated(&dispute.transaction),
+                }
+            }
+        }
+        Ok(expired)
+    }
+}
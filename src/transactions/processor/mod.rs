@@ -0,0 +1,8869 @@
+use super::audit::{chain_hash, content_hash, AuditedEvent, GENESIS_HASH};
+use super::client_lock::{ClientGuard, ClientLocks};
+use super::close::parse_business_date;
+use super::inspect;
+use super::priority::{WriteGuard, WriteLanes};
+use super::slow::WriteSteps;
+use super::{
+    Adjustment, Alert, AlertCounts, AlertKind, AlertThresholds, Annotation, Approval,
+    ApprovalAction, ApprovalRules, ApprovalStatus, AuditRecord, AuditVerification, BalanceSnapshot,
+    Batch, CashPosition, Checkpoints, Client, ClientDump, ClientFilter, ClientId, ClientStats,
+    ClientTag, ClientWithStats, Clock, Dispute, DisputeEvidence, DisputeStatus, DormantClient,
+    Erasure, ErasurePolicy, EventObserver, ExternalId, ExternalIds, GroupBy, HouseAccount,
+    IgnoreReason, LedgerCheck, LedgerEvent, LedgerState, MaintenanceReport, OutboxEvent,
+    Pagination, ProcessingOutcome, Projection, Provenance, Pseudonymizer, Result, RetryPolicy,
+    RiskLevel, SourceOffset, StateClient, StateDayClose, StateDispute, StateSettlement,
+    StorageHandle, Transaction, TransactionAggregate, TransactionError, TransactionFilter,
+    TransactionHandler, TransactionId, TransactionOutcome, TransactionPolicy,
+    TransactionServiceBuilder, TransactionType, TransactionValidator, UnknownTargetAction, Verdict,
+    Violation, Void, STATE_VERSION,
+};
+use futures::{stream::Stream, StreamExt};
+use rust_decimal::prelude::ToPrimitive;
+use serde::Serialize;
+use sqlx::query::QueryAs;
+use sqlx::sqlite::{Sqlite, SqliteArguments, SqliteConnection};
+use sqlx::{types::Decimal, Connection, Executor, FromRow, Pool, Row};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::future::Future;
+use std::path::Path;
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use tracing::Instrument;
+
+/// Converts between [`Decimal`] amounts and the fixed point `i64` values stored in the database.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Precision {
+    scale: u32,
+    storage_mul: i64,
+}
+
+impl Precision {
+    pub(super) fn new(scale: u32) -> Self {
+        Self {
+            scale,
+            storage_mul: 10i64.pow(scale),
+        }
+    }
+
+    fn to_decimal(self, amount: i64) -> Decimal {
+        Decimal::new(amount, self.scale)
+    }
+
+    //sqlite dosent support "decimal" so covert to i64
+    fn to_storage(self, amount: Decimal) -> Option<i64> {
+        amount
+            .checked_mul(Decimal::from(self.storage_mul))?
+            .to_i64()
+    }
+}
+
+/// The conditions of a [`TransactionFilter`] on `[Transactions] t`, bound by
+/// [`bind_transaction_filter`] as parameters 1 to 8. A macro so queries can `concat!` it.
+macro_rules! transaction_filter {
+    () => {
+        "(?1 IS NULL OR t.client_id = ?1)
+        AND (?2 IS NULL OR t.[type] = ?2)
+        AND (?3 IS NULL OR EXISTS (SELECT 1 FROM [Disputes] d WHERE d.transaction_id = t.id) = ?3)
+        AND (?4 IS NULL OR t.amount >= ?4)
+        AND (?5 IS NULL OR t.amount <= ?5)
+        AND (?6 IS NULL OR t.created_at >= ?6)
+        AND (?7 IS NULL OR t.created_at < ?7)
+        AND (?8 IS NULL OR EXISTS (SELECT 1 FROM [ClientTags] g WHERE g.client_id = t.client_id AND g.tag = ?8))"
+    };
+}
+
+/// The evidence of the dispute `[Disputes] d` as a json array of [`DisputeEvidence`].
+macro_rules! dispute_evidence {
+    () => {
+        "(SELECT json_group_array(json_object('reference', e.reference, 'added_at', e.added_at))
+            FROM [DisputeEvidence] e WHERE e.transaction_id = d.transaction_id)"
+    };
+}
+
+/// The columns of a [`DBDispute`] selected from `[Disputes] d` joined to `[Transactions] t`.
+/// A macro so queries can `concat!` it.
+macro_rules! dispute_columns {
+    () => {
+        concat!(
+            "t.*, d.opened_at, d.escalated_at, d.reason_code, d.notes, d.status, d.assignee, ",
+            dispute_evidence!(),
+            " AS evidence"
+        )
+    };
+}
+
+// Declared after the macros, which they use
+mod disputes;
+mod risk;
+mod scheduled;
+mod settlement;
+
+fn bind_transaction_filter<'q, O>(
+    query: QueryAs<'q, Sqlite, O, SqliteArguments<'q>>,
+    filter: &TransactionFilter,
+    precision: Precision,
+) -> QueryAs<'q, Sqlite, O, SqliteArguments<'q>> {
+    let amount_bound = |amount: Option<Decimal>| {
+        amount.map(|a| {
+            precision.to_storage(a).unwrap_or(if a.is_sign_negative() {
+                i64::MIN
+            } else {
+                i64::MAX
+            })
+        })
+    };
+    query
+        .bind(filter.client_id)
+        .bind(
+            filter
+                .transaction_type
+                .as_ref()
+                .map(|t| t.to_str().to_string()),
+        )
+        .bind(filter.disputed)
+        .bind(amount_bound(filter.min_amount))
+        .bind(amount_bound(filter.max_amount))
+        .bind(filter.from)
+        .bind(filter.until)
+        .bind(filter.tag.clone())
+}
+
+/// Transaction ids as stored, as they are: `u32` ids fit in SQLite's `i64`.
+#[cfg(not(feature = "wide-ids"))]
+fn db_id(id: TransactionId) -> i64 {
+    i64::from(id)
+}
+
+#[cfg(not(feature = "wide-ids"))]
+fn from_db_id(id: i64) -> TransactionId {
+    id as TransactionId
+}
+
+/// Transaction ids as stored. `u64` ids are offset by `2^63` to fit in SQLite's `i64`, so
+/// they keep their order. The database records which width it stores, see [`super::schema::prepare`].
+#[cfg(feature = "wide-ids")]
+fn db_id(id: TransactionId) -> i64 {
+    (id ^ (1 << 63)) as i64
+}
+
+#[cfg(feature = "wide-ids")]
+fn from_db_id(id: i64) -> TransactionId {
+    id as TransactionId ^ (1 << 63)
+}
+
+/// Whether `e` is SQLite's `SQLITE_BUSY` or `SQLITE_LOCKED`, with any extended code.
+fn is_locked(e: &sqlx::Error) -> bool {
+    let sqlx::Error::Database(e) = e else {
+        return false;
+    };
+    let code = e.code().and_then(|code| code.parse::<i32>().ok());
+    matches!(code.map(|code| code & 0xff), Some(5 | 6))
+}
+
+/// A URI filename for the database file at `path`, opened with `mode`, e.g. `rwc`. Plain
+/// paths attached to an in-memory database would be opened in memory too.
+fn sqlite_uri(path: &Path, mode: &str) -> Result<String> {
+    let path = path.to_str().ok_or_else(|| {
+        TransactionError::InvalidArgument(format!("\"{}\" is not valid UTF-8", path.display()))
+    })?;
+    let path = path
+        .replace('%', "%25")
+        .replace('?', "%3f")
+        .replace('#', "%23");
+    Ok(format!("file:{}?mode={}", path, mode))
+}
+
+#[derive(Debug, PartialEq, FromRow, Serialize)]
+pub(super) struct ClientDb {
+    #[serde(rename = "client")]
+    pub id: ClientId,
+    pub available: i64,
+    pub held: i64,
+    pub total: i64,
+    pub locked: bool,
+}
+
+impl ClientDb {
+    pub(super) fn into_client(self, precision: Precision) -> Client {
+        Client {
+            id: self.id,
+            available: precision.to_decimal(self.available),
+            held: precision.to_decimal(self.held),
+            total: precision.to_decimal(self.total),
+            locked: self.locked,
+        }
+    }
+}
+
+/// The [`ClientStats`] columns of a client, or the change an applied transaction makes to them.
+#[derive(Debug, Default, FromRow)]
+struct ClientStatsDb {
+    deposits: i64,
+    deposited: i64,
+    withdrawals: i64,
+    withdrawn: i64,
+    disputes: i64,
+    chargebacks: i64,
+    risk_score: i64,
+}
+
+impl ClientStatsDb {
+    fn of_outcome(outcome: &TransactionOutcome, amount: i64) -> Self {
+        match outcome {
+            TransactionOutcome::Deposit => Self {
+                deposits: 1,
+                deposited: amount,
+                ..Self::default()
+            },
+            TransactionOutcome::Withdrawal => Self {
+                withdrawals: 1,
+                withdrawn: amount,
+                ..Self::default()
+            },
+            TransactionOutcome::DisputeOpened(_) => Self {
+                disputes: 1,
+                ..Self::default()
+            },
+            TransactionOutcome::Chargeback { .. } => Self {
+                chargebacks: 1,
+                ..Self::default()
+            },
+            _ => Self::default(),
+        }
+    }
+
+    fn into_stats(self, precision: Precision) -> ClientStats {
+        let count = |n: i64| u64::try_from(n).unwrap_or_default();
+        ClientStats {
+            deposits: count(self.deposits),
+            deposited: precision.to_decimal(self.deposited),
+            withdrawals: count(self.withdrawals),
+            withdrawn: precision.to_decimal(self.withdrawn),
+            disputes: count(self.disputes),
+            chargebacks: count(self.chargebacks),
+            risk_score: self.risk_score,
+        }
+    }
+}
+
+#[derive(FromRow)]
+struct DBTransaction {
+    pub id: i64,
+    #[sqlx(rename = "type")]
+    pub transaction_type: String,
+    pub client_id: ClientId,
+    pub amount: Option<i64>,
+    pub reference: Option<String>,
+}
+
+#[derive(FromRow)]
+struct DBApproval {
+    id: i64,
+    client_id: ClientId,
+    action: String,
+    amount: Option<i64>,
+    reason: Option<String>,
+    status: String,
+    requested_by: String,
+    requested_at: i64,
+    decided_by: Option<String>,
+    decided_at: Option<i64>,
+}
+
+impl DBApproval {
+    fn into_approval(self, precision: Precision) -> Result<Approval> {
+        let action = match (self.action.as_str(), self.amount, self.reason) {
+            ("unlock", _, _) => ApprovalAction::Unlock,
+            ("adjustment", Some(amount), Some(reason)) => ApprovalAction::Adjustment {
+                amount: precision.to_decimal(amount),
+                reason,
+            },
+            (action, _, _) => {
+                return Err(TransactionError::InvalidArgument(format!(
+                    "Approval {} has an invalid {} action",
+                    self.id, action
+                )))
+            }
+        };
+        Ok(Approval {
+            id: self.id,
+            client_id: self.client_id,
+            action,
+            status: ApprovalStatus::parse(&self.status)?,
+            requested_by: self.requested_by,
+            requested_at: self.requested_at,
+            decided_by: self.decided_by,
+            decided_at: self.decided_at,
+        })
+    }
+}
+
+#[derive(FromRow)]
+struct DBDispute {
+    #[sqlx(flatten)]
+    transaction: DBTransaction,
+    opened_at: i64,
+    escalated_at: Option<i64>,
+    reason_code: Option<String>,
+    notes: Option<String>,
+    status: String,
+    assignee: Option<String>,
+    evidence: String,
+}
+
+impl DBDispute {
+    fn into_dispute(self, precision: Precision) -> Result<Dispute> {
+        Ok(Dispute {
+            transaction: self.transaction.into_transaction(precision)?,
+            opened_at: self.opened_at,
+            escalated_at: self.escalated_at,
+            reason_code: self.reason_code,
+            notes: self.notes,
+            status: DisputeStatus::parse(&self.status)?,
+            assignee: self.assignee,
+            evidence: Self::parse_evidence(&self.evidence)?,
+        })
+    }
+
+    fn parse_evidence(evidence: &str) -> Result<Vec<DisputeEvidence>> {
+        let mut evidence: Vec<DisputeEvidence> = serde_json::from_str(evidence)?;
+        evidence.sort_by_key(|e| e.added_at);
+        Ok(evidence)
+    }
+}
+
+impl DBTransaction {
+    fn into_transaction(self, precision: Precision) -> Result<Transaction> {
+        let transaction_type =
+            TransactionType::from_str(&self.transaction_type).ok_or_else(|| {
+                TransactionError::invalid(from_db_id(self.id), "Stored without a type")
+            })?;
+        Ok(Transaction {
+            id: from_db_id(self.id),
+            transaction_type,
+            client_id: self.client_id,
+            amount: self.amount.map(|a| precision.to_decimal(a)),
+            reference: self.reference,
+            reason_code: None,
+            notes: None,
+            sequence: None,
+        })
+    }
+}
+
+/// The [`HouseAccount`]s.
+const CASH: &str = "cash";
+const CHARGEBACK_EXPENSE: &str = "chargeback_expense";
+const FEES: &str = "fees";
+
+/// What [`TransactionService::forget_client`] replaces erased text with.
+const ERASED: &str = "[erased]";
+
+/// The longest accepted idempotency key, in bytes.
+pub const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+/// A transaction with the line and, for partitioned logs, the offset it was read from.
+type SourcedTransaction = (u64, Option<SourceOffset>, Transaction);
+
+/// Applies transactions to client accounts stored in a sqlite database.
+///
+/// The service is shared by concurrent callers, such as the server's requests, behind an
+/// [`Arc`]. Submitted transactions and batches, and the administrative changes to a client,
+/// hold the locks of the clients they can change until they are applied and notified: the
+/// client of each transaction, and the owner of the disputed transaction for disputes,
+/// resolves and chargebacks. Concurrent changes to the same client are therefore applied one
+/// after the other, each seeing the balances left by the last, while changes to other clients
+/// go ahead. Scheduled jobs such as [`TransactionService::expire_holds`] only take the write
+/// lock. The database write itself is made holding the service's write lock, as sqlite only
+/// has one writer per database; spread clients over databases written at the same time with
+/// [`TransactionServiceBuilder::build_sharded`] to write them in parallel.
+pub struct TransactionService {
+    pool: Pool<Sqlite>,
+    /// The read-only copies of the database queries are spread over, see
+    /// [`TransactionServiceBuilder::read_replica_url`].
+    read_pools: Vec<Pool<Sqlite>>,
+    next_read_pool: AtomicUsize,
+    precision: Precision,
+    policy: TransactionPolicy,
+    observers: Vec<Arc<dyn EventObserver>>,
+    batch_size: usize,
+    outbox: bool,
+    audit_log: bool,
+    handlers: HashMap<String, Arc<dyn TransactionHandler>>,
+    validators: Vec<Arc<dyn TransactionValidator>>,
+    clock: Arc<dyn Clock>,
+    write_lock: Arc<Mutex<()>>,
+    /// Held by submissions for a client, before they wait for the write lock.
+    client_locks: ClientLocks,
+    /// Where bulk writes wait for the write lock.
+    write_lanes: WriteLanes,
+    /// Set from the start of [`TransactionService::pause`] until it is resumed.
+    paused: AtomicBool,
+    /// The write lock, held while paused.
+    pause_guard: Mutex<Option<OwnedMutexGuard<()>>>,
+    pseudonymizer: Option<Pseudonymizer>,
+    alerts: AlertThresholds,
+    approvals: ApprovalRules,
+    /// The alerts raised by the current write, delivered once it commits.
+    pending_alerts: std::sync::Mutex<Vec<Alert>>,
+    low_available_alerts: AtomicU64,
+    high_held_alerts: AtomicU64,
+    balance_overflow_alerts: AtomicU64,
+    /// The outcomes of the committed transactions, by transaction type.
+    transaction_counts: std::sync::Mutex<BTreeMap<String, ProcessingOutcome>>,
+    slow_transaction: Option<Duration>,
+    unknown_targets: UnknownTargetAction,
+    unknown_target_count: AtomicU64,
+    retry: RetryPolicy,
+    isolate_failures: bool,
+    /// Set when replaying history, see [`TransactionServiceBuilder::backfill`].
+    backfill: bool,
+    /// The steps of the current write, kept when `slow_transaction` is set.
+    write_steps: std::sync::Mutex<WriteSteps>,
+    #[cfg(feature = "chaos")]
+    pub(super) chaos: Option<super::chaos::Chaos>,
+}
+
+impl TransactionService {
+    /// Creates the service with default options, creating the schema in `pool` if it does not
+    /// already exist. Use [`TransactionService::builder`] to configure the service.
+    pub async fn new(pool: Pool<Sqlite>) -> Result<Self> {
+        Self::builder().pool(pool).build().await
+    }
+
+    /// Starts building a service.
+    pub fn builder() -> TransactionServiceBuilder {
+        TransactionServiceBuilder::default()
+    }
+
+    /// A service over `pool`, reading from `read_pools` too, configured by `builder`.
+    pub(super) async fn from_builder(
+        builder: TransactionServiceBuilder,
+        pool: Pool<Sqlite>,
+        read_pools: Vec<Pool<Sqlite>>,
+    ) -> Result<Self> {
+        super::schema::prepare(&pool).await?;
+        for handler in builder.handlers.values() {
+            if !handler.schema().is_empty() {
+                sqlx::query(handler.schema()).execute(&pool).await?;
+            }
+        }
+        Ok(Self {
+            pool,
+            read_pools,
+            next_read_pool: AtomicUsize::new(0),
+            precision: Precision::new(builder.precision),
+            policy: builder.policy,
+            observers: builder.observers,
+            batch_size: builder.batch_size,
+            outbox: builder.outbox,
+            audit_log: builder.audit_log,
+            handlers: builder.handlers,
+            validators: builder.validators,
+            clock: builder.clock,
+            write_lock: Arc::new(Mutex::new(())),
+            client_locks: ClientLocks::default(),
+            write_lanes: WriteLanes::default(),
+            paused: AtomicBool::new(false),
+            pause_guard: Mutex::new(None),
+            pseudonymizer: builder.pseudonymizer,
+            alerts: builder.alerts,
+            approvals: builder.approvals,
+            pending_alerts: std::sync::Mutex::new(Vec::new()),
+            low_available_alerts: AtomicU64::new(0),
+            high_held_alerts: AtomicU64::new(0),
+            balance_overflow_alerts: AtomicU64::new(0),
+            transaction_counts: std::sync::Mutex::new(BTreeMap::new()),
+            slow_transaction: builder.slow_transaction,
+            unknown_targets: builder.unknown_targets,
+            unknown_target_count: AtomicU64::new(0),
+            retry: builder.retry,
+            isolate_failures: builder.isolate_failures,
+            backfill: builder.backfill,
+            write_steps: std::sync::Mutex::new(WriteSteps::default()),
+            #[cfg(feature = "chaos")]
+            chaos: builder.chaos.map(super::chaos::Chaos::new),
+        })
+    }
+
+    /// The pseudonymizer client ids are logged with, if any. Callers producing reports should
+    /// use it to replace client ids too.
+    pub fn pseudonymizer(&self) -> Option<&Pseudonymizer> {
+        self.pseudonymizer.as_ref()
+    }
+
+    /// The number of [`Alert`]s raised since the service was built.
+    pub fn alert_counts(&self) -> AlertCounts {
+        AlertCounts {
+            low_available: self.low_available_alerts.load(Ordering::Relaxed),
+            high_held: self.high_held_alerts.load(Ordering::Relaxed),
+            balance_overflow: self.balance_overflow_alerts.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The number of disputes, resolves and chargebacks with an unknown target committed since
+    /// the service was built, when they are [`UnknownTargetAction::Warn`]ed about.
+    pub fn unknown_target_count(&self) -> u64 {
+        self.unknown_target_count.load(Ordering::Relaxed)
+    }
+
+    /// The number of transactions applied, rejected and ignored since the service was built, by
+    /// transaction type. Types without any transactions yet are left out.
+    pub fn transaction_counts(&self) -> BTreeMap<String, ProcessingOutcome> {
+        self.transaction_counts.lock().unwrap().clone()
+    }
+
+    /// The clock the service stamps changes with.
+    pub(super) fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
+    }
+
+    /// The pool queries are read from, taking turns between the read replicas if there are
+    /// any.
+    fn read_pool(&self) -> &Pool<Sqlite> {
+        if self.read_pools.is_empty() {
+            return &self.pool;
+        }
+        let next = self.next_read_pool.fetch_add(1, Ordering::Relaxed);
+        &self.read_pools[next % self.read_pools.len()]
+    }
+
+    /// Pauses writes, e.g. to copy the database file while the service keeps running. Waits for
+    /// the write in progress to commit; later writes wait until
+    /// [`TransactionService::resume`] is called. Reads are not paused, except those made
+    /// together under the write lock such as [`TransactionService::get_cash_position`].
+    ///
+    /// [`TransactionService::is_paused`] is true from the start of the call, so that callers
+    /// taking in transactions can turn them away instead of queueing them.
+    pub async fn pause(&self) {
+        let mut pause_guard = self.pause_guard.lock().await;
+        self.paused.store(true, Ordering::Relaxed);
+        if pause_guard.is_none() {
+            *pause_guard = Some(self.write_lock.clone().lock_owned().await);
+            tracing::info!("Paused writes");
+        }
+    }
+
+    /// Resumes the writes paused by [`TransactionService::pause`].
+    pub async fn resume(&self) {
+        let mut pause_guard = self.pause_guard.lock().await;
+        if pause_guard.take().is_some() {
+            tracing::info!("Resumed writes");
+        }
+        self.paused.store(false, Ordering::Relaxed);
+    }
+
+    /// Whether writes are paused, see [`TransactionService::pause`].
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// How `client_id` appears in logs.
+    fn client_label(&self, client_id: ClientId) -> String {
+        match &self.pseudonymizer {
+            Some(pseudonymizer) => pseudonymizer.client(client_id),
+            None => client_id.to_string(),
+        }
+    }
+
+    /// [`TransactionError::ClientNotFound`] for `client_id`.
+    fn client_not_found(&self, client_id: ClientId) -> TransactionError {
+        TransactionError::ClientNotFound {
+            client_id,
+            client: self.client_label(client_id),
+        }
+    }
+
+    /// [`TransactionError::ClientLocked`] for `client_id`.
+    fn client_locked(&self, client_id: ClientId) -> TransactionError {
+        TransactionError::ClientLocked {
+            client_id,
+            client: self.client_label(client_id),
+        }
+    }
+
+    /// Gets a single client by id.
+    pub async fn get_client(&self, client_id: ClientId) -> Result<Option<Client>> {
+        let client = Self::fetch_client(self.read_pool(), client_id).await?;
+        Ok(client.map(|c| c.into_client(self.precision)))
+    }
+
+    /// Streams the clients matching `filter`, ordered by client id.
+    pub async fn get_clients(
+        &self,
+        filter: &ClientFilter,
+        pagination: Pagination<ClientId>,
+    ) -> impl Stream<Item = Result<Client>> + '_ {
+        let precision = self.precision;
+        let total_bound = |amount: Option<Decimal>| {
+            amount.map(|a| {
+                precision.to_storage(a).unwrap_or(if a.is_sign_negative() {
+                    i64::MIN
+                } else {
+                    i64::MAX
+                })
+            })
+        };
+
+        sqlx::query_as::<_, ClientDb>(
+            "SELECT *, (held+available) as total from Clients
+            WHERE (?1 = 0 OR locked)
+                AND (?2 IS NULL OR (held+available) >= ?2)
+                AND (?3 IS NULL OR (held+available) <= ?3)
+                AND (?4 IS NULL OR EXISTS (SELECT 1 FROM [ClientTags] g WHERE g.client_id = id AND g.tag = ?4))
+                AND (?5 IS NULL OR id > ?5)
+                AND (?7 IS NULL OR EXISTS (SELECT 1 FROM [BatchClients] b WHERE b.batch_id = ?7 AND b.client_id = id))
+                AND (?8 IS NULL OR risk_score >= ?8)
+            ORDER BY id
+            LIMIT ?6",
+        )
+        .bind(filter.locked_only)
+        .bind(total_bound(filter.min_total))
+        .bind(total_bound(filter.max_total))
+        .bind(filter.tag.clone())
+        .bind(pagination.after)
+        .bind(pagination.sql_limit())
+        .bind(filter.changed_in_batch)
+        .bind(filter.min_risk_score)
+        .fetch(self.read_pool())
+        .map(move |cstream_client| {
+            cstream_client
+                .map(|c| c.into_client(precision))
+                .map_err(Into::into)
+        })
+    }
+
+    /// Gets the clients that have not made a transaction for at least `inactive_for`, ordered
+    /// by client id. Activity is any applied transaction of the client, or a withdrawal rejected
+    /// for insufficient funds, not changes made by operators.
+    pub async fn get_dormant_clients(&self, inactive_for: Duration) -> Result<Vec<DormantClient>> {
+        let cutoff = self
+            .clock
+            .unix_millis()
+            .saturating_sub(i64::try_from(inactive_for.as_millis()).unwrap_or(i64::MAX));
+        let rows = sqlx::query(
+            "SELECT *, (held+available) as total FROM Clients
+            WHERE last_activity_at IS NULL OR last_activity_at <= ?
+            ORDER BY id",
+        )
+        .bind(cutoff)
+        .fetch_all(self.read_pool())
+        .await?;
+        rows.iter()
+            .map(|row| {
+                Ok(DormantClient {
+                    client: ClientDb::from_row(row)?.into_client(self.precision),
+                    last_activity_at: row.try_get("last_activity_at")?,
+                })
+            })
+            .collect()
+    }
+
+    /// Gets every client with the counts and sums of its applied transactions, ordered by
+    /// client id.
+    pub async fn get_client_stats(&self) -> Result<Vec<ClientWithStats>> {
+        let rows = sqlx::query("SELECT *, (held+available) as total FROM Clients ORDER BY id")
+            .fetch_all(self.read_pool())
+            .await?;
+        rows.iter()
+            .map(|row| {
+                Ok(ClientWithStats {
+                    client: ClientDb::from_row(row)?.into_client(self.precision),
+                    stats: ClientStatsDb::from_row(row)?.into_stats(self.precision),
+                })
+            })
+            .collect()
+    }
+
+    /// Reads the `clients` with the most deposits and withdrawals, with their transactions
+    /// and disputes, so the pages they are stored in are cached before the first batch
+    /// rather than read from disk as it runs. Returns the number of clients read.
+    ///
+    /// There is no cache of clients in the service itself; this fills the page cache of the
+    /// connection used and the operating system's file cache.
+    pub async fn warm_up(&self, clients: u32) -> Result<usize> {
+        let ids: Vec<(i64,)> = sqlx::query_as(
+            "SELECT id FROM Clients ORDER BY deposits + withdrawals DESC, id LIMIT ?",
+        )
+        .bind(clients)
+        .fetch_all(&self.pool)
+        .await?;
+        for (id,) in &ids {
+            sqlx::query(
+                "SELECT * FROM Clients c
+                LEFT JOIN Transactions t ON t.client_id = c.id
+                LEFT JOIN Disputes d ON d.transaction_id = t.id
+                WHERE c.id = ?",
+            )
+            .bind(id)
+            .fetch_all(&self.pool)
+            .await?;
+        }
+        tracing::debug!(clients = ids.len(), "Warmed up the most active clients");
+        Ok(ids.len())
+    }
+
+    /// Gets the house accounts, the counterparties of every deposit, withdrawal, chargeback and
+    /// adjustment, with the funds owed to clients. Cash always equals the client funds plus
+    /// the fees account, changes made by custom transaction types aside.
+    pub async fn get_cash_position(&self) -> Result<CashPosition> {
+        // Read together, without a write in between
+        let (_write, mut tx) = self.begin_write().await?;
+        let accounts = self.fetch_house_accounts(&mut *tx).await?;
+        let (client_funds,): (i64,) =
+            sqlx::query_as("SELECT COALESCE(SUM(held + available), 0) FROM Clients")
+                .fetch_one(&mut *tx)
+                .await?;
+        tx.commit().await?;
+        Ok(CashPosition {
+            accounts,
+            client_funds: self.precision.to_decimal(client_funds),
+        })
+    }
+
+    /// Gets the stored external client ids, to resolve those of an input with
+    /// [`TransactionReader::external_ids`](super::TransactionReader::external_ids). External
+    /// ids seen for the first time are given client ids after the highest one in use.
+    pub async fn get_external_ids(&self) -> Result<ExternalIds> {
+        let (_write, mut tx) = self.begin_write().await?;
+        let mappings = Self::fetch_external_ids(&mut *tx).await?;
+        let (last,): (Option<ClientId>,) = sqlx::query_as("SELECT MAX(id) FROM Clients")
+            .fetch_one(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(ExternalIds::with_mappings(
+            mappings,
+            last.unwrap_or_default(),
+        ))
+    }
+
+    /// Stores the mappings `external_ids` created since it was last saved. Fails if another
+    /// process stored a mapping for the same external id or client in the meantime.
+    pub async fn save_external_ids(&self, external_ids: &ExternalIds) -> Result<()> {
+        let created = external_ids.take_created();
+        if created.is_empty() {
+            return Ok(());
+        }
+        let (_write, mut tx) = self.begin_write().await?;
+        for mapping in &created {
+            sqlx::query("INSERT INTO ExternalIds (external_id, client_id) VALUES (?, ?)")
+                .bind(&mapping.external_id)
+                .bind(mapping.client)
+                .execute(&mut *tx)
+                .await?;
+        }
+        self.commit(tx).await?;
+        tracing::info!(created = created.len(), "Stored new external client ids");
+        Ok(())
+    }
+
+    /// Collects every client into a [`Vec`].
+    pub async fn get_clients_vec(&self) -> Result<Vec<Client>> {
+        sqlx::query_as("SELECT *, (held+available) as total from Clients")
+            .fetch_all(self.read_pool())
+            .await
+            .map(|cstream_client: Vec<ClientDb>| {
+                cstream_client
+                    .into_iter()
+                    .map(|c| c.into_client(self.precision))
+                    .collect()
+            })
+            .map_err(Into::into)
+    }
+
+    /// Gets a stored deposit or withdrawal by its transaction id.
+    pub async fn get_transaction(
+        &self,
+        transaction_id: TransactionId,
+    ) -> Result<Option<Transaction>> {
+        let transaction = Self::fetch_transaction(self.read_pool(), transaction_id).await?;
+        transaction
+            .map(|t| t.into_transaction(self.precision))
+            .transpose()
+    }
+
+    /// Gets the stored deposits and withdrawals with an external `reference`, such as the one
+    /// on a bank statement, ordered by transaction id.
+    pub async fn get_transactions_by_reference(&self, reference: &str) -> Result<Vec<Transaction>> {
+        sqlx::query_as::<_, DBTransaction>(
+            "SELECT * FROM [Transactions] WHERE reference = ? ORDER BY id",
+        )
+        .bind(reference)
+        .fetch_all(self.read_pool())
+        .await?
+        .into_iter()
+        .map(|t| t.into_transaction(self.precision))
+        .collect()
+    }
+
+    /// Streams the deposits and withdrawals made by a client, ordered by transaction id.
+    pub fn get_client_transactions(
+        &self,
+        client_id: ClientId,
+        filter: &TransactionFilter,
+        pagination: Pagination<TransactionId>,
+    ) -> impl Stream<Item = Result<Transaction>> + '_ {
+        let filter = TransactionFilter {
+            client_id: Some(client_id),
+            ..filter.clone()
+        };
+        self.search_transactions(&filter, pagination)
+    }
+
+    /// Streams the stored deposits and withdrawals matching `filter`, ordered by transaction
+    /// id.
+    pub fn search_transactions(
+        &self,
+        filter: &TransactionFilter,
+        pagination: Pagination<TransactionId>,
+    ) -> impl Stream<Item = Result<Transaction>> + '_ {
+        let precision = self.precision;
+        let query = concat!(
+            "SELECT t.* FROM [Transactions] t
+            WHERE ",
+            transaction_filter!(),
+            " AND (?9 IS NULL OR t.id > ?9)
+            ORDER BY t.id
+            LIMIT ?10"
+        );
+        bind_transaction_filter(sqlx::query_as::<_, DBTransaction>(query), filter, precision)
+            .bind(pagination.after.map(db_id))
+            .bind(pagination.sql_limit())
+            .fetch(self.read_pool())
+            .map(move |t| t?.into_transaction(precision))
+    }
+
+    /// Counts and sums the stored deposits and withdrawals matching `filter` in groups,
+    /// ordered by group. Withdrawals rejected for insufficient funds are stored, and counted,
+    /// too.
+    pub async fn get_aggregates(
+        &self,
+        group_by: GroupBy,
+        filter: &TransactionFilter,
+    ) -> Result<Vec<TransactionAggregate>> {
+        let group = match group_by {
+            GroupBy::TransactionType => "t.[type]",
+            GroupBy::Client => "t.client_id",
+            GroupBy::Day => "date(t.created_at / 1000, 'unixepoch')",
+        };
+        let query = format!(
+            "SELECT CAST({group} AS TEXT) AS [group], COUNT(*) AS count,
+                COALESCE(SUM(t.amount), 0) AS amount
+            FROM [Transactions] t
+            WHERE {filter}
+            GROUP BY {group}
+            ORDER BY {group}",
+            group = group,
+            filter = transaction_filter!()
+        );
+        let rows: Vec<(Option<String>, i64, i64)> =
+            bind_transaction_filter(sqlx::query_as(&query), filter, self.precision)
+                .fetch_all(self.read_pool())
+                .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(group, count, amount)| TransactionAggregate {
+                group,
+                count: count as u64,
+                amount: self.precision.to_decimal(amount),
+            })
+            .collect())
+    }
+
+    /// Starts a database transaction that writes, holding the returned guard until it is
+    /// committed or dropped.
+    ///
+    /// Sqlite only allows one writer at a time, and concurrent write transactions on the pool
+    /// fail as deadlocked instead of waiting for each other, so they are serialized here, in
+    /// the lane of the current [`WritePriority`](super::WritePriority).
+    async fn begin_write(&self) -> Result<(WriteGuard<'_>, sqlx::Transaction<'_, Sqlite>)> {
+        let started = Instant::now();
+        let guard = self.write_lanes.lock(&self.write_lock).await;
+        // Left by a write that was rolled back
+        self.pending_alerts.lock().unwrap().clear();
+        if self.slow_transaction.is_some() {
+            let mut steps = self.write_steps.lock().unwrap();
+            steps.start(started);
+            steps.finish("write_lock");
+        }
+        let tx = self.pool.begin().await?;
+        self.step("begin");
+        Ok((guard, tx))
+    }
+
+    /// Runs a write again after transient database errors, as allowed by the service's
+    /// [`RetryPolicy`]. Each attempt must be a whole database transaction.
+    async fn with_retries<T, F, Fut>(&self, mut write: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match write().await {
+                Err(e) if e.is_transient() && attempt < self.retry.max_attempts => {
+                    let backoff = self.retry.backoff(attempt);
+                    tracing::warn!(
+                        attempt,
+                        backoff_ms = backoff.as_millis() as u64,
+                        error = %e,
+                        "Retrying a write after a transient database error"
+                    );
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Ends a step of the current write, see [`WriteSteps`].
+    fn step(&self, step: &'static str) {
+        if self.slow_transaction.is_some() {
+            self.write_steps.lock().unwrap().finish(step);
+        }
+    }
+
+    /// Logs the steps of the current write when it took longer than the slow transaction
+    /// threshold. Called before releasing the write lock, while they are still its steps.
+    fn warn_if_slow(&self, transaction: &Transaction, outcome: &Result<TransactionOutcome>) {
+        let Some(threshold) = self.slow_transaction else {
+            return;
+        };
+        let steps = self.write_steps.lock().unwrap();
+        let elapsed = steps.elapsed();
+        if elapsed < threshold {
+            return;
+        }
+        let (slowest_step, slowest) = steps.slowest().unwrap_or(("none", Duration::ZERO));
+        tracing::warn!(
+            tx = transaction.id,
+            r#type = transaction.transaction_type.to_str(),
+            client = %self.client_label(transaction.client_id),
+            outcome = outcome.as_ref().map_or("error", |o| o.to_str()),
+            elapsed_ms = elapsed.as_millis() as u64,
+            slowest_step,
+            slowest_ms = slowest.as_millis() as u64,
+            steps = %steps.summary(),
+            "Slow transaction"
+        );
+    }
+
+    /// Commits a database transaction started with [`Self::begin_write`].
+    async fn commit(&self, tx: sqlx::Transaction<'_, Sqlite>) -> Result<()> {
+        #[cfg(feature = "chaos")]
+        if let Some(chaos) = &self.chaos {
+            chaos.before_commit().await?;
+        }
+        tx.commit()
+            .instrument(tracing::debug_span!("commit"))
+            .await?;
+        self.step("commit");
+        let alerts = std::mem::take(&mut *self.pending_alerts.lock().unwrap());
+        for alert in &alerts {
+            self.raise(alert);
+        }
+        #[cfg(feature = "chaos")]
+        if let Some(chaos) = &self.chaos {
+            chaos.after_commit()?;
+        }
+        Ok(())
+    }
+
+    /// Logs, counts and notifies the observers of `alert`.
+    fn raise(&self, alert: &Alert) {
+        tracing::warn!(
+            alert = alert.kind.to_str(),
+            client = %self.client_label(alert.client_id),
+            available = %alert.available,
+            held = %alert.held,
+            threshold = %alert.threshold,
+            "Client crossed an alert threshold"
+        );
+        match alert.kind {
+            AlertKind::LowAvailable => &self.low_available_alerts,
+            AlertKind::HighHeld => &self.high_held_alerts,
+            AlertKind::BalanceOverflow => &self.balance_overflow_alerts,
+        }
+        .fetch_add(1, Ordering::Relaxed);
+        for observer in &self.observers {
+            observer.on_alert(alert);
+        }
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub(super) async fn fetch_client<'e>(
+        executor: impl Executor<'e, Database = Sqlite>,
+        client_id: ClientId,
+    ) -> Result<Option<ClientDb>> {
+        sqlx::query_as("SELECT *, (held+available) as total from [Clients] WHERE id=? LIMIT 1")
+            .bind(client_id)
+            .fetch_optional(executor)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(level = "debug", skip(executor))]
+    async fn fetch_transaction<'e>(
+        executor: impl Executor<'e, Database = Sqlite>,
+        transaction_id: TransactionId,
+    ) -> Result<Option<DBTransaction>> {
+        sqlx::query_as("SELECT * FROM [Transactions] WHERE id=? LIMIT 1")
+            .bind(db_id(transaction_id))
+            .fetch_optional(executor)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// The client the stored transaction `transaction_id` was made by.
+    async fn transaction_owner(&self, transaction_id: TransactionId) -> Result<Option<ClientId>> {
+        sqlx::query_scalar("SELECT client_id FROM [Transactions] WHERE id=? LIMIT 1")
+            .bind(db_id(transaction_id))
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Waits for the lock of the owner of the stored transaction `transaction_id`, if it is
+    /// stored.
+    async fn lock_owner(&self, transaction_id: TransactionId) -> Result<Option<ClientGuard<'_>>> {
+        Ok(match self.transaction_owner(transaction_id).await? {
+            Some(client_id) => Some(self.client_locks.lock(client_id).await),
+            None => None,
+        })
+    }
+
+    /// Waits for the locks of the clients `transactions` can change: the client of each, and
+    /// the owner of the disputed transaction for disputes, resolves and chargebacks.
+    ///
+    /// A disputed transaction stored while waiting, by a submission of its own client, is
+    /// noticed after taking the locks, which are then taken again including its owner.
+    async fn lock_clients(&self, transactions: &[&Transaction]) -> Result<Vec<ClientGuard<'_>>> {
+        let mut locked = BTreeSet::new();
+        let mut guards = Vec::new();
+        loop {
+            let mut clients = BTreeSet::new();
+            for transaction in transactions {
+                clients.insert(transaction.client_id);
+                if matches!(
+                    transaction.transaction_type,
+                    TransactionType::Dispute
+                        | TransactionType::Resolve
+                        | TransactionType::Chargeback
+                ) {
+                    clients.extend(self.transaction_owner(transaction.id).await?);
+                }
+            }
+            if clients.is_subset(&locked) {
+                return Ok(guards);
+            }
+            drop(guards);
+            locked.extend(clients);
+            guards = self.client_locks.lock_all(locked.iter().copied()).await;
+        }
+    }
+
+    /// The policy of the segment of the client, or the default policy.
+    async fn client_policy(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        client_id: ClientId,
+    ) -> Result<&TransactionPolicy> {
+        if self.policy.segments.is_empty() {
+            return Ok(&self.policy);
+        }
+        let tags: Vec<String> = sqlx::query_scalar("SELECT tag FROM ClientTags WHERE client_id=?")
+            .bind(client_id)
+            .fetch_all(tx)
+            .await?;
+        Ok(self.policy.for_tags(&tags))
+    }
+
+    /// Whether changes are recorded as [`LedgerEvent`]s, in the outbox or the audit log.
+    fn records_events(&self) -> bool {
+        self.outbox || self.audit_log
+    }
+
+    /// Adds `event` to the outbox, to be shipped once `tx` commits, and chains it to the end
+    /// of the audit log, for the ones that are enabled.
+    async fn record_event(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        client_id: ClientId,
+        event: &LedgerEvent,
+    ) -> Result<()> {
+        let payload = serde_json::to_string(event)?;
+        let created_at = self.clock.unix_millis();
+        if self.outbox {
+            sqlx::query("INSERT INTO Outbox (client_id, payload, created_at) VALUES (?, ?, ?)")
+                .bind(client_id)
+                .bind(&payload)
+                .bind(created_at)
+                .execute(&mut *tx)
+                .await?;
+        }
+        if self.audit_log {
+            // Writes are serialized by begin_write, so the last record can not change under us
+            let prev_hash: Option<(String,)> =
+                sqlx::query_as("SELECT hash FROM AuditLog ORDER BY id DESC LIMIT 1")
+                    .fetch_optional(&mut *tx)
+                    .await?;
+            let prev_hash = prev_hash.map_or_else(|| GENESIS_HASH.to_string(), |(hash,)| hash);
+            sqlx::query("INSERT INTO AuditLog (event, created_at, hash) VALUES (?, ?, ?)")
+                .bind(&payload)
+                .bind(created_at)
+                .bind(chain_hash(&prev_hash, created_at, &content_hash(&payload)))
+                .execute(&mut *tx)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Fetches a client that was just changed, and so must exist.
+    async fn fetch_updated_client<'e>(
+        &self,
+        executor: impl Executor<'e, Database = Sqlite>,
+        client_id: ClientId,
+    ) -> Result<Client> {
+        let client = Self::fetch_client(executor, client_id)
+            .await?
+            .ok_or_else(|| self.client_not_found(client_id))?;
+        Ok(client.into_client(self.precision))
+    }
+
+    /// Applies a single transaction in its own database transaction.
+    ///
+    /// Transactions that can not be applied (locked clients, insufficient funds, unknown
+    /// dispute targets) are not treated as errors, the returned [`TransactionOutcome`]
+    /// describes what happened.
+    #[tracing::instrument(skip_all, fields(tx = transaction.id))]
+    pub async fn process_transaction(
+        &self,
+        transaction: &Transaction,
+    ) -> Result<TransactionOutcome> {
+        let _clients = self.lock_clients(&[transaction]).await?;
+        let outcome = self
+            .with_retries(|| async {
+                let (write, mut tx) = self.begin_write().await?;
+                let outcome = match self.apply(&mut tx, transaction).await {
+                    Ok(outcome) => self.commit(tx).await.map(|_| outcome),
+                    Err(e) => Err(e),
+                };
+                self.warn_if_slow(transaction, &outcome);
+                drop(write);
+                outcome
+            })
+            .await?;
+
+        self.notify(transaction, &outcome);
+
+        Ok(outcome)
+    }
+
+    /// Applies `transactions` in order without committing them, returning their outcomes and
+    /// the balances the clients they change would have before and after, to see what a sequence of
+    /// transactions, such as chargebacks of several deposits, would do to the ledger.
+    ///
+    /// Nothing is stored, sent to the observers or counted. Other writes wait until the
+    /// simulation is done.
+    #[tracing::instrument(skip_all, fields(size = transactions.len()))]
+    pub async fn simulate(&self, transactions: &[Transaction]) -> Result<Projection> {
+        let (_write, mut tx) = self.begin_write().await?;
+        let mut outcomes = Vec::with_capacity(transactions.len());
+        let mut changed = BTreeSet::new();
+        let mut before = HashMap::new();
+        for transaction in transactions {
+            // The clients the transaction can change, before it does
+            let mut touched = vec![transaction.client_id];
+            if matches!(
+                transaction.transaction_type,
+                TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback
+            ) {
+                if let Some(disputed) = Self::fetch_transaction(&mut *tx, transaction.id).await? {
+                    touched.push(disputed.client_id);
+                }
+            }
+            for client_id in touched {
+                if let std::collections::hash_map::Entry::Vacant(entry) = before.entry(client_id) {
+                    let client = Self::fetch_client(&mut *tx, client_id).await?;
+                    entry.insert(client.map(|c| c.into_client(self.precision)));
+                }
+            }
+            let outcome = self.apply(&mut tx, transaction).await?;
+            changed.insert(outcome.changed_client(transaction));
+            outcomes.push(outcome);
+        }
+        let mut clients = Vec::with_capacity(changed.len());
+        let mut previous = Vec::with_capacity(changed.len());
+        for client_id in changed {
+            if let Some(client) = Self::fetch_client(&mut *tx, client_id).await? {
+                clients.push(client.into_client(self.precision));
+                previous.push(before.remove(&client_id).flatten());
+            }
+        }
+        tx.rollback().await?;
+        Ok(Projection {
+            outcomes,
+            clients,
+            before: previous,
+        })
+    }
+
+    /// Applies every transaction from `transactions`, committing them in batches of up to
+    /// [`TransactionServiceBuilder::batch_size`] transactions.
+    ///
+    /// Processing stops at the first error, either from the stream or from applying a
+    /// transaction. Transactions in the batch that failed are rolled back, earlier batches
+    /// stay committed.
+    #[tracing::instrument(skip_all)]
+    pub async fn process_stream<S>(&self, transactions: S) -> Result<ProcessingOutcome>
+    where
+        S: Stream<Item = Result<Transaction>>,
+    {
+        let transactions = transactions.map(|t| t.map(|t| (0, None, t)));
+        self.process_lines(None, None, transactions, |_, _, _, _| Ok(()))
+            .await
+    }
+
+    /// Starts a batch of transactions read from `source`, such as an input file, with the
+    /// hex sha256 of its contents. See [`TransactionService::process_batch`].
+    pub async fn begin_batch(&self, source: &str, sha256: Option<&str>) -> Result<Batch> {
+        // In a transaction, as a RETURNING statement run on the pool can leave its connection
+        // holding the write lock
+        let (_write, mut tx) = self.begin_write().await?;
+        let batch = sqlx::query_as(
+            "INSERT INTO Batches (source, sha256, created_at) VALUES (?, ?, ?) RETURNING *",
+        )
+        .bind(source)
+        .bind(sha256)
+        .bind(self.clock.unix_millis())
+        .fetch_one(&mut *tx)
+        .await?;
+        self.commit(tx).await?;
+        Ok(batch)
+    }
+
+    /// Like [`TransactionService::process_stream`], also recording the `batch` and the line of
+    /// its source each stored deposit and withdrawal came from, see
+    /// [`TransactionService::get_provenance`].
+    pub async fn process_batch<S>(
+        &self,
+        batch: &Batch,
+        transactions: S,
+    ) -> Result<ProcessingOutcome>
+    where
+        S: Stream<Item = Result<(u64, Transaction)>>,
+    {
+        self.process_batch_with(batch, transactions, |_, _, _| Ok(()))
+            .await
+    }
+
+    /// Like [`TransactionService::process_batch`], also calling `on_outcome` with the line,
+    /// the transaction and its outcome for every transaction, once it has been committed.
+    /// Processing stops at the first error returned by `on_outcome`.
+    #[tracing::instrument(skip_all, fields(batch = batch.id))]
+    pub async fn process_batch_with<S, F>(
+        &self,
+        batch: &Batch,
+        transactions: S,
+        mut on_outcome: F,
+    ) -> Result<ProcessingOutcome>
+    where
+        S: Stream<Item = Result<(u64, Transaction)>>,
+        F: FnMut(u64, &Transaction, &TransactionOutcome) -> Result<()>,
+    {
+        let transactions = transactions.map(|t| t.map(|(line, t)| (line, None, t)));
+        self.process_lines(Some(batch.id), None, transactions, |line, _, t, outcome| {
+            on_outcome(line, t, outcome)
+        })
+        .await
+    }
+
+    /// Applies every transaction from a partitioned log such as a kafka topic, storing the
+    /// offset of the last message applied from each partition under `consumer` in the same
+    /// database transaction as the transactions. A consumer that resumes after the offsets
+    /// from [`TransactionService::get_source_offsets`] neither applies a message twice nor
+    /// skips one after a crash, and messages at or before a stored offset are skipped.
+    ///
+    /// Like [`TransactionService::process_stream`] otherwise, calling `on_outcome` with the
+    /// offset, the transaction and its outcome for every transaction once it has been
+    /// committed.
+    #[tracing::instrument(skip(self, transactions, on_outcome))]
+    pub async fn process_with_offsets<S, F>(
+        &self,
+        consumer: &str,
+        transactions: S,
+        mut on_outcome: F,
+    ) -> Result<ProcessingOutcome>
+    where
+        S: Stream<Item = Result<(SourceOffset, Transaction)>>,
+        F: FnMut(SourceOffset, &Transaction, &TransactionOutcome) -> Result<()>,
+    {
+        if consumer.is_empty() {
+            return Err(TransactionError::InvalidArgument(
+                "Offsets must be stored under a consumer name".into(),
+            ));
+        }
+        let transactions =
+            transactions.map(|t| t.map(|(offset, t)| (offset.offset as u64, Some(offset), t)));
+        self.process_lines(
+            None,
+            Some(consumer),
+            transactions,
+            |_, offset, t, outcome| match offset {
+                Some(offset) => on_outcome(offset, t, outcome),
+                None => Ok(()),
+            },
+        )
+        .await
+    }
+
+    /// Gets the offset of the last message applied from each partition by
+    /// [`TransactionService::process_with_offsets`] as `consumer`, by partition.
+    pub async fn get_source_offsets(&self, consumer: &str) -> Result<BTreeMap<i32, i64>> {
+        Self::fetch_source_offsets(&self.pool, consumer).await
+    }
+
+    async fn fetch_source_offsets<'e>(
+        executor: impl Executor<'e, Database = Sqlite>,
+        consumer: &str,
+    ) -> Result<BTreeMap<i32, i64>> {
+        let offsets: Vec<(i32, i64)> =
+            sqlx::query_as("SELECT [partition], [offset] FROM SourceOffsets WHERE consumer = ?")
+                .bind(consumer)
+                .fetch_all(executor)
+                .await?;
+        Ok(offsets.into_iter().collect())
+    }
+
+    /// Gets the batch and line a stored deposit or withdrawal came from, if it was processed
+    /// with [`TransactionService::process_batch`].
+    pub async fn get_provenance(
+        &self,
+        transaction_id: TransactionId,
+    ) -> Result<Option<Provenance>> {
+        let row: Option<(i64, String, Option<String>, i64, i64)> = sqlx::query_as(
+            "SELECT b.id, b.source, b.sha256, b.created_at, t.line FROM [Transactions] t
+            INNER JOIN Batches b ON b.id = t.batch_id
+            WHERE t.id = ?",
+        )
+        .bind(db_id(transaction_id))
+        .fetch_optional(self.read_pool())
+        .await?;
+        Ok(
+            row.map(|(id, source, sha256, created_at, line)| Provenance {
+                batch: Batch {
+                    id,
+                    source,
+                    sha256,
+                    created_at,
+                },
+                line: line as u64,
+            }),
+        )
+    }
+
+    /// Processes `transactions` with their lines, recording them with the stored
+    /// transactions when `batch_id` is set, and their offsets under `consumer` when it is set.
+    async fn process_lines<S, F>(
+        &self,
+        batch_id: Option<i64>,
+        consumer: Option<&str>,
+        transactions: S,
+        mut on_outcome: F,
+    ) -> Result<ProcessingOutcome>
+    where
+        S: Stream<Item = Result<SourcedTransaction>>,
+        F: FnMut(u64, Option<SourceOffset>, &Transaction, &TransactionOutcome) -> Result<()>,
+    {
+        let mut summary = ProcessingOutcome::default();
+        let mut batches = pin!(transactions.ready_chunks(self.batch_size));
+
+        while let Some(batch) = batches.next().await {
+            // Nothing of a batch with a read error would be committed
+            let batch = batch.into_iter().collect::<Result<Vec<_>>>()?;
+            let _clients = self
+                .lock_clients(&batch.iter().map(|(_, _, t)| t).collect::<Vec<_>>())
+                .await?;
+            let outcomes = self
+                .with_retries(|| self.apply_batch(batch_id, consumer, &batch))
+                .await?;
+            for ((line, offset, transaction), outcome) in &outcomes {
+                summary.record(outcome);
+                self.notify(transaction, outcome);
+                on_outcome(*line, *offset, transaction, outcome)?;
+            }
+        }
+
+        Ok(summary)
+    }
+
+    /// Applies `transaction` as part of a batch, in a savepoint when
+    /// [`TransactionServiceBuilder::isolate_failures`] is set so that only its own changes are
+    /// rolled back when it fails.
+    async fn apply_in_batch(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        transaction: &Transaction,
+    ) -> Result<TransactionOutcome> {
+        if !self.isolate_failures {
+            return self.apply(tx, transaction).await;
+        }
+        let alerts = self.pending_alerts.lock().unwrap().len();
+        let mut savepoint = tx.begin().await?;
+        match self.apply(&mut savepoint, transaction).await {
+            Ok(outcome) => {
+                savepoint.commit().await?;
+                Ok(outcome)
+            }
+            Err(e)
+                if e.is_unavailable()
+                    || matches!(e, TransactionError::InvalidTransaction { .. }) =>
+            {
+                Err(e)
+            }
+            Err(e) => {
+                savepoint.rollback().await?;
+                self.pending_alerts.lock().unwrap().truncate(alerts);
+                tracing::warn!(
+                    transaction_id = transaction.id,
+                    error = %e,
+                    "Rolled back a transaction that failed, continuing with its batch"
+                );
+                Ok(TransactionOutcome::Rejected {
+                    reason: TransactionOutcome::FAILED.to_string(),
+                })
+            }
+        }
+    }
+
+    /// Applies a batch of transactions in one database transaction.
+    #[tracing::instrument(skip_all, fields(size = batch.len()))]
+    async fn apply_batch(
+        &self,
+        batch_id: Option<i64>,
+        consumer: Option<&str>,
+        batch: &[SourcedTransaction],
+    ) -> Result<Vec<(SourcedTransaction, TransactionOutcome)>> {
+        let (_write, mut tx) = self.begin_write().await?;
+        let mut offsets = match consumer {
+            Some(consumer) => Self::fetch_source_offsets(&mut *tx, consumer).await?,
+            None => BTreeMap::new(),
+        };
+        let mut advanced = BTreeSet::new();
+        let mut outcomes = Vec::with_capacity(batch.len());
+        for (line, offset, transaction) in batch {
+            if let Some(SourceOffset { partition, offset }) = *offset {
+                if offsets.get(&partition).is_some_and(|last| offset <= *last) {
+                    tracing::debug!(partition, offset, "Skipping an applied message");
+                    continue;
+                }
+                offsets.insert(partition, offset);
+                advanced.insert(partition);
+            }
+            let outcome = self.apply_in_batch(&mut tx, transaction).await?;
+            let stored = matches!(
+                outcome,
+                TransactionOutcome::Deposit
+                    | TransactionOutcome::Withdrawal
+                    | TransactionOutcome::WithdrawalRejected
+            );
+            if let Some(batch_id) = batch_id.filter(|_| stored) {
+                sqlx::query("UPDATE [Transactions] SET batch_id = ?, line = ? WHERE id = ?")
+                    .bind(batch_id)
+                    .bind(*line as i64)
+                    .bind(db_id(transaction.id))
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            if let Some(batch_id) = batch_id.filter(|_| outcome.is_applied()) {
+                sqlx::query(
+                    "INSERT OR IGNORE INTO BatchClients (batch_id, client_id) VALUES (?, ?)",
+                )
+                .bind(batch_id)
+                .bind(outcome.changed_client(transaction))
+                .execute(&mut *tx)
+                .await?;
+            }
+            outcomes.push(((*line, *offset, transaction.clone()), outcome));
+        }
+        if let Some(consumer) = consumer {
+            for partition in advanced {
+                sqlx::query(
+                    "INSERT INTO SourceOffsets (consumer, [partition], [offset]) VALUES (?, ?, ?)
+                    ON CONFLICT (consumer, [partition]) DO UPDATE SET [offset] = excluded.[offset]",
+                )
+                .bind(consumer)
+                .bind(partition)
+                .bind(offsets[&partition])
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+        self.commit(tx).await?;
+        Ok(outcomes)
+    }
+
+    /// Applies `transactions` in a single database transaction, at most once per
+    /// `idempotency_key`.
+    ///
+    /// The key is stored alongside the submitted transactions and their outcomes. Submitting
+    /// the same transactions with the key again returns the stored outcomes without applying
+    /// anything, while submitting different transactions with it fails with
+    /// [`TransactionError::IdempotencyKeyReused`].
+    #[tracing::instrument(skip(self, transactions), fields(size = transactions.len()))]
+    pub async fn process_transactions_once(
+        &self,
+        idempotency_key: &str,
+        transactions: &[Transaction],
+    ) -> Result<Vec<TransactionOutcome>> {
+        if idempotency_key.is_empty() || idempotency_key.len() > MAX_IDEMPOTENCY_KEY_LEN {
+            return Err(TransactionError::InvalidArgument(format!(
+                "An idempotency key must be between 1 and {} bytes",
+                MAX_IDEMPOTENCY_KEY_LEN
+            )));
+        }
+        let request = serde_json::to_string(transactions)?;
+        let _clients = self
+            .lock_clients(&transactions.iter().collect::<Vec<_>>())
+            .await?;
+        let (outcomes, applied) = self
+            .with_retries(|| self.apply_once(idempotency_key, &request, transactions))
+            .await?;
+        if applied {
+            for (transaction, outcome) in transactions.iter().zip(&outcomes) {
+                self.notify(transaction, outcome);
+            }
+        }
+        Ok(outcomes)
+    }
+
+    /// Applies and commits `transactions` unless `idempotency_key` was already used for
+    /// `request`, returning their outcomes and whether they were applied now.
+    async fn apply_once(
+        &self,
+        idempotency_key: &str,
+        request: &str,
+        transactions: &[Transaction],
+    ) -> Result<(Vec<TransactionOutcome>, bool)> {
+        let (_write, mut tx) = self.begin_write().await?;
+
+        let stored: Option<(String, String)> =
+            sqlx::query_as("SELECT request, outcomes FROM IdempotencyKeys WHERE [key]=?")
+                .bind(idempotency_key)
+                .fetch_optional(&mut *tx)
+                .await?;
+        if let Some((stored_request, outcomes)) = stored {
+            if stored_request != request {
+                return Err(TransactionError::IdempotencyKeyReused {
+                    key: idempotency_key.to_string(),
+                });
+            }
+            return Ok((serde_json::from_str(&outcomes)?, false));
+        }
+
+        let mut outcomes = Vec::with_capacity(transactions.len());
+        for transaction in transactions {
+            outcomes.push(self.apply_in_batch(&mut tx, transaction).await?);
+        }
+
+        sqlx::query("INSERT INTO IdempotencyKeys ([key], request, outcomes) VALUES (?, ?, ?)")
+            .bind(idempotency_key)
+            .bind(request)
+            .bind(serde_json::to_string(&outcomes)?)
+            .execute(&mut *tx)
+            .await?;
+        self.commit(tx).await?;
+        Ok((outcomes, true))
+    }
+
+    /// Applies a single transaction at most once per `idempotency_key`, see
+    /// [`TransactionService::process_transactions_once`].
+    pub async fn process_transaction_once(
+        &self,
+        idempotency_key: &str,
+        transaction: &Transaction,
+    ) -> Result<TransactionOutcome> {
+        let mut outcomes = self
+            .process_transactions_once(idempotency_key, std::slice::from_ref(transaction))
+            .await?;
+        Ok(outcomes
+            .pop()
+            .expect("A stored request has one outcome per transaction"))
+    }
+
+    /// Manually corrects a client's available funds by `delta`, recording the `reason` and the
+    /// `operator` making the change.
+    ///
+    /// Fails if the client does not exist, is locked, or a deduction would leave the client
+    /// with negative available funds. Adjustments matching the service's [`ApprovalRules`]
+    /// are not applied, and fail with [`TransactionError::ApprovalRequired`] until a second
+    /// operator approves them.
+    #[tracing::instrument(
+        skip(self, client_id, reason),
+        fields(client = %self.client_label(client_id))
+    )]
+    pub async fn adjust_balance(
+        &self,
+        client_id: ClientId,
+        delta: Decimal,
+        reason: &str,
+        operator: &str,
+    ) -> Result<Adjustment> {
+        if reason.trim().is_empty() {
+            return Err(TransactionError::InvalidArgument(
+                "An adjustment requires a reason".into(),
+            ));
+        }
+        if operator.trim().is_empty() {
+            return Err(TransactionError::InvalidArgument(
+                "An adjustment requires an operator".into(),
+            ));
+        }
+        let amount = self
+            .precision
+            .to_storage(delta)
+            .filter(|a| *a != 0)
+            .ok_or_else(|| {
+                TransactionError::InvalidArgument(format!("Invalid adjustment amount {}", delta))
+            })?;
+
+        let _client = self.client_locks.lock(client_id).await;
+        let (_write, mut tx) = self.begin_write().await?;
+        self.check_adjustment(&mut tx, client_id, amount).await?;
+        let action = ApprovalAction::Adjustment {
+            amount: self.precision.to_decimal(amount),
+            reason: reason.to_string(),
+        };
+        if self.approvals.requires(&action) {
+            return Err(self.request_approval(tx, client_id, action, operator).await);
+        }
+        let adjustment = self
+            .apply_adjustment(&mut tx, client_id, amount, reason, operator)
+            .await?;
+        self.commit(tx).await?;
+
+        for observer in &self.observers {
+            observer.on_adjustment(&adjustment);
+        }
+
+        Ok(adjustment)
+    }
+
+    /// Checks an adjustment of the client's available funds by `amount` can be applied.
+    ///
+    /// Like [`Self::reject_overflow`] for transactions, rejects an adjustment that would change
+    /// the client's funds, or the fees house account it is booked against, by more than their
+    /// stored fixed point values can hold, raising an [`AlertKind::BalanceOverflow`] alert.
+    async fn check_adjustment(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        client_id: ClientId,
+        amount: i64,
+    ) -> Result<()> {
+        let client = Self::fetch_client(&mut **tx, client_id)
+            .await?
+            .ok_or_else(|| self.client_not_found(client_id))?;
+        if client.locked {
+            return Err(self.client_locked(client_id));
+        }
+        let fees: i64 = sqlx::query_scalar("SELECT balance FROM HouseAccounts WHERE name = ?")
+            .bind(FEES)
+            .fetch_one(&mut **tx)
+            .await?;
+        if client.available.checked_add(amount).is_none()
+            || client.total.checked_add(amount).is_none()
+            || fees.checked_sub(amount).is_none()
+        {
+            let alert = self.overflow_alert(client_id, Some(client));
+            tracing::warn!(
+                client = %self.client_label(client_id),
+                available = %alert.available,
+                held = %alert.held,
+                "Rejected an adjustment overflowing the balances"
+            );
+            // Raised now, as nothing is committed
+            self.raise(&alert);
+            return Err(TransactionError::BalanceOverflow {
+                client_id,
+                client: self.client_label(client_id),
+            });
+        }
+        if client.available + amount < 0 {
+            return Err(TransactionError::InsufficientFunds {
+                client_id,
+                client: self.client_label(client_id),
+            });
+        }
+        Ok(())
+    }
+
+    async fn apply_adjustment(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        client_id: ClientId,
+        amount: i64,
+        reason: &str,
+        operator: &str,
+    ) -> Result<Adjustment> {
+        sqlx::query("UPDATE Clients SET available = (available + ?) WHERE id=?")
+            .bind(amount)
+            .bind(client_id)
+            .execute(&mut **tx)
+            .await?;
+        Self::book(&mut **tx, FEES, -amount).await?;
+
+        let (id,): (i64,) = sqlx::query_as(
+            "INSERT INTO Adjustments (client_id, amount, reason, operator) VALUES (?, ?, ?, ?) RETURNING id",
+        )
+        .bind(client_id)
+        .bind(amount)
+        .bind(reason)
+        .bind(operator)
+        .fetch_one(&mut **tx)
+        .await?;
+
+        let adjustment = Adjustment {
+            id,
+            client_id,
+            amount: self.precision.to_decimal(amount),
+            reason: reason.to_string(),
+            operator: operator.to_string(),
+        };
+        if self.records_events() {
+            let client = self.fetch_updated_client(&mut **tx, client_id).await?;
+            let event = LedgerEvent::Adjustment {
+                adjustment: adjustment.clone(),
+                client,
+            };
+            self.record_event(tx, client_id, &event).await?;
+        }
+        Ok(adjustment)
+    }
+
+    /// Unlocks a client that was locked by a chargeback, so it can transact again. The
+    /// `operator` unlocking it is recorded in the [`LedgerEvent::ClientUnlocked`].
+    ///
+    /// Fails if `operator` is empty, the client does not exist, or with
+    /// [`TransactionError::ClientLocked`] if its chargebacks escalated to
+    /// [`RiskLevel::PermanentlyLocked`]. Unlocking a client that is not locked does nothing.
+    /// When the service's [`ApprovalRules`] cover unlocks, the client stays locked, and this
+    /// fails with [`TransactionError::ApprovalRequired`] until a second operator approves it.
+    #[tracing::instrument(skip_all, fields(client = %self.client_label(client_id), operator = operator))]
+    pub async fn unlock_client(&self, client_id: ClientId, operator: &str) -> Result<Client> {
+        if operator.trim().is_empty() {
+            return Err(TransactionError::InvalidArgument(
+                "An unlock requires an operator".into(),
+            ));
+        }
+        let _client = self.client_locks.lock(client_id).await;
+        let (_write, mut tx) = self.begin_write().await?;
+
+        let client = Self::fetch_client(&mut *tx, client_id)
+            .await?
+            .ok_or_else(|| self.client_not_found(client_id))?;
+        if !client.locked {
+            return Ok(client.into_client(self.precision));
+        }
+        if Self::fetch_risk(&mut *tx, client_id)
+            .await?
+            .is_some_and(|risk| risk.level == RiskLevel::PermanentlyLocked)
+        {
+            return Err(self.client_locked(client_id));
+        }
+        if self.approvals.requires(&ApprovalAction::Unlock) {
+            let action = ApprovalAction::Unlock;
+            return Err(self.request_approval(tx, client_id, action, operator).await);
+        }
+
+        self.apply_unlock(&mut tx, client_id, operator).await?;
+        self.commit(tx).await?;
+
+        for observer in &self.observers {
+            observer.on_client_unlocked(client_id);
+        }
+
+        Ok(Client {
+            locked: false,
+            ..client.into_client(self.precision)
+        })
+    }
+
+    async fn apply_unlock(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        client_id: ClientId,
+        operator: &str,
+    ) -> Result<()> {
+        sqlx::query("UPDATE Clients SET locked = false WHERE id=?")
+            .bind(client_id)
+            .execute(&mut **tx)
+            .await?;
+        if self.records_events() {
+            let client = self.fetch_updated_client(&mut **tx, client_id).await?;
+            let event = LedgerEvent::ClientUnlocked {
+                client,
+                operator: operator.to_string(),
+            };
+            self.record_event(tx, client_id, &event).await?;
+        }
+        Ok(())
+    }
+
+    /// Stores `action` as a pending [`Approval`] and commits, returning the
+    /// [`TransactionError::ApprovalRequired`] to fail the request with.
+    async fn request_approval(
+        &self,
+        mut tx: sqlx::Transaction<'_, Sqlite>,
+        client_id: ClientId,
+        action: ApprovalAction,
+        operator: &str,
+    ) -> TransactionError {
+        let result: Result<Approval> = async {
+            let requested_at = self.clock.unix_millis();
+            let (amount, reason) = match &action {
+                ApprovalAction::Unlock => (None, None),
+                ApprovalAction::Adjustment { amount, reason } => {
+                    (self.precision.to_storage(*amount), Some(reason.as_str()))
+                }
+            };
+            let (id,): (i64,) = sqlx::query_as(
+                "INSERT INTO Approvals (client_id, action, amount, reason, status, requested_by,
+                    requested_at)
+                VALUES (?, ?, ?, ?, ?, ?, ?) RETURNING id",
+            )
+            .bind(client_id)
+            .bind(action.to_str())
+            .bind(amount)
+            .bind(reason)
+            .bind(ApprovalStatus::Pending.to_str())
+            .bind(operator)
+            .bind(requested_at)
+            .fetch_one(&mut *tx)
+            .await?;
+            let approval = Approval {
+                id,
+                client_id,
+                action,
+                status: ApprovalStatus::Pending,
+                requested_by: operator.to_string(),
+                requested_at,
+                decided_by: None,
+                decided_at: None,
+            };
+            if self.records_events() {
+                let event = LedgerEvent::ApprovalRequested {
+                    approval: approval.clone(),
+                };
+                self.record_event(&mut tx, client_id, &event).await?;
+            }
+            self.commit(tx).await?;
+            tracing::info!(approval = id, "Requested a second operator's approval");
+            Ok(approval)
+        }
+        .await;
+        match result {
+            Ok(approval) => {
+                TransactionError::ApprovalRequired(Box::new(approval), self.client_label(client_id))
+            }
+            Err(e) => e,
+        }
+    }
+
+    /// Approves a pending [`Approval`], applying its unlock or adjustment as if it was made by
+    /// the operator who requested it, and returns the decided approval.
+    ///
+    /// Fails if the approval is not pending, `operator` is the one who requested it, or the
+    /// change can no longer be applied, e.g. because an adjustment would now leave the client
+    /// with negative available funds. A failed approval stays pending.
+    #[tracing::instrument(skip(self))]
+    pub async fn approve_request(&self, approval_id: i64, operator: &str) -> Result<Approval> {
+        let client_id: Option<ClientId> =
+            sqlx::query_scalar("SELECT client_id FROM Approvals WHERE id = ?")
+                .bind(approval_id)
+                .fetch_optional(&self.pool)
+                .await?;
+        let _client = match client_id {
+            Some(client_id) => Some(self.client_locks.lock(client_id).await),
+            None => None,
+        };
+        let (_write, mut tx) = self.begin_write().await?;
+        let approval = self.fetch_pending_approval(&mut tx, approval_id).await?;
+        if operator.trim().is_empty() || operator == approval.requested_by {
+            return Err(TransactionError::InvalidArgument(format!(
+                "Approval {} needs a second operator other than {}",
+                approval_id, approval.requested_by
+            )));
+        }
+
+        let client_id = approval.client_id;
+        let requested_by = approval.requested_by.as_str();
+        let mut applied = None;
+        match &approval.action {
+            ApprovalAction::Unlock => {
+                let locked = Self::fetch_client(&mut *tx, client_id)
+                    .await?
+                    .ok_or_else(|| self.client_not_found(client_id))?
+                    .locked;
+                if locked {
+                    if Self::fetch_risk(&mut *tx, client_id)
+                        .await?
+                        .is_some_and(|risk| risk.level == RiskLevel::PermanentlyLocked)
+                    {
+                        return Err(self.client_locked(client_id));
+                    }
+                    self.apply_unlock(&mut tx, client_id, requested_by).await?;
+                }
+            }
+            ApprovalAction::Adjustment { amount, reason } => {
+                let amount = self.precision.to_storage(*amount).ok_or_else(|| {
+                    TransactionError::InvalidArgument(format!(
+                        "Invalid adjustment amount {}",
+                        amount
+                    ))
+                })?;
+                self.check_adjustment(&mut tx, client_id, amount).await?;
+                let adjustment = self
+                    .apply_adjustment(&mut tx, client_id, amount, reason, requested_by)
+                    .await?;
+                applied = Some(adjustment);
+            }
+        }
+        let approval = self
+            .decide_approval(&mut tx, approval, ApprovalStatus::Approved, operator)
+            .await?;
+        self.commit(tx).await?;
+
+        for observer in &self.observers {
+            match &applied {
+                Some(adjustment) => observer.on_adjustment(adjustment),
+                None => observer.on_client_unlocked(client_id),
+            }
+        }
+        Ok(approval)
+    }
+
+    /// Rejects a pending [`Approval`] without applying its change, and returns the decided
+    /// approval. The operator who requested it can reject it to withdraw the request.
+    #[tracing::instrument(skip(self))]
+    pub async fn reject_request(&self, approval_id: i64, operator: &str) -> Result<Approval> {
+        if operator.trim().is_empty() {
+            return Err(TransactionError::InvalidArgument(
+                "A rejection requires an operator".into(),
+            ));
+        }
+        let (_write, mut tx) = self.begin_write().await?;
+        let approval = self.fetch_pending_approval(&mut tx, approval_id).await?;
+        let approval = self
+            .decide_approval(&mut tx, approval, ApprovalStatus::Rejected, operator)
+            .await?;
+        self.commit(tx).await?;
+        Ok(approval)
+    }
+
+    /// Gets the approvals waiting for a second operator, oldest first.
+    pub async fn get_pending_approvals(&self) -> Result<Vec<Approval>> {
+        sqlx::query_as::<_, DBApproval>("SELECT * FROM Approvals WHERE status = ? ORDER BY id")
+            .bind(ApprovalStatus::Pending.to_str())
+            .fetch_all(self.read_pool())
+            .await?
+            .into_iter()
+            .map(|approval| approval.into_approval(self.precision))
+            .collect()
+    }
+
+    async fn fetch_pending_approval(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        approval_id: i64,
+    ) -> Result<Approval> {
+        sqlx::query_as::<_, DBApproval>("SELECT * FROM Approvals WHERE id = ? AND status = ?")
+            .bind(approval_id)
+            .bind(ApprovalStatus::Pending.to_str())
+            .fetch_optional(&mut **tx)
+            .await?
+            .ok_or_else(|| {
+                TransactionError::InvalidArgument(format!(
+                    "Approval {} is not pending",
+                    approval_id
+                ))
+            })?
+            .into_approval(self.precision)
+    }
+
+    async fn decide_approval(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        approval: Approval,
+        status: ApprovalStatus,
+        operator: &str,
+    ) -> Result<Approval> {
+        let decided_at = self.clock.unix_millis();
+        sqlx::query("UPDATE Approvals SET status = ?, decided_by = ?, decided_at = ? WHERE id = ?")
+            .bind(status.to_str())
+            .bind(operator)
+            .bind(decided_at)
+            .bind(approval.id)
+            .execute(&mut **tx)
+            .await?;
+        let approval = Approval {
+            status,
+            decided_by: Some(operator.to_string()),
+            decided_at: Some(decided_at),
+            ..approval
+        };
+        if self.records_events() {
+            let event = LedgerEvent::ApprovalDecided {
+                approval: approval.clone(),
+            };
+            self.record_event(tx, approval.client_id, &event).await?;
+        }
+        tracing::info!(
+            approval = approval.id,
+            status = status.to_str(),
+            "Decided an approval"
+        );
+        Ok(approval)
+    }
+
+    /// Tags clients, e.g. as `vip` or `retail`, to report on them separately with
+    /// [`ClientFilter::tag`] and [`TransactionFilter::tag`]. Clients can have any number of
+    /// tags, and can be tagged before they first transact.
+    ///
+    /// Returns the number of tags added, not counting those the clients already had. Fails
+    /// without adding any if a tag is empty.
+    pub async fn tag_clients(&self, tags: &[ClientTag]) -> Result<u64> {
+        for tag in tags {
+            tag.validate()?;
+        }
+        let (_write, mut tx) = self.begin_write().await?;
+        let mut added = 0;
+        for tag in tags {
+            added += sqlx::query("INSERT OR IGNORE INTO ClientTags (client_id, tag) VALUES (?, ?)")
+                .bind(tag.client)
+                .bind(&tag.tag)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+        }
+        self.commit(tx).await?;
+        Ok(added)
+    }
+
+    /// Removes a tag from a client. Returns whether the client had it.
+    pub async fn untag_client(&self, client_id: ClientId, tag: &str) -> Result<bool> {
+        let (_write, mut tx) = self.begin_write().await?;
+        let removed = sqlx::query("DELETE FROM ClientTags WHERE client_id = ? AND tag = ?")
+            .bind(client_id)
+            .bind(tag)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        self.commit(tx).await?;
+        Ok(removed > 0)
+    }
+
+    /// Gets the tags of a client, in alphabetical order.
+    pub async fn get_client_tags(&self, client_id: ClientId) -> Result<Vec<String>> {
+        let tags: Vec<(String,)> =
+            sqlx::query_as("SELECT tag FROM ClientTags WHERE client_id = ? ORDER BY tag")
+                .bind(client_id)
+                .fetch_all(self.read_pool())
+                .await?;
+        Ok(tags.into_iter().map(|(tag,)| tag).collect())
+    }
+
+    /// Gets the ids of the clients with a tag, in order.
+    pub async fn get_tagged_clients(&self, tag: &str) -> Result<Vec<ClientId>> {
+        let clients: Vec<(ClientId,)> =
+            sqlx::query_as("SELECT client_id FROM ClientTags WHERE tag = ? ORDER BY client_id")
+                .bind(tag)
+                .fetch_all(self.read_pool())
+                .await?;
+        Ok(clients.into_iter().map(|(client,)| client).collect())
+    }
+
+    /// Erases the personal data recorded about a client, for right to erasure requests. The
+    /// client's balances and, unless `policy` is [`ErasurePolicy::Tombstone`], its
+    /// transactions are kept.
+    ///
+    /// The reasons of the client's adjustments and voids are also replaced in the outbox and
+    /// the audit log. Audit log records keep the hash of their original event, so the chain
+    /// still verifies. The erasure itself is recorded, with the `operator` requesting it, and written
+    /// to the outbox and audit log like any other change.
+    ///
+    /// Fails if the client does not exist, or has open disputes or clearing deposits when
+    /// tombstoning.
+    #[tracing::instrument(
+        skip(self, client_id, operator),
+        fields(client = %self.client_label(client_id))
+    )]
+    pub async fn forget_client(
+        &self,
+        client_id: ClientId,
+        policy: ErasurePolicy,
+        operator: &str,
+    ) -> Result<Erasure> {
+        if operator.trim().is_empty() {
+            return Err(TransactionError::InvalidArgument(
+                "An erasure requires an operator".into(),
+            ));
+        }
+        let _client = self.client_locks.lock(client_id).await;
+        let (_write, mut tx) = self.begin_write().await?;
+        if Self::fetch_client(&mut *tx, client_id).await?.is_none() {
+            return Err(self.client_not_found(client_id));
+        }
+
+        let mut redacted = sqlx::query(
+            "UPDATE Annotations SET annotation = ?1 WHERE annotation != ?1
+                AND transaction_id IN (SELECT id FROM Transactions WHERE client_id = ?2)",
+        )
+        .bind(ERASED)
+        .bind(client_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        for table in ["Adjustments", "Approvals", "Voids"] {
+            let query = format!(
+                "UPDATE {} SET reason = ?1 WHERE reason != ?1 AND client_id = ?2",
+                table
+            );
+            redacted += sqlx::query(&query)
+                .bind(ERASED)
+                .bind(client_id)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+        }
+        self.redact_events(&mut tx, client_id).await?;
+
+        let mut removed = 0;
+        if policy == ErasurePolicy::Tombstone {
+            let (open_disputes,): (i64,) = sqlx::query_as(
+                "SELECT (SELECT COUNT(*) FROM Disputes d
+                        JOIN Transactions t ON t.id = d.transaction_id WHERE t.client_id = ?1)
+                    + (SELECT COUNT(*) FROM Settlements s
+                        JOIN Transactions t ON t.id = s.transaction_id WHERE t.client_id = ?1)",
+            )
+            .bind(client_id)
+            .fetch_one(&mut *tx)
+            .await?;
+            if open_disputes > 0 {
+                return Err(TransactionError::InvalidArgument(format!(
+                    "Client {} has open disputes or clearing deposits",
+                    self.client_label(client_id)
+                )));
+            }
+            sqlx::query(
+                "DELETE FROM Annotations
+                    WHERE transaction_id IN (SELECT id FROM Transactions WHERE client_id = ?)",
+            )
+            .bind(client_id)
+            .execute(&mut *tx)
+            .await?;
+            removed = sqlx::query("DELETE FROM Transactions WHERE client_id = ?")
+                .bind(client_id)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+        }
+
+        let erased_at = self.clock.unix_millis();
+        let (id,): (i64,) = sqlx::query_as(
+            "INSERT INTO Erasures (client_id, policy, operator, erased_at) VALUES (?, ?, ?, ?) RETURNING id",
+        )
+        .bind(client_id)
+        .bind(policy.to_str())
+        .bind(operator)
+        .bind(erased_at)
+        .fetch_one(&mut *tx)
+        .await?;
+        let erasure = Erasure {
+            id,
+            client_id,
+            policy,
+            operator: operator.to_string(),
+            erased_at,
+            redacted,
+            removed,
+        };
+        if self.records_events() {
+            let event = LedgerEvent::ClientForgotten {
+                erasure: erasure.clone(),
+            };
+            self.record_event(&mut tx, client_id, &event).await?;
+        }
+        self.commit(tx).await?;
+
+        Ok(erasure)
+    }
+
+    /// Replaces the reasons of the client's adjustments, of its requested adjustments and of
+    /// its voided deposits, in the outbox and audit log events.
+    async fn redact_events(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        client_id: ClientId,
+    ) -> Result<()> {
+        const REASONS: [(&str, &str, &str); 4] = [
+            (
+                "adjustment",
+                "$.adjustment.client_id",
+                "$.adjustment.reason",
+            ),
+            (
+                "approval_requested",
+                "$.approval.client_id",
+                "$.approval.reason",
+            ),
+            (
+                "approval_decided",
+                "$.approval.client_id",
+                "$.approval.reason",
+            ),
+            ("deposit_voided", "$.void.client_id", "$.void.reason"),
+        ];
+        for (event, client_path, reason_path) in REASONS {
+            sqlx::query(
+                "UPDATE Outbox SET payload = json_set(payload, ?1, ?2)
+                    WHERE json_extract(payload, '$.event') = ?3
+                    AND json_extract(payload, ?4) = ?5
+                    AND json_extract(payload, ?1) IS NOT NULL",
+            )
+            .bind(reason_path)
+            .bind(ERASED)
+            .bind(event)
+            .bind(client_path)
+            .bind(client_id)
+            .execute(&mut **tx)
+            .await?;
+
+            let records: Vec<(i64, String, Option<String>)> = sqlx::query_as(
+                "SELECT id, event, redacted_hash FROM AuditLog
+                    WHERE json_extract(event, '$.event') = ?
+                    AND json_extract(event, ?) = ?
+                    AND json_extract(event, ?) != ?",
+            )
+            .bind(event)
+            .bind(client_path)
+            .bind(client_id)
+            .bind(reason_path)
+            .bind(ERASED)
+            .fetch_all(&mut **tx)
+            .await?;
+            for (id, event, redacted_hash) in records {
+                sqlx::query(
+                    "UPDATE AuditLog SET event = json_set(event, ?, ?), redacted_hash = ? WHERE id = ?",
+                )
+                .bind(reason_path)
+                .bind(ERASED)
+                .bind(redacted_hash.unwrap_or_else(|| content_hash(&event)))
+                .bind(id)
+                .execute(&mut **tx)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Gets the annotations validators attached to transactions with `transaction_id`, in the
+    /// order they were made.
+    pub async fn get_annotations(&self, transaction_id: TransactionId) -> Result<Vec<Annotation>> {
+        let annotations: Vec<(String, String)> = sqlx::query_as(
+            "SELECT [type], annotation FROM Annotations WHERE transaction_id=? ORDER BY id",
+        )
+        .bind(db_id(transaction_id))
+        .fetch_all(self.read_pool())
+        .await?;
+        Ok(annotations
+            .into_iter()
+            .map(|(transaction_type, annotation)| Annotation {
+                transaction_type,
+                annotation,
+            })
+            .collect())
+    }
+
+    /// Reads up to `limit` events from the outbox, oldest first. See
+    /// [`TransactionServiceBuilder::outbox`].
+    pub async fn outbox_events(&self, limit: u32) -> Result<Vec<OutboxEvent>> {
+        Ok(sqlx::query_as(
+            "SELECT id, client_id, payload, created_at FROM Outbox ORDER BY id LIMIT ?",
+        )
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    /// Removes the outbox events up to and including `id`, once they have been shipped.
+    pub async fn remove_outbox_events(&self, id: i64) -> Result<()> {
+        let (_write, mut tx) = self.begin_write().await?;
+        sqlx::query("DELETE FROM Outbox WHERE id <= ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        self.commit(tx).await
+    }
+
+    /// Reads up to `limit` audit log records from before the record `before`, or the latest
+    /// ones, newest first. Empty without the audit log.
+    ///
+    /// Like the other inspection methods, reads the database written to rather than a read
+    /// replica, so it shows what was committed.
+    pub async fn audit_records(&self, before: Option<i64>, limit: u32) -> Result<Vec<AuditRecord>> {
+        let records: Vec<(i64, i64, String, String, bool)> = sqlx::query_as(
+            "SELECT id, created_at, event, hash, redacted_hash IS NOT NULL FROM AuditLog
+            WHERE id < ? ORDER BY id DESC LIMIT ?",
+        )
+        .bind(before.unwrap_or(i64::MAX))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        records
+            .into_iter()
+            .map(|(id, created_at, event, hash, redacted)| {
+                Ok(AuditRecord {
+                    id,
+                    created_at,
+                    event: serde_json::from_str(&event)?,
+                    hash,
+                    redacted,
+                })
+            })
+            .collect()
+    }
+
+    /// Reads the latest `limit` rows of every table with rows about `client_id`, as they are
+    /// stored, e.g. to investigate a balance without access to the database file.
+    pub async fn dump_client(&self, client_id: ClientId, limit: u32) -> Result<ClientDump> {
+        // Read in one transaction, so the tables agree with each other
+        let mut tx = self.pool.begin().await?;
+        let mut tables = serde_json::Map::new();
+        for (table, query) in inspect::CLIENT_TABLES {
+            let rows = sqlx::query(query)
+                .bind(client_id)
+                .bind(limit)
+                .fetch_all(&mut *tx)
+                .await?;
+            let rows = rows
+                .iter()
+                .map(inspect::row_to_json)
+                .collect::<Result<Vec<_>>>()?;
+            tables.insert(table.to_string(), serde_json::Value::Array(rows));
+        }
+        Ok(ClientDump { client_id, tables })
+    }
+
+    /// Reads the checkpoints of the ledger: the end of the audit log, the closed business
+    /// dates and balance snapshots, and the offsets committed by partitioned sources.
+    pub async fn checkpoints(&self) -> Result<Checkpoints> {
+        let mut tx = self.pool.begin().await?;
+        let (audit_head,): (i64,) = sqlx::query_as("SELECT IFNULL(MAX(id), 0) FROM AuditLog")
+            .fetch_one(&mut *tx)
+            .await?;
+        let day_closes = sqlx::query_as(
+            "SELECT business_date, closed_at, checkpoint FROM DayCloses ORDER BY business_date DESC",
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let snapshots: Vec<(i64, i64, i64)> = sqlx::query_as(
+            "SELECT taken_at, checkpoint, COUNT(*) FROM BalanceSnapshots
+            GROUP BY checkpoint, taken_at ORDER BY checkpoint DESC",
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let source_offsets = sqlx::query_as(
+            "SELECT consumer, [partition], [offset] FROM SourceOffsets
+            ORDER BY consumer, [partition]",
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        Ok(Checkpoints {
+            audit_head,
+            day_closes,
+            balance_snapshots: snapshots
+                .into_iter()
+                .map(|(taken_at, checkpoint, clients)| BalanceSnapshot {
+                    taken_at,
+                    checkpoint,
+                    clients: clients as u64,
+                })
+                .collect(),
+            source_offsets,
+        })
+    }
+
+    /// Checks the invariants of the stored ledger, such as one imported with
+    /// [`TransactionService::import_state`] from another system, before it goes live:
+    ///
+    /// - `held_funds`: a client's held funds are the amounts of its open disputes and of its
+    ///   other deposits waiting to settle
+    /// - `locked_client`: only clients with a chargeback are locked
+    /// - `client_stats`: a client's counts and sums are not negative
+    /// - `transaction_client`: every stored transaction belongs to a stored client
+    /// - `transaction_amount`: every deposit and withdrawal has a positive amount
+    /// - `dispute_target`: every open dispute is of a stored deposit or withdrawal
+    /// - `settlement_target`: every settlement is of a stored deposit
+    /// - `cash`: the cash house account is the client funds plus the fees account
+    /// - `audit_log`: the ledger matches its audit log, see
+    ///   [`TransactionService::verify_audit_log`], when the service keeps one
+    ///
+    /// Every violation is reported, rather than only the first.
+    #[tracing::instrument(skip_all)]
+    pub async fn check_ledger(&self) -> Result<LedgerCheck> {
+        // Read together, without a write in between
+        let (write, mut tx) = self.begin_write().await?;
+        let (clients, transactions): (i64, i64) = sqlx::query_as(
+            "SELECT (SELECT COUNT(*) FROM Clients), (SELECT COUNT(*) FROM [Transactions])",
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        let amount = |amount: Option<i64>| self.precision.to_decimal(amount.unwrap_or(0));
+        let mut violations = Vec::new();
+        let mut violation =
+            |invariant, client_id: Option<ClientId>, transaction_id: Option<i64>, detail| {
+                violations.push(Violation {
+                    invariant,
+                    client_id,
+                    transaction_id: transaction_id.map(from_db_id),
+                    detail,
+                })
+            };
+
+        let rows: Vec<(ClientId, i64, Option<i64>, Option<i64>)> = sqlx::query_as(
+            "SELECT c.id, c.held, d.amount, s.amount FROM Clients c
+            LEFT JOIN (SELECT t.client_id, SUM(t.amount) AS amount FROM Disputes d
+                JOIN [Transactions] t ON t.id = d.transaction_id GROUP BY t.client_id) d
+                ON d.client_id = c.id
+            LEFT JOIN (SELECT t.client_id, SUM(t.amount) AS amount FROM Settlements s
+                JOIN [Transactions] t ON t.id = s.transaction_id
+                WHERE s.transaction_id NOT IN (SELECT transaction_id FROM Disputes)
+                GROUP BY t.client_id) s
+                ON s.client_id = c.id
+            WHERE c.held != COALESCE(d.amount, 0) + COALESCE(s.amount, 0)
+            ORDER BY c.id",
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        for (client_id, held, disputed, settling) in rows {
+            violation(
+                "held_funds",
+                Some(client_id),
+                None,
+                format!(
+                    "held {} is not the {} of its open disputes and the {} of its deposits waiting to settle",
+                    amount(Some(held)),
+                    amount(disputed),
+                    amount(settling)
+                ),
+            );
+        }
+
+        let locked: Vec<(ClientId,)> =
+            sqlx::query_as("SELECT id FROM Clients WHERE locked AND chargebacks = 0 ORDER BY id")
+                .fetch_all(&mut *tx)
+                .await?;
+        for (client_id,) in locked {
+            violation(
+                "locked_client",
+                Some(client_id),
+                None,
+                "locked without a chargeback".into(),
+            );
+        }
+
+        let negative: Vec<(ClientId,)> = sqlx::query_as(
+            "SELECT id FROM Clients WHERE deposits < 0 OR deposited < 0 OR withdrawals < 0
+                OR withdrawn < 0 OR disputes < 0 OR chargebacks < 0
+            ORDER BY id",
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        for (client_id,) in negative {
+            violation(
+                "client_stats",
+                Some(client_id),
+                None,
+                "has a negative count or sum of transactions".into(),
+            );
+        }
+
+        let orphans: Vec<(i64, ClientId)> = sqlx::query_as(
+            "SELECT id, client_id FROM [Transactions]
+            WHERE client_id NOT IN (SELECT id FROM Clients) ORDER BY id",
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        for (transaction_id, client_id) in orphans {
+            violation(
+                "transaction_client",
+                Some(client_id),
+                Some(transaction_id),
+                "the client is not stored".into(),
+            );
+        }
+
+        let amounts: Vec<(i64, ClientId, String, Option<i64>)> = sqlx::query_as(
+            "SELECT id, client_id, [type], amount FROM [Transactions]
+            WHERE [type] IN ('deposit', 'withdrawal') AND (amount IS NULL OR amount <= 0)
+            ORDER BY id",
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        for (transaction_id, client_id, transaction_type, stored) in amounts {
+            violation(
+                "transaction_amount",
+                Some(client_id),
+                Some(transaction_id),
+                match stored {
+                    Some(stored) => format!("{} of {}", transaction_type, amount(Some(stored))),
+                    None => format!("{} without an amount", transaction_type),
+                },
+            );
+        }
+
+        for (invariant, table, types) in [
+            ("dispute_target", "Disputes", "'deposit', 'withdrawal'"),
+            ("settlement_target", "Settlements", "'deposit'"),
+        ] {
+            let targets: Vec<(i64, Option<ClientId>, Option<String>)> = sqlx::query_as(&format!(
+                "SELECT x.transaction_id, t.client_id, t.[type] FROM {table} x
+                LEFT JOIN [Transactions] t ON t.id = x.transaction_id
+                WHERE t.id IS NULL OR t.[type] NOT IN ({types})
+                ORDER BY x.transaction_id",
+            ))
+            .fetch_all(&mut *tx)
+            .await?;
+            for (transaction_id, client_id, transaction_type) in targets {
+                violation(
+                    invariant,
+                    client_id,
+                    Some(transaction_id),
+                    match transaction_type {
+                        Some(transaction_type) => format!("of a {}", transaction_type),
+                        None => "the transaction is not stored".into(),
+                    },
+                );
+            }
+        }
+
+        let (cash, fees, client_funds): (Option<i64>, Option<i64>, i64) = sqlx::query_as(
+            "SELECT (SELECT balance FROM HouseAccounts WHERE name = 'cash'),
+                (SELECT balance FROM HouseAccounts WHERE name = 'fees'),
+                (SELECT COALESCE(SUM(held + available), 0) FROM Clients)",
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if cash != Some(client_funds + fees.unwrap_or(0)) {
+            violation(
+                "cash",
+                None,
+                None,
+                format!(
+                    "cash {} is not the client funds {} plus the fees {}",
+                    amount(cash),
+                    amount(Some(client_funds)),
+                    amount(fees)
+                ),
+            );
+        }
+        tx.rollback().await?;
+        drop(write);
+
+        if self.audit_log {
+            for problem in self.verify_audit_log().await?.problems {
+                violation("audit_log", None, None, problem);
+            }
+        }
+        Ok(LedgerCheck {
+            clients: clients as u64,
+            transactions: transactions as u64,
+            violations,
+        })
+    }
+
+    /// Checks the ledger against the audit log, see [`TransactionServiceBuilder::audit_log`].
+    ///
+    /// Finds records that were changed, inserted or removed, except at the end of the log,
+    /// deposits and withdrawals that no longer match their record and clients that no longer
+    /// match their last record. Records redacted by [`TransactionService::forget_client`] are
+    /// checked against the hash of their original event, and transactions removed by it are
+    /// not reported.
+    pub async fn verify_audit_log(&self) -> Result<AuditVerification> {
+        let records: Vec<(i64, String, i64, String, Option<String>)> = sqlx::query_as(
+            "SELECT id, event, created_at, hash, redacted_hash FROM AuditLog ORDER BY id",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let erasures: Vec<(ClientId, String)> =
+            sqlx::query_as("SELECT client_id, policy FROM Erasures")
+                .fetch_all(&self.pool)
+                .await?;
+        let erased: HashSet<ClientId> = erasures.iter().map(|(client_id, _)| *client_id).collect();
+        let tombstoned: HashSet<ClientId> = erasures
+            .iter()
+            .filter(|(_, policy)| policy == ErasurePolicy::Tombstone.to_str())
+            .map(|(client_id, _)| *client_id)
+            .collect();
+
+        let mut problems = Vec::new();
+        let mut prev_hash = GENESIS_HASH.to_string();
+        let mut clients = BTreeMap::new();
+        for (id, event, created_at, hash, redacted_hash) in &records {
+            let content = redacted_hash.clone().unwrap_or_else(|| content_hash(event));
+            if *hash != chain_hash(&prev_hash, *created_at, &content) {
+                problems.push(format!(
+                    "record {}: does not match its hash or the previous record",
+                    id
+                ));
+            }
+            prev_hash = hash.clone();
+
+            let event: AuditedEvent = match serde_json::from_str(event) {
+                Ok(event) => event,
+                Err(e) => {
+                    problems.push(format!("record {}: invalid event: {}", id, e));
+                    continue;
+                }
+            };
+            if redacted_hash.is_some()
+                && !event
+                    .client
+                    .as_ref()
+                    .is_some_and(|c| erased.contains(&c.id))
+            {
+                problems.push(format!(
+                    "record {}: redacted without an erasure of its client",
+                    id
+                ));
+            }
+            if let Some(mut transaction) = event.transaction.filter(|t| {
+                matches!(
+                    t.transaction_type,
+                    TransactionType::Deposit | TransactionType::Withdrawal
+                ) && !tombstoned.contains(&t.client_id)
+            }) {
+                // Compare the amount as it was stored
+                transaction.amount = transaction
+                    .amount
+                    .and_then(|a| self.precision.to_storage(a))
+                    .map(|a| self.precision.to_decimal(a));
+                let stored = Self::fetch_transaction(&self.pool, transaction.id)
+                    .await?
+                    .map(|t| t.into_transaction(self.precision))
+                    .transpose()?;
+                if stored.as_ref() != Some(&transaction) {
+                    problems.push(format!(
+                        "record {}: transaction {} does not match the stored transaction",
+                        id, transaction.id
+                    ));
+                }
+            }
+            if let Some(client) = event.client {
+                clients.insert(client.id, client);
+            }
+        }
+
+        for (client_id, expected) in clients {
+            let client = Self::fetch_client(&self.pool, client_id)
+                .await?
+                .map(|c| c.into_client(self.precision));
+            if client.as_ref() != Some(&expected) {
+                problems.push(format!(
+                    "client {}: does not match the last record of it",
+                    self.client_label(client_id)
+                ));
+            }
+        }
+
+        Ok(AuditVerification {
+            records: records.len() as u64,
+            head: prev_hash,
+            problems,
+        })
+    }
+
+    /// Gets a client as it was at `timestamp`, in milliseconds since the unix epoch, from the
+    /// last state of it recorded in the audit log at or before then. Clients that were not
+    /// changed by then are [`None`].
+    ///
+    /// Starts from the last [`BalanceSnapshot`] taken by then, so only the audit log after it
+    /// is read. Needs the audit log, and only knows about changes made while it was enabled.
+    pub async fn get_client_as_of(
+        &self,
+        client_id: ClientId,
+        timestamp: i64,
+    ) -> Result<Option<Client>> {
+        self.require_audit_log()?;
+        let checkpoint = self.snapshot_checkpoint(timestamp).await?;
+        let event: Option<(String,)> = sqlx::query_as(
+            "SELECT event FROM AuditLog
+            WHERE json_extract(event, '$.client.client') = ? AND created_at <= ? AND id > ?
+            ORDER BY id DESC
+            LIMIT 1",
+        )
+        .bind(client_id)
+        .bind(timestamp)
+        .bind(checkpoint)
+        .fetch_optional(self.read_pool())
+        .await?;
+        if let Some((event,)) = event {
+            return Self::recorded_client(&event).map(Some);
+        }
+        let client: Option<ClientDb> = sqlx::query_as(
+            "SELECT client_id AS id, available, held, available+held AS total, locked
+            FROM BalanceSnapshots
+            WHERE checkpoint = ? AND client_id = ?",
+        )
+        .bind(checkpoint)
+        .bind(client_id)
+        .fetch_optional(self.read_pool())
+        .await?;
+        Ok(client.map(|c| c.into_client(self.precision)))
+    }
+
+    /// Gets every client as it was at `timestamp`, ordered by client id, see
+    /// [`TransactionService::get_client_as_of`].
+    pub async fn get_clients_as_of(&self, timestamp: i64) -> Result<Vec<Client>> {
+        self.require_audit_log()?;
+        let checkpoint = self.snapshot_checkpoint(timestamp).await?;
+        let mut clients: BTreeMap<ClientId, Client> = sqlx::query_as::<_, ClientDb>(
+            "SELECT client_id AS id, available, held, available+held AS total, locked
+            FROM BalanceSnapshots
+            WHERE checkpoint = ?",
+        )
+        .bind(checkpoint)
+        .fetch_all(self.read_pool())
+        .await?
+        .into_iter()
+        .map(|c| (c.id, c.into_client(self.precision)))
+        .collect();
+        let events: Vec<(String,)> = sqlx::query_as(
+            "SELECT event FROM AuditLog
+            WHERE id IN (
+                SELECT MAX(id) FROM AuditLog
+                WHERE json_extract(event, '$.client.client') IS NOT NULL
+                    AND created_at <= ? AND id > ?
+                GROUP BY json_extract(event, '$.client.client')
+            )",
+        )
+        .bind(timestamp)
+        .bind(checkpoint)
+        .fetch_all(self.read_pool())
+        .await?;
+        for (event,) in &events {
+            let client = Self::recorded_client(event)?;
+            clients.insert(client.id, client);
+        }
+        Ok(clients.into_values().collect())
+    }
+
+    /// Copies the clients, stored transactions, open disputes and end-of-day closes into a
+    /// [`LedgerState`], to move the ledger to another database with
+    /// [`TransactionService::import_state`]. Writes wait until the copy is made.
+    #[tracing::instrument(skip_all)]
+    pub async fn export_state(&self) -> Result<LedgerState> {
+        let (_write, mut tx) = self.begin_write().await?;
+
+        let rows = sqlx::query("SELECT *, (held+available) as total FROM Clients ORDER BY id")
+            .fetch_all(&mut *tx)
+            .await?;
+        let clients = rows
+            .iter()
+            .map(|row| {
+                Ok(StateClient {
+                    client: ClientDb::from_row(row)?.into_client(self.precision),
+                    last_activity_at: row.try_get("last_activity_at")?,
+                    stats: ClientStatsDb::from_row(row)?.into_stats(self.precision),
+                })
+            })
+            .collect::<Result<_>>()?;
+        let transactions =
+            sqlx::query_as::<_, DBTransaction>("SELECT * FROM [Transactions] ORDER BY id")
+                .fetch_all(&mut *tx)
+                .await?
+                .into_iter()
+                .map(|t| t.into_transaction(self.precision))
+                .collect::<Result<_>>()?;
+        type DisputeRow = (
+            i64,
+            i64,
+            Option<i64>,
+            Option<String>,
+            Option<String>,
+            String,
+            Option<String>,
+            String,
+        );
+        let disputes = sqlx::query_as::<_, DisputeRow>(concat!(
+            "SELECT transaction_id, opened_at, escalated_at, reason_code, notes, status, assignee, ",
+            dispute_evidence!(),
+            " FROM Disputes d ORDER BY transaction_id"
+        ))
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(
+            |(tx, opened_at, escalated_at, reason_code, notes, status, assignee, evidence)| {
+                Ok(StateDispute {
+                    tx: from_db_id(tx),
+                    opened_at,
+                    escalated_at,
+                    reason_code,
+                    notes,
+                    status: DisputeStatus::parse(&status)?,
+                    assignee,
+                    evidence: DBDispute::parse_evidence(&evidence)?,
+                })
+            },
+        )
+        .collect::<Result<_>>()?;
+        let settlements = sqlx::query_as::<_, (i64, i64)>(
+            "SELECT transaction_id, release_at FROM Settlements ORDER BY transaction_id",
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|(tx, release_at)| StateSettlement {
+            tx: from_db_id(tx),
+            release_at,
+        })
+        .collect();
+        let risks = sqlx::query_as::<_, (ClientId, String, i64, i64)>(
+            "SELECT client_id, level, chargebacks, escalated_at FROM ClientRisks
+            ORDER BY client_id",
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(Self::into_risk)
+        .collect::<Result<_>>()?;
+        let voids = sqlx::query_as::<_, (i64, ClientId, i64, String, String, i64)>(
+            "SELECT transaction_id, client_id, amount, reason, operator, voided_at FROM Voids
+            ORDER BY transaction_id",
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(
+            |(transaction_id, client_id, amount, reason, operator, voided_at)| Void {
+                transaction_id: from_db_id(transaction_id),
+                client_id,
+                amount: self.precision.to_decimal(amount),
+                reason,
+                operator,
+                voided_at,
+            },
+        )
+        .collect();
+        let day_closes = sqlx::query_as::<_, (String, i64, i64, String, String)>(
+            "SELECT business_date, closed_at, checkpoint, clients, totals FROM DayCloses
+            ORDER BY rowid",
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|(business_date, closed_at, checkpoint, clients, totals)| {
+            Ok(StateDayClose {
+                business_date,
+                closed_at,
+                checkpoint,
+                clients: serde_json::from_str(&clients)?,
+                totals: serde_json::from_str(&totals)?,
+            })
+        })
+        .collect::<Result<_>>()?;
+
+        let house_accounts = self.fetch_house_accounts(&mut *tx).await?;
+        let external_ids = Self::fetch_external_ids(&mut *tx).await?;
+        Ok(LedgerState {
+            version: STATE_VERSION,
+            clients,
+            transactions,
+            disputes,
+            day_closes,
+            house_accounts,
+            external_ids,
+            settlements,
+            risks,
+            voids,
+        })
+    }
+
+    /// Loads a [`LedgerState`] made by [`TransactionService::export_state`] into this
+    /// service's database, which must not have any clients or transactions yet.
+    ///
+    /// The audit log continues after the checkpoint of the last end-of-day close, so the next
+    /// close only covers changes made after the import.
+    #[tracing::instrument(skip_all, fields(version = state.version))]
+    pub async fn import_state(&self, state: &LedgerState) -> Result<()> {
+        if state.version != STATE_VERSION {
+            return Err(TransactionError::InvalidArgument(format!(
+                "Can not import version {} of the ledger state, only version {}",
+                state.version, STATE_VERSION
+            )));
+        }
+        let (_write, mut tx) = self.begin_write().await?;
+
+        let (existing,): (i64,) = sqlx::query_as(
+            "SELECT (SELECT COUNT(*) FROM Clients) + (SELECT COUNT(*) FROM [Transactions])",
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if existing > 0 {
+            return Err(TransactionError::InvalidArgument(
+                "Can only import the ledger state into an empty database".into(),
+            ));
+        }
+
+        let to_storage = |amount: Decimal| {
+            self.precision.to_storage(amount).ok_or_else(|| {
+                TransactionError::InvalidArgument(format!("Amount {} is out of range", amount))
+            })
+        };
+        let to_count = |count: u64| {
+            i64::try_from(count).map_err(|_| {
+                TransactionError::InvalidArgument(format!("Count {} is out of range", count))
+            })
+        };
+        for StateClient {
+            client,
+            last_activity_at,
+            stats,
+        } in &state.clients
+        {
+            sqlx::query(
+                "INSERT INTO Clients (id, available, held, locked, last_activity_at, deposits,
+                    deposited, withdrawals, withdrawn, disputes, chargebacks, risk_score)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(client.id)
+            .bind(to_storage(client.available)?)
+            .bind(to_storage(client.held)?)
+            .bind(client.locked)
+            .bind(last_activity_at)
+            .bind(to_count(stats.deposits)?)
+            .bind(to_storage(stats.deposited)?)
+            .bind(to_count(stats.withdrawals)?)
+            .bind(to_storage(stats.withdrawn)?)
+            .bind(to_count(stats.disputes)?)
+            .bind(to_count(stats.chargebacks)?)
+            .bind(stats.risk_score)
+            .execute(&mut *tx)
+            .await?;
+        }
+        for transaction in &state.transactions {
+            sqlx::query(
+                "INSERT INTO [Transactions] (id, [type], client_id, amount, reference)
+                VALUES (?, ?, ?, ?, ?)",
+            )
+            .bind(db_id(transaction.id))
+            .bind(transaction.transaction_type.to_str())
+            .bind(transaction.client_id)
+            .bind(transaction.amount.map(to_storage).transpose()?)
+            .bind(&transaction.reference)
+            .execute(&mut *tx)
+            .await?;
+        }
+        for dispute in &state.disputes {
+            sqlx::query(
+                "INSERT INTO Disputes (transaction_id, opened_at, escalated_at, reason_code, notes,
+                    status, assignee)
+                VALUES (?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(db_id(dispute.tx))
+            .bind(dispute.opened_at)
+            .bind(dispute.escalated_at)
+            .bind(&dispute.reason_code)
+            .bind(&dispute.notes)
+            .bind(dispute.status.to_str())
+            .bind(&dispute.assignee)
+            .execute(&mut *tx)
+            .await?;
+            for evidence in &dispute.evidence {
+                sqlx::query(
+                    "INSERT INTO DisputeEvidence (transaction_id, reference, added_at)
+                    VALUES (?, ?, ?)",
+                )
+                .bind(db_id(dispute.tx))
+                .bind(&evidence.reference)
+                .bind(evidence.added_at)
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+        for settlement in &state.settlements {
+            sqlx::query("INSERT INTO Settlements (transaction_id, release_at) VALUES (?, ?)")
+                .bind(db_id(settlement.tx))
+                .bind(settlement.release_at)
+                .execute(&mut *tx)
+                .await?;
+        }
+        for risk in &state.risks {
+            Self::insert_risk(&mut *tx, risk).await?;
+        }
+        for void in &state.voids {
+            Self::insert_void(&mut *tx, void, self.precision).await?;
+        }
+        for close in &state.day_closes {
+            parse_business_date(&close.business_date)?;
+            sqlx::query(
+                "INSERT INTO DayCloses (business_date, closed_at, checkpoint, clients, totals)
+                VALUES (?, ?, ?, ?, ?)",
+            )
+            .bind(&close.business_date)
+            .bind(close.closed_at)
+            .bind(close.checkpoint)
+            .bind(serde_json::to_string(&close.clients)?)
+            .bind(serde_json::to_string(&close.totals)?)
+            .execute(&mut *tx)
+            .await?;
+        }
+        for account in &state.house_accounts {
+            sqlx::query("INSERT OR REPLACE INTO HouseAccounts (name, balance) VALUES (?, ?)")
+                .bind(&account.name)
+                .bind(to_storage(account.balance)?)
+                .execute(&mut *tx)
+                .await?;
+        }
+        for mapping in &state.external_ids {
+            sqlx::query("INSERT INTO ExternalIds (external_id, client_id) VALUES (?, ?)")
+                .bind(&mapping.external_id)
+                .bind(mapping.client)
+                .execute(&mut *tx)
+                .await?;
+        }
+        // Continue the audit log after the last close, as AuditLog ids are its checkpoints
+        if let Some(checkpoint) = state.day_closes.iter().map(|c| c.checkpoint).max() {
+            let updated =
+                sqlx::query("UPDATE sqlite_sequence SET seq = MAX(seq, ?) WHERE name = 'AuditLog'")
+                    .bind(checkpoint)
+                    .execute(&mut *tx)
+                    .await?;
+            if updated.rows_affected() == 0 {
+                sqlx::query("INSERT INTO sqlite_sequence (name, seq) VALUES ('AuditLog', ?)")
+                    .bind(checkpoint)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+        self.commit(tx).await?;
+        tracing::info!(
+            clients = state.clients.len(),
+            transactions = state.transactions.len(),
+            "Imported the ledger state"
+        );
+        Ok(())
+    }
+
+    /// Writes a consistent copy of the database to a new file at `path`, while it is in use.
+    /// The copy is compacted like `VACUUM` would, and can be opened as a database of its own or
+    /// loaded into another one with [`TransactionService::restore`].
+    pub async fn backup(&self, path: &Path) -> Result<()> {
+        if path.exists() {
+            return Err(TransactionError::InvalidArgument(format!(
+                "\"{}\" already exists",
+                path.display()
+            )));
+        }
+        sqlx::query("VACUUM INTO ?")
+            .bind(sqlite_uri(path, "rwc")?)
+            .execute(&self.pool)
+            .await?;
+        tracing::info!(path = %path.display(), "Backed up the database");
+        Ok(())
+    }
+
+    /// Loads a copy written by [`TransactionService::backup`] into this database, which must be
+    /// empty. Fails without changing anything if the copy does not pass
+    /// `PRAGMA integrity_check`.
+    pub async fn restore(&self, path: &Path) -> Result<()> {
+        if !path.is_file() {
+            return Err(TransactionError::InvalidArgument(format!(
+                "\"{}\" is not a file",
+                path.display()
+            )));
+        }
+        // Not in a transaction of begin_write, as sqlite can not attach a database in one
+        let _write = self.write_lanes.lock(&self.write_lock).await;
+        let mut conn = self.pool.acquire().await?;
+        sqlx::query("ATTACH DATABASE ? AS backup")
+            .bind(sqlite_uri(path, "ro")?)
+            .execute(&mut *conn)
+            .await?;
+        let restored = Self::restore_attached(&mut conn).await;
+        // The connection goes back to the pool
+        sqlx::query("DETACH DATABASE backup")
+            .execute(&mut *conn)
+            .await?;
+        let tables = restored?;
+        tracing::info!(path = %path.display(), tables, "Restored the database");
+        Ok(())
+    }
+
+    /// Checks the database with `PRAGMA integrity_check`, then reclaims free space with
+    /// `VACUUM` and updates the query planner statistics with `ANALYZE`. A database with
+    /// integrity problems is left as it is.
+    ///
+    /// Refuses to run while the service is writing or paused, or while another process is
+    /// writing to the database. Writes that start while it runs wait for it, so pause or stop
+    /// servers using the database first.
+    pub async fn maintain(&self) -> Result<MaintenanceReport> {
+        let busy = || {
+            TransactionError::InvalidArgument(
+                "Can not maintain the database while it is being written to".into(),
+            )
+        };
+        let _write = self
+            .write_lanes
+            .try_lock(&self.write_lock)
+            .ok_or_else(busy)?;
+        let mut conn = self.pool.acquire().await?;
+        let (busy_timeout,): (i64,) = sqlx::query_as("PRAGMA busy_timeout")
+            .fetch_one(&mut *conn)
+            .await?;
+        // Fail instead of waiting for other writers
+        sqlx::query("PRAGMA busy_timeout = 0")
+            .execute(&mut *conn)
+            .await?;
+        let report = Self::maintain_connection(&mut conn).await;
+        sqlx::query(&format!("PRAGMA busy_timeout = {}", busy_timeout))
+            .execute(&mut *conn)
+            .await?;
+        let report = report.map_err(|e| match e {
+            TransactionError::Database(e) if is_locked(&e) => busy(),
+            e => e,
+        })?;
+        tracing::info!(
+            problems = report.problems.len(),
+            size_before = report.size_before,
+            size_after = report.size_after,
+            "Maintained the database"
+        );
+        Ok(report)
+    }
+
+    async fn maintain_connection(conn: &mut SqliteConnection) -> Result<MaintenanceReport> {
+        // Only succeeds when no other connection is writing
+        sqlx::query("BEGIN IMMEDIATE").execute(&mut *conn).await?;
+        sqlx::query("ROLLBACK").execute(&mut *conn).await?;
+
+        let size_before = Self::database_size(&mut *conn).await?;
+        let problems: Vec<(String,)> = sqlx::query_as("PRAGMA integrity_check")
+            .fetch_all(&mut *conn)
+            .await?;
+        let problems: Vec<_> = problems
+            .into_iter()
+            .map(|(problem,)| problem)
+            .filter(|problem| problem != "ok")
+            .collect();
+        if !problems.is_empty() {
+            return Ok(MaintenanceReport {
+                problems,
+                size_before,
+                size_after: None,
+            });
+        }
+
+        sqlx::query("VACUUM").execute(&mut *conn).await?;
+        sqlx::query("ANALYZE").execute(&mut *conn).await?;
+        Ok(MaintenanceReport {
+            problems,
+            size_before,
+            size_after: Some(Self::database_size(&mut *conn).await?),
+        })
+    }
+
+    /// The size of the database file, in bytes.
+    async fn database_size(conn: &mut SqliteConnection) -> Result<u64> {
+        let (size,): (i64,) = sqlx::query_as(
+            "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+        )
+        .fetch_one(conn)
+        .await?;
+        Ok(size as u64)
+    }
+
+    /// Copies the tables of the database attached as `backup` into the main one, returning the
+    /// number of tables copied.
+    async fn restore_attached(conn: &mut SqliteConnection) -> Result<usize> {
+        let (integrity,): (String,) = sqlx::query_as("PRAGMA backup.integrity_check(1)")
+            .fetch_one(&mut *conn)
+            .await?;
+        if integrity != "ok" {
+            return Err(TransactionError::InvalidArgument(format!(
+                "The backup is damaged: {}",
+                integrity
+            )));
+        }
+
+        let mut tx = conn.begin().await?;
+        let (existing,): (i64,) = sqlx::query_as(
+            "SELECT (SELECT COUNT(*) FROM main.Clients) + (SELECT COUNT(*) FROM main.[Transactions])",
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if existing > 0 {
+            return Err(TransactionError::InvalidArgument(
+                "Can only restore a backup into an empty database".into(),
+            ));
+        }
+        // Tables are copied in any order
+        sqlx::query("PRAGMA defer_foreign_keys = ON")
+            .execute(&mut *tx)
+            .await?;
+
+        // Tables of custom transaction types that are not registered are left out
+        let tables: Vec<(String,)> = sqlx::query_as(
+            "SELECT name FROM backup.sqlite_master
+            WHERE type = 'table' AND name NOT LIKE 'sqlite_%'
+                AND name IN (SELECT name FROM main.sqlite_master WHERE type = 'table')
+            ORDER BY name",
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let quote = |name: &str| format!("\"{}\"", name.replace('"', "\"\""));
+        for (table,) in &tables {
+            let columns: Vec<(String,)> =
+                sqlx::query_as("SELECT name FROM pragma_table_info(?, 'backup')")
+                    .bind(table)
+                    .fetch_all(&mut *tx)
+                    .await?;
+            let columns = columns
+                .iter()
+                .map(|(column,)| quote(column))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let table = quote(table);
+            sqlx::query(&format!("DELETE FROM main.{}", table))
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query(&format!(
+                "INSERT INTO main.{table} ({columns}) SELECT {columns} FROM backup.{table}"
+            ))
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(tables.len())
+    }
+
+    fn require_audit_log(&self) -> Result<()> {
+        if !self.audit_log {
+            return Err(TransactionError::InvalidArgument(
+                "Past balances are read from the audit log, which is not enabled".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The client state recorded with an audit log event that has one.
+    fn recorded_client(event: &str) -> Result<Client> {
+        let event: AuditedEvent = serde_json::from_str(event)?;
+        event.client.ok_or_else(|| {
+            TransactionError::InvalidArgument("The audit log event has no client".into())
+        })
+    }
+
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(
+            tx = transaction.id,
+            r#type = transaction.transaction_type.to_str(),
+            client = %self.client_label(transaction.client_id)
+        )
+    )]
+    async fn apply(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        transaction: &Transaction,
+    ) -> Result<TransactionOutcome> {
+        #[cfg(feature = "chaos")]
+        if let Some(chaos) = &self.chaos {
+            chaos.before_apply()?;
+        }
+        let verdict = self.validate(tx, transaction).await?;
+        self.step("validate");
+        let (annotations, risk_score_change) = match verdict {
+            Verdict::Accept {
+                annotations,
+                risk_score_change,
+            } => (annotations, risk_score_change),
+            Verdict::Reject { reason } => return Ok(TransactionOutcome::Rejected { reason }),
+        };
+
+        let before = if self.alerts.is_empty() {
+            None
+        } else {
+            Self::fetch_client(&mut *tx, transaction.client_id).await?
+        };
+        let outcome = self.apply_transaction(tx, transaction).await?;
+        self.step("apply");
+        for annotation in annotations {
+            sqlx::query(
+                "INSERT INTO Annotations (transaction_id, [type], annotation) VALUES (?, ?, ?)",
+            )
+            .bind(db_id(transaction.id))
+            .bind(transaction.transaction_type.to_str())
+            .bind(annotation)
+            .execute(&mut *tx)
+            .await?;
+        }
+        // Scores the client the transaction changed, the disputed transaction's owner for
+        // disputes. Replayed history, such as duplicates that are ignored, does not score
+        // clients again
+        let client_id = outcome.changed_client(transaction);
+        if risk_score_change != 0
+            && !self.backfill
+            && (outcome.is_applied() || outcome == TransactionOutcome::WithdrawalRejected)
+        {
+            sqlx::query("UPDATE Clients SET risk_score = risk_score + ? WHERE id = ?")
+                .bind(risk_score_change)
+                .bind(client_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        if !self.alerts.is_empty() && outcome.is_applied() {
+            if let Some(after) = Self::fetch_client(&mut *tx, client_id).await? {
+                let before = before
+                    .filter(|b| b.id == client_id)
+                    .map(|b| b.into_client(self.precision));
+                let alerts = self
+                    .alerts
+                    .crossed(before.as_ref(), &after.into_client(self.precision));
+                self.pending_alerts.lock().unwrap().extend(alerts);
+            }
+        }
+        self.step("annotations_and_alerts");
+        // A rejected withdrawal is still the client using its account
+        if outcome.is_applied() || outcome == TransactionOutcome::WithdrawalRejected {
+            let amount = transaction
+                .amount
+                .and_then(|a| self.precision.to_storage(a))
+                .unwrap_or_default();
+            let stats = ClientStatsDb::of_outcome(&outcome, amount);
+            sqlx::query(
+                "UPDATE Clients SET last_activity_at = ?1, deposits = deposits + ?2,
+                    deposited = IIF(deposited > 9223372036854775807 - ?3, 9223372036854775807, deposited + ?3),
+                    withdrawals = withdrawals + ?4,
+                    withdrawn = IIF(withdrawn > 9223372036854775807 - ?5, 9223372036854775807, withdrawn + ?5),
+                    disputes = disputes + ?6, chargebacks = chargebacks + ?7
+                WHERE id = ?8",
+            )
+            .bind(self.clock.unix_millis())
+            .bind(stats.deposits)
+            .bind(stats.deposited)
+            .bind(stats.withdrawals)
+            .bind(stats.withdrawn)
+            .bind(stats.disputes)
+            .bind(stats.chargebacks)
+            .bind(client_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+        if outcome.is_applied() {
+            self.book_counterparty(tx, transaction, &outcome, client_id)
+                .await?;
+        }
+        self.step("last_activity");
+        if self.records_events() && outcome.is_applied() {
+            let client = Self::fetch_client(&mut *tx, client_id).await?;
+            let event = LedgerEvent::Transaction {
+                transaction: transaction.clone(),
+                outcome: outcome.clone(),
+                client: client.map(|c| c.into_client(self.precision)),
+            };
+            self.record_event(tx, client_id, &event).await?;
+            self.step("record_event");
+        }
+        if matches!(outcome, TransactionOutcome::Chargeback { .. }) && !self.backfill {
+            self.escalate_chargebacks(tx, client_id).await?;
+        }
+        Ok(outcome)
+    }
+
+    /// Runs the validators, stopping at the first rejection.
+    async fn validate(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        transaction: &Transaction,
+    ) -> Result<Verdict> {
+        if self.validators.is_empty() {
+            return Ok(Verdict::accept());
+        }
+        let client = Self::fetch_client(&mut *tx, transaction.client_id)
+            .await?
+            .map(|c| c.into_client(self.precision));
+        if client.as_ref().is_some_and(|c| c.locked) {
+            // Ignored by apply_transaction
+            return Ok(Verdict::accept());
+        }
+
+        let risk_score = match client {
+            Some(_) => Self::fetch_risk_score(&mut *tx, transaction.client_id).await?,
+            None => 0,
+        };
+
+        let mut annotations = Vec::new();
+        let mut risk_score_change = 0i64;
+        for validator in &self.validators {
+            match validator.validate_with_risk(transaction, client.as_ref(), risk_score)? {
+                Verdict::Accept {
+                    annotations: a,
+                    risk_score_change: change,
+                } => {
+                    annotations.extend(a);
+                    risk_score_change = risk_score_change.saturating_add(change);
+                }
+                rejected @ Verdict::Reject { .. } => return Ok(rejected),
+            }
+        }
+        Ok(Verdict::Accept {
+            annotations,
+            risk_score_change,
+        })
+    }
+
+    async fn apply_transaction(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        transaction: &Transaction,
+    ) -> Result<TransactionOutcome> {
+        let amount_i64 = transaction
+            .amount
+            .and_then(|a| self.precision.to_storage(a));
+        let is_basic_transaction = matches!(
+            transaction.transaction_type,
+            TransactionType::Deposit | TransactionType::Withdrawal
+        );
+
+        if let (true, Some(amount)) = (is_basic_transaction, transaction.amount) {
+            if amount <= Decimal::ZERO {
+                return Ok(TransactionOutcome::Rejected {
+                    reason: TransactionOutcome::INVALID_AMOUNT.to_string(),
+                });
+            }
+            // Too large to store at all, rather than only in the sums
+            if amount_i64.is_none() {
+                let client = Self::fetch_client(&mut *tx, transaction.client_id).await?;
+                return Ok(self.overflowed(transaction.id, transaction.client_id, client));
+            }
+        }
+
+        let policy = self.client_policy(&mut *tx, transaction.client_id).await?;
+        if let Some(amount) = transaction.amount {
+            let risk_score = match policy.risk_limits.is_empty() {
+                true => 0,
+                false => Self::fetch_risk_score(&mut *tx, transaction.client_id).await?,
+            };
+            if !policy.allows(&transaction.transaction_type, amount, risk_score) {
+                return Ok(Self::ignored(IgnoreReason::OutsidePolicy));
+            }
+        }
+
+        let client = Self::fetch_client(&mut *tx, transaction.client_id)
+            .await?
+            .map(|c| c.into_client(self.precision));
+
+        if client.as_ref().is_some_and(|c| c.locked) {
+            return Ok(Self::ignored(IgnoreReason::ClientLocked));
+        }
+        if matches!(transaction.transaction_type, TransactionType::Withdrawal)
+            && Self::fetch_risk(&mut *tx, transaction.client_id)
+                .await?
+                .is_some_and(|risk| risk.level >= RiskLevel::WithdrawalsBlocked)
+        {
+            return Ok(Self::ignored(IgnoreReason::WithdrawalsBlocked));
+        }
+        if is_basic_transaction
+            && Self::fetch_transaction(&mut *tx, transaction.id)
+                .await?
+                .is_some()
+        {
+            return Ok(Self::ignored(IgnoreReason::Duplicate));
+        }
+
+        let clearing_period = transaction
+            .amount
+            .and_then(|amount| policy.clearing_period(amount));
+        if let (TransactionType::Deposit, Some(amount)) =
+            (&transaction.transaction_type, amount_i64)
+        {
+            let (available, held) = match clearing_period {
+                Some(_) => (0, amount),
+                None => (amount, 0),
+            };
+            // Before a new client is created, so that a rejected deposit leaves none behind
+            if let Some(rejected) = self
+                .reject_overflow(
+                    tx,
+                    transaction.id,
+                    transaction.client_id,
+                    available,
+                    held,
+                    amount,
+                )
+                .await?
+            {
+                return Ok(rejected);
+            }
+        }
+
+        // Create client for basic transactions if dosent exist
+        let client = match client {
+            Some(c @ Client { locked: false, .. }) => Some(c),
+            None if is_basic_transaction => Some(
+                sqlx::query_as::<_, ClientDb>(
+                    "INSERT INTO Clients (id, available, held, locked) VALUES (?, 0, 0, false)
+                    RETURNING *, (held+available) as total",
+                )
+                .bind(transaction.client_id)
+                .fetch_one(&mut *tx)
+                .await?
+                .into_client(self.precision),
+            ),
+            client => client,
+        };
+
+        if is_basic_transaction {
+            sqlx::query(
+                "INSERT INTO [Transactions] (id, [type], client_id, amount, reference, created_at)
+                VALUES (?, ?, ?, ?, ?, ?)",
+            )
+            .bind(db_id(transaction.id))
+            .bind(transaction.transaction_type.to_str())
+            .bind(transaction.client_id)
+            .bind(amount_i64)
+            .bind(&transaction.reference)
+            .bind(self.clock.unix_millis())
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        match (&transaction.transaction_type, client) {
+            (TransactionType::Deposit, Some(client)) => {
+                let amount = amount_i64.ok_or_else(|| {
+                    TransactionError::invalid(
+                        transaction.id,
+                        "Deposit transaction requires an amount",
+                    )
+                })?;
+
+                self.process_deposit(tx, transaction.id, client, amount, clearing_period)
+                    .await
+            }
+            (TransactionType::Withdrawal, Some(client)) => {
+                let amount = amount_i64.ok_or_else(|| {
+                    TransactionError::invalid(
+                        transaction.id,
+                        "Withdrawal transaction requires an amount",
+                    )
+                })?;
+
+                self.process_withdraw(tx, transaction.id, client, amount)
+                    .await
+            }
+            (TransactionType::Dispute, _) => {
+                self.process_dispute(tx, transaction, policy.dispute_window)
+                    .await
+            }
+            (TransactionType::Resolve, _) => self.process_resolve(tx, transaction.id).await,
+            (TransactionType::Chargeback, _) => self.process_chargeback(tx, transaction.id).await,
+            (TransactionType::Custom(name), _) => self.process_custom(tx, name, transaction).await,
+            (TransactionType::Deposit | TransactionType::Withdrawal, None) => {
+                unreachable!("Clients are created for deposits and withdrawals")
+            }
+        }
+    }
+
+    /// Counts the committed `outcome` and notifies the observers of it.
+    fn notify(&self, transaction: &Transaction, outcome: &TransactionOutcome) {
+        self.transaction_counts
+            .lock()
+            .unwrap()
+            .entry(transaction.transaction_type.to_str().to_string())
+            .or_default()
+            .record(outcome);
+        if *outcome == TransactionOutcome::WithdrawalRejected {
+            tracing::warn!(
+                tx = transaction.id,
+                client = %self.client_label(transaction.client_id),
+                amount = ?transaction.amount,
+                "Rejected a withdrawal for insufficient funds"
+            );
+        }
+        if let TransactionOutcome::Ignored {
+            reason: reason @ (IgnoreReason::UnknownTarget | IgnoreReason::NotDisputed),
+        } = outcome
+        {
+            if self.unknown_targets == UnknownTargetAction::Warn {
+                self.unknown_target_count.fetch_add(1, Ordering::Relaxed);
+                tracing::warn!(
+                    tx = transaction.id,
+                    r#type = transaction.transaction_type.to_str(),
+                    reason = reason.to_str(),
+                    "Ignored a transaction with an unknown target"
+                );
+            }
+        }
+        for observer in &self.observers {
+            match outcome {
+                TransactionOutcome::Deposit => observer.on_deposit(transaction),
+                TransactionOutcome::Withdrawal => observer.on_withdrawal(transaction),
+                TransactionOutcome::WithdrawalRejected => {
+                    observer.on_withdrawal_rejected(transaction)
+                }
+                TransactionOutcome::DisputeOpened(disputed) => observer.on_dispute_opened(disputed),
+                TransactionOutcome::DisputeResolved(disputed) => {
+                    observer.on_dispute_resolved(disputed)
+                }
+                TransactionOutcome::Chargeback { disputed, locked } => {
+                    observer.on_chargeback(disputed);
+                    if *locked {
+                        observer.on_client_locked(disputed.client_id);
+                    }
+                }
+                TransactionOutcome::Custom => observer.on_custom(transaction),
+                TransactionOutcome::Rejected { reason } => {
+                    observer.on_rejected(transaction, reason)
+                }
+                TransactionOutcome::Ignored { .. } => {}
+            }
+        }
+    }
+
+    #[tracing::instrument(
+        level = "debug",
+        skip(self, tx, client),
+        fields(client = %self.client_label(client.id))
+    )]
+    async fn process_deposit(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        transaction_id: TransactionId,
+        client: Client,
+        amount: i64,
+        clearing_period: Option<Duration>,
+    ) -> Result<TransactionOutcome> {
+        let Some(clearing_period) = clearing_period else {
+            sqlx::query("UPDATE Clients SET available = (available + ?) WHERE id=?")
+                .bind(amount)
+                .bind(client.id)
+                .execute(tx)
+                .await?;
+            return Ok(TransactionOutcome::Deposit);
+        };
+
+        sqlx::query("UPDATE Clients SET held = (held + ?) WHERE id=?")
+            .bind(amount)
+            .bind(client.id)
+            .execute(&mut *tx)
+            .await?;
+        let clearing_period = i64::try_from(clearing_period.as_millis()).unwrap_or(i64::MAX);
+        sqlx::query("INSERT INTO Settlements (transaction_id, release_at) VALUES (?, ?)")
+            .bind(db_id(transaction_id))
+            .bind(self.clock.unix_millis().saturating_add(clearing_period))
+            .execute(tx)
+            .await?;
+
+        Ok(TransactionOutcome::Deposit)
+    }
+
+    #[tracing::instrument(
+        level = "debug",
+        skip(self, tx, client),
+        fields(client = %self.client_label(client.id))
+    )]
+    async fn process_withdraw(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        _transaction_id: TransactionId,
+        client: Client,
+        amount: i64,
+    ) -> Result<TransactionOutcome> {
+        let result = sqlx::query(
+            "UPDATE Clients SET available = (available - ?) WHERE id=? AND available >= ?",
+        )
+        .bind(amount)
+        .bind(client.id)
+        .bind(amount)
+        .execute(tx)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Ok(TransactionOutcome::WithdrawalRejected);
+        }
+        Ok(TransactionOutcome::Withdrawal)
+    }
+
+    #[tracing::instrument(level = "debug", skip(self, tx, dispute), fields(transaction_id = dispute.id))]
+    async fn process_dispute(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        dispute: &Transaction,
+        dispute_window: Option<Duration>,
+    ) -> Result<TransactionOutcome> {
+        let transaction_id = dispute.id;
+        let disputed_transaction = match Self::fetch_transaction(&mut *tx, transaction_id).await? {
+            Some(t) => t.into_transaction(self.precision)?,
+            None => return self.unknown_target(transaction_id, IgnoreReason::UnknownTarget),
+        };
+        let voided: bool =
+            sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM Voids WHERE transaction_id=?)")
+                .bind(db_id(transaction_id))
+                .fetch_one(&mut *tx)
+                .await?;
+        if voided {
+            return self.unknown_target(transaction_id, IgnoreReason::UnknownTarget);
+        }
+        if let Some(window) = dispute_window {
+            let created_at: Option<i64> =
+                sqlx::query_scalar("SELECT created_at FROM [Transactions] WHERE id=?")
+                    .bind(db_id(transaction_id))
+                    .fetch_one(&mut *tx)
+                    .await?;
+            let window = i64::try_from(window.as_millis()).unwrap_or(i64::MAX);
+            if created_at.is_some_and(|created_at| self.clock.unix_millis() - created_at > window) {
+                return Ok(Self::ignored(IgnoreReason::OutsidePolicy));
+            }
+        }
+        if Self::fetch_dispute(&mut *tx, transaction_id)
+            .await?
+            .is_some()
+        {
+            return Ok(Self::ignored(IgnoreReason::Duplicate));
+        }
+
+        let amount_i64 = disputed_transaction
+            .amount
+            .and_then(|a| self.precision.to_storage(a))
+            .ok_or_else(|| {
+                TransactionError::invalid(transaction_id, "No amount in disputed transaction")
+            })?;
+        // A deposit that is still clearing is already held, and stays held until the dispute
+        // is closed
+        if !Self::is_clearing(&mut *tx, transaction_id).await? {
+            if let Some(rejected) = self
+                .reject_overflow(
+                    tx,
+                    transaction_id,
+                    disputed_transaction.client_id,
+                    -amount_i64,
+                    amount_i64,
+                    0,
+                )
+                .await?
+            {
+                return Ok(rejected);
+            }
+
+            sqlx::query(
+                "UPDATE Clients SET available = (available - ?), held = (held + ?) WHERE id=?",
+            )
+            .bind(amount_i64)
+            .bind(amount_i64)
+            .bind(disputed_transaction.client_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        sqlx::query(
+            "INSERT INTO Disputes (transaction_id, opened_at, reason_code, notes) VALUES (?, ?, ?, ?)",
+        )
+        .bind(db_id(transaction_id))
+        .bind(self.clock.unix_millis())
+        .bind(&dispute.reason_code)
+        .bind(&dispute.notes)
+        .execute(tx)
+            .await?;
+
+        Ok(TransactionOutcome::DisputeOpened(disputed_transaction))
+    }
+
+    #[tracing::instrument(level = "debug", skip(self, tx))]
+    async fn process_resolve(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        transaction_id: TransactionId,
+    ) -> Result<TransactionOutcome> {
+        let disputed_transaction = match Self::fetch_dispute(&mut *tx, transaction_id).await? {
+            Some(t) => t.into_transaction(self.precision)?,
+            None => return self.unknown_target(transaction_id, IgnoreReason::NotDisputed),
+        };
+
+        let amount_i64 = disputed_transaction
+            .amount
+            .and_then(|a| self.precision.to_storage(a))
+            .ok_or_else(|| {
+                TransactionError::invalid(transaction_id, "No amount in disputed transaction")
+            })?;
+        // A deposit that is still clearing stays held until it clears
+        if !Self::is_clearing(&mut *tx, transaction_id).await? {
+            if let Some(rejected) = self
+                .reject_overflow(
+                    tx,
+                    transaction_id,
+                    disputed_transaction.client_id,
+                    amount_i64,
+                    -amount_i64,
+                    0,
+                )
+                .await?
+            {
+                return Ok(rejected);
+            }
+
+            sqlx::query("UPDATE Clients SET available = available + ?, held = held - ? WHERE id=?")
+                .bind(amount_i64)
+                .bind(amount_i64)
+                .bind(disputed_transaction.client_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        sqlx::query("DELETE FROM DisputeEvidence WHERE transaction_id=?")
+            .bind(db_id(transaction_id))
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM Disputes WHERE transaction_id=?")
+            .bind(db_id(transaction_id))
+            .execute(tx)
+            .await?;
+        Ok(TransactionOutcome::DisputeResolved(disputed_transaction))
+    }
+
+    #[tracing::instrument(level = "debug", skip(self, tx))]
+    async fn process_chargeback(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        transaction_id: TransactionId,
+    ) -> Result<TransactionOutcome> {
+        let disputed_transaction = match Self::fetch_dispute(&mut *tx, transaction_id).await? {
+            Some(t) => t.into_transaction(self.precision)?,
+            None => return self.unknown_target(transaction_id, IgnoreReason::NotDisputed),
+        };
+
+        let amount_i64 = disputed_transaction
+            .amount
+            .and_then(|a| self.precision.to_storage(a))
+            .ok_or_else(|| {
+                TransactionError::invalid(transaction_id, "No amount in disputed transaction")
+            })?;
+
+        let was_locked = Self::fetch_client(&mut *tx, disputed_transaction.client_id)
+            .await?
+            .is_some_and(|c| c.locked);
+
+        sqlx::query("UPDATE Clients SET held = held - ?, locked=true WHERE id=?")
+            .bind(amount_i64)
+            .bind(disputed_transaction.client_id)
+            .execute(&mut *tx)
+            .await?;
+        // The charged back funds of a deposit that was still clearing are never released
+        sqlx::query("DELETE FROM Settlements WHERE transaction_id=?")
+            .bind(db_id(transaction_id))
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("DELETE FROM DisputeEvidence WHERE transaction_id=?")
+            .bind(db_id(transaction_id))
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM Disputes WHERE transaction_id=?")
+            .bind(db_id(transaction_id))
+            .execute(tx)
+            .await?;
+
+        Ok(TransactionOutcome::Chargeback {
+            disputed: disputed_transaction,
+            locked: !was_locked,
+        })
+    }
+
+    #[tracing::instrument(level = "debug", skip(self, tx, transaction))]
+    async fn process_custom(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        name: &str,
+        transaction: &Transaction,
+    ) -> Result<TransactionOutcome> {
+        let handler = self.handlers.get(name).ok_or_else(|| {
+            TransactionError::invalid(
+                transaction.id,
+                format!("Unknown transaction type \"{}\"", name),
+            )
+        })?;
+
+        let mut storage = StorageHandle::new(tx, self.precision);
+        if handler.apply(&mut storage, transaction).await? {
+            Ok(TransactionOutcome::Custom)
+        } else {
+            Ok(Self::ignored(IgnoreReason::Declined))
+        }
+    }
+
+    fn ignored(reason: IgnoreReason) -> TransactionOutcome {
+        TransactionOutcome::Ignored { reason }
+    }
+
+    /// Rejects a transaction that would change `client_id`'s available and held funds, and
+    /// the cash house account, by more than their stored fixed point values can hold, raising
+    /// an [`AlertKind::BalanceOverflow`] alert. Sqlite would otherwise silently turn the
+    /// overflowing sums into floating point values. A client that does not exist yet has no
+    /// funds.
+    async fn reject_overflow(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        transaction_id: TransactionId,
+        client_id: ClientId,
+        available: i64,
+        held: i64,
+        cash: i64,
+    ) -> Result<Option<TransactionOutcome>> {
+        let client = Self::fetch_client(&mut *tx, client_id).await?;
+        let (current_available, current_held, total) = client
+            .as_ref()
+            .map_or((0, 0, 0), |c| (c.available, c.held, c.total));
+        let mut fits = current_available.checked_add(available).is_some()
+            && current_held.checked_add(held).is_some()
+            && available
+                .checked_add(held)
+                .and_then(|change| total.checked_add(change))
+                .is_some();
+        if fits && cash != 0 {
+            let balance: i64 =
+                sqlx::query_scalar("SELECT balance FROM HouseAccounts WHERE name = ?")
+                    .bind(CASH)
+                    .fetch_one(&mut *tx)
+                    .await?;
+            fits = balance.checked_add(cash).is_some();
+        }
+        if fits {
+            return Ok(None);
+        }
+        Ok(Some(self.overflowed(transaction_id, client_id, client)))
+    }
+
+    /// The rejection of a transaction overflowing the balances of `client_id`, currently
+    /// `client`, raising an [`AlertKind::BalanceOverflow`] alert.
+    fn overflowed(
+        &self,
+        transaction_id: TransactionId,
+        client_id: ClientId,
+        client: Option<ClientDb>,
+    ) -> TransactionOutcome {
+        let alert = self.overflow_alert(client_id, client);
+        tracing::warn!(
+            tx = transaction_id,
+            client = %self.client_label(client_id),
+            available = %alert.available,
+            held = %alert.held,
+            "Rejected a transaction overflowing the balances"
+        );
+        self.pending_alerts.lock().unwrap().push(alert);
+        TransactionOutcome::Rejected {
+            reason: TransactionOutcome::OVERFLOW.to_string(),
+        }
+    }
+
+    /// The [`AlertKind::BalanceOverflow`] alert of `client_id`, currently `client`.
+    fn overflow_alert(&self, client_id: ClientId, client: Option<ClientDb>) -> Alert {
+        let (available, held) = client.map_or((Decimal::ZERO, Decimal::ZERO), |c| {
+            let c = c.into_client(self.precision);
+            (c.available, c.held)
+        });
+        Alert {
+            kind: AlertKind::BalanceOverflow,
+            threshold: self.precision.to_decimal(i64::MAX),
+            client_id,
+            available,
+            held,
+        }
+    }
+
+    /// The outcome of a dispute, resolve or chargeback of `transaction_id` whose target is
+    /// unknown for `reason`, according to the service's [`UnknownTargetAction`].
+    fn unknown_target(
+        &self,
+        transaction_id: TransactionId,
+        reason: IgnoreReason,
+    ) -> Result<TransactionOutcome> {
+        if self.unknown_targets != UnknownTargetAction::Error {
+            return Ok(Self::ignored(reason));
+        }
+        Err(TransactionError::invalid(
+            transaction_id,
+            match reason {
+                IgnoreReason::UnknownTarget => "Disputes a transaction that is not stored",
+                _ => "The transaction is not under dispute",
+            },
+        ))
+    }
+
+    /// Books the counterparty of the change `outcome` made to `client_id`'s funds in the house
+    /// accounts, see [`HouseAccount`].
+    async fn book_counterparty(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        transaction: &Transaction,
+        outcome: &TransactionOutcome,
+        client_id: ClientId,
+    ) -> Result<()> {
+        let to_storage = |amount: Option<Decimal>| {
+            amount
+                .and_then(|a| self.precision.to_storage(a))
+                .unwrap_or_default()
+        };
+        match outcome {
+            TransactionOutcome::Deposit => {
+                Self::book(&mut *tx, CASH, to_storage(transaction.amount)).await
+            }
+            TransactionOutcome::Withdrawal => {
+                Self::book(&mut *tx, CASH, -to_storage(transaction.amount)).await
+            }
+            TransactionOutcome::Chargeback { disputed, .. } => {
+                let amount = to_storage(disputed.amount);
+                Self::book(&mut *tx, CASH, -amount).await?;
+                let after = Self::fetch_client(&mut *tx, client_id)
+                    .await?
+                    .map(|c| c.total)
+                    .unwrap_or_default();
+                // Only the part of the total that went below zero
+                let shortfall = (-after).max(0) - (-(after + amount)).max(0);
+                if shortfall > 0 {
+                    Self::book(&mut *tx, CHARGEBACK_EXPENSE, shortfall).await?;
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn book<'e, E: Executor<'e, Database = Sqlite>>(
+        executor: E,
+        account: &str,
+        amount: i64,
+    ) -> Result<()> {
+        sqlx::query("UPDATE HouseAccounts SET balance = balance + ? WHERE name = ?")
+            .bind(amount)
+            .bind(account)
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+
+    async fn fetch_external_ids<'e, E: Executor<'e, Database = Sqlite>>(
+        executor: E,
+    ) -> Result<Vec<ExternalId>> {
+        let mappings: Vec<(String, ClientId)> =
+            sqlx::query_as("SELECT external_id, client_id FROM ExternalIds ORDER BY client_id")
+                .fetch_all(executor)
+                .await?;
+        Ok(mappings
+            .into_iter()
+            .map(|(external_id, client)| ExternalId {
+                external_id,
+                client,
+            })
+            .collect())
+    }
+
+    async fn fetch_house_accounts<'e, E: Executor<'e, Database = Sqlite>>(
+        &self,
+        executor: E,
+    ) -> Result<Vec<HouseAccount>> {
+        let accounts: Vec<(String, i64)> =
+            sqlx::query_as("SELECT name, balance FROM HouseAccounts ORDER BY name")
+                .fetch_all(executor)
+                .await?;
+        Ok(accounts
+            .into_iter()
+            .map(|(name, balance)| HouseAccount {
+                name,
+                balance: self.precision.to_decimal(balance),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::audit::{chain_hash, content_hash};
+    use super::{
+        db_id, Annotation, BalanceSnapshot, Client, ClientFilter, ClientStats, ClientTag, Dispute,
+        ErasurePolicy, EventObserver, ExternalId, GroupBy, LedgerState, Pagination,
+        ProcessingOutcome, StorageHandle, Transaction, TransactionError, TransactionFilter,
+        TransactionHandler, TransactionOutcome, TransactionPolicy, TransactionService,
+        TransactionType, TransactionValidator, UnknownTargetAction, Verdict, STATE_VERSION,
+    };
+    use crate::{
+        ApprovalAction, ApprovalRules, ApprovalStatus, ChargebackEscalation, ClientId,
+        DisputeStatus, ExpiredHoldAction, HoldExpiry, IgnoreReason, ManualClock, RetryPolicy,
+        RiskLevel, RiskLimit, RuleSet, SegmentPolicy, SettlementDelay, TransactionId,
+    };
+    use futures::{future::BoxFuture, TryStreamExt};
+    use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    async fn create_service() -> TransactionService {
+        let options = SqliteConnectOptions::from_str("sqlite://:memory:")
+            .unwrap()
+            .create_if_missing(true);
+        let db_pool = sqlx::sqlite::SqlitePool::connect_with(options)
+            .await
+            .unwrap();
+        TransactionService::new(db_pool).await.unwrap()
+    }
+
+    async fn test_service(transactions: &[Transaction], mut expected_output: Vec<Client>) {
+        let svc = create_service().await;
+        for t in transactions {
+            svc.process_transaction(t).await.unwrap();
+        }
+
+        let mut clients: Vec<Client> = svc.get_clients_vec().await.unwrap();
+        clients.sort_by_key(|c| c.id);
+
+        expected_output.sort_by_key(|c| c.id);
+
+        assert_eq!(clients, expected_output);
+    }
+    #[tokio::test]
+    async fn test_deposit() {
+        test_service(
+            &[
+                Transaction {
+                    id: 0,
+                    transaction_type: TransactionType::Deposit,
+                    client_id: 1,
+                    amount: Decimal::from_f64(10.5563),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 1,
+                    transaction_type: TransactionType::Deposit,
+                    client_id: 1,
+                    amount: Decimal::from_f64(2.1234),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 2,
+                    transaction_type: TransactionType::Deposit,
+                    client_id: 1,
+                    amount: Decimal::from_f64(13.5),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 3,
+                    transaction_type: TransactionType::Deposit,
+                    client_id: 1,
+                    amount: Decimal::from_f64(1.3),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 4,
+                    transaction_type: TransactionType::Deposit,
+                    client_id: 2,
+                    amount: Decimal::from_f64(10.5563),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+            ],
+            vec![
+                Client {
+                    id: 1,
+                    available: dec!(27.4797),
+                    held: dec!(0.0),
+                    total: dec!(27.4797),
+                    locked: false,
+                },
+                Client {
+                    id: 2,
+                    available: dec!(10.5563),
+                    held: dec!(0.0),
+                    total: dec!(10.5563),
+                    locked: false,
+                },
+            ],
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_deposit_withdraw() {
+        test_service(
+            &[
+                Transaction {
+                    id: 0,
+                    transaction_type: TransactionType::Deposit,
+                    client_id: 1,
+                    amount: Decimal::from_f64(10.5563),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 1,
+                    transaction_type: TransactionType::Deposit,
+                    client_id: 1,
+                    amount: Decimal::from_f64(2.1234),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 2,
+                    transaction_type: TransactionType::Deposit,
+                    client_id: 1,
+                    amount: Decimal::from_f64(13.5),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 3,
+                    transaction_type: TransactionType::Deposit,
+                    client_id: 1,
+                    amount: Decimal::from_f64(1.3),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 4,
+                    transaction_type: TransactionType::Withdrawal,
+                    client_id: 1,
+                    amount: Decimal::from_f64(5.8367),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 5,
+                    transaction_type: TransactionType::Deposit,
+                    client_id: 2,
+                    amount: Decimal::from_f64(10.5563),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 6,
+                    transaction_type: TransactionType::Deposit,
+                    client_id: 3,
+                    amount: Decimal::from_f64(2.1234),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 7,
+                    transaction_type: TransactionType::Deposit,
+                    client_id: 2,
+                    amount: Decimal::from_f64(13.5),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 8,
+                    transaction_type: TransactionType::Deposit,
+                    client_id: 3,
+                    amount: Decimal::from_f64(1.3),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 9,
+                    transaction_type: TransactionType::Withdrawal,
+                    client_id: 2,
+                    amount: Decimal::from_f64(5.8367),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                // Withdraw should fail
+                Transaction {
+                    id: 10,
+                    transaction_type: TransactionType::Withdrawal,
+                    client_id: 3,
+                    amount: Decimal::from_f64(5.8367),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 11,
+                    transaction_type: TransactionType::Withdrawal,
+                    client_id: 1,
+                    amount: Decimal::from_f64(5.8367),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+            ],
+            vec![
+                Client {
+                    id: 1,
+                    available: dec!(15.8063),
+                    held: dec!(0.0),
+                    total: dec!(15.8063),
+                    locked: false,
+                },
+                Client {
+                    id: 2,
+                    available: dec!(18.2196),
+                    held: dec!(0.0),
+                    total: dec!(18.2196),
+                    locked: false,
+                },
+                Client {
+                    id: 3,
+                    available: dec!(3.4234),
+                    held: dec!(0.0),
+                    total: dec!(3.4234),
+                    locked: false,
+                },
+            ],
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_disputes() {
+        test_service(
+            &[
+                Transaction {
+                    id: 0,
+                    transaction_type: TransactionType::Deposit,
+                    client_id: 1,
+                    amount: Decimal::from_f64(10.5563),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 1,
+                    transaction_type: TransactionType::Deposit,
+                    client_id: 1,
+                    amount: Decimal::from_f64(2.1234),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 2,
+                    transaction_type: TransactionType::Deposit,
+                    client_id: 1,
+                    amount: Decimal::from_f64(13.5),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 3,
+                    transaction_type: TransactionType::Deposit,
+                    client_id: 1,
+                    amount: Decimal::from_f64(1.3),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 4,
+                    transaction_type: TransactionType::Withdrawal,
+                    client_id: 1,
+                    amount: Decimal::from_f64(5.8367),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 5,
+                    transaction_type: TransactionType::Deposit,
+                    client_id: 2,
+                    amount: Decimal::from_f64(10.5563),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 6,
+                    transaction_type: TransactionType::Deposit,
+                    client_id: 3,
+                    amount: Decimal::from_f64(2.1234),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 7,
+                    transaction_type: TransactionType::Deposit,
+                    client_id: 2,
+                    amount: Decimal::from_f64(13.5),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 8,
+                    transaction_type: TransactionType::Deposit,
+                    client_id: 3,
+                    amount: Decimal::from_f64(1.3),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 9,
+                    transaction_type: TransactionType::Withdrawal,
+                    client_id: 2,
+                    amount: Decimal::from_f64(5.8367),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                // Withdraw should fail
+                Transaction {
+                    id: 10,
+                    transaction_type: TransactionType::Withdrawal,
+                    client_id: 3,
+                    amount: Decimal::from_f64(5.8367),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 11,
+                    transaction_type: TransactionType::Withdrawal,
+                    client_id: 1,
+                    amount: Decimal::from_f64(5.8367),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 3,
+                    transaction_type: TransactionType::Dispute,
+                    client_id: 1,
+                    amount: None,
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 3,
+                    transaction_type: TransactionType::Resolve,
+                    client_id: 1,
+                    amount: None,
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 5,
+                    transaction_type: TransactionType::Dispute,
+                    client_id: 2,
+                    amount: None,
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 5,
+                    transaction_type: TransactionType::Chargeback,
+                    client_id: 2,
+                    amount: None,
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 8,
+                    transaction_type: TransactionType::Dispute,
+                    client_id: 3,
+                    amount: None,
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+            ],
+            vec![
+                Client {
+                    id: 1,
+                    available: dec!(15.8063),
+                    held: dec!(0.0),
+                    total: dec!(15.8063),
+                    locked: false,
+                },
+                Client {
+                    id: 2,
+                    available: dec!(7.6633),
+                    held: dec!(0.0),
+                    total: dec!(7.6633),
+                    locked: true,
+                },
+                Client {
+                    id: 3,
+                    available: dec!(2.1234),
+                    held: dec!(1.3),
+                    total: dec!(3.4234),
+                    locked: false,
+                },
+            ],
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_deposit_requires_amount() {
+        let svc = create_service().await;
+        let err = svc
+            .process_transaction(&Transaction {
+                id: 0,
+                transaction_type: TransactionType::Deposit,
+                client_id: 1,
+                amount: None,
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            TransactionError::InvalidTransaction {
+                transaction_id: 0,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_precision() {
+        let svc = TransactionService::builder()
+            .precision(2)
+            .build()
+            .await
+            .unwrap();
+        svc.process_transaction(&Transaction {
+            id: 0,
+            transaction_type: TransactionType::Deposit,
+            client_id: 1,
+            amount: Some(dec!(1.2399)),
+            reference: None,
+            reason_code: None,
+            notes: None,
+            sequence: None,
+        })
+        .await
+        .unwrap();
+
+        let client = svc.get_client(1).await.unwrap().unwrap();
+        assert_eq!(client.available, dec!(1.23));
+
+        assert!(TransactionService::builder()
+            .precision(18)
+            .build()
+            .await
+            .is_ok());
+        assert!(matches!(
+            TransactionService::builder().precision(19).build().await,
+            Err(TransactionError::InvalidArgument(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_policy_limits() {
+        let svc = TransactionService::builder()
+            .policy(TransactionPolicy {
+                max_deposit: Some(dec!(100)),
+                max_withdrawal: Some(dec!(10)),
+                ..Default::default()
+            })
+            .build()
+            .await
+            .unwrap();
+
+        for t in [
+            Transaction {
+                id: 0,
+                transaction_type: TransactionType::Deposit,
+                client_id: 1,
+                amount: Some(dec!(100)),
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            },
+            // Over the deposit limit
+            Transaction {
+                id: 1,
+                transaction_type: TransactionType::Deposit,
+                client_id: 1,
+                amount: Some(dec!(100.01)),
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            },
+            // Over the withdrawal limit
+            Transaction {
+                id: 2,
+                transaction_type: TransactionType::Withdrawal,
+                client_id: 1,
+                amount: Some(dec!(20)),
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            },
+            Transaction {
+                id: 3,
+                transaction_type: TransactionType::Withdrawal,
+                client_id: 1,
+                amount: Some(dec!(10)),
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            },
+        ] {
+            svc.process_transaction(&t).await.unwrap();
+        }
+
+        let client = svc.get_client(1).await.unwrap().unwrap();
+        assert_eq!(client.available, dec!(90));
+        assert!(svc.get_transaction(1).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_segment_policy() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let svc = TransactionService::builder()
+            .clock(clock.clone())
+            .policy(TransactionPolicy {
+                max_withdrawal: Some(dec!(10)),
+                dispute_window: Some(Duration::from_secs(60)),
+                segments: vec![SegmentPolicy {
+                    tag: "vip".to_string(),
+                    policy: TransactionPolicy {
+                        max_withdrawal: Some(dec!(100)),
+                        ..Default::default()
+                    },
+                }],
+                ..Default::default()
+            })
+            .build()
+            .await
+            .unwrap();
+        svc.tag_clients(&[ClientTag {
+            client: 2,
+            tag: "vip".to_string(),
+        }])
+        .await
+        .unwrap();
+
+        let process = |transaction_type, id, client_id, amount| {
+            let svc = &svc;
+            async move {
+                svc.process_transaction(&Transaction {
+                    id,
+                    transaction_type,
+                    client_id,
+                    amount,
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                })
+                .await
+                .unwrap()
+            }
+        };
+        for (id, client_id) in [(1, 1), (2, 2)] {
+            process(TransactionType::Deposit, id, client_id, Some(dec!(100))).await;
+        }
+        let outside_policy = TransactionOutcome::Ignored {
+            reason: IgnoreReason::OutsidePolicy,
+        };
+        assert_eq!(
+            process(TransactionType::Withdrawal, 3, 1, Some(dec!(50))).await,
+            outside_policy
+        );
+        assert_eq!(
+            process(TransactionType::Withdrawal, 4, 2, Some(dec!(50))).await,
+            TransactionOutcome::Withdrawal
+        );
+
+        clock.advance(Duration::from_secs(120));
+        assert_eq!(
+            process(TransactionType::Dispute, 1, 1, None).await,
+            outside_policy
+        );
+        assert!(matches!(
+            process(TransactionType::Dispute, 2, 2, None).await,
+            TransactionOutcome::DisputeOpened(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_risk_score() {
+        let svc = TransactionService::builder()
+            .validator(
+                RuleSet::new([
+                    r#"score +10 when type == "withdrawal" && amount >= 50"#,
+                    r#"score -5 when type == "deposit" && risk_score > 0"#,
+                ])
+                .unwrap(),
+            )
+            .policy(TransactionPolicy {
+                risk_limits: vec![RiskLimit {
+                    min_score: 20,
+                    max_deposit: None,
+                    max_withdrawal: Some(dec!(1)),
+                }],
+                ..Default::default()
+            })
+            .build()
+            .await
+            .unwrap();
+        let process = |transaction_type, id, amount| {
+            let svc = &svc;
+            async move {
+                svc.process_transaction(&Transaction {
+                    id,
+                    transaction_type,
+                    client_id: 1,
+                    amount: Some(amount),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                })
+                .await
+                .unwrap()
+            }
+        };
+        let risk_score = || async { svc.get_client_stats().await.unwrap()[0].stats.risk_score };
+
+        process(TransactionType::Deposit, 1, dec!(10)).await;
+        assert_eq!(risk_score().await, 0);
+        // Rejected withdrawals still raise the score
+        for id in [2, 3] {
+            assert_eq!(
+                process(TransactionType::Withdrawal, id, dec!(50)).await,
+                TransactionOutcome::WithdrawalRejected
+            );
+        }
+        assert_eq!(risk_score().await, 20);
+        assert_eq!(
+            process(TransactionType::Withdrawal, 4, dec!(5)).await,
+            TransactionOutcome::Ignored {
+                reason: IgnoreReason::OutsidePolicy
+            }
+        );
+
+        process(TransactionType::Deposit, 5, dec!(10)).await;
+        assert_eq!(risk_score().await, 15);
+        let risky = ClientFilter {
+            min_risk_score: Some(15),
+            ..Default::default()
+        };
+        let clients: Vec<Client> = svc
+            .get_clients(&risky, Pagination::default())
+            .await
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(clients.len(), 1);
+        let riskier = ClientFilter {
+            min_risk_score: Some(16),
+            ..Default::default()
+        };
+        let clients: Vec<Client> = svc
+            .get_clients(&riskier, Pagination::default())
+            .await
+            .try_collect()
+            .await
+            .unwrap();
+        assert!(clients.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_risk_score_replayed() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let rules =
+            || RuleSet::new([r#"score +10 when type == "withdrawal" && amount >= 50"#]).unwrap();
+        let csv = "type, client, tx, amount
+            deposit, 1, 1, 100
+            withdrawal, 1, 2, 50
+            withdrawal, 1, 3, 500";
+        let replay = |svc: TransactionService| async move {
+            let mut reader = crate::TransactionReader::new(csv.as_bytes());
+            svc.process_stream(futures::stream::iter(reader.transactions()))
+                .await
+                .unwrap();
+            svc.get_client_stats().await.unwrap()[0].stats.risk_score
+        };
+        let build = |backfill| {
+            TransactionService::builder()
+                .pool(pool.clone())
+                .validator(rules())
+                .backfill(backfill)
+                .build()
+        };
+
+        assert_eq!(replay(build(false).await.unwrap()).await, 20);
+        // Every transaction is ignored as a duplicate
+        assert_eq!(replay(build(false).await.unwrap()).await, 20);
+        assert_eq!(replay(build(true).await.unwrap()).await, 20);
+    }
+
+    #[tokio::test]
+    async fn test_client_locks() {
+        let svc = TransactionService::new(SqlitePool::connect("sqlite::memory:").await.unwrap())
+            .await
+            .unwrap();
+        let transaction = |transaction_type, client_id, id, amount| Transaction {
+            id,
+            transaction_type,
+            client_id,
+            amount,
+            reference: None,
+            reason_code: None,
+            notes: None,
+            sequence: None,
+        };
+        svc.process_transaction(&transaction(TransactionType::Deposit, 1, 1, Some(dec!(10))))
+            .await
+            .unwrap();
+        async fn waits(f: impl std::future::Future) -> bool {
+            tokio::time::timeout(Duration::from_millis(50), f)
+                .await
+                .is_err()
+        }
+
+        let held = svc.client_locks.lock(1).await;
+        // Client 2 disputing client 1's deposit changes client 1
+        let dispute = transaction(TransactionType::Dispute, 2, 1, None);
+        assert!(waits(svc.process_transaction(&dispute)).await);
+        let csv = "type, client, tx, amount
+            deposit, 1, 2, 1.0";
+        let mut reader = crate::TransactionReader::new(csv.as_bytes());
+        assert!(waits(svc.process_stream(futures::stream::iter(reader.transactions()))).await);
+        assert!(waits(svc.adjust_balance(1, dec!(1), "Fee refund", "ops")).await);
+        assert!(
+            !waits(svc.process_transaction(&transaction(
+                TransactionType::Deposit,
+                3,
+                3,
+                Some(dec!(1))
+            )))
+            .await
+        );
+        drop(held);
+
+        assert!(matches!(
+            svc.process_transaction(&dispute).await.unwrap(),
+            TransactionOutcome::DisputeOpened(_)
+        ));
+        assert_eq!(svc.client_locks.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_risk_score_cross_client_dispute() {
+        let svc = TransactionService::builder()
+            .validator(RuleSet::new([r#"score +30 when type == "dispute""#]).unwrap())
+            .build()
+            .await
+            .unwrap();
+        // Client 2 disputes client 1's deposit
+        let csv = "type, client, tx, amount
+            deposit, 1, 1, 100
+            deposit, 2, 2, 100
+            dispute, 2, 1,";
+        let mut reader = crate::TransactionReader::new(csv.as_bytes());
+        svc.process_stream(futures::stream::iter(reader.transactions()))
+            .await
+            .unwrap();
+
+        let scores: Vec<_> = svc
+            .get_client_stats()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|c| (c.client.id, c.stats.risk_score))
+            .collect();
+        assert_eq!(scores, [(1, 30), (2, 0)]);
+    }
+
+    #[tokio::test]
+    async fn test_settlement_delay() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let svc = TransactionService::builder()
+            .clock(clock.clone())
+            .policy(TransactionPolicy {
+                settlement_delay: Some(SettlementDelay {
+                    threshold: dec!(50),
+                    clearing_period: Duration::from_secs(60 * 60),
+                }),
+                ..Default::default()
+            })
+            .build()
+            .await
+            .unwrap();
+        let process = |transaction_type, id, amount| {
+            let svc = &svc;
+            async move {
+                svc.process_transaction(&Transaction {
+                    id,
+                    transaction_type,
+                    client_id: 1,
+                    amount: Some(amount),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                })
+                .await
+                .unwrap()
+            }
+        };
+        let balances = || async {
+            let client = svc.get_client(1).await.unwrap().unwrap();
+            (client.available, client.held)
+        };
+
+        process(TransactionType::Deposit, 1, dec!(100)).await;
+        process(TransactionType::Deposit, 2, dec!(20)).await;
+        assert_eq!(balances().await, (dec!(20), dec!(100)));
+        assert_eq!(
+            process(TransactionType::Withdrawal, 3, dec!(50)).await,
+            TransactionOutcome::WithdrawalRejected
+        );
+
+        let state = svc.export_state().await.unwrap();
+        assert_eq!(state.settlements.len(), 1);
+        assert_eq!(state.settlements[0].tx, 1);
+
+        clock.advance(Duration::from_secs(30 * 60));
+        assert_eq!(svc.release_settlements().await.unwrap(), []);
+        clock.advance(Duration::from_secs(30 * 60));
+        let released = svc.release_settlements().await.unwrap();
+        assert_eq!(released.iter().map(|t| t.id).collect::<Vec<_>>(), [1]);
+        assert_eq!(balances().await, (dec!(120), dec!(0)));
+        assert_eq!(svc.release_settlements().await.unwrap(), []);
+
+        // The held deposit is still held after moving the ledger
+        let imported = TransactionService::builder()
+            .clock(clock.clone())
+            .build()
+            .await
+            .unwrap();
+        imported.import_state(&state).await.unwrap();
+        assert_eq!(imported.release_settlements().await.unwrap().len(), 1);
+        let client = imported.get_client(1).await.unwrap().unwrap();
+        assert_eq!((client.available, client.held), (dec!(120), dec!(0)));
+    }
+
+    #[tokio::test]
+    async fn test_settlement_disputes() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let svc = TransactionService::builder()
+            .clock(clock.clone())
+            .policy(TransactionPolicy {
+                settlement_delay: Some(SettlementDelay {
+                    threshold: dec!(50),
+                    clearing_period: Duration::from_secs(60 * 60),
+                }),
+                ..Default::default()
+            })
+            .build()
+            .await
+            .unwrap();
+        let process = |transaction_type, id, amount| {
+            let svc = &svc;
+            async move {
+                svc.process_transaction(&Transaction {
+                    id,
+                    transaction_type,
+                    client_id: 1,
+                    amount,
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                })
+                .await
+                .unwrap()
+            }
+        };
+        let balances = || async {
+            let client = svc.get_client(1).await.unwrap().unwrap();
+            (client.available, client.held)
+        };
+        let released = || async {
+            let released = svc.release_settlements().await.unwrap();
+            released.iter().map(|t| t.id).collect::<Vec<_>>()
+        };
+
+        // A dispute while clearing keeps the deposit held, until it is resolved and clears
+        process(TransactionType::Deposit, 1, Some(dec!(100))).await;
+        process(TransactionType::Deposit, 2, Some(dec!(100))).await;
+        process(TransactionType::Dispute, 1, None).await;
+        assert_eq!(balances().await, (dec!(0), dec!(200)));
+        assert_eq!(svc.check_ledger().await.unwrap().violations, []);
+        clock.advance(Duration::from_secs(60 * 60));
+        assert_eq!(released().await, [2]);
+        assert_eq!(balances().await, (dec!(100), dec!(100)));
+        process(TransactionType::Resolve, 1, None).await;
+        assert_eq!(balances().await, (dec!(100), dec!(100)));
+        assert_eq!(released().await, [1]);
+        assert_eq!(balances().await, (dec!(200), dec!(0)));
+
+        // A charged back deposit is never released
+        process(TransactionType::Deposit, 3, Some(dec!(100))).await;
+        process(TransactionType::Dispute, 3, None).await;
+        assert_eq!(
+            process(TransactionType::Chargeback, 3, None).await,
+            TransactionOutcome::Chargeback {
+                disputed: svc.get_transaction(3).await.unwrap().unwrap(),
+                locked: true,
+            }
+        );
+        assert_eq!(balances().await, (dec!(200), dec!(0)));
+        clock.advance(Duration::from_secs(60 * 60));
+        assert_eq!(released().await, Vec::<TransactionId>::new());
+        assert_eq!(balances().await, (dec!(200), dec!(0)));
+        assert_eq!(svc.check_ledger().await.unwrap().violations, []);
+    }
+
+    #[tokio::test]
+    async fn test_void_deposit() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let svc = TransactionService::builder()
+            .clock(clock.clone())
+            .policy(TransactionPolicy {
+                settlement_delay: Some(SettlementDelay {
+                    threshold: dec!(50),
+                    clearing_period: Duration::from_secs(60 * 60),
+                }),
+                ..Default::default()
+            })
+            .audit_log(true)
+            .build()
+            .await
+            .unwrap();
+        let csv = "type, client, tx, amount
+            deposit, 1, 1, 100
+            deposit, 1, 2, 20
+            deposit, 1, 3, 100
+            dispute, 1, 3,";
+        let mut reader = crate::TransactionReader::new(csv.as_bytes());
+        svc.process_stream(futures::stream::iter(reader.transactions()))
+            .await
+            .unwrap();
+
+        // Only clearing deposits that are not under dispute
+        assert!(svc.void_deposit(1, "Returned", " ").await.is_err());
+        assert!(svc.void_deposit(2, "Returned", "alice").await.is_err());
+        assert!(svc.void_deposit(3, "Returned", "alice").await.is_err());
+        let void = svc.void_deposit(1, "Returned", "alice").await.unwrap();
+        assert_eq!(
+            (void.client_id, void.amount, void.operator.as_str()),
+            (1, dec!(100), "alice")
+        );
+        assert!(svc.void_deposit(1, "Returned", "alice").await.is_err());
+
+        let client = svc.get_client(1).await.unwrap().unwrap();
+        assert_eq!((client.available, client.held), (dec!(20), dec!(100)));
+        clock.advance(Duration::from_secs(60 * 60));
+        assert_eq!(svc.release_settlements().await.unwrap(), []);
+        let dispute = svc
+            .process_transaction(&Transaction {
+                id: 1,
+                transaction_type: TransactionType::Dispute,
+                client_id: 1,
+                amount: None,
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            dispute,
+            TransactionOutcome::Ignored {
+                reason: IgnoreReason::UnknownTarget
+            }
+        );
+        assert_eq!(svc.check_ledger().await.unwrap().violations, []);
+        let record = &svc.audit_records(None, 1).await.unwrap()[0];
+        assert_eq!(record.event["event"], "deposit_voided");
+        assert_eq!(record.event["void"]["reason"], "Returned");
+
+        // Voids are carried over, and their reasons erased with the client
+        let state = svc.export_state().await.unwrap();
+        assert_eq!(state.voids, [void]);
+        svc.forget_client(1, ErasurePolicy::Anonymize, "dpo")
+            .await
+            .unwrap();
+        let record = &svc.audit_records(None, 2).await.unwrap()[1];
+        assert_eq!(record.event["void"]["reason"], "[erased]");
+        assert!(svc.verify_audit_log().await.unwrap().is_valid());
+    }
+
+    #[tokio::test]
+    async fn test_chargeback_escalation() {
+        let svc = TransactionService::builder()
+            .policy(TransactionPolicy {
+                chargeback_escalation: ChargebackEscalation {
+                    flag: Some(1),
+                    block_withdrawals: Some(2),
+                    lock_permanently: Some(3),
+                },
+                ..Default::default()
+            })
+            .audit_log(true)
+            .build()
+            .await
+            .unwrap();
+        let process = |transaction_type, id, amount| {
+            let svc = &svc;
+            async move {
+                svc.process_transaction(&Transaction {
+                    id,
+                    transaction_type,
+                    client_id: 1,
+                    amount,
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                })
+                .await
+                .unwrap()
+            }
+        };
+        let charge_back = |id| {
+            let (svc, process) = (&svc, &process);
+            async move {
+                process(TransactionType::Dispute, id, None).await;
+                process(TransactionType::Chargeback, id, None).await;
+                svc.get_client_risk(1).await.unwrap().map(|risk| risk.level)
+            }
+        };
+        for id in 1..=4 {
+            process(TransactionType::Deposit, id, Some(dec!(100))).await;
+        }
+
+        assert_eq!(charge_back(1).await, Some(RiskLevel::Flagged));
+        svc.unlock_client(1, "ops").await.unwrap();
+        assert_eq!(
+            process(TransactionType::Withdrawal, 5, Some(dec!(10))).await,
+            TransactionOutcome::Withdrawal
+        );
+
+        assert_eq!(charge_back(2).await, Some(RiskLevel::WithdrawalsBlocked));
+        svc.unlock_client(1, "ops").await.unwrap();
+        assert_eq!(
+            process(TransactionType::Withdrawal, 6, Some(dec!(10))).await,
+            TransactionOutcome::Ignored {
+                reason: IgnoreReason::WithdrawalsBlocked
+            }
+        );
+        assert!(svc.get_transaction(6).await.unwrap().is_none());
+
+        assert_eq!(charge_back(3).await, Some(RiskLevel::PermanentlyLocked));
+        assert!(matches!(
+            svc.unlock_client(1, "ops").await,
+            Err(TransactionError::ClientLocked { client_id: 1, .. })
+        ));
+        let risk = svc.get_client_risk(1).await.unwrap().unwrap();
+        assert_eq!(risk.chargebacks, 3);
+        assert!(svc.verify_audit_log().await.unwrap().is_valid());
+
+        let state = svc.export_state().await.unwrap();
+        assert_eq!(state.risks, [risk]);
+    }
+
+    #[tokio::test]
+    async fn test_dispute_workflow() {
+        let svc = TransactionService::builder()
+            .audit_log(true)
+            .build()
+            .await
+            .unwrap();
+        for (transaction_type, id, amount) in [
+            (TransactionType::Deposit, 1, Some(dec!(10))),
+            (TransactionType::Deposit, 2, Some(dec!(10))),
+            (TransactionType::Dispute, 1, None),
+            (TransactionType::Dispute, 2, None),
+        ] {
+            svc.process_transaction(&Transaction {
+                id,
+                transaction_type,
+                client_id: 1,
+                amount,
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+            .await
+            .unwrap();
+        }
+
+        let dispute = svc.assign_dispute(1, Some("alice")).await.unwrap();
+        assert_eq!(
+            (dispute.status, dispute.assignee.as_deref()),
+            (DisputeStatus::Open, Some("alice"))
+        );
+        let dispute = (svc.update_dispute_status(1, DisputeStatus::AwaitingEvidence))
+            .await
+            .unwrap();
+        assert_eq!(dispute.assignee.as_deref(), Some("alice"));
+        assert!(svc.assign_dispute(1, Some(" ")).await.is_err());
+        let state = svc.export_state().await.unwrap();
+        assert_eq!(state.disputes[0].status, DisputeStatus::AwaitingEvidence);
+        assert_eq!(state.disputes[0].assignee.as_deref(), Some("alice"));
+        let disputes: Vec<Dispute> = svc.get_open_disputes(None).try_collect().await.unwrap();
+        assert_eq!(disputes[0], dispute);
+
+        let resolved = (svc.update_dispute_status(1, DisputeStatus::Resolved))
+            .await
+            .unwrap();
+        assert_eq!(resolved.status, DisputeStatus::Resolved);
+        let client = svc.get_client(1).await.unwrap().unwrap();
+        assert_eq!((client.available, client.held), (dec!(10), dec!(10)));
+        assert!(svc
+            .update_dispute_status(1, DisputeStatus::UnderReview)
+            .await
+            .is_err());
+
+        (svc.update_dispute_status(2, DisputeStatus::ChargedBack))
+            .await
+            .unwrap();
+        let client = svc.get_client(1).await.unwrap().unwrap();
+        assert!(client.locked);
+        assert_eq!(client.total, dec!(10));
+        assert!(svc.verify_audit_log().await.unwrap().is_valid());
+    }
+
+    #[tokio::test]
+    async fn test_dispute_workflow_not_applied() {
+        let svc = TransactionService::builder()
+            .validator(RuleSet::new([r#"reject "frozen" when type == "chargeback""#]).unwrap())
+            .build()
+            .await
+            .unwrap();
+        for (transaction_type, id, amount) in [
+            (TransactionType::Deposit, 1, Some(dec!(10))),
+            (TransactionType::Dispute, 1, None),
+        ] {
+            svc.process_transaction(&Transaction {
+                id,
+                transaction_type,
+                client_id: 1,
+                amount,
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+            .await
+            .unwrap();
+        }
+
+        // A rejected chargeback leaves the dispute open
+        let e = (svc.update_dispute_status(1, DisputeStatus::ChargedBack))
+            .await
+            .unwrap_err();
+        assert!(e.to_string().contains("frozen"), "{e}");
+        let disputes: Vec<Dispute> = svc.get_open_disputes(None).try_collect().await.unwrap();
+        assert_eq!(disputes[0].status, DisputeStatus::Open);
+        let client = svc.get_client(1).await.unwrap().unwrap();
+        assert_eq!((client.held, client.locked), (dec!(10), false));
+
+        // Only one of two concurrent transitions closes the dispute
+        let (first, second) = tokio::join!(
+            svc.update_dispute_status(1, DisputeStatus::Resolved),
+            svc.update_dispute_status(1, DisputeStatus::Resolved),
+        );
+        assert!(first.is_ok() != second.is_ok());
+        let client = svc.get_client(1).await.unwrap().unwrap();
+        assert_eq!((client.available, client.held), (dec!(10), dec!(0)));
+    }
+
+    #[tokio::test]
+    async fn test_dispute_evidence() {
+        let svc = TransactionService::builder()
+            .audit_log(true)
+            .build()
+            .await
+            .unwrap();
+        for (transaction_type, amount) in [
+            (TransactionType::Deposit, Some(dec!(10))),
+            (TransactionType::Dispute, None),
+        ] {
+            svc.process_transaction(&Transaction {
+                id: 1,
+                transaction_type,
+                client_id: 1,
+                amount,
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+            .await
+            .unwrap();
+        }
+
+        let receipt = "https://docs.example.com/receipt.pdf";
+        let hash = "sha256:9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+        svc.add_dispute_evidence(1, receipt).await.unwrap();
+        let dispute = svc.add_dispute_evidence(1, hash).await.unwrap();
+        assert_eq!(svc.add_dispute_evidence(1, receipt).await.unwrap(), dispute);
+        let references: Vec<_> = dispute.evidence.iter().map(|e| &e.reference).collect();
+        assert_eq!(references, [receipt, hash]);
+        for invalid in ["", "a receipt", "https://", "sha256:xyz", "receipt pdf://x"] {
+            assert!(
+                svc.add_dispute_evidence(1, invalid).await.is_err(),
+                "{invalid}"
+            );
+        }
+        assert!(svc.add_dispute_evidence(2, receipt).await.is_err());
+        let disputes: Vec<Dispute> = svc.get_open_disputes(None).try_collect().await.unwrap();
+        assert_eq!(disputes, std::slice::from_ref(&dispute));
+
+        let state = svc.export_state().await.unwrap();
+        assert_eq!(state.disputes[0].evidence, dispute.evidence);
+        let imported = TransactionService::builder().build().await.unwrap();
+        imported.import_state(&state).await.unwrap();
+        let disputes: Vec<Dispute> = imported
+            .get_open_disputes(None)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(disputes, [dispute]);
+
+        // Evidence is removed with the dispute
+        (svc.update_dispute_status(1, DisputeStatus::Resolved))
+            .await
+            .unwrap();
+        svc.process_transaction(&Transaction {
+            id: 1,
+            transaction_type: TransactionType::Dispute,
+            client_id: 1,
+            amount: None,
+            reference: None,
+            reason_code: None,
+            notes: None,
+            sequence: None,
+        })
+        .await
+        .unwrap();
+        let dispute = svc
+            .get_open_disputes(None)
+            .try_next()
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(dispute.evidence.is_empty());
+        assert!(svc.verify_audit_log().await.unwrap().is_valid());
+    }
+
+    #[derive(Clone, Default)]
+    struct RecordingObserver(Arc<Mutex<Vec<String>>>);
+
+    impl EventObserver for RecordingObserver {
+        fn on_deposit(&self, deposit: &Transaction) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("deposit {}", deposit.id));
+        }
+        fn on_withdrawal(&self, withdrawal: &Transaction) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("withdrawal {}", withdrawal.id));
+        }
+        fn on_withdrawal_rejected(&self, withdrawal: &Transaction) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("withdrawal rejected {}", withdrawal.id));
+        }
+        fn on_dispute_opened(&self, disputed: &Transaction) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("dispute {}", disputed.id));
+        }
+        fn on_dispute_resolved(&self, disputed: &Transaction) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("resolve {}", disputed.id));
+        }
+        fn on_chargeback(&self, disputed: &Transaction) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("chargeback {}", disputed.id));
+        }
+        fn on_client_locked(&self, client_id: ClientId) {
+            self.0.lock().unwrap().push(format!("locked {}", client_id));
+        }
+        fn on_hold_escalated(&self, disputed: &Transaction) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("escalated {}", disputed.id));
+        }
+        fn on_alert(&self, alert: &crate::Alert) {
+            self.0.lock().unwrap().push(format!(
+                "alert {} {}",
+                alert.kind.to_str(),
+                alert.client_id
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_observer() {
+        let observer = RecordingObserver::default();
+        let svc = TransactionService::builder()
+            .observer(observer.clone())
+            .build()
+            .await
+            .unwrap();
+
+        let transactions = [
+            (TransactionType::Deposit, 1, Some(dec!(10))),
+            (TransactionType::Deposit, 2, Some(dec!(5))),
+            (TransactionType::Withdrawal, 3, Some(dec!(20))),
+            (TransactionType::Withdrawal, 4, Some(dec!(1))),
+            (TransactionType::Dispute, 1, None),
+            (TransactionType::Resolve, 1, None),
+            (TransactionType::Dispute, 2, None),
+            (TransactionType::Chargeback, 2, None),
+            // Unknown dispute target
+            (TransactionType::Dispute, 99, None),
+        ];
+        for (transaction_type, id, amount) in transactions {
+            svc.process_transaction(&Transaction {
+                id,
+                transaction_type,
+                client_id: 1,
+                amount,
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(
+            *observer.0.lock().unwrap(),
+            [
+                "deposit 1",
+                "deposit 2",
+                "withdrawal rejected 3",
+                "withdrawal 4",
+                "dispute 1",
+                "resolve 1",
+                "dispute 2",
+                "chargeback 2",
+                "locked 1",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_stream() {
+        let svc = TransactionService::builder()
+            .batch_size(2)
+            .build()
+            .await
+            .unwrap();
+
+        let transactions = [
+            (TransactionType::Deposit, 1, Some(dec!(10))),
+            (TransactionType::Withdrawal, 2, Some(dec!(20))),
+            (TransactionType::Dispute, 1, None),
+            (TransactionType::Resolve, 1, None),
+            (TransactionType::Dispute, 99, None),
+        ]
+        .map(|(transaction_type, id, amount)| {
+            Ok(Transaction {
+                id,
+                transaction_type,
+                client_id: 1,
+                amount,
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+        });
+
+        let outcome = svc
+            .process_stream(futures::stream::iter(transactions))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            outcome,
+            ProcessingOutcome {
+                processed: 5,
+                applied: 3,
+                rejected: 1,
+                ignored: 1,
+                insufficient_funds: 1,
+            }
+        );
+        let client = svc.get_client(1).await.unwrap().unwrap();
+        assert_eq!(client.available, dec!(10));
+    }
+
+    #[tokio::test]
+    async fn test_process_stream_error_rolls_back_batch() {
+        let svc = TransactionService::builder()
+            .batch_size(2)
+            .build()
+            .await
+            .unwrap();
+
+        let transactions = [
+            (TransactionType::Deposit, 1, Some(dec!(10))),
+            (TransactionType::Deposit, 2, Some(dec!(10))),
+            (TransactionType::Deposit, 3, Some(dec!(10))),
+            (TransactionType::Deposit, 4, None),
+        ]
+        .map(|(transaction_type, id, amount)| {
+            Ok(Transaction {
+                id,
+                transaction_type,
+                client_id: 1,
+                amount,
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+        });
+
+        svc.process_stream(futures::stream::iter(transactions))
+            .await
+            .unwrap_err();
+
+        // The first batch is committed, the second is rolled back
+        let client = svc.get_client(1).await.unwrap().unwrap();
+        assert_eq!(client.available, dec!(20));
+        assert!(svc.get_transaction(3).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_get_client_transactions() {
+        let svc = create_service().await;
+        let transactions = [
+            (TransactionType::Deposit, 1, 1, Some(dec!(10))),
+            (TransactionType::Deposit, 2, 2, Some(dec!(10))),
+            (TransactionType::Withdrawal, 3, 1, Some(dec!(1))),
+            (TransactionType::Deposit, 4, 1, Some(dec!(5))),
+            (TransactionType::Dispute, 4, 1, None),
+        ];
+        for (transaction_type, id, client_id, amount) in transactions {
+            svc.process_transaction(&Transaction {
+                id,
+                transaction_type,
+                client_id,
+                amount,
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+            .await
+            .unwrap();
+        }
+
+        let ids = |filter: TransactionFilter, pagination: Pagination<TransactionId>| {
+            let svc = &svc;
+            async move {
+                svc.get_client_transactions(1, &filter, pagination)
+                    .map_ok(|t| t.id)
+                    .try_collect::<Vec<_>>()
+                    .await
+                    .unwrap()
+            }
+        };
+
+        assert_eq!(
+            ids(TransactionFilter::default(), Pagination::default()).await,
+            [1, 3, 4]
+        );
+        assert_eq!(
+            ids(TransactionFilter::default(), Pagination::first(2)).await,
+            [1, 3]
+        );
+        assert_eq!(
+            ids(TransactionFilter::default(), Pagination::after(3, 2)).await,
+            [4]
+        );
+        assert_eq!(
+            ids(
+                TransactionFilter {
+                    transaction_type: Some(TransactionType::Deposit),
+                    ..Default::default()
+                },
+                Pagination::default()
+            )
+            .await,
+            [1, 4]
+        );
+        assert_eq!(
+            ids(
+                TransactionFilter {
+                    disputed: Some(true),
+                    ..Default::default()
+                },
+                Pagination::default()
+            )
+            .await,
+            [4]
+        );
+        assert_eq!(
+            ids(
+                TransactionFilter {
+                    disputed: Some(false),
+                    ..Default::default()
+                },
+                Pagination::default()
+            )
+            .await,
+            [1, 3]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_search_transactions() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let svc = TransactionService::builder()
+            .clock(clock.clone())
+            .build()
+            .await
+            .unwrap();
+        let transactions = [
+            (TransactionType::Deposit, 1, 42, Some(dec!(5000))),
+            (TransactionType::Withdrawal, 2, 42, Some(dec!(1500))),
+            (TransactionType::Withdrawal, 3, 42, Some(dec!(20))),
+            (TransactionType::Withdrawal, 4, 7, Some(dec!(2000))),
+            (TransactionType::Withdrawal, 5, 42, Some(dec!(1000))),
+            (TransactionType::Dispute, 5, 42, None),
+        ];
+        for (transaction_type, id, client_id, amount) in transactions {
+            svc.process_transaction(&Transaction {
+                id,
+                transaction_type,
+                client_id,
+                amount,
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+            .await
+            .unwrap();
+            clock.advance(Duration::from_secs(1));
+        }
+
+        let ids = |filter: TransactionFilter, pagination: Pagination<TransactionId>| {
+            let svc = &svc;
+            async move {
+                svc.search_transactions(&filter, pagination)
+                    .map_ok(|t| t.id)
+                    .try_collect::<Vec<_>>()
+                    .await
+                    .unwrap()
+            }
+        };
+        let large_withdrawals = TransactionFilter {
+            client_id: Some(42),
+            transaction_type: Some(TransactionType::Withdrawal),
+            min_amount: Some(dec!(1000)),
+            ..Default::default()
+        };
+        assert_eq!(
+            ids(large_withdrawals.clone(), Pagination::default()).await,
+            [2, 5]
+        );
+        assert_eq!(
+            ids(large_withdrawals.clone(), Pagination::after(2, 10)).await,
+            [5]
+        );
+        assert_eq!(
+            ids(
+                TransactionFilter {
+                    disputed: Some(false),
+                    ..large_withdrawals
+                },
+                Pagination::default()
+            )
+            .await,
+            [2]
+        );
+        assert_eq!(
+            ids(
+                TransactionFilter {
+                    max_amount: Some(dec!(1500)),
+                    from: Some(1_700_000_001_000),
+                    until: Some(1_700_000_004_000),
+                    ..Default::default()
+                },
+                Pagination::default()
+            )
+            .await,
+            [2, 3]
+        );
+        assert_eq!(
+            ids(TransactionFilter::default(), Pagination::first(2)).await,
+            [1, 2]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_aggregates() {
+        // 2023-11-14T22:13:20Z
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let svc = TransactionService::builder()
+            .clock(clock.clone())
+            .build()
+            .await
+            .unwrap();
+        let transactions = [
+            (TransactionType::Deposit, 1, 1, dec!(10)),
+            (TransactionType::Deposit, 2, 2, dec!(5)),
+            (TransactionType::Withdrawal, 3, 1, dec!(4)),
+            (TransactionType::Deposit, 4, 1, dec!(2.5)),
+        ];
+        for (transaction_type, id, client_id, amount) in transactions {
+            svc.process_transaction(&Transaction {
+                id,
+                transaction_type,
+                client_id,
+                amount: Some(amount),
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+            .await
+            .unwrap();
+            clock.advance(Duration::from_secs(3600));
+        }
+        sqlx::query("UPDATE [Transactions] SET created_at = NULL WHERE id = ?")
+            .bind(db_id(4))
+            .execute(&svc.pool)
+            .await
+            .unwrap();
+
+        let aggregates = |group_by, filter: TransactionFilter| {
+            let svc = &svc;
+            async move {
+                svc.get_aggregates(group_by, &filter)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|a| (a.group, a.count, a.amount))
+                    .collect::<Vec<_>>()
+            }
+        };
+        let group = |name: &str| Some(name.to_string());
+        assert_eq!(
+            aggregates(GroupBy::TransactionType, TransactionFilter::default()).await,
+            [
+                (group("deposit"), 3, dec!(17.5)),
+                (group("withdrawal"), 1, dec!(4))
+            ]
+        );
+        assert_eq!(
+            aggregates(
+                GroupBy::Client,
+                TransactionFilter {
+                    transaction_type: Some(TransactionType::Deposit),
+                    ..Default::default()
+                }
+            )
+            .await,
+            [(group("1"), 2, dec!(12.5)), (group("2"), 1, dec!(5))]
+        );
+        assert_eq!(
+            aggregates(GroupBy::Day, TransactionFilter::default()).await,
+            [
+                (None, 1, dec!(2.5)),
+                (group("2023-11-14"), 2, dec!(15)),
+                (group("2023-11-15"), 1, dec!(4))
+            ]
+        );
+        assert_eq!(
+            aggregates(
+                GroupBy::Client,
+                TransactionFilter {
+                    client_id: Some(9),
+                    ..Default::default()
+                }
+            )
+            .await,
+            []
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_tags() {
+        let svc = create_service().await;
+        for client_id in 1..=3 {
+            svc.process_transaction(&Transaction {
+                id: TransactionId::from(client_id),
+                transaction_type: TransactionType::Deposit,
+                client_id,
+                amount: Some(dec!(10)),
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+            .await
+            .unwrap();
+        }
+        let tag = |client, tag: &str| ClientTag {
+            client,
+            tag: tag.to_string(),
+        };
+        let tags = [tag(1, "vip"), tag(3, "vip"), tag(3, "staff"), tag(9, "vip")];
+        assert_eq!(svc.tag_clients(&tags).await.unwrap(), 4);
+        assert_eq!(svc.tag_clients(&tags[..1]).await.unwrap(), 0);
+        assert!(svc
+            .tag_clients(&[tag(2, "retail"), tag(2, "")])
+            .await
+            .is_err());
+        assert_eq!(svc.get_client_tags(2).await.unwrap(), Vec::<String>::new());
+        assert_eq!(svc.get_client_tags(3).await.unwrap(), ["staff", "vip"]);
+        assert_eq!(svc.get_tagged_clients("vip").await.unwrap(), [1, 3, 9]);
+
+        let vip_clients: Vec<_> = svc
+            .get_clients(
+                &ClientFilter {
+                    tag: Some("vip".to_string()),
+                    ..Default::default()
+                },
+                Pagination::default(),
+            )
+            .await
+            .map_ok(|c| c.id)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(vip_clients, [1, 3]);
+        let vip_transactions: Vec<_> = svc
+            .search_transactions(
+                &TransactionFilter {
+                    tag: Some("vip".to_string()),
+                    ..Default::default()
+                },
+                Pagination::default(),
+            )
+            .map_ok(|t| t.client_id)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(vip_transactions, [1, 3]);
+
+        assert!(svc.untag_client(3, "vip").await.unwrap());
+        assert!(!svc.untag_client(3, "vip").await.unwrap());
+        assert_eq!(svc.get_tagged_clients("vip").await.unwrap(), [1, 9]);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_stored_transaction() {
+        let svc = create_service().await;
+        sqlx::query(&format!(
+            "INSERT INTO Clients (id, available, held, locked) VALUES (1, 10000, 0, false);
+            INSERT INTO [Transactions] (id, [type], client_id, amount) VALUES ({}, '', 1, 10000);",
+            db_id(1)
+        ))
+        .execute(&svc.pool)
+        .await
+        .unwrap();
+
+        assert!(matches!(
+            svc.get_transaction(1).await,
+            Err(TransactionError::InvalidTransaction {
+                transaction_id: 1,
+                ..
+            })
+        ));
+        assert!(matches!(
+            svc.process_transaction(&Transaction {
+                id: 1,
+                transaction_type: TransactionType::Dispute,
+                client_id: 1,
+                amount: None,
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+            .await,
+            Err(TransactionError::InvalidTransaction { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_open_disputes() {
+        let svc = create_service().await;
+        let transactions = [
+            (TransactionType::Deposit, 1, 1, Some(dec!(10))),
+            (TransactionType::Deposit, 2, 2, Some(dec!(10))),
+            (TransactionType::Deposit, 3, 1, Some(dec!(5))),
+            (TransactionType::Dispute, 3, 1, None),
+            (TransactionType::Dispute, 2, 2, None),
+            (TransactionType::Dispute, 1, 1, None),
+            (TransactionType::Resolve, 1, 1, None),
+        ];
+        for (transaction_type, id, client_id, amount) in transactions {
+            svc.process_transaction(&Transaction {
+                id,
+                transaction_type,
+                client_id,
+                amount,
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+            .await
+            .unwrap();
+        }
+
+        let all: Vec<_> = svc.get_open_disputes(None).try_collect().await.unwrap();
+        assert_eq!(
+            all.iter().map(|d| d.transaction.id).collect::<Vec<_>>(),
+            [2, 3]
+        );
+
+        let client_1: Vec<_> = svc.get_open_disputes(Some(1)).try_collect().await.unwrap();
+        assert_eq!(
+            client_1
+                .iter()
+                .map(|d| (&d.transaction, d.escalated_at))
+                .collect::<Vec<_>>(),
+            [(
+                &Transaction {
+                    id: 3,
+                    transaction_type: TransactionType::Deposit,
+                    client_id: 1,
+                    amount: Some(dec!(5)),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                None
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_clients_filtered() {
+        let svc = create_service().await;
+        let transactions = [
+            (TransactionType::Deposit, 1, 1, Some(dec!(10))),
+            (TransactionType::Deposit, 2, 2, Some(dec!(20))),
+            (TransactionType::Deposit, 3, 3, Some(dec!(30))),
+            (TransactionType::Deposit, 4, 4, Some(dec!(40))),
+            (TransactionType::Dispute, 4, 4, None),
+            (TransactionType::Chargeback, 4, 4, None),
+        ];
+        for (transaction_type, id, client_id, amount) in transactions {
+            svc.process_transaction(&Transaction {
+                id,
+                transaction_type,
+                client_id,
+                amount,
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+            .await
+            .unwrap();
+        }
+
+        let ids = |filter: ClientFilter, pagination: Pagination<ClientId>| {
+            let svc = &svc;
+            async move {
+                svc.get_clients(&filter, pagination)
+                    .await
+                    .map_ok(|c| c.id)
+                    .try_collect::<Vec<_>>()
+                    .await
+                    .unwrap()
+            }
+        };
+
+        assert_eq!(
+            ids(ClientFilter::default(), Pagination::default()).await,
+            [1, 2, 3, 4]
+        );
+        assert_eq!(
+            ids(ClientFilter::default(), Pagination::after(1, 2)).await,
+            [2, 3]
+        );
+        assert_eq!(
+            ids(
+                ClientFilter {
+                    locked_only: true,
+                    ..Default::default()
+                },
+                Pagination::default()
+            )
+            .await,
+            [4]
+        );
+        assert_eq!(
+            ids(
+                ClientFilter {
+                    min_total: Some(dec!(15)),
+                    max_total: Some(dec!(30)),
+                    ..Default::default()
+                },
+                Pagination::default()
+            )
+            .await,
+            [2, 3]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_adjust_balance() {
+        let svc = create_service().await;
+        svc.process_transaction(&Transaction {
+            id: 1,
+            transaction_type: TransactionType::Deposit,
+            client_id: 1,
+            amount: Some(dec!(10)),
+            reference: None,
+            reason_code: None,
+            notes: None,
+            sequence: None,
+        })
+        .await
+        .unwrap();
+
+        let adjustment = svc
+            .adjust_balance(1, dec!(-0.01), "Rounding correction", "ops")
+            .await
+            .unwrap();
+        assert_eq!(adjustment.amount, dec!(-0.01));
+        assert_eq!(
+            svc.get_client(1).await.unwrap().unwrap().available,
+            dec!(9.99)
+        );
+
+        assert!(matches!(
+            svc.adjust_balance(1, dec!(1), " ", "ops").await,
+            Err(TransactionError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            svc.adjust_balance(1, dec!(-100), "Too much", "ops").await,
+            Err(TransactionError::InsufficientFunds { client_id: 1, .. })
+        ));
+        assert!(matches!(
+            svc.adjust_balance(2, dec!(1), "Unknown client", "ops")
+                .await,
+            Err(TransactionError::ClientNotFound { client_id: 2, .. })
+        ));
+        assert_eq!(
+            svc.get_client(1).await.unwrap().unwrap().available,
+            dec!(9.99)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_transactions_once() {
+        let svc = create_service().await;
+        let transactions = [
+            Transaction {
+                id: 1,
+                transaction_type: TransactionType::Deposit,
+                client_id: 1,
+                amount: Some(dec!(5)),
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            },
+            Transaction {
+                id: 2,
+                transaction_type: TransactionType::Withdrawal,
+                client_id: 1,
+                amount: Some(dec!(10)),
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            },
+        ];
+
+        let outcomes = svc
+            .process_transactions_once("key-1", &transactions)
+            .await
+            .unwrap();
+        assert_eq!(
+            outcomes,
+            [
+                TransactionOutcome::Deposit,
+                TransactionOutcome::WithdrawalRejected
+            ]
+        );
+
+        // A replay returns the stored outcomes without applying the deposit again.
+        let replayed = svc
+            .process_transactions_once("key-1", &transactions)
+            .await
+            .unwrap();
+        assert_eq!(replayed, outcomes);
+        assert_eq!(svc.get_client(1).await.unwrap().unwrap().available, dec!(5));
+
+        assert!(matches!(
+            svc.process_transactions_once("key-1", &transactions[..1])
+                .await,
+            Err(TransactionError::IdempotencyKeyReused { .. })
+        ));
+        assert!(matches!(
+            svc.process_transactions_once("", &transactions).await,
+            Err(TransactionError::InvalidArgument(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_unlock_client() {
+        let svc = create_service().await;
+        for (id, transaction_type, amount) in [
+            (1, TransactionType::Deposit, Some(dec!(3))),
+            (1, TransactionType::Dispute, None),
+            (1, TransactionType::Chargeback, None),
+        ] {
+            svc.process_transaction(&Transaction {
+                id,
+                transaction_type,
+                client_id: 1,
+                amount,
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+            .await
+            .unwrap();
+        }
+        assert!(svc.get_client(1).await.unwrap().unwrap().locked);
+
+        assert!(matches!(
+            svc.unlock_client(1, " ").await,
+            Err(TransactionError::InvalidArgument(_))
+        ));
+        let client = svc.unlock_client(1, "ops").await.unwrap();
+        assert!(!client.locked);
+
+        let outcome = svc
+            .process_transaction(&Transaction {
+                id: 2,
+                transaction_type: TransactionType::Deposit,
+                client_id: 1,
+                amount: Some(dec!(1)),
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(outcome, TransactionOutcome::Deposit);
+
+        assert!(matches!(
+            svc.unlock_client(2, "ops").await,
+            Err(TransactionError::ClientNotFound { client_id: 2, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_approvals() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let svc = TransactionService::builder()
+            .pool(pool.clone())
+            .audit_log(true)
+            .approvals(ApprovalRules {
+                unlocks: true,
+                adjustments_over: Some(dec!(100)),
+            })
+            .build()
+            .await
+            .unwrap();
+        for (id, transaction_type, client_id, amount) in [
+            (1, TransactionType::Deposit, 1, Some(dec!(3))),
+            (1, TransactionType::Dispute, 1, None),
+            (1, TransactionType::Chargeback, 1, None),
+            (2, TransactionType::Deposit, 2, Some(dec!(500))),
+        ] {
+            svc.process_transaction(&Transaction {
+                id,
+                transaction_type,
+                client_id,
+                amount,
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+            .await
+            .unwrap();
+        }
+
+        let unlock = match svc.unlock_client(1, "alice").await {
+            Err(TransactionError::ApprovalRequired(approval, _)) => approval,
+            result => panic!("unexpected {:?}", result),
+        };
+        assert_eq!(unlock.action, ApprovalAction::Unlock);
+        assert!(svc.get_client(1).await.unwrap().unwrap().locked);
+        // Small adjustments do not need approval
+        svc.adjust_balance(2, dec!(-100), "Fee", "alice")
+            .await
+            .unwrap();
+        let refund = match svc
+            .adjust_balance(2, dec!(-250), "Refund for Jane Doe", "alice")
+            .await
+        {
+            Err(TransactionError::ApprovalRequired(approval, _)) => approval,
+            result => panic!("unexpected {:?}", result),
+        };
+        assert_eq!(
+            svc.get_client(2).await.unwrap().unwrap().available,
+            dec!(400)
+        );
+        assert_eq!(
+            svc.get_pending_approvals().await.unwrap(),
+            [(*unlock).clone(), (*refund).clone()]
+        );
+
+        assert!(matches!(
+            svc.approve_request(unlock.id, "alice").await,
+            Err(TransactionError::InvalidArgument(_))
+        ));
+        let approved = svc.approve_request(unlock.id, "bob").await.unwrap();
+        assert_eq!(approved.status, ApprovalStatus::Approved);
+        assert_eq!(approved.decided_by.as_deref(), Some("bob"));
+        assert!(!svc.get_client(1).await.unwrap().unwrap().locked);
+        assert!(matches!(
+            svc.approve_request(unlock.id, "bob").await,
+            Err(TransactionError::InvalidArgument(_))
+        ));
+
+        let rejected = svc.reject_request(refund.id, "bob").await.unwrap();
+        assert_eq!(rejected.status, ApprovalStatus::Rejected);
+        assert_eq!(
+            svc.get_client(2).await.unwrap().unwrap().available,
+            dec!(400)
+        );
+        assert!(svc.get_pending_approvals().await.unwrap().is_empty());
+
+        // The approved unlock is recorded as made by the operator who requested it
+        let verification = svc.verify_audit_log().await.unwrap();
+        assert!(verification.is_valid(), "{verification}");
+        let events: Vec<(String, Option<String>)> = sqlx::query_as(
+            "SELECT json_extract(event, '$.event'), json_extract(event, '$.operator')
+            FROM AuditLog WHERE json_extract(event, '$.event') NOT LIKE 'transaction%' ORDER BY id",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        let events: Vec<(&str, Option<&str>)> = events
+            .iter()
+            .map(|(event, operator)| (event.as_str(), operator.as_deref()))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                ("approval_requested", None),
+                ("adjustment", None),
+                ("approval_requested", None),
+                ("client_unlocked", Some("alice")),
+                ("approval_decided", None),
+                ("approval_decided", None),
+            ]
+        );
+
+        svc.forget_client(2, ErasurePolicy::Anonymize, "dpo")
+            .await
+            .unwrap();
+        let (reasons,): (String,) =
+            sqlx::query_as("SELECT group_concat(reason) FROM Approvals WHERE reason IS NOT NULL")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(reasons, "[erased]");
+    }
+
+    #[tokio::test]
+    async fn test_outbox() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let svc = TransactionService::builder()
+            .outbox(true)
+            .clock(clock.clone())
+            .build()
+            .await
+            .unwrap();
+        for (id, transaction_type, amount) in [
+            (1, TransactionType::Deposit, Some(dec!(3))),
+            (2, TransactionType::Withdrawal, Some(dec!(5))),
+            (1, TransactionType::Dispute, None),
+        ] {
+            svc.process_transaction(&Transaction {
+                id,
+                transaction_type,
+                client_id: 1,
+                amount,
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+            .await
+            .unwrap();
+            clock.advance(Duration::from_millis(250));
+        }
+        svc.adjust_balance(1, dec!(1), "Fee refund", "ops")
+            .await
+            .unwrap();
+
+        // The rejected withdrawal did not change anything
+        let events = svc.outbox_events(10).await.unwrap();
+        let payloads: Vec<serde_json::Value> = events
+            .iter()
+            .map(|e| serde_json::from_str(&e.payload).unwrap())
+            .collect();
+        assert_eq!(
+            payloads
+                .iter()
+                .map(|p| p["event"].as_str().unwrap())
+                .collect::<Vec<_>>(),
+            ["transaction", "transaction", "adjustment"]
+        );
+        assert_eq!(
+            events.iter().map(|e| e.created_at).collect::<Vec<_>>(),
+            [1_700_000_000_000, 1_700_000_000_500, 1_700_000_000_750]
+        );
+        assert_eq!(payloads[0]["outcome"], "deposit");
+        assert_eq!(payloads[1]["client"]["available"], "0.0000");
+        assert_eq!(payloads[1]["client"]["held"], "3.0000");
+        assert_eq!(payloads[2]["client"]["available"], "1.0000");
+        assert!(events.iter().all(|e| e.client_id == 1));
+
+        svc.remove_outbox_events(events[1].id).await.unwrap();
+        assert_eq!(svc.outbox_events(10).await.unwrap(), events[2..]);
+    }
+
+    #[tokio::test]
+    async fn test_audit_log() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let svc = TransactionService::builder()
+            .pool(pool.clone())
+            .audit_log(true)
+            .build()
+            .await
+            .unwrap();
+        for (id, transaction_type, client_id, amount) in [
+            (1, TransactionType::Deposit, 1, Some(dec!(3))),
+            (2, TransactionType::Deposit, 2, Some(dec!(4.12345))),
+            (3, TransactionType::Withdrawal, 1, Some(dec!(5))),
+            (1, TransactionType::Dispute, 1, None),
+            (1, TransactionType::Chargeback, 1, None),
+        ] {
+            svc.process_transaction(&Transaction {
+                id,
+                transaction_type,
+                client_id,
+                amount,
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+            .await
+            .unwrap();
+        }
+        svc.unlock_client(1, "ops").await.unwrap();
+        svc.adjust_balance(2, dec!(1), "Fee refund", "ops")
+            .await
+            .unwrap();
+
+        // The rejected withdrawal did not change anything
+        let verification = svc.verify_audit_log().await.unwrap();
+        assert!(verification.is_valid(), "{verification}");
+        assert_eq!(verification.records, 6);
+        let operators: Vec<(String, String)> = sqlx::query_as(
+            "SELECT json_extract(event, '$.event'), json_extract(event, '$.operator')
+            FROM AuditLog WHERE json_extract(event, '$.operator') IS NOT NULL ORDER BY id",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(operators, [("client_unlocked".into(), "ops".into())]);
+
+        sqlx::query("UPDATE Transactions SET amount = 50000 WHERE id = ?")
+            .bind(db_id(1))
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            svc.verify_audit_log().await.unwrap().problems,
+            ["record 1: transaction 1 does not match the stored transaction"]
+        );
+        sqlx::query("UPDATE Transactions SET amount = 30000 WHERE id = ?")
+            .bind(db_id(1))
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        sqlx::query("UPDATE Clients SET available = 0 WHERE id = 2")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            svc.verify_audit_log().await.unwrap().problems,
+            ["client 2: does not match the last record of it"]
+        );
+        sqlx::query("UPDATE Clients SET available = 51234 WHERE id = 2")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(svc.verify_audit_log().await.unwrap().is_valid());
+
+        // Rewriting a record along with its hash still breaks the next one
+        let (event, created_at): (String, i64) =
+            sqlx::query_as("SELECT event, created_at FROM AuditLog WHERE id = 2")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        let event = event.replace("4.1234", "40.1234");
+        sqlx::query("UPDATE AuditLog SET event = ?, hash = ? WHERE id = 2")
+            .bind(&event)
+            .bind(chain_hash(
+                &audit_hash(&pool, 1).await,
+                created_at,
+                &content_hash(&event),
+            ))
+            .execute(&pool)
+            .await
+            .unwrap();
+        let problems = svc.verify_audit_log().await.unwrap().problems;
+        assert!(
+            problems
+                .contains(&"record 3: does not match its hash or the previous record".to_string()),
+            "{problems:?}"
+        );
+
+        sqlx::query("DELETE FROM AuditLog WHERE id = 5")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let verification = svc.verify_audit_log().await.unwrap();
+        assert_eq!(verification.records, 5);
+        assert!(verification
+            .problems
+            .contains(&"record 6: does not match its hash or the previous record".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_provenance() {
+        use crate::{TransactionReader, TransactionSource};
+
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let svc = TransactionService::builder()
+            .clock(clock)
+            .build()
+            .await
+            .unwrap();
+        let csv = "type,client,tx,amount\ndeposit,1,1,2\n\nwithdrawal,1,2,5\ndispute,1,1\n";
+        let batch = svc
+            .begin_batch("partner.csv", Some("abc123"))
+            .await
+            .unwrap();
+        assert_eq!(batch.created_at, 1_700_000_000_000);
+        svc.process_batch(
+            &batch,
+            TransactionReader::new(csv.as_bytes()).stream_with_lines(),
+        )
+        .await
+        .unwrap();
+        svc.process_transaction(&Transaction {
+            id: 3,
+            transaction_type: TransactionType::Deposit,
+            client_id: 1,
+            amount: Some(dec!(1)),
+            reference: None,
+            reason_code: None,
+            notes: None,
+            sequence: None,
+        })
+        .await
+        .unwrap();
+
+        let deposit = svc.get_provenance(1).await.unwrap().unwrap();
+        assert_eq!(deposit.batch, batch);
+        assert_eq!(deposit.line, 2);
+        // Rejected withdrawals are stored too
+        assert_eq!(svc.get_provenance(2).await.unwrap().unwrap().line, 4);
+        assert_eq!(svc.get_provenance(3).await.unwrap(), None);
+        assert_eq!(svc.get_provenance(4).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_changed_in_batch() {
+        use crate::{TransactionReader, TransactionSource};
+
+        let svc = create_service().await;
+        let process = |csv: &'static str| {
+            let svc = &svc;
+            async move {
+                let batch = svc.begin_batch("partner.csv", None).await.unwrap();
+                svc.process_batch(
+                    &batch,
+                    TransactionReader::new(csv.as_bytes()).stream_with_lines(),
+                )
+                .await
+                .unwrap();
+                let filter = ClientFilter {
+                    changed_in_batch: Some(batch.id),
+                    ..Default::default()
+                };
+                svc.get_clients(&filter, Pagination::default())
+                    .await
+                    .map_ok(|c| c.id)
+                    .try_collect::<Vec<_>>()
+                    .await
+                    .unwrap()
+            }
+        };
+
+        assert_eq!(
+            process("type,client,tx,amount\ndeposit,1,1,2\ndeposit,2,2,2\n").await,
+            [1, 2]
+        );
+        // Rejected and ignored transactions change nothing, disputes do
+        assert_eq!(
+            process("type,client,tx,amount\nwithdrawal,1,3,5\ndispute,2,2\ndispute,1,99\n").await,
+            [2]
+        );
+        // The client of a chargeback is the owner of the disputed deposit, not its submitter
+        assert_eq!(
+            process("type,client,tx,amount\nchargeback,1,2\n").await,
+            [2]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_with_offsets() {
+        use crate::SourceOffset;
+        use std::collections::BTreeMap;
+
+        let svc = create_service().await;
+        let message = |partition, offset, id, transaction_type, amount| {
+            Ok((
+                SourceOffset { partition, offset },
+                Transaction {
+                    id,
+                    transaction_type,
+                    client_id: 1,
+                    amount: Some(amount),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+            ))
+        };
+        let process = |messages: Vec<crate::Result<(SourceOffset, Transaction)>>| {
+            let mut offsets = Vec::new();
+            let svc = &svc;
+            async move {
+                let summary = svc
+                    .process_with_offsets(
+                        "kafka:ledger:payments",
+                        futures::stream::iter(messages),
+                        |offset, _, _| {
+                            offsets.push(offset);
+                            Ok(())
+                        },
+                    )
+                    .await;
+                summary.map(|summary| (summary.processed, offsets))
+            }
+        };
+
+        let (processed, _) = process(vec![
+            message(0, 10, 1, TransactionType::Deposit, dec!(5)),
+            message(1, 3, 2, TransactionType::Deposit, dec!(5)),
+            message(0, 11, 3, TransactionType::Withdrawal, dec!(2)),
+        ])
+        .await
+        .unwrap();
+        assert_eq!(processed, 3);
+        let stored = svc
+            .get_source_offsets("kafka:ledger:payments")
+            .await
+            .unwrap();
+        assert_eq!(stored, BTreeMap::from([(0, 11), (1, 3)]));
+        assert!(svc
+            .get_source_offsets("kafka:other")
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Redelivered messages are skipped
+        let (processed, offsets) = process(vec![
+            message(0, 11, 3, TransactionType::Withdrawal, dec!(2)),
+            message(0, 12, 4, TransactionType::Withdrawal, dec!(1)),
+        ])
+        .await
+        .unwrap();
+        assert_eq!(processed, 1);
+        assert_eq!(
+            offsets,
+            [SourceOffset {
+                partition: 0,
+                offset: 12
+            }]
+        );
+        assert_eq!(svc.get_client(1).await.unwrap().unwrap().available, dec!(7));
+
+        // A failed batch stores neither its transactions nor its offsets
+        assert!(process(vec![
+            message(0, 13, 5, TransactionType::Deposit, dec!(100)),
+            Err(TransactionError::InvalidArgument("bad message".into())),
+        ])
+        .await
+        .is_err());
+        assert_eq!(svc.get_client(1).await.unwrap().unwrap().available, dec!(7));
+        assert_eq!(
+            svc.get_source_offsets("kafka:ledger:payments")
+                .await
+                .unwrap(),
+            BTreeMap::from([(0, 12), (1, 3)])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_as_of() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let svc = TransactionService::builder()
+            .clock(clock.clone())
+            .audit_log(true)
+            .build()
+            .await
+            .unwrap();
+        for (id, transaction_type, client_id, amount) in [
+            (1, TransactionType::Deposit, 1, Some(dec!(3))),
+            (2, TransactionType::Deposit, 2, Some(dec!(4))),
+            (3, TransactionType::Withdrawal, 1, Some(dec!(1))),
+            (1, TransactionType::Dispute, 1, None),
+        ] {
+            svc.process_transaction(&Transaction {
+                id,
+                transaction_type,
+                client_id,
+                amount,
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+            .await
+            .unwrap();
+            clock.advance(Duration::from_secs(1));
+        }
+
+        let client = |available, held| Client {
+            id: 1,
+            available,
+            held,
+            total: available + held,
+            locked: false,
+        };
+        let start = 1_700_000_000_000;
+        assert_eq!(svc.get_client_as_of(1, start - 1).await.unwrap(), None);
+        assert_eq!(
+            svc.get_client_as_of(1, start).await.unwrap(),
+            Some(client(dec!(3), dec!(0)))
+        );
+        assert_eq!(
+            svc.get_client_as_of(1, start + 2_500).await.unwrap(),
+            Some(client(dec!(2), dec!(0)))
+        );
+        assert_eq!(
+            svc.get_client_as_of(1, start + 3_000).await.unwrap(),
+            svc.get_client(1).await.unwrap()
+        );
+
+        let clients = svc.get_clients_as_of(start + 1_000).await.unwrap();
+        assert_eq!(
+            clients.iter().map(|c| (c.id, c.total)).collect::<Vec<_>>(),
+            [(1, dec!(3)), (2, dec!(4))]
+        );
+        assert_eq!(svc.get_clients_as_of(start - 1).await.unwrap(), []);
+
+        let svc = TransactionService::builder().build().await.unwrap();
+        assert!(svc.get_client_as_of(1, start).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_balance_snapshots() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let svc = TransactionService::builder()
+            .clock(clock.clone())
+            .audit_log(true)
+            .build()
+            .await
+            .unwrap();
+        let transaction = |id, transaction_type, client_id, amount| Transaction {
+            id,
+            transaction_type,
+            client_id,
+            amount: Some(amount),
+            reference: None,
+            reason_code: None,
+            notes: None,
+            sequence: None,
+        };
+        for t in [
+            transaction(1, TransactionType::Deposit, 1, dec!(3)),
+            transaction(2, TransactionType::Deposit, 2, dec!(4)),
+        ] {
+            svc.process_transaction(&t).await.unwrap();
+        }
+        let start = 1_700_000_000_000;
+        clock.advance(Duration::from_secs(1));
+        let snapshot = svc.snapshot_balances().await.unwrap();
+        assert_eq!(
+            snapshot,
+            BalanceSnapshot {
+                taken_at: start + 1_000,
+                checkpoint: 2,
+                clients: 2
+            }
+        );
+        clock.advance(Duration::from_secs(1));
+        // Nothing changed since
+        assert_eq!(svc.snapshot_balances().await.unwrap(), snapshot);
+
+        svc.process_transaction(&transaction(3, TransactionType::Withdrawal, 1, dec!(1)))
+            .await
+            .unwrap();
+        // Reports after the snapshot do not read the audit log before it
+        sqlx::query("DELETE FROM AuditLog WHERE id <= ?")
+            .bind(snapshot.checkpoint)
+            .execute(&svc.pool)
+            .await
+            .unwrap();
+        let totals =
+            |clients: Vec<Client>| clients.iter().map(|c| (c.id, c.total)).collect::<Vec<_>>();
+        assert_eq!(
+            totals(svc.get_clients_as_of(start + 1_000).await.unwrap()),
+            [(1, dec!(3)), (2, dec!(4))]
+        );
+        assert_eq!(
+            totals(svc.get_clients_as_of(start + 2_000).await.unwrap()),
+            [(1, dec!(2)), (2, dec!(4))]
+        );
+        assert_eq!(
+            svc.get_client_as_of(2, start + 2_000).await.unwrap(),
+            svc.get_client(2).await.unwrap()
+        );
+        assert_eq!(svc.get_client_as_of(3, start + 2_000).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_close_day() {
+        use time::macros::date;
+
+        let svc = TransactionService::builder()
+            .audit_log(true)
+            .build()
+            .await
+            .unwrap();
+        let process = |id, transaction_type, amount| {
+            let svc = &svc;
+            async move {
+                svc.process_transaction(&Transaction {
+                    id,
+                    transaction_type,
+                    client_id: 1,
+                    amount,
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                })
+                .await
+                .unwrap();
+            }
+        };
+        process(1, TransactionType::Deposit, Some(dec!(10))).await;
+        process(2, TransactionType::Deposit, Some(dec!(2.5))).await;
+        process(3, TransactionType::Withdrawal, Some(dec!(100))).await;
+        process(1, TransactionType::Dispute, None).await;
+        svc.adjust_balance(1, dec!(-1), "Fee", "ops").await.unwrap();
+
+        let close = svc.close_day(date!(2024 - 01 - 31)).await.unwrap();
+        assert_eq!(close.checkpoint, 4);
+        assert_eq!(close.clients, [svc.get_client(1).await.unwrap().unwrap()]);
+        let total = |name: &str, count, amount| crate::TypeTotal {
+            name: name.into(),
+            count,
+            amount,
+        };
+        // The rejected withdrawal is not counted
+        assert_eq!(
+            close.totals,
+            [
+                total("adjustment", 1, dec!(-1)),
+                total("deposit", 2, dec!(12.5)),
+                total("dispute", 1, dec!(10)),
+            ]
+        );
+        assert_eq!(
+            svc.get_day_close(date!(2024 - 01 - 31)).await.unwrap(),
+            Some(close)
+        );
+
+        assert!(svc.close_day(date!(2024 - 01 - 31)).await.is_err());
+        assert!(svc.close_day(date!(2024 - 01 - 30)).await.is_err());
+
+        // Only changes since the previous close count towards the next date
+        process(1, TransactionType::Chargeback, None).await;
+        let close = svc.close_day(date!(2024 - 02 - 01)).await.unwrap();
+        assert_eq!(close.checkpoint, 5);
+        assert_eq!(close.totals, [total("chargeback", 1, dec!(10))]);
+        assert!(close.clients[0].locked);
+        let close = svc.close_day(date!(2024 - 02 - 02)).await.unwrap();
+        assert_eq!(close.checkpoint, 5);
+        assert_eq!(close.totals, []);
+        assert_eq!(
+            svc.get_day_close(date!(2024 - 02 - 03)).await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_transactions_by_reference() {
+        let svc = TransactionService::builder().build().await.unwrap();
+        let csv = "type, client, tx, amount, reference
+            deposit, 1, 1, 5.0, BANK-1
+            deposit, 2, 2, 3.0,
+            withdrawal, 1, 3, 1.0, BANK-1
+            dispute, 1, 1,, BANK-2";
+        let mut reader = crate::TransactionReader::new(csv.as_bytes());
+        svc.process_stream(futures::stream::iter(reader.transactions()))
+            .await
+            .unwrap();
+
+        let ids = |transactions: Vec<Transaction>| -> Vec<TransactionId> {
+            transactions.iter().map(|t| t.id).collect()
+        };
+        let found = svc.get_transactions_by_reference("BANK-1").await.unwrap();
+        assert_eq!(found[0].reference.as_deref(), Some("BANK-1"));
+        assert_eq!(ids(found), [1, 3]);
+        assert_eq!(
+            svc.get_transaction(2).await.unwrap().unwrap().reference,
+            None
+        );
+        // Only deposits and withdrawals are stored with their reference
+        assert!(svc
+            .get_transactions_by_reference("BANK-2")
+            .await
+            .unwrap()
+            .is_empty());
+
+        let imported = TransactionService::builder().build().await.unwrap();
+        imported
+            .import_state(&svc.export_state().await.unwrap())
+            .await
+            .unwrap();
+        assert_eq!(
+            ids(imported
+                .get_transactions_by_reference("BANK-1")
+                .await
+                .unwrap()),
+            [1, 3]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dispute_metadata() {
+        let svc = TransactionService::builder().build().await.unwrap();
+        let csv = "type, client, tx, amount, reference, reason_code, notes
+            deposit, 1, 1, 5.0,,,
+            deposit, 1, 2, 3.0,,,
+            dispute, 1, 1,,, 10.4, Card not present
+            dispute, 1, 2,,,,";
+        let mut reader = crate::TransactionReader::new(csv.as_bytes());
+        svc.process_stream(futures::stream::iter(reader.transactions()))
+            .await
+            .unwrap();
+
+        let disputes: Vec<Dispute> = svc.get_open_disputes(None).try_collect().await.unwrap();
+        assert_eq!(disputes[0].reason_code.as_deref(), Some("10.4"));
+        assert_eq!(disputes[0].notes.as_deref(), Some("Card not present"));
+        assert_eq!(disputes[1].reason_code, None);
+        assert_eq!(disputes[1].notes, None);
+        // The disputed deposit is unchanged
+        assert_eq!(disputes[0].transaction.reason_code, None);
+
+        let imported = TransactionService::builder().build().await.unwrap();
+        imported
+            .import_state(&svc.export_state().await.unwrap())
+            .await
+            .unwrap();
+        let imported: Vec<Dispute> = imported
+            .get_open_disputes(None)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(imported, disputes);
+    }
+
+    #[tokio::test]
+    async fn test_client_stats() {
+        let svc = TransactionService::builder().build().await.unwrap();
+        let csv = "type, client, tx, amount
+            deposit, 1, 1, 5.0
+            deposit, 1, 2, 3.0
+            withdrawal, 1, 3, 1.0
+            withdrawal, 1, 4, 100.0
+            dispute, 1, 1,
+            resolve, 1, 1,
+            dispute, 1, 2,
+            chargeback, 1, 2,
+            deposit, 1, 5, 1.0
+            deposit, 2, 6, 1.5";
+        let mut reader = crate::TransactionReader::new(csv.as_bytes());
+        svc.process_stream(futures::stream::iter(reader.transactions()))
+            .await
+            .unwrap();
+
+        let stats = svc.get_client_stats().await.unwrap();
+        assert_eq!(stats[0].client, svc.get_client(1).await.unwrap().unwrap());
+        // The rejected withdrawal and the deposit ignored after the lock do not count
+        assert_eq!(
+            stats[0].stats,
+            ClientStats {
+                deposits: 2,
+                deposited: dec!(8),
+                withdrawals: 1,
+                withdrawn: dec!(1),
+                disputes: 2,
+                chargebacks: 1,
+                risk_score: 0,
+            }
+        );
+        assert_eq!(stats[1].stats.deposited, dec!(1.5));
+
+        let imported = TransactionService::builder().build().await.unwrap();
+        imported
+            .import_state(&svc.export_state().await.unwrap())
+            .await
+            .unwrap();
+        assert_eq!(imported.get_client_stats().await.unwrap(), stats);
+    }
+
+    #[tokio::test]
+    async fn test_backfill() {
+        let observer = RecordingObserver::default();
+        let svc = TransactionService::builder()
+            .observer(observer.clone())
+            .outbox(true)
+            .audit_log(true)
+            .alerts(crate::AlertThresholds {
+                min_available: None,
+                max_held: Some(dec!(1)),
+            })
+            .backfill(true)
+            .build()
+            .await
+            .unwrap();
+        let csv = "type, client, tx, amount
+            deposit, 1, 1, 5.0
+            dispute, 1, 1,
+            chargeback, 1, 1,";
+        let mut reader = crate::TransactionReader::new(csv.as_bytes());
+        let outcome = svc
+            .process_stream(futures::stream::iter(reader.transactions()))
+            .await
+            .unwrap();
+        assert_eq!(outcome.applied, 3);
+        assert!(svc.get_client(1).await.unwrap().unwrap().locked);
+
+        // Nothing is sent, but the audit log is still written
+        assert!(observer.0.lock().unwrap().is_empty());
+        assert!(svc.outbox_events(10).await.unwrap().is_empty());
+        assert_eq!(svc.alert_counts(), Default::default());
+        let verification = svc.verify_audit_log().await.unwrap();
+        assert!(verification.is_valid());
+        assert_eq!(verification.records, 3);
+    }
+
+    #[tokio::test]
+    async fn test_backfill_chargebacks() {
+        let svc = TransactionService::builder()
+            .policy(TransactionPolicy {
+                chargeback_escalation: ChargebackEscalation {
+                    flag: Some(1),
+                    block_withdrawals: Some(2),
+                    lock_permanently: Some(3),
+                },
+                ..Default::default()
+            })
+            .validator(RuleSet::new([r#"score +10 when type == "chargeback""#]).unwrap())
+            .backfill(true)
+            .build()
+            .await
+            .unwrap();
+        let mut csv = String::from("type, client, tx, amount\n");
+        for client in 1..=4 {
+            for id in client * 10..client * 10 + 2 {
+                csv += &format!(
+                    "deposit, {client}, {id}, 100\ndispute, {client}, {id},\nchargeback, {client}, {id},\n"
+                );
+            }
+        }
+        let mut reader = crate::TransactionReader::new(csv.as_bytes());
+        let outcome = svc
+            .process_stream(futures::stream::iter(reader.transactions()))
+            .await
+            .unwrap();
+
+        // Only the first chargeback of each client applies, as it locks the client
+        assert_eq!((outcome.applied, outcome.ignored), (12, 12));
+        for client in svc.get_client_stats().await.unwrap() {
+            assert!(client.client.locked);
+            assert_eq!(svc.get_client_risk(client.client.id).await.unwrap(), None);
+            assert_eq!(client.stats.risk_score, 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_simulate() {
+        let observer = RecordingObserver::default();
+        let svc = TransactionService::builder()
+            .observer(observer.clone())
+            .audit_log(true)
+            .alerts(crate::AlertThresholds {
+                min_available: Some(dec!(0)),
+                max_held: None,
+            })
+            .build()
+            .await
+            .unwrap();
+        let transaction = |id, transaction_type, client_id, amount| Transaction {
+            id,
+            transaction_type,
+            client_id,
+            amount,
+            reference: None,
+            reason_code: None,
+            notes: None,
+            sequence: None,
+        };
+        for (id, client_id) in [(1, 1), (2, 1), (3, 2)] {
+            svc.process_transaction(&transaction(
+                id,
+                TransactionType::Deposit,
+                client_id,
+                Some(dec!(5)),
+            ))
+            .await
+            .unwrap();
+        }
+        svc.process_transaction(&transaction(
+            4,
+            TransactionType::Withdrawal,
+            1,
+            Some(dec!(8)),
+        ))
+        .await
+        .unwrap();
+        observer.0.lock().unwrap().clear();
+        let before = svc.export_state().await.unwrap();
+        let audited = svc.verify_audit_log().await.unwrap().records;
+
+        let projection = svc
+            .simulate(&[
+                transaction(1, TransactionType::Dispute, 1, None),
+                transaction(1, TransactionType::Chargeback, 1, None),
+                transaction(9, TransactionType::Dispute, 2, None),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(
+            projection.outcomes[2],
+            TransactionOutcome::Ignored {
+                reason: IgnoreReason::UnknownTarget
+            }
+        );
+        assert_eq!(
+            projection.clients,
+            [
+                Client {
+                    id: 1,
+                    available: dec!(-3),
+                    held: dec!(0),
+                    total: dec!(-3),
+                    locked: true,
+                },
+                svc.get_client(2).await.unwrap().unwrap(),
+            ]
+        );
+        assert_eq!(
+            projection.before,
+            [
+                svc.get_client(1).await.unwrap(),
+                svc.get_client(2).await.unwrap()
+            ]
+        );
+        // Client 2 was only targeted by an ignored dispute
+        let changes: Vec<_> = projection.changes().collect();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].0.unwrap().available, dec!(2));
+        assert_eq!(changes[0].1.id, 1);
+
+        // Nothing was stored or sent
+        assert_eq!(svc.export_state().await.unwrap(), before);
+        assert_eq!(svc.verify_audit_log().await.unwrap().records, audited);
+        assert!(observer.0.lock().unwrap().is_empty());
+        assert_eq!(svc.alert_counts().low_available, 0);
+        svc.process_transaction(&transaction(5, TransactionType::Deposit, 2, Some(dec!(1))))
+            .await
+            .unwrap();
+        assert_eq!(svc.alert_counts().low_available, 0);
+    }
+
+    #[tokio::test]
+    async fn test_cash_position() {
+        let svc = TransactionService::builder().build().await.unwrap();
+        let csv = "type, client, tx, amount
+            deposit, 1, 1, 10.0
+            deposit, 2, 2, 4.0
+            withdrawal, 1, 3, 8.0
+            withdrawal, 1, 4, 100.0
+            dispute, 1, 1,
+            chargeback, 1, 1,
+            dispute, 2, 2,
+            resolve, 2, 2,";
+        let mut reader = crate::TransactionReader::new(csv.as_bytes());
+        svc.process_stream(futures::stream::iter(reader.transactions()))
+            .await
+            .unwrap();
+        svc.adjust_balance(2, dec!(-1), "Monthly fee", "ops")
+            .await
+            .unwrap();
+
+        let position = svc.get_cash_position().await.unwrap();
+        let balances: Vec<_> = position
+            .accounts
+            .iter()
+            .map(|a| (a.name.as_str(), a.balance))
+            .collect();
+        // The chargeback of 10 took client 1 from 2 to -8
+        assert_eq!(
+            balances,
+            [
+                ("cash", dec!(-4)),
+                ("chargeback_expense", dec!(8)),
+                ("fees", dec!(1))
+            ]
+        );
+        assert_eq!(position.client_funds, dec!(-5));
+        assert_eq!(
+            position.accounts[0].balance,
+            position.client_funds + dec!(1)
+        );
+
+        let imported = TransactionService::builder().build().await.unwrap();
+        imported
+            .import_state(&svc.export_state().await.unwrap())
+            .await
+            .unwrap();
+        assert_eq!(imported.get_cash_position().await.unwrap(), position);
+    }
+
+    #[tokio::test]
+    async fn test_balance_overflow() {
+        let svc = TransactionService::builder().build().await.unwrap();
+        // The largest balance is 922337203685477.5807
+        let csv = "type, client, tx, amount
+            deposit, 1, 1, 500000000000000
+            deposit, 1, 2, 500000000000000
+            deposit, 2, 3, 500000000000000
+            withdrawal, 1, 4, 400000000000000
+            deposit, 2, 5, 500000000000000
+            dispute, 1, 2,";
+        let mut reader = crate::TransactionReader::new(csv.as_bytes());
+        let outcome = svc
+            .process_stream(futures::stream::iter(reader.transactions()))
+            .await
+            .unwrap();
+        // The second deposit overflows client 1, the third the cash account
+        assert_eq!(outcome.rejected, 2);
+        assert_eq!(outcome.ignored, 1);
+        assert_eq!(svc.alert_counts().balance_overflow, 2);
+        assert_eq!(
+            svc.get_client(1).await.unwrap().unwrap().total,
+            dec!(100000000000000)
+        );
+        assert_eq!(
+            svc.get_client(2).await.unwrap().unwrap().total,
+            dec!(500000000000000)
+        );
+        let rejected = svc
+            .process_transaction(&Transaction {
+                id: 6,
+                transaction_type: TransactionType::Deposit,
+                client_id: 2,
+                amount: Some(dec!(500000000000000)),
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(rejected.reason(), Some(TransactionOutcome::OVERFLOW));
+        // Nor is a client created for a deposit overflowing the cash account
+        let rejected = svc
+            .process_transaction(&Transaction {
+                id: 7,
+                transaction_type: TransactionType::Deposit,
+                client_id: 3,
+                amount: Some(dec!(500000000000000)),
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(rejected.reason(), Some(TransactionOutcome::OVERFLOW));
+        assert!(svc.get_client(3).await.unwrap().is_none());
+        assert_eq!(svc.alert_counts().balance_overflow, 4);
+
+        // Adjustments overflowing the client's funds or the fees account are rejected too
+        assert!(matches!(
+            svc.adjust_balance(1, dec!(900000000000000), "Goodwill", "ops")
+                .await,
+            Err(TransactionError::BalanceOverflow { client_id: 1, .. })
+        ));
+        sqlx::query("UPDATE HouseAccounts SET balance = ? WHERE name = ?")
+            .bind(i64::MIN)
+            .bind(super::FEES)
+            .execute(&svc.pool)
+            .await
+            .unwrap();
+        assert!(matches!(
+            svc.adjust_balance(1, dec!(1), "Goodwill", "ops").await,
+            Err(TransactionError::BalanceOverflow { client_id: 1, .. })
+        ));
+        assert_eq!(svc.alert_counts().balance_overflow, 6);
+        assert_eq!(
+            svc.get_client(1).await.unwrap().unwrap().total,
+            dec!(100000000000000)
+        );
+
+        // The deposited total saturates instead
+        let csv = "type, client, tx, amount
+            deposit, 1, 1, 300000000000000
+            withdrawal, 1, 2, 300000000000000
+            deposit, 1, 3, 300000000000000
+            withdrawal, 1, 4, 300000000000000
+            deposit, 1, 5, 300000000000000
+            withdrawal, 1, 6, 300000000000000
+            deposit, 1, 7, 300000000000000";
+        let svc = TransactionService::builder().build().await.unwrap();
+        let mut reader = crate::TransactionReader::new(csv.as_bytes());
+        svc.process_stream(futures::stream::iter(reader.transactions()))
+            .await
+            .unwrap();
+        let stats = svc.get_client_stats().await.unwrap();
+        assert_eq!(stats[0].stats.deposits, 4);
+        assert_eq!(stats[0].stats.deposited, dec!(922337203685477.5807));
+        assert_eq!(stats[0].client.total, dec!(300000000000000));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_amounts() {
+        use crate::{TransactionReader, TransactionSource};
+
+        let svc = TransactionService::builder().build().await.unwrap();
+        // Too large to be stored at all, then not positive
+        let csv = "type, client, tx, amount
+            deposit, 1, 1, 10
+            deposit, 1, 2, 10000000000000000
+            withdrawal, 1, 3, -100
+            deposit, 1, 4, 0
+            deposit, 2, 5, 10000000000000000
+            withdrawal, 1, 6, 4";
+        let batch = svc.begin_batch("partner.csv", None).await.unwrap();
+        let mut reasons = Vec::new();
+        let outcome = svc
+            .process_batch_with(
+                &batch,
+                TransactionReader::new(csv.as_bytes()).stream_with_lines(),
+                |_, _, outcome| {
+                    reasons.extend(outcome.reason().map(str::to_string));
+                    Ok(())
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(outcome.applied, 2);
+        assert_eq!(
+            reasons,
+            [
+                TransactionOutcome::OVERFLOW,
+                TransactionOutcome::INVALID_AMOUNT,
+                TransactionOutcome::INVALID_AMOUNT,
+                TransactionOutcome::OVERFLOW,
+            ]
+        );
+        assert_eq!(svc.alert_counts().balance_overflow, 2);
+        assert_eq!(svc.get_client(1).await.unwrap().unwrap().total, dec!(6));
+        assert!(svc.get_client(2).await.unwrap().is_none());
+        assert!(svc.get_transaction(3).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_external_ids() {
+        let svc = TransactionService::builder().build().await.unwrap();
+        let csv = "type, client, tx, amount
+            deposit, 5, 1, 1.0";
+        let mut reader = crate::TransactionReader::new(csv.as_bytes());
+        svc.process_stream(futures::stream::iter(reader.transactions()))
+            .await
+            .unwrap();
+
+        // New external ids are given client ids after the existing clients
+        let external_ids = svc.get_external_ids().await.unwrap();
+        let csv = "type, client, tx, amount
+            deposit, ACME-7, 2, 2.0
+            deposit, ACME-x, 3, 3.0
+            withdrawal, ACME-7, 4, 0.5";
+        let mut reader =
+            crate::TransactionReader::new(csv.as_bytes()).external_ids(external_ids.clone());
+        svc.process_stream(futures::stream::iter(reader.transactions()))
+            .await
+            .unwrap();
+        svc.save_external_ids(&external_ids).await.unwrap();
+        assert_eq!(
+            svc.get_client(6).await.unwrap().unwrap().available,
+            dec!(1.5)
+        );
+
+        let external_ids = svc.get_external_ids().await.unwrap();
+        let jsonl = r#"{"type": "deposit", "client": "ACME-x", "tx": 5, "amount": 1}
+{"type": "deposit", "client": 42, "tx": 6, "amount": 1}"#;
+        let mut reader =
+            crate::JsonLinesReader::new(jsonl.as_bytes()).external_ids(external_ids.clone());
+        svc.process_stream(futures::stream::iter(reader.transactions()))
+            .await
+            .unwrap();
+        svc.save_external_ids(&external_ids).await.unwrap();
+        assert_eq!(svc.get_client(7).await.unwrap().unwrap().total, dec!(4));
+
+        let mapping = |external_id: &str, client| ExternalId {
+            external_id: external_id.to_string(),
+            client,
+        };
+        let expected = [mapping("ACME-7", 6), mapping("ACME-x", 7), mapping("42", 8)];
+        assert_eq!(svc.get_external_ids().await.unwrap().mappings(), expected);
+        assert_eq!(svc.export_state().await.unwrap().external_ids, expected);
+    }
+
+    #[tokio::test]
+    async fn test_backup_and_restore() {
+        let dir = std::env::temp_dir().join(format!("ledger-backup-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let backup = dir.join("backup.db");
+
+        let svc = TransactionService::builder()
+            .audit_log(true)
+            .build()
+            .await
+            .unwrap();
+        let csv = "type, client, tx, amount
+            deposit, 1, 1, 5.0
+            deposit, 2, 2, 3.0
+            withdrawal, 1, 3, 1.5
+            dispute, 2, 2,";
+        let mut reader = crate::TransactionReader::new(csv.as_bytes());
+        svc.process_stream(futures::stream::iter(reader.transactions()))
+            .await
+            .unwrap();
+        svc.backup(&backup).await.unwrap();
+        assert!(svc.backup(&backup).await.is_err());
+
+        let restored = TransactionService::builder()
+            .audit_log(true)
+            .build()
+            .await
+            .unwrap();
+        assert!(restored.restore(&dir.join("missing.db")).await.is_err());
+        let not_a_database = dir.join("not-a-database.db");
+        std::fs::write(&not_a_database, "not a database").unwrap();
+        assert!(restored.restore(&not_a_database).await.is_err());
+
+        // Waits while writes are paused, like other writes
+        restored.pause().await;
+        let paused = tokio::time::timeout(Duration::from_millis(50), restored.restore(&backup));
+        assert!(paused.await.is_err());
+        restored.resume().await;
+
+        restored.restore(&backup).await.unwrap();
+        let state = restored.export_state().await.unwrap();
+        assert_eq!(state, svc.export_state().await.unwrap());
+        assert_eq!(
+            restored.get_cash_position().await.unwrap(),
+            svc.get_cash_position().await.unwrap()
+        );
+        assert!(restored
+            .verify_audit_log()
+            .await
+            .unwrap()
+            .problems
+            .is_empty());
+        assert!(restored.restore(&backup).await.is_err());
+
+        // The restored ledger carries on where the backup left off
+        let csv = "type, client, tx, amount
+            resolve, 2, 2,
+            deposit, 3, 4, 1.0";
+        let mut reader = crate::TransactionReader::new(csv.as_bytes());
+        let outcome = restored
+            .process_stream(futures::stream::iter(reader.transactions()))
+            .await
+            .unwrap();
+        assert_eq!(outcome.applied, 2);
+        assert!(restored
+            .verify_audit_log()
+            .await
+            .unwrap()
+            .problems
+            .is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_replicas() {
+        let dir = std::env::temp_dir().join(format!("ledger-replicas-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let primary = format!("sqlite://{}", dir.join("ledger.db").display());
+        let deposit = |id, amount| Transaction {
+            id,
+            transaction_type: TransactionType::Deposit,
+            client_id: 1,
+            amount: Some(amount),
+            reference: None,
+            reason_code: None,
+            notes: None,
+            sequence: None,
+        };
+        let svc = TransactionService::builder()
+            .database_url(&primary)
+            .build()
+            .await
+            .unwrap();
+        svc.process_transaction(&deposit(1, dec!(5))).await.unwrap();
+        svc.backup(&dir.join("replica.db")).await.unwrap();
+        svc.pool.close().await;
+
+        let svc = TransactionService::builder()
+            .database_url(&primary)
+            .read_replica_url(format!("sqlite://{}", dir.join("replica.db").display()))
+            .build()
+            .await
+            .unwrap();
+        svc.process_transaction(&deposit(2, dec!(3))).await.unwrap();
+        // Queries read the replica, which has not caught up
+        assert_eq!(svc.get_client(1).await.unwrap().unwrap().total, dec!(5));
+        assert_eq!(svc.get_transaction(2).await.unwrap(), None);
+        assert_eq!(svc.get_cash_position().await.unwrap().client_funds, dec!(8));
+        // Writes still see their own changes
+        let withdrawal = Transaction {
+            transaction_type: TransactionType::Withdrawal,
+            ..deposit(3, dec!(7))
+        };
+        assert_eq!(
+            svc.process_transaction(&withdrawal).await.unwrap(),
+            TransactionOutcome::Withdrawal
+        );
+
+        svc.pool.close().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_maintain() {
+        let dir = std::env::temp_dir().join(format!("ledger-maintain-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let url = format!("sqlite://{}", dir.join("ledger.db").display());
+        let svc = TransactionService::builder()
+            .database_url(&url)
+            .build()
+            .await
+            .unwrap();
+        let transactions = (1..=2000).map(|id| {
+            Ok(Transaction {
+                id,
+                transaction_type: TransactionType::Deposit,
+                client_id: 1,
+                amount: Some(dec!(1)),
+                reference: Some(format!("BANK-{}", id)),
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+        });
+        svc.process_stream(futures::stream::iter(transactions))
+            .await
+            .unwrap();
+        sqlx::query("DELETE FROM [Transactions] WHERE id > ?")
+            .bind(db_id(1))
+            .execute(&svc.pool)
+            .await
+            .unwrap();
+
+        let report = svc.maintain().await.unwrap();
+        assert!(report.is_healthy());
+        assert!(report.size_after.unwrap() < report.size_before);
+        assert_eq!(svc.get_client(1).await.unwrap().unwrap().total, dec!(2000));
+
+        // Refused while writes are paused, or another process is writing
+        svc.pause().await;
+        assert!(svc.maintain().await.is_err());
+        svc.resume().await;
+        let other = sqlx::sqlite::SqlitePool::connect(&url).await.unwrap();
+        let mut write = other.begin().await.unwrap();
+        sqlx::query("UPDATE Clients SET locked = 0")
+            .execute(&mut *write)
+            .await
+            .unwrap();
+        assert!(matches!(
+            svc.maintain().await,
+            Err(TransactionError::InvalidArgument(_))
+        ));
+        write.rollback().await.unwrap();
+        assert!(svc.maintain().await.unwrap().is_healthy());
+
+        other.close().await;
+        svc.pool.close().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_retry_transient_errors() {
+        use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
+        use sqlx::{ConnectOptions, Connection};
+
+        let dir = std::env::temp_dir().join(format!("ledger-retry-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // Fail at once instead of waiting for the lock
+        let options = SqliteConnectOptions::new()
+            .filename(dir.join("ledger.db"))
+            .create_if_missing(true)
+            // Commits do not wait for readers, so the other process can always release it
+            .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(Duration::ZERO);
+        let pool = SqlitePoolOptions::new()
+            .connect_with(options.clone())
+            .await
+            .unwrap();
+        let build = |retry| {
+            TransactionService::builder()
+                .pool(pool.clone())
+                .retry_policy(retry)
+                .build()
+        };
+        let deposit = |id| Transaction {
+            id,
+            transaction_type: TransactionType::Deposit,
+            client_id: 1,
+            amount: Some(dec!(1)),
+            reference: None,
+            reason_code: None,
+            notes: None,
+            sequence: None,
+        };
+
+        // Another process holds the database for a while
+        let svc = build(RetryPolicy {
+            max_attempts: 50,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(20),
+        })
+        .await
+        .unwrap();
+        let mut other = options.connect().await.unwrap();
+        sqlx::query("BEGIN IMMEDIATE")
+            .execute(&mut other)
+            .await
+            .unwrap();
+        let release = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            sqlx::query("COMMIT").execute(&mut other).await.unwrap();
+            other
+        });
+        svc.process_stream(futures::stream::iter([deposit(1), deposit(2)].map(Ok)))
+            .await
+            .unwrap();
+        let mut other = release.await.unwrap();
+        assert_eq!(svc.get_client(1).await.unwrap().unwrap().total, dec!(2));
+
+        let svc = build(RetryPolicy::none()).await.unwrap();
+        sqlx::query("BEGIN IMMEDIATE")
+            .execute(&mut other)
+            .await
+            .unwrap();
+        let error = svc.process_transaction(&deposit(3)).await.unwrap_err();
+        assert!(error.is_transient(), "{}", error);
+        other.close().await.unwrap();
+
+        pool.close().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_warm_up() {
+        let svc = TransactionService::builder().build().await.unwrap();
+        assert_eq!(svc.warm_up(10).await.unwrap(), 0);
+        let transactions = (1..=30).map(|id: ClientId| {
+            Ok(Transaction {
+                id: TransactionId::from(id),
+                transaction_type: TransactionType::Deposit,
+                client_id: id % 3 + 1,
+                amount: Some(dec!(1)),
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+        });
+        svc.process_stream(futures::stream::iter(transactions))
+            .await
+            .unwrap();
+        assert_eq!(svc.warm_up(2).await.unwrap(), 2);
+        assert_eq!(svc.warm_up(10).await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_pause() {
+        let svc = TransactionService::builder().build().await.unwrap();
+        let deposit = |id| Transaction {
+            id,
+            transaction_type: TransactionType::Deposit,
+            client_id: 1,
+            amount: Some(dec!(1)),
+            reference: None,
+            reason_code: None,
+            notes: None,
+            sequence: None,
+        };
+        svc.process_transaction(&deposit(1)).await.unwrap();
+
+        svc.pause().await;
+        svc.pause().await;
+        assert!(svc.is_paused());
+        // Writes wait, reads do not
+        let transaction = deposit(2);
+        let write = svc.process_transaction(&transaction);
+        assert!(tokio::time::timeout(Duration::from_millis(50), write)
+            .await
+            .is_err());
+        assert_eq!(svc.get_client(1).await.unwrap().unwrap().total, dec!(1));
+
+        svc.resume().await;
+        assert!(!svc.is_paused());
+        svc.process_transaction(&transaction).await.unwrap();
+        assert_eq!(svc.get_client(1).await.unwrap().unwrap().total, dec!(2));
+    }
+
+    #[tokio::test]
+    async fn test_slow_transaction_steps() {
+        let svc = TransactionService::builder()
+            .slow_transaction_threshold(Duration::ZERO)
+            .build()
+            .await
+            .unwrap();
+        svc.process_transaction(&Transaction {
+            id: 1,
+            transaction_type: TransactionType::Deposit,
+            client_id: 1,
+            amount: Some(dec!(5)),
+            reference: None,
+            reason_code: None,
+            notes: None,
+            sequence: None,
+        })
+        .await
+        .unwrap();
+        let summary = svc.write_steps.lock().unwrap().summary();
+        let steps: Vec<_> = summary
+            .split(' ')
+            .map(|s| s.split('=').next().unwrap())
+            .collect();
+        assert_eq!(
+            steps,
+            [
+                "write_lock",
+                "begin",
+                "validate",
+                "apply",
+                "annotations_and_alerts",
+                "last_activity",
+                "commit"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_transaction_counts() {
+        let svc = TransactionService::builder().build().await.unwrap();
+        let transactions = [
+            Transaction {
+                id: 1,
+                transaction_type: TransactionType::Deposit,
+                client_id: 1,
+                amount: Some(dec!(5)),
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            },
+            Transaction {
+                id: 2,
+                transaction_type: TransactionType::Withdrawal,
+                client_id: 1,
+                amount: Some(dec!(10)),
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            },
+            Transaction {
+                id: 3,
+                transaction_type: TransactionType::Dispute,
+                client_id: 1,
+                amount: None,
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            },
+        ];
+        svc.process_stream(futures::stream::iter(transactions.clone().map(Ok)))
+            .await
+            .unwrap();
+        svc.process_transaction(&transactions[0]).await.unwrap();
+        // Rolled back transactions are not counted
+        assert!(svc
+            .process_transaction(&Transaction {
+                amount: None,
+                id: 4,
+                ..transactions[0].clone()
+            })
+            .await
+            .is_err());
+
+        let counts = svc.transaction_counts();
+        let count = |processed, applied, rejected, ignored, insufficient_funds| ProcessingOutcome {
+            processed,
+            applied,
+            rejected,
+            ignored,
+            insufficient_funds,
+        };
+        assert_eq!(counts.len(), 3);
+        assert_eq!(counts["deposit"], count(2, 1, 0, 1, 0));
+        assert_eq!(counts["withdrawal"], count(1, 0, 1, 0, 1));
+        assert_eq!(counts["dispute"], count(1, 0, 0, 1, 0));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_targets() {
+        let transaction = |id, transaction_type, amount| Transaction {
+            id,
+            transaction_type,
+            client_id: 1,
+            amount,
+            reference: None,
+            reason_code: None,
+            notes: None,
+            sequence: None,
+        };
+        let transactions = [
+            transaction(1, TransactionType::Deposit, Some(dec!(5))),
+            transaction(2, TransactionType::Dispute, None),
+            transaction(1, TransactionType::Resolve, None),
+            transaction(1, TransactionType::Chargeback, None),
+        ];
+
+        for action in [UnknownTargetAction::Ignore, UnknownTargetAction::Warn] {
+            let svc = TransactionService::builder()
+                .unknown_targets(action)
+                .build()
+                .await
+                .unwrap();
+            let summary = svc
+                .process_stream(futures::stream::iter(transactions.clone().map(Ok)))
+                .await
+                .unwrap();
+            assert_eq!(summary.ignored, 3);
+            let warned = match action {
+                UnknownTargetAction::Warn => 3,
+                _ => 0,
+            };
+            assert_eq!(svc.unknown_target_count(), warned);
+        }
+
+        let svc = TransactionService::builder()
+            .unknown_targets(UnknownTargetAction::Error)
+            .build()
+            .await
+            .unwrap();
+        assert!(matches!(
+            svc.process_stream(futures::stream::iter(transactions.clone().map(Ok)))
+                .await,
+            Err(TransactionError::InvalidTransaction {
+                transaction_id: 2,
+                ..
+            })
+        ));
+        // The batch with the unknown target was rolled back
+        assert_eq!(svc.get_client(1).await.unwrap(), None);
+        svc.process_transaction(&transactions[0]).await.unwrap();
+        for t in &transactions[1..] {
+            assert!(svc.process_transaction(t).await.is_err());
+        }
+        assert_eq!(svc.unknown_target_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_export_state() {
+        use time::macros::date;
+
+        let svc = TransactionService::builder()
+            .audit_log(true)
+            .build()
+            .await
+            .unwrap();
+        let process = |id, client_id, transaction_type, amount| {
+            let svc = &svc;
+            async move {
+                svc.process_transaction(&Transaction {
+                    id,
+                    transaction_type,
+                    client_id,
+                    amount,
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                })
+                .await
+                .unwrap();
+            }
+        };
+        process(1, 1, TransactionType::Deposit, Some(dec!(10.5))).await;
+        process(2, 2, TransactionType::Deposit, Some(dec!(3))).await;
+        process(1, 1, TransactionType::Dispute, None).await;
+        svc.close_day(date!(2024 - 01 - 31)).await.unwrap();
+        process(2, 2, TransactionType::Dispute, None).await;
+        process(2, 2, TransactionType::Chargeback, None).await;
+
+        let state = svc.export_state().await.unwrap();
+        assert_eq!(state.version, STATE_VERSION);
+        assert_eq!(state.transactions.len(), 2);
+        assert_eq!(state.disputes.len(), 1);
+        assert_eq!(state.disputes[0].tx, 1);
+        assert_eq!(state.day_closes[0].checkpoint, 3);
+        let json = serde_json::to_string(&state).unwrap();
+        let state: LedgerState = serde_json::from_str(&json).unwrap();
+
+        let imported = TransactionService::builder()
+            .audit_log(true)
+            .build()
+            .await
+            .unwrap();
+        imported.import_state(&state).await.unwrap();
+        assert_eq!(imported.export_state().await.unwrap(), state);
+        assert_eq!(
+            imported.get_client(2).await.unwrap(),
+            svc.get_client(2).await.unwrap()
+        );
+        // Disputes carry over, and the next close only covers changes after the import
+        imported
+            .process_transaction(&Transaction {
+                id: 1,
+                transaction_type: TransactionType::Resolve,
+                client_id: 1,
+                amount: None,
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(imported.get_client(1).await.unwrap().unwrap().held, dec!(0));
+        let close = imported.close_day(date!(2024 - 02 - 01)).await.unwrap();
+        assert_eq!(close.checkpoint, 4);
+        assert_eq!(close.totals.len(), 1);
+        assert_eq!(close.totals[0].name, "resolve");
+
+        // Only into an empty ledger, and only known versions
+        assert!(imported.import_state(&state).await.is_err());
+        let empty = TransactionService::builder().build().await.unwrap();
+        let future = LedgerState {
+            version: STATE_VERSION + 1,
+            ..state
+        };
+        assert!(empty.import_state(&future).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_check_ledger() {
+        let svc = TransactionService::builder()
+            .audit_log(true)
+            .build()
+            .await
+            .unwrap();
+        let csv = "type, client, tx, amount
+            deposit, 1, 1, 10.0
+            deposit, 2, 2, 4.0
+            deposit, 3, 3, 1.0
+            withdrawal, 1, 4, 3.0
+            dispute, 1, 1,
+            dispute, 2, 2,
+            chargeback, 2, 2,";
+        let mut reader = crate::TransactionReader::new(csv.as_bytes());
+        svc.process_stream(futures::stream::iter(reader.transactions()))
+            .await
+            .unwrap();
+        svc.adjust_balance(3, dec!(-0.5), "Monthly fee", "ops")
+            .await
+            .unwrap();
+        let check = svc.check_ledger().await.unwrap();
+        assert!(check.is_consistent(), "{check}");
+        assert_eq!((check.clients, check.transactions), (3, 4));
+
+        let imported = TransactionService::builder()
+            .audit_log(true)
+            .build()
+            .await
+            .unwrap();
+        imported
+            .import_state(&svc.export_state().await.unwrap())
+            .await
+            .unwrap();
+        let check = imported.check_ledger().await.unwrap();
+        assert!(check.is_consistent(), "{check}");
+
+        // A ledger broken by the system it was imported from, written without foreign keys
+        sqlx::query(&format!(
+            "PRAGMA foreign_keys = OFF;
+            UPDATE Clients SET held = held + 10000 WHERE id = 1;
+            UPDATE Clients SET locked = true WHERE id = 3;
+            INSERT INTO Disputes (transaction_id, opened_at) VALUES ({}, 0);
+            INSERT INTO [Transactions] (id, [type], client_id, amount) VALUES ({}, 'deposit', 4, 0);
+            PRAGMA foreign_keys = ON;",
+            db_id(9),
+            db_id(10)
+        ))
+        .execute(&imported.pool)
+        .await
+        .unwrap();
+        let check = imported.check_ledger().await.unwrap();
+        let violations: Vec<_> = check
+            .violations
+            .iter()
+            .map(|v| (v.invariant, v.client_id, v.transaction_id))
+            .collect();
+        assert_eq!(
+            violations,
+            [
+                ("held_funds", Some(1), None),
+                ("locked_client", Some(3), None),
+                ("transaction_client", Some(4), Some(10)),
+                ("transaction_amount", Some(4), Some(10)),
+                ("dispute_target", None, Some(9)),
+                ("cash", None, None),
+            ]
+        );
+        assert_eq!(
+            check.violations[0].detail,
+            "held 11.0000 is not the 10.0000 of its open disputes and the 0.0000 of its deposits waiting to settle"
+        );
+        assert_eq!(
+            check.violations[4].to_string(),
+            "dispute_target tx 9: the transaction is not stored"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expire_holds() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let observer = RecordingObserver::default();
+        let svc = TransactionService::builder()
+            .clock(clock.clone())
+            .observer(observer.clone())
+            .audit_log(true)
+            .build()
+            .await
+            .unwrap();
+        let process = |id, transaction_type, amount| {
+            let svc = &svc;
+            async move {
+                svc.process_transaction(&Transaction {
+                    id,
+                    transaction_type,
+                    client_id: 1,
+                    amount,
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                })
+                .await
+                .unwrap();
+            }
+        };
+        process(1, TransactionType::Deposit, Some(dec!(10))).await;
+        process(2, TransactionType::Deposit, Some(dec!(5))).await;
+        process(1, TransactionType::Dispute, None).await;
+        clock.advance(Duration::from_secs(60));
+        process(2, TransactionType::Dispute, None).await;
+        observer.0.lock().unwrap().clear();
+
+        let escalate = HoldExpiry::new(Duration::from_secs(60)).action(ExpiredHoldAction::Escalate);
+        let expired = svc.expire_holds(&escalate).await.unwrap();
+        assert_eq!(
+            expired
+                .iter()
+                .map(|d| (d.transaction.id, d.opened_at, d.escalated_at))
+                .collect::<Vec<_>>(),
+            [(1, 1_700_000_000_000, Some(1_700_000_060_000))]
+        );
+        // Escalating keeps the funds held, and only happens once
+        let client = svc.get_client(1).await.unwrap().unwrap();
+        assert_eq!((client.available, client.held), (dec!(0), dec!(15)));
+        assert_eq!(svc.expire_holds(&escalate).await.unwrap(), []);
+        let disputes: Vec<_> = svc.get_open_disputes(None).try_collect().await.unwrap();
+        assert_eq!(disputes[0].escalated_at, Some(1_700_000_060_000));
+
+        // Resolving also releases escalated disputes
+        clock.advance(Duration::from_secs(30));
+        let resolve = HoldExpiry::new(Duration::from_secs(60));
+        let expired = svc.expire_holds(&resolve).await.unwrap();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].transaction.id, 1);
+        let client = svc.get_client(1).await.unwrap().unwrap();
+        assert_eq!((client.available, client.held), (dec!(10), dec!(5)));
+        assert_eq!(
+            *observer.0.lock().unwrap(),
+            ["escalated 1".to_string(), "resolve 1".to_string()]
+        );
+
+        let verification = svc.verify_audit_log().await.unwrap();
+        assert!(verification.is_valid(), "{verification}");
+        let close = svc
+            .close_day(time::macros::date!(2024 - 01 - 31))
+            .await
+            .unwrap();
+        assert_eq!(
+            close
+                .totals
+                .iter()
+                .filter(|t| t.name.starts_with("expired_"))
+                .map(|t| (t.name.as_str(), t.count, t.amount))
+                .collect::<Vec<_>>(),
+            [
+                ("expired_escalate", 1, dec!(10)),
+                ("expired_resolve", 1, dec!(10))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expire_holds_locked_client() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let observer = RecordingObserver::default();
+        let svc = TransactionService::builder()
+            .clock(clock.clone())
+            .observer(observer.clone())
+            .audit_log(true)
+            .build()
+            .await
+            .unwrap();
+        // The chargeback of deposit 2 locks the client while deposit 1 is disputed
+        for (id, transaction_type, amount) in [
+            (1, TransactionType::Deposit, Some(dec!(10))),
+            (2, TransactionType::Deposit, Some(dec!(5))),
+            (1, TransactionType::Dispute, None),
+            (2, TransactionType::Dispute, None),
+            (2, TransactionType::Chargeback, None),
+        ] {
+            svc.process_transaction(&Transaction {
+                id,
+                transaction_type,
+                client_id: 1,
+                amount,
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+            .await
+            .unwrap();
+        }
+        observer.0.lock().unwrap().clear();
+        clock.advance(Duration::from_secs(60));
+
+        let resolve = HoldExpiry::new(Duration::from_secs(60));
+        for _ in 0..2 {
+            assert_eq!(svc.expire_holds(&resolve).await.unwrap(), []);
+        }
+        let client = svc.get_client(1).await.unwrap().unwrap();
+        assert_eq!(
+            (client.available, client.held, client.locked),
+            (dec!(0), dec!(10), true)
+        );
+        assert!(svc.get_dispute(1).await.unwrap().is_some());
+        assert!(observer.0.lock().unwrap().is_empty());
+        let close = svc
+            .close_day(time::macros::date!(2024 - 01 - 31))
+            .await
+            .unwrap();
+        assert!(!close.totals.iter().any(|t| t.name.starts_with("expired_")));
+    }
+
+    #[tokio::test]
+    async fn test_dormant_clients() {
+        const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let svc = TransactionService::builder()
+            .clock(clock.clone())
+            .build()
+            .await
+            .unwrap();
+        let process = |id, transaction_type, client_id, amount| {
+            let svc = &svc;
+            async move {
+                svc.process_transaction(&Transaction {
+                    id,
+                    transaction_type,
+                    client_id,
+                    amount,
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                })
+                .await
+                .unwrap();
+            }
+        };
+        process(1, TransactionType::Deposit, 1, Some(dec!(10))).await;
+        process(2, TransactionType::Deposit, 2, Some(dec!(5))).await;
+        process(3, TransactionType::Deposit, 3, Some(dec!(1))).await;
+        clock.advance(DAY * 10);
+        // Operator changes are not activity, attempts to withdraw are
+        svc.adjust_balance(1, dec!(1), "Interest", "ops")
+            .await
+            .unwrap();
+        process(4, TransactionType::Withdrawal, 2, Some(dec!(100))).await;
+        clock.advance(DAY * 10);
+        process(3, TransactionType::Dispute, 3, None).await;
+
+        let dormant = svc.get_dormant_clients(DAY * 15).await.unwrap();
+        assert_eq!(dormant.len(), 1);
+        assert_eq!(dormant[0].client.id, 1);
+        assert_eq!(dormant[0].client.available, dec!(11));
+        assert_eq!(dormant[0].last_activity_at, Some(1_700_000_000_000));
+        assert_eq!(
+            svc.get_dormant_clients(DAY * 10)
+                .await
+                .unwrap()
+                .iter()
+                .map(|d| d.client.id)
+                .collect::<Vec<_>>(),
+            [1, 2]
+        );
+        assert_eq!(svc.get_dormant_clients(DAY * 21).await.unwrap(), []);
+    }
+
+    #[tokio::test]
+    async fn test_alerts() {
+        let observer = RecordingObserver::default();
+        let svc = TransactionService::builder()
+            .observer(observer.clone())
+            .alerts(crate::AlertThresholds {
+                min_available: Some(dec!(0)),
+                max_held: Some(dec!(8)),
+            })
+            .build()
+            .await
+            .unwrap();
+        for (transaction_type, id, amount) in [
+            (TransactionType::Deposit, 1, Some(dec!(10))),
+            (TransactionType::Withdrawal, 2, Some(dec!(5))),
+            // Moves 10 to held, leaving -5 available
+            (TransactionType::Dispute, 1, None),
+            (TransactionType::Deposit, 3, Some(dec!(1))),
+            (TransactionType::Resolve, 1, None),
+            (TransactionType::Dispute, 1, None),
+        ] {
+            svc.process_transaction(&Transaction {
+                id,
+                transaction_type,
+                client_id: 1,
+                amount,
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+            .await
+            .unwrap();
+        }
+
+        let alerts: Vec<_> = observer
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|e| e.starts_with("alert"))
+            .cloned()
+            .collect();
+        // Raised when crossing, again after the resolve brought the client back
+        assert_eq!(
+            alerts,
+            [
+                "alert low_available 1",
+                "alert high_held 1",
+                "alert low_available 1",
+                "alert high_held 1"
+            ]
+        );
+        assert_eq!(
+            svc.alert_counts(),
+            crate::AlertCounts {
+                low_available: 2,
+                high_held: 2,
+                balance_overflow: 0,
+            }
+        );
+    }
+
+    /// Annotates every transaction with the reviewer's name.
+    struct Reviewer;
+
+    impl TransactionValidator for Reviewer {
+        fn validate(
+            &self,
+            _transaction: &Transaction,
+            _client: Option<&Client>,
+        ) -> crate::Result<Verdict> {
+            Ok(Verdict::Accept {
+                annotations: vec!["Reviewed by Jane Doe".into()],
+                risk_score_change: 0,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_forget_client() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let svc = TransactionService::builder()
+            .pool(pool.clone())
+            .outbox(true)
+            .audit_log(true)
+            .validator(Reviewer)
+            .build()
+            .await
+            .unwrap();
+        for (id, transaction_type, client_id, amount) in [
+            (1, TransactionType::Deposit, 1, Some(dec!(3))),
+            (2, TransactionType::Deposit, 2, Some(dec!(4))),
+            (1, TransactionType::Dispute, 1, None),
+        ] {
+            svc.process_transaction(&Transaction {
+                id,
+                transaction_type,
+                client_id,
+                amount,
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+            .await
+            .unwrap();
+        }
+        svc.adjust_balance(1, dec!(1), "Refund for Jane Doe", "ops")
+            .await
+            .unwrap();
+        svc.adjust_balance(2, dec!(1), "Refund for John Roe", "ops")
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            svc.forget_client(1, ErasurePolicy::Tombstone, "dpo").await,
+            Err(TransactionError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            svc.forget_client(3, ErasurePolicy::Anonymize, "dpo").await,
+            Err(TransactionError::ClientNotFound { client_id: 3, .. })
+        ));
+
+        let erasure = svc
+            .forget_client(1, ErasurePolicy::Anonymize, "dpo")
+            .await
+            .unwrap();
+        // The annotations of the deposit and the dispute, and the adjustment's reason
+        assert_eq!((erasure.redacted, erasure.removed), (3, 0));
+        assert!(svc
+            .get_annotations(1)
+            .await
+            .unwrap()
+            .iter()
+            .all(|a| a.annotation == "[erased]"));
+        assert_eq!(
+            svc.get_annotations(2).await.unwrap()[0].annotation,
+            "Reviewed by Jane Doe"
+        );
+        assert_eq!(svc.get_client(1).await.unwrap().unwrap().total, dec!(4));
+        assert!(svc.get_transaction(1).await.unwrap().is_some());
+
+        let payloads: Vec<String> = svc
+            .outbox_events(100)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| e.payload)
+            .collect();
+        let (audit_events,): (String,) =
+            sqlx::query_as("SELECT group_concat(event, '\n') FROM AuditLog")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        for events in [payloads.join("\n"), audit_events] {
+            assert!(!events.contains("Jane Doe"), "{events}");
+            assert!(events.contains("John Roe"));
+            assert!(events.contains("client_forgotten"));
+        }
+        let verification = svc.verify_audit_log().await.unwrap();
+        assert!(verification.is_valid(), "{verification}");
+
+        svc.process_transaction(&Transaction {
+            id: 1,
+            transaction_type: TransactionType::Resolve,
+            client_id: 1,
+            amount: None,
+            reference: None,
+            reason_code: None,
+            notes: None,
+            sequence: None,
+        })
+        .await
+        .unwrap();
+        let erasure = svc
+            .forget_client(1, ErasurePolicy::Tombstone, "dpo")
+            .await
+            .unwrap();
+        // The resolve was annotated after the first erasure
+        assert_eq!((erasure.redacted, erasure.removed), (1, 1));
+        assert!(svc.get_transaction(1).await.unwrap().is_none());
+        assert_eq!(svc.get_client(1).await.unwrap().unwrap().total, dec!(4));
+        let verification = svc.verify_audit_log().await.unwrap();
+        assert!(verification.is_valid(), "{verification}");
+
+        // Only records of erased clients can be redacted
+        sqlx::query("UPDATE AuditLog SET redacted_hash = 'x' WHERE event LIKE '%John Roe%'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(!svc.verify_audit_log().await.unwrap().is_valid());
+    }
+
+    async fn audit_hash(pool: &SqlitePool, id: i64) -> String {
+        let (hash,): (String,) = sqlx::query_as("SELECT hash FROM AuditLog WHERE id = ?")
+            .bind(id)
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        hash
+    }
+
+    /// Counts loyalty points per client, rejecting negative amounts.
+    struct LoyaltyPoints;
+
+    impl TransactionHandler for LoyaltyPoints {
+        fn schema(&self) -> &str {
+            "CREATE TABLE IF NOT EXISTS LoyaltyPoints (client_id INTEGER PRIMARY KEY, points INTEGER NOT NULL)"
+        }
+
+        fn apply<'a>(
+            &'a self,
+            storage: &'a mut StorageHandle<'_>,
+            transaction: &'a Transaction,
+        ) -> BoxFuture<'a, crate::Result<bool>> {
+            Box::pin(async move {
+                let points = transaction
+                    .amount
+                    .and_then(|a| a.to_i64())
+                    .filter(|p| *p >= 0)
+                    .ok_or_else(|| TransactionError::invalid(transaction.id, "Invalid points"))?;
+                if storage.client(transaction.client_id).await?.is_none() {
+                    return Ok(false);
+                }
+                sqlx::query(
+                    "INSERT INTO LoyaltyPoints VALUES (?1, ?2)
+                    ON CONFLICT(client_id) DO UPDATE SET points = points + ?2",
+                )
+                .bind(transaction.client_id)
+                .bind(points)
+                .execute(storage.connection())
+                .await?;
+                Ok(true)
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_handler() {
+        let svc = TransactionService::builder()
+            .handler("loyalty", LoyaltyPoints)
+            .build()
+            .await
+            .unwrap();
+        let transaction = |id, transaction_type: &str, client_id, amount| Transaction {
+            id,
+            transaction_type: TransactionType::from_str(transaction_type).unwrap(),
+            client_id,
+            amount,
+            reference: None,
+            reason_code: None,
+            notes: None,
+            sequence: None,
+        };
+
+        let mut outcomes = Vec::new();
+        for t in [
+            transaction(1, "deposit", 1, Some(dec!(1))),
+            transaction(2, "loyalty", 1, Some(dec!(20))),
+            transaction(3, "loyalty", 1, Some(dec!(5))),
+            // Unknown clients are ignored by the handler
+            transaction(4, "loyalty", 2, Some(dec!(5))),
+        ] {
+            outcomes.push(svc.process_transaction(&t).await.unwrap());
+        }
+        assert_eq!(
+            outcomes,
+            [
+                TransactionOutcome::Deposit,
+                TransactionOutcome::Custom,
+                TransactionOutcome::Custom,
+                TransactionOutcome::Ignored {
+                    reason: IgnoreReason::Declined
+                }
+            ]
+        );
+        let (points,): (i64,) =
+            sqlx::query_as("SELECT points FROM LoyaltyPoints WHERE client_id = 1")
+                .fetch_one(&svc.pool)
+                .await
+                .unwrap();
+        assert_eq!(points, 25);
+
+        // Handler errors roll back the transaction like any other error
+        assert!(matches!(
+            svc.process_transaction(&transaction(5, "loyalty", 1, Some(dec!(-1))))
+                .await,
+            Err(TransactionError::InvalidTransaction {
+                transaction_id: 5,
+                ..
+            })
+        ));
+        assert!(matches!(
+            svc.process_transaction(&transaction(6, "cashback", 1, Some(dec!(1))))
+                .await,
+            Err(TransactionError::InvalidTransaction {
+                transaction_id: 6,
+                ..
+            })
+        ));
+
+        assert!(matches!(
+            TransactionService::builder()
+                .handler("deposit", LoyaltyPoints)
+                .build()
+                .await,
+            Err(TransactionError::InvalidArgument(_))
+        ));
+    }
+
+    /// Awards a loyalty point, then fails with a database error.
+    struct BrokenHandler;
+
+    impl TransactionHandler for BrokenHandler {
+        fn schema(&self) -> &str {
+            LoyaltyPoints.schema()
+        }
+
+        fn apply<'a>(
+            &'a self,
+            storage: &'a mut StorageHandle<'_>,
+            transaction: &'a Transaction,
+        ) -> BoxFuture<'a, crate::Result<bool>> {
+            Box::pin(async move {
+                sqlx::query("INSERT INTO LoyaltyPoints VALUES (?, 1)")
+                    .bind(transaction.client_id)
+                    .execute(storage.connection())
+                    .await?;
+                sqlx::query("SELECT * FROM Missing")
+                    .execute(storage.connection())
+                    .await?;
+                Ok(true)
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_isolate_failures() {
+        let transaction = |id, transaction_type: &str| Transaction {
+            id,
+            transaction_type: TransactionType::from_str(transaction_type).unwrap(),
+            client_id: 1,
+            amount: Some(dec!(5)),
+            reference: None,
+            reason_code: None,
+            notes: None,
+            sequence: None,
+        };
+        let transactions = [
+            transaction(1, "deposit"),
+            transaction(2, "broken"),
+            transaction(3, "deposit"),
+        ];
+
+        // The whole batch is rolled back by default
+        let svc = TransactionService::builder()
+            .handler("broken", BrokenHandler)
+            .build()
+            .await
+            .unwrap();
+        assert!(matches!(
+            svc.process_stream(futures::stream::iter(transactions.clone().map(Ok)))
+                .await,
+            Err(TransactionError::Database(_))
+        ));
+        assert_eq!(svc.get_client(1).await.unwrap(), None);
+
+        let svc = TransactionService::builder()
+            .handler("broken", BrokenHandler)
+            .isolate_failures(true)
+            .build()
+            .await
+            .unwrap();
+        let outcomes = svc
+            .process_transactions_once("key", &transactions)
+            .await
+            .unwrap();
+        assert_eq!(
+            outcomes,
+            [
+                TransactionOutcome::Deposit,
+                TransactionOutcome::Rejected {
+                    reason: TransactionOutcome::FAILED.into()
+                },
+                TransactionOutcome::Deposit,
+            ]
+        );
+        assert_eq!(
+            svc.get_client(1).await.unwrap().unwrap().available,
+            dec!(10)
+        );
+        // Only the failed transaction's own writes were rolled back
+        let (points,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM LoyaltyPoints")
+            .fetch_one(&svc.pool)
+            .await
+            .unwrap();
+        assert_eq!(points, 0);
+        assert!(svc.get_transaction(2).await.unwrap().is_none());
+
+        // Invalid transactions still stop the batch
+        let invalid = Transaction {
+            amount: None,
+            ..transaction(4, "deposit")
+        };
+        assert!(matches!(
+            svc.process_stream(futures::stream::iter([Ok(invalid)]))
+                .await,
+            Err(TransactionError::InvalidTransaction {
+                transaction_id: 4,
+                ..
+            })
+        ));
+    }
+
+    /// Rejects withdrawals over 100 and annotates deposits to new clients.
+    struct LimitValidator;
+
+    impl TransactionValidator for LimitValidator {
+        fn validate(
+            &self,
+            transaction: &Transaction,
+            client: Option<&Client>,
+        ) -> crate::Result<Verdict> {
+            if transaction.transaction_type == TransactionType::Withdrawal
+                && transaction.amount > Some(dec!(100))
+            {
+                return Ok(Verdict::Reject {
+                    reason: "over 100".into(),
+                });
+            }
+            if client.is_none() {
+                return Ok(Verdict::Accept {
+                    annotations: vec!["new client".into()],
+                    risk_score_change: 0,
+                });
+            }
+            Ok(Verdict::accept())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_validator() {
+        let svc = TransactionService::builder()
+            .validator(LimitValidator)
+            .build()
+            .await
+            .unwrap();
+        let summary = svc
+            .process_stream(futures::stream::iter(
+                [
+                    (TransactionType::Deposit, 1, Some(dec!(500))),
+                    (TransactionType::Withdrawal, 2, Some(dec!(150))),
+                    (TransactionType::Withdrawal, 3, Some(dec!(50))),
+                ]
+                .map(|(transaction_type, id, amount)| {
+                    Ok(Transaction {
+                        id,
+                        transaction_type,
+                        client_id: 1,
+                        amount,
+                        reference: None,
+                        reason_code: None,
+                        notes: None,
+                        sequence: None,
+                    })
+                }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(
+            summary,
+            ProcessingOutcome {
+                processed: 3,
+                applied: 2,
+                rejected: 1,
+                ignored: 0,
+                insufficient_funds: 0,
+            }
+        );
+        assert_eq!(
+            svc.get_client(1).await.unwrap().unwrap().available,
+            dec!(450)
+        );
+        // The rejected withdrawal is not recorded
+        assert_eq!(svc.get_transaction(2).await.unwrap(), None);
+        assert_eq!(
+            svc.get_annotations(1).await.unwrap(),
+            [Annotation {
+                transaction_type: "deposit".into(),
+                annotation: "new client".into(),
+            }]
+        );
+        assert_eq!(svc.get_annotations(3).await.unwrap(), []);
+    }
+
+    #[cfg(feature = "wide-ids")]
+    #[tokio::test]
+    async fn test_wide_ids() {
+        let svc = create_service().await;
+        let csv = format!(
+            "type, client, tx, amount
+            deposit, 70000, 5000000000, 5.0
+            deposit, 70000, {max}, 2.0
+            dispute, 70000, {max},",
+            max = u64::MAX
+        );
+        let mut reader = crate::TransactionReader::new(csv.as_bytes());
+        svc.process_stream(futures::stream::iter(reader.transactions()))
+            .await
+            .unwrap();
+
+        let client = svc.get_client(70000).await.unwrap().unwrap();
+        assert_eq!((client.total, client.held), (dec!(7), dec!(2)));
+        let transaction = svc.get_transaction(u64::MAX).await.unwrap().unwrap();
+        assert_eq!(transaction.id, u64::MAX);
+        let ids: Vec<_> = svc
+            .get_client_transactions(70000, &TransactionFilter::default(), Pagination::default())
+            .map_ok(|t| t.id)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(ids, [5_000_000_000, u64::MAX]);
+    }
+
+    #[cfg(feature = "wide-ids")]
+    #[tokio::test]
+    async fn test_wide_ids_pagination() {
+        let svc = create_service().await;
+        let boundary = 1 << 63;
+        let ids = [
+            0,
+            1,
+            boundary - 2,
+            boundary - 1,
+            boundary,
+            boundary + 1,
+            u64::MAX,
+        ];
+        for id in ids.into_iter().rev() {
+            svc.process_transaction(&Transaction {
+                transaction_type: TransactionType::Deposit,
+                client_id: 1,
+                id,
+                amount: Some(dec!(1.0)),
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+            .await
+            .unwrap();
+        }
+
+        let mut pages = Vec::new();
+        let mut after = None;
+        loop {
+            let page: Vec<_> = svc
+                .search_transactions(
+                    &TransactionFilter::default(),
+                    Pagination {
+                        after,
+                        limit: Some(2),
+                    },
+                )
+                .map_ok(|t| t.id)
+                .try_collect()
+                .await
+                .unwrap();
+            let Some(&last) = page.last() else { break };
+            after = Some(last);
+            pages.push(page);
+        }
+        assert_eq!(
+            pages,
+            [
+                vec![0, 1],
+                vec![boundary - 2, boundary - 1],
+                vec![boundary, boundary + 1],
+                vec![u64::MAX],
+            ]
+        );
+    }
+}
@@ -0,0 +1,116 @@
+use super::super::{ChargebackEscalation, ClientId, ClientRisk, LedgerEvent, Result, RiskLevel};
+use super::TransactionService;
+use sqlx::{sqlite::Sqlite, Executor};
+
+impl TransactionService {
+    pub(super) async fn fetch_risk<'e>(
+        executor: impl Executor<'e, Database = Sqlite>,
+        client_id: ClientId,
+    ) -> Result<Option<ClientRisk>> {
+        sqlx::query_as::<_, (ClientId, String, i64, i64)>(
+            "SELECT client_id, level, chargebacks, escalated_at FROM ClientRisks
+            WHERE client_id=?",
+        )
+        .bind(client_id)
+        .fetch_optional(executor)
+        .await?
+        .map(Self::into_risk)
+        .transpose()
+    }
+
+    /// The client's [`ClientStats::risk_score`](super::super::ClientStats::risk_score), 0 for a
+    /// client that does not exist.
+    pub(super) async fn fetch_risk_score<'e>(
+        executor: impl Executor<'e, Database = Sqlite>,
+        client_id: ClientId,
+    ) -> Result<i64> {
+        let score: Option<i64> = sqlx::query_scalar("SELECT risk_score FROM Clients WHERE id=?")
+            .bind(client_id)
+            .fetch_optional(executor)
+            .await?;
+        Ok(score.unwrap_or_default())
+    }
+
+    pub(super) fn into_risk(
+        (client, level, chargebacks, escalated_at): (ClientId, String, i64, i64),
+    ) -> Result<ClientRisk> {
+        Ok(ClientRisk {
+            client,
+            level: RiskLevel::parse(&level)?,
+            chargebacks: u64::try_from(chargebacks).unwrap_or_default(),
+            escalated_at,
+        })
+    }
+
+    pub(super) async fn insert_risk<'e>(
+        executor: impl Executor<'e, Database = Sqlite>,
+        risk: &ClientRisk,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO ClientRisks (client_id, level, chargebacks, escalated_at)
+            VALUES (?, ?, ?, ?)",
+        )
+        .bind(risk.client)
+        .bind(risk.level.to_str())
+        .bind(i64::try_from(risk.chargebacks).unwrap_or(i64::MAX))
+        .bind(risk.escalated_at)
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    /// Gets the risk level a client's chargebacks escalated to, if they did, see
+    /// [`ChargebackEscalation`].
+    pub async fn get_client_risk(&self, client_id: ClientId) -> Result<Option<ClientRisk>> {
+        Self::fetch_risk(self.read_pool(), client_id).await
+    }
+
+    /// Raises the risk level of a client that was charged back, when its chargebacks reached
+    /// a threshold of the [`ChargebackEscalation`] of its policy.
+    pub(super) async fn escalate_chargebacks(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        client_id: ClientId,
+    ) -> Result<()> {
+        let escalation = &self
+            .client_policy(&mut *tx, client_id)
+            .await?
+            .chargeback_escalation;
+        if *escalation == ChargebackEscalation::default() {
+            return Ok(());
+        }
+        let (chargebacks,): (i64,) = sqlx::query_as("SELECT chargebacks FROM Clients WHERE id=?")
+            .bind(client_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        let chargebacks = u64::try_from(chargebacks).unwrap_or_default();
+        let Some(level) = escalation.level(chargebacks) else {
+            return Ok(());
+        };
+        if Self::fetch_risk(&mut *tx, client_id)
+            .await?
+            .is_some_and(|risk| risk.level >= level)
+        {
+            return Ok(());
+        }
+
+        let risk = ClientRisk {
+            client: client_id,
+            level,
+            chargebacks,
+            escalated_at: self.clock.unix_millis(),
+        };
+        Self::insert_risk(&mut *tx, &risk).await?;
+        if self.records_events() {
+            self.record_event(tx, client_id, &LedgerEvent::RiskEscalated { risk })
+                .await?;
+        }
+        tracing::warn!(
+            client = %self.client_label(client_id),
+            level = level.to_str(),
+            chargebacks,
+            "Escalated the client's risk level"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,202 @@
+use super::super::close::{parse_business_date, ClosedEvent};
+use super::super::{
+    BalanceSnapshot, Client, DayClose, Result, TransactionError, TransactionType, TypeTotal,
+};
+use super::{ClientDb, TransactionService};
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+
+impl TransactionService {
+    /// Records every client's balances in the `BalanceSnapshots` table, so
+    /// [`TransactionService::get_clients_as_of`] and
+    /// [`TransactionService::get_client_as_of`] start from the snapshot instead of reading the
+    /// whole audit log. When nothing was recorded in the audit log since the last snapshot, it
+    /// is returned instead of taking a new one.
+    ///
+    /// Needs the audit log, usually run periodically by
+    /// [`Job::SnapshotBalances`](super::super::Job::SnapshotBalances).
+    #[tracing::instrument(skip(self))]
+    pub async fn snapshot_balances(&self) -> Result<BalanceSnapshot> {
+        self.require_audit_log()?;
+        let (_write, mut tx) = self.begin_write().await?;
+        let (checkpoint,): (i64,) = sqlx::query_as("SELECT IFNULL(MAX(id), 0) FROM AuditLog")
+            .fetch_one(&mut *tx)
+            .await?;
+        let unchanged: Option<(i64, i64)> = sqlx::query_as(
+            "SELECT taken_at, COUNT(*) FROM BalanceSnapshots WHERE checkpoint = ? GROUP BY taken_at",
+        )
+        .bind(checkpoint)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if let Some((taken_at, clients)) = unchanged {
+            return Ok(BalanceSnapshot {
+                taken_at,
+                checkpoint,
+                clients: clients as u64,
+            });
+        }
+
+        let taken_at = self.clock.unix_millis();
+        let clients = sqlx::query(
+            "INSERT INTO BalanceSnapshots (taken_at, checkpoint, client_id, available, held, locked)
+            SELECT ?, ?, id, available, held, locked FROM Clients",
+        )
+        .bind(taken_at)
+        .bind(checkpoint)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        self.commit(tx).await?;
+        tracing::info!(checkpoint, clients, "Snapshotted balances");
+        Ok(BalanceSnapshot {
+            taken_at,
+            checkpoint,
+            clients,
+        })
+    }
+
+    /// The checkpoint of the last balance snapshot taken at or before `timestamp`, 0 if there
+    /// is none.
+    pub(super) async fn snapshot_checkpoint(&self, timestamp: i64) -> Result<i64> {
+        let (checkpoint,): (i64,) = sqlx::query_as(
+            "SELECT IFNULL(MAX(checkpoint), 0) FROM BalanceSnapshots WHERE taken_at <= ?",
+        )
+        .bind(timestamp)
+        .fetch_one(self.read_pool())
+        .await?;
+        Ok(checkpoint)
+    }
+
+    /// Closes `business_date`, recording every client's balances and the totals of the changes
+    /// made since the previous close, and moves the checkpoint to the end of the audit log.
+    /// Changes made after the close count towards the next date.
+    ///
+    /// Business dates must be closed in order, closing a date that is not after the last
+    /// closed one fails. Needs the audit log.
+    #[tracing::instrument(skip(self), fields(date = %business_date))]
+    pub async fn close_day(&self, business_date: time::Date) -> Result<DayClose> {
+        self.require_audit_log()?;
+        let (_write, mut tx) = self.begin_write().await?;
+
+        let previous: Option<(String, i64)> = sqlx::query_as(
+            "SELECT business_date, checkpoint FROM DayCloses ORDER BY rowid DESC LIMIT 1",
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        let previous_checkpoint = match previous {
+            Some((date, checkpoint)) => {
+                let date = parse_business_date(&date)?;
+                if business_date <= date {
+                    return Err(TransactionError::InvalidArgument(format!(
+                        "Business date {} is not after the last closed date {}",
+                        business_date, date
+                    )));
+                }
+                checkpoint
+            }
+            None => 0,
+        };
+
+        let records: Vec<(i64, String)> =
+            sqlx::query_as("SELECT id, event FROM AuditLog WHERE id > ? ORDER BY id")
+                .bind(previous_checkpoint)
+                .fetch_all(&mut *tx)
+                .await?;
+        let mut totals = BTreeMap::<String, (u64, Decimal)>::new();
+        for (_, event) in &records {
+            let event: ClosedEvent = serde_json::from_str(event)?;
+            let (name, amount) = match event {
+                ClosedEvent {
+                    disputed: Some(disputed),
+                    action: Some(action),
+                    ..
+                } => (format!("expired_{}", action.to_str()), disputed.amount),
+                ClosedEvent {
+                    transaction: Some(transaction),
+                    ..
+                } => {
+                    let amount = match transaction.transaction_type {
+                        TransactionType::Dispute
+                        | TransactionType::Resolve
+                        | TransactionType::Chargeback => {
+                            Self::fetch_transaction(&mut *tx, transaction.id)
+                                .await?
+                                .map(|t| t.into_transaction(self.precision))
+                                .transpose()?
+                                .and_then(|t| t.amount)
+                        }
+                        _ => transaction.amount,
+                    };
+                    (transaction.transaction_type.to_str().to_string(), amount)
+                }
+                ClosedEvent {
+                    adjustment: Some(adjustment),
+                    ..
+                } => ("adjustment".to_string(), Some(adjustment.amount)),
+                _ => continue,
+            };
+            let total = totals.entry(name).or_default();
+            total.0 += 1;
+            total.1 += amount.unwrap_or_default();
+        }
+        let totals: Vec<TypeTotal> = totals
+            .into_iter()
+            .map(|(name, (count, amount))| TypeTotal {
+                name,
+                count,
+                amount,
+            })
+            .collect();
+
+        let clients: Vec<Client> = sqlx::query_as::<_, ClientDb>(
+            "SELECT *, (held+available) as total FROM Clients ORDER BY id",
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|c| c.into_client(self.precision))
+        .collect();
+
+        let close = DayClose {
+            business_date,
+            closed_at: self.clock.unix_millis(),
+            checkpoint: records.last().map_or(previous_checkpoint, |(id, _)| *id),
+            clients,
+            totals,
+        };
+        sqlx::query(
+            "INSERT INTO DayCloses (business_date, closed_at, checkpoint, clients, totals)
+            VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(close.business_date.to_string())
+        .bind(close.closed_at)
+        .bind(close.checkpoint)
+        .bind(serde_json::to_string(&close.clients)?)
+        .bind(serde_json::to_string(&close.totals)?)
+        .execute(&mut *tx)
+        .await?;
+        self.commit(tx).await?;
+        tracing::info!(checkpoint = close.checkpoint, "Closed business date");
+        Ok(close)
+    }
+
+    /// Gets a business date closed with [`TransactionService::close_day`].
+    pub async fn get_day_close(&self, business_date: time::Date) -> Result<Option<DayClose>> {
+        let row: Option<(i64, i64, String, String)> = sqlx::query_as(
+            "SELECT closed_at, checkpoint, clients, totals FROM DayCloses WHERE business_date = ?",
+        )
+        .bind(business_date.to_string())
+        .fetch_optional(self.read_pool())
+        .await?;
+        row.map(|(closed_at, checkpoint, clients, totals)| {
+            Ok(DayClose {
+                business_date,
+                closed_at,
+                checkpoint,
+                clients: serde_json::from_str(&clients)?,
+                totals: serde_json::from_str(&totals)?,
+            })
+        })
+        .transpose()
+    }
+}
@@ -0,0 +1,184 @@
+use super::super::{LedgerEvent, Result, Transaction, TransactionError, TransactionId, Void};
+use super::{db_id, DBTransaction, Precision, TransactionService, CASH};
+use sqlx::{sqlite::Sqlite, Executor};
+
+impl TransactionService {
+    /// Whether the deposit `transaction_id` is still held by the
+    /// [`SettlementDelay`](super::super::SettlementDelay), waiting to clear.
+    pub(super) async fn is_clearing<'e>(
+        executor: impl Executor<'e, Database = Sqlite>,
+        transaction_id: TransactionId,
+    ) -> Result<bool> {
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM Settlements WHERE transaction_id=?)")
+            .bind(db_id(transaction_id))
+            .fetch_one(executor)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Moves the deposits held by the [`SettlementDelay`](super::super::SettlementDelay) whose
+    /// clearing period has passed to their client's available funds, returning the released
+    /// deposits. Deposits under dispute are released once the dispute is resolved.
+    ///
+    /// Each release is recorded as a [`LedgerEvent::SettlementReleased`].
+    #[tracing::instrument(skip_all)]
+    pub async fn release_settlements(&self) -> Result<Vec<Transaction>> {
+        let now = self.clock.unix_millis();
+        let (_write, mut tx) = self.begin_write().await?;
+
+        let deposits: Vec<DBTransaction> = sqlx::query_as(
+            "SELECT t.* FROM [Settlements] s
+            INNER JOIN [Transactions] t ON t.id = s.transaction_id
+            WHERE s.release_at <= ?
+                AND NOT EXISTS (SELECT 1 FROM Disputes d WHERE d.transaction_id = s.transaction_id)
+            ORDER BY s.release_at, s.transaction_id",
+        )
+        .bind(now)
+        .fetch_all(&mut *tx)
+        .await?;
+        let mut released = Vec::with_capacity(deposits.len());
+        for deposit in deposits {
+            let deposit = deposit.into_transaction(self.precision)?;
+            let amount = deposit
+                .amount
+                .and_then(|a| self.precision.to_storage(a))
+                .ok_or_else(|| TransactionError::invalid(deposit.id, "No amount in deposit"))?;
+            sqlx::query(
+                "UPDATE Clients SET held = (held - ?), available = (available + ?) WHERE id=?",
+            )
+            .bind(amount)
+            .bind(amount)
+            .bind(deposit.client_id)
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query("DELETE FROM Settlements WHERE transaction_id=?")
+                .bind(db_id(deposit.id))
+                .execute(&mut *tx)
+                .await?;
+            if self.records_events() {
+                let client = self
+                    .fetch_updated_client(&mut *tx, deposit.client_id)
+                    .await?;
+                let event = LedgerEvent::SettlementReleased {
+                    deposit: deposit.clone(),
+                    client,
+                };
+                self.record_event(&mut tx, deposit.client_id, &event)
+                    .await?;
+            }
+            tracing::info!(
+                tx = deposit.id,
+                client = %self.client_label(deposit.client_id),
+                "Deposit cleared"
+            );
+            released.push(deposit);
+        }
+        self.commit(tx).await?;
+        Ok(released)
+    }
+
+    /// Voids a deposit held by the [`SettlementDelay`](super::super::SettlementDelay) before it
+    /// clears, e.g. when the bank returns it: its amount is removed from the client's held
+    /// funds without ever becoming available. The `operator` and `reason` are stored with the
+    /// [`Void`] and recorded in a [`LedgerEvent::DepositVoided`]. A voided deposit can not be
+    /// disputed.
+    ///
+    /// Fails if `operator` or `reason` is empty, or if the transaction is not a deposit waiting
+    /// to clear, or is under dispute.
+    #[tracing::instrument(skip_all, fields(tx = transaction_id, operator = operator))]
+    pub async fn void_deposit(
+        &self,
+        transaction_id: TransactionId,
+        reason: &str,
+        operator: &str,
+    ) -> Result<Void> {
+        if operator.trim().is_empty() || reason.trim().is_empty() {
+            return Err(TransactionError::InvalidArgument(
+                "A void requires an operator and a reason".into(),
+            ));
+        }
+        let _client = self.lock_owner(transaction_id).await?;
+        let (_write, mut tx) = self.begin_write().await?;
+
+        if !Self::is_clearing(&mut *tx, transaction_id).await? {
+            return Err(TransactionError::invalid(
+                transaction_id,
+                "Not a deposit waiting to clear",
+            ));
+        }
+        if Self::fetch_dispute(&mut *tx, transaction_id)
+            .await?
+            .is_some()
+        {
+            return Err(TransactionError::invalid(
+                transaction_id,
+                "The deposit is under dispute",
+            ));
+        }
+        let deposit = Self::fetch_transaction(&mut *tx, transaction_id)
+            .await?
+            .ok_or_else(|| TransactionError::invalid(transaction_id, "No deposit"))?
+            .into_transaction(self.precision)?;
+        let amount = deposit
+            .amount
+            .and_then(|a| self.precision.to_storage(a))
+            .ok_or_else(|| TransactionError::invalid(transaction_id, "No amount in deposit"))?;
+
+        sqlx::query("UPDATE Clients SET held = held - ? WHERE id=?")
+            .bind(amount)
+            .bind(deposit.client_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM Settlements WHERE transaction_id=?")
+            .bind(db_id(transaction_id))
+            .execute(&mut *tx)
+            .await?;
+        Self::book(&mut *tx, CASH, -amount).await?;
+        let void = Void {
+            transaction_id,
+            client_id: deposit.client_id,
+            amount: self.precision.to_decimal(amount),
+            reason: reason.to_string(),
+            operator: operator.to_string(),
+            voided_at: self.clock.unix_millis(),
+        };
+        Self::insert_void(&mut *tx, &void, self.precision).await?;
+        if self.records_events() {
+            let client = self
+                .fetch_updated_client(&mut *tx, deposit.client_id)
+                .await?;
+            let event = LedgerEvent::DepositVoided {
+                void: void.clone(),
+                client,
+            };
+            self.record_event(&mut tx, deposit.client_id, &event)
+                .await?;
+        }
+        self.commit(tx).await?;
+        tracing::info!(
+            client = %self.client_label(deposit.client_id),
+            "Deposit voided"
+        );
+        Ok(void)
+    }
+
+    pub(super) async fn insert_void<'e>(
+        executor: impl Executor<'e, Database = Sqlite>,
+        void: &Void,
+        precision: Precision,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO Voids (transaction_id, client_id, amount, reason, operator, voided_at)
+            VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(db_id(void.transaction_id))
+        .bind(void.client_id)
+        .bind(precision.to_storage(void.amount))
+        .bind(&void.reason)
+        .bind(&void.operator)
+        .bind(void.voided_at)
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,42 @@
+use super::Result;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use std::fmt::Write;
+use std::io;
+
+/// An input file, or other batch of transactions, started with
+/// [`TransactionService::begin_batch`](super::TransactionService::begin_batch).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, FromRow)]
+pub struct Batch {
+    pub id: i64,
+    /// Where the transactions were read from, e.g. the file name.
+    pub source: String,
+    /// The hex sha256 of the source's contents, when it is a file.
+    pub sha256: Option<String>,
+    /// When the batch was started, in milliseconds since the unix epoch, from the service's
+    /// [`Clock`](super::Clock).
+    pub created_at: i64,
+}
+
+/// Where a stored transaction came from, see
+/// [`TransactionService::get_provenance`](super::TransactionService::get_provenance).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Provenance {
+    pub batch: Batch,
+    /// The line of the source the transaction was read from.
+    pub line: u64,
+}
+
+/// The hex sha256 of everything read from `reader`, to record with a [`Batch`].
+pub fn content_sha256(mut reader: impl io::Read) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut reader, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .fold(String::new(), |mut hash, byte| {
+            let _ = write!(hash, "{:02x}", byte);
+            hash
+        }))
+}
@@ -0,0 +1,71 @@
+use super::{ClientId, Result};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The number of bytes of the HMAC kept in a token.
+const TOKEN_BYTES: usize = 8;
+
+/// Replaces client ids with tokens derived from a secret key, set with
+/// [`TransactionServiceBuilder::pseudonymizer`](super::TransactionServiceBuilder::pseudonymizer).
+///
+/// A token is the first 8 bytes of the HMAC-SHA256 of the value, as hex. The same key always
+/// gives the same token for a value, so pseudonymized reports can still be joined with each
+/// other, but the real value can not be recovered without the key.
+#[derive(Clone)]
+pub struct Pseudonymizer {
+    key: Arc<[u8]>,
+}
+
+impl Pseudonymizer {
+    pub fn new(key: impl AsRef<[u8]>) -> Self {
+        Self {
+            key: key.as_ref().into(),
+        }
+    }
+
+    /// Reads the key from `path`, ignoring surrounding whitespace.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::new(std::fs::read(path)?.trim_ascii()))
+    }
+
+    /// The token for a client id.
+    pub fn client(&self, client_id: ClientId) -> String {
+        self.token("client", &client_id.to_string())
+    }
+
+    /// The token for `value`. `kind` separates the tokens of different kinds of values, so
+    /// equal values of different kinds get different tokens.
+    pub fn token(&self, kind: &str, value: &str) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(kind.as_bytes());
+        mac.update(b":");
+        mac.update(value.as_bytes());
+        mac.finalize().into_bytes()[..TOKEN_BYTES]
+            .iter()
+            .fold(String::new(), |mut token, byte| {
+                let _ = write!(token, "{:02x}", byte);
+                token
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pseudonymizer;
+
+    #[test]
+    fn test_pseudonymizer() {
+        let pseudonymizer = Pseudonymizer::new("secret");
+        let token = pseudonymizer.client(1);
+        assert_eq!(token.len(), 16);
+        assert!(token.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(Pseudonymizer::new("secret").client(1), token);
+        assert_ne!(pseudonymizer.client(2), token);
+        assert_ne!(Pseudonymizer::new("other").client(1), token);
+        assert_ne!(pseudonymizer.token("tx", "1"), token);
+    }
+}
@@ -0,0 +1,113 @@
+use super::{ClientId, TransactionType};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+/// Keyset pagination for list queries. Results are ordered by id and only ids after `after`
+/// are returned, so the last id of a page is used as `after` for the next page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pagination<K> {
+    /// Only return items with an id greater than this.
+    pub after: Option<K>,
+    /// The maximum number of items to return. `None` returns every item.
+    pub limit: Option<u32>,
+}
+
+impl<K> Default for Pagination<K> {
+    fn default() -> Self {
+        Self {
+            after: None,
+            limit: None,
+        }
+    }
+}
+
+impl<K> Pagination<K> {
+    /// The first `limit` items.
+    pub fn first(limit: u32) -> Self {
+        Self {
+            after: None,
+            limit: Some(limit),
+        }
+    }
+
+    /// The `limit` items after `after`.
+    pub fn after(after: K, limit: u32) -> Self {
+        Self {
+            after: Some(after),
+            limit: Some(limit),
+        }
+    }
+
+    /// The limit as a sqlite `LIMIT` value, where -1 is unlimited.
+    pub(crate) fn sql_limit(&self) -> i64 {
+        self.limit.map_or(-1, i64::from)
+    }
+}
+
+/// Filters for listing stored transactions.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TransactionFilter {
+    /// Only return transactions made by this client.
+    pub client_id: Option<ClientId>,
+    /// Only return transactions of this type.
+    pub transaction_type: Option<TransactionType>,
+    /// Only return transactions that are (or are not) under an open dispute.
+    pub disputed: Option<bool>,
+    /// Only return transactions of at least this amount.
+    pub min_amount: Option<Decimal>,
+    /// Only return transactions of at most this amount.
+    pub max_amount: Option<Decimal>,
+    /// Only return transactions applied at or after this moment, in milliseconds since the
+    /// unix epoch.
+    pub from: Option<i64>,
+    /// Only return transactions applied before this moment, in milliseconds since the unix
+    /// epoch. Transactions applied before versions that record the time match neither bound.
+    pub until: Option<i64>,
+    /// Only return transactions of clients with this tag.
+    pub tag: Option<String>,
+}
+
+/// What [`TransactionService::get_aggregates`](super::TransactionService::get_aggregates)
+/// groups transactions by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupBy {
+    /// The [`TransactionType`] name.
+    #[serde(rename = "type")]
+    TransactionType,
+    /// The client id.
+    Client,
+    /// The UTC date the transaction was applied, e.g. `2024-01-31`.
+    Day,
+}
+
+/// The count and sum of a group of stored transactions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransactionAggregate {
+    /// The type name, client id or date the transactions share. No date for transactions
+    /// applied before the time was recorded.
+    pub group: Option<String>,
+    /// The number of transactions.
+    pub count: u64,
+    /// The sum of their amounts.
+    pub amount: Decimal,
+}
+
+/// Filters for listing clients.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClientFilter {
+    /// Only return locked clients.
+    pub locked_only: bool,
+    /// Only return clients with a total balance of at least this amount.
+    pub min_total: Option<Decimal>,
+    /// Only return clients with a total balance of at most this amount.
+    pub max_total: Option<Decimal>,
+    /// Only return clients with this tag.
+    pub tag: Option<String>,
+    /// Only return clients with a [`ClientStats::risk_score`](super::ClientStats::risk_score)
+    /// of at least this.
+    pub min_risk_score: Option<i64>,
+    /// Only return clients whose balances were changed by the transactions of this
+    /// [`Batch`](super::Batch).
+    pub changed_in_batch: Option<i64>,
+}
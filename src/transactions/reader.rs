@@ -1,30 +1,367 @@
-use super::Transaction;
+use super::{ClientId, ExternalIds, Result, Transaction, TransactionId, TransactionType};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::collections::VecDeque;
 use std::io;
+use std::sync::{Arc, Mutex};
+
+/// Reads [`Transaction`]s from csv input with a `type, client, tx, amount` header, and
+/// optionally `reference`, `reason_code`, `notes` and `sequence` columns.
 pub struct TransactionReader<R: io::Read> {
-    reader: csv::Reader<R>,
+    reader: csv::Reader<LineIndex<R>>,
+    line_starts: LineStarts,
+    external_ids: Option<ExternalIds>,
+    number_locale: NumberLocale,
 }
 
 impl<R: io::Read> TransactionReader<R> {
+    /// Creates a reader over csv input. Whitespace around fields is trimmed and the
+    /// `amount` column may be omitted for disputes, resolves and chargebacks.
     pub fn new(reader: R) -> Self {
+        let line_starts = LineStarts::default();
         let reader = csv::ReaderBuilder::new()
             .has_headers(true)
             .flexible(true)
             .trim(csv::Trim::All)
-            .from_reader(reader);
-        Self { reader }
+            .from_reader(LineIndex::new(reader, line_starts.clone()));
+        Self {
+            reader,
+            line_starts,
+            external_ids: None,
+            number_locale: NumberLocale::default(),
+        }
+    }
+
+    /// Reads the `client` column as partner-specific string ids, resolved to client ids with
+    /// `external_ids`.
+    pub fn external_ids(mut self, external_ids: ExternalIds) -> Self {
+        self.external_ids = Some(external_ids);
+        self
+    }
+
+    /// Reads the `amount` column in `number_locale`, plain `1234.56` amounts by default.
+    pub fn number_locale(mut self, number_locale: NumberLocale) -> Self {
+        self.number_locale = number_locale;
+        self
+    }
+
+    /// Iterates over the remaining transactions in the input.
+    pub fn transactions(&mut self) -> impl Iterator<Item = Result<Transaction>> + '_ {
+        self.transactions_with_lines()
+            .map(|t| t.map(|(_, transaction)| transaction))
+    }
+
+    /// Iterates over the remaining transactions in the input with the line each starts on.
+    pub fn transactions_with_lines(
+        &mut self,
+    ) -> impl Iterator<Item = Result<(u64, Transaction)>> + '_ {
+        let headers = self.reader.headers().cloned().ok();
+        let line_starts = &self.line_starts;
+        let column = |name| {
+            headers
+                .as_ref()
+                .and_then(|h| h.iter().position(|column| column == name))
+        };
+        let external_ids = self.external_ids.as_ref().zip(column("client"));
+        let number_locale = self.number_locale;
+        let amount_column = column("amount").filter(|_| number_locale != NumberLocale::Plain);
+        self.reader.records().map(move |record| {
+            let mut record = record?;
+            let line = record
+                .position()
+                .map_or(0, |p| line_starts.line_at(p.byte()));
+            if external_ids.is_some() || amount_column.is_some() {
+                record = record
+                    .iter()
+                    .enumerate()
+                    .map(|(i, field)| match external_ids {
+                        Some((ids, column)) if i == column => {
+                            ids.resolve(field).map(|id| id.to_string())
+                        }
+                        _ if Some(i) == amount_column => {
+                            Ok(number_locale.normalize(field).into_owned())
+                        }
+                        _ => Ok(field.to_string()),
+                    })
+                    .collect::<Result<_>>()?;
+            }
+            Ok((line, record.deserialize(headers.as_ref())?))
+        })
+    }
+}
+
+/// The byte offset and number of every line with content that has been read but not yet
+/// looked up.
+#[derive(Clone, Default)]
+struct LineStarts(Arc<Mutex<VecDeque<(u64, u64)>>>);
+
+impl LineStarts {
+    /// The line of the first content at or after `byte`.
+    ///
+    /// The csv reader's record positions start after the previous record, before any blank
+    /// lines it skipped and, for `\r\n` line endings, before the `\n`, so they can not be used
+    /// as is. Lines before `byte` are forgotten, as records are read in order.
+    fn line_at(&self, byte: u64) -> u64 {
+        let mut starts = self.0.lock().unwrap();
+        while let Some(&(offset, line)) = starts.front() {
+            if offset >= byte {
+                return line;
+            }
+            starts.pop_front();
+        }
+        0
+    }
+}
+
+/// Records where each line with content starts as the input is read.
+struct LineIndex<R> {
+    inner: R,
+    offset: u64,
+    line: u64,
+    at_line_start: bool,
+    starts: LineStarts,
+}
+
+impl<R> LineIndex<R> {
+    fn new(inner: R, starts: LineStarts) -> Self {
+        Self {
+            inner,
+            offset: 0,
+            line: 1,
+            at_line_start: true,
+            starts,
+        }
+    }
+}
+
+impl<R: io::Read> io::Read for LineIndex<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        let mut starts = self.starts.0.lock().unwrap();
+        for &byte in &buf[..read] {
+            match byte {
+                b'\n' => {
+                    self.line += 1;
+                    self.at_line_start = true;
+                }
+                b'\r' => {}
+                _ if self.at_line_start => {
+                    starts.push_back((self.offset, self.line));
+                    self.at_line_start = false;
+                }
+                _ => {}
+            }
+            self.offset += 1;
+        }
+        Ok(read)
+    }
+}
+
+/// How amounts are written in an input, see [`TransactionReader::number_locale`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NumberLocale {
+    /// A `.` decimal separator without grouping, e.g. `1234.56`.
+    #[default]
+    Plain,
+    /// A `.` decimal separator with `,`, space or `'` grouping, e.g. `1,234.56` or `1 234.56`.
+    Point,
+    /// A `,` decimal separator with `.`, space or `'` grouping, e.g. `1.234,56` or `1 234,56`.
+    Comma,
+}
+
+impl NumberLocale {
+    /// The decimal separator and grouping separators, for locales other than `Plain`.
+    fn separators(self) -> Option<(char, &'static [char])> {
+        // Including the no-break and narrow no-break spaces some exports group with
+        match self {
+            Self::Plain => None,
+            Self::Point => Some(('.', &[',', ' ', '\'', '\u{a0}', '\u{202f}'])),
+            Self::Comma => Some((',', &['.', ' ', '\'', '\u{a0}', '\u{202f}'])),
+        }
+    }
+
+    /// Rewrites an amount in this locale as a plain amount, e.g. `1.234,56` as `1234.56`.
+    /// Amounts that are not valid in the locale, such as ones with grouping separators after
+    /// the decimal separator or not between digits, are returned unchanged to fail parsing.
+    pub fn normalize(self, amount: &str) -> Cow<'_, str> {
+        let Some((decimal, grouping)) = self.separators() else {
+            return Cow::Borrowed(amount);
+        };
+        let (integer, fraction) = match amount.split_once(decimal) {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (amount, None),
+        };
+        if fraction.is_some_and(|f| f.contains(|c| grouping.contains(&c) || c == decimal)) {
+            return Cow::Borrowed(amount);
+        }
+        let mut normalized = String::with_capacity(amount.len());
+        let mut previous = None;
+        for c in integer.chars() {
+            if grouping.contains(&c) {
+                if !previous.is_some_and(|p: char| p.is_ascii_digit()) {
+                    return Cow::Borrowed(amount);
+                }
+            } else {
+                normalized.push(c);
+            }
+            previous = Some(c);
+        }
+        if previous.is_some_and(|p| grouping.contains(&p)) {
+            return Cow::Borrowed(amount);
+        }
+        if let Some(fraction) = fraction {
+            normalized.push('.');
+            normalized.push_str(fraction);
+        }
+        Cow::Owned(normalized)
+    }
+}
+
+/// Reads [`Transaction`]s from newline delimited json, one object per line with the same
+/// fields as the csv input, e.g. `{"type": "deposit", "client": 1, "tx": 1, "amount": "1.0"}`.
+///
+/// Amounts can be given as strings or numbers. Blank lines are skipped.
+pub struct JsonLinesReader<R: io::BufRead> {
+    lines: io::Lines<R>,
+    external_ids: Option<ExternalIds>,
+    number_locale: NumberLocale,
+}
+
+impl<R: io::BufRead> JsonLinesReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: reader.lines(),
+            external_ids: None,
+            number_locale: NumberLocale::default(),
+        }
+    }
+
+    /// Reads the `client` field as partner-specific string ids, resolved to client ids with
+    /// `external_ids`.
+    pub fn external_ids(mut self, external_ids: ExternalIds) -> Self {
+        self.external_ids = Some(external_ids);
+        self
     }
 
-    pub fn transactions<'a>(
-        &'a mut self,
-    ) -> impl Iterator<Item = Result<Transaction, csv::Error>> + 'a {
-        self.reader.deserialize()
+    /// Reads amounts given as strings in `number_locale`, plain `1234.56` amounts by default.
+    /// Numeric amounts are not affected.
+    pub fn number_locale(mut self, number_locale: NumberLocale) -> Self {
+        self.number_locale = number_locale;
+        self
     }
+
+    /// Iterates over the remaining transactions in the input.
+    pub fn transactions(&mut self) -> impl Iterator<Item = Result<Transaction>> + '_ {
+        self.transactions_with_lines()
+            .map(|t| t.map(|(_, transaction)| transaction))
+    }
+
+    /// Iterates over the remaining transactions in the input with the line each is on.
+    pub fn transactions_with_lines(
+        &mut self,
+    ) -> impl Iterator<Item = Result<(u64, Transaction)>> + '_ {
+        self.lines
+            .by_ref()
+            .zip(1..)
+            .filter(|(line, _)| !matches!(line, Ok(l) if l.trim().is_empty()))
+            .map(|(line, number)| {
+                let line = line?;
+                let transaction = match (&self.external_ids, self.number_locale) {
+                    (None, NumberLocale::Plain) => parse_json_transaction(line.as_bytes())?,
+                    (ids, number_locale) => {
+                        parse_json_value(line.as_bytes(), ids.as_ref(), number_locale)?
+                    }
+                };
+                Ok((number, transaction))
+            })
+    }
+}
+
+/// Parses a json encoded transaction whose `client` may be an external id, as a string or
+/// number, and whose amount may be a string in a [`NumberLocale`].
+fn parse_json_value(
+    json: &[u8],
+    ids: Option<&ExternalIds>,
+    number_locale: NumberLocale,
+) -> Result<Transaction> {
+    let mut value: serde_json::Value = serde_json::from_slice(json)?;
+    if let Some(serde_json::Value::String(amount)) = value.get_mut("amount") {
+        if let Cow::Owned(normalized) = number_locale.normalize(amount) {
+            *amount = normalized;
+        }
+    }
+    if let (Some(ids), Some(client)) = (ids, value.get_mut("client")) {
+        let external_id = match &*client {
+            serde_json::Value::String(id) => Some(id.clone()),
+            serde_json::Value::Number(id) => Some(id.to_string()),
+            // Left for deserialization to reject
+            _ => None,
+        };
+        if let Some(external_id) = external_id {
+            *client = ids.resolve(&external_id)?.into();
+        }
+    }
+    serde_json::from_value::<JsonTransaction>(value)?.into_transaction()
+}
+
+/// A transaction in its json form, which unlike the csv form accepts numeric amounts. Those
+/// are read as written, like string amounts, rather than through a float.
+#[derive(Deserialize)]
+pub(crate) struct JsonTransaction {
+    tx: TransactionId,
+    #[serde(rename = "type")]
+    transaction_type: TransactionType,
+    client: ClientId,
+    #[serde(default, deserialize_with = "json_amount")]
+    amount: Option<Decimal>,
+    #[serde(default)]
+    reference: Option<String>,
+    #[serde(default)]
+    reason_code: Option<String>,
+    #[serde(default)]
+    notes: Option<String>,
+    #[serde(default)]
+    sequence: Option<u64>,
+}
+
+/// Reads an amount given as a string, or as a number exactly as it is written.
+fn json_amount<'de, D>(deserializer: D) -> Result<Option<Decimal>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    struct Amount(#[serde(with = "rust_decimal::serde::arbitrary_precision")] Decimal);
+    Ok(Option::<Amount>::deserialize(deserializer)?.map(|Amount(amount)| amount))
+}
+
+impl JsonTransaction {
+    pub(crate) fn into_transaction(self) -> Result<Transaction> {
+        Ok(Transaction {
+            id: self.tx,
+            transaction_type: self.transaction_type,
+            client_id: self.client,
+            amount: self.amount,
+            reference: self.reference,
+            reason_code: self.reason_code,
+            notes: self.notes,
+            sequence: self.sequence,
+        })
+    }
+}
+
+/// Parses a single json encoded transaction.
+pub(crate) fn parse_json_transaction(json: &[u8]) -> Result<Transaction> {
+    serde_json::from_slice::<JsonTransaction>(json)?.into_transaction()
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::transactions::{Transaction, TransactionReader, TransactionType};
+    use crate::transactions::{
+        JsonLinesReader, NumberLocale, Transaction, TransactionReader, TransactionType,
+    };
     use rust_decimal::{prelude::FromPrimitive, Decimal};
+    use rust_decimal_macros::dec;
     use std::io;
 
     #[test]
@@ -54,33 +391,202 @@ chargeback, 1, 1"#;
                     id: 1,
                     transaction_type: TransactionType::Deposit,
                     client_id: 1,
-                    amount: Decimal::from_f64(1.0)
+                    amount: Decimal::from_f64(1.0),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
                 },
                 Transaction {
                     id: 4,
                     transaction_type: TransactionType::Withdrawal,
                     client_id: 1,
-                    amount: Decimal::from_f64(1.5)
+                    amount: Decimal::from_f64(1.5),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
                 },
                 Transaction {
                     id: 5,
                     transaction_type: TransactionType::Dispute,
                     client_id: 2,
-                    amount: None
+                    amount: None,
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
                 },
                 Transaction {
                     id: 1,
                     transaction_type: TransactionType::Resolve,
                     client_id: 1,
-                    amount: None
+                    amount: None,
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
                 },
                 Transaction {
                     id: 1,
                     transaction_type: TransactionType::Chargeback,
                     client_id: 1,
-                    amount: None
+                    amount: None,
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
                 }
             ]
         );
     }
+
+    #[test]
+    fn test_transaction_reader_lines() {
+        let lines = |csv: &str| {
+            TransactionReader::new(csv.as_bytes())
+                .transactions_with_lines()
+                .map(|t| t.map(|(line, t)| (line, t.id)))
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        };
+        let csv = "type,client,tx,amount\ndeposit,1,1,1\n\n\ndeposit,1,2,1\n";
+        assert_eq!(lines(csv), [(2, 1), (5, 2)]);
+        assert_eq!(lines(&csv.replace('\n', "\r\n")), [(2, 1), (5, 2)]);
+        // Quoted fields can span lines
+        let csv = "type,client,tx,amount\n\"deposit\n\",1,1,1\n\ndeposit,1,2,1";
+        assert_eq!(lines(csv), [(2, 1), (5, 2)]);
+    }
+
+    #[test]
+    fn test_json_lines_reader() {
+        let test_jsonl = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "1.0001"}
+{"type": "withdrawal", "client": 1, "tx": 4, "amount": 1.5}
+
+{"type": "dispute", "client": 2, "tx": 5}
+{"type": "resolve", "client": 1, "tx": 1, "amount": null}
+"#;
+
+        let mut transaction_reader = JsonLinesReader::new(io::Cursor::new(test_jsonl));
+        let transactions = transaction_reader
+            .transactions()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(
+            transactions,
+            &[
+                Transaction {
+                    id: 1,
+                    transaction_type: TransactionType::Deposit,
+                    client_id: 1,
+                    amount: Some(dec!(1.0001)),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 4,
+                    transaction_type: TransactionType::Withdrawal,
+                    client_id: 1,
+                    amount: Some(dec!(1.5)),
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 5,
+                    transaction_type: TransactionType::Dispute,
+                    client_id: 2,
+                    amount: None,
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+                Transaction {
+                    id: 1,
+                    transaction_type: TransactionType::Resolve,
+                    client_id: 1,
+                    amount: None,
+                    reference: None,
+                    reason_code: None,
+                    notes: None,
+                    sequence: None,
+                },
+            ]
+        );
+        let lines = JsonLinesReader::new(io::Cursor::new(test_jsonl))
+            .transactions_with_lines()
+            .map(|t| t.unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(lines, [1, 2, 4, 5]);
+
+        // Numeric amounts are read as written, whether or not the line is rewritten first
+        let line = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 1234567890123.4567}"#;
+        for locale in [NumberLocale::Plain, NumberLocale::Comma] {
+            let transaction = JsonLinesReader::new(io::Cursor::new(line))
+                .number_locale(locale)
+                .transactions()
+                .next()
+                .unwrap()
+                .unwrap();
+            assert_eq!(transaction.amount, Some(dec!(1234567890123.4567)));
+        }
+    }
+
+    #[test]
+    fn test_json_lines_reader_invalid() {
+        let mut transaction_reader =
+            JsonLinesReader::new(io::Cursor::new(r#"{"type": "deposit", "client": 1}"#));
+        assert!(transaction_reader.transactions().next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_number_locale() {
+        let normalize = |locale: NumberLocale, amount| locale.normalize(amount).into_owned();
+        assert_eq!(normalize(NumberLocale::Comma, "1.234,56"), "1234.56");
+        assert_eq!(normalize(NumberLocale::Comma, "-1 234 567,5"), "-1234567.5");
+        assert_eq!(normalize(NumberLocale::Comma, "12"), "12");
+        assert_eq!(normalize(NumberLocale::Point, "1 234.56"), "1234.56");
+        assert_eq!(normalize(NumberLocale::Point, "1'234,567.8"), "1234567.8");
+        assert_eq!(normalize(NumberLocale::Plain, "1 234.56"), "1 234.56");
+        // Not valid in the locale, left to fail parsing
+        for amount in [
+            "1.234.5,6,7",
+            "1,23.4",
+            ".1",
+            "1..234",
+            "1. 234",
+            "1.",
+            "1,2.3",
+        ] {
+            assert_eq!(normalize(NumberLocale::Comma, amount), amount);
+        }
+
+        let csv =
+            "type,client,tx,amount\ndeposit,1,1,\"1.234,56\"\ndispute,1,1\ndeposit,1,2,1 000\n";
+        let amounts: Vec<_> = TransactionReader::new(csv.as_bytes())
+            .number_locale(NumberLocale::Comma)
+            .transactions()
+            .map(|t| t.unwrap().amount)
+            .collect();
+        assert_eq!(amounts, [Some(dec!(1234.56)), None, Some(dec!(1000))]);
+        assert!(TransactionReader::new(csv.as_bytes())
+            .transactions()
+            .next()
+            .unwrap()
+            .is_err());
+
+        let jsonl = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "1.234,5"}
+{"type": "deposit", "client": 1, "tx": 2, "amount": 2.5}"#;
+        let amounts: Vec<_> = JsonLinesReader::new(io::Cursor::new(jsonl))
+            .number_locale(NumberLocale::Comma)
+            .transactions()
+            .map(|t| t.unwrap().amount)
+            .collect();
+        assert_eq!(amounts, [Some(dec!(1234.5)), Some(dec!(2.5))]);
+    }
 }
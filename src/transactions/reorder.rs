@@ -0,0 +1,174 @@
+use super::{Result, Transaction, TransactionSource};
+use futures::stream::{self, BoxStream, StreamExt};
+use std::collections::BTreeMap;
+
+/// A [`TransactionSource`] that applies the transactions of another source in the order of
+/// their [`Transaction::sequence`], for sources that deliver slightly out of order, such as a
+/// resolve arriving a few messages before its dispute.
+///
+/// Up to `capacity` transactions are held back, and the one with the lowest sequence is
+/// released whenever another arrives. A transaction that arrives more than `capacity`
+/// transactions late can not be put back in order and is applied when it is released, with a
+/// warning. Transactions without a sequence are released before those with one, and equal
+/// sequences keep their input order.
+///
+/// ```
+/// # async fn run() -> transaction_app::Result<()> {
+/// use transaction_app::{ReorderBuffer, TransactionReader, TransactionService, TransactionSource};
+///
+/// let csv = "type, client, tx, amount, sequence
+///     deposit, 1, 1, 2.0, 1
+///     resolve, 1, 1,, 3
+///     dispute, 1, 1,, 2";
+/// let mut source = ReorderBuffer::new(TransactionReader::new(csv.as_bytes()), 8);
+/// let svc = TransactionService::builder().build().await?;
+/// svc.process_stream(source.stream()).await?;
+/// assert_eq!(svc.get_client(1).await?.unwrap().held, 0.into());
+/// # Ok(())
+/// # }
+/// # tokio::runtime::Runtime::new().unwrap().block_on(run()).unwrap();
+/// ```
+pub struct ReorderBuffer<S> {
+    source: S,
+    capacity: usize,
+}
+
+impl<S: TransactionSource> ReorderBuffer<S> {
+    pub fn new(source: S, capacity: usize) -> Self {
+        Self { source, capacity }
+    }
+}
+
+/// The state of [`ReorderBuffer::stream_with_lines`].
+struct Reordering<'a> {
+    source: BoxStream<'a, Result<(u64, Transaction)>>,
+    capacity: usize,
+    /// Keyed by sequence, then arrival.
+    buffer: BTreeMap<(Option<u64>, u64), (u64, Transaction)>,
+    arrived: u64,
+    exhausted: bool,
+    released: Option<u64>,
+}
+
+impl Reordering<'_> {
+    async fn next(&mut self) -> Option<Result<(u64, Transaction)>> {
+        while !self.exhausted && self.buffer.len() <= self.capacity {
+            match self.source.next().await {
+                Some(Ok((line, transaction))) => {
+                    self.arrived += 1;
+                    self.buffer
+                        .insert((transaction.sequence, self.arrived), (line, transaction));
+                }
+                Some(Err(e)) => return Some(Err(e)),
+                None => self.exhausted = true,
+            }
+        }
+
+        let ((sequence, _), (line, transaction)) = self.buffer.pop_first()?;
+        if let Some(sequence) = sequence {
+            match self.released {
+                Some(released) if sequence < released => tracing::warn!(
+                    tx = transaction.id,
+                    sequence,
+                    released,
+                    "Applying a transaction that arrived too late to be put back in order"
+                ),
+                _ => self.released = Some(sequence),
+            }
+        }
+        Some(Ok((line, transaction)))
+    }
+}
+
+impl<S: TransactionSource> TransactionSource for ReorderBuffer<S> {
+    fn stream(&mut self) -> BoxStream<'_, Result<Transaction>> {
+        self.stream_with_lines()
+            .map(|transaction| transaction.map(|(_, t)| t))
+            .boxed()
+    }
+
+    fn stream_with_lines(&mut self) -> BoxStream<'_, Result<(u64, Transaction)>> {
+        let reordering = Reordering {
+            source: self.source.stream_with_lines(),
+            capacity: self.capacity,
+            buffer: BTreeMap::new(),
+            arrived: 0,
+            exhausted: false,
+            released: None,
+        };
+        stream::unfold(reordering, |mut reordering| async move {
+            let next = reordering.next().await?;
+            Some((next, reordering))
+        })
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ReorderBuffer, Result, TransactionId, TransactionReader, TransactionSource};
+    use futures::{StreamExt, TryStreamExt};
+
+    async fn reordered(csv: &str, capacity: usize) -> Vec<(u64, TransactionId, Option<u64>)> {
+        let mut source = ReorderBuffer::new(TransactionReader::new(csv.as_bytes()), capacity);
+        let transactions: Vec<_> = source.stream_with_lines().try_collect().await.unwrap();
+        transactions
+            .into_iter()
+            .map(|(line, t)| (line, t.id, t.sequence))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_reorder_buffer() {
+        let csv = "type, client, tx, amount, sequence
+            deposit, 1, 1, 2.0, 10
+            resolve, 1, 1,, 30
+            dispute, 1, 1,, 20
+            deposit, 1, 2, 1.0, 40";
+        assert_eq!(
+            reordered(csv, 1).await,
+            [
+                (2, 1, Some(10)),
+                (4, 1, Some(20)),
+                (3, 1, Some(30)),
+                (5, 2, Some(40))
+            ]
+        );
+        // Nothing is held back
+        assert_eq!(
+            reordered(csv, 0).await,
+            [
+                (2, 1, Some(10)),
+                (3, 1, Some(30)),
+                (4, 1, Some(20)),
+                (5, 2, Some(40))
+            ]
+        );
+
+        // Too late to be put back in order, and equal sequences keep their order
+        let csv = "type, client, tx, amount, sequence
+            deposit, 1, 2, 1.0, 2
+            deposit, 1, 3, 1.0, 3
+            deposit, 1, 4, 1.0, 3
+            deposit, 1, 1, 1.0, 1";
+        let ids: Vec<_> = reordered(csv, 1).await.iter().map(|t| t.1).collect();
+        assert_eq!(ids, [2, 3, 1, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_reorder_buffer_errors() {
+        let csv = "type, client, tx, amount, sequence
+            deposit, 1, 1, 2.0, 2
+            deposit, 1, x, 2.0, 1
+            deposit, 1, 2, 1.0, 1";
+        let mut source = ReorderBuffer::new(TransactionReader::new(csv.as_bytes()), 4);
+        let transactions: Vec<Result<_>> = source.stream().collect().await;
+        // Errors are passed on as they arrive
+        assert!(transactions[0].is_err());
+        let ids: Vec<_> = transactions[1..]
+            .iter()
+            .map(|t| t.as_ref().unwrap().id)
+            .collect();
+        assert_eq!(ids, [2, 1]);
+    }
+}
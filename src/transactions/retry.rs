@@ -0,0 +1,121 @@
+use super::TransactionError;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// Sqlite's primary result codes for a database held by another connection or process.
+const SQLITE_BUSY: i64 = 5;
+const SQLITE_LOCKED: i64 = 6;
+/// And for a database that cannot be used.
+const SQLITE_IOERR: i64 = 10;
+const SQLITE_FULL: i64 = 13;
+const SQLITE_CANTOPEN: i64 = 14;
+
+/// How often a write failing with a transient database error, such as `SQLITE_BUSY` while
+/// another process holds the database, is retried, see
+/// [`TransactionServiceBuilder::retry_policy`](super::TransactionServiceBuilder::retry_policy).
+///
+/// The whole database transaction is rolled back and applied again, so a batch is never
+/// partially committed. Retries wait an exponentially growing, jittered backoff.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// How many times a write is attempted in total, at least once.
+    pub max_attempts: u32,
+    /// The backoff before the first retry, doubled for each retry after it.
+    pub initial_backoff: Duration,
+    /// The longest backoff between attempts.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    /// Up to 5 attempts, backing off from 50ms to at most 2s.
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Fails on the first error.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// The backoff after the failed `attempt`, counted from 1: half of the exponential
+    /// backoff, plus a random part of the other half so writers retrying at the same time
+    /// spread out.
+    pub(super) fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+            .min(self.max_backoff);
+        let jitter = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        backoff / 2 + (backoff / 2).mul_f64(jitter)
+    }
+}
+
+impl TransactionError {
+    /// Whether the error is likely to go away when the write is tried again: the database was
+    /// busy or locked by another connection, or no connection was available in time.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Database(sqlx::Error::Database(e)) => e
+                .code()
+                .and_then(|code| code.parse::<i64>().ok())
+                // Extended result codes keep the primary code in their lowest byte
+                .is_some_and(|code| matches!(code & 0xff, SQLITE_BUSY | SQLITE_LOCKED)),
+            Self::Database(sqlx::Error::PoolTimedOut) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the error means the database could not be used at all, rather than that the
+    /// request was wrong: a [transient](Self::is_transient) error that outlasted the retries,
+    /// or a database that cannot be read or opened.
+    pub fn is_unavailable(&self) -> bool {
+        match self {
+            Self::Database(
+                sqlx::Error::Io(_) | sqlx::Error::PoolClosed | sqlx::Error::WorkerCrashed,
+            ) => true,
+            Self::Database(sqlx::Error::Database(e)) if !self.is_transient() => e
+                .code()
+                .and_then(|code| code.parse::<i64>().ok())
+                .is_some_and(|code| {
+                    matches!(code & 0xff, SQLITE_IOERR | SQLITE_FULL | SQLITE_CANTOPEN)
+                }),
+            _ => self.is_transient(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+        };
+        for (attempt, max) in [(1, 100), (2, 200), (3, 400), (4, 500), (40, 500)] {
+            let backoff = policy.backoff(attempt);
+            let max = Duration::from_millis(max);
+            assert!(backoff >= max / 2 && backoff <= max, "{:?}", backoff);
+        }
+
+        assert!(TransactionError::Database(sqlx::Error::PoolTimedOut).is_transient());
+        assert!(!TransactionError::Database(sqlx::Error::RowNotFound).is_transient());
+        assert!(!TransactionError::InvalidArgument("busy".into()).is_transient());
+        assert!(TransactionError::Database(sqlx::Error::PoolClosed).is_unavailable());
+        assert!(TransactionError::Database(sqlx::Error::PoolTimedOut).is_unavailable());
+        assert!(!TransactionError::Database(sqlx::Error::RowNotFound).is_unavailable());
+    }
+}
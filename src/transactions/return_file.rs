@@ -0,0 +1,279 @@
+use super::{IgnoreReason, Result, Transaction, TransactionId, TransactionOutcome};
+use std::io::Write;
+use time::OffsetDateTime;
+
+/// The format of a [`ReturnFile`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReturnFormat {
+    /// A csv file with a row per transaction: `line,tx,type,status,reason_code,reason`.
+    #[default]
+    Csv,
+    /// An ISO 20022 `pain.002.001.10` customer payment status report.
+    Pain002,
+}
+
+/// One transaction of a [`ReturnFile`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ReturnEntry {
+    line: u64,
+    transaction_id: TransactionId,
+    transaction_type: String,
+    /// The outcome's [`TransactionOutcome::reason`], `None` if it was accepted.
+    reason: Option<String>,
+}
+
+impl ReturnEntry {
+    /// The ISO 20022 status reason code of a transaction that was not applied. Reasons without
+    /// a code of their own are `NARR`, with the reason as the narrative.
+    fn reason_code(&self) -> Option<&'static str> {
+        let reason = self.reason.as_deref()?;
+        Some(match reason {
+            "insufficient_funds" => "AM04",
+            _ if reason == IgnoreReason::ClientLocked.to_str() => "AC06",
+            _ if reason == IgnoreReason::WithdrawalsBlocked.to_str() => "AG01",
+            _ if reason == IgnoreReason::OutsidePolicy.to_str() => "AM02",
+            TransactionOutcome::OVERFLOW => "AM02",
+            TransactionOutcome::INVALID_AMOUNT => "AM12",
+            _ if reason == IgnoreReason::Duplicate.to_str() => "AM05",
+            _ => "NARR",
+        })
+    }
+}
+
+/// The acknowledgement of a batch ingested from a partner, with every transaction as accepted
+/// or rejected with a reason code, see [`ReturnFormat`].
+///
+/// Transactions are recorded with their outcome as they are committed, e.g. from the
+/// `on_outcome` of
+/// [`TransactionService::process_batch_with`](super::TransactionService::process_batch_with),
+/// and written once the batch is done.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReturnFile {
+    source: String,
+    entries: Vec<ReturnEntry>,
+}
+
+impl ReturnFile {
+    /// Acknowledges the batch read from `source`, e.g. the partner's file name.
+    pub fn new(source: &str) -> Self {
+        Self {
+            source: source.to_string(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// Records the outcome of the transaction read from `line` of the source.
+    pub fn record(&mut self, line: u64, transaction: &Transaction, outcome: &TransactionOutcome) {
+        self.entries.push(ReturnEntry {
+            line,
+            transaction_id: transaction.id,
+            transaction_type: transaction.transaction_type.to_str().to_string(),
+            reason: outcome.reason().map(str::to_string),
+        });
+    }
+
+    /// Writes the return file, stamped with `created_at` in milliseconds since the unix epoch.
+    pub fn write<W: Write>(&self, format: ReturnFormat, w: W, created_at: i64) -> Result<()> {
+        match format {
+            ReturnFormat::Csv => self.write_csv(w),
+            ReturnFormat::Pain002 => self.write_pain002(w, created_at),
+        }
+    }
+
+    fn write_csv<W: Write>(&self, w: W) -> Result<()> {
+        let mut w = csv::Writer::from_writer(w);
+        w.write_record(["line", "tx", "type", "status", "reason_code", "reason"])?;
+        for entry in &self.entries {
+            let status = if entry.reason.is_some() {
+                "rejected"
+            } else {
+                "accepted"
+            };
+            w.write_record([
+                entry.line.to_string().as_str(),
+                &entry.transaction_id.to_string(),
+                &entry.transaction_type,
+                status,
+                entry.reason_code().unwrap_or_default(),
+                entry.reason.as_deref().unwrap_or_default(),
+            ])?;
+        }
+        w.flush()?;
+        Ok(())
+    }
+
+    fn write_pain002<W: Write>(&self, mut w: W, created_at: i64) -> Result<()> {
+        let rejected = self.entries.iter().filter(|e| e.reason.is_some()).count();
+        let group_status = match rejected {
+            0 => "ACCP",
+            rejected if rejected == self.entries.len() => "RJCT",
+            _ => "PART",
+        };
+        // The file name, as the partner knows it, limited to the 35 characters ids can have
+        let original = self.source.rsplit(['/', '\\']).next().unwrap_or_default();
+        let original: String = original.chars().take(35).collect();
+
+        writeln!(w, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            w,
+            r#"<Document xmlns="urn:iso:std:iso:20022:tech:xsd:pain.002.001.10">"#
+        )?;
+        writeln!(w, "  <CstmrPmtStsRpt>")?;
+        writeln!(w, "    <GrpHdr>")?;
+        writeln!(w, "      <MsgId>RTN-{}</MsgId>", created_at)?;
+        writeln!(w, "      <CreDtTm>{}</CreDtTm>", iso_date_time(created_at))?;
+        writeln!(w, "    </GrpHdr>")?;
+        writeln!(w, "    <OrgnlGrpInfAndSts>")?;
+        writeln!(w, "      <OrgnlMsgId>{}</OrgnlMsgId>", escape(&original))?;
+        writeln!(
+            w,
+            "      <OrgnlMsgNmId>{}</OrgnlMsgNmId>",
+            env!("CARGO_PKG_NAME")
+        )?;
+        writeln!(
+            w,
+            "      <OrgnlNbOfTxs>{}</OrgnlNbOfTxs>",
+            self.entries.len()
+        )?;
+        writeln!(w, "      <GrpSts>{}</GrpSts>", group_status)?;
+        writeln!(w, "    </OrgnlGrpInfAndSts>")?;
+        writeln!(w, "    <OrgnlPmtInfAndSts>")?;
+        writeln!(
+            w,
+            "      <OrgnlPmtInfId>{}</OrgnlPmtInfId>",
+            escape(&original)
+        )?;
+        for entry in &self.entries {
+            writeln!(w, "      <TxInfAndSts>")?;
+            writeln!(w, "        <OrgnlInstrId>{}</OrgnlInstrId>", entry.line)?;
+            writeln!(
+                w,
+                "        <OrgnlEndToEndId>{}</OrgnlEndToEndId>",
+                entry.transaction_id
+            )?;
+            match (&entry.reason, entry.reason_code()) {
+                (Some(reason), Some(code)) => {
+                    // Additional information is limited to 105 characters
+                    let reason: String = reason.chars().take(105).collect();
+                    writeln!(w, "        <TxSts>RJCT</TxSts>")?;
+                    writeln!(w, "        <StsRsnInf>")?;
+                    writeln!(w, "          <Rsn><Cd>{}</Cd></Rsn>", code)?;
+                    writeln!(w, "          <AddtlInf>{}</AddtlInf>", escape(&reason))?;
+                    writeln!(w, "        </StsRsnInf>")?;
+                }
+                _ => writeln!(w, "        <TxSts>ACCP</TxSts>")?,
+            }
+            writeln!(w, "      </TxInfAndSts>")?;
+        }
+        writeln!(w, "    </OrgnlPmtInfAndSts>")?;
+        writeln!(w, "  </CstmrPmtStsRpt>")?;
+        writeln!(w, "</Document>")?;
+        Ok(())
+    }
+}
+
+/// Formats milliseconds since the unix epoch as an ISO 8601 UTC date and time.
+fn iso_date_time(unix_millis: i64) -> String {
+    let t = OffsetDateTime::from_unix_timestamp(unix_millis.div_euclid(1000))
+        .unwrap_or(OffsetDateTime::UNIX_EPOCH);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        t.year(),
+        t.month() as u8,
+        t.day(),
+        t.hour(),
+        t.minute(),
+        t.second()
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TransactionType;
+    use rust_decimal_macros::dec;
+
+    fn return_file() -> ReturnFile {
+        let transaction = |id, transaction_type| Transaction {
+            id,
+            transaction_type,
+            client_id: 1,
+            amount: Some(dec!(1)),
+            reference: None,
+            reason_code: None,
+            notes: None,
+            sequence: None,
+        };
+        let mut file = ReturnFile::new("inbox/partner-2024-01-31.csv");
+        file.record(
+            2,
+            &transaction(1, TransactionType::Deposit),
+            &TransactionOutcome::Deposit,
+        );
+        file.record(
+            3,
+            &transaction(2, TransactionType::Withdrawal),
+            &TransactionOutcome::WithdrawalRejected,
+        );
+        file.record(
+            4,
+            &transaction(1, TransactionType::Deposit),
+            &TransactionOutcome::Ignored {
+                reason: IgnoreReason::Duplicate,
+            },
+        );
+        file.record(
+            5,
+            &transaction(3, TransactionType::Deposit),
+            &TransactionOutcome::Rejected {
+                reason: "amount > 5 & type == <deposit>".into(),
+            },
+        );
+        file
+    }
+
+    #[test]
+    fn test_return_file_csv() {
+        let mut csv = Vec::new();
+        return_file().write(ReturnFormat::Csv, &mut csv, 0).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "line,tx,type,status,reason_code,reason\n\
+            2,1,deposit,accepted,,\n\
+            3,2,withdrawal,rejected,AM04,insufficient_funds\n\
+            4,1,deposit,rejected,AM05,duplicate\n\
+            5,3,deposit,rejected,NARR,amount > 5 & type == <deposit>\n"
+        );
+    }
+
+    #[test]
+    fn test_return_file_pain002() {
+        let mut xml = Vec::new();
+        return_file()
+            .write(ReturnFormat::Pain002, &mut xml, 1_706_745_600_000)
+            .unwrap();
+        let xml = String::from_utf8(xml).unwrap();
+        assert!(xml.contains("<CreDtTm>2024-02-01T00:00:00Z</CreDtTm>"));
+        assert!(xml.contains("<OrgnlMsgId>partner-2024-01-31.csv</OrgnlMsgId>"));
+        assert!(xml.contains("<OrgnlNbOfTxs>4</OrgnlNbOfTxs>"));
+        assert!(xml.contains("<GrpSts>PART</GrpSts>"));
+        assert_eq!(xml.matches("<TxSts>ACCP</TxSts>").count(), 1);
+        assert_eq!(xml.matches("<TxSts>RJCT</TxSts>").count(), 3);
+        assert!(xml.contains("<Rsn><Cd>AM04</Cd></Rsn>"));
+        assert!(xml.contains("<AddtlInf>amount &gt; 5 &amp; type == &lt;deposit&gt;</AddtlInf>"));
+
+        let mut xml = Vec::new();
+        let empty = ReturnFile::new("empty.csv");
+        empty.write(ReturnFormat::Pain002, &mut xml, 0).unwrap();
+        assert!(String::from_utf8(xml)
+            .unwrap()
+            .contains("<GrpSts>ACCP</GrpSts>"));
+    }
+}
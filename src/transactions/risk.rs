@@ -0,0 +1,99 @@
+use super::{ClientId, Result, TransactionError};
+use serde::{Deserialize, Serialize};
+
+/// How far a client's chargebacks have escalated, see [`ChargebackEscalation`]. Levels only
+/// ever rise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RiskLevel {
+    /// Flagged for the risk team, without changing how the client's transactions are applied.
+    Flagged,
+    /// Withdrawals are ignored as
+    /// [`IgnoreReason::WithdrawalsBlocked`](super::IgnoreReason::WithdrawalsBlocked), even
+    /// after the client is unlocked.
+    WithdrawalsBlocked,
+    /// The client stays locked, and
+    /// [`TransactionService::unlock_client`](super::TransactionService::unlock_client) fails.
+    PermanentlyLocked,
+}
+
+impl RiskLevel {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Flagged => "flagged",
+            Self::WithdrawalsBlocked => "withdrawals_blocked",
+            Self::PermanentlyLocked => "permanently_locked",
+        }
+    }
+
+    pub(super) fn parse(level: &str) -> Result<Self> {
+        match level {
+            "flagged" => Ok(Self::Flagged),
+            "withdrawals_blocked" => Ok(Self::WithdrawalsBlocked),
+            "permanently_locked" => Ok(Self::PermanentlyLocked),
+            _ => Err(TransactionError::InvalidArgument(format!(
+                "Unknown risk level \"{}\"",
+                level
+            ))),
+        }
+    }
+}
+
+/// The chargeback counts at which a client's [`RiskLevel`] is raised. A chargeback always
+/// locks its client; these escalate clients that keep being charged back after they were
+/// unlocked.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChargebackEscalation {
+    pub flag: Option<u64>,
+    pub block_withdrawals: Option<u64>,
+    pub lock_permanently: Option<u64>,
+}
+
+impl ChargebackEscalation {
+    /// The highest level reached by a client with `chargebacks` chargebacks, if any.
+    pub fn level(&self, chargebacks: u64) -> Option<RiskLevel> {
+        [
+            (self.lock_permanently, RiskLevel::PermanentlyLocked),
+            (self.block_withdrawals, RiskLevel::WithdrawalsBlocked),
+            (self.flag, RiskLevel::Flagged),
+        ]
+        .into_iter()
+        .find(|(threshold, _)| threshold.is_some_and(|threshold| chargebacks >= threshold))
+        .map(|(_, level)| level)
+    }
+}
+
+/// The escalation decided for a client, recorded in the audit log and outbox as a
+/// [`LedgerEvent::RiskEscalated`](super::LedgerEvent::RiskEscalated).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientRisk {
+    pub client: ClientId,
+    pub level: RiskLevel,
+    /// The client's chargebacks when the level was reached.
+    pub chargebacks: u64,
+    /// When the level was reached, in milliseconds since the unix epoch.
+    pub escalated_at: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escalation_level() {
+        let escalation = ChargebackEscalation {
+            flag: Some(1),
+            block_withdrawals: None,
+            lock_permanently: Some(3),
+        };
+        assert_eq!(escalation.level(0), None);
+        assert_eq!(escalation.level(1), Some(RiskLevel::Flagged));
+        assert_eq!(escalation.level(2), Some(RiskLevel::Flagged));
+        assert_eq!(escalation.level(5), Some(RiskLevel::PermanentlyLocked));
+        assert_eq!(ChargebackEscalation::default().level(5), None);
+        assert_eq!(
+            RiskLevel::parse(RiskLevel::WithdrawalsBlocked.to_str()).unwrap(),
+            RiskLevel::WithdrawalsBlocked
+        );
+    }
+}
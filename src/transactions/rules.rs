@@ -0,0 +1,617 @@
+use super::{Client, Result, Transaction, TransactionError, TransactionValidator, Verdict};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+/// Declarative validation rules, compiled once and evaluated before every transaction.
+///
+/// Each rule is `<action> when <condition>`, where the action is `reject`, `reject "<reason>"`,
+/// `annotate "<annotation>"` or `score <change>`, e.g. `score +10` or `score -5`. Conditions
+/// compare fields with `==`, `!=`, `<`, `<=`, `>` and `>=`, and combine comparisons with
+/// `&&`, `||`, `!` and parentheses. The fields are:
+///
+/// - `type`: the transaction type, compared with strings
+/// - `tx`, `client` and `amount`: the transaction id, client id and amount
+/// - `available`, `held`, `total` and `locked`: the client's current state, zero and `false`
+///   for a new client
+/// - `risk_score`: the client's current
+///   [`ClientStats::risk_score`](super::ClientStats::risk_score), zero for a new client
+///
+/// Comparisons against `amount` are false for transactions without an amount. Rejections
+/// without a reason are rejected with the rule's text. The changes of every matching `score`
+/// rule are added to the client's risk score, unless a rule rejects the transaction.
+///
+/// Rules are usually loaded from a toml file with a `rules` array:
+///
+/// ```toml
+/// rules = [
+///     'reject "withdrawal over 10000" when type == "withdrawal" && amount > 10000',
+///     'annotate "large deposit" when type == "deposit" && amount >= 5000',
+///     'score +10 when type == "withdrawal" && amount >= 5000',
+/// ]
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+}
+
+/// A [`RuleSet`] that can be replaced while the service runs, e.g. when its file was edited.
+/// Clones share the rules, so a clone registered with
+/// [`TransactionServiceBuilder::validator`](super::TransactionServiceBuilder::validator) applies
+/// the rules passed to [`ReloadableRules::replace`] from the next transaction on.
+#[derive(Debug, Clone, Default)]
+pub struct ReloadableRules {
+    rules: Arc<RwLock<Arc<RuleSet>>>,
+}
+
+#[derive(Deserialize)]
+struct RulesFile {
+    rules: Vec<String>,
+}
+
+impl RuleSet {
+    /// Compiles `rules`, failing with [`TransactionError::InvalidArgument`] on the first
+    /// invalid rule.
+    pub fn new<S: AsRef<str>>(rules: impl IntoIterator<Item = S>) -> Result<Self> {
+        let rules = rules
+            .into_iter()
+            .map(|rule| {
+                let rule = rule.as_ref();
+                Rule::parse(rule).map_err(|e| {
+                    TransactionError::InvalidArgument(format!("Invalid rule '{}': {}", rule, e))
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    /// Compiles the `rules` array of a toml document.
+    pub fn from_toml(toml: &str) -> Result<Self> {
+        let file: RulesFile = toml::from_str(toml)
+            .map_err(|e| TransactionError::InvalidArgument(format!("Invalid rules file: {}", e)))?;
+        Self::new(file.rules)
+    }
+
+    /// Reads and compiles a toml rules file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+}
+
+impl ReloadableRules {
+    pub fn new(rules: RuleSet) -> Self {
+        Self {
+            rules: Arc::new(RwLock::new(Arc::new(rules))),
+        }
+    }
+
+    pub fn replace(&self, rules: RuleSet) {
+        *self.rules.write().unwrap() = Arc::new(rules);
+    }
+}
+
+impl TransactionValidator for ReloadableRules {
+    fn validate(&self, transaction: &Transaction, client: Option<&Client>) -> Result<Verdict> {
+        self.validate_with_risk(transaction, client, 0)
+    }
+
+    fn validate_with_risk(
+        &self,
+        transaction: &Transaction,
+        client: Option<&Client>,
+        risk_score: i64,
+    ) -> Result<Verdict> {
+        let rules = Arc::clone(&self.rules.read().unwrap());
+        rules.validate_with_risk(transaction, client, risk_score)
+    }
+}
+
+impl TransactionValidator for RuleSet {
+    fn validate(&self, transaction: &Transaction, client: Option<&Client>) -> Result<Verdict> {
+        self.validate_with_risk(transaction, client, 0)
+    }
+
+    fn validate_with_risk(
+        &self,
+        transaction: &Transaction,
+        client: Option<&Client>,
+        risk_score: i64,
+    ) -> Result<Verdict> {
+        let mut annotations = Vec::new();
+        let mut risk_score_change = 0i64;
+        for rule in &self.rules {
+            if !rule.condition.matches(transaction, client, risk_score) {
+                continue;
+            }
+            match &rule.action {
+                Action::Reject(reason) => {
+                    return Ok(Verdict::Reject {
+                        reason: reason.clone(),
+                    })
+                }
+                Action::Annotate(annotation) => annotations.push(annotation.clone()),
+                Action::Score(change) => {
+                    risk_score_change = risk_score_change.saturating_add(*change)
+                }
+            }
+        }
+        Ok(Verdict::Accept {
+            annotations,
+            risk_score_change,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+    action: Action,
+    condition: Condition,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Action {
+    Reject(String),
+    Annotate(String),
+    Score(i64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Condition {
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+    Not(Box<Condition>),
+    Type(Op, String),
+    Number(Field, Op, Decimal),
+    Locked(Op, bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Tx,
+    Client,
+    Amount,
+    Available,
+    Held,
+    Total,
+    RiskScore,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    fn compare<T: PartialOrd>(self, left: T, right: T) -> bool {
+        match self {
+            Op::Eq => left == right,
+            Op::Ne => left != right,
+            Op::Lt => left < right,
+            Op::Le => left <= right,
+            Op::Gt => left > right,
+            Op::Ge => left >= right,
+        }
+    }
+}
+
+impl Condition {
+    fn matches(&self, transaction: &Transaction, client: Option<&Client>, risk_score: i64) -> bool {
+        let matches = |c: &Condition| c.matches(transaction, client, risk_score);
+        match self {
+            Self::And(a, b) => matches(a) && matches(b),
+            Self::Or(a, b) => matches(a) || matches(b),
+            Self::Not(c) => !matches(c),
+            Self::Type(op, name) => op.compare(transaction.transaction_type.to_str(), name),
+            Self::Number(field, op, value) => {
+                let actual = match field {
+                    Field::Tx => Some(transaction.id.into()),
+                    Field::Client => Some(transaction.client_id.into()),
+                    Field::Amount => transaction.amount,
+                    Field::Available => Some(client.map_or(Decimal::ZERO, |c| c.available)),
+                    Field::Held => Some(client.map_or(Decimal::ZERO, |c| c.held)),
+                    Field::Total => Some(client.map_or(Decimal::ZERO, |c| c.total)),
+                    Field::RiskScore => Some(risk_score.into()),
+                };
+                actual.is_some_and(|actual| op.compare(actual, *value))
+            }
+            Self::Locked(op, value) => op.compare(client.is_some_and(|c| c.locked), *value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Text(String),
+    Number(Decimal),
+    Op(Op),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+fn tokenize(rule: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = rule.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '=' if chars.next_if_eq(&'=').is_some() => Token::Op(Op::Eq),
+            '!' if chars.next_if_eq(&'=').is_some() => Token::Op(Op::Ne),
+            '!' => Token::Not,
+            '<' if chars.next_if_eq(&'=').is_some() => Token::Op(Op::Le),
+            '<' => Token::Op(Op::Lt),
+            '>' if chars.next_if_eq(&'=').is_some() => Token::Op(Op::Ge),
+            '>' => Token::Op(Op::Gt),
+            '&' if chars.next_if_eq(&'&').is_some() => Token::And,
+            '|' if chars.next_if_eq(&'|').is_some() => Token::Or,
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => text.push(c),
+                        None => return Err("unterminated string".into()),
+                    }
+                }
+                Token::Text(text)
+            }
+            c if c.is_ascii_digit() || c == '-' || c == '+' => {
+                let mut number = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || *c == '.') {
+                    number.push(c);
+                }
+                Token::Number(
+                    Decimal::from_str(&number).map_err(|_| format!("invalid number {}", number))?,
+                )
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+                    word.push(c);
+                }
+                Token::Word(word)
+            }
+            c => return Err(format!("unexpected '{}'", c)),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// A recursive descent parser over the tokens of a rule.
+struct Parser {
+    tokens: std::vec::IntoIter<Token>,
+    peeked: Option<Token>,
+}
+
+impl Rule {
+    fn parse(rule: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            tokens: tokenize(rule)?.into_iter(),
+            peeked: None,
+        };
+        let action = match parser.next() {
+            Some(Token::Word(w)) if w == "reject" => match parser.peek() {
+                Some(Token::Text(_)) => Action::Reject(parser.text()?),
+                _ => Action::Reject(rule.to_string()),
+            },
+            Some(Token::Word(w)) if w == "annotate" => Action::Annotate(parser.text()?),
+            Some(Token::Word(w)) if w == "score" => match parser.next() {
+                Some(Token::Number(change)) => Action::Score(
+                    i64::try_from(change)
+                        .ok()
+                        .filter(|_| change.fract().is_zero())
+                        .ok_or_else(|| format!("invalid score change {}", change))?,
+                ),
+                _ => return Err("expected a whole number after score".into()),
+            },
+            _ => return Err("a rule must start with reject, annotate or score".into()),
+        };
+        match parser.next() {
+            Some(Token::Word(w)) if w == "when" => {}
+            _ => return Err("expected when after the action".into()),
+        }
+        let condition = parser.or()?;
+        if let Some(token) = parser.next() {
+            return Err(format!("unexpected {:?} after the condition", token));
+        }
+        Ok(Self { action, condition })
+    }
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        self.peeked.take().or_else(|| self.tokens.next())
+    }
+
+    fn peek(&mut self) -> Option<&Token> {
+        if self.peeked.is_none() {
+            self.peeked = self.tokens.next();
+        }
+        self.peeked.as_ref()
+    }
+
+    fn text(&mut self) -> Result<String, String> {
+        match self.next() {
+            Some(Token::Text(text)) => Ok(text),
+            _ => Err("expected a quoted string".into()),
+        }
+    }
+
+    fn or(&mut self) -> Result<Condition, String> {
+        let mut condition = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            condition = Condition::Or(Box::new(condition), Box::new(self.and()?));
+        }
+        Ok(condition)
+    }
+
+    fn and(&mut self) -> Result<Condition, String> {
+        let mut condition = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            condition = Condition::And(Box::new(condition), Box::new(self.unary()?));
+        }
+        Ok(condition)
+    }
+
+    fn unary(&mut self) -> Result<Condition, String> {
+        match self.next() {
+            Some(Token::Not) => Ok(Condition::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let condition = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(condition),
+                    _ => Err("expected )".into()),
+                }
+            }
+            Some(Token::Word(field)) => self.comparison(&field),
+            token => Err(format!("expected a condition, found {:?}", token)),
+        }
+    }
+
+    fn comparison(&mut self, field: &str) -> Result<Condition, String> {
+        const FIELDS: [&str; 9] = [
+            "type",
+            "tx",
+            "client",
+            "amount",
+            "available",
+            "held",
+            "total",
+            "locked",
+            "risk_score",
+        ];
+        if !FIELDS.contains(&field) {
+            return Err(format!("unknown field {}", field));
+        }
+        let op = match self.next() {
+            Some(Token::Op(op)) => op,
+            _ => return Err(format!("expected a comparison after {}", field)),
+        };
+        let number_field = match field {
+            "tx" => Field::Tx,
+            "client" => Field::Client,
+            "amount" => Field::Amount,
+            "available" => Field::Available,
+            "held" => Field::Held,
+            "total" => Field::Total,
+            "risk_score" => Field::RiskScore,
+            "type" => {
+                if !matches!(op, Op::Eq | Op::Ne) {
+                    return Err("type can only be compared with == or !=".into());
+                }
+                return Ok(Condition::Type(op, self.text()?));
+            }
+            "locked" => {
+                let value = match self.next() {
+                    Some(Token::Word(w)) if w == "true" => true,
+                    Some(Token::Word(w)) if w == "false" => false,
+                    _ => return Err("locked must be compared with true or false".into()),
+                };
+                if !matches!(op, Op::Eq | Op::Ne) {
+                    return Err("locked can only be compared with == or !=".into());
+                }
+                return Ok(Condition::Locked(op, value));
+            }
+            _ => unreachable!("checked against FIELDS"),
+        };
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Condition::Number(number_field, op, value)),
+            _ => Err(format!("{} must be compared with a number", field)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ReloadableRules, RuleSet};
+    use crate::{
+        Client, Transaction, TransactionError, TransactionType, TransactionValidator, Verdict,
+    };
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_rules() {
+        let rules = RuleSet::from_toml(
+            r#"
+            rules = [
+                'reject "withdrawal over 10000" when type == "withdrawal" && amount > 10000',
+                'reject when !(client == 1 || client == 2) && type != "deposit"',
+                'annotate "large deposit" when type == "deposit" && amount >= 5000',
+                'annotate "empty" when total == 0 && locked == false',
+            ]
+            "#,
+        )
+        .unwrap();
+        let transaction = |transaction_type, client_id, amount| Transaction {
+            id: 1,
+            transaction_type,
+            client_id,
+            amount,
+            reference: None,
+            reason_code: None,
+            notes: None,
+            sequence: None,
+        };
+        let client = Client {
+            id: 1,
+            available: dec!(20000),
+            held: dec!(0),
+            total: dec!(20000),
+            locked: false,
+        };
+
+        assert_eq!(
+            rules
+                .validate(
+                    &transaction(TransactionType::Withdrawal, 1, Some(dec!(10000.01))),
+                    Some(&client)
+                )
+                .unwrap(),
+            Verdict::Reject {
+                reason: "withdrawal over 10000".into()
+            }
+        );
+        assert_eq!(
+            rules
+                .validate(
+                    &transaction(TransactionType::Withdrawal, 1, Some(dec!(10000))),
+                    Some(&client)
+                )
+                .unwrap(),
+            Verdict::accept()
+        );
+        assert_eq!(
+            rules
+                .validate(&transaction(TransactionType::Dispute, 3, None), None)
+                .unwrap(),
+            Verdict::Reject {
+                reason: r#"reject when !(client == 1 || client == 2) && type != "deposit""#.into()
+            }
+        );
+        assert_eq!(
+            rules
+                .validate(
+                    &transaction(TransactionType::Deposit, 3, Some(dec!(5000))),
+                    None
+                )
+                .unwrap(),
+            Verdict::Accept {
+                annotations: vec!["large deposit".into(), "empty".into()],
+                risk_score_change: 0,
+            }
+        );
+
+        let reloadable = ReloadableRules::new(rules);
+        let deposit = transaction(TransactionType::Deposit, 1, Some(dec!(100)));
+        let registered = reloadable.clone();
+        assert_eq!(
+            registered.validate(&deposit, None).unwrap(),
+            Verdict::Accept {
+                annotations: vec!["empty".into()],
+                risk_score_change: 0,
+            }
+        );
+        reloadable.replace(RuleSet::new(["reject when amount >= 100"]).unwrap());
+        assert!(matches!(
+            registered.validate(&deposit, None).unwrap(),
+            Verdict::Reject { .. }
+        ));
+    }
+
+    #[test]
+    fn test_score_rules() {
+        let rules = RuleSet::new([
+            r#"score +10 when type == "withdrawal" && amount >= 50"#,
+            r#"score -1 when type == "deposit""#,
+            "score 5 when amount > 1000",
+            r#"reject "risky" when risk_score >= 50 && type == "withdrawal""#,
+        ])
+        .unwrap();
+        let transaction = |transaction_type, amount| Transaction {
+            id: 1,
+            transaction_type,
+            client_id: 1,
+            amount: Some(amount),
+            reference: None,
+            reason_code: None,
+            notes: None,
+            sequence: None,
+        };
+        let withdrawal = transaction(TransactionType::Withdrawal, dec!(2000));
+
+        assert_eq!(
+            rules.validate_with_risk(&withdrawal, None, 0).unwrap(),
+            Verdict::Accept {
+                annotations: vec![],
+                risk_score_change: 15,
+            }
+        );
+        assert_eq!(
+            rules
+                .validate_with_risk(&transaction(TransactionType::Deposit, dec!(1)), None, 0)
+                .unwrap(),
+            Verdict::Accept {
+                annotations: vec![],
+                risk_score_change: -1,
+            }
+        );
+        assert_eq!(
+            rules.validate_with_risk(&withdrawal, None, 50).unwrap(),
+            Verdict::Reject {
+                reason: "risky".into()
+            }
+        );
+        // Validating without a score treats it as 0
+        assert!(matches!(
+            ReloadableRules::new(rules)
+                .validate(&withdrawal, None)
+                .unwrap(),
+            Verdict::Accept { .. }
+        ));
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        for rule in [
+            "block when amount > 1",
+            "reject amount > 1",
+            r#"annotate when amount > 1"#,
+            "reject when balance > 1",
+            "reject when amount > \"1\"",
+            "reject when type > \"deposit\"",
+            "reject when (amount > 1",
+            "reject when amount > 1 amount",
+            "reject when amount > 1 & amount < 2",
+            "reject when locked == 1",
+            "reject when amount > available / 2",
+            "score when amount > 1",
+            "score 1.5 when amount > 1",
+            r#"score "high" when amount > 1"#,
+            "reject when risk_score == true",
+        ] {
+            assert!(
+                matches!(
+                    RuleSet::new([rule]),
+                    Err(TransactionError::InvalidArgument(_))
+                ),
+                "{}",
+                rule
+            );
+        }
+    }
+}
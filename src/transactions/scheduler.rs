@@ -0,0 +1,628 @@
+use super::{
+    ExpiredHoldAction, HoldExpiry, Result, TransactionError, TransactionService, WritePriority,
+};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+
+/// When a [`Job`] runs, as a cron expression in UTC: minute, hour, day of the month, month
+/// and day of the week (0 or 7 is Sunday). Fields are `*`, a number, a range like `1-5`, a
+/// step like `*/15` or `0-30/10`, or a comma separated list of those. When both the day of
+/// the month and the day of the week are restricted, either matching is enough, as in cron.
+/// `@hourly`, `@daily`, `@weekly` and `@monthly` are also accepted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    expression: String,
+    minutes: Field,
+    hours: Field,
+    days: Field,
+    months: Field,
+    weekdays: Field,
+}
+
+/// The values a cron field matches, as bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Field {
+    bits: u64,
+    /// Whether the field was `*`.
+    any: bool,
+}
+
+impl Field {
+    fn parse(field: &str, min: u8, max: u8) -> Option<Self> {
+        let mut bits = 0u64;
+        for part in field.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (range, step.parse::<u8>().ok().filter(|s| *s > 0)?),
+                None => (part, 1),
+            };
+            let (low, high) = match range.split_once('-') {
+                _ if range == "*" => (min, max),
+                Some((low, high)) => (low.parse().ok()?, high.parse().ok()?),
+                // `5/15` starts at 5 and runs to the end of the field
+                None if step > 1 => (range.parse().ok()?, max),
+                None => {
+                    let value = range.parse().ok()?;
+                    (value, value)
+                }
+            };
+            if low < min || high > max || low > high {
+                return None;
+            }
+            for value in (low..=high).step_by(step.into()) {
+                bits |= 1 << value;
+            }
+        }
+        Some(Self {
+            bits,
+            any: field == "*",
+        })
+    }
+
+    fn contains(self, value: u8) -> bool {
+        self.bits & (1 << value) != 0
+    }
+}
+
+impl Schedule {
+    /// Parses a cron expression, failing if it is invalid or never matches, e.g. `0 0 30 2 *`.
+    pub fn parse(expression: &str) -> Result<Self> {
+        let invalid =
+            || TransactionError::InvalidArgument(format!("Invalid schedule \"{}\"", expression));
+        let fields = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            fields => fields,
+        };
+        let fields: Vec<&str> = fields.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(invalid());
+        };
+        let mut weekdays = Field::parse(weekdays, 0, 7).ok_or_else(invalid)?;
+        if weekdays.contains(7) {
+            weekdays.bits = (weekdays.bits & !(1 << 7)) | 1;
+        }
+        let schedule = Self {
+            expression: expression.trim().to_string(),
+            minutes: Field::parse(minutes, 0, 59).ok_or_else(invalid)?,
+            hours: Field::parse(hours, 0, 23).ok_or_else(invalid)?,
+            days: Field::parse(days, 1, 31).ok_or_else(invalid)?,
+            months: Field::parse(months, 1, 12).ok_or_else(invalid)?,
+            weekdays,
+        };
+        schedule.next_after(0).ok_or_else(invalid)?;
+        Ok(schedule)
+    }
+
+    /// Every minute, `* * * * *`.
+    pub fn every_minute() -> Self {
+        Self::parse("* * * * *").expect("valid schedule")
+    }
+
+    /// The first time after `unix_millis` the schedule matches, in milliseconds since the unix
+    /// epoch. `None` if it does not match within the next eight years.
+    pub fn next_after(&self, unix_millis: i64) -> Option<i64> {
+        let minute = unix_millis
+            .div_euclid(60_000)
+            .checked_add(1)?
+            .checked_mul(60)?;
+        let mut t = OffsetDateTime::from_unix_timestamp(minute).ok()?;
+        let limit = t.checked_add(time::Duration::days(8 * 366))?;
+        while t < limit {
+            let midnight = t.replace_time(time::Time::MIDNIGHT);
+            if !self.months.contains(t.month() as u8) {
+                let (year, month) = match t.month() {
+                    time::Month::December => (t.year() + 1, time::Month::January),
+                    month => (t.year(), month.next()),
+                };
+                t = midnight.replace_date(time::Date::from_calendar_date(year, month, 1).ok()?);
+            } else if !self.matches_day(t.date()) {
+                t = midnight.checked_add(time::Duration::DAY)?;
+            } else if !self.hours.contains(t.hour()) {
+                t = t
+                    .replace_minute(0)
+                    .ok()?
+                    .checked_add(time::Duration::HOUR)?;
+            } else if !self.minutes.contains(t.minute()) {
+                t = t.checked_add(time::Duration::MINUTE)?;
+            } else {
+                return Some(t.unix_timestamp() * 1000);
+            }
+        }
+        None
+    }
+
+    fn matches_day(&self, date: time::Date) -> bool {
+        let day = self.days.contains(date.day());
+        let weekday = self
+            .weekdays
+            .contains(date.weekday().number_days_from_sunday());
+        match (self.days.any, self.weekdays.any) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+/// A periodic task run by a [`Scheduler`]. The ledger does not accrue interest, so there is no
+/// interest job.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Job {
+    /// [`TransactionService::expire_holds`].
+    ExpireHolds(HoldExpiry),
+    /// [`TransactionService::release_settlements`].
+    ReleaseSettlements,
+    /// Logs the number of clients that have not made a transaction for `inactive_for`, see
+    /// [`TransactionService::get_dormant_clients`].
+    DormancyCheck { inactive_for: Duration },
+    /// Closes the UTC date before the one the job runs on with
+    /// [`TransactionService::close_day`]. Needs the audit log.
+    CloseDay,
+    /// [`TransactionService::snapshot_balances`]. Needs the audit log.
+    SnapshotBalances,
+}
+
+impl Job {
+    /// The name of the job in the schedule file and the metrics.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ExpireHolds(_) => "expire_holds",
+            Self::ReleaseSettlements => "release_settlements",
+            Self::DormancyCheck { .. } => "dormancy_check",
+            Self::CloseDay => "close_day",
+            Self::SnapshotBalances => "snapshot_balances",
+        }
+    }
+
+    /// Runs the job once, returning the number of disputes, deposits, clients or dates it
+    /// handled. Balance snapshots count the clients they recorded.
+    pub async fn run(&self, svc: &TransactionService) -> Result<u64> {
+        let count = match self {
+            Self::ExpireHolds(expiry) => svc.expire_holds(expiry).await?.len(),
+            Self::ReleaseSettlements => svc.release_settlements().await?.len(),
+            Self::DormancyCheck { inactive_for } => {
+                let dormant = svc.get_dormant_clients(*inactive_for).await?.len();
+                tracing::info!(dormant, "Checked for dormant clients");
+                dormant
+            }
+            Self::CloseDay => {
+                let now =
+                    OffsetDateTime::from_unix_timestamp(svc.clock().unix_millis().div_euclid(1000))
+                        .map_err(|e| TransactionError::InvalidArgument(e.to_string()))?;
+                let yesterday = now.date().previous_day().ok_or_else(|| {
+                    TransactionError::InvalidArgument(format!("No date before {}", now.date()))
+                })?;
+                svc.close_day(yesterday).await?;
+                1
+            }
+            Self::SnapshotBalances => svc.snapshot_balances().await?.clients as usize,
+        };
+        Ok(count as u64)
+    }
+}
+
+/// How a [`Scheduler`]'s job has run since the scheduler was built.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobMetrics {
+    pub job: &'static str,
+    pub runs: u64,
+    /// Runs that failed. A failed job runs again at its next scheduled time.
+    pub failures: u64,
+    /// The number of items the last successful run handled, see [`Job::run`].
+    pub last_count: u64,
+    pub last_duration: Duration,
+    /// In milliseconds since the unix epoch.
+    pub last_run_at: Option<i64>,
+    /// In milliseconds since the unix epoch, unset until the scheduler first runs.
+    pub next_run_at: Option<i64>,
+}
+
+#[derive(Debug)]
+struct ScheduledJob {
+    job: Job,
+    schedule: Schedule,
+    metrics: Mutex<JobMetrics>,
+}
+
+/// Runs [`Job`]s on their [`Schedule`]s, one at a time, against the time of the service's
+/// [`Clock`](super::Clock). Jobs first run at their next scheduled time after the scheduler
+/// starts, and a job that is still running when it falls due again skips that run.
+///
+/// Schedules are usually loaded from a toml file with a table per job:
+///
+/// ```toml
+/// [jobs.expire_holds]
+/// schedule = "* * * * *"
+/// max_age_days = 90
+/// action = "escalate"
+///
+/// [jobs.release_settlements]
+/// schedule = "*/5 * * * *"
+///
+/// [jobs.dormancy_check]
+/// schedule = "0 6 * * 1"
+/// inactive_days = 365
+///
+/// [jobs.close_day]
+/// schedule = "5 0 * * *"
+///
+/// [jobs.snapshot_balances]
+/// schedule = "@hourly"
+/// ```
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    jobs: Vec<ScheduledJob>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScheduleFile {
+    #[serde(default)]
+    jobs: BTreeMap<JobName, JobFile>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum JobName {
+    ExpireHolds,
+    ReleaseSettlements,
+    DormancyCheck,
+    CloseDay,
+    SnapshotBalances,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobFile {
+    schedule: String,
+    max_age_days: Option<u64>,
+    action: Option<ExpiredHoldAction>,
+    inactive_days: Option<u64>,
+}
+
+fn days(days: u64) -> Duration {
+    Duration::from_secs(days.saturating_mul(24 * 60 * 60))
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `job` on `schedule`, replacing a job with the same [`Job::name`].
+    pub fn job(mut self, job: Job, schedule: Schedule) -> Self {
+        self.jobs
+            .retain(|scheduled| scheduled.job.name() != job.name());
+        let metrics = Mutex::new(JobMetrics {
+            job: job.name(),
+            runs: 0,
+            failures: 0,
+            last_count: 0,
+            last_duration: Duration::ZERO,
+            last_run_at: None,
+            next_run_at: None,
+        });
+        self.jobs.push(ScheduledJob {
+            job,
+            schedule,
+            metrics,
+        });
+        self
+    }
+
+    /// Whether a job named `name` is scheduled.
+    pub fn contains(&self, name: &str) -> bool {
+        self.jobs
+            .iter()
+            .any(|scheduled| scheduled.job.name() == name)
+    }
+
+    /// Parses a toml schedule document.
+    pub fn from_toml(toml: &str) -> Result<Self> {
+        let invalid = |reason: String| TransactionError::InvalidArgument(reason);
+        let file: ScheduleFile =
+            toml::from_str(toml).map_err(|e| invalid(format!("Invalid schedule file: {}", e)))?;
+        let mut scheduler = Self::new();
+        for (name, job) in file.jobs {
+            let schedule = Schedule::parse(&job.schedule)?;
+            let job = match (name, job) {
+                (
+                    JobName::ExpireHolds,
+                    JobFile {
+                        max_age_days: Some(max_age_days),
+                        action,
+                        inactive_days: None,
+                        ..
+                    },
+                ) => Job::ExpireHolds(
+                    HoldExpiry::new(days(max_age_days)).action(action.unwrap_or_default()),
+                ),
+                (
+                    JobName::ReleaseSettlements,
+                    JobFile {
+                        max_age_days: None,
+                        action: None,
+                        inactive_days: None,
+                        ..
+                    },
+                ) => Job::ReleaseSettlements,
+                (
+                    JobName::DormancyCheck,
+                    JobFile {
+                        max_age_days: None,
+                        action: None,
+                        inactive_days: Some(inactive_days),
+                        ..
+                    },
+                ) => Job::DormancyCheck {
+                    inactive_for: days(inactive_days),
+                },
+                (
+                    JobName::CloseDay,
+                    JobFile {
+                        max_age_days: None,
+                        action: None,
+                        inactive_days: None,
+                        ..
+                    },
+                ) => Job::CloseDay,
+                (
+                    JobName::SnapshotBalances,
+                    JobFile {
+                        max_age_days: None,
+                        action: None,
+                        inactive_days: None,
+                        ..
+                    },
+                ) => Job::SnapshotBalances,
+                (name, _) => {
+                    return Err(invalid(format!(
+                        "Invalid settings for {:?}: expire_holds takes max_age_days and an \
+                        optional action, dormancy_check takes inactive_days, and the other jobs \
+                        only a schedule",
+                        name
+                    )))
+                }
+            };
+            scheduler = scheduler.job(job, schedule);
+        }
+        Ok(scheduler)
+    }
+
+    /// Reads a toml schedule file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    /// The metrics of every job, in the order they were added.
+    pub fn metrics(&self) -> Vec<JobMetrics> {
+        self.jobs
+            .iter()
+            .map(|scheduled| scheduled.metrics.lock().unwrap().clone())
+            .collect()
+    }
+
+    /// Runs the jobs that are due, returning how many ran. Failures are logged and counted in
+    /// the job's metrics. The first call only schedules the jobs' first runs.
+    pub async fn run_due(&self, svc: &TransactionService) -> usize {
+        let now = svc.clock().unix_millis();
+        let mut ran = 0;
+        for scheduled in &self.jobs {
+            let due = {
+                let mut metrics = scheduled.metrics.lock().unwrap();
+                match metrics.next_run_at {
+                    Some(next_run_at) => next_run_at <= now,
+                    None => {
+                        metrics.next_run_at = scheduled.schedule.next_after(now);
+                        false
+                    }
+                }
+            };
+            if !due {
+                continue;
+            }
+
+            let name = scheduled.job.name();
+            let started = Instant::now();
+            let result = WritePriority::Bulk.scope(scheduled.job.run(svc)).await;
+            let elapsed = started.elapsed();
+            let mut metrics = scheduled.metrics.lock().unwrap();
+            metrics.runs += 1;
+            metrics.last_duration = elapsed;
+            metrics.last_run_at = Some(now);
+            match result {
+                Ok(count) => {
+                    metrics.last_count = count;
+                    tracing::debug!(job = name, count, ?elapsed, "Ran scheduled job");
+                }
+                Err(e) => {
+                    metrics.failures += 1;
+                    tracing::error!(job = name, error = %e, "Scheduled job failed");
+                }
+            }
+            // Runs missed while the job was running are skipped
+            metrics.next_run_at = scheduled
+                .schedule
+                .next_after(now.max(svc.clock().unix_millis()));
+            ran += 1;
+        }
+        ran
+    }
+
+    /// Runs the jobs as they fall due, forever. Checks at least every `max_wait`, so changes
+    /// to the service's clock are picked up.
+    pub async fn run(&self, svc: &TransactionService, max_wait: Duration) {
+        loop {
+            self.run_due(svc).await;
+            let now = svc.clock().unix_millis();
+            let wait = self
+                .metrics()
+                .iter()
+                .filter_map(|metrics| metrics.next_run_at)
+                .min()
+                .map_or(max_wait, |next| {
+                    Duration::from_millis(next.saturating_sub(now).max(0) as u64)
+                });
+            tokio::time::sleep(wait.min(max_wait)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ManualClock, Transaction, TransactionType};
+    use rust_decimal_macros::dec;
+    use std::time::UNIX_EPOCH;
+
+    fn at(timestamp: &str) -> i64 {
+        let format = time::format_description::well_known::Rfc3339;
+        (OffsetDateTime::parse(timestamp, &format)
+            .unwrap()
+            .unix_timestamp_nanos()
+            / 1_000_000) as i64
+    }
+
+    #[test]
+    fn test_schedule() {
+        let next = |expression: &str, after: &str| {
+            Schedule::parse(expression).unwrap().next_after(at(after))
+        };
+        // Monday 2024-01-01
+        assert_eq!(
+            next("* * * * *", "2024-01-01T10:00:30Z"),
+            Some(at("2024-01-01T10:01:00Z"))
+        );
+        assert_eq!(
+            next("*/15 9-17 * * *", "2024-01-01T17:50:00Z"),
+            Some(at("2024-01-02T09:00:00Z"))
+        );
+        assert_eq!(
+            next("5 0 * * *", "2024-01-01T00:05:00Z"),
+            Some(at("2024-01-02T00:05:00Z"))
+        );
+        assert_eq!(
+            next("0 6 * * 7", "2024-01-01T00:00:00Z"),
+            Some(at("2024-01-07T06:00:00Z"))
+        );
+        assert_eq!(
+            next("0 0 29 2 *", "2024-03-01T00:00:00Z"),
+            Some(at("2028-02-29T00:00:00Z"))
+        );
+        // Either the 15th or a Friday
+        assert_eq!(
+            next("0 0 15 * 5", "2024-01-01T00:00:00Z"),
+            Some(at("2024-01-05T00:00:00Z"))
+        );
+        assert_eq!(
+            next("@monthly", "2024-12-15T00:00:00Z"),
+            Some(at("2025-01-01T00:00:00Z"))
+        );
+
+        for invalid in [
+            "* * * *",
+            "60 * * * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "0 0 30 2 *",
+        ] {
+            assert!(Schedule::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_scheduler_from_toml() {
+        let scheduler = Scheduler::from_toml(
+            r#"
+            [jobs.expire_holds]
+            schedule = "* * * * *"
+            max_age_days = 90
+            action = "escalate"
+
+            [jobs.close_day]
+            schedule = "5 0 * * *"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            scheduler.jobs[0].job,
+            Job::ExpireHolds(HoldExpiry::new(days(90)).action(ExpiredHoldAction::Escalate))
+        );
+        assert_eq!(scheduler.jobs[1].schedule.to_string(), "5 0 * * *");
+        assert!(!scheduler.contains("release_settlements"));
+
+        for invalid in [
+            "[jobs.expire_holds]\nschedule = \"* * * * *\"",
+            "[jobs.close_day]\nschedule = \"* * * * *\"\ninactive_days = 1",
+            "[jobs.accrue_interest]\nschedule = \"* * * * *\"",
+            "[jobs.close_day]\nschedule = \"daily\"",
+        ] {
+            assert!(Scheduler::from_toml(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scheduler_run_due() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let svc = TransactionService::builder()
+            .audit_log(true)
+            .clock(clock.clone())
+            .build()
+            .await
+            .unwrap();
+        svc.process_transaction(&Transaction {
+            id: 1,
+            transaction_type: TransactionType::Deposit,
+            client_id: 1,
+            amount: Some(dec!(1)),
+            reference: None,
+            reason_code: None,
+            notes: None,
+            sequence: None,
+        })
+        .await
+        .unwrap();
+        let scheduler = Scheduler::new()
+            .job(
+                Job::DormancyCheck {
+                    inactive_for: days(1),
+                },
+                Schedule::every_minute(),
+            )
+            .job(Job::CloseDay, Schedule::parse("*/2 * * * *").unwrap());
+
+        assert_eq!(scheduler.run_due(&svc).await, 0);
+        clock.advance(Duration::from_secs(2 * 60));
+        assert_eq!(scheduler.run_due(&svc).await, 2);
+        assert_eq!(scheduler.run_due(&svc).await, 0);
+        clock.advance(Duration::from_secs(2 * 24 * 60 * 60));
+        assert_eq!(scheduler.run_due(&svc).await, 2);
+
+        let [dormancy, close] = &scheduler.metrics()[..] else {
+            panic!("two jobs");
+        };
+        assert_eq!((dormancy.runs, dormancy.failures), (2, 0));
+        assert_eq!(dormancy.last_count, 1);
+        // The second close is of a later date
+        assert_eq!((close.runs, close.failures), (2, 0));
+        assert!(close.next_run_at > Some(svc.clock().unix_millis()));
+
+        // Closing the same date again fails, and the job runs again at its next time
+        clock.advance(Duration::from_secs(2 * 60));
+        scheduler.run_due(&svc).await;
+        let close = &scheduler.metrics()[1];
+        assert_eq!((close.runs, close.failures), (3, 1));
+    }
+}
@@ -0,0 +1,330 @@
+use super::{Result, TransactionError};
+use sqlx::{Connection, Pool, Sqlite, SqliteConnection};
+use std::collections::BTreeSet;
+
+const SCHEMA: &str = include_str!("../../SCHEMA.sql");
+
+/// The columns added to the tables of version 1 of the schema, as `(table, column, type)`.
+/// Each raises the version by one, and is added to existing databases of an earlier version
+/// when they are opened. New columns go in [`SCHEMA`] too, for new databases.
+const MIGRATIONS: &[(&str, &str, &str)] = &[
+    // Version 2
+    ("Transactions", "created_at", "BIGINT"),
+    // Version 3
+    ("Disputes", "status", "TEXT NOT NULL DEFAULT 'open'"),
+    // Version 4
+    ("Disputes", "assignee", "TEXT"),
+    // Version 5
+    ("Clients", "risk_score", "BIGINT NOT NULL DEFAULT 0"),
+];
+
+/// The version of [`SCHEMA`], stored in the database's `user_version`.
+pub const SCHEMA_VERSION: i64 = 1 + MIGRATIONS.len() as i64;
+
+/// Set in `user_version` alongside the schema version by builds with the `wide-ids` feature,
+/// which store transaction ids offset by `2^63`.
+const WIDE_IDS: i64 = 1 << 16;
+
+/// The transaction id width of this build, as recorded in `user_version`.
+const ID_WIDTH: i64 = if cfg!(feature = "wide-ids") {
+    WIDE_IDS
+} else {
+    0
+};
+
+/// The tables and columns of the database.
+const COLUMNS: &str = "SELECT m.name, p.name FROM sqlite_master m, pragma_table_info(m.name) p
+    WHERE m.type = 'table' AND m.name NOT LIKE 'sqlite_%'";
+
+/// Checks the database is compatible with this version, then creates any missing tables and
+/// records the schema version and transaction id width.
+///
+/// Fails with [`TransactionError::IncompatibleDatabase`] for databases written by a newer
+/// version, for those written before the schema was versioned that are missing columns
+/// added since, rather than once a query needs them, and for those storing transaction ids
+/// of the other width, with or without the `wide-ids` feature.
+pub(super) async fn prepare(pool: &Pool<Sqlite>) -> Result<()> {
+    let (stored,): (i64,) = sqlx::query_as("PRAGMA user_version")
+        .fetch_one(pool)
+        .await?;
+    let version = stored & !WIDE_IDS;
+    let id_width = match stored & WIDE_IDS {
+        // Narrow, or wide but written before the width was recorded
+        0 if stores_wide_ids(pool).await? => WIDE_IDS,
+        0 if stores_transactions(pool).await? => 0,
+        // Either build reads a database without transactions
+        0 => ID_WIDTH,
+        width => width,
+    };
+    if id_width != ID_WIDTH {
+        let width = |id_width| if id_width == WIDE_IDS { 64 } else { 32 };
+        return Err(TransactionError::IncompatibleDatabase(format!(
+            "stores {}-bit transaction ids, this build reads {}-bit ids",
+            width(id_width),
+            width(ID_WIDTH)
+        )));
+    }
+    if version > SCHEMA_VERSION {
+        return Err(TransactionError::IncompatibleDatabase(format!(
+            "written with schema version {}, this version only reads up to {}",
+            version, SCHEMA_VERSION
+        )));
+    }
+    // Written before the schema was versioned, or new
+    let migrated_from = match version {
+        0 if check_unversioned(pool).await? => 1,
+        0 => SCHEMA_VERSION,
+        version => version,
+    };
+    let mut tx = pool.begin().await?;
+    for (table, column, column_type) in &MIGRATIONS[(migrated_from - 1) as usize..] {
+        // Missing tables are created by the schema, with the column
+        let (exists,): (bool,) = sqlx::query_as(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?)",
+        )
+        .bind(table)
+        .fetch_one(&mut *tx)
+        .await?;
+        if !exists {
+            continue;
+        }
+        tracing::info!(table, column, "Adding a column to the database");
+        sqlx::query(&format!(
+            "ALTER TABLE [{}] ADD COLUMN {} {}",
+            table, column, column_type
+        ))
+        .execute(&mut *tx)
+        .await?;
+    }
+    sqlx::query(SCHEMA).execute(&mut *tx).await?;
+    if stored != SCHEMA_VERSION | ID_WIDTH {
+        sqlx::query(&format!(
+            "PRAGMA user_version = {}",
+            SCHEMA_VERSION | ID_WIDTH
+        ))
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Whether the database has transaction ids stored by the `wide-ids` feature, which stores
+/// ids below `2^63` as negative. For databases written before the width was recorded.
+async fn stores_wide_ids(pool: &Pool<Sqlite>) -> Result<bool> {
+    Ok(stores_transactions(pool).await?
+        && sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM Transactions WHERE id < 0)")
+            .fetch_one(pool)
+            .await?)
+}
+
+/// Whether the database has any transactions.
+async fn stores_transactions(pool: &Pool<Sqlite>) -> Result<bool> {
+    let (exists,): (bool,) = sqlx::query_as(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'Transactions')",
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(exists
+        && sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM Transactions)")
+            .fetch_one(pool)
+            .await?)
+}
+
+/// Fails if the tables of a database written before the schema was versioned lack any
+/// columns of version 1 of [`SCHEMA`]. Returns whether it has any tables, new databases have
+/// none.
+async fn check_unversioned(pool: &Pool<Sqlite>) -> Result<bool> {
+    let existing: BTreeSet<(String, String)> = sqlx::query_as(COLUMNS)
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .collect();
+    if existing.is_empty() {
+        return Ok(false);
+    }
+    let mut expected = SqliteConnection::connect("sqlite::memory:").await?;
+    sqlx::query(SCHEMA).execute(&mut expected).await?;
+    let expected: Vec<(String, String)> = sqlx::query_as(COLUMNS).fetch_all(&mut expected).await?;
+
+    let tables: BTreeSet<_> = existing.iter().map(|(table, _)| table).collect();
+    let missing: Vec<_> = expected
+        .iter()
+        .filter(|column| tables.contains(&column.0) && !existing.contains(*column))
+        .filter(|(table, column)| !(MIGRATIONS.iter()).any(|(t, c, _)| t == table && c == column))
+        .map(|(table, column)| format!("{}.{}", table, column))
+        .collect();
+    if !missing.is_empty() {
+        return Err(TransactionError::IncompatibleDatabase(format!(
+            "written by an older version, missing the columns {}",
+            missing.join(", ")
+        )));
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ID_WIDTH, SCHEMA_VERSION, WIDE_IDS};
+    use crate::{Transaction, TransactionError, TransactionService, TransactionType};
+    use rust_decimal_macros::dec;
+    use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
+
+    /// Transaction id 1 as this build stores it.
+    const STORED_ID: i64 = if ID_WIDTH == WIDE_IDS {
+        1 ^ i64::MIN
+    } else {
+        1
+    };
+
+    /// A service over a database set up with `statements`, and the database.
+    async fn build(statements: &str) -> (SqlitePool, crate::Result<TransactionService>) {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::query(statements).execute(&pool).await.unwrap();
+        let svc = TransactionService::builder()
+            .pool(pool.clone())
+            .build()
+            .await;
+        (pool, svc)
+    }
+
+    #[tokio::test]
+    async fn test_schema_preflight() {
+        assert!(build("SELECT 1").await.1.is_ok());
+
+        // Version 1, before transactions recorded when they were made
+        let (pool, svc) = build(&format!(
+            "CREATE TABLE Transactions (id INTEGER PRIMARY KEY, [type] TEXT NOT NULL,
+                client_id INTEGER NOT NULL, amount BIGINT, batch_id INTEGER, line INTEGER,
+                reference TEXT);
+            INSERT INTO Transactions (id, [type], client_id, amount) VALUES ({id}, 'deposit', 1, 1);
+            CREATE TABLE Disputes (transaction_id INTEGER PRIMARY KEY, opened_at BIGINT NOT NULL,
+                escalated_at BIGINT, reason_code TEXT, notes TEXT);
+            INSERT INTO Disputes (transaction_id, opened_at) VALUES ({id}, 0);
+            PRAGMA user_version = 1;",
+            id = STORED_ID
+        ))
+        .await;
+        assert!(svc.is_ok());
+        let (version,): (i64,) = sqlx::query_as("PRAGMA user_version")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(version, SCHEMA_VERSION | ID_WIDTH);
+        let (created_at,): (Option<i64>,) =
+            sqlx::query_as("SELECT created_at FROM Transactions WHERE id = ?")
+                .bind(STORED_ID)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(created_at, None);
+        let (status, assignee): (String, Option<String>) =
+            sqlx::query_as("SELECT status, assignee FROM Disputes WHERE transaction_id = ?")
+                .bind(STORED_ID)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!((status.as_str(), assignee), ("open", None));
+
+        let (_, newer) = build(&format!("PRAGMA user_version = {}", SCHEMA_VERSION + 1)).await;
+        assert!(matches!(
+            newer,
+            Err(TransactionError::IncompatibleDatabase(message)) if message.contains("schema version")
+        ));
+
+        // Written before clients had statistics
+        let (_, older) = build(
+            "CREATE TABLE Clients (id INTEGER PRIMARY KEY, available BIGINT NOT NULL,
+                held BIGINT NOT NULL, locked BOOLEAN NOT NULL)",
+        )
+        .await;
+        let Err(TransactionError::IncompatibleDatabase(message)) = older else {
+            panic!("expected an incompatible database");
+        };
+        assert!(message.contains("Clients.last_activity_at"));
+        assert!(message.contains("Clients.deposits"));
+    }
+
+    #[tokio::test]
+    async fn test_id_width() {
+        let (pool, svc) = build("SELECT 1").await;
+        assert!(svc.is_ok());
+        let (version,): (i64,) = sqlx::query_as("PRAGMA user_version")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(version, SCHEMA_VERSION | ID_WIDTH);
+
+        // Recorded by the other build
+        let other_width = ID_WIDTH ^ WIDE_IDS;
+        let (_, other) = build(&format!(
+            "CREATE TABLE Transactions (id INTEGER PRIMARY KEY);
+            INSERT INTO Transactions (id) VALUES (1);
+            PRAGMA user_version = {};",
+            SCHEMA_VERSION | other_width
+        ))
+        .await;
+        assert!(matches!(
+            other,
+            Err(TransactionError::IncompatibleDatabase(message)) if message.contains("transaction ids")
+        ));
+
+        // Written by the other build before the width was recorded, told by its ids
+        let other_id = if other_width == WIDE_IDS {
+            1 ^ i64::MIN
+        } else {
+            1
+        };
+        let (_, other) = build(&format!(
+            "CREATE TABLE Transactions (id INTEGER PRIMARY KEY);
+            INSERT INTO Transactions (id) VALUES ({});
+            PRAGMA user_version = {};",
+            other_id, SCHEMA_VERSION
+        ))
+        .await;
+        assert!(matches!(
+            other,
+            Err(TransactionError::IncompatibleDatabase(message)) if message.contains("transaction ids")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_held_funds_check() {
+        let (pool, svc) = build("SELECT 1").await;
+        let svc = svc.unwrap();
+        for (id, transaction_type, amount) in [
+            (1, TransactionType::Deposit, Some(dec!(5))),
+            (2, TransactionType::Deposit, Some(dec!(3))),
+            (1, TransactionType::Dispute, None),
+            (1, TransactionType::Chargeback, None),
+        ] {
+            svc.process_transaction(&Transaction {
+                id,
+                transaction_type,
+                client_id: 1,
+                amount,
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            })
+            .await
+            .unwrap();
+        }
+        let client = svc.get_client(1).await.unwrap().unwrap();
+        assert_eq!((client.available, client.held), (dec!(3), dec!(0)));
+        assert_eq!(client.total, dec!(3));
+
+        for statement in [
+            "UPDATE Clients SET held = held - 1 WHERE id = 1",
+            "INSERT INTO Clients (id, available, held, locked) VALUES (2, 1, -1, false)",
+        ] {
+            let error = sqlx::query(statement).execute(&pool).await.unwrap_err();
+            assert!(error.to_string().contains("held >= 0"), "{}", statement);
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use super::{Client, Result, Transaction, TransactionError, TransactionValidator, Verdict};
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+use std::path::Path;
+
+/// The most operations a script can run for one transaction, so a runaway loop fails the
+/// transaction instead of stalling processing.
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// Validates transactions with a [rhai](https://rhai.rs) script.
+///
+/// The script runs before each transaction with these variables:
+/// - `tx`: the transaction, with `id`, `type`, `client` and `amount` (a decimal, or `()`)
+/// - `client`: the client's current `available`, `held`, `total` and `locked`, or `()` for a
+///   new client
+/// - `annotations`: an empty array, strings pushed to it are recorded as
+///   [`Annotation`](super::Annotation)s
+///
+/// Throwing rejects the transaction with the thrown value as the reason, any other script
+/// error fails the transaction.
+///
+/// ```rhai
+/// if tx.type == "withdrawal" && tx.amount > 10000 {
+///     throw "withdrawal over 10000";
+/// }
+/// if client == () {
+///     annotations.push("first transaction");
+/// }
+/// ```
+pub struct ScriptValidator {
+    engine: Engine,
+    ast: AST,
+}
+
+impl ScriptValidator {
+    /// Compiles `script`, failing on syntax errors.
+    pub fn new(script: &str) -> Result<Self> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        let ast = engine
+            .compile(script)
+            .map_err(|e| TransactionError::Script(e.to_string()))?;
+        Ok(Self { engine, ast })
+    }
+
+    /// Reads and compiles the script at `path`.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::new(&std::fs::read_to_string(path)?)
+    }
+}
+
+impl TransactionValidator for ScriptValidator {
+    fn validate(&self, transaction: &Transaction, client: Option<&Client>) -> Result<Verdict> {
+        let mut tx = Map::new();
+        tx.insert("id".into(), Dynamic::from_int(transaction.id as i64));
+        tx.insert(
+            "type".into(),
+            transaction.transaction_type.to_str().to_string().into(),
+        );
+        tx.insert(
+            "client".into(),
+            Dynamic::from_int(transaction.client_id.into()),
+        );
+        tx.insert(
+            "amount".into(),
+            transaction
+                .amount
+                .map_or(Dynamic::UNIT, Dynamic::from_decimal),
+        );
+
+        let client = client.map_or(Dynamic::UNIT, |client| {
+            let mut map = Map::new();
+            map.insert("available".into(), Dynamic::from_decimal(client.available));
+            map.insert("held".into(), Dynamic::from_decimal(client.held));
+            map.insert("total".into(), Dynamic::from_decimal(client.total));
+            map.insert("locked".into(), Dynamic::from_bool(client.locked));
+            map.into()
+        });
+
+        let mut scope = Scope::new();
+        scope.push_constant("tx", tx);
+        scope.push_constant("client", client);
+        scope.push("annotations", Array::new());
+
+        match self.engine.run_ast_with_scope(&mut scope, &self.ast) {
+            Ok(()) => {}
+            Err(e) => match *e {
+                EvalAltResult::ErrorRuntime(reason, _) => {
+                    return Ok(Verdict::Reject {
+                        reason: reason.to_string(),
+                    })
+                }
+                e => return Err(TransactionError::Script(e.to_string())),
+            },
+        }
+
+        let annotations = scope
+            .get_value::<Array>("annotations")
+            .unwrap_or_default()
+            .into_iter()
+            .map(|a| a.to_string())
+            .collect();
+        Ok(Verdict::Accept {
+            annotations,
+            risk_score_change: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScriptValidator;
+    use crate::{
+        Client, Transaction, TransactionError, TransactionType, TransactionValidator, Verdict,
+    };
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_script_validator() {
+        let validator = ScriptValidator::new(
+            r#"
+            if tx.type == "withdrawal" && tx.amount > client.available / 2 {
+                throw `withdrawal of ${tx.amount} is over half the balance`;
+            }
+            if client == () {
+                annotations.push("first transaction");
+            }
+            "#,
+        )
+        .unwrap();
+        let transaction = |transaction_type, amount| Transaction {
+            id: 1,
+            transaction_type,
+            client_id: 1,
+            amount: Some(amount),
+            reference: None,
+            reason_code: None,
+            notes: None,
+            sequence: None,
+        };
+        let client = Client {
+            id: 1,
+            available: dec!(10),
+            held: dec!(0),
+            total: dec!(10),
+            locked: false,
+        };
+
+        assert_eq!(
+            validator
+                .validate(&transaction(TransactionType::Deposit, dec!(10)), None)
+                .unwrap(),
+            Verdict::Accept {
+                annotations: vec!["first transaction".into()],
+                risk_score_change: 0,
+            }
+        );
+        assert_eq!(
+            validator
+                .validate(
+                    &transaction(TransactionType::Withdrawal, dec!(5)),
+                    Some(&client)
+                )
+                .unwrap(),
+            Verdict::accept()
+        );
+        assert_eq!(
+            validator
+                .validate(
+                    &transaction(TransactionType::Withdrawal, dec!(5.5)),
+                    Some(&client)
+                )
+                .unwrap(),
+            Verdict::Reject {
+                reason: "withdrawal of 5.5 is over half the balance".into()
+            }
+        );
+
+        let looping = ScriptValidator::new("loop {}").unwrap();
+        assert!(matches!(
+            looping.validate(&transaction(TransactionType::Deposit, dec!(1)), None),
+            Err(TransactionError::Script(_))
+        ));
+        assert!(matches!(
+            ScriptValidator::new("if {"),
+            Err(TransactionError::Script(_))
+        ));
+    }
+}
@@ -0,0 +1,311 @@
+use super::{
+    Client, ClientId, ClientWithStats, ProcessingOutcome, Result, Transaction, TransactionError,
+    TransactionOutcome, TransactionService,
+};
+use futures::channel::mpsc;
+use futures::{SinkExt, Stream, StreamExt};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::pin;
+use std::sync::Mutex;
+
+/// The transactions buffered for each shard while the others catch up.
+const SHARD_CHANNEL_CAPACITY: usize = 1024;
+
+/// Clients spread over several [`TransactionService`]s by a hash of their id, each with a
+/// database of its own so they can write at the same time. Build it with
+/// [`TransactionServiceBuilder::build_sharded`](super::TransactionServiceBuilder::build_sharded).
+///
+/// A client's transactions always go to the same shard, as long as the number of shards does
+/// not change, so disputes find the transactions they refer to. Transaction ids are only
+/// checked for duplicates within a shard.
+pub struct ShardedTransactionService {
+    shards: Vec<TransactionService>,
+}
+
+impl ShardedTransactionService {
+    /// Shards clients over `shards`, in order. Fails if there are none.
+    pub fn new(shards: Vec<TransactionService>) -> Result<Self> {
+        if shards.is_empty() {
+            return Err(TransactionError::InvalidArgument(
+                "At least one shard is needed".into(),
+            ));
+        }
+        Ok(Self { shards })
+    }
+
+    pub fn shards(&self) -> &[TransactionService] {
+        &self.shards
+    }
+
+    /// The index of the shard holding `client_id`.
+    pub fn shard_index(&self, client_id: ClientId) -> usize {
+        // Stable across runs and platforms, unlike the std hasher
+        let hash = (client_id as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32;
+        (hash % self.shards.len() as u64) as usize
+    }
+
+    /// The shard holding `client_id`.
+    pub fn shard(&self, client_id: ClientId) -> &TransactionService {
+        &self.shards[self.shard_index(client_id)]
+    }
+
+    /// Applies a single transaction in the shard of its client.
+    pub async fn process_transaction(
+        &self,
+        transaction: &Transaction,
+    ) -> Result<TransactionOutcome> {
+        self.shard(transaction.client_id)
+            .process_transaction(transaction)
+            .await
+    }
+
+    /// Like [`TransactionService::process_stream`], applying the transactions of each shard at
+    /// the same time.
+    ///
+    /// When the stream or a shard fails, every shard rolls back its current batch and the first
+    /// error is returned.
+    pub async fn process_stream<S>(&self, transactions: S) -> Result<ProcessingOutcome>
+    where
+        S: Stream<Item = Result<Transaction>>,
+    {
+        self.route(
+            transactions.map(|t| t.map(|t| (0, t))),
+            |index, transactions| {
+                self.shards[index].process_stream(transactions.map(|t| t.map(|(_, t)| t)))
+            },
+        )
+        .await
+    }
+
+    /// Like [`TransactionService::process_batch_with`], starting a batch read from `source` in
+    /// every shard. `on_outcome` is called in the order the shards commit, not the order of the
+    /// stream.
+    pub async fn process_batch_with<S, F>(
+        &self,
+        source: &str,
+        sha256: Option<&str>,
+        transactions: S,
+        on_outcome: F,
+    ) -> Result<ProcessingOutcome>
+    where
+        S: Stream<Item = Result<(u64, Transaction)>>,
+        F: FnMut(u64, &Transaction, &TransactionOutcome) -> Result<()>,
+    {
+        let mut batches = Vec::with_capacity(self.shards.len());
+        for shard in &self.shards {
+            batches.push(shard.begin_batch(source, sha256).await?);
+        }
+        let on_outcome = Mutex::new(on_outcome);
+        let on_outcome = &on_outcome;
+        let batches = &batches;
+        self.route(transactions, |index, transactions| async move {
+            self.shards[index]
+                .process_batch_with(&batches[index], transactions, |line, t, outcome| {
+                    (on_outcome.lock().unwrap())(line, t, outcome)
+                })
+                .await
+        })
+        .await
+    }
+
+    /// Sends each transaction to the shard of its client, processing the shards with
+    /// `process` at the same time.
+    async fn route<S, P, Fut>(&self, transactions: S, process: P) -> Result<ProcessingOutcome>
+    where
+        S: Stream<Item = Result<(u64, Transaction)>>,
+        P: Fn(usize, mpsc::Receiver<Result<(u64, Transaction)>>) -> Fut,
+        Fut: Future<Output = Result<ProcessingOutcome>>,
+    {
+        let (mut senders, receivers): (Vec<_>, Vec<_>) = self
+            .shards
+            .iter()
+            .map(|_| mpsc::channel(SHARD_CHANNEL_CAPACITY))
+            .unzip();
+        let shards = futures::future::join_all(
+            receivers
+                .into_iter()
+                .enumerate()
+                .map(|(index, receiver)| process(index, receiver)),
+        );
+        let routed = async move {
+            let mut transactions = pin!(transactions);
+            let failed = loop {
+                match transactions.next().await {
+                    Some(Ok(transaction)) => {
+                        let shard = self.shard_index(transaction.1.client_id);
+                        // The shard stopped with an error, which it returns
+                        if senders[shard].send(Ok(transaction)).await.is_err() {
+                            break None;
+                        }
+                    }
+                    Some(Err(e)) => break Some(e),
+                    None => return Ok(()),
+                }
+            };
+            // Roll back the current batch of the other shards
+            for sender in &mut senders {
+                let _ = sender
+                    .send(Err(TransactionError::Source(
+                        "processing stopped in another shard".into(),
+                    )))
+                    .await;
+            }
+            failed.map_or(Ok(()), Err)
+        };
+
+        let (routed, outcomes) = futures::join!(routed, shards);
+        routed?;
+        let mut total = ProcessingOutcome::default();
+        let mut failed = None;
+        for outcome in outcomes {
+            match outcome {
+                Ok(outcome) => total.merge(&outcome),
+                // Stopped by the error of another shard, which is returned instead
+                Err(TransactionError::Source(_)) => {}
+                Err(e) => {
+                    failed.get_or_insert(e);
+                }
+            }
+        }
+        failed.map_or(Ok(total), Err)
+    }
+
+    /// Gets a single client by id from its shard.
+    pub async fn get_client(&self, client_id: ClientId) -> Result<Option<Client>> {
+        self.shard(client_id).get_client(client_id).await
+    }
+
+    /// Collects the clients of every shard, ordered by client id.
+    pub async fn get_clients_vec(&self) -> Result<Vec<Client>> {
+        let mut clients = Vec::new();
+        for shard in &self.shards {
+            clients.extend(shard.get_clients_vec().await?);
+        }
+        clients.sort_by_key(|c| c.id);
+        Ok(clients)
+    }
+
+    /// Collects the clients of every shard with their statistics, ordered by client id.
+    pub async fn get_client_stats(&self) -> Result<Vec<ClientWithStats>> {
+        let mut clients = Vec::new();
+        for shard in &self.shards {
+            clients.extend(shard.get_client_stats().await?);
+        }
+        clients.sort_by_key(|c| c.client.id);
+        Ok(clients)
+    }
+
+    /// The transactions applied, rejected and ignored by every shard, by transaction type.
+    pub fn transaction_counts(&self) -> BTreeMap<String, ProcessingOutcome> {
+        let mut counts = BTreeMap::<String, ProcessingOutcome>::new();
+        for shard in &self.shards {
+            for (transaction_type, outcome) in shard.transaction_counts() {
+                counts.entry(transaction_type).or_default().merge(&outcome);
+            }
+        }
+        counts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        Transaction, TransactionError, TransactionId, TransactionService, TransactionType,
+    };
+    use rust_decimal_macros::dec;
+
+    fn transactions() -> Vec<Transaction> {
+        let transaction = |id, transaction_type, client_id, amount| Transaction {
+            id,
+            transaction_type,
+            client_id,
+            amount,
+            reference: None,
+            reason_code: None,
+            notes: None,
+            sequence: None,
+        };
+        let mut transactions: Vec<_> = (1..=20)
+            .map(|client| {
+                transaction(
+                    TransactionId::from(client),
+                    TransactionType::Deposit,
+                    client,
+                    Some(dec!(10)),
+                )
+            })
+            .collect();
+        transactions.extend((1..=20).map(|client| {
+            transaction(
+                100 + TransactionId::from(client),
+                TransactionType::Withdrawal,
+                client,
+                Some(dec!(3)),
+            )
+        }));
+        transactions.push(transaction(7, TransactionType::Dispute, 7, None));
+        transactions.push(transaction(7, TransactionType::Chargeback, 7, None));
+        transactions.push(transaction(
+            200,
+            TransactionType::Withdrawal,
+            9,
+            Some(dec!(50)),
+        ));
+        transactions
+    }
+
+    #[tokio::test]
+    async fn test_sharded_processing() {
+        let sharded = TransactionService::builder()
+            .batch_size(4)
+            .build_sharded(3)
+            .await
+            .unwrap();
+        let outcome = sharded
+            .process_stream(futures::stream::iter(transactions().into_iter().map(Ok)))
+            .await
+            .unwrap();
+        assert_eq!(
+            (outcome.processed, outcome.applied, outcome.rejected),
+            (43, 42, 1)
+        );
+        let counts = sharded.transaction_counts();
+        assert_eq!(counts["withdrawal"].rejected, 1);
+
+        let single = TransactionService::builder().build().await.unwrap();
+        single
+            .process_stream(futures::stream::iter(transactions().into_iter().map(Ok)))
+            .await
+            .unwrap();
+        assert_eq!(
+            sharded.get_clients_vec().await.unwrap(),
+            single.get_clients_vec().await.unwrap()
+        );
+        assert!(sharded.get_client(7).await.unwrap().unwrap().locked);
+        for shard in sharded.shards() {
+            let clients = shard.get_clients_vec().await.unwrap();
+            assert!(!clients.is_empty());
+            assert!(clients
+                .iter()
+                .all(|c| std::ptr::eq(sharded.shard(c.id), shard)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sharded_failure() {
+        let sharded = TransactionService::builder()
+            .build_sharded(2)
+            .await
+            .unwrap();
+        let transactions = transactions().into_iter().take(10).map(Ok).chain([Err(
+            TransactionError::InvalidArgument("broken input".into()),
+        )]);
+        let result = sharded
+            .process_stream(futures::stream::iter(transactions))
+            .await;
+        assert!(matches!(result, Err(TransactionError::InvalidArgument(_))));
+        // Every shard rolled back the batch it was processing
+        assert_eq!(sharded.get_clients_vec().await.unwrap(), []);
+    }
+}
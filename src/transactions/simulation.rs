@@ -0,0 +1,382 @@
+use super::{
+    Client, ClientId, EventObserver, ProcessingOutcome, Result, Transaction, TransactionId,
+    TransactionServiceBuilder, TransactionType,
+};
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const DEFAULT_TRANSACTIONS: u32 = 10_000;
+const DEFAULT_CLIENTS: u16 = 100;
+
+/// Runs a generated workload through a [`TransactionService`](super::TransactionService) and
+/// checks the resulting ledger.
+///
+/// The workload is a mix of deposits, withdrawals (some for more than the client has) and
+/// disputes, resolves and chargebacks of earlier transactions, including disputes of unknown
+/// transactions. The same seed always generates the same workload.
+///
+/// ```no_run
+/// # async fn run() -> transaction_app::Result<()> {
+/// use transaction_app::{Simulation, TransactionService};
+///
+/// let report = Simulation::new(42)
+///     .run(TransactionService::builder())
+///     .await?;
+/// assert!(report.violations.is_empty(), "{report}");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    seed: u64,
+    transactions: u32,
+    clients: u16,
+}
+
+impl Simulation {
+    /// A simulation of 10000 transactions over 100 clients.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            transactions: DEFAULT_TRANSACTIONS,
+            clients: DEFAULT_CLIENTS,
+        }
+    }
+
+    pub fn transactions(mut self, transactions: u32) -> Self {
+        self.transactions = transactions;
+        self
+    }
+
+    pub fn clients(mut self, clients: u16) -> Self {
+        self.clients = clients.max(1);
+        self
+    }
+
+    /// Generates the workload.
+    pub fn workload(&self) -> Vec<Transaction> {
+        let mut rng = XorShift::new(self.seed);
+        // Deposits and withdrawals by client, and the ones currently disputed
+        let mut history: Vec<Vec<TransactionId>> = vec![Vec::new(); self.clients.into()];
+        let mut disputed: Vec<Vec<TransactionId>> = vec![Vec::new(); self.clients.into()];
+        let mut next_id = 1;
+        let mut workload = Vec::with_capacity(self.transactions as usize);
+
+        while workload.len() < self.transactions as usize {
+            let client = rng.below(self.clients.into()) as usize;
+            let client_id = client as ClientId;
+            let amount = Some(Decimal::new(rng.below(100_000) as i64 + 1, 2));
+            let roll = rng.below(100);
+            let transaction = match roll {
+                0..=69 => {
+                    let transaction_type = if roll < 45 {
+                        TransactionType::Deposit
+                    } else {
+                        TransactionType::Withdrawal
+                    };
+                    history[client].push(next_id);
+                    next_id += 1;
+                    Transaction {
+                        id: next_id - 1,
+                        transaction_type,
+                        client_id,
+                        amount,
+                        reference: None,
+                        reason_code: None,
+                        notes: None,
+                        sequence: None,
+                    }
+                }
+                70..=84 => {
+                    let candidates: Vec<TransactionId> = history[client]
+                        .iter()
+                        .copied()
+                        .filter(|id| !disputed[client].contains(id))
+                        .collect();
+                    // Sometimes dispute a transaction that does not exist
+                    let id = if candidates.is_empty() || rng.below(10) == 0 {
+                        TransactionId::MAX - rng.below(1000) as TransactionId
+                    } else {
+                        let id = candidates[rng.below(candidates.len() as u64) as usize];
+                        disputed[client].push(id);
+                        id
+                    };
+                    Transaction {
+                        id,
+                        transaction_type: TransactionType::Dispute,
+                        client_id,
+                        amount: None,
+                        reference: None,
+                        reason_code: None,
+                        notes: None,
+                        sequence: None,
+                    }
+                }
+                _ if disputed[client].is_empty() => continue,
+                _ => {
+                    let open = &mut disputed[client];
+                    let id = open.swap_remove(rng.below(open.len() as u64) as usize);
+                    // Chargebacks lock the client, keep them rare so most clients stay active
+                    let transaction_type = if roll == 99 && rng.below(10) == 0 {
+                        TransactionType::Chargeback
+                    } else {
+                        TransactionType::Resolve
+                    };
+                    Transaction {
+                        id,
+                        transaction_type,
+                        client_id,
+                        amount: None,
+                        reference: None,
+                        reason_code: None,
+                        notes: None,
+                        sequence: None,
+                    }
+                }
+            };
+            workload.push(transaction);
+        }
+        workload
+    }
+
+    /// Processes the workload with a service from `builder` and checks every client against
+    /// the applied outcomes:
+    /// - `total` is `available + held`
+    /// - `held` is never negative and is the sum of the client's open disputes
+    /// - `total` is the applied deposits, less applied withdrawals and chargebacks
+    /// - only clients with a chargeback are locked
+    pub async fn run(&self, builder: TransactionServiceBuilder) -> Result<SimulationReport> {
+        let workload = self.workload();
+        let expected = ExpectedLedger::default();
+        let svc = builder.observer(expected.clone()).build().await?;
+
+        let start = Instant::now();
+        let outcome = svc
+            .process_stream(futures::stream::iter(workload.into_iter().map(Ok)))
+            .await?;
+        let elapsed = start.elapsed();
+
+        let clients = svc.get_clients_vec().await?;
+        let violations = expected.check(&clients);
+        Ok(SimulationReport {
+            seed: self.seed,
+            outcome,
+            clients: clients.len(),
+            locked_clients: clients.iter().filter(|c| c.locked).count(),
+            digest: digest(&clients),
+            elapsed,
+            violations,
+        })
+    }
+}
+
+/// The result of [`Simulation::run`].
+#[derive(Debug, Clone)]
+pub struct SimulationReport {
+    pub seed: u64,
+    pub outcome: ProcessingOutcome,
+    pub clients: usize,
+    pub locked_clients: usize,
+    /// A hash of every client's final state. Runs with the same seed produce the same digest.
+    pub digest: u64,
+    pub elapsed: Duration,
+    /// A description of every broken invariant, empty if the ledger is consistent.
+    pub violations: Vec<String>,
+}
+
+impl SimulationReport {
+    pub fn transactions_per_second(&self) -> f64 {
+        self.outcome.processed as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl fmt::Display for SimulationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "seed:         {}", self.seed)?;
+        writeln!(
+            f,
+            "transactions: {} ({} applied, {} rejected, {} ignored)",
+            self.outcome.processed,
+            self.outcome.applied,
+            self.outcome.rejected,
+            self.outcome.ignored
+        )?;
+        writeln!(
+            f,
+            "clients:      {} ({} locked)",
+            self.clients, self.locked_clients
+        )?;
+        writeln!(f, "digest:       {:016x}", self.digest)?;
+        writeln!(
+            f,
+            "elapsed:      {:.3}s ({:.0} transactions/s)",
+            self.elapsed.as_secs_f64(),
+            self.transactions_per_second()
+        )?;
+        if self.violations.is_empty() {
+            write!(f, "invariants:   ok")
+        } else {
+            write!(f, "invariants:   {} violated", self.violations.len())?;
+            for violation in &self.violations {
+                write!(f, "\n  {}", violation)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct ExpectedClient {
+    total: Decimal,
+    held: Decimal,
+    locked: bool,
+}
+
+/// Tracks what each client's balance should be from the committed outcomes.
+#[derive(Clone, Default)]
+struct ExpectedLedger(Arc<Mutex<BTreeMap<ClientId, ExpectedClient>>>);
+
+impl ExpectedLedger {
+    fn update(&self, client_id: ClientId, f: impl FnOnce(&mut ExpectedClient)) {
+        f(self.0.lock().unwrap().entry(client_id).or_default());
+    }
+
+    fn check(&self, clients: &[Client]) -> Vec<String> {
+        let expected = self.0.lock().unwrap();
+        let mut violations = Vec::new();
+        let mut seen = HashSet::new();
+        for client in clients {
+            seen.insert(client.id);
+            let mut violation = |message: String| {
+                violations.push(format!("client {}: {}", client.id, message));
+            };
+            if client.total != client.available + client.held {
+                violation(format!(
+                    "total {} is not available {} + held {}",
+                    client.total, client.available, client.held
+                ));
+            }
+            if client.held < Decimal::ZERO {
+                violation(format!("negative held {}", client.held));
+            }
+            let default = ExpectedClient::default();
+            let expected = expected.get(&client.id).unwrap_or(&default);
+            if client.total != expected.total {
+                violation(format!(
+                    "total {}, expected {}",
+                    client.total, expected.total
+                ));
+            }
+            if client.held != expected.held {
+                violation(format!("held {}, expected {}", client.held, expected.held));
+            }
+            if client.locked != expected.locked {
+                violation(format!(
+                    "locked {}, expected {}",
+                    client.locked, expected.locked
+                ));
+            }
+        }
+        for client_id in expected.keys().filter(|id| !seen.contains(id)) {
+            violations.push(format!("client {}: missing", client_id));
+        }
+        violations
+    }
+}
+
+impl EventObserver for ExpectedLedger {
+    fn on_deposit(&self, deposit: &Transaction) {
+        let amount = deposit.amount.unwrap_or_default();
+        self.update(deposit.client_id, |c| c.total += amount);
+    }
+
+    fn on_withdrawal(&self, withdrawal: &Transaction) {
+        let amount = withdrawal.amount.unwrap_or_default();
+        self.update(withdrawal.client_id, |c| c.total -= amount);
+    }
+
+    fn on_dispute_opened(&self, disputed: &Transaction) {
+        let amount = disputed.amount.unwrap_or_default();
+        self.update(disputed.client_id, |c| c.held += amount);
+    }
+
+    fn on_dispute_resolved(&self, disputed: &Transaction) {
+        let amount = disputed.amount.unwrap_or_default();
+        self.update(disputed.client_id, |c| c.held -= amount);
+    }
+
+    fn on_chargeback(&self, disputed: &Transaction) {
+        let amount = disputed.amount.unwrap_or_default();
+        self.update(disputed.client_id, |c| {
+            c.held -= amount;
+            c.total -= amount;
+            c.locked = true;
+        });
+    }
+}
+
+/// FNV-1a over the clients in id order, stable across platforms and releases.
+fn digest(clients: &[Client]) -> u64 {
+    let mut clients: Vec<_> = clients.iter().collect();
+    clients.sort_by_key(|c| c.id);
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for client in clients {
+        let line = format!(
+            "{},{},{},{}\n",
+            client.id, client.available, client.held, client.locked
+        );
+        for byte in line.bytes() {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+/// A small deterministic generator, so a seed always gives the same workload.
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        // xorshift gets stuck on zero
+        Self(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % bound
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Simulation;
+    use crate::TransactionService;
+
+    #[tokio::test]
+    async fn test_simulation() {
+        let simulation = Simulation::new(42).transactions(2000).clients(20);
+        assert_eq!(simulation.workload(), simulation.workload());
+        assert_ne!(
+            simulation.workload(),
+            Simulation::new(43)
+                .transactions(2000)
+                .clients(20)
+                .workload()
+        );
+
+        let report = simulation.run(TransactionService::builder()).await.unwrap();
+        assert!(report.violations.is_empty(), "{report}");
+        assert_eq!(report.outcome.processed, 2000);
+        assert!(report.outcome.rejected > 0 && report.outcome.ignored > 0);
+        assert!(report.locked_clients > 0);
+
+        let again = simulation.run(TransactionService::builder()).await.unwrap();
+        assert_eq!(again.digest, report.digest);
+    }
+}
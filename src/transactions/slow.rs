@@ -0,0 +1,73 @@
+use std::time::{Duration, Instant};
+
+/// The time spent in each step of the current write, kept when
+/// [`TransactionServiceBuilder::slow_transaction_threshold`](super::TransactionServiceBuilder::slow_transaction_threshold)
+/// is set, to log which step of a slow transaction took the time.
+#[derive(Debug, Default)]
+pub(super) struct WriteSteps {
+    started: Option<Instant>,
+    last: Option<Instant>,
+    steps: Vec<(&'static str, Duration)>,
+}
+
+impl WriteSteps {
+    /// Starts timing a write that started at `started`.
+    pub fn start(&mut self, started: Instant) {
+        self.started = Some(started);
+        self.last = Some(started);
+        self.steps.clear();
+    }
+
+    /// Ends `step`, which took the time since the previous step ended.
+    pub fn finish(&mut self, step: &'static str) {
+        if let Some(last) = self.last {
+            let now = Instant::now();
+            self.steps.push((step, now - last));
+            self.last = Some(now);
+        }
+    }
+
+    /// The time since the write started.
+    pub fn elapsed(&self) -> Duration {
+        self.started.map(|s| s.elapsed()).unwrap_or_default()
+    }
+
+    pub fn slowest(&self) -> Option<(&'static str, Duration)> {
+        self.steps.iter().copied().max_by_key(|(_, d)| *d)
+    }
+
+    /// Every step with its time in milliseconds, e.g. `write_lock=0.1ms begin=0.2ms`.
+    pub fn summary(&self) -> String {
+        self.steps
+            .iter()
+            .map(|(step, d)| format!("{}={:.1}ms", step, d.as_secs_f64() * 1000.0))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WriteSteps;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_write_steps() {
+        let mut steps = WriteSteps::default();
+        // Not started
+        steps.finish("begin");
+        assert_eq!(steps.slowest(), None);
+
+        steps.start(Instant::now() - Duration::from_millis(50));
+        steps.finish("write_lock");
+        steps.finish("begin");
+        assert_eq!(steps.slowest().unwrap().0, "write_lock");
+        assert!(steps.elapsed() >= Duration::from_millis(50));
+        assert!(steps.summary().starts_with("write_lock=5"));
+        assert!(steps.summary().contains(" begin=0."));
+
+        steps.start(Instant::now());
+        steps.finish("commit");
+        assert_eq!(steps.steps.len(), 1);
+    }
+}
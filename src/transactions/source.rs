@@ -0,0 +1,60 @@
+use super::{JsonLinesReader, Result, Transaction, TransactionReader};
+use futures::stream::{self, BoxStream, StreamExt};
+use std::io;
+
+/// A source of transactions to process, such as a csv file or a message queue.
+///
+/// The returned stream can be passed straight to
+/// [`TransactionService::process_stream`](super::TransactionService::process_stream).
+pub trait TransactionSource {
+    /// Streams the transactions from the source.
+    fn stream(&mut self) -> BoxStream<'_, Result<Transaction>>;
+
+    /// Streams the transactions with the line of the source each was read from, for
+    /// [`TransactionService::process_batch`](super::TransactionService::process_batch).
+    /// Sources without lines number the transactions from 1.
+    fn stream_with_lines(&mut self) -> BoxStream<'_, Result<(u64, Transaction)>> {
+        self.stream()
+            .zip(stream::iter(1..))
+            .map(|(transaction, line)| transaction.map(|t| (line, t)))
+            .boxed()
+    }
+}
+
+/// The position of a message in a partitioned log such as a kafka topic, see
+/// [`TransactionService::process_with_offsets`](super::TransactionService::process_with_offsets).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SourceOffset {
+    pub partition: i32,
+    pub offset: i64,
+}
+
+impl<S: TransactionSource + ?Sized> TransactionSource for Box<S> {
+    fn stream(&mut self) -> BoxStream<'_, Result<Transaction>> {
+        (**self).stream()
+    }
+
+    fn stream_with_lines(&mut self) -> BoxStream<'_, Result<(u64, Transaction)>> {
+        (**self).stream_with_lines()
+    }
+}
+
+impl<R: io::Read + Send> TransactionSource for TransactionReader<R> {
+    fn stream(&mut self) -> BoxStream<'_, Result<Transaction>> {
+        stream::iter(self.transactions()).boxed()
+    }
+
+    fn stream_with_lines(&mut self) -> BoxStream<'_, Result<(u64, Transaction)>> {
+        stream::iter(self.transactions_with_lines()).boxed()
+    }
+}
+
+impl<R: io::BufRead + Send> TransactionSource for JsonLinesReader<R> {
+    fn stream(&mut self) -> BoxStream<'_, Result<Transaction>> {
+        stream::iter(self.transactions()).boxed()
+    }
+
+    fn stream_with_lines(&mut self) -> BoxStream<'_, Result<(u64, Transaction)>> {
+        stream::iter(self.transactions_with_lines()).boxed()
+    }
+}
@@ -0,0 +1,82 @@
+use super::{
+    Client, ClientRisk, ClientStats, DisputeEvidence, DisputeStatus, ExternalId, HouseAccount,
+    Transaction, TransactionId, TypeTotal, Void,
+};
+use serde::{Deserialize, Serialize};
+
+/// The version of the [`LedgerState`] document written by
+/// [`TransactionService::export_state`](super::TransactionService::export_state). Increased
+/// whenever the document changes in a way older versions can not read.
+pub const STATE_VERSION: u32 = 1;
+
+/// A copy of the ledger, to move it to another database with
+/// [`TransactionService::import_state`](super::TransactionService::import_state).
+///
+/// Holds what later transactions and closes depend on: the clients, the stored deposits and
+/// withdrawals, the open disputes, the clearing deposits and the voided ones, the end-of-day
+/// closes, the house accounts, the external client ids and the risk levels of clients. The audit
+/// log, outbox, batches, annotations, adjustments and erasures stay behind.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct LedgerState {
+    pub version: u32,
+    pub clients: Vec<StateClient>,
+    pub transactions: Vec<Transaction>,
+    pub disputes: Vec<StateDispute>,
+    pub day_closes: Vec<StateDayClose>,
+    #[serde(default)]
+    pub house_accounts: Vec<HouseAccount>,
+    #[serde(default)]
+    pub external_ids: Vec<ExternalId>,
+    #[serde(default)]
+    pub settlements: Vec<StateSettlement>,
+    #[serde(default)]
+    pub risks: Vec<ClientRisk>,
+    #[serde(default)]
+    pub voids: Vec<Void>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct StateClient {
+    #[serde(flatten)]
+    pub client: Client,
+    pub last_activity_at: Option<i64>,
+    #[serde(default)]
+    pub stats: ClientStats,
+}
+
+/// An open dispute, on the transaction with id `tx`.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateDispute {
+    pub tx: TransactionId,
+    pub opened_at: i64,
+    pub escalated_at: Option<i64>,
+    #[serde(default)]
+    pub reason_code: Option<String>,
+    #[serde(default)]
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub status: DisputeStatus,
+    #[serde(default)]
+    pub assignee: Option<String>,
+    #[serde(default)]
+    pub evidence: Vec<DisputeEvidence>,
+}
+
+/// A deposit, with id `tx`, held until it clears at `release_at`, see
+/// [`SettlementDelay`](super::SettlementDelay).
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateSettlement {
+    pub tx: TransactionId,
+    pub release_at: i64,
+}
+
+/// A closed business date, see [`DayClose`](super::DayClose).
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct StateDayClose {
+    /// As `YYYY-MM-DD`.
+    pub business_date: String,
+    pub closed_at: i64,
+    pub checkpoint: i64,
+    pub clients: Vec<Client>,
+    pub totals: Vec<TypeTotal>,
+}
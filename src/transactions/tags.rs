@@ -0,0 +1,56 @@
+use super::{ClientId, Result, TransactionError};
+use serde::{Deserialize, Serialize};
+use std::io;
+
+/// A tag of a client, such as the segment `vip`, set with
+/// [`TransactionService::tag_clients`](super::TransactionService::tag_clients).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientTag {
+    pub client: ClientId,
+    pub tag: String,
+}
+
+impl ClientTag {
+    /// Fails if the tag is empty.
+    pub(super) fn validate(&self) -> Result<()> {
+        if self.tag.is_empty() {
+            return Err(TransactionError::InvalidArgument(format!(
+                "empty tag for client {}",
+                self.client
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Reads client tags from csv input with a `client, tag` header, one tag per row. Whitespace
+/// around fields is trimmed.
+pub fn read_client_tags(reader: impl io::Read) -> Result<Vec<ClientTag>> {
+    let tags: Vec<ClientTag> = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader)
+        .deserialize()
+        .collect::<std::result::Result<_, _>>()?;
+    for tag in &tags {
+        tag.validate()?;
+    }
+    Ok(tags)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{read_client_tags, ClientTag};
+
+    #[test]
+    fn test_read_client_tags() {
+        let tags = read_client_tags("client, tag\n1, vip\n2,retail\n1,staff\n".as_bytes()).unwrap();
+        let tag = |client, tag: &str| ClientTag {
+            client,
+            tag: tag.to_string(),
+        };
+        assert_eq!(tags, [tag(1, "vip"), tag(2, "retail"), tag(1, "staff")]);
+
+        assert!(read_client_tags("client,tag\n1,\n".as_bytes()).is_err());
+        assert!(read_client_tags("client,tag\nx,vip\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,173 @@
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// Records how many transactions of a run were committed in each interval, such as each
+/// minute, and how long they took from being read to being committed, to find the sections of
+/// an input that were slow.
+#[derive(Debug)]
+pub struct ThroughputRecorder {
+    started: Instant,
+    interval: Duration,
+    transactions: u64,
+    buckets: Vec<ThroughputBucket>,
+    /// The latencies of the last bucket, in microseconds.
+    latencies: Vec<u64>,
+}
+
+/// The transactions committed in one interval of a run, see [`ThroughputRecorder`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ThroughputBucket {
+    /// When the interval starts, in seconds since the start of the run.
+    pub start_secs: u64,
+    pub transactions: u64,
+    /// The first and last input lines committed in the interval, empty if there were none.
+    pub first_line: Option<u64>,
+    pub last_line: Option<u64>,
+    /// The percentiles of the time from reading a transaction to committing it, empty if
+    /// there were no transactions.
+    pub latency_ms: Option<LatencyPercentiles>,
+}
+
+/// Percentiles of latencies in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LatencyPercentiles {
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+/// The throughput of a run, see [`ThroughputRecorder::finish`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ThroughputStats {
+    /// The length of each bucket's interval.
+    pub interval_secs: u64,
+    pub elapsed_secs: f64,
+    pub transactions: u64,
+    /// Every interval of the run, including those where nothing was committed.
+    pub buckets: Vec<ThroughputBucket>,
+}
+
+impl ThroughputRecorder {
+    /// Starts recording a run in buckets of `interval`, at least a second.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            started: Instant::now(),
+            interval: interval.max(Duration::from_secs(1)),
+            transactions: 0,
+            buckets: Vec::new(),
+            latencies: Vec::new(),
+        }
+    }
+
+    /// Records that the transaction on input `line`, read at `read_at`, was committed now.
+    pub fn record(&mut self, line: u64, read_at: Instant) {
+        let now = Instant::now();
+        self.record_at(
+            line,
+            now.saturating_duration_since(read_at),
+            now.saturating_duration_since(self.started),
+        );
+    }
+
+    /// Records a transaction committed `at` after the start of the run, `latency` after it was
+    /// read.
+    fn record_at(&mut self, line: u64, latency: Duration, at: Duration) {
+        self.advance(at);
+        let bucket = self.buckets.last_mut().expect("advanced to a bucket");
+        bucket.transactions += 1;
+        bucket.first_line.get_or_insert(line);
+        bucket.last_line = Some(line);
+        self.latencies
+            .push(u64::try_from(latency.as_micros()).unwrap_or(u64::MAX));
+        self.transactions += 1;
+    }
+
+    /// Closes the buckets before the one `at` is in, adding empty ones for the intervals
+    /// nothing was committed in.
+    fn advance(&mut self, at: Duration) {
+        let index = (at.as_secs() / self.interval.as_secs()) as usize;
+        while self.buckets.len() <= index {
+            self.close_bucket();
+            self.buckets.push(ThroughputBucket {
+                start_secs: self.buckets.len() as u64 * self.interval.as_secs(),
+                transactions: 0,
+                first_line: None,
+                last_line: None,
+                latency_ms: None,
+            });
+        }
+    }
+
+    fn close_bucket(&mut self) {
+        if let Some(bucket) = self.buckets.last_mut() {
+            bucket.latency_ms = percentiles(&mut self.latencies);
+            self.latencies.clear();
+        }
+    }
+
+    /// Stops recording, closing the last bucket.
+    pub fn finish(mut self) -> ThroughputStats {
+        let elapsed = self.started.elapsed();
+        self.advance(elapsed);
+        self.close_bucket();
+        ThroughputStats {
+            interval_secs: self.interval.as_secs(),
+            elapsed_secs: elapsed.as_secs_f64(),
+            transactions: self.transactions,
+            buckets: self.buckets,
+        }
+    }
+}
+
+/// The nearest-rank percentiles of `latencies` in microseconds, sorting them.
+fn percentiles(latencies: &mut [u64]) -> Option<LatencyPercentiles> {
+    latencies.sort_unstable();
+    let max = *latencies.last()?;
+    let rank = |p: f64| {
+        let index = ((p * latencies.len() as f64).ceil() as usize).clamp(1, latencies.len()) - 1;
+        latencies[index] as f64 / 1000.0
+    };
+    Some(LatencyPercentiles {
+        p50: rank(0.5),
+        p90: rank(0.9),
+        p99: rank(0.99),
+        max: max as f64 / 1000.0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LatencyPercentiles, ThroughputRecorder};
+    use std::time::Duration;
+
+    #[test]
+    fn test_throughput_recorder() {
+        let mut recorder = ThroughputRecorder::new(Duration::from_secs(60));
+        for line in 1..=100 {
+            recorder.record_at(line, Duration::from_millis(line), Duration::from_secs(5));
+        }
+        // Nothing was committed in the second minute
+        recorder.record_at(101, Duration::from_millis(2500), Duration::from_secs(150));
+
+        let stats = recorder.finish();
+        assert_eq!(stats.transactions, 101);
+        assert_eq!(stats.buckets.len(), 3);
+        let first = &stats.buckets[0];
+        assert_eq!((first.start_secs, first.transactions), (0, 100));
+        assert_eq!((first.first_line, first.last_line), (Some(1), Some(100)));
+        assert_eq!(
+            first.latency_ms,
+            Some(LatencyPercentiles {
+                p50: 50.0,
+                p90: 90.0,
+                p99: 99.0,
+                max: 100.0,
+            })
+        );
+        assert_eq!(stats.buckets[1].transactions, 0);
+        assert_eq!(stats.buckets[1].latency_ms, None);
+        assert_eq!(stats.buckets[2].start_secs, 120);
+        assert_eq!(stats.buckets[2].latency_ms.unwrap().p50, 2500.0);
+    }
+}
@@ -0,0 +1,65 @@
+use super::{Client, Result, Transaction};
+use serde::Serialize;
+
+/// What a [`TransactionValidator`] decided about a transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// Apply the transaction, recording the annotations alongside it and adding
+    /// `risk_score_change` to the client's
+    /// [`ClientStats::risk_score`](super::ClientStats::risk_score), whatever the transaction's
+    /// outcome.
+    Accept {
+        annotations: Vec<String>,
+        risk_score_change: i64,
+    },
+    /// Do not apply the transaction.
+    Reject { reason: String },
+}
+
+impl Verdict {
+    /// Accepts without annotations.
+    pub fn accept() -> Self {
+        Self::Accept {
+            annotations: Vec::new(),
+            risk_score_change: 0,
+        }
+    }
+}
+
+/// Checks transactions before they are applied, registered with
+/// [`TransactionServiceBuilder::validator`](super::TransactionServiceBuilder::validator).
+///
+/// Validators run in the order they were registered. The first rejection stops the
+/// transaction, which gets a [`TransactionOutcome::Rejected`](super::TransactionOutcome::Rejected)
+/// outcome. Transactions for locked clients are ignored without being validated.
+pub trait TransactionValidator: Send + Sync {
+    /// Decides on `transaction`. `client` is the client's state before the transaction, `None`
+    /// if this is the client's first transaction.
+    ///
+    /// Returning an error fails the transaction like any other processing error.
+    fn validate(&self, transaction: &Transaction, client: Option<&Client>) -> Result<Verdict>;
+
+    /// Like [`validate`](Self::validate), also given the client's current
+    /// [`ClientStats::risk_score`](super::ClientStats::risk_score), 0 for a new client. This is
+    /// what the service calls; validators that use the score implement it, the default ignores
+    /// the score.
+    fn validate_with_risk(
+        &self,
+        transaction: &Transaction,
+        client: Option<&Client>,
+        risk_score: i64,
+    ) -> Result<Verdict> {
+        let _ = risk_score;
+        self.validate(transaction, client)
+    }
+}
+
+/// A note a [`TransactionValidator`] attached to a transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Annotation {
+    /// The type of the annotated transaction, since disputes, resolves and chargebacks share
+    /// the id of the transaction they refer to.
+    #[serde(rename = "type")]
+    pub transaction_type: String,
+    pub annotation: String,
+}
@@ -0,0 +1,343 @@
+use super::{Alert, AlertKind, ClientId, EventObserver, Transaction, TransactionId};
+use hmac::{Hmac, Mac};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use sha2::Sha256;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// The header containing the unix time, in seconds, the event was sent at.
+pub const TIMESTAMP_HEADER: &str = "x-webhook-timestamp";
+/// The header containing `sha256=<hex>`, the HMAC-SHA256 of `<timestamp>.<body>` keyed with
+/// the webhook secret.
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+
+/// The json body POSTed to the webhook.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// A disputed transaction was charged back.
+    Chargeback {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Option<Decimal>,
+    },
+    /// The client was locked.
+    ClientLocked { client: ClientId },
+    /// A dispute held the client's funds for too long and was escalated.
+    HoldEscalated {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Option<Decimal>,
+    },
+    /// The client's balances crossed an alert threshold.
+    Alert {
+        client: ClientId,
+        alert: AlertKind,
+        threshold: Decimal,
+        available: Decimal,
+        held: Decimal,
+    },
+}
+
+/// Where and how [`WebhookSink`] delivers events.
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    url: String,
+    secret: Option<Vec<u8>>,
+    max_attempts: u32,
+    initial_backoff: Duration,
+}
+
+impl WebhookConfig {
+    /// POSTs events to `url`, trying each event 5 times starting with a 1 second backoff.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            secret: None,
+            max_attempts: 5,
+            initial_backoff: Duration::from_secs(1),
+        }
+    }
+
+    /// Signs events with `secret`, see [`SIGNATURE_HEADER`].
+    pub fn secret(mut self, secret: impl Into<Vec<u8>>) -> Self {
+        self.secret = Some(secret.into());
+        self
+    }
+
+    /// How many times an event is sent before it is dropped.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// How long to wait before the first retry. The wait doubles after every failed attempt.
+    pub fn initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    /// Starts delivering events on a background task. The task finishes once every
+    /// [`WebhookSink`] is dropped and the queued events have been delivered.
+    pub fn spawn(self) -> (WebhookSink, JoinHandle<()>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let handle = tokio::spawn(self.deliver(receiver));
+        (WebhookSink { sender }, handle)
+    }
+
+    async fn deliver(mut self, mut receiver: mpsc::UnboundedReceiver<Message>) {
+        let client = reqwest::Client::new();
+        while let Some(message) = receiver.recv().await {
+            let event = match message {
+                Message::Event(event) => event,
+                Message::Retarget(url) => {
+                    if url != self.url {
+                        tracing::info!(url, "Delivering webhook events to a new url");
+                        self.url = url;
+                    }
+                    continue;
+                }
+            };
+            let body = match serde_json::to_vec(&event) {
+                Ok(body) => body,
+                Err(e) => {
+                    tracing::error!(?event, error = %e, "Failed to serialize webhook event");
+                    continue;
+                }
+            };
+
+            let mut backoff = self.initial_backoff;
+            for attempt in 1..=self.max_attempts {
+                match self.send(&client, &body).await {
+                    Ok(()) => break,
+                    Err((e, retryable)) if retryable && attempt < self.max_attempts => {
+                        tracing::debug!(attempt, error = %e, "Webhook delivery failed, retrying");
+                        tokio::time::sleep(backoff).await;
+                        backoff *= 2;
+                    }
+                    Err((e, _)) => {
+                        tracing::warn!(?event, attempt, error = %e, "Dropping webhook event");
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sends one attempt, returning the error and whether it is worth retrying.
+    async fn send(&self, client: &reqwest::Client, body: &[u8]) -> Result<(), (String, bool)> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            .to_string();
+        let mut request = client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(TIMESTAMP_HEADER, &timestamp)
+            .body(body.to_vec());
+        if let Some(secret) = &self.secret {
+            request = request.header(SIGNATURE_HEADER, sign(secret, &timestamp, body));
+        }
+
+        let response = request.send().await.map_err(|e| (e.to_string(), true))?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        // Other client errors will fail the same way on every attempt
+        let retryable =
+            status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+        Err((format!("webhook responded with {}", status), retryable))
+    }
+}
+
+/// Computes the [`SIGNATURE_HEADER`] value for a webhook body.
+pub fn sign(secret: &[u8], timestamp: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    let hex: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("sha256={}", hex)
+}
+
+/// POSTs a [`WebhookEvent`] when a chargeback is applied or a client is locked. Created with
+/// [`WebhookConfig::spawn`] and registered with
+/// [`TransactionServiceBuilder::observer`](super::TransactionServiceBuilder::observer).
+///
+/// Events are delivered one at a time, in order, so an unavailable webhook delays the events
+/// after it until its retries run out.
+#[derive(Clone)]
+pub struct WebhookSink {
+    sender: mpsc::UnboundedSender<Message>,
+}
+
+/// What a [`WebhookSink`] queues for the delivery task.
+enum Message {
+    Event(WebhookEvent),
+    /// Deliver the events queued after this one to a new url.
+    Retarget(String),
+}
+
+impl WebhookSink {
+    /// Delivers the events observed from now on to `url`. The events queued before are still
+    /// delivered to the previous url.
+    pub fn retarget(&self, url: impl Into<String>) {
+        if self.sender.send(Message::Retarget(url.into())).is_err() {
+            tracing::warn!("Webhook delivery task has stopped");
+        }
+    }
+
+    fn send(&self, event: WebhookEvent) {
+        if self.sender.send(Message::Event(event)).is_err() {
+            tracing::warn!("Webhook delivery task has stopped");
+        }
+    }
+}
+
+impl EventObserver for WebhookSink {
+    fn on_chargeback(&self, disputed: &Transaction) {
+        self.send(WebhookEvent::Chargeback {
+            client: disputed.client_id,
+            tx: disputed.id,
+            amount: disputed.amount,
+        });
+    }
+
+    fn on_client_locked(&self, client_id: ClientId) {
+        self.send(WebhookEvent::ClientLocked { client: client_id });
+    }
+
+    fn on_alert(&self, alert: &Alert) {
+        self.send(WebhookEvent::Alert {
+            client: alert.client_id,
+            alert: alert.kind,
+            threshold: alert.threshold,
+            available: alert.available,
+            held: alert.held,
+        });
+    }
+
+    fn on_hold_escalated(&self, disputed: &Transaction) {
+        self.send(WebhookEvent::HoldEscalated {
+            client: disputed.client_id,
+            tx: disputed.id,
+            amount: disputed.amount,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sign, WebhookConfig, WebhookEvent, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+    use crate::{EventObserver, Transaction, TransactionType};
+    use rust_decimal_macros::dec;
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// Accepts a request, returning its headers and body after responding with `status`.
+    async fn respond(listener: &TcpListener, status: &str) -> (Vec<String>, Vec<u8>) {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = BufReader::new(stream);
+        let mut headers = Vec::new();
+        loop {
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            let line = line.trim_end().to_ascii_lowercase();
+            if line.is_empty() {
+                break;
+            }
+            headers.push(line);
+        }
+        let length = headers
+            .iter()
+            .find_map(|h| h.strip_prefix("content-length: "))
+            .map_or(0, |l| l.parse().unwrap());
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).await.unwrap();
+        stream
+            .write_all(
+                format!(
+                    "HTTP/1.1 {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+        (headers, body)
+    }
+
+    #[tokio::test]
+    async fn test_webhook() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (sink, handle) =
+            WebhookConfig::new(format!("http://{}/hook", listener.local_addr().unwrap()))
+                .secret("secret")
+                .initial_backoff(Duration::from_millis(10))
+                .max_attempts(2)
+                .spawn();
+
+        sink.on_chargeback(&Transaction {
+            id: 3,
+            transaction_type: TransactionType::Deposit,
+            client_id: 1,
+            amount: Some(dec!(1.5)),
+            reference: None,
+            reason_code: None,
+            notes: None,
+            sequence: None,
+        });
+        sink.on_client_locked(1);
+        let moved = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        sink.retarget(format!("http://{}/hook", moved.local_addr().unwrap()));
+        sink.on_client_locked(2);
+        drop(sink);
+
+        // The chargeback is retried after a server error
+        let (_, first) = respond(&listener, "500 Internal Server Error").await;
+        let (headers, body) = respond(&listener, "200 OK").await;
+        assert_eq!(first, body);
+        let event: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            event,
+            serde_json::json!({"event": "chargeback", "client": 1, "tx": 3, "amount": "1.5"})
+        );
+
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find_map(|h| h.strip_prefix(&format!("{}: ", name)))
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(
+            header(SIGNATURE_HEADER),
+            sign(b"secret", &header(TIMESTAMP_HEADER), &body)
+        );
+
+        // Giving up after max attempts moves on to the next event
+        respond(&listener, "503 Service Unavailable").await;
+        let (_, body) = respond(&listener, "503 Service Unavailable").await;
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            serde_json::to_value(WebhookEvent::ClientLocked { client: 1 }).unwrap()
+        );
+        // Events observed after retargeting go to the new url
+        let (_, body) = respond(&moved, "200 OK").await;
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            serde_json::to_value(WebhookEvent::ClientLocked { client: 2 }).unwrap()
+        );
+        handle.await.unwrap();
+    }
+}
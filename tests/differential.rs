@@ -0,0 +1,200 @@
+//! Checks the sqlite engine against a simple in-memory model of the ledger on arbitrary
+//! transaction sequences.
+//!
+//! Ids and client ids are drawn from small ranges so sequences are full of duplicate ids,
+//! disputes of other clients' transactions, repeated disputes and transactions for locked
+//! clients.
+
+use proptest::prelude::*;
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use transaction_app::{
+    Client, ClientId, Transaction, TransactionId, TransactionOutcome, TransactionService,
+    TransactionType,
+};
+
+const SCALE: u32 = 4;
+/// Few ids and clients, so that transactions often refer to the same ones.
+const MAX_ID: TransactionId = 12;
+const MAX_CLIENT: ClientId = 3;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct ModelClient {
+    available: i64,
+    held: i64,
+    locked: bool,
+}
+
+/// What the model expects [`TransactionService::process_transaction`] to return.
+#[derive(Debug, PartialEq, Eq)]
+enum Expected {
+    Outcome(&'static str),
+    Chargeback { locked: bool },
+    Error,
+}
+
+/// The reference ledger, with amounts in the engine's fixed point storage units.
+#[derive(Debug, Default)]
+struct Model {
+    clients: BTreeMap<ClientId, ModelClient>,
+    /// Every stored deposit and withdrawal, including rejected withdrawals, by id.
+    transactions: HashMap<TransactionId, (ClientId, i64)>,
+    disputes: HashSet<TransactionId>,
+}
+
+impl Model {
+    fn is_locked(&self, client_id: ClientId) -> bool {
+        self.clients.get(&client_id).is_some_and(|c| c.locked)
+    }
+
+    fn apply(&mut self, transaction: &Transaction) -> Expected {
+        if self.is_locked(transaction.client_id) {
+            return Expected::Outcome("ignored");
+        }
+        match transaction.transaction_type {
+            TransactionType::Deposit | TransactionType::Withdrawal => {
+                if self.transactions.contains_key(&transaction.id) {
+                    return Expected::Outcome("ignored");
+                }
+                let amount = to_storage(transaction.amount.unwrap());
+                self.transactions
+                    .insert(transaction.id, (transaction.client_id, amount));
+                let client = self.clients.entry(transaction.client_id).or_default();
+                if transaction.transaction_type == TransactionType::Deposit {
+                    client.available += amount;
+                    Expected::Outcome("deposit")
+                } else if client.available >= amount {
+                    client.available -= amount;
+                    Expected::Outcome("withdrawal")
+                } else {
+                    Expected::Outcome("withdrawal_rejected")
+                }
+            }
+            TransactionType::Dispute => {
+                let Some(&(client_id, amount)) = self.transactions.get(&transaction.id) else {
+                    return Expected::Outcome("ignored");
+                };
+                if !self.disputes.insert(transaction.id) {
+                    return Expected::Outcome("ignored");
+                }
+                let client = self.clients.get_mut(&client_id).unwrap();
+                client.available -= amount;
+                client.held += amount;
+                Expected::Outcome("dispute_opened")
+            }
+            TransactionType::Resolve => {
+                if !self.disputes.remove(&transaction.id) {
+                    return Expected::Outcome("ignored");
+                }
+                let (client_id, amount) = self.transactions[&transaction.id];
+                let client = self.clients.get_mut(&client_id).unwrap();
+                client.available += amount;
+                client.held -= amount;
+                Expected::Outcome("dispute_resolved")
+            }
+            TransactionType::Chargeback => {
+                if !self.disputes.remove(&transaction.id) {
+                    return Expected::Outcome("ignored");
+                }
+                let (client_id, amount) = self.transactions[&transaction.id];
+                let client = self.clients.get_mut(&client_id).unwrap();
+                client.held -= amount;
+                let locked = !client.locked;
+                client.locked = true;
+                Expected::Chargeback { locked }
+            }
+            TransactionType::Custom(_) => unreachable!(),
+        }
+    }
+
+    fn clients(&self) -> Vec<Client> {
+        self.clients
+            .iter()
+            .map(|(&id, c)| Client {
+                id,
+                available: Decimal::new(c.available, SCALE),
+                held: Decimal::new(c.held, SCALE),
+                total: Decimal::new(c.available + c.held, SCALE),
+                locked: c.locked,
+            })
+            .collect()
+    }
+}
+
+fn to_storage(amount: Decimal) -> i64 {
+    (amount * Decimal::from(10i64.pow(SCALE)))
+        .try_into()
+        .unwrap()
+}
+
+fn observed(result: transaction_app::Result<TransactionOutcome>) -> Expected {
+    match result {
+        Ok(TransactionOutcome::Chargeback { locked, .. }) => Expected::Chargeback { locked },
+        Ok(outcome) => Expected::Outcome(outcome.to_str()),
+        Err(_) => Expected::Error,
+    }
+}
+
+fn transaction() -> impl Strategy<Value = Transaction> {
+    let transaction_type = prop_oneof![
+        3 => Just(TransactionType::Deposit),
+        2 => Just(TransactionType::Withdrawal),
+        2 => Just(TransactionType::Dispute),
+        1 => Just(TransactionType::Resolve),
+        1 => Just(TransactionType::Chargeback),
+    ];
+    (transaction_type, 1..=MAX_ID, 1..=MAX_CLIENT, 1..=100_000i64).prop_map(
+        |(transaction_type, id, client_id, amount)| {
+            let amount = matches!(
+                transaction_type,
+                TransactionType::Deposit | TransactionType::Withdrawal
+            )
+            .then(|| Decimal::new(amount, SCALE));
+            Transaction {
+                id,
+                transaction_type,
+                client_id,
+                amount,
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            }
+        },
+    )
+}
+
+async fn run(transactions: &[Transaction]) -> Result<(), TestCaseError> {
+    let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+    let svc = TransactionService::new(pool).await.unwrap();
+    let mut model = Model::default();
+
+    for (i, transaction) in transactions.iter().enumerate() {
+        let expected = model.apply(transaction);
+        let actual = observed(svc.process_transaction(transaction).await);
+        prop_assert_eq!(actual, expected, "transaction {}: {:?}", i, transaction);
+    }
+
+    let clients = svc.get_clients_vec().await.unwrap();
+    for client in &clients {
+        prop_assert_eq!(client.total, client.available + client.held);
+        prop_assert!(client.held >= Decimal::ZERO, "negative held: {:?}", client);
+    }
+    let mut clients = clients;
+    clients.sort_by_key(|c| c.id);
+    prop_assert_eq!(clients, model.clients());
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(512))]
+
+    #[test]
+    fn test_engine_matches_model(transactions in prop::collection::vec(transaction(), 1..60)) {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(run(&transactions))?;
+    }
+}
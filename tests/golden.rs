@@ -0,0 +1,100 @@
+//! Runs the binary over every case in `tests/fixtures` and compares its output with the
+//! case's `expected.csv`.
+//!
+//! Each case is a directory with an `input.csv` or `input.jsonl`, the `expected.csv` output
+//! and optionally a `rules.toml` passed with `--rules`, a `pseudonymize.key` passed with
+//! `--pseudonymize-key-file`, an `expected-rejected.csv` compared with the output of
+//! `--rejected` and an `args` file of further arguments, one per line. Run with `UPDATE_FIXTURES=1` to rewrite the expected output
+//! after an intended behavior change.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+fn run_case(case: &Path) -> Result<(), String> {
+    let input = ["input.csv", "input.jsonl"]
+        .iter()
+        .map(|name| case.join(name))
+        .find(|path| path.exists())
+        .ok_or("no input.csv or input.jsonl")?;
+
+    let mut command = Command::new(env!("CARGO_BIN_EXE_transaction-app"));
+    command.arg(&input);
+    let rules = case.join("rules.toml");
+    if rules.exists() {
+        command.arg("--rules").arg(&rules);
+    }
+    let key = case.join("pseudonymize.key");
+    if key.exists() {
+        command.arg("--pseudonymize-key-file").arg(&key);
+    }
+    let expected_rejected_path = case.join("expected-rejected.csv");
+    let rejected = std::env::temp_dir().join(format!(
+        "golden-{}-{}-rejected.csv",
+        std::process::id(),
+        case.file_name().unwrap().to_string_lossy()
+    ));
+    if expected_rejected_path.exists() {
+        command.arg("--rejected").arg(&rejected);
+    }
+    if let Ok(args) = fs::read_to_string(case.join("args")) {
+        command.args(args.lines().map(str::trim).filter(|arg| !arg.is_empty()));
+    }
+    let output = command.output().map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(format!(
+            "exited with {}:\n{}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    let actual = String::from_utf8(output.stdout).map_err(|e| e.to_string())?;
+    compare(&case.join("expected.csv"), actual)?;
+
+    if expected_rejected_path.exists() {
+        let actual = fs::read_to_string(&rejected).map_err(|e| e.to_string())?;
+        fs::remove_file(&rejected).map_err(|e| e.to_string())?;
+        compare(&expected_rejected_path, actual)?;
+    }
+    Ok(())
+}
+
+fn compare(expected_path: &Path, actual: String) -> Result<(), String> {
+    if std::env::var_os("UPDATE_FIXTURES").is_some() {
+        return fs::write(expected_path, actual).map_err(|e| e.to_string());
+    }
+    let expected = fs::read_to_string(expected_path).map_err(|e| e.to_string())?;
+    if actual != expected {
+        let name = expected_path.file_name().unwrap().to_string_lossy();
+        return Err(format!("{name} expected:\n{expected}\nactual:\n{actual}"));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_fixtures() {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let mut cases: Vec<_> = fs::read_dir(&fixtures)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_dir())
+        .collect();
+    cases.sort();
+    assert!(!cases.is_empty(), "no cases in {}", fixtures.display());
+
+    let failures: Vec<_> = cases
+        .iter()
+        .filter_map(|case| {
+            run_case(case)
+                .err()
+                .map(|e| format!("{}: {e}", case.file_name().unwrap().to_string_lossy()))
+        })
+        .collect();
+    assert!(
+        failures.is_empty(),
+        "{} of {} fixtures failed\n\n{}",
+        failures.len(),
+        cases.len(),
+        failures.join("\n\n")
+    );
+}
@@ -0,0 +1,122 @@
+//! Runs the binary's administrative commands with `--pseudonymize-key-file` and checks that
+//! none of them print the raw client id, only its pseudonym.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Distinct enough not to turn up in amounts, transaction ids or pseudonyms by chance.
+const CLIENT: &str = "7101";
+
+struct Ledger {
+    dir: PathBuf,
+}
+
+impl Ledger {
+    fn new() -> Self {
+        let dir = std::env::temp_dir().join(format!("pseudonymized-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("pseudonymize.key"), "cli-key").unwrap();
+        fs::write(
+            dir.join("policy.toml"),
+            "[settlement_delay]\nthreshold = \"5000\"\nclearing_hours = 48\n",
+        )
+        .unwrap();
+        Self { dir }
+    }
+
+    /// Runs the binary with `args` against the ledger's database, returning its output.
+    fn run(&self, args: &[&str]) -> String {
+        // The global options go after the command, where they are not taken for the input
+        let output = Command::new(env!("CARGO_BIN_EXE_transaction-app"))
+            .args(args)
+            .arg("--database")
+            .arg(format!("sqlite://{}", self.dir.join("ledger.db").display()))
+            .arg("--pseudonymize-key-file")
+            .arg(self.dir.join("pseudonymize.key"))
+            .arg("--policy-file")
+            .arg(self.dir.join("policy.toml"))
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{args:?} failed:\n{stderr}");
+        assert!(
+            !stdout.contains(CLIENT) && !stderr.contains(CLIENT),
+            "{args:?} printed the raw client id:\n{stdout}\n{stderr}"
+        );
+        stdout
+    }
+
+    fn input(&self, name: &str, rows: &str) -> String {
+        let path = self.dir.join(name);
+        fs::write(&path, format!("type, client, tx, amount\n{rows}")).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+}
+
+impl Drop for Ledger {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[test]
+fn test_admin_commands_print_pseudonyms() {
+    let ledger = Ledger::new();
+    // The deposit over the settlement threshold is held until it clears, so it can be voided
+    let input = ledger.input(
+        "deposits.csv",
+        &format!("deposit, {CLIENT}, 1, 100.0\ndeposit, {CLIENT}, 2, 6000.0\n"),
+    );
+    let report = ledger.run(&[&input]);
+    let label = report
+        .lines()
+        .nth(1)
+        .and_then(|row| row.split(',').next())
+        .expect("A report row for the client")
+        .to_string();
+    let printed = |output: String| {
+        assert!(output.contains(&label), "missing {label} in:\n{output}");
+    };
+
+    printed(ledger.run(&[
+        "adjust",
+        CLIENT,
+        "-1",
+        "--reason",
+        "Fee",
+        "--operator",
+        "alice",
+    ]));
+    printed(ledger.run(&[
+        "adjust",
+        CLIENT,
+        "-10",
+        "--reason",
+        "Refund",
+        "--operator",
+        "alice",
+        "--approve-adjustments-over",
+        "5",
+    ]));
+    let approvals = ledger.run(&["approvals"]);
+    printed(approvals.clone());
+    let id = approvals
+        .lines()
+        .nth(1)
+        .and_then(|row| row.split(',').next())
+        .expect("A pending approval");
+    printed(ledger.run(&["approve", id, "--operator", "bob"]));
+
+    printed(ledger.run(&["void", "2", "--reason", "Returned", "--operator", "alice"]));
+
+    let chargeback = ledger.input(
+        "chargeback.csv",
+        &format!("dispute, {CLIENT}, 1,\nchargeback, {CLIENT}, 1,\n"),
+    );
+    printed(ledger.run(&[&chargeback]));
+    printed(ledger.run(&["unlock", CLIENT, "--operator", "alice"]));
+    printed(ledger.run(&["forget-client", CLIENT, "--operator", "alice"]));
+}
@@ -0,0 +1,138 @@
+//! Fires interleaved transactions for overlapping clients at a shared [`TransactionService`]
+//! from many tasks at once, the way the server does, and checks the final balances match
+//! processing the same transactions one at a time.
+//!
+//! Each client's transactions keep their order within a task, so the only difference from
+//! the serial run is the interleaving between clients. Set `STRESS_TRANSACTIONS` to run more
+//! transactions than the default.
+
+use rust_decimal::Decimal;
+use std::sync::Arc;
+use transaction_app::{
+    Client, ClientId, Transaction, TransactionId, TransactionService, TransactionType,
+};
+
+/// The outcome name, or `error` for transactions that failed and were rolled back, e.g. a
+/// deposit without an amount.
+async fn process(svc: &TransactionService, transaction: &Transaction) -> &'static str {
+    match svc.process_transaction(transaction).await {
+        Ok(outcome) => outcome.to_str(),
+        Err(_) => "error",
+    }
+}
+
+const DEFAULT_TRANSACTIONS: TransactionId = 4000;
+const CLIENTS: ClientId = 16;
+const CONNECTIONS: u32 = 8;
+
+/// A small deterministic generator, so failures can be reproduced.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self, bound: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % bound
+    }
+}
+
+/// Deposits, withdrawals and disputes, resolves and chargebacks of the same client's earlier
+/// deposits.
+fn transactions(count: TransactionId) -> Vec<Transaction> {
+    let mut rng = XorShift(0x9e37_79b9_7f4a_7c15);
+    let mut deposits: Vec<Vec<TransactionId>> = vec![Vec::new(); CLIENTS as usize];
+    (1..=count)
+        .map(|id| {
+            let client_id = rng.next(CLIENTS.into()) as ClientId;
+            let client_deposits = &mut deposits[client_id as usize];
+            let amount = Some(Decimal::new(rng.next(100_000) as i64 + 1, 2));
+            let (transaction_type, id, amount) = match rng.next(10) {
+                0..=3 => {
+                    client_deposits.push(id);
+                    (TransactionType::Deposit, id, amount)
+                }
+                4..=6 => (TransactionType::Withdrawal, id, amount),
+                kind if !client_deposits.is_empty() => {
+                    let disputed = client_deposits[rng.next(client_deposits.len() as u64) as usize];
+                    let transaction_type = match kind {
+                        7 | 8 => TransactionType::Dispute,
+                        _ if rng.next(4) == 0 => TransactionType::Chargeback,
+                        _ => TransactionType::Resolve,
+                    };
+                    (transaction_type, disputed, None)
+                }
+                _ => (TransactionType::Deposit, id, amount),
+            };
+            Transaction {
+                id,
+                transaction_type,
+                client_id,
+                amount,
+                reference: None,
+                reason_code: None,
+                notes: None,
+                sequence: None,
+            }
+        })
+        .collect()
+}
+
+async fn service() -> TransactionService {
+    TransactionService::builder()
+        .max_connections(CONNECTIONS)
+        .build()
+        .await
+        .unwrap()
+}
+
+async fn clients(svc: &TransactionService) -> Vec<Client> {
+    let mut clients = svc.get_clients_vec().await.unwrap();
+    clients.sort_by_key(|c| c.id);
+    clients
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn test_concurrent_matches_serial() {
+    let count = std::env::var("STRESS_TRANSACTIONS")
+        .map(|count| count.parse().unwrap())
+        .unwrap_or(DEFAULT_TRANSACTIONS);
+    let transactions = transactions(count);
+
+    let serial = service().await;
+    let mut expected_outcomes = Vec::with_capacity(transactions.len());
+    for transaction in &transactions {
+        expected_outcomes.push(process(&serial, transaction).await);
+    }
+
+    let concurrent = Arc::new(service().await);
+    let tasks: Vec<_> = (0..CLIENTS)
+        .map(|client_id| {
+            let svc = concurrent.clone();
+            let transactions: Vec<_> = transactions
+                .iter()
+                .cloned()
+                .enumerate()
+                .filter(|(_, t)| t.client_id == client_id)
+                .collect();
+            tokio::spawn(async move {
+                let mut outcomes = Vec::with_capacity(transactions.len());
+                for (i, transaction) in &transactions {
+                    outcomes.push((*i, process(&svc, transaction).await));
+                }
+                outcomes
+            })
+        })
+        .collect();
+    let mut outcomes = Vec::with_capacity(transactions.len());
+    for task in tasks {
+        outcomes.extend(task.await.unwrap());
+    }
+    outcomes.sort_by_key(|(i, _)| *i);
+    let outcomes: Vec<_> = outcomes.into_iter().map(|(_, outcome)| outcome).collect();
+
+    assert_eq!(outcomes, expected_outcomes);
+    let expected = clients(&serial).await;
+    assert_eq!(expected.len(), CLIENTS as usize);
+    assert_eq!(clients(&concurrent).await, expected);
+}